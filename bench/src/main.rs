@@ -0,0 +1,189 @@
+use bytes::Bytes;
+use rand::Rng;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+use tokio::net::TcpStream;
+
+use ddbb_libs::connection::Connection;
+use ddbb_libs::data_structure::{AuthEntry, CommandEntry, FrameCast, MessageEntry, NotLeaderEntry};
+use ddbb_libs::frame::Frame;
+use ddbb_libs::Error;
+
+/// Drives a read/write mix against the client ports of a cluster and reports the
+/// throughput and latency of each, e.g.
+/// `ddbb_bench --nodes 127.0.0.1:6142 127.0.0.1:6143 --connections 32 --read-ratio 0.9`.
+#[derive(Debug, StructOpt)]
+struct Args {
+    /// client addresses of the nodes, the connections are spread across them
+    #[structopt(long, required = true)]
+    nodes: Vec<String>,
+    /// connections, each sending its next request once the previous one is answered
+    #[structopt(long, default_value = "16")]
+    connections: usize,
+    #[structopt(long, default_value = "10")]
+    duration_secs: u64,
+    /// share of the requests that are reads, the others are writes
+    #[structopt(long, default_value = "0.5")]
+    read_ratio: f64,
+    /// read with `StaleGet` from the node connected to, instead of linearizable gets
+    #[structopt(long)]
+    stale_reads: bool,
+    /// keys written and read, picked uniformly
+    #[structopt(long, default_value = "1000")]
+    keys: u64,
+    #[structopt(long, default_value = "64")]
+    value_size: usize,
+}
+
+/// The latencies of the requests answered by one or more connections.
+#[derive(Debug, Default)]
+struct Latencies {
+    reads: Vec<Duration>,
+    writes: Vec<Duration>,
+    errors: u64,
+}
+
+impl Latencies {
+    fn merge(&mut self, other: Latencies) {
+        self.reads.extend(other.reads);
+        self.writes.extend(other.writes);
+        self.errors += other.errors;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Arc::new(Args::from_args());
+    let deadline = Instant::now() + Duration::from_secs(args.duration_secs);
+    let started = Instant::now();
+    let workers: Vec<_> = (0..args.connections)
+        .map(|i| {
+            let addr = args.nodes[i % args.nodes.len()].clone();
+            tokio::spawn(worker(addr, args.clone(), deadline))
+        })
+        .collect();
+    let mut latencies = Latencies::default();
+    for worker in workers {
+        match worker.await.unwrap() {
+            Ok(worker_latencies) => latencies.merge(worker_latencies),
+            Err(e) => eprintln!("connection failed: {}", e),
+        }
+    }
+    let elapsed = started.elapsed();
+
+    println!(
+        "{:<6} {:>10} {:>12} {:>10} {:>10} {:>10}",
+        "op", "requests", "req/s", "p50 ms", "p99 ms", "max ms"
+    );
+    report("read", &mut latencies.reads, elapsed);
+    report("write", &mut latencies.writes, elapsed);
+    println!("errors {}", latencies.errors);
+}
+
+/// Connect to `addr`, sending the token in `DDBB_AUTH_TOKEN` if it is set.
+async fn connect(addr: &str) -> ddbb_libs::Result<Connection> {
+    let mut connection = Connection::new(TcpStream::connect(addr).await?);
+    if let Ok(token) = env::var("DDBB_AUTH_TOKEN") {
+        connection
+            .write_frame(&AuthEntry { token }.to_frame())
+            .await?;
+        let res = connection
+            .read_frame()
+            .await?
+            .ok_or(Error::ConnectionClosed)?;
+        if let MessageEntry::Error { err_msg } = *MessageEntry::from_frame(&res)? {
+            return Err(Error::from_message(&err_msg));
+        }
+    }
+    Ok(connection)
+}
+
+/// Send requests on one connection to `addr` until `deadline`, timing each.
+async fn worker(addr: String, args: Arc<Args>, deadline: Instant) -> ddbb_libs::Result<Latencies> {
+    let mut connection = connect(&addr).await?;
+    let value = Bytes::from("x".repeat(args.value_size));
+    let mut latencies = Latencies::default();
+    while Instant::now() < deadline {
+        let (read, cmd) = {
+            let mut rng = rand::thread_rng();
+            let key = format!("bench/{}", rng.gen_range(0..args.keys.max(1)));
+            let read = rng.gen_bool(args.read_ratio.clamp(0.0, 1.0));
+            let cmd = match (read, args.stale_reads) {
+                (true, true) => CommandEntry::StaleGet { key },
+                (true, false) => CommandEntry::GetValue { key },
+                (false, _) => CommandEntry::SetValue {
+                    key,
+                    value: value.clone(),
+                },
+            };
+            (read, cmd)
+        };
+        let sent = Instant::now();
+        connection.write_frame(&cmd.to_frame()).await?;
+        let res = connection
+            .read_frame()
+            .await?
+            .ok_or(Error::ConnectionClosed)?;
+        let latency = sent.elapsed();
+        if is_error(&res) {
+            latencies.errors += 1;
+        } else if read {
+            latencies.reads.push(latency);
+        } else {
+            latencies.writes.push(latency);
+        }
+    }
+    Ok(latencies)
+}
+
+/// A `MessageEntry::Error` or a `NotLeaderEntry` reply, a request not served.
+fn is_error(res: &Frame) -> bool {
+    NotLeaderEntry::from_frame(res).is_ok()
+        || matches!(
+            MessageEntry::from_frame(res).map(|msg| *msg),
+            Ok(MessageEntry::Error { .. })
+        )
+}
+
+fn report(op: &str, latencies: &mut [Duration], elapsed: Duration) {
+    latencies.sort();
+    let ms = |latency: Option<&Duration>| latency.map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+    println!(
+        "{:<6} {:>10} {:>12.1} {:>10.2} {:>10.2} {:>10.2}",
+        op,
+        latencies.len(),
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        ms(percentile(latencies, 0.5)),
+        ms(percentile(latencies, 0.99)),
+        ms(latencies.last()),
+    );
+}
+
+/// The latency `p` (0 to 1) of the requests are at most, of `sorted` latencies.
+fn percentile(sorted: &[Duration], p: f64) -> Option<&Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len()) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 0.5), Some(&Duration::from_millis(50)));
+        assert_eq!(percentile(&sorted, 0.99), Some(&Duration::from_millis(99)));
+        assert_eq!(percentile(&sorted, 1.0), Some(&Duration::from_millis(100)));
+        assert_eq!(
+            percentile(&sorted[..1], 0.0),
+            Some(&Duration::from_millis(1))
+        );
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+}
@@ -0,0 +1,266 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+/// Client address of the node used when `DDBB_NODES` is not set.
+pub const DEFAULT_NODE: &str = "127.0.0.1:6142";
+/// A node that failed a request is not picked again for this long.
+const DOWN_BACKOFF: Duration = Duration::from_millis(2000);
+/// Weight of the latest request in the average latency of a node.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    RoundRobin,
+    /// the node with the lowest average latency
+    LatencyAware,
+}
+
+#[derive(Clone, Debug)]
+struct NodeHealth {
+    addr: String,
+    /// excluded until then after a failure
+    down_until: Option<Instant>,
+    /// average latency, in ms, `None` before the first request
+    latency_ms: Option<f64>,
+    zone: Option<String>,
+}
+
+impl NodeHealth {
+    fn new(addr: String) -> Self {
+        NodeHealth {
+            addr,
+            down_until: None,
+            latency_ms: None,
+            zone: None,
+        }
+    }
+
+    fn is_up(&self, now: Instant) -> bool {
+        self.down_until.map_or(true, |until| now >= until)
+    }
+}
+
+/// Spreads stale reads across the known nodes and sends everything else to the
+/// leader, leaving out the nodes that recently failed. Stale reads go to the nodes
+/// in the zone of the client while any of them is up.
+#[derive(Debug)]
+pub struct Balancer {
+    nodes: Vec<NodeHealth>,
+    strategy: Strategy,
+    /// zone of the client, `None` reads from every zone alike
+    zone: Option<String>,
+    next: usize,
+    leader: Option<String>,
+    /// round of the leader named by the latest hint, 0 if found otherwise
+    leader_ballot: u64,
+}
+
+impl Balancer {
+    pub fn new(addrs: Vec<String>, strategy: Strategy) -> Self {
+        let nodes = addrs.into_iter().map(NodeHealth::new).collect();
+        Self {
+            nodes,
+            strategy,
+            zone: None,
+            next: 0,
+            leader: None,
+            leader_ballot: 0,
+        }
+    }
+
+    /// #Descriptions: the nodes in `DDBB_NODES`, comma separated client addresses each
+    /// possibly followed by `@zone`, the zone of the client in `DDBB_ZONE` and the
+    /// strategy in `DDBB_BALANCE`, `latency` or round robin by default.
+    pub fn from_env() -> Self {
+        let nodes: Vec<(String, Option<String>)> = env::var("DDBB_NODES")
+            .unwrap_or_else(|_| DEFAULT_NODE.to_string())
+            .split(',')
+            .map(|node| node.trim())
+            .filter(|node| !node.is_empty())
+            .map(|node| match node.split_once('@') {
+                Some((addr, zone)) => (addr.to_string(), Some(zone.to_string())),
+                None => (node.to_string(), None),
+            })
+            .collect();
+        let strategy = match env::var("DDBB_BALANCE").as_deref() {
+            Ok("latency") => Strategy::LatencyAware,
+            _ => Strategy::RoundRobin,
+        };
+        let (addrs, zones): (Vec<String>, Vec<Option<String>>) = nodes.into_iter().unzip();
+        let mut balancer = Self::new(addrs, strategy);
+        balancer.set_zones(env::var("DDBB_ZONE").ok(), zones);
+        balancer
+    }
+
+    /// #Descriptions: the zone of the client and those of the nodes, in their order.
+    pub fn set_zones(&mut self, zone: Option<String>, zones: Vec<Option<String>>) {
+        self.zone = zone;
+        for (node, zone) in self.nodes.iter_mut().zip(zones) {
+            node.zone = zone;
+        }
+    }
+
+    /// Whether the stale reads may go to `node`, one of the zone of the client if any
+    /// of them is up.
+    fn may_read(&self, node: &NodeHealth, now: Instant) -> bool {
+        if !node.is_up(now) {
+            return false;
+        }
+        let local = |node: &NodeHealth| self.zone.is_some() && node.zone == self.zone;
+        local(node) || !self.nodes.iter().any(|node| local(node) && node.is_up(now))
+    }
+
+    pub fn addrs(&self) -> Vec<String> {
+        self.nodes.iter().map(|node| node.addr.clone()).collect()
+    }
+
+    /// #Descriptions: a node for a stale read, `None` if all nodes are down.
+    pub fn pick_read(&mut self) -> Option<String> {
+        let now = Instant::now();
+        match self.strategy {
+            Strategy::RoundRobin => {
+                for _ in 0..self.nodes.len() {
+                    let node = &self.nodes[self.next % self.nodes.len()];
+                    self.next = (self.next + 1) % self.nodes.len();
+                    if self.may_read(node, now) {
+                        return Some(node.addr.clone());
+                    }
+                }
+                None
+            }
+            // nodes never measured go first, so that every node gets measured
+            Strategy::LatencyAware => self
+                .nodes
+                .iter()
+                .filter(|node| self.may_read(node, now))
+                .min_by(|a, b| {
+                    let a = a.latency_ms.unwrap_or(0.0);
+                    let b = b.latency_ms.unwrap_or(0.0);
+                    a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .map(|node| node.addr.clone()),
+        }
+    }
+
+    /// #Descriptions: the node for writes and linearizable reads, the leader if known
+    /// and up, otherwise any node that is up.
+    pub fn pick_leader(&mut self) -> Option<String> {
+        let now = Instant::now();
+        if let Some(leader) = &self.leader {
+            if self.nodes.iter().any(|node| &node.addr == leader && node.is_up(now)) {
+                return Some(leader.clone());
+            }
+        }
+        self.nodes
+            .iter()
+            .find(|node| node.is_up(now))
+            .map(|node| node.addr.clone())
+    }
+
+    pub fn leader(&self) -> Option<&String> {
+        self.leader.as_ref()
+    }
+
+    pub fn set_leader(&mut self, leader: Option<String>) {
+        self.leader = leader;
+        self.leader_ballot = 0;
+    }
+
+    /// #Descriptions: a follower named `addr` the leader of round `ballot`, returns
+    /// whether it is followed. A hint older than the leader known is not, a leader
+    /// missing from the nodes is added to them.
+    pub fn follow_hint(&mut self, addr: String, ballot: u64) -> bool {
+        if self.leader.is_some() && ballot < self.leader_ballot {
+            return false;
+        }
+        if !self.nodes.iter().any(|node| node.addr == addr) {
+            self.nodes.push(NodeHealth::new(addr.clone()));
+        }
+        self.leader = Some(addr);
+        self.leader_ballot = ballot;
+        true
+    }
+
+    /// #Descriptions: `addr` answered a request in `latency`.
+    pub fn mark_up(&mut self, addr: &str, latency: Duration) {
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.addr == addr) {
+            let latency_ms = latency.as_secs_f64() * 1000.0;
+            node.down_until = None;
+            node.latency_ms = Some(match node.latency_ms {
+                Some(avg) => avg + LATENCY_WEIGHT * (latency_ms - avg),
+                None => latency_ms,
+            });
+        }
+    }
+
+    /// #Descriptions: `addr` could not be reached, leave it out for `DOWN_BACKOFF`.
+    pub fn mark_down(&mut self, addr: &str) {
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.addr == addr) {
+            node.down_until = Some(Instant::now() + DOWN_BACKOFF);
+        }
+        if self.leader.as_deref() == Some(addr) {
+            self.leader = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<String> {
+        vec!["n1:6142".to_string(), "n2:6142".to_string(), "n3:6142".to_string()]
+    }
+
+    #[test]
+    fn test_balancer_excludes_down_nodes() {
+        let mut balancer = Balancer::new(addrs(), Strategy::RoundRobin);
+        balancer.mark_down("n2:6142");
+        let picked: Vec<String> = (0..4).filter_map(|_| balancer.pick_read()).collect();
+        assert_eq!(picked, vec!["n1:6142", "n3:6142", "n1:6142", "n3:6142"]);
+
+        balancer.set_leader(Some("n3:6142".to_string()));
+        assert_eq!(balancer.pick_leader().as_deref(), Some("n3:6142"));
+        balancer.mark_down("n3:6142");
+        assert_eq!(balancer.leader(), None);
+        assert_eq!(balancer.pick_leader().as_deref(), Some("n1:6142"));
+
+        let mut balancer = Balancer::new(addrs(), Strategy::LatencyAware);
+        balancer.mark_up("n1:6142", Duration::from_millis(30));
+        balancer.mark_up("n2:6142", Duration::from_millis(5));
+        balancer.mark_up("n3:6142", Duration::from_millis(10));
+        assert_eq!(balancer.pick_read().as_deref(), Some("n2:6142"));
+    }
+
+    #[test]
+    fn test_balancer_follows_hints() {
+        let mut balancer = Balancer::new(addrs(), Strategy::RoundRobin);
+        assert!(balancer.follow_hint("n2:6142".to_string(), 2));
+        assert_eq!(balancer.pick_leader().as_deref(), Some("n2:6142"));
+        // a follower that did not hear of the new leader yet
+        assert!(!balancer.follow_hint("n1:6142".to_string(), 1));
+        assert_eq!(balancer.leader().map(String::as_str), Some("n2:6142"));
+
+        assert!(balancer.follow_hint("n4:6142".to_string(), 3));
+        assert_eq!(balancer.pick_leader().as_deref(), Some("n4:6142"));
+        assert_eq!(balancer.addrs().len(), 4);
+    }
+
+    #[test]
+    fn test_balancer_prefers_local_zone() {
+        let mut balancer = Balancer::new(addrs(), Strategy::RoundRobin);
+        let zones = vec![
+            Some("us".to_string()),
+            Some("eu".to_string()),
+            Some("eu".to_string()),
+        ];
+        balancer.set_zones(Some("eu".to_string()), zones);
+        let picked: Vec<String> = (0..4).filter_map(|_| balancer.pick_read()).collect();
+        assert_eq!(picked, vec!["n2:6142", "n3:6142", "n2:6142", "n3:6142"]);
+
+        // other zones once the local nodes are down
+        balancer.mark_down("n2:6142");
+        balancer.mark_down("n3:6142");
+        assert_eq!(balancer.pick_read().as_deref(), Some("n1:6142"));
+    }
+}
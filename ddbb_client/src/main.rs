@@ -1,5 +1,6 @@
 //Imports
 #![allow(unused)]
+use std::collections::HashMap;
 use std::env;
 use std::error::Error;
 
@@ -8,17 +9,42 @@ use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream, ToSocketAddrs},
     sync::mpsc,
+    task::JoinHandle,
 };
 //Serde - used for serializing (turning into bytes) and deserializing messages
 use serde::{Serialize, Deserialize};
 use async_stream::try_stream;
 use bytes::Bytes;
 use std::io::{ErrorKind};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio_stream::Stream;
 use tracing::{debug, instrument};
-use ddbb_libs::data_structure::{CommandEntry, DataEntry, FrameCast, MessageEntry};
+use ddbb_libs::data_structure::{AdminEntry, AuthEntry, CommandEntry, DataEntry, ElectionEventEntry, FrameCast, KeepAliveEntry, MessageEntry, NotLeaderEntry, WatchDelivery};
 use ddbb_libs::connection::Connection;
+use ddbb_libs::frame::Frame;
+
+mod balancer;
+mod retry;
+mod shell;
+mod watches;
+use balancer::Balancer;
+use retry::RetryPolicy;
+use watches::{WatchUpdate, Watches};
+use rustyline::error::ReadlineError;
+
+/// How long the server waits for a command to be decided
+const REQUEST_TIMEOUT_MS: u64 = 1000;
+/// Pairs sent per chunk by `load`, each written by a single proposal
+const BULK_LOAD_CHUNK_KEYS: usize = 1000;
+/// Keys deleted per chunk by `rmp`, each deleted by a single proposal
+const DELETE_PREFIX_CHUNK_KEYS: u64 = 1000;
+/// Keys offered at most by the tab completion of the shell
+const COMPLETION_MAX_KEYS: usize = 100;
+/// A keepalive stream refreshes its session this many times per ttl
+const SESSION_REFRESHES_PER_TTL: u32 = 3;
+/// A keepalive stream silent for the ttl divided by this is opened again
+const SESSION_SILENCE_PER_TTL: u64 = 2;
 
 #[tokio::main]
 async fn main()  {
@@ -27,6 +53,19 @@ async fn main()  {
     // let sender_messages = sender_peers.clone();
     // default cmd
     let mut user_cmd: CommandEntry = CommandEntry::Empty;
+    // (namespace, token) the commands are scoped to, set by `use`
+    let mut namespace: Option<(String, String)> = None;
+    // nodes from `DDBB_NODES`, with their health
+    let balancer = Arc::new(Mutex::new(Balancer::from_env()));
+    // revision the reads run at, set by `snapshot`
+    let mut pinned: Option<u64> = None;
+    // elections campaigned in by `elect`, until `resign`
+    let mut campaigns: HashMap<String, JoinHandle<()>> = HashMap::new();
+    // the session opened or resumed by `session`, with the task keeping it alive
+    let mut session: Option<(u64, JoinHandle<()>)> = None;
+    // the watches registered by `watch`, until `unwatch`, printed as their writes come
+    let (mut watches, watch_updates) = Watches::new(balancer.clone());
+    tokio::spawn(watch_printer(watch_updates));
     
     //Spawn threads
     // tokio::spawn(async move {
@@ -40,18 +79,29 @@ async fn main()  {
     //    println!("my parameter {:#?}", opt)
     // }
     let sign = format!(">>");
-    
-    //Std:io is required for the read_line method; needs to be imported here in order to not conflict with Tokio
-    use std::io::{Write};
+    // tab completes the commands, and the keys from a scan of the cluster
+    let keys_balancer = balancer.clone();
+    let mut editor = shell::editor(Box::new(move |namespace, prefix| scan_keys(&keys_balancer, namespace, prefix)))
+        .expect("Could not start the shell");
+
     //Loop through and read input from the command line of the client
     loop {
         //Get the input command
-        print!("{}", sign);
-        std::io::stdout().flush().unwrap();
-        let mut input = String::new();
-        match std::io::stdin().read_line(&mut input) {
-            Ok(_) => (),
-            Err(err) => println!("Could not parse input: {}", err)
+        editor.helper_mut().unwrap().namespace = namespace.clone();
+        let input = match editor.readline(&sign) {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Could not parse input: {}", err);
+                continue;
+            }
+        };
+        if !input.trim().is_empty() {
+            editor.add_history_entry(input.trim());
+            if let Some(path) = shell::history_path() {
+                let _ = editor.save_history(&path);
+            }
         }
         //Vectorize the input - check the first word to determine if it is a get or a put message
         let input_vector:Vec<&str> = input.trim().split(" ").collect();
@@ -62,23 +112,401 @@ async fn main()  {
             if input_vector.len() == 2 {
                 // sender_messages.send(("get", bincode::serialize(&input).unwrap())).await.unwrap();
                 user_cmd = CommandEntry::GetValue { key: input_vector[1].to_string()};
-                message_sender(user_cmd).await;
+                message_sender(user_cmd, &namespace, pinned, &balancer).await;
+            } else if let (3, Ok(revision)) = (input_vector.len(), input_vector[2].parse::<u64>()) {
+                // the value as it was at that revision, as in a snapshot
+                user_cmd = CommandEntry::GetValue { key: input_vector[1].to_string()};
+                message_sender(user_cmd, &namespace, Some(revision), &balancer).await;
             } else {
                 println!(" -> ERROR: Incorrect  command");
             }
             
 
+        }
+        else if input_vector[0] == "sget" {
+            if input_vector.len() == 2 {
+                user_cmd = CommandEntry::StaleGet { key: input_vector[1].to_string() };
+                message_sender(user_cmd, &namespace, pinned, &balancer).await;
+            } else {
+                println!(" -> ERROR: Incorrect  command");
+            }
         }
         else if input_vector[0] == "set" {
             if input_vector.len() == 3 {
                 // sender_messages.send(("set", bincode::serialize(&input).unwrap())).await.unwrap();
                 user_cmd = CommandEntry::SetValue { key: input_vector[1].to_string(), value: Bytes::from(input_vector[2].to_string()) };
-                message_sender(user_cmd).await;
+                message_sender(user_cmd, &namespace, pinned, &balancer).await;
             } else {
                 println!(" -> ERROR: Incorrect command");
             }
             
         }
+        else if input_vector[0] == "cas" {
+            if input_vector.len() == 4 {
+                match input_vector[3].parse::<u64>() {
+                    Ok(expected_mod_rev) => {
+                        user_cmd = CommandEntry::PutIfRevision { key: input_vector[1].to_string(), value: Bytes::from(input_vector[2].to_string()), expected_mod_rev };
+                        message_sender(user_cmd, &namespace, pinned, &balancer).await;
+                    }
+                    Err(_) => println!(" -> ERROR: The revision needs to be a number"),
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+
+        }
+        else if input_vector[0] == "fset" {
+            if input_vector.len() == 5 {
+                match input_vector[4].parse::<u64>() {
+                    Ok(token) => {
+                        user_cmd = CommandEntry::FencedSet { key: input_vector[1].to_string(), value: Bytes::from(input_vector[2].to_string()), election: input_vector[3].to_string(), token };
+                        message_sender(user_cmd, &namespace, pinned, &balancer).await;
+                    }
+                    Err(_) => println!(" -> ERROR: The token needs to be a number"),
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+
+        }
+        else if input_vector[0] == "eset" {
+            match &session {
+                _ if input_vector.len() != 3 => println!(" -> ERROR: Incorrect command"),
+                Some((id, _)) => {
+                    user_cmd = CommandEntry::EphemeralSet { session: *id, key: input_vector[1].to_string(), value: Bytes::from(input_vector[2].to_string()) };
+                    message_sender(user_cmd, &namespace, pinned, &balancer).await;
+                }
+                None => println!(" -> ERROR: No session, `session open <ttl_ms>` first"),
+            }
+        }
+        else if input_vector[0] == "session" {
+            let number = |arg: &str| arg.parse::<u64>().ok();
+            match input_vector[1..] {
+                [] => match &session {
+                    Some((id, _)) => println!(" -> session {}", id),
+                    None => println!(" -> no session"),
+                },
+                _ if input_vector[1] != "close" && session.is_some() => {
+                    println!(" -> ERROR: Already in a session, `session close` first")
+                }
+                ["open", ttl_ms] => match number(ttl_ms) {
+                    Some(ttl_ms) => match session_request(&balancer, CommandEntry::OpenSession { ttl_ms }).await {
+                        Ok(msg) => match msg.strip_prefix("session ").and_then(number) {
+                            Some(id) => {
+                                println!(" -> session {} open", id);
+                                session = Some((id, tokio::spawn(session_keeper(balancer.clone(), id, ttl_ms))));
+                            }
+                            None => println!(" -> ERROR: unexpected reply: {}", msg),
+                        },
+                        Err(e) => print_error(&e),
+                    },
+                    None => println!(" -> ERROR: The ttl needs to be a number of milliseconds"),
+                },
+                // e.g. after restarting the shell, within the ttl of the session
+                ["resume", id, ttl_ms] => match (number(id), number(ttl_ms)) {
+                    (Some(id), Some(ttl_ms)) => match session_request(&balancer, CommandEntry::KeepAlive { session: id }).await {
+                        Ok(_) => {
+                            println!(" -> session {} resumed", id);
+                            session = Some((id, tokio::spawn(session_keeper(balancer.clone(), id, ttl_ms))));
+                        }
+                        Err(e) => print_error(&e),
+                    },
+                    _ => println!(" -> ERROR: The id and ttl need to be numbers"),
+                },
+                ["close"] => match session.take() {
+                    Some((id, keeper)) => {
+                        keeper.abort();
+                        match session_request(&balancer, CommandEntry::CloseSession { session: id }).await {
+                            Ok(msg) => println!(" -> {}", msg),
+                            Err(e) => print_error(&e),
+                        }
+                    }
+                    None => println!(" -> ERROR: No session"),
+                },
+                _ => println!(" -> ERROR: Incorrect command"),
+            }
+        }
+        else if input_vector[0] == "stat" {
+            if input_vector.len() == 2 {
+                user_cmd = CommandEntry::Stat { key: input_vector[1].to_string() };
+                message_sender(user_cmd, &namespace, pinned, &balancer).await;
+            } else {
+                println!(" -> ERROR: Incorrect  command");
+            }
+        }
+        else if input_vector[0] == "scan" {
+            if input_vector.len() <= 2 {
+                user_cmd = CommandEntry::Scan { prefix: input_vector.get(1).unwrap_or(&"").to_string() };
+                message_sender(user_cmd, &namespace, pinned, &balancer).await;
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "ls" {
+            if input_vector.len() <= 2 {
+                user_cmd = CommandEntry::ListChildren { path: input_vector.get(1).unwrap_or(&"/").to_string() };
+                message_sender(user_cmd, &namespace, pinned, &balancer).await;
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "rmr" {
+            if input_vector.len() == 2 {
+                user_cmd = CommandEntry::DeleteTree { path: input_vector[1].to_string() };
+                message_sender(user_cmd, &namespace, pinned, &balancer).await;
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "rmp" {
+            let keys_per_sec = match input_vector.get(2) {
+                Some(rate) => rate.parse::<u64>().ok().filter(|rate| *rate > 0),
+                None => Some(u64::MAX),
+            };
+            match keys_per_sec {
+                Some(keys_per_sec) if input_vector.len() <= 3 => {
+                    if let Err(e) = delete_prefix(input_vector[1], keys_per_sec, &namespace, &balancer).await {
+                        println!(" -> ERROR: {}", e);
+                    }
+                }
+                _ => println!(" -> ERROR: Incorrect command"),
+            }
+        }
+        else if input_vector[0] == "snapshot" {
+            if input_vector.len() == 1 {
+                match pin_revision(&balancer).await {
+                    Ok(revision) => {
+                        println!(" -> reads run at revision {} until `snapshot end`", revision);
+                        pinned = Some(revision);
+                    }
+                    Err(e) => print_error(&e),
+                }
+            } else if input_vector.len() == 2 && input_vector[1] == "end" {
+                pinned = None;
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "load" {
+            if input_vector.len() == 2 {
+                if let Err(e) = bulk_load(input_vector[1], &namespace, &balancer).await {
+                    println!(" -> ERROR: {}", e);
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "watch" {
+            let after_revision = match input_vector.get(2) {
+                Some(revision) => revision.parse::<u64>().ok(),
+                None => Some(0),
+            };
+            let delivery = match input_vector.get(3) {
+                None => Some(WatchDelivery::Each),
+                Some(&"batched") => Some(WatchDelivery::Batched),
+                Some(&"latest") => Some(WatchDelivery::Latest),
+                Some(_) => None,
+            };
+            match (after_revision, delivery) {
+                _ if input_vector.len() > 4 => println!(" -> ERROR: Incorrect command"),
+                _ if namespace.is_some() => println!(" -> ERROR: Watches are not scoped to a namespace, `use` without one"),
+                (Some(after_revision), Some(delivery)) => {
+                    let prefix = input_vector.get(1).unwrap_or(&"").to_string();
+                    let id = watches.watch(prefix, after_revision, delivery);
+                    println!(" -> watch {} registered", id);
+                }
+                (None, _) => println!(" -> ERROR: The revision needs to be a number"),
+                (_, None) => println!(" -> ERROR: Deliver the writes `batched` or `latest` only"),
+            }
+        }
+        else if input_vector[0] == "watches" {
+            for (id, prefix, revision) in watches.list() {
+                println!(" -> watch {} on {:?}, seen up to revision {}", id, prefix, revision);
+            }
+        }
+        else if input_vector[0] == "unwatch" {
+            match input_vector.get(1).and_then(|id| id.parse::<u64>().ok()) {
+                _ if input_vector.len() != 2 => println!(" -> ERROR: Incorrect command"),
+                Some(id) if watches.unwatch(id) => println!(" -> watch {} stopped", id),
+                Some(id) => println!(" -> ERROR: No watch {}", id),
+                None => println!(" -> ERROR: The watch id needs to be a number"),
+            }
+        }
+        else if input_vector[0] == "elect" {
+            let ttl_ms = input_vector.get(3).and_then(|ttl| ttl.parse::<u64>().ok());
+            match ttl_ms {
+                _ if input_vector.len() != 4 => println!(" -> ERROR: Incorrect command"),
+                _ if namespace.is_some() => println!(" -> ERROR: Elections are not scoped to a namespace, `use` without one"),
+                _ if campaigns.contains_key(input_vector[1]) => println!(" -> ERROR: Already campaigning in {}, `resign` first", input_vector[1]),
+                Some(ttl_ms) => {
+                    let election = input_vector[1].to_string();
+                    let campaign = tokio::spawn(elect_sender(balancer.clone(), election.clone(), input_vector[2].to_string(), ttl_ms));
+                    campaigns.insert(election, campaign);
+                }
+                None => println!(" -> ERROR: The ttl needs to be a number of milliseconds"),
+            }
+        }
+        else if input_vector[0] == "resign" {
+            if input_vector.len() == 2 {
+                match campaigns.remove(input_vector[1]) {
+                    // the node resigns the candidate once its connection is closed
+                    Some(campaign) => campaign.abort(),
+                    None => println!(" -> ERROR: Not campaigning in {}", input_vector[1]),
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "slowlog" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::SlowLog).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "metrics" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::Metrics).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "catchup" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::CatchUp).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "status" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::Status).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "backup" {
+            // the leader backs itself up by default
+            match input_vector.get(1).map_or(Ok(0), |node| node.parse::<u64>()) {
+                Ok(node) if input_vector.len() <= 2 => {
+                    if let Err(e) = admin_sender(&balancer, AdminEntry::Backup { node }).await {
+                        println!(" -> ERROR: {}", e);
+                    }
+                }
+                _ => println!(" -> ERROR: Incorrect command"),
+            }
+        }
+        else if input_vector[0] == "compact" {
+            match input_vector.get(1).map(|revision| revision.parse::<u64>()) {
+                Some(Ok(revision)) if input_vector.len() == 2 => {
+                    if let Err(e) = admin_sender(&balancer, AdminEntry::CompactRevision { revision }).await {
+                        println!(" -> ERROR: {}", e);
+                    }
+                }
+                _ => println!(" -> ERROR: Incorrect command"),
+            }
+        }
+        else if input_vector[0] == "keyspace" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::Keyspace).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "cluster-status" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::ClusterStatus).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "topology" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::ClusterTopology).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "latency" {
+            // the msgs the leader sends to a peer are delayed
+            let args: Vec<Option<u64>> = input_vector[1..].iter().map(|arg| arg.parse::<u64>().ok()).collect();
+            let admin = match args[..] {
+                [Some(peer), Some(delay_ms)] => Some(AdminEntry::SetLinkLatency { peer, delay_ms, jitter_ms: 0 }),
+                [Some(peer), Some(delay_ms), Some(jitter_ms)] => Some(AdminEntry::SetLinkLatency { peer, delay_ms, jitter_ms }),
+                _ => None,
+            };
+            match admin {
+                Some(admin) => if let Err(e) = admin_sender(&balancer, admin).await {
+                    println!(" -> ERROR: {}", e);
+                },
+                None => println!(" -> ERROR: Incorrect command"),
+            }
+        }
+        else if input_vector[0] == "step-down" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::StepDown).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "config" {
+            let admin = match input_vector.len() {
+                1 => Some(AdminEntry::Config),
+                3 => Some(AdminEntry::SetConfig { name: input_vector[1].to_string(), value: input_vector[2].to_string() }),
+                _ => None,
+            };
+            match admin {
+                Some(admin) => if let Err(e) = admin_sender(&balancer, admin).await {
+                    println!(" -> ERROR: {}", e);
+                },
+                None => println!(" -> ERROR: Incorrect command"),
+            }
+        }
+        else if input_vector[0] == "events" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::Events).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "list-backups" {
+            if let Err(e) = admin_sender(&balancer, AdminEntry::ListBackups).await {
+                println!(" -> ERROR: {}", e);
+            }
+        }
+        else if input_vector[0] == "restore" {
+            if input_vector.len() == 2 {
+                if let Err(e) = admin_sender(&balancer, AdminEntry::RestoreBackup { name: input_vector[1].to_string() }).await {
+                    println!(" -> ERROR: {}", e);
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "export" {
+            if input_vector.len() == 2 {
+                if let Err(e) = admin_sender(&balancer, AdminEntry::ExportSnapshot { path: input_vector[1].to_string() }).await {
+                    println!(" -> ERROR: {}", e);
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "use" {
+            match input_vector.len() {
+                1 => namespace = None,
+                2 => namespace = Some((input_vector[1].to_string(), String::new())),
+                3 => namespace = Some((input_vector[1].to_string(), input_vector[2].to_string())),
+                _ => println!(" -> ERROR: Incorrect command"),
+            }
+        }
+        else if input_vector[0] == "nscreate" {
+            if input_vector.len() == 3 || input_vector.len() == 4 {
+                match input_vector[2].parse::<u64>() {
+                    Ok(max_keys) => {
+                        let token = input_vector.get(3).map(|token| token.to_string());
+                        let admin = AdminEntry::CreateNamespace { name: input_vector[1].to_string(), max_keys, token };
+                        if let Err(e) = admin_sender(&balancer, admin).await {
+                            println!(" -> ERROR: {}", e);
+                        }
+                    }
+                    Err(_) => println!(" -> ERROR: The max keys needs to be a number"),
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
+        else if input_vector[0] == "nsdelete" {
+            if input_vector.len() == 2 {
+                if let Err(e) = admin_sender(&balancer, AdminEntry::DeleteNamespace { name: input_vector[1].to_string() }).await {
+                    println!(" -> ERROR: {}", e);
+                }
+            } else {
+                println!(" -> ERROR: Incorrect command");
+            }
+        }
         else{
             //If it is not a put or a get
             println!(" -> ERROR: Unknown command");
@@ -87,58 +515,459 @@ async fn main()  {
 
     }
 }
-async fn message_sender(mut user_cmd: CommandEntry) -> Result<(), Box<dyn Error>>{
-    let mut tcp_stream = TcpStream::connect("127.0.0.1:6142").await?;
+/// Connect to the node at `addr`, sending the token in `DDBB_AUTH_TOKEN` if it is set.
+async fn connect(addr: &str) -> ddbb_libs::Result<Connection> {
+    let mut tcp_stream = TcpStream::connect(addr).await?;
     let mut connection = Connection::new(tcp_stream);
+    if let Ok(token) = env::var("DDBB_AUTH_TOKEN") {
+        connection.write_frame(&AuthEntry { token }.to_frame()).await?;
+        let res = connection.read_frame().await?.ok_or(ddbb_libs::Error::ConnectionClosed)?;
+        if let MessageEntry::Error {err_msg} = *MessageEntry::from_frame(&res)? {
+            return Err(ddbb_libs::Error::from_message(&err_msg));
+        }
+    }
+    Ok(connection)
+}
+
+/// Send `frame` on a new connection and return the reply as is.
+async fn exchange(addr: &str, frame: &Frame) -> ddbb_libs::Result<Frame> {
+    let mut connection = connect(addr).await?;
+    connection.write_frame(frame).await?;
+    connection.read_frame().await?.ok_or(ddbb_libs::Error::ConnectionClosed)
+}
+
+/// A `MessageEntry::Error` or a `NotLeaderEntry` reply as error.
+fn reply_result(res: Frame) -> ddbb_libs::Result<Frame> {
+    if NotLeaderEntry::from_frame(&res).is_ok() {
+        return Err(ddbb_libs::Error::NotLeader);
+    }
+    if let Ok(msg) = MessageEntry::from_frame(&res) {
+        if let MessageEntry::Error {err_msg} = *msg {
+            return Err(ddbb_libs::Error::from_message(&err_msg));
+        }
+    }
+    Ok(res)
+}
+
+/// Send `frame` on a new connection and return the reply, a `MessageEntry::Error` as error.
+async fn request(addr: &str, frame: &Frame) -> ddbb_libs::Result<Frame> {
+    exchange(addr, frame).await.and_then(reply_result)
+}
+
+/// Send `frame` to the node picked by `balancer`, any node for a `stale` read and
+/// otherwise the leader, keeping track of the health of the node. A follower naming
+/// the leader instead of serving the request is followed at once, but only once.
+async fn routed_request(balancer: &Arc<Mutex<Balancer>>, frame: &Frame, stale: bool) -> ddbb_libs::Result<Frame> {
+    let mut redirected = false;
+    loop {
+        if !stale && balancer.lock().unwrap().leader().is_none() {
+            find_leader(balancer).await;
+        }
+        let addr = {
+            let mut balancer = balancer.lock().unwrap();
+            if stale { balancer.pick_read() } else { balancer.pick_leader() }
+        };
+        let addr = addr.ok_or_else(|| ddbb_libs::Error::Unavailable("no node is up".to_string()))?;
+        let started = Instant::now();
+        let res = exchange(&addr, frame).await;
+        if let Some(hint) = res.as_ref().ok().and_then(|res| NotLeaderEntry::from_frame(res).ok()) {
+            let mut balancer = balancer.lock().unwrap();
+            balancer.mark_up(&addr, started.elapsed());
+            // without an address the leader is found through `status`
+            let followed = match hint.leader_addr {
+                Some(leader_addr) => balancer.follow_hint(leader_addr, hint.ballot),
+                None => {
+                    balancer.set_leader(None);
+                    false
+                }
+            };
+            if followed && !redirected {
+                redirected = true;
+                continue;
+            }
+            return Err(ddbb_libs::Error::NotLeader);
+        }
+        let res = res.and_then(reply_result);
+        match &res {
+            Ok(_) => balancer.lock().unwrap().mark_up(&addr, started.elapsed()),
+            Err(ddbb_libs::Error::IoError(_)) | Err(ddbb_libs::Error::ConnectionClosed) => {
+                balancer.lock().unwrap().mark_down(&addr)
+            }
+            Err(ddbb_libs::Error::NotLeader) => balancer.lock().unwrap().set_leader(None),
+            Err(_) => {}
+        }
+        return res;
+    }
+}
+
+/// Ask the nodes for their status until one of them knows the leader.
+async fn find_leader(balancer: &Arc<Mutex<Balancer>>) {
+    let addrs = balancer.lock().unwrap().addrs();
+    let mut statuses = Vec::new();
+    for addr in addrs {
+        let status = request(&addr, &AdminEntry::Status.to_frame()).await.and_then(|res| {
+            match *MessageEntry::from_frame(&res)? {
+                MessageEntry::Success { msg } => Ok(serde_json::from_str::<serde_json::Value>(&msg)?),
+                MessageEntry::Error { err_msg } => Err(ddbb_libs::Error::from_message(&err_msg)),
+            }
+        });
+        match status {
+            Ok(status) => statuses.push((addr, status)),
+            Err(_) => balancer.lock().unwrap().mark_down(&addr),
+        }
+    }
+    let leader = statuses.iter().find_map(|(_, status)| status["leader"].as_u64());
+    let leader_addr = statuses
+        .into_iter()
+        .find(|(_, status)| leader.is_some() && status["node_id"].as_u64() == leader)
+        .map(|(addr, _)| addr);
+    balancer.lock().unwrap().set_leader(leader_addr);
+}
+
+/// The keys under `prefix` for the tab completion of the shell, none if the scan fails.
+fn scan_keys(balancer: &Arc<Mutex<Balancer>>, namespace: &Option<(String, String)>, prefix: &str) -> Vec<String> {
+    let mut cmd = CommandEntry::Scan { prefix: prefix.to_string() };
+    if let Some((namespace, token)) = namespace {
+        cmd = CommandEntry::Namespaced { namespace: namespace.clone(), token: token.clone(), cmd: Box::new(cmd) };
+    }
+    let frame = CommandEntry::Deadline { timeout_ms: REQUEST_TIMEOUT_MS, cmd: Box::new(cmd) }.to_frame();
+    // the editor completes synchronously, within the runtime of the shell
+    let res = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(routed_request(balancer, &frame, true))
+    });
+    match res.ok().and_then(|res| DataEntry::from_frame(&res).ok()).map(|data| *data) {
+        Some(DataEntry::KeyValues { pairs }) => pairs.into_iter().map(|(key, _)| key).take(COMPLETION_MAX_KEYS).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Pin the revision of the latest write on the leader, for reads at a consistent cut.
+async fn pin_revision(balancer: &Arc<Mutex<Balancer>>) -> ddbb_libs::Result<u64> {
+    let frame = CommandEntry::Revision.to_frame();
+    let res = RetryPolicy::default().run(true, || routed_request(balancer, &frame, false)).await?;
+    match *MessageEntry::from_frame(&res)? {
+        MessageEntry::Success { msg } => msg
+            .rsplit(' ')
+            .next()
+            .and_then(|revision| revision.parse().ok())
+            .ok_or_else(|| ddbb_libs::Error::Other(format!("unexpected reply: {}", msg))),
+        MessageEntry::Error { err_msg } => Err(ddbb_libs::Error::from_message(&err_msg)),
+    }
+}
+
+/// Send `user_cmd`, a read running at the `pinned` revision if there is one.
+async fn message_sender(mut user_cmd: CommandEntry, namespace: &Option<(String, String)>, pinned: Option<u64>, balancer: &Arc<Mutex<Balancer>>) -> Result<(), Box<dyn Error>>{
+    let with_deadline = |cmd: CommandEntry| {
+        let cmd = match namespace {
+            Some((namespace, token)) => CommandEntry::Namespaced { namespace: namespace.clone(), token: token.clone(), cmd: Box::new(cmd) },
+            None => cmd,
+        };
+        CommandEntry::Deadline { timeout_ms: REQUEST_TIMEOUT_MS, cmd: Box::new(cmd) }
+    };
     match user_cmd{
         CommandEntry::Empty => {
             println!("Wrong command!")
         },
-        CommandEntry::GetValue {
-            key
-        } => {
-            // client.set(&key, value).await?;
-            // println!("OK");
-            let cmd = CommandEntry::GetValue { key };
-            connection.write_frame(&cmd.to_frame()).await;
-            let res = connection.read_frame().await.unwrap().unwrap();
-
-            match *DataEntry::from_frame(&res).unwrap(){
-                DataEntry::KeyValue{key, value} => {
-                    println!("{:?}", value)
-                }
+        CommandEntry::GetValue { .. } | CommandEntry::StaleGet { .. } | CommandEntry::Stat { .. } | CommandEntry::Scan { .. } | CommandEntry::ListChildren { .. } | CommandEntry::SetValue { .. } | CommandEntry::PutIfRevision { .. } | CommandEntry::FencedSet { .. } | CommandEntry::DeleteTree { .. } | CommandEntry::EphemeralSet { .. } => {
+            // e.g. a cas is not sent again once it may have been proposed
+            let idempotent = retry::is_idempotent(&user_cmd);
+            let is_read = matches!(user_cmd, CommandEntry::GetValue { .. } | CommandEntry::StaleGet { .. } | CommandEntry::Scan { .. } | CommandEntry::ListChildren { .. });
+            let stale = matches!(user_cmd, CommandEntry::StaleGet { .. } | CommandEntry::Scan { .. } | CommandEntry::ListChildren { .. });
+            // any node that applied the pinned revision serves the same cut
+            let (user_cmd, stale) = match pinned {
+                Some(revision) if is_read => (CommandEntry::AtRevision { revision, cmd: Box::new(user_cmd) }, true),
+                _ => (user_cmd, stale),
+            };
+            let frame = with_deadline(user_cmd).to_frame();
+            match RetryPolicy::default().run(idempotent, || routed_request(balancer, &frame, stale)).await {
+                Ok(res) => print_reply(&res),
+                Err(e) => print_error(&e),
             }
         },
-        CommandEntry::SetValue {
-            key,
-            value
-        } => {
-            // client.set(&key, value).await?;
-            // println!("OK");
-            let cmd = CommandEntry::SetValue { key, value };
-            connection.write_frame(&cmd.to_frame()).await;
-            let res = connection.read_frame().await.unwrap().unwrap();
 
-            match *MessageEntry::from_frame(&res).unwrap(){
-                MessageEntry::Success {msg} => {
-                    println!("Receive success msg: {}", msg);
-                }
+        _ => {
+            println!("Wrong command!")
+        }
+
+    }
+    Ok(())
+}
 
-                MessageEntry::Error {err_msg} => {
-                    println!("Receive err_msg: {}", err_msg);
+/// Send the session command `cmd` through the leader, returning the msg of its reply.
+async fn session_request(balancer: &Arc<Mutex<Balancer>>, cmd: CommandEntry) -> ddbb_libs::Result<String> {
+    let idempotent = retry::is_idempotent(&cmd);
+    let frame = CommandEntry::Deadline { timeout_ms: REQUEST_TIMEOUT_MS, cmd: Box::new(cmd) }.to_frame();
+    let res = RetryPolicy::default().run(idempotent, || routed_request(balancer, &frame, false)).await?;
+    match *MessageEntry::from_frame(&res)? {
+        MessageEntry::Success { msg } => Ok(msg),
+        MessageEntry::Error { err_msg } => Err(ddbb_libs::Error::from_message(&err_msg)),
+    }
+}
 
+/// Keep `session` alive, with all its ephemeral keys, through a single stream to
+/// whichever node leads, so that it survives a failover, until it expired or the task
+/// is aborted.
+async fn session_keeper(balancer: Arc<Mutex<Balancer>>, session: u64, ttl_ms: u64) {
+    let policy = RetryPolicy::default();
+    loop {
+        if balancer.lock().unwrap().leader().is_none() {
+            find_leader(&balancer).await;
+        }
+        let addr = balancer.lock().unwrap().pick_leader();
+        let addr = match addr {
+            Some(addr) => addr,
+            None => {
+                tokio::time::sleep(policy.max_backoff).await;
+                continue;
+            }
+        };
+        match keep_alive_events(&addr, session, ttl_ms).await {
+            Err(e) if e.is_retryable() => {
+                balancer.lock().unwrap().mark_down(&addr);
+                println!(" -> session {}: {}, keeping it alive again", session, e);
+                tokio::time::sleep(policy.initial_backoff).await;
+            }
+            // its ephemeral keys are gone
+            Err(e) => {
+                println!(" -> session {} lost", session);
+                print_error(&e);
+                return;
+            }
+            Ok(()) => return,
+        }
+    }
+}
+
+/// Keep `session` alive through the node at `addr`, sending it a `KeepAlive`
+/// `SESSION_REFRESHES_PER_TTL` times per ttl, until the connection fails or a refresh
+/// is not answered within part of the ttl.
+async fn keep_alive_events(addr: &str, session: u64, ttl_ms: u64) -> ddbb_libs::Result<()> {
+    let mut connection = connect(addr).await?;
+    connection.write_frame(&CommandEntry::KeepAliveStream { session }.to_frame()).await?;
+    let silence = Duration::from_millis(ttl_ms / SESSION_SILENCE_PER_TTL);
+    let mut refresh = tokio::time::interval(Duration::from_millis(ttl_ms) / SESSION_REFRESHES_PER_TTL);
+    // "keeping session N alive"
+    let mut reply = keep_alive_reply(&mut connection, silence).await?;
+    loop {
+        // a refresh, of this session and all its ephemeral keys
+        if KeepAliveEntry::from_frame(&reply).is_err() {
+            if let MessageEntry::Error { err_msg } = *MessageEntry::from_frame(&reply)? {
+                return Err(ddbb_libs::Error::from_message(&err_msg));
+            }
+        }
+        refresh.tick().await;
+        connection.write_frame(&CommandEntry::KeepAlive { session }.to_frame()).await?;
+        reply = keep_alive_reply(&mut connection, silence).await?;
+    }
+}
+
+/// The next frame of a keepalive stream, failing once it stays silent for `silence`.
+async fn keep_alive_reply(connection: &mut Connection, silence: Duration) -> ddbb_libs::Result<Frame> {
+    match tokio::time::timeout(silence, connection.read_frame()).await {
+        Ok(frame) => frame?.ok_or(ddbb_libs::Error::ConnectionClosed),
+        Err(_) => Err(ddbb_libs::Error::Unavailable(format!("no keepalive for {:?}", silence))),
+    }
+}
+
+/// Write the `key value` lines of the file at `path`, in chunks of `BULK_LOAD_CHUNK_KEYS`.
+/// The next chunk is only sent once the previous one is decided, printing the progress.
+async fn bulk_load(path: &str, namespace: &Option<(String, String)>, balancer: &Arc<Mutex<Balancer>>) -> Result<(), Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    let pairs: Vec<(String, Bytes)> = content
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(key, value)| (key.to_string(), Bytes::from(value.to_string())))
+        .collect();
+    let started = Instant::now();
+    let mut loaded = 0;
+    for chunk in pairs.chunks(BULK_LOAD_CHUNK_KEYS) {
+        let mut cmd = CommandEntry::BulkLoad { pairs: chunk.to_vec() };
+        if let Some((namespace, token)) = namespace {
+            cmd = CommandEntry::Namespaced { namespace: namespace.clone(), token: token.clone(), cmd: Box::new(cmd) };
+        }
+        let frame = CommandEntry::Deadline { timeout_ms: REQUEST_TIMEOUT_MS, cmd: Box::new(cmd) }.to_frame();
+        RetryPolicy::default().run(true, || routed_request(balancer, &frame, false)).await?;
+        loaded += chunk.len();
+        let rate = loaded as f64 / started.elapsed().as_secs_f64().max(0.001);
+        println!(" -> loaded {} of {} pairs ({:.0} pairs/s)", loaded, pairs.len(), rate);
+    }
+    Ok(())
+}
+
+/// Delete the keys under `prefix` in chunks of `DELETE_PREFIX_CHUNK_KEYS`, at most
+/// `keys_per_sec` keys a second, printing the progress, until none is left.
+async fn delete_prefix(prefix: &str, keys_per_sec: u64, namespace: &Option<(String, String)>, balancer: &Arc<Mutex<Balancer>>) -> Result<(), Box<dyn Error>> {
+    let limit = DELETE_PREFIX_CHUNK_KEYS.min(keys_per_sec);
+    let mut cmd = CommandEntry::DeletePrefix { prefix: prefix.to_string(), limit };
+    if let Some((namespace, token)) = namespace {
+        cmd = CommandEntry::Namespaced { namespace: namespace.clone(), token: token.clone(), cmd: Box::new(cmd) };
+    }
+    let frame = CommandEntry::Deadline { timeout_ms: REQUEST_TIMEOUT_MS, cmd: Box::new(cmd) }.to_frame();
+    let started = Instant::now();
+    let mut deleted: u64 = 0;
+    loop {
+        let res = RetryPolicy::default().run(true, || routed_request(balancer, &frame, false)).await?;
+        // "X keys deleted, Y left, decided at Z"
+        let msg = match *MessageEntry::from_frame(&res)? {
+            MessageEntry::Success { msg } => msg,
+            MessageEntry::Error { err_msg } => return Err(err_msg.into()),
+        };
+        let words: Vec<&str> = msg.split_whitespace().collect();
+        let (chunk, left) = match (words.first(), words.get(3)) {
+            (Some(chunk), Some(left)) => (chunk.parse::<u64>()?, left.parse::<u64>()?),
+            _ => return Err(format!("unexpected reply: {}", msg).into()),
+        };
+        deleted += chunk;
+        let elapsed = started.elapsed().as_secs_f64().max(0.001);
+        println!(" -> deleted {} keys, {} left ({:.0} keys/s)", deleted, left, deleted as f64 / elapsed);
+        if chunk == 0 || left == 0 {
+            return Ok(());
+        }
+        // the next chunk waits until the keys deleted so far are within the rate
+        let due = deleted as f64 / keys_per_sec as f64;
+        if due > elapsed {
+            tokio::time::sleep(Duration::from_secs_f64(due - elapsed)).await;
+        }
+    }
+}
+
+/// Print what the watches tell, until the shell quits.
+async fn watch_printer(mut updates: mpsc::UnboundedReceiver<WatchUpdate>) {
+    while let Some(update) = updates.recv().await {
+        match update {
+            WatchUpdate::Event { watch, event } if event.deleted => {
+                println!(" -> watch {} [{}] {} deleted", watch, event.revision, event.key)
+            }
+            WatchUpdate::Event { watch, event } => {
+                println!(" -> watch {} [{}] {} = {:?}", watch, event.revision, event.key, event.value)
+            }
+            WatchUpdate::Coalesced { watch, revision } => {
+                println!(" -> watch {}: writes up to revision {} coalesced", watch, revision)
+            }
+            WatchUpdate::Resuming { watch, revision, reason } => {
+                println!(" -> watch {}: {}, resuming after revision {}", watch, reason, revision)
+            }
+            WatchUpdate::Stopped { watch, reason } => println!(" -> watch {} stopped: {}", watch, reason),
+        }
+    }
+}
+
+/// Campaign in `election` as `candidate`, on another node if the connection fails,
+/// until the task is aborted.
+async fn elect_sender(balancer: Arc<Mutex<Balancer>>, election: String, candidate: String, ttl_ms: u64) {
+    let policy = RetryPolicy::default();
+    let mut elected = false;
+    loop {
+        if balancer.lock().unwrap().leader().is_none() {
+            find_leader(&balancer).await;
+        }
+        let addr = balancer.lock().unwrap().pick_leader();
+        let addr = match addr {
+            Some(addr) => addr,
+            None => {
+                tokio::time::sleep(policy.max_backoff).await;
+                continue;
+            }
+        };
+        match elect_events(&addr, &election, &candidate, ttl_ms, &mut elected).await {
+            Err(e) if e.is_retryable() => {
+                balancer.lock().unwrap().mark_down(&addr);
+                if elected {
+                    println!(" -> election {:?}: you lost leadership", election);
+                    elected = false;
                 }
+                // the candidate joined keeps its place while it is refreshed within its ttl
+                println!(" -> election {:?}: {}, campaigning again", election, e);
+                tokio::time::sleep(policy.initial_backoff).await;
             }
-        },
+            Err(e) => {
+                println!(" -> election {:?} stopped", election);
+                print_error(&e);
+                return;
+            }
+            Ok(()) => return,
+        }
+    }
+}
 
-        _ => {
-            println!("Wrong command!")
+/// Campaign on the node at `addr` until the connection fails, printing each change
+/// of the leader.
+async fn elect_events(addr: &str, election: &str, candidate: &str, ttl_ms: u64, elected: &mut bool) -> ddbb_libs::Result<()> {
+    let mut connection = connect(addr).await?;
+    let cmd = CommandEntry::Elect { election: election.to_string(), candidate: candidate.to_string(), ttl_ms };
+    connection.write_frame(&cmd.to_frame()).await?;
+    loop {
+        let frame = connection.read_frame().await?.ok_or(ddbb_libs::Error::ConnectionClosed)?;
+        if let Ok(event) = ElectionEventEntry::from_frame(&frame) {
+            match event.leader {
+                _ if event.elected => match event.fencing_token {
+                    Some(token) => println!(" -> election {:?}: you are leader, fencing token {}", election, token),
+                    None => println!(" -> election {:?}: you are leader", election),
+                },
+                _ if *elected => println!(" -> election {:?}: you lost leadership", election),
+                Some(leader) => println!(" -> election {:?}: {} is leader", election, leader),
+                None => println!(" -> election {:?}: no leader known", election),
+            }
+            *elected = event.elected;
+            continue;
+        }
+        // "campaigning in <election>"
+        if let MessageEntry::Error { err_msg } = *MessageEntry::from_frame(&frame)? {
+            return Err(ddbb_libs::Error::from_message(&err_msg));
+        }
+    }
+}
+
+fn print_reply(res: &Frame) {
+    if let Ok(data) = DataEntry::from_frame(res) {
+        match *data {
+            DataEntry::KeyValue{key, value} => {
+                println!("{:?}", value)
+            }
+            DataEntry::KeyValues{pairs} => {
+                for (key, value) in pairs {
+                    println!("{} = {:?}", key, value)
+                }
+            }
+            DataEntry::KeyValueMeta{key, value, meta} => {
+                println!("{:?} (revision {}, version {})", value, meta.mod_rev, meta.version)
+            }
+            DataEntry::Stat{key, meta} => {
+                println!("{}", serde_json::to_string(&meta).unwrap_or_default())
+            }
+            DataEntry::Children{children} => {
+                for child in children {
+                    println!("{}", child)
+                }
+            }
         }
+    } else if let Ok(msg) = MessageEntry::from_frame(res) {
+        if let MessageEntry::Success {msg} = *msg {
+            println!("Receive success msg: {}", msg);
+        }
+    }
+}
 
+async fn admin_sender(balancer: &Arc<Mutex<Balancer>>, admin: AdminEntry) -> Result<(), Box<dyn Error>>{
+    let addr = balancer.lock().unwrap().pick_leader().ok_or("no node is up")?;
+    let mut connection = connect(&addr).await?;
+    connection.write_frame(&admin.to_frame()).await?;
+    let res = connection.read_frame().await.map_err(|e| e.to_string())?.ok_or("connection closed")?;
+    match *MessageEntry::from_frame(&res).map_err(|e| e.to_string())? {
+        MessageEntry::Success {msg} => println!("{}", msg),
+        MessageEntry::Error {err_msg} => println!("Receive err_msg: {}", err_msg),
     }
     Ok(())
 }
 
+fn print_error(error: &ddbb_libs::Error) {
+    if error.is_retryable() {
+        println!("Receive err_msg: {} (retryable)", error);
+    } else {
+        println!("Receive err_msg: {}", error);
+    }
+}
+
 //The message_receiver function handles the messages sent within the client's code
 async fn message_receiver(mut receiver: mpsc::Receiver<(&str, Vec<u8>)>) {
     //Record of the number of peers (i.e. active nodes - 1), default is 0
@@ -0,0 +1,138 @@
+use std::future::Future;
+use std::time::Duration;
+
+use ddbb_libs::data_structure::CommandEntry;
+use ddbb_libs::Error;
+
+/// When and how often a failed request is sent again, e.g. while the cluster
+/// fails over to a new leader.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// attempts in total, 1 for no retries
+    pub max_attempts: u32,
+    /// wait before the first retry, multiplied by `multiplier` after every retry
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_millis(1000),
+            multiplier: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn no_retries() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// #Descriptions: whether to send the request again after `error` on attempt
+    /// `attempt`, counted from 1. A request that is not idempotent is only sent again
+    /// when the server rejected it before proposing it, since after e.g. a timeout it
+    /// may still be applied.
+    pub fn should_retry(&self, attempt: u32, error: &Error, idempotent: bool) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        if idempotent {
+            error.is_retryable() || matches!(error, Error::Timeout(_))
+        } else {
+            matches!(
+                error,
+                Error::NotLeader | Error::Overloaded(_) | Error::Unavailable(_)
+            )
+        }
+    }
+
+    /// #Descriptions: how long to wait after attempt `attempt` failed.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// #Descriptions: run `request` until it succeeds or the policy gives up,
+    /// returning the last error.
+    pub async fn run<T, F, Fut>(&self, idempotent: bool, mut request: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match request().await {
+                Ok(res) => return Ok(res),
+                Err(e) if self.should_retry(attempt, &e, idempotent) => {
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// #Descriptions: whether applying `cmd` twice has the same effect as once.
+pub fn is_idempotent(cmd: &CommandEntry) -> bool {
+    match cmd {
+        CommandEntry::GetValue { .. }
+        | CommandEntry::StaleGet { .. }
+        | CommandEntry::Stat { .. }
+        | CommandEntry::SetValue { .. }
+        | CommandEntry::FencedSet { .. }
+        | CommandEntry::BulkLoad { .. }
+        | CommandEntry::DeletePrefix { .. }
+        | CommandEntry::Scan { .. }
+        | CommandEntry::ListChildren { .. }
+        | CommandEntry::Revision
+        | CommandEntry::KeepAlive { .. }
+        | CommandEntry::EphemeralSet { .. } => true,
+        CommandEntry::Deadline { cmd, .. }
+        | CommandEntry::Namespaced { cmd, .. }
+        | CommandEntry::AtRevision { cmd, .. } => is_idempotent(cmd),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::default();
+        let timeout = Error::Timeout("not decided".to_string());
+        assert!(policy.should_retry(1, &timeout, true));
+        // may still be applied
+        assert!(!policy.should_retry(1, &timeout, false));
+        assert!(policy.should_retry(1, &Error::NotLeader, false));
+        assert!(!policy.should_retry(1, &Error::Conflict("stale".to_string()), true));
+        assert!(!policy.should_retry(policy.max_attempts, &Error::NotLeader, true));
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(50));
+        assert_eq!(policy.backoff(3), Duration::from_millis(200));
+        assert_eq!(policy.backoff(10), policy.max_backoff);
+
+        let cas = CommandEntry::PutIfRevision {
+            key: "k1".to_string(),
+            value: Bytes::from("v1"),
+            expected_mod_rev: 0,
+        };
+        assert!(!is_idempotent(&cas));
+        assert!(is_idempotent(&CommandEntry::Deadline {
+            timeout_ms: 1000,
+            cmd: Box::new(CommandEntry::GetValue { key: "k1".to_string() }),
+        }));
+    }
+}
@@ -0,0 +1,158 @@
+use std::env;
+use std::path::PathBuf;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+/// Commands of the shell, completed on tab.
+pub const COMMANDS: &[&str] = &[
+    "get", "sget", "set", "cas", "fset", "stat", "scan", "ls", "rmr", "rmp", "snapshot", "load",
+    "watch", "elect", "resign", "slowlog", "metrics", "catchup", "status", "keyspace", "compact",
+    "cluster-status", "topology", "latency", "step-down", "config", "events", "list-backups",
+    "backup", "restore", "export", "use", "nscreate", "nsdelete", "session", "eset", "watches",
+    "unwatch",
+];
+
+/// Commands whose first argument is a key or a key prefix, completed from a scan.
+const KEY_COMMANDS: &[&str] = &[
+    "get", "sget", "set", "cas", "fset", "eset", "stat", "scan", "ls", "rmr", "rmp", "watch",
+];
+
+/// The keys under a prefix in a namespace, for the completion.
+pub type KeyLister = Box<dyn Fn(&Option<(String, String)>, &str) -> Vec<String>>;
+
+/// Completes the commands, and the keys of the commands taking one.
+pub struct ShellHelper {
+    keys: KeyLister,
+    /// the namespace the commands are scoped to, set by `use`
+    pub namespace: Option<(String, String)>,
+}
+
+impl ShellHelper {
+    pub fn new(keys: KeyLister) -> Self {
+        Self {
+            keys,
+            namespace: None,
+        }
+    }
+}
+
+/// #Descriptions: where the word under the cursor at `pos` starts in `line`, and its
+/// completions, from `keys` for the first argument of a `KEY_COMMANDS`.
+pub fn complete_line(
+    line: &str,
+    pos: usize,
+    keys: impl FnOnce(&str) -> Vec<String>,
+) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before.rfind(' ').map_or(0, |space| space + 1);
+    let word = &before[start..];
+    let mut args = before[..start].split_whitespace();
+    match (args.next(), args.next()) {
+        (None, _) => {
+            let commands = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .map(|command| command.to_string())
+                .collect();
+            (start, commands)
+        }
+        (Some(command), None) if KEY_COMMANDS.contains(&command) => {
+            let mut keys: Vec<String> = keys(word)
+                .into_iter()
+                .filter(|key| key.starts_with(word))
+                .collect();
+            keys.sort();
+            keys.dedup();
+            (start, keys)
+        }
+        _ => (start, Vec::new()),
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let keys = |prefix: &str| (self.keys)(&self.namespace, prefix);
+        let (start, words) = complete_line(line, pos, keys);
+        let pairs = words
+            .into_iter()
+            .map(|word| Pair {
+                display: word.clone(),
+                replacement: word,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// #Descriptions: the file the history of the shell is kept in, `DDBB_HISTORY` or
+/// `~/.ddbb_history`, none without a home directory.
+pub fn history_path() -> Option<PathBuf> {
+    if let Ok(path) = env::var("DDBB_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".ddbb_history"))
+}
+
+/// #Descriptions: a line editor completing with `keys`, with the history of the
+/// previous sessions loaded.
+pub fn editor(keys: KeyLister) -> rustyline::Result<Editor<ShellHelper>> {
+    let mut editor = Editor::<ShellHelper>::new()?;
+    editor.set_helper(Some(ShellHelper::new(keys)));
+    if let Some(path) = history_path() {
+        // none yet on the first session
+        let _ = editor.load_history(&path);
+    }
+    Ok(editor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(prefix: &str) -> Vec<String> {
+        vec!["app/a".to_string(), "app/b".to_string(), "other".to_string()]
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect()
+    }
+
+    #[test]
+    fn test_complete_line() {
+        assert_eq!(
+            complete_line("st", 2, keys),
+            (0, vec!["stat".to_string(), "status".to_string(), "step-down".to_string()])
+        );
+        assert_eq!(
+            complete_line("get app/", 8, keys),
+            (4, vec!["app/a".to_string(), "app/b".to_string()])
+        );
+        // only the first argument is a key
+        assert_eq!(complete_line("set app/a ap", 12, keys), (10, Vec::new()));
+        assert_eq!(complete_line("metrics o", 9, keys), (8, Vec::new()));
+        // the cursor in the middle of the line
+        assert_eq!(complete_line("get o app", 5, keys), (4, vec!["other".to_string()]));
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use ddbb_libs::data_structure::{
+    CommandEntry, FrameCast, MessageEntry, WatchBatchEntry, WatchDelivery, WatchEventEntry,
+};
+use ddbb_libs::frame::Frame;
+
+use crate::balancer::Balancer;
+use crate::retry::RetryPolicy;
+
+/// What the registered watches tell the application, on a single stream.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchUpdate {
+    /// a write to a key under the prefix of watch `watch`, each seen once
+    Event { watch: u64, event: WatchEventEntry },
+    /// some writes up to `revision` were dropped for a later one to the same key
+    Coalesced { watch: u64, revision: u64 },
+    /// the connection failed, the watch resumes after `revision` on any node
+    Resuming { watch: u64, revision: u64, reason: String },
+    /// the watch ended for good, e.g. its revision was compacted
+    Stopped { watch: u64, reason: String },
+}
+
+#[derive(Debug)]
+struct Registered {
+    prefix: String,
+    /// the last revision seen, where the watch resumes
+    revision: Arc<AtomicU64>,
+    task: JoinHandle<()>,
+}
+
+/// The watches of the application, each established again after a disconnect or a
+/// failover on whichever node is up, resuming after the last revision it saw, so
+/// that the application sees every write once on one stream.
+#[derive(Debug)]
+pub struct Watches {
+    balancer: Arc<Mutex<Balancer>>,
+    next_id: u64,
+    registered: BTreeMap<u64, Registered>,
+    updates: mpsc::UnboundedSender<WatchUpdate>,
+}
+
+impl Watches {
+    /// #Descriptions: no watches yet, and the stream of their updates.
+    pub fn new(balancer: Arc<Mutex<Balancer>>) -> (Self, mpsc::UnboundedReceiver<WatchUpdate>) {
+        let (updates, receiver) = mpsc::unbounded_channel();
+        let watches = Self {
+            balancer,
+            next_id: 1,
+            registered: BTreeMap::new(),
+            updates,
+        };
+        (watches, receiver)
+    }
+
+    /// #Descriptions: watch the writes under `prefix` after `after_revision`, 0 for
+    /// only the writes from now on, until `unwatch`. Returns its id.
+    pub fn watch(&mut self, prefix: String, after_revision: u64, delivery: WatchDelivery) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let revision = Arc::new(AtomicU64::new(after_revision));
+        let task = tokio::spawn(run_watch(
+            self.balancer.clone(),
+            id,
+            prefix.clone(),
+            revision.clone(),
+            delivery,
+            self.updates.clone(),
+        ));
+        self.registered.insert(id, Registered { prefix, revision, task });
+        id
+    }
+
+    /// #Descriptions: stop watch `id`, false if it was not registered.
+    pub fn unwatch(&mut self, id: u64) -> bool {
+        match self.registered.remove(&id) {
+            Some(registered) => {
+                registered.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The watches still running, by id, with their prefix and the last revision seen.
+    pub fn list(&self) -> Vec<(u64, String, u64)> {
+        self.registered
+            .iter()
+            .filter(|(_, registered)| !registered.task.is_finished())
+            .map(|(id, registered)| {
+                let revision = registered.revision.load(Ordering::Relaxed);
+                (*id, registered.prefix.clone(), revision)
+            })
+            .collect()
+    }
+}
+
+impl Drop for Watches {
+    fn drop(&mut self) {
+        for registered in self.registered.values() {
+            registered.task.abort();
+        }
+    }
+}
+
+/// #Descriptions: run watch `id`, on another node after each retryable failure.
+async fn run_watch(
+    balancer: Arc<Mutex<Balancer>>,
+    id: u64,
+    prefix: String,
+    revision: Arc<AtomicU64>,
+    delivery: WatchDelivery,
+    updates: mpsc::UnboundedSender<WatchUpdate>,
+) {
+    let policy = RetryPolicy::default();
+    loop {
+        let addr = balancer.lock().unwrap().pick_read();
+        let addr = match addr {
+            Some(addr) => addr,
+            None => {
+                tokio::time::sleep(policy.max_backoff).await;
+                continue;
+            }
+        };
+        match stream_watch(&addr, id, &prefix, &revision, delivery, &updates).await {
+            Err(e) if e.is_retryable() => {
+                balancer.lock().unwrap().mark_down(&addr);
+                let update = WatchUpdate::Resuming {
+                    watch: id,
+                    revision: revision.load(Ordering::Relaxed),
+                    reason: e.to_string(),
+                };
+                if updates.send(update).is_err() {
+                    return;
+                }
+                tokio::time::sleep(policy.initial_backoff).await;
+            }
+            Err(e) => {
+                let _ = updates.send(WatchUpdate::Stopped {
+                    watch: id,
+                    reason: e.to_string(),
+                });
+                return;
+            }
+            // nobody reads the updates anymore
+            Ok(()) => return,
+        }
+    }
+}
+
+/// #Descriptions: watch on the node at `addr` until the connection fails, keeping
+/// `revision` at the last revision seen.
+async fn stream_watch(
+    addr: &str,
+    id: u64,
+    prefix: &str,
+    revision: &AtomicU64,
+    delivery: WatchDelivery,
+    updates: &mpsc::UnboundedSender<WatchUpdate>,
+) -> ddbb_libs::Result<()> {
+    let mut connection = crate::connect(addr).await?;
+    let cmd = CommandEntry::Watch {
+        prefix: prefix.to_string(),
+        after_revision: revision.load(Ordering::Relaxed),
+        delivery,
+    };
+    connection.write_frame(&cmd.to_frame()).await?;
+    loop {
+        let frame = connection
+            .read_frame()
+            .await?
+            .ok_or(ddbb_libs::Error::ConnectionClosed)?;
+        let mut last = revision.load(Ordering::Relaxed);
+        for update in read_frame(&frame, id, &mut last)? {
+            if updates.send(update).is_err() {
+                return Ok(());
+            }
+        }
+        revision.store(last, Ordering::Relaxed);
+    }
+}
+
+/// #Descriptions: the updates of watch `id` carried by `frame`, leaving out the writes
+/// up to `last`, the last revision seen, and moving it on.
+fn read_frame(frame: &Frame, id: u64, last: &mut u64) -> ddbb_libs::Result<Vec<WatchUpdate>> {
+    let mut updates = Vec::new();
+    let mut seen = |event: WatchEventEntry, last: &mut u64| {
+        // a node behind the one watched before sends some writes again
+        if event.revision > *last {
+            *last = event.revision;
+            updates.push(WatchUpdate::Event { watch: id, event });
+        }
+    };
+    if let Ok(event) = WatchEventEntry::from_frame(frame) {
+        seen(*event, last);
+        return Ok(updates);
+    }
+    if let Ok(batch) = WatchBatchEntry::from_frame(frame) {
+        let revision = batch.revision;
+        let coalesced = batch.coalesced && revision > *last;
+        for event in batch.events {
+            seen(event, last);
+        }
+        *last = (*last).max(revision);
+        if coalesced {
+            updates.insert(0, WatchUpdate::Coalesced { watch: id, revision });
+        }
+        return Ok(updates);
+    }
+    match *MessageEntry::from_frame(frame)? {
+        // "watching from revision N", resumed from there if nothing was seen yet
+        MessageEntry::Success { msg } => {
+            if *last == 0 {
+                *last = msg.rsplit(' ').next().and_then(|rev| rev.parse().ok()).unwrap_or(0);
+            }
+            Ok(updates)
+        }
+        MessageEntry::Error { err_msg } => Err(ddbb_libs::Error::from_message(&err_msg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn event(revision: u64, key: &str) -> WatchEventEntry {
+        WatchEventEntry {
+            revision,
+            key: key.to_string(),
+            value: Bytes::from("v"),
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut last = 0;
+        let watching = MessageEntry::Success {
+            msg: "watching from revision 4".to_string(),
+        };
+        assert!(read_frame(&watching.to_frame(), 1, &mut last).unwrap().is_empty());
+        assert_eq!(last, 4);
+
+        // the writes already seen on the node watched before are left out
+        let batch = WatchBatchEntry {
+            revision: 6,
+            coalesced: true,
+            events: vec![event(4, "a/1"), event(6, "a/2")],
+        };
+        assert_eq!(
+            read_frame(&batch.to_frame(), 1, &mut last).unwrap(),
+            vec![
+                WatchUpdate::Coalesced { watch: 1, revision: 6 },
+                WatchUpdate::Event { watch: 1, event: event(6, "a/2") },
+            ]
+        );
+        assert!(read_frame(&event(5, "a/1").to_frame(), 1, &mut last).unwrap().is_empty());
+        assert_eq!(last, 6);
+
+        let compacted = MessageEntry::Error {
+            err_msg: ddbb_libs::Error::Compacted("revision 6 compacted".to_string()).to_string(),
+        };
+        let error = read_frame(&compacted.to_frame(), 1, &mut last).unwrap_err();
+        assert!(!error.is_retryable());
+    }
+}
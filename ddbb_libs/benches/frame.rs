@@ -0,0 +1,67 @@
+use std::io::Cursor;
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use ddbb_libs::connection::encode_frame;
+use ddbb_libs::data_structure::{CommandEntry, FrameCast, LogEntry};
+use ddbb_libs::frame::Frame;
+
+fn set_value(value_size: usize) -> CommandEntry {
+    CommandEntry::SetValue {
+        key: "bench/key".to_string(),
+        value: Bytes::from(vec![b'x'; value_size]),
+    }
+}
+
+fn encoded(frame: &Frame) -> BytesMut {
+    let mut dst = BytesMut::new();
+    encode_frame(frame, &mut dst);
+    dst
+}
+
+/// Encoding and decoding a client write, as written to and read from a connection.
+fn bench_frame_codec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    for value_size in [64, 4096] {
+        let frame = set_value(value_size).to_frame();
+        let bytes = encoded(&frame);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("encode", value_size),
+            &frame,
+            |b, frame| {
+                let mut dst = BytesMut::with_capacity(bytes.len());
+                b.iter(|| {
+                    dst.clear();
+                    encode_frame(black_box(frame), &mut dst);
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("check", value_size), &bytes, |b, bytes| {
+            b.iter(|| Frame::check(&mut Cursor::new(black_box(&bytes[..]))).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("parse", value_size), &bytes, |b, bytes| {
+            b.iter(|| Frame::parse(&mut Cursor::new(black_box(&bytes[..]))).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Casting between frames and the entries they carry.
+fn bench_frame_cast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_cast");
+    let frame = set_value(64).to_frame();
+    group.bench_function("command_from_frame", |b| {
+        b.iter(|| CommandEntry::from_frame(black_box(&frame)).unwrap())
+    });
+    let log = LogEntry::SetValue {
+        key: "bench/key".to_string(),
+        value: vec![b'x'; 64],
+    };
+    group.bench_function("log_to_frame", |b| b.iter(|| black_box(&log).to_frame()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_frame_codec, bench_frame_cast);
+criterion_main!(benches);
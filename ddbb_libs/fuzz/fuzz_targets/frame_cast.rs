@@ -0,0 +1,28 @@
+#![no_main]
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+
+use ddbb_libs::data_structure::{
+    AdminEntry, CommandEntry, DataEntry, ElectionEventEntry, FrameCast, LogEntry, MessageEntry,
+    WatchEventEntry,
+};
+use ddbb_libs::frame::Frame;
+
+// every FrameCast type rejects frames it does not understand without panicking
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    if Frame::check(&mut cursor).is_err() {
+        return;
+    }
+    cursor.set_position(0);
+    if let Ok(frame) = Frame::parse(&mut cursor) {
+        let _ = CommandEntry::from_frame(&frame);
+        let _ = DataEntry::from_frame(&frame);
+        let _ = MessageEntry::from_frame(&frame);
+        let _ = AdminEntry::from_frame(&frame);
+        let _ = LogEntry::from_frame(&frame);
+        let _ = WatchEventEntry::from_frame(&frame);
+        let _ = ElectionEventEntry::from_frame(&frame);
+    }
+});
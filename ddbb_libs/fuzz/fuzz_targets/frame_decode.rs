@@ -0,0 +1,25 @@
+#![no_main]
+use std::io::Cursor;
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+
+use ddbb_libs::connection::encode_frame;
+use ddbb_libs::frame::Frame;
+
+fn decode(data: &[u8]) -> Option<Frame> {
+    let mut cursor = Cursor::new(data);
+    Frame::check(&mut cursor).ok()?;
+    cursor.set_position(0);
+    Frame::parse(&mut cursor).ok()
+}
+
+// random bytes never panic, and whatever decodes survives a round trip
+fuzz_target!(|data: &[u8]| {
+    if let Some(frame) = decode(data) {
+        let mut encoded = BytesMut::new();
+        encode_frame(&frame, &mut encoded);
+        let decoded = decode(&encoded).expect("encoded frame does not decode");
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", frame));
+    }
+});
@@ -0,0 +1,206 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Notify;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// #Descriptions: where the time-dependent parts read the time and wait, e.g. the
+/// election ticks, the ttls of campaigns and sessions and the reconnect backoff.
+/// Tests drive them with a `MockClock` instead of waiting for real time to pass.
+pub trait Clock: Debug + Send + Sync {
+    /// monotonic time, for ticks and timeouts
+    fn now(&self) -> Instant;
+    /// milliseconds since the unix epoch, for the ttls replicated through the log
+    fn unix_millis(&self) -> u64;
+    /// completes once `duration` passed on this clock
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The clock of the system, the one used unless another is set.
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+#[derive(Debug)]
+struct MockTime {
+    start: Instant,
+    unix_start_ms: u64,
+    elapsed: Mutex<Duration>,
+    advanced: Notify,
+}
+
+/// A clock that only moves when advanced, the sleeps on it complete once it is advanced
+/// past their end. Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    time: Arc<MockTime>,
+}
+
+impl MockClock {
+    /// A clock reading `unix_start_ms` as unix time until advanced.
+    pub fn new(unix_start_ms: u64) -> Self {
+        MockClock {
+            time: Arc::new(MockTime {
+                start: Instant::now(),
+                unix_start_ms,
+                elapsed: Mutex::new(Duration::ZERO),
+                advanced: Notify::new(),
+            }),
+        }
+    }
+
+    pub fn shared(&self) -> SharedClock {
+        Arc::new(self.clone())
+    }
+
+    /// Move the clock `by` forward, waking the sleeps ending until then.
+    pub fn advance(&self, by: Duration) {
+        *self.time.elapsed.lock().unwrap() += by;
+        self.time.advanced.notify_waiters();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        *self.time.elapsed.lock().unwrap()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.time.start + self.elapsed()
+    }
+
+    fn unix_millis(&self) -> u64 {
+        self.time.unix_start_ms + self.elapsed().as_millis() as u64
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let time = self.time.clone();
+        let until = self.elapsed() + duration;
+        Box::pin(async move {
+            loop {
+                let advanced = time.advanced.notified();
+                tokio::pin!(advanced);
+                // registered before checking, an advance in between is not missed
+                advanced.as_mut().enable();
+                if *time.elapsed.lock().unwrap() >= until {
+                    return;
+                }
+                advanced.await;
+            }
+        })
+    }
+}
+
+/// #Descriptions: ticks every `period` of `clock`, the first tick completes at once.
+/// A tick late because the ticker was not polled delays the ticks after it, so a
+/// stalled loop never gets several at once. Dropping a pending `tick` is safe.
+pub struct Ticker {
+    clock: SharedClock,
+    period: Duration,
+    next: Instant,
+}
+
+impl Ticker {
+    pub fn new(clock: SharedClock, period: Duration) -> Self {
+        let next = clock.now();
+        Ticker {
+            clock,
+            period,
+            next,
+        }
+    }
+
+    /// The first tick comes after a whole period, instead of at once.
+    pub fn reset(&mut self) {
+        self.next = self.clock.now() + self.period;
+    }
+
+    pub async fn tick(&mut self) {
+        let now = self.clock.now();
+        if now < self.next {
+            self.clock.sleep(self.next - now).await;
+        }
+        self.next = self.clock.now() + self.period;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1000);
+        let shared = clock.shared();
+        let start = shared.now();
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(shared.now() - start, Duration::from_millis(250));
+        assert_eq!(shared.unix_millis(), 1250);
+    }
+
+    #[tokio::test]
+    async fn test_mock_sleep() {
+        let clock = MockClock::new(0);
+        let sleep = tokio::spawn(clock.sleep(Duration::from_millis(100)));
+        clock.advance(Duration::from_millis(99));
+        tokio::task::yield_now().await;
+        assert!(!sleep.is_finished());
+        clock.advance(Duration::from_millis(1));
+        timeout(Duration::from_secs(1), sleep)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ticker() {
+        let clock = MockClock::new(0);
+        let mut ticker = Ticker::new(clock.shared(), Duration::from_millis(100));
+        // the first tick completes at once
+        timeout(Duration::from_secs(1), ticker.tick())
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(10), ticker.tick())
+            .await
+            .is_err());
+        // a stalled ticker ticks once, then a period later
+        clock.advance(Duration::from_millis(350));
+        timeout(Duration::from_secs(1), ticker.tick())
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(10), ticker.tick())
+            .await
+            .is_err());
+        clock.advance(Duration::from_millis(100));
+        timeout(Duration::from_secs(1), ticker.tick())
+            .await
+            .unwrap();
+    }
+}
@@ -1,11 +1,24 @@
+use crate::clock::{Clock, SystemClock};
 use crate::frame::{self, Frame};
-use crate::Result;
+use crate::{Error, Result};
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use std::io::{self, Cursor};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
-use tokio::net::TcpStream;
-use tokio::time::{sleep, Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::{timeout, Duration};
+
+/// The stream a `Connection` reads and writes, a `TcpStream` or a TLS session over one.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug {
+    /// The underlying socket, e.g. to set socket options.
+    fn tcp_stream(&self) -> &TcpStream;
+}
+
+impl Stream for TcpStream {
+    fn tcp_stream(&self) -> &TcpStream {
+        self
+    }
+}
 
 /// Send and receive `Frame` values from a remote peer.
 ///
@@ -21,32 +34,57 @@ use tokio::time::{sleep, Duration};
 /// The contents of the write buffer are then written to the socket.
 #[derive(Debug)]
 pub struct Connection {
-    // The `TcpStream`. It is decorated with a `BufWriter`, which provides write
-    // level buffering. The `BufWriter` implementation provided by Tokio is
-    // sufficient for our needs.
-    stream: BufWriter<TcpStream>,
+    // The `TcpStream`, or a TLS session over one. It is decorated with a
+    // `BufWriter`, which provides write level buffering. The `BufWriter`
+    // implementation provided by Tokio is sufficient for our needs.
+    stream: BufWriter<Box<dyn Stream>>,
 
     // The buffer for reading frames.
     buffer: BytesMut,
+
+    // Encoded frames waiting to be written by `flush`.
+    write_buffer: BytesMut,
+
+    // Frames longer than this are neither read nor written.
+    max_frame_size: usize,
 }
 
+/// The default maximum frame size of a `Connection`, in bytes.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
 const RECONNECT_INTERVAL: u64 = 100;
+const DNS_RESOLVE_TIMEOUT: u64 = 1000;
 const RECONNECT_MSG: &str = "##RECONNECT";
+const PING_MSG: &str = "##PING";
+const PONG_MSG: &str = "##PONG";
 
 impl Connection {
     /// Create a new `Connection`, backed by `socket`. Read and write buffers
     /// are initialized.
     pub fn new(tcp_socket: TcpStream) -> Connection {
+        Self::with_stream(Box::new(tcp_socket))
+    }
+
+    /// Create a new `Connection` over `stream`, e.g. a TLS session.
+    pub fn with_stream(stream: Box<dyn Stream>) -> Connection {
         Connection {
-            stream: BufWriter::new(tcp_socket),
+            stream: BufWriter::new(stream),
             // Default to a 4KB read buffer. For the use case of mini redis,
             // this is fine. However, real applications will want to tune this
             // value to their specific use case. There is a high likelihood that
             // a larger read buffer will work better.
             buffer: BytesMut::with_capacity(4 * 1024),
+            write_buffer: BytesMut::with_capacity(4 * 1024),
+            max_frame_size: MAX_FRAME_SIZE,
         }
     }
 
+    /// Limit the frames read from and written to this connection to
+    /// `max_frame_size` bytes, a longer frame is an `Error::FrameTooLarge`.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
     pub fn got_reconnect_msg(frame: &Frame) -> bool {
         match frame {
             Frame::Error(e) => e == RECONNECT_MSG,
@@ -54,15 +92,100 @@ impl Connection {
         }
     }
 
+    pub fn got_ping_msg(frame: &Frame) -> bool {
+        match frame {
+            Frame::Error(e) => e == PING_MSG,
+            _ => false,
+        }
+    }
+
+    /// The underlying socket, e.g. to set socket options.
+    pub fn tcp_stream(&self) -> &TcpStream {
+        self.stream.get_ref().tcp_stream()
+    }
+
+    /// Check the peer is alive: send a ping and wait up to `wait` for its pong.
+    /// Frames other than the pong that arrive meanwhile are dropped.
+    pub async fn ping(&mut self, wait: Duration) -> Result<()> {
+        self.write_frame(&Frame::Error(PING_MSG.to_string())).await?;
+        match timeout(wait, self.read_pong()).await {
+            Ok(res) => res,
+            Err(_) => Err(Error::Timeout("ping".to_string())),
+        }
+    }
+
+    async fn read_pong(&mut self) -> Result<()> {
+        loop {
+            match self.read_frame().await? {
+                Some(Frame::Error(e)) if e == PONG_MSG => return Ok(()),
+                Some(_) => continue,
+                None => return Err(Error::ConnectionClosed),
+            }
+        }
+    }
+
+    /// Answer a ping received by `read_frame`.
+    pub async fn pong(&mut self) -> io::Result<()> {
+        self.write_frame(&Frame::Error(PONG_MSG.to_string())).await
+    }
+
+    /// Connect to `addr`, an ip or a host name with a port. Host names are
+    /// resolved again on every call, so a peer behind DNS can move, and each
+    /// resolved address is tried in turn.
+    pub async fn connect(addr: &str) -> Result<TcpStream> {
+        Self::connect_within(addr, None).await
+    }
+
+    /// Connect to `addr` like `connect`, giving each resolved address up to
+    /// `connect_timeout` to accept, instead of the few minutes the OS waits.
+    pub async fn connect_within(
+        addr: &str,
+        connect_timeout: Option<Duration>,
+    ) -> Result<TcpStream> {
+        let resolved = timeout(Duration::from_millis(DNS_RESOLVE_TIMEOUT), lookup_host(addr))
+            .await
+            .map_err(|_| Error::Timeout(format!("resolving {}", addr)))??;
+        let mut last_err: Option<io::Error> = None;
+        for socket_addr in resolved {
+            let connected = match connect_timeout {
+                Some(wait) => timeout(wait, TcpStream::connect(socket_addr))
+                    .await
+                    .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+                None => TcpStream::connect(socket_addr).await,
+            };
+            match connected {
+                Ok(tcp_stream) => return Ok(tcp_stream),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e.into()),
+            None => Err(format!("{} resolved to no address", addr).into()),
+        }
+    }
+
     pub async fn reconnect(&mut self, addr: String) -> Result<()> {
+        self.reconnect_with_clock(addr, &SystemClock, None).await
+    }
+
+    /// Reconnect to `addr`, waiting `RECONNECT_INTERVAL` of `clock` between attempts
+    /// each given `connect_timeout`, see `connect_within`.
+    pub async fn reconnect_with_clock(
+        &mut self,
+        addr: String,
+        clock: &dyn Clock,
+        connect_timeout: Option<Duration>,
+    ) -> Result<()> {
         loop {
-            if let Ok(tcp_stream) = TcpStream::connect(&addr).await {
-                self.stream = BufWriter::new(tcp_stream);
+            if let Ok(tcp_stream) = Self::connect_within(&addr, connect_timeout).await {
+                self.stream = BufWriter::new(Box::new(tcp_stream));
+                // frames half written to the old stream are lost
+                self.write_buffer.clear();
                 self.write_frame(&Frame::Error(RECONNECT_MSG.to_string()))
                     .await;
                 return Ok(());
             };
-            sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
+            clock.sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
         }
     }
 
@@ -96,7 +219,7 @@ impl Connection {
                 // sending a frame.
 
                 return if self.buffer.is_empty() {
-                    Err("connection closed by peer".into())
+                    Err(Error::ConnectionClosed)
                 } else {
                     Err("peer shutdown with data remain".into())
                 };
@@ -122,7 +245,9 @@ impl Connection {
         // parse of the frame, and allows us to skip allocating data structures
         // to hold the frame data unless we know the full frame has been
         // received.
-        match Frame::check(&mut buf) {
+        // A bulk length over the maximum frame size is rejected before the
+        // bulk is buffered.
+        match Frame::check_bounded(&mut buf, self.max_frame_size) {
             Ok(_) => {
                 // The `check` function will have advanced the cursor until the
                 // end of the frame. Since the cursor had position set to zero
@@ -160,7 +285,14 @@ impl Connection {
             // after this `match`.
             //
             // We do not want to return `Err` from here as this "error" is an
-            // expected runtime condition.
+            // expected runtime condition, unless the partial frame is already
+            // over the maximum frame size.
+            Err(Incomplete) if self.buffer.len() > self.max_frame_size => {
+                Err(Error::FrameTooLarge {
+                    size: self.buffer.len(),
+                    max: self.max_frame_size,
+                })
+            }
             Err(Incomplete) => Ok(None),
             // An error was encountered while parsing the frame. The connection
             // is now in an invalid state. Returning `Err` from here will result
@@ -171,91 +303,172 @@ impl Connection {
 
     /// Write a single `Frame` value to the underlying stream.
     ///
-    /// The `Frame` value is written to the socket using the various `write_*`
-    /// functions provided by `AsyncWrite`. Calling these functions directly on
-    /// a `TcpStream` is **not** advised, as this will result in a large number of
-    /// syscalls. However, it is fine to call these functions on a *buffered*
-    /// write stream. The data will be written to the buffer. Once the buffer is
-    /// full, it is flushed to the underlying socket.
+    /// The `Frame` value is encoded into the write buffer, together with any
+    /// frame buffered by `buffer_frame`, and the whole buffer is flushed to the
+    /// socket.
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-        // Arrays are encoded by encoding each entry. All other frame types are
-        // considered literals. For now, mini-redis is not able to encode
-        // recursive frame structures. See below for more details.
-        match frame {
-            Frame::Array(val) => {
-                // Encode the frame type prefix. For an array, it is `*`.
-                self.stream.write_u8(b'*').await?;
-
-                // Encode the length of the array.
-                self.write_decimal(val.len() as u64).await?;
+        self.buffer_frame(frame)?;
+        self.flush().await
+    }
 
-                // Iterate and encode each entry in the array.
-                for entry in &**val {
-                    self.write_value(entry).await?;
-                }
-            }
-            // The frame type is a literal. Encode the value directly.
-            _ => self.write_value(frame).await?,
+    /// Encode a frame into the write buffer without writing it, so that many
+    /// frames can be sent by a single `flush`. A frame over the maximum frame
+    /// size is not buffered, the error wraps an `Error::FrameTooLarge`.
+    pub fn buffer_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let size = frame.encoded_len();
+        if size > self.max_frame_size {
+            let err = Error::FrameTooLarge {
+                size,
+                max: self.max_frame_size,
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
         }
+        encode_frame(frame, &mut self.write_buffer);
+        Ok(())
+    }
 
-        // Ensure the encoded frame is written to the socket. The calls above
-        // are to the buffered stream and writes. Calling `flush` writes the
-        // remaining contents of the buffer to the socket.
+    /// Write all the buffered frames to the socket.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.stream.write_all_buf(&mut self.write_buffer).await?;
         self.stream.flush().await
     }
+}
 
-    /// Write a frame literal to the stream
-    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
-        match frame {
-            Frame::Simple(val) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Error(val) => {
-                let mut buf = BytesMut::with_capacity(64);
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Integer(val) => {
-                self.stream.write_u8(b':').await?;
-                self.write_decimal(*val).await?;
-            }
-            Frame::Null => {
-                self.stream.write_all(b"$-1\r\n").await?;
-            }
-            Frame::Bulk(val) => {
-                let len = val.len();
-
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
+/// Encode a frame, arrays may be nested.
+pub fn encode_frame(frame: &Frame, dst: &mut BytesMut) {
+    match frame {
+        Frame::Simple(val) => {
+            dst.put_u8(b'+');
+            dst.put_slice(val.as_bytes());
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Error(val) => {
+            dst.put_u8(b'-');
+            dst.put_slice(val.as_bytes());
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Integer(val) => {
+            dst.put_u8(b':');
+            encode_decimal(*val, dst);
+        }
+        Frame::Null => {
+            dst.put_slice(b"$-1\r\n");
+        }
+        Frame::Bulk(val) => {
+            dst.put_u8(b'$');
+            encode_decimal(val.len() as u64, dst);
+            dst.put_slice(val);
+            dst.put_slice(b"\r\n");
+        }
+        Frame::Array(val) => {
+            dst.put_u8(b'*');
+            encode_decimal(val.len() as u64, dst);
+            for entry in val {
+                encode_frame(entry, dst);
             }
-            // Encoding an `Array` from within a value cannot be done using a
-            // recursive strategy. In general, async fns do not support
-            // recursion. Mini-redis has not needed to encode nested arrays yet,
-            // so for now it is skipped.
-            Frame::Array(_val) => unreachable!(),
         }
+    }
+}
 
-        Ok(())
+fn encode_decimal(val: u64, dst: &mut BytesMut) {
+    dst.put_slice(val.to_string().as_bytes());
+    dst.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::MAX_ARRAY_DEPTH;
+    use bytes::Bytes;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_encode_nested_array() {
+        let frame = Frame::Array(vec![
+            Frame::Simple("outer".to_string()),
+            Frame::Integer(7),
+            Frame::Array(vec![Frame::Bulk(Bytes::from("inner")), Frame::Null]),
+        ]);
+        let mut encoded = BytesMut::new();
+        encode_frame(&frame, &mut encoded);
+
+        let mut cursor = Cursor::new(&encoded[..]);
+        Frame::check(&mut cursor).unwrap();
+        assert_eq!(cursor.position() as usize, encoded.len());
+        cursor.set_position(0);
+        let parsed = Frame::parse(&mut cursor).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", frame));
     }
 
-    /// Write a decimal frame to the stream
-    async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
-        use std::io::Write;
+    fn frame_strategy() -> impl Strategy<Value = Frame> {
+        // simple strings and errors are line terminated
+        let line = "[a-zA-Z0-9 _/:.#-]{0,16}";
+        let leaf = prop_oneof![
+            line.prop_map(Frame::Simple),
+            line.prop_map(Frame::Error),
+            any::<u64>().prop_map(Frame::Integer),
+            proptest::collection::vec(any::<u8>(), 0..64).prop_map(|b| Frame::Bulk(Bytes::from(b))),
+            Just(Frame::Null),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            proptest::collection::vec(inner, 0..8).prop_map(Frame::Array)
+        })
+    }
 
-        // Convert the value to a string
-        let mut buf = [0u8; 20];
-        let mut buf = Cursor::new(&mut buf[..]);
-        write!(&mut buf, "{}", val)?;
+    fn decode(bytes: &[u8]) -> std::result::Result<Frame, frame::Error> {
+        let mut cursor = Cursor::new(bytes);
+        Frame::check(&mut cursor)?;
+        cursor.set_position(0);
+        Frame::parse(&mut cursor)
+    }
 
-        let pos = buf.position() as usize;
-        self.stream.write_all(&buf.get_ref()[..pos]).await?;
-        self.stream.write_all(b"\r\n").await?;
+    proptest! {
+        #[test]
+        fn prop_frame_round_trip(frame in frame_strategy()) {
+            let mut encoded = BytesMut::new();
+            encode_frame(&frame, &mut encoded);
+            prop_assert_eq!(frame.encoded_len(), encoded.len());
+            let parsed = decode(&encoded).unwrap();
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", frame));
+        }
 
-        Ok(())
+        #[test]
+        fn prop_truncated_frame_is_incomplete(frame in frame_strategy(), cut in any::<prop::sample::Index>()) {
+            let mut encoded = BytesMut::new();
+            encode_frame(&frame, &mut encoded);
+            let cut = cut.index(encoded.len());
+            prop_assert!(matches!(decode(&encoded[..cut]), Err(frame::Error::Incomplete)));
+        }
+
+        #[test]
+        fn prop_random_bytes_never_panic(bytes in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let _ = decode(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_oversized_lengths() {
+        assert!(decode(b"$18446744073709551615\r\nab\r\n").is_err());
+        assert!(matches!(decode(b"*4294967296\r\n:1\r\n"), Err(frame::Error::Incomplete)));
+        let nested = "*1\r\n".repeat(MAX_ARRAY_DEPTH + 1) + ":1\r\n";
+        assert!(matches!(decode(nested.as_bytes()), Err(frame::Error::Other(_))));
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        let mut cursor = Cursor::new(&b"*2\r\n:1\r\n$4096\r\n"[..]);
+        match Frame::check_bounded(&mut cursor, 1024) {
+            Err(frame::Error::Other(Error::FrameTooLarge { size, max })) => {
+                assert_eq!(size, 4 + 4 + 7 + 4096 + 2);
+                assert_eq!(max, 1024);
+            }
+            other => panic!("expected FrameTooLarge, got {:?}", other),
+        }
+        // within the limit the frame is only incomplete
+        cursor.set_position(0);
+        assert!(matches!(
+            Frame::check_bounded(&mut cursor, 8192),
+            Err(frame::Error::Incomplete)
+        ));
     }
 }
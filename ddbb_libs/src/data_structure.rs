@@ -14,6 +14,32 @@ pub trait FrameCast {
 #[derive(Clone, Debug)]
 pub enum DataEntry {
     KeyValue { key: String, value: Bytes },
+    /// The keys of a scan, in order
+    KeyValues { pairs: Vec<(String, Bytes)> },
+    /// A read answered with the metadata of the key
+    KeyValueMeta {
+        key: String,
+        value: Bytes,
+        meta: KeyMeta,
+    },
+    /// The metadata of a key, without its value
+    Stat { key: String, meta: KeyMeta },
+    /// The names right under a path, in order
+    Children { children: Vec<String> },
+}
+
+/// Metadata of a key, as of the read that returned it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyMeta {
+    /// revision of the write that created the key
+    pub create_rev: u64,
+    /// revision of the last write to the key
+    pub mod_rev: u64,
+    /// writes to the key since it was created, 1 after the first
+    pub version: u64,
+    pub value_len: u64,
+    /// the session the key is attached to, an ephemeral key is deleted with it
+    pub lease: Option<u64>,
 }
 
 /// For omni-paxos.
@@ -33,7 +59,214 @@ pub enum LogEntry {
         key: String,
         value: Vec<u8>,
     },
-    Compact
+    /// A `LINRead` of the value and the metadata of `key`, both filled once applied.
+    LINStat {
+        opid: (String, u64),
+        key: String,
+        value: Option<Vec<u8>>,
+        meta: Option<KeyMeta>,
+    },
+    Compact,
+    /// Take one of the `permits` permits of semaphore `name` for `session`, held until
+    /// released or until the session closes or expires, `now` is unix ms at the proposer.
+    /// Acquiring again in the same session keeps the one permit.
+    SemAcquire {
+        opid: (String, u64),
+        name: String,
+        permits: u64,
+        /// 0 in the logs of older versions, which never acquire
+        #[serde(default)]
+        session: u64,
+        now: u64,
+        acquired: bool,
+    },
+    /// Give back the permit of `session`.
+    SemRelease {
+        opid: (String, u64),
+        name: String,
+        #[serde(default)]
+        session: u64,
+    },
+    /// Write only if the key was last modified at `expected_mod_rev` (0 for a missing key).
+    /// Once applied, `mod_rev` is the new revision, or the current one if it failed.
+    PutIfRevision {
+        opid: (String, u64),
+        key: String,
+        value: Vec<u8>,
+        expected_mod_rev: u64,
+        succeeded: bool,
+        mod_rev: u64,
+    },
+    /// Create namespace `name` holding at most `max_keys` keys (0 for no limit), whose
+    /// commands must carry `token` if set. `created` is false if it already existed.
+    CreateNamespace {
+        opid: (String, u64),
+        name: String,
+        max_keys: u64,
+        token: Option<String>,
+        created: bool,
+    },
+    /// Delete namespace `name` with all its keys.
+    DeleteNamespace {
+        opid: (String, u64),
+        name: String,
+        deleted: bool,
+    },
+    /// Write all `pairs` in order, a chunk of a bulk load. Once applied, only the
+    /// pairs that were written are left.
+    BulkSet {
+        opid: (String, u64),
+        pairs: Vec<(String, Vec<u8>)>,
+    },
+    /// Delete `path` and every key under `path/`. Once applied, `deleted` holds the
+    /// keys that were deleted, in order.
+    DeleteTree {
+        opid: (String, u64),
+        path: String,
+        deleted: Vec<String>,
+    },
+    /// Delete the first `limit` keys under `prefix`, in order, a batch of a bulk
+    /// delete. Once applied, `deleted` holds the keys that were deleted.
+    DeletePrefix {
+        opid: (String, u64),
+        prefix: String,
+        limit: u64,
+        deleted: Vec<String>,
+    },
+    /// Join `election` as `candidate`, or stay in it, until `ttl` ms after `now` (unix
+    /// ms at the proposer). Once applied, `leader` is the candidate leading it.
+    Campaign {
+        opid: (String, u64),
+        election: String,
+        candidate: String,
+        ttl: u64,
+        now: u64,
+        leader: Option<String>,
+    },
+    /// Leave `election`, `leader` is filled once applied as for `Campaign`.
+    Resign {
+        opid: (String, u64),
+        election: String,
+        candidate: String,
+        leader: Option<String>,
+    },
+    /// Write only if `token`, the fencing token of a leader of `election`, is not older
+    /// than its current leader's, nor than the token the key was last written with.
+    /// Once applied, `fence` is the token the key is fenced with, `token` on success.
+    FencedWrite {
+        opid: (String, u64),
+        key: String,
+        value: Vec<u8>,
+        election: String,
+        token: u64,
+        succeeded: bool,
+        fence: u64,
+    },
+    /// Piece `seq` of the value written by the `ChunkedSet` of `upload`, the ts of its
+    /// opid at the same proposer. Held by the state machine until that log is applied,
+    /// or dropped with its upload once `UPLOAD_TTL` passed since its first piece, as of
+    /// `now`, unix ms at the proposer.
+    ValueChunk {
+        opid: (String, u64),
+        upload: u64,
+        seq: u32,
+        data: Vec<u8>,
+        /// 0 in the logs of older versions
+        #[serde(default)]
+        now: u64,
+    },
+    /// Write `key` with the `chunks` pieces proposed before it as `ValueChunk`s, in the
+    /// order of their `seq`. Once applied, `written` is false if a piece was missing or
+    /// the key could not be written.
+    ChunkedSet {
+        opid: (String, u64),
+        key: String,
+        chunks: u32,
+        written: bool,
+    },
+    /// Open a session kept until `ttl` ms after `now` (unix ms at the proposer). Once
+    /// applied, `session` is its id.
+    OpenSession {
+        opid: (String, u64),
+        ttl: u64,
+        now: u64,
+        session: u64,
+    },
+    /// Keep `session` alive for another ttl from `now`, `alive` is false once applied
+    /// if it had expired.
+    KeepAlive {
+        opid: (String, u64),
+        session: u64,
+        now: u64,
+        alive: bool,
+    },
+    /// Write `key` attached to `session`, deleted once the session ends. Once applied,
+    /// `written` is false if the session had expired or the key could not be written.
+    EphemeralSet {
+        opid: (String, u64),
+        session: u64,
+        key: String,
+        value: Vec<u8>,
+        written: bool,
+    },
+    /// Close `session`, or with `only_expired` only if it expired at `now`. Once
+    /// applied, `deleted` holds its ephemeral keys, in order, `closed` is false if it
+    /// was not closed.
+    CloseSession {
+        opid: (String, u64),
+        session: u64,
+        now: u64,
+        only_expired: bool,
+        closed: bool,
+        deleted: Vec<String>,
+    },
+    /// Discard the history of the writes up to `revision` on every node, so that reads
+    /// at an older revision and watches resuming before it fail as compacted. Once
+    /// applied, `compacted` is the revision compacted up to, at most the latest one.
+    CompactRevision {
+        opid: (String, u64),
+        revision: u64,
+        compacted: u64,
+    },
+    /// Back up the state machine of `node` as it applies this log, at the same point of
+    /// the log on every node. Once applied, `revision` is the revision it was taken at,
+    /// `None` for a log applied already.
+    Backup {
+        opid: (String, u64),
+        node: u64,
+        revision: Option<u64>,
+    },
+}
+
+impl LogEntry {
+    /// Id of the operation that proposed this log, `None` for logs that are not tracked.
+    pub fn opid(&self) -> Option<&(String, u64)> {
+        match self {
+            LogEntry::LINRead { opid, .. } => Some(opid),
+            LogEntry::LINWrite { opid, .. } => Some(opid),
+            LogEntry::LINStat { opid, .. } => Some(opid),
+            LogEntry::SemAcquire { opid, .. } => Some(opid),
+            LogEntry::SemRelease { opid, .. } => Some(opid),
+            LogEntry::PutIfRevision { opid, .. } => Some(opid),
+            LogEntry::CreateNamespace { opid, .. } => Some(opid),
+            LogEntry::DeleteNamespace { opid, .. } => Some(opid),
+            LogEntry::BulkSet { opid, .. } => Some(opid),
+            LogEntry::DeleteTree { opid, .. } => Some(opid),
+            LogEntry::DeletePrefix { opid, .. } => Some(opid),
+            LogEntry::Campaign { opid, .. } => Some(opid),
+            LogEntry::Resign { opid, .. } => Some(opid),
+            LogEntry::FencedWrite { opid, .. } => Some(opid),
+            LogEntry::ValueChunk { opid, .. } => Some(opid),
+            LogEntry::ChunkedSet { opid, .. } => Some(opid),
+            LogEntry::OpenSession { opid, .. } => Some(opid),
+            LogEntry::KeepAlive { opid, .. } => Some(opid),
+            LogEntry::EphemeralSet { opid, .. } => Some(opid),
+            LogEntry::CloseSession { opid, .. } => Some(opid),
+            LogEntry::CompactRevision { opid, .. } => Some(opid),
+            LogEntry::Backup { opid, .. } => Some(opid),
+            _ => None,
+        }
+    }
 }
 
 /// For ddbb_client and ddbb_sever.
@@ -41,9 +274,218 @@ pub enum LogEntry {
 pub enum CommandEntry {
     SetValue { key: String, value: Bytes },
     GetValue { key: String },
+    /// Read `key` from the state machine of the node serving the client, possibly
+    /// stale, so that any node can serve it.
+    StaleGet { key: String },
+    /// Read the metadata of `key`, answered with a `DataEntry::Stat`.
+    Stat { key: String },
+    PutIfRevision {
+        key: String,
+        value: Bytes,
+        expected_mod_rev: u64,
+    },
+    /// Run `cmd`, giving up waiting for it after `timeout_ms`.
+    Deadline {
+        timeout_ms: u64,
+        cmd: Box<CommandEntry>,
+    },
+    /// Run `cmd` on the keys of `namespace`, `token` is empty for a namespace without one.
+    Namespaced {
+        namespace: String,
+        token: String,
+        cmd: Box<CommandEntry>,
+    },
+    /// Write all `pairs` in a single proposal, a chunk of a bulk load.
+    BulkLoad { pairs: Vec<(String, Bytes)> },
+    /// Read the keys under `prefix` from the node serving the client, possibly stale.
+    Scan { prefix: String },
+    /// Run the read `cmd`, a `GetValue`, `StaleGet` or `Scan`, against the store as it
+    /// was at `revision`, so that several reads observe the same cut of the store.
+    AtRevision {
+        revision: u64,
+        cmd: Box<CommandEntry>,
+    },
+    /// The revision of the latest write applied by the node, to pin for `AtRevision`.
+    Revision,
+    /// List the names right under `path` of the `/` separated keys, from the node
+    /// serving the client, possibly stale. Answered with a `DataEntry::Children`.
+    ListChildren { path: String },
+    /// Delete `path` and every key under it.
+    DeleteTree { path: String },
+    /// Delete the first `limit` keys under `prefix`, a batch of a bulk delete sent
+    /// again until no key is left.
+    DeletePrefix { prefix: String, limit: u64 },
+    /// Stream the writes to keys under `prefix` made after `after_revision`, the last
+    /// revision the client saw, 0 for only the writes from now on. Answered with the
+    /// current revision, then the writes as `delivery` tells.
+    Watch {
+        prefix: String,
+        after_revision: u64,
+        delivery: WatchDelivery,
+    },
+    /// Campaign in `election` as `candidate` for as long as the connection is open,
+    /// refreshed by the server within `ttl_ms`. Answered like a `Watch`, then with an
+    /// `ElectionEventEntry` whenever the leader changes.
+    Elect {
+        election: String,
+        candidate: String,
+        ttl_ms: u64,
+    },
+    /// Write `key` with `token`, the fencing token of a leader of `election`, rejected
+    /// once a newer leader of it was elected or wrote the key.
+    FencedSet {
+        key: String,
+        value: Bytes,
+        election: String,
+        token: u64,
+    },
+    /// Open a session that lives while kept alive within `ttl_ms`, on any node.
+    /// Answered with `session <id>`.
+    OpenSession { ttl_ms: u64 },
+    /// Keep `session` alive, e.g. from another node after a failover.
+    KeepAlive { session: u64 },
+    /// Write `key` attached to `session`, deleted once the session closes or expires.
+    EphemeralSet {
+        session: u64,
+        key: String,
+        value: Bytes,
+    },
+    /// Close `session`, deleting its ephemeral keys.
+    CloseSession { session: u64 },
+    /// Keep `session` alive, with all its ephemeral keys, for as long as the client sends
+    /// a `KeepAlive` of the session on the connection within every ttl. Answered like a
+    /// `Watch`, then with a `KeepAliveEntry` per refresh.
+    KeepAliveStream { session: u64 },
     Empty,
 }
 
+/// How a watch streams the writes decided together, e.g. by a bulk load or a tree
+/// deletion, so that a slow watcher is not sent a frame per write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchDelivery {
+    /// a `WatchEventEntry` per write
+    Each,
+    /// a `WatchBatchEntry` per batch of writes
+    Batched,
+    /// a `WatchBatchEntry` per batch of writes, with only the latest write of each key
+    Latest,
+}
+
+impl Default for WatchDelivery {
+    fn default() -> Self {
+        WatchDelivery::Each
+    }
+}
+
+/// A write streamed to a watching ddbb_client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchEventEntry {
+    /// revision of the write
+    pub revision: u64,
+    pub key: String,
+    pub value: Bytes,
+    /// the key was deleted, `value` is empty
+    pub deleted: bool,
+}
+
+/// The writes decided together, streamed to a ddbb_client watching with
+/// `WatchDelivery::Batched` or `WatchDelivery::Latest`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchBatchEntry {
+    /// revision of the latest write of the batch, to resume the watch after
+    pub revision: u64,
+    /// some writes were left out for a later one to the same key
+    pub coalesced: bool,
+    /// in revision order
+    pub events: Vec<WatchEventEntry>,
+}
+
+/// A change of the leader of an election, streamed to a campaigning ddbb_client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElectionEventEntry {
+    pub election: String,
+    /// `None` while no candidate could be confirmed as leader
+    pub leader: Option<String>,
+    /// the candidate of the campaign is the leader
+    pub elected: bool,
+    /// while elected, the token to fence its writes with, see `CommandEntry::FencedSet`
+    pub fencing_token: Option<u64>,
+}
+
+/// A refresh of a session, streamed to a ddbb_client keeping it alive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeepAliveEntry {
+    pub session: u64,
+    /// the session expires this long after the refresh unless refreshed again
+    pub ttl_ms: u64,
+}
+
+/// For operators, answered with a `MessageEntry` carrying json.
+#[derive(Clone, Debug)]
+pub enum AdminEntry {
+    SlowLog,
+    Metrics,
+    CatchUp,
+    Status,
+    CreateNamespace {
+        name: String,
+        max_keys: u64,
+        token: Option<String>,
+    },
+    DeleteNamespace {
+        name: String,
+    },
+    /// The settings replicated through the log
+    Config,
+    SetConfig {
+        name: String,
+        value: String,
+    },
+    /// Export the state machine to `path` on the server
+    ExportSnapshot {
+        path: String,
+    },
+    ListBackups,
+    /// Restore backup `name` at the next start of the node
+    RestoreBackup {
+        name: String,
+    },
+    /// The latest membership, leadership and snapshot events of the node
+    Events,
+    /// The status of every node, gathered by the node asked
+    ClusterStatus,
+    /// Hand the leadership of the leader asked over to another node
+    StepDown,
+    /// The membership, links, leader and lag of the nodes as a DOT graph
+    ClusterTopology,
+    /// Delay the msgs of the node asked to `peer` by `delay_ms`, give or take up to
+    /// `jitter_ms`, 0 and 0 to stop. Only served by nodes started for fault injection.
+    SetLinkLatency {
+        peer: u64,
+        delay_ms: u64,
+        jitter_ms: u64,
+    },
+    /// The keys and bytes held by the node asked, in total, per namespace and per
+    /// top-level prefix
+    Keyspace,
+    /// Discard the history of the writes up to `revision` on every node
+    CompactRevision {
+        revision: u64,
+    },
+    /// Back up the state of node `node`, 0 for the leader, at a revision pinned by
+    /// the leader through the log
+    Backup {
+        node: u64,
+    },
+}
+
+/// First frame of a ddbb_client connection when the server requires a token,
+/// answered with a `MessageEntry`.
+#[derive(Clone, Debug)]
+pub struct AuthEntry {
+    pub token: String,
+}
+
 /// For ddbb_client and ddbb_server
 #[derive(Clone, Debug)]
 pub enum MessageEntry {
@@ -99,6 +541,90 @@ impl FrameCast for MessageEntry {
     }
 }
 
+/// The reply of a follower that does not forward commands to the leader, naming the
+/// leader to send them to instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotLeaderEntry {
+    /// `None` while no leader is established
+    pub leader_id: Option<u64>,
+    /// client address of the leader, if the follower knows it
+    pub leader_addr: Option<String>,
+    /// round of the leader, the hint with the higher one is the latest
+    pub ballot: u64,
+}
+
+impl FrameCast for NotLeaderEntry {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("NotLeaderEntry".to_string()),
+            match self.leader_id {
+                Some(leader_id) => Frame::Integer(leader_id),
+                None => Frame::Null,
+            },
+            match &self.leader_addr {
+                Some(leader_addr) => Frame::Bulk(Bytes::from(leader_addr.clone())),
+                None => Frame::Null,
+            },
+            Frame::Integer(self.ballot),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, leader_id, leader_addr, Frame::Integer(ballot)]
+                    if *begin_tag == "NotLeaderEntry" =>
+                {
+                    let leader_id = match leader_id {
+                        Frame::Integer(leader_id) => Some(*leader_id),
+                        _ => None,
+                    };
+                    let leader_addr = match leader_addr {
+                        Frame::Bulk(leader_addr) => Some(String::from_utf8(leader_addr.to_vec())?),
+                        _ => None,
+                    };
+                    Ok(Box::new(NotLeaderEntry {
+                        leader_id,
+                        leader_addr,
+                        ballot: *ballot,
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+/// `[key, value]` arrays, of a bulk load or a scan.
+fn pairs_to_frame(pairs: &[(String, Bytes)]) -> Frame {
+    Frame::Array(
+        pairs
+            .iter()
+            .map(|(key, value)| {
+                Frame::Array(vec![
+                    Frame::Simple(key.to_string()),
+                    Frame::Bulk(value.clone()),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn pairs_from_frame(pairs: &[Frame], frame: &Frame) -> Result<Vec<(String, Bytes)>, Error> {
+    pairs
+        .iter()
+        .map(|pair| match pair {
+            Frame::Array(pair) => match pair.as_slice() {
+                [key, Frame::Bulk(value)] => Ok((key.to_string(), value.clone())),
+                _ => Err(frame.to_error()),
+            },
+            _ => Err(frame.to_error()),
+        })
+        .collect()
+}
+
 impl FrameCast for DataEntry {
     fn to_frame(&self) -> Frame {
         return match self {
@@ -111,6 +637,50 @@ impl FrameCast for DataEntry {
                     Frame::Bulk(value.clone()),
                 ])
             }
+
+            /// DataEntry::KeyValues
+            DataEntry::KeyValues { pairs } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("DataEntry::KeyValues".to_string()),
+                    pairs_to_frame(pairs),
+                ])
+            }
+
+            /// DataEntry::KeyValueMeta
+            DataEntry::KeyValueMeta { key, value, meta } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("DataEntry::KeyValueMeta".to_string()),
+                    Frame::Simple(key.to_string()),
+                    Frame::Bulk(value.clone()),
+                    Frame::Bulk(serde_json::to_vec(meta).unwrap().into()),
+                ])
+            }
+
+            /// DataEntry::Stat
+            DataEntry::Stat { key, meta } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("DataEntry::Stat".to_string()),
+                    Frame::Simple(key.to_string()),
+                    Frame::Bulk(serde_json::to_vec(meta).unwrap().into()),
+                ])
+            }
+
+            /// DataEntry::Children
+            DataEntry::Children { children } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("DataEntry::Children".to_string()),
+                    Frame::Array(
+                        children
+                            .iter()
+                            .map(|child| Frame::Bulk(Bytes::from(child.clone())))
+                            .collect(),
+                    ),
+                ])
+            }
         };
     }
 
@@ -124,6 +694,44 @@ impl FrameCast for DataEntry {
                         value: Bytes::from(value.to_string()),
                     }))
                 }
+
+                /// DataEntry::KeyValues
+                [begin_tag, Frame::Array(pairs)] if *begin_tag == "DataEntry::KeyValues" => {
+                    Ok(Box::new(DataEntry::KeyValues {
+                        pairs: pairs_from_frame(pairs, frame)?,
+                    }))
+                }
+
+                /// DataEntry::KeyValueMeta
+                [begin_tag, key, Frame::Bulk(value), Frame::Bulk(meta)]
+                    if *begin_tag == "DataEntry::KeyValueMeta" =>
+                {
+                    Ok(Box::new(DataEntry::KeyValueMeta {
+                        key: key.to_string(),
+                        value: value.clone(),
+                        meta: serde_json::from_slice(meta)?,
+                    }))
+                }
+
+                /// DataEntry::Stat
+                [begin_tag, key, Frame::Bulk(meta)] if *begin_tag == "DataEntry::Stat" => {
+                    Ok(Box::new(DataEntry::Stat {
+                        key: key.to_string(),
+                        meta: serde_json::from_slice(meta)?,
+                    }))
+                }
+
+                /// DataEntry::Children
+                [begin_tag, Frame::Array(children)] if *begin_tag == "DataEntry::Children" => {
+                    let children = children
+                        .iter()
+                        .map(|child| match child {
+                            Frame::Bulk(child) => Ok(String::from_utf8(child.to_vec())?),
+                            _ => Err(frame.to_error()),
+                        })
+                        .collect::<Result<Vec<String>, Error>>()?;
+                    Ok(Box::new(DataEntry::Children { children }))
+                }
                 _ => Err(frame.to_error()).into(),
             },
 
@@ -147,7 +755,7 @@ impl FrameCast for LogEntry {
                 /// MessageEntry::Success
                 [begin_tag, msg] if *begin_tag == "LogEntry" => {
                     if let Frame::Bulk(serialized_msg) = msg {
-                        let result: LogEntry = serde_json::from_slice(&serialized_msg).unwrap();
+                        let result: LogEntry = serde_json::from_slice(&serialized_msg)?;
                         Ok(Box::new(result))
                     } else {
                         Err(frame.to_error()).into()
@@ -162,77 +770,1295 @@ impl FrameCast for LogEntry {
     }
 }
 
-impl FrameCast for CommandEntry {
+impl FrameCast for AdminEntry {
     fn to_frame(&self) -> Frame {
         return match self {
-            /// CommandEntry::SetValue
-            CommandEntry::SetValue { key, value } => {
+            /// AdminEntry::SlowLog
+            AdminEntry::SlowLog => {
                 Frame::Array(vec![
                     // begin tag
-                    Frame::Simple("CommandEntry::SetValue".to_string()),
-                    Frame::Simple(key.to_string()),
-                    Frame::Bulk(value.clone()),
+                    Frame::Simple("AdminEntry::SlowLog".to_string()),
                 ])
             }
 
-            /// CommandEntry::GetValue
-            CommandEntry::GetValue { key } => {
+            /// AdminEntry::Metrics
+            AdminEntry::Metrics => {
                 Frame::Array(vec![
                     // begin tag
-                    Frame::Simple("CommandEntry::GetValue".to_string()),
-                    Frame::Simple("CommandEntry::GetValue".to_string()), //不知道为什么要多加一行，不然会报错
-                    Frame::Simple(key.to_string()),
+                    Frame::Simple("AdminEntry::Metrics".to_string()),
                 ])
             }
-            CommandEntry::Empty => Frame::Array(vec![]),
-        };
-    }
 
-    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
-        match frame {
-            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
-                /// CommandEntry::GetValue
-                [begin_tag, key, value] if *begin_tag == "CommandEntry::GetValue" => {
-                    Ok(Box::new(CommandEntry::GetValue {
-                        key: key.to_string(),
-                    }))
-                }
+            /// AdminEntry::CatchUp
+            AdminEntry::CatchUp => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::CatchUp".to_string()),
+                ])
+            }
 
-                /// CommandEntry::SetValue
-                [begin_tag, key, value] if *begin_tag == "CommandEntry::SetValue" => {
-                    Ok(Box::new(CommandEntry::SetValue {
-                        key: key.to_string(),
-                        value: Bytes::from(value.to_string()),
-                    }))
-                }
+            /// AdminEntry::Status
+            AdminEntry::Status => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::Status".to_string()),
+                ])
+            }
 
-                /// CommandEntry::GetValue
-                [begin_tag, key, value] if *begin_tag == "CommandEntry::GetValue" => {
-                    Ok(Box::new(CommandEntry::GetValue {
-                        key: key.to_string(),
-                    }))
-                }
+            /// AdminEntry::CreateNamespace
+            AdminEntry::CreateNamespace {
+                name,
+                max_keys,
+                token,
+            } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::CreateNamespace".to_string()),
+                    Frame::Simple(name.to_string()),
+                    Frame::Integer(*max_keys),
+                    match token {
+                        Some(token) => Frame::Bulk(Bytes::from(token.clone())),
+                        None => Frame::Null,
+                    },
+                ])
+            }
 
-                _ => Err(frame.to_error()).into(),
-            },
-            _ => Err(frame.to_error()).into(),
-        }
-    }
-}
+            /// AdminEntry::DeleteNamespace
+            AdminEntry::DeleteNamespace { name } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::DeleteNamespace".to_string()),
+                    Frame::Simple(name.to_string()),
+                ])
+            }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+            /// AdminEntry::Config
+            AdminEntry::Config => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::Config".to_string()),
+                ])
+            }
 
-    #[test]
-    fn test_log_entry() {
-        let log = LogEntry::SetValue {
-            key: "testKey".to_string(),
-            value: Vec::from("tempValue"),
-        };
-        println!("log: {:?}", log);
-        let frame = log.to_frame();
-        let de_frame = LogEntry::from_frame(&frame).unwrap();
-        println!("de frame: {:?}", de_frame);
+            /// AdminEntry::SetConfig
+            AdminEntry::SetConfig { name, value } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::SetConfig".to_string()),
+                    Frame::Simple(name.to_string()),
+                    Frame::Bulk(Bytes::from(value.clone())),
+                ])
+            }
+
+            /// AdminEntry::ExportSnapshot
+            AdminEntry::ExportSnapshot { path } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::ExportSnapshot".to_string()),
+                    Frame::Bulk(Bytes::from(path.clone())),
+                ])
+            }
+
+            /// AdminEntry::ListBackups
+            AdminEntry::ListBackups => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::ListBackups".to_string()),
+                ])
+            }
+
+            /// AdminEntry::RestoreBackup
+            AdminEntry::RestoreBackup { name } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::RestoreBackup".to_string()),
+                    Frame::Simple(name.to_string()),
+                ])
+            }
+
+            /// AdminEntry::Events
+            AdminEntry::Events => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::Events".to_string()),
+                ])
+            }
+
+            /// AdminEntry::ClusterStatus
+            AdminEntry::ClusterStatus => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::ClusterStatus".to_string()),
+                ])
+            }
+
+            /// AdminEntry::StepDown
+            AdminEntry::StepDown => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::StepDown".to_string()),
+                ])
+            }
+
+            /// AdminEntry::ClusterTopology
+            AdminEntry::ClusterTopology => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::ClusterTopology".to_string()),
+                ])
+            }
+
+            /// AdminEntry::SetLinkLatency
+            AdminEntry::SetLinkLatency {
+                peer,
+                delay_ms,
+                jitter_ms,
+            } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::SetLinkLatency".to_string()),
+                    Frame::Integer(*peer),
+                    Frame::Integer(*delay_ms),
+                    Frame::Integer(*jitter_ms),
+                ])
+            }
+
+            /// AdminEntry::Keyspace
+            AdminEntry::Keyspace => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::Keyspace".to_string()),
+                ])
+            }
+
+            /// AdminEntry::CompactRevision
+            AdminEntry::CompactRevision { revision } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::CompactRevision".to_string()),
+                    Frame::Integer(*revision),
+                ])
+            }
+
+            /// AdminEntry::Backup
+            AdminEntry::Backup { node } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("AdminEntry::Backup".to_string()),
+                    Frame::Integer(*node),
+                ])
+            }
+        };
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                /// AdminEntry::SlowLog
+                [begin_tag] if *begin_tag == "AdminEntry::SlowLog" => Ok(Box::new(AdminEntry::SlowLog)),
+
+                /// AdminEntry::Metrics
+                [begin_tag] if *begin_tag == "AdminEntry::Metrics" => Ok(Box::new(AdminEntry::Metrics)),
+
+                /// AdminEntry::CatchUp
+                [begin_tag] if *begin_tag == "AdminEntry::CatchUp" => Ok(Box::new(AdminEntry::CatchUp)),
+
+                /// AdminEntry::Status
+                [begin_tag] if *begin_tag == "AdminEntry::Status" => Ok(Box::new(AdminEntry::Status)),
+
+                /// AdminEntry::CreateNamespace
+                [begin_tag, name, Frame::Integer(max_keys), token]
+                    if *begin_tag == "AdminEntry::CreateNamespace" =>
+                {
+                    let token = match token {
+                        Frame::Bulk(token) => Some(String::from_utf8(token.to_vec())?),
+                        _ => None,
+                    };
+                    Ok(Box::new(AdminEntry::CreateNamespace {
+                        name: name.to_string(),
+                        max_keys: *max_keys,
+                        token,
+                    }))
+                }
+
+                /// AdminEntry::DeleteNamespace
+                [begin_tag, name] if *begin_tag == "AdminEntry::DeleteNamespace" => {
+                    Ok(Box::new(AdminEntry::DeleteNamespace {
+                        name: name.to_string(),
+                    }))
+                }
+
+                /// AdminEntry::Config
+                [begin_tag] if *begin_tag == "AdminEntry::Config" => Ok(Box::new(AdminEntry::Config)),
+
+                /// AdminEntry::SetConfig
+                [begin_tag, name, Frame::Bulk(value)] if *begin_tag == "AdminEntry::SetConfig" => {
+                    Ok(Box::new(AdminEntry::SetConfig {
+                        name: name.to_string(),
+                        value: String::from_utf8(value.to_vec())?,
+                    }))
+                }
+
+                /// AdminEntry::ExportSnapshot
+                [begin_tag, Frame::Bulk(path)] if *begin_tag == "AdminEntry::ExportSnapshot" => {
+                    Ok(Box::new(AdminEntry::ExportSnapshot {
+                        path: String::from_utf8(path.to_vec())?,
+                    }))
+                }
+
+                /// AdminEntry::ListBackups
+                [begin_tag] if *begin_tag == "AdminEntry::ListBackups" => {
+                    Ok(Box::new(AdminEntry::ListBackups))
+                }
+
+                /// AdminEntry::RestoreBackup
+                [begin_tag, name] if *begin_tag == "AdminEntry::RestoreBackup" => {
+                    Ok(Box::new(AdminEntry::RestoreBackup {
+                        name: name.to_string(),
+                    }))
+                }
+
+                /// AdminEntry::Events
+                [begin_tag] if *begin_tag == "AdminEntry::Events" => Ok(Box::new(AdminEntry::Events)),
+
+                /// AdminEntry::ClusterStatus
+                [begin_tag] if *begin_tag == "AdminEntry::ClusterStatus" => {
+                    Ok(Box::new(AdminEntry::ClusterStatus))
+                }
+
+                /// AdminEntry::StepDown
+                [begin_tag] if *begin_tag == "AdminEntry::StepDown" => {
+                    Ok(Box::new(AdminEntry::StepDown))
+                }
+
+                /// AdminEntry::ClusterTopology
+                [begin_tag] if *begin_tag == "AdminEntry::ClusterTopology" => {
+                    Ok(Box::new(AdminEntry::ClusterTopology))
+                }
+
+                /// AdminEntry::SetLinkLatency
+                [begin_tag, Frame::Integer(peer), Frame::Integer(delay_ms), Frame::Integer(jitter_ms)]
+                    if *begin_tag == "AdminEntry::SetLinkLatency" =>
+                {
+                    Ok(Box::new(AdminEntry::SetLinkLatency {
+                        peer: *peer,
+                        delay_ms: *delay_ms,
+                        jitter_ms: *jitter_ms,
+                    }))
+                }
+
+                /// AdminEntry::Keyspace
+                [begin_tag] if *begin_tag == "AdminEntry::Keyspace" => {
+                    Ok(Box::new(AdminEntry::Keyspace))
+                }
+
+                /// AdminEntry::CompactRevision
+                [begin_tag, Frame::Integer(revision)]
+                    if *begin_tag == "AdminEntry::CompactRevision" =>
+                {
+                    Ok(Box::new(AdminEntry::CompactRevision {
+                        revision: *revision,
+                    }))
+                }
+
+                /// AdminEntry::Backup
+                [begin_tag, Frame::Integer(node)] if *begin_tag == "AdminEntry::Backup" => {
+                    Ok(Box::new(AdminEntry::Backup { node: *node }))
+                }
+
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+impl FrameCast for AuthEntry {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("AuthEntry".to_string()),
+            Frame::Bulk(Bytes::from(self.token.clone())),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Bulk(token)] if *begin_tag == "AuthEntry" => {
+                    Ok(Box::new(AuthEntry {
+                        token: String::from_utf8(token.to_vec())?,
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+impl FrameCast for WatchEventEntry {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("WatchEventEntry".to_string()),
+            Frame::Integer(self.revision),
+            Frame::Simple(self.key.to_string()),
+            Frame::Bulk(self.value.clone()),
+            Frame::Integer(self.deleted as u64),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Integer(revision), key, Frame::Bulk(value), Frame::Integer(deleted)]
+                    if *begin_tag == "WatchEventEntry" =>
+                {
+                    Ok(Box::new(WatchEventEntry {
+                        revision: *revision,
+                        key: key.to_string(),
+                        value: value.clone(),
+                        deleted: *deleted != 0,
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+impl FrameCast for WatchBatchEntry {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("WatchBatchEntry".to_string()),
+            Frame::Integer(self.revision),
+            Frame::Integer(self.coalesced as u64),
+            Frame::Array(self.events.iter().map(|event| event.to_frame()).collect()),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Integer(revision), Frame::Integer(coalesced), Frame::Array(events)]
+                    if *begin_tag == "WatchBatchEntry" =>
+                {
+                    Ok(Box::new(WatchBatchEntry {
+                        revision: *revision,
+                        coalesced: *coalesced != 0,
+                        events: events
+                            .iter()
+                            .map(|event| WatchEventEntry::from_frame(event).map(|event| *event))
+                            .collect::<Result<_, _>>()?,
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+impl FrameCast for ElectionEventEntry {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("ElectionEventEntry".to_string()),
+            Frame::Simple(self.election.to_string()),
+            match &self.leader {
+                Some(leader) => Frame::Bulk(Bytes::from(leader.clone())),
+                None => Frame::Null,
+            },
+            Frame::Integer(self.elected as u64),
+            match self.fencing_token {
+                Some(token) => Frame::Integer(token),
+                None => Frame::Null,
+            },
+        ])
+    }
+
+    /// Events of servers from before fencing tokens have none.
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, election, leader, Frame::Integer(elected), fencing_token @ ..]
+                    if *begin_tag == "ElectionEventEntry" && fencing_token.len() <= 1 =>
+                {
+                    let leader = match leader {
+                        Frame::Bulk(leader) => Some(String::from_utf8(leader.to_vec())?),
+                        _ => None,
+                    };
+                    let fencing_token = match fencing_token {
+                        [Frame::Integer(token)] => Some(*token),
+                        _ => None,
+                    };
+                    Ok(Box::new(ElectionEventEntry {
+                        election: election.to_string(),
+                        leader,
+                        elected: *elected != 0,
+                        fencing_token,
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+impl FrameCast for KeepAliveEntry {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("KeepAliveEntry".to_string()),
+            Frame::Integer(self.session),
+            Frame::Integer(self.ttl_ms),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Integer(session), Frame::Integer(ttl_ms)]
+                    if *begin_tag == "KeepAliveEntry" =>
+                {
+                    Ok(Box::new(KeepAliveEntry {
+                        session: *session,
+                        ttl_ms: *ttl_ms,
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+impl FrameCast for CommandEntry {
+    fn to_frame(&self) -> Frame {
+        return match self {
+            /// CommandEntry::SetValue
+            CommandEntry::SetValue { key, value } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::SetValue".to_string()),
+                    Frame::Simple(key.to_string()),
+                    Frame::Bulk(value.clone()),
+                ])
+            }
+
+            /// CommandEntry::GetValue
+            CommandEntry::GetValue { key } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::GetValue".to_string()),
+                    Frame::Simple("CommandEntry::GetValue".to_string()), //不知道为什么要多加一行，不然会报错
+                    Frame::Simple(key.to_string()),
+                ])
+            }
+
+            /// CommandEntry::StaleGet
+            CommandEntry::StaleGet { key } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::StaleGet".to_string()),
+                    Frame::Simple(key.to_string()),
+                ])
+            }
+
+            /// CommandEntry::Stat
+            CommandEntry::Stat { key } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::Stat".to_string()),
+                    Frame::Simple(key.to_string()),
+                ])
+            }
+
+            /// CommandEntry::PutIfRevision
+            CommandEntry::PutIfRevision {
+                key,
+                value,
+                expected_mod_rev,
+            } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::PutIfRevision".to_string()),
+                    Frame::Simple(key.to_string()),
+                    Frame::Bulk(value.clone()),
+                    Frame::Integer(*expected_mod_rev),
+                ])
+            }
+
+            /// CommandEntry::Deadline
+            CommandEntry::Deadline { timeout_ms, cmd } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::Deadline".to_string()),
+                    Frame::Integer(*timeout_ms),
+                    cmd.to_frame(),
+                ])
+            }
+
+            /// CommandEntry::Namespaced
+            CommandEntry::Namespaced {
+                namespace,
+                token,
+                cmd,
+            } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::Namespaced".to_string()),
+                    Frame::Simple(namespace.to_string()),
+                    Frame::Bulk(Bytes::from(token.clone())),
+                    cmd.to_frame(),
+                ])
+            }
+
+            /// CommandEntry::BulkLoad
+            CommandEntry::BulkLoad { pairs } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::BulkLoad".to_string()),
+                    pairs_to_frame(pairs),
+                ])
+            }
+
+            /// CommandEntry::Scan
+            CommandEntry::Scan { prefix } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::Scan".to_string()),
+                    Frame::Bulk(Bytes::from(prefix.clone())),
+                ])
+            }
+
+            /// CommandEntry::AtRevision
+            CommandEntry::AtRevision { revision, cmd } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::AtRevision".to_string()),
+                    Frame::Integer(*revision),
+                    cmd.to_frame(),
+                ])
+            }
+
+            /// CommandEntry::Revision
+            CommandEntry::Revision => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::Revision".to_string()),
+                ])
+            }
+
+            /// CommandEntry::ListChildren
+            CommandEntry::ListChildren { path } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::ListChildren".to_string()),
+                    Frame::Bulk(Bytes::from(path.clone())),
+                ])
+            }
+
+            /// CommandEntry::DeleteTree
+            CommandEntry::DeleteTree { path } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::DeleteTree".to_string()),
+                    Frame::Bulk(Bytes::from(path.clone())),
+                ])
+            }
+
+            /// CommandEntry::DeletePrefix
+            CommandEntry::DeletePrefix { prefix, limit } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::DeletePrefix".to_string()),
+                    Frame::Bulk(Bytes::from(prefix.clone())),
+                    Frame::Integer(*limit),
+                ])
+            }
+
+            /// CommandEntry::Watch
+            CommandEntry::Watch {
+                prefix,
+                after_revision,
+                delivery,
+            } => {
+                let mut frame = vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::Watch".to_string()),
+                    Frame::Bulk(Bytes::from(prefix.clone())),
+                    Frame::Integer(*after_revision),
+                ];
+                // left out for each write, as sent by the clients predating the batches
+                match delivery {
+                    WatchDelivery::Each => {}
+                    WatchDelivery::Batched => frame.push(Frame::Integer(1)),
+                    WatchDelivery::Latest => frame.push(Frame::Integer(2)),
+                }
+                Frame::Array(frame)
+            }
+
+            /// CommandEntry::Elect
+            CommandEntry::Elect {
+                election,
+                candidate,
+                ttl_ms,
+            } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::Elect".to_string()),
+                    Frame::Simple(election.to_string()),
+                    Frame::Bulk(Bytes::from(candidate.clone())),
+                    Frame::Integer(*ttl_ms),
+                ])
+            }
+
+            /// CommandEntry::FencedSet
+            CommandEntry::FencedSet {
+                key,
+                value,
+                election,
+                token,
+            } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::FencedSet".to_string()),
+                    Frame::Simple(key.to_string()),
+                    Frame::Bulk(value.clone()),
+                    Frame::Simple(election.to_string()),
+                    Frame::Integer(*token),
+                ])
+            }
+
+            /// CommandEntry::OpenSession
+            CommandEntry::OpenSession { ttl_ms } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::OpenSession".to_string()),
+                    Frame::Integer(*ttl_ms),
+                ])
+            }
+
+            /// CommandEntry::KeepAlive
+            CommandEntry::KeepAlive { session } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::KeepAlive".to_string()),
+                    Frame::Integer(*session),
+                ])
+            }
+
+            /// CommandEntry::EphemeralSet
+            CommandEntry::EphemeralSet {
+                session,
+                key,
+                value,
+            } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::EphemeralSet".to_string()),
+                    Frame::Integer(*session),
+                    Frame::Bulk(Bytes::from(key.clone())),
+                    Frame::Bulk(value.clone()),
+                ])
+            }
+
+            /// CommandEntry::CloseSession
+            CommandEntry::CloseSession { session } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::CloseSession".to_string()),
+                    Frame::Integer(*session),
+                ])
+            }
+
+            /// CommandEntry::KeepAliveStream
+            CommandEntry::KeepAliveStream { session } => {
+                Frame::Array(vec![
+                    // begin tag
+                    Frame::Simple("CommandEntry::KeepAliveStream".to_string()),
+                    Frame::Integer(*session),
+                ])
+            }
+            CommandEntry::Empty => Frame::Array(vec![]),
+        };
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>, Error> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                /// CommandEntry::GetValue
+                [begin_tag, _, key] if *begin_tag == "CommandEntry::GetValue" => {
+                    Ok(Box::new(CommandEntry::GetValue {
+                        key: key.to_string(),
+                    }))
+                }
+
+                /// CommandEntry::StaleGet
+                [begin_tag, key] if *begin_tag == "CommandEntry::StaleGet" => {
+                    Ok(Box::new(CommandEntry::StaleGet {
+                        key: key.to_string(),
+                    }))
+                }
+
+                /// CommandEntry::Stat
+                [begin_tag, key] if *begin_tag == "CommandEntry::Stat" => {
+                    Ok(Box::new(CommandEntry::Stat {
+                        key: key.to_string(),
+                    }))
+                }
+
+                /// CommandEntry::SetValue
+                [begin_tag, key, value] if *begin_tag == "CommandEntry::SetValue" => {
+                    Ok(Box::new(CommandEntry::SetValue {
+                        key: key.to_string(),
+                        value: Bytes::from(value.to_string()),
+                    }))
+                }
+
+                /// CommandEntry::PutIfRevision
+                [begin_tag, key, value, Frame::Integer(expected_mod_rev)]
+                    if *begin_tag == "CommandEntry::PutIfRevision" =>
+                {
+                    Ok(Box::new(CommandEntry::PutIfRevision {
+                        key: key.to_string(),
+                        value: Bytes::from(value.to_string()),
+                        expected_mod_rev: *expected_mod_rev,
+                    }))
+                }
+
+                /// CommandEntry::Deadline
+                [begin_tag, Frame::Integer(timeout_ms), cmd]
+                    if *begin_tag == "CommandEntry::Deadline" =>
+                {
+                    Ok(Box::new(CommandEntry::Deadline {
+                        timeout_ms: *timeout_ms,
+                        cmd: CommandEntry::from_frame(cmd)?,
+                    }))
+                }
+
+                /// CommandEntry::Namespaced
+                [begin_tag, namespace, Frame::Bulk(token), cmd]
+                    if *begin_tag == "CommandEntry::Namespaced" =>
+                {
+                    Ok(Box::new(CommandEntry::Namespaced {
+                        namespace: namespace.to_string(),
+                        token: String::from_utf8(token.to_vec())?,
+                        cmd: CommandEntry::from_frame(cmd)?,
+                    }))
+                }
+
+                /// CommandEntry::BulkLoad
+                [begin_tag, Frame::Array(pairs)] if *begin_tag == "CommandEntry::BulkLoad" => {
+                    Ok(Box::new(CommandEntry::BulkLoad {
+                        pairs: pairs_from_frame(pairs, frame)?,
+                    }))
+                }
+
+                /// CommandEntry::Scan
+                [begin_tag, Frame::Bulk(prefix)] if *begin_tag == "CommandEntry::Scan" => {
+                    Ok(Box::new(CommandEntry::Scan {
+                        prefix: String::from_utf8(prefix.to_vec())?,
+                    }))
+                }
+
+                /// CommandEntry::AtRevision
+                [begin_tag, Frame::Integer(revision), cmd]
+                    if *begin_tag == "CommandEntry::AtRevision" =>
+                {
+                    Ok(Box::new(CommandEntry::AtRevision {
+                        revision: *revision,
+                        cmd: CommandEntry::from_frame(cmd)?,
+                    }))
+                }
+
+                /// CommandEntry::Revision
+                [begin_tag] if *begin_tag == "CommandEntry::Revision" => {
+                    Ok(Box::new(CommandEntry::Revision))
+                }
+
+                /// CommandEntry::ListChildren
+                [begin_tag, Frame::Bulk(path)] if *begin_tag == "CommandEntry::ListChildren" => {
+                    Ok(Box::new(CommandEntry::ListChildren {
+                        path: String::from_utf8(path.to_vec())?,
+                    }))
+                }
+
+                /// CommandEntry::DeleteTree
+                [begin_tag, Frame::Bulk(path)] if *begin_tag == "CommandEntry::DeleteTree" => {
+                    Ok(Box::new(CommandEntry::DeleteTree {
+                        path: String::from_utf8(path.to_vec())?,
+                    }))
+                }
+
+                /// CommandEntry::DeletePrefix
+                [begin_tag, Frame::Bulk(prefix), Frame::Integer(limit)]
+                    if *begin_tag == "CommandEntry::DeletePrefix" =>
+                {
+                    Ok(Box::new(CommandEntry::DeletePrefix {
+                        prefix: String::from_utf8(prefix.to_vec())?,
+                        limit: *limit,
+                    }))
+                }
+
+                /// CommandEntry::Watch
+                [begin_tag, Frame::Bulk(prefix), Frame::Integer(after_revision)]
+                    if *begin_tag == "CommandEntry::Watch" =>
+                {
+                    Ok(Box::new(CommandEntry::Watch {
+                        prefix: String::from_utf8(prefix.to_vec())?,
+                        after_revision: *after_revision,
+                        delivery: WatchDelivery::Each,
+                    }))
+                }
+                [begin_tag, Frame::Bulk(prefix), Frame::Integer(after_revision), Frame::Integer(delivery)]
+                    if *begin_tag == "CommandEntry::Watch" =>
+                {
+                    let delivery = match *delivery {
+                        0 => WatchDelivery::Each,
+                        1 => WatchDelivery::Batched,
+                        2 => WatchDelivery::Latest,
+                        _ => return Err(frame.to_error()),
+                    };
+                    Ok(Box::new(CommandEntry::Watch {
+                        prefix: String::from_utf8(prefix.to_vec())?,
+                        after_revision: *after_revision,
+                        delivery,
+                    }))
+                }
+
+                /// CommandEntry::Elect
+                [begin_tag, election, Frame::Bulk(candidate), Frame::Integer(ttl_ms)]
+                    if *begin_tag == "CommandEntry::Elect" =>
+                {
+                    Ok(Box::new(CommandEntry::Elect {
+                        election: election.to_string(),
+                        candidate: String::from_utf8(candidate.to_vec())?,
+                        ttl_ms: *ttl_ms,
+                    }))
+                }
+
+                /// CommandEntry::FencedSet
+                [begin_tag, key, value, election, Frame::Integer(token)]
+                    if *begin_tag == "CommandEntry::FencedSet" =>
+                {
+                    Ok(Box::new(CommandEntry::FencedSet {
+                        key: key.to_string(),
+                        value: Bytes::from(value.to_string()),
+                        election: election.to_string(),
+                        token: *token,
+                    }))
+                }
+
+                /// CommandEntry::OpenSession
+                [begin_tag, Frame::Integer(ttl_ms)] if *begin_tag == "CommandEntry::OpenSession" => {
+                    Ok(Box::new(CommandEntry::OpenSession { ttl_ms: *ttl_ms }))
+                }
+
+                /// CommandEntry::KeepAlive
+                [begin_tag, Frame::Integer(session)] if *begin_tag == "CommandEntry::KeepAlive" => {
+                    Ok(Box::new(CommandEntry::KeepAlive { session: *session }))
+                }
+
+                /// CommandEntry::EphemeralSet
+                [begin_tag, Frame::Integer(session), Frame::Bulk(key), Frame::Bulk(value)]
+                    if *begin_tag == "CommandEntry::EphemeralSet" =>
+                {
+                    Ok(Box::new(CommandEntry::EphemeralSet {
+                        session: *session,
+                        key: String::from_utf8(key.to_vec())?,
+                        value: value.clone(),
+                    }))
+                }
+
+                /// CommandEntry::CloseSession
+                [begin_tag, Frame::Integer(session)] if *begin_tag == "CommandEntry::CloseSession" => {
+                    Ok(Box::new(CommandEntry::CloseSession { session: *session }))
+                }
+
+                /// CommandEntry::KeepAliveStream
+                [begin_tag, Frame::Integer(session)]
+                    if *begin_tag == "CommandEntry::KeepAliveStream" =>
+                {
+                    Ok(Box::new(CommandEntry::KeepAliveStream { session: *session }))
+                }
+
+                /// CommandEntry::GetValue
+                [begin_tag, key, value] if *begin_tag == "CommandEntry::GetValue" => {
+                    Ok(Box::new(CommandEntry::GetValue {
+                        key: key.to_string(),
+                    }))
+                }
+
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn log_entry_strategy() -> impl Strategy<Value = LogEntry> {
+        let opid = ("[a-z0-9.:]{1,16}", any::<u64>());
+        let bytes = proptest::collection::vec(any::<u8>(), 0..32);
+        prop_oneof![
+            (".*", bytes.clone()).prop_map(|(key, value)| LogEntry::SetValue { key, value }),
+            (opid.clone(), ".*", proptest::option::of(bytes.clone()))
+                .prop_map(|(opid, key, value)| LogEntry::LINRead { opid, key, value }),
+            (opid.clone(), ".*", bytes.clone())
+                .prop_map(|(opid, key, value)| LogEntry::LINWrite { opid, key, value }),
+            (opid.clone(), ".*", proptest::option::of(bytes.clone()), any::<u64>()).prop_map(
+                |(opid, key, value, mod_rev)| LogEntry::LINStat {
+                    opid,
+                    key,
+                    value,
+                    meta: Some(KeyMeta {
+                        mod_rev,
+                        ..KeyMeta::default()
+                    }),
+                }
+            ),
+            Just(LogEntry::Compact),
+            (opid.clone(), ".*", any::<u64>(), any::<u64>(), any::<u64>(), any::<bool>()).prop_map(
+                |(opid, name, permits, session, now, acquired)| LogEntry::SemAcquire {
+                    opid,
+                    name,
+                    permits,
+                    session,
+                    now,
+                    acquired,
+                }
+            ),
+            (opid.clone(), ".*", any::<u64>())
+                .prop_map(|(opid, name, session)| LogEntry::SemRelease { opid, name, session }),
+            (opid.clone(), proptest::collection::vec((".*", bytes.clone()), 0..4))
+                .prop_map(|(opid, pairs)| LogEntry::BulkSet { opid, pairs }),
+            (opid.clone(), ".*", proptest::collection::vec(".*", 0..4))
+                .prop_map(|(opid, path, deleted)| LogEntry::DeleteTree { opid, path, deleted }),
+            (opid.clone(), ".*", ".*", any::<u64>(), proptest::option::of(".*")).prop_map(
+                |(opid, election, candidate, now, leader)| LogEntry::Campaign {
+                    opid,
+                    election,
+                    candidate,
+                    ttl: 1000,
+                    now,
+                    leader,
+                }
+            ),
+            (opid.clone(), ".*", bytes.clone(), any::<u64>(), any::<bool>(), any::<u64>()).prop_map(
+                |(opid, key, value, expected_mod_rev, succeeded, mod_rev)| LogEntry::PutIfRevision {
+                    opid,
+                    key,
+                    value,
+                    expected_mod_rev,
+                    succeeded,
+                    mod_rev,
+                }
+            ),
+            (opid, ".*", bytes, ".*", any::<u64>(), any::<bool>(), any::<u64>())
+                .prop_map(|(opid, key, value, election, token, succeeded, fence)| {
+                    LogEntry::FencedWrite {
+                        opid,
+                        key,
+                        value,
+                        election,
+                        token,
+                        succeeded,
+                        fence,
+                    }
+                }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_log_entry_round_trip(log in log_entry_strategy()) {
+            let decoded = LogEntry::from_frame(&log.to_frame()).unwrap();
+            prop_assert_eq!(*decoded, log);
+        }
+    }
+
+    #[test]
+    fn test_log_entry() {
+        let log = LogEntry::SetValue {
+            key: "testKey".to_string(),
+            value: Vec::from("tempValue"),
+        };
+        println!("log: {:?}", log);
+        let frame = log.to_frame();
+        let de_frame = LogEntry::from_frame(&frame).unwrap();
+        println!("de frame: {:?}", de_frame);
+    }
+
+    #[test]
+    fn test_get_value_command() {
+        let cmd = CommandEntry::GetValue {
+            key: "testKey".to_string(),
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::GetValue { key } => assert_eq!(key, "testKey"),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stale_get_command() {
+        let cmd = CommandEntry::StaleGet {
+            key: "testKey".to_string(),
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::StaleGet { key } => assert_eq!(key, "testKey"),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deadline_command() {
+        let cmd = CommandEntry::Deadline {
+            timeout_ms: 500,
+            cmd: Box::new(CommandEntry::GetValue {
+                key: "testKey".to_string(),
+            }),
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::Deadline { timeout_ms, cmd } => {
+                assert_eq!(timeout_ms, 500);
+                match *cmd {
+                    CommandEntry::GetValue { key } => assert_eq!(key, "testKey"),
+                    other => panic!("unexpected command: {:?}", other),
+                }
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_put_if_revision_command() {
+        let cmd = CommandEntry::PutIfRevision {
+            key: "testKey".to_string(),
+            value: Bytes::from("tempValue"),
+            expected_mod_rev: 3,
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::PutIfRevision {
+                key,
+                value,
+                expected_mod_rev,
+            } => {
+                assert_eq!(key, "testKey");
+                assert_eq!(value, Bytes::from("tempValue"));
+                assert_eq!(expected_mod_rev, 3);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_watch_command() {
+        let cmd = CommandEntry::Watch {
+            prefix: String::new(),
+            after_revision: 7,
+            delivery: WatchDelivery::Each,
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::Watch {
+                prefix,
+                after_revision,
+                delivery,
+            } => {
+                assert_eq!(prefix, "");
+                assert_eq!(after_revision, 7);
+                assert_eq!(delivery, WatchDelivery::Each);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        let cmd = CommandEntry::Watch {
+            prefix: "a/".to_string(),
+            after_revision: 0,
+            delivery: WatchDelivery::Latest,
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::Watch { delivery, .. } => assert_eq!(delivery, WatchDelivery::Latest),
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        let event = WatchEventEntry {
+            revision: 8,
+            key: "testKey".to_string(),
+            value: Bytes::from("tempValue"),
+            deleted: false,
+        };
+        assert_eq!(*WatchEventEntry::from_frame(&event.to_frame()).unwrap(), event);
+        let batch = WatchBatchEntry {
+            revision: 9,
+            coalesced: true,
+            events: vec![event.clone(), WatchEventEntry { revision: 9, ..event }],
+        };
+        assert_eq!(*WatchBatchEntry::from_frame(&batch.to_frame()).unwrap(), batch);
+    }
+
+    #[test]
+    fn test_bulk_load_command() {
+        let cmd = CommandEntry::BulkLoad {
+            pairs: vec![
+                ("k1".to_string(), Bytes::from("v1")),
+                ("k2".to_string(), Bytes::new()),
+            ],
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::BulkLoad { pairs } => {
+                assert_eq!(pairs.len(), 2);
+                assert_eq!(pairs[0], ("k1".to_string(), Bytes::from("v1")));
+                assert_eq!(pairs[1], ("k2".to_string(), Bytes::new()));
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_at_revision_command() {
+        let cmd = CommandEntry::AtRevision {
+            revision: 5,
+            cmd: Box::new(CommandEntry::Scan {
+                prefix: "app/".to_string(),
+            }),
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::AtRevision { revision, cmd } => {
+                assert_eq!(revision, 5);
+                match *cmd {
+                    CommandEntry::Scan { prefix } => assert_eq!(prefix, "app/"),
+                    other => panic!("unexpected command: {:?}", other),
+                }
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        let data = DataEntry::KeyValues {
+            pairs: vec![("app/k1".to_string(), Bytes::from("v1"))],
+        };
+        match *DataEntry::from_frame(&data.to_frame()).unwrap() {
+            DataEntry::KeyValues { pairs } => {
+                assert_eq!(pairs, vec![("app/k1".to_string(), Bytes::from("v1"))])
+            }
+            other => panic!("unexpected data: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_key_meta_data() {
+        let meta = KeyMeta {
+            create_rev: 2,
+            mod_rev: 5,
+            version: 3,
+            value_len: 9,
+            lease: None,
+        };
+        let data = DataEntry::KeyValueMeta {
+            key: "testKey".to_string(),
+            value: Bytes::from("tempValue"),
+            meta: meta.clone(),
+        };
+        match *DataEntry::from_frame(&data.to_frame()).unwrap() {
+            DataEntry::KeyValueMeta { value, meta: decoded, .. } => {
+                assert_eq!(value, Bytes::from("tempValue"));
+                assert_eq!(decoded, meta);
+            }
+            other => panic!("unexpected data: {:?}", other),
+        }
+        let stat = DataEntry::Stat {
+            key: "testKey".to_string(),
+            meta: meta.clone(),
+        };
+        match *DataEntry::from_frame(&stat.to_frame()).unwrap() {
+            DataEntry::Stat { meta: decoded, .. } => assert_eq!(decoded, meta),
+            other => panic!("unexpected data: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_elect_command() {
+        let cmd = CommandEntry::Elect {
+            election: "scheduler".to_string(),
+            candidate: "node-1".to_string(),
+            ttl_ms: 3000,
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::Elect {
+                election,
+                candidate,
+                ttl_ms,
+            } => {
+                assert_eq!(election, "scheduler");
+                assert_eq!(candidate, "node-1");
+                assert_eq!(ttl_ms, 3000);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        for leader in [Some("node-1".to_string()), None] {
+            let event = ElectionEventEntry {
+                election: "scheduler".to_string(),
+                elected: leader.is_some(),
+                fencing_token: leader.as_ref().map(|_| 7),
+                leader,
+            };
+            assert_eq!(*ElectionEventEntry::from_frame(&event.to_frame()).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn test_not_leader_entry() {
+        let hints = vec![
+            NotLeaderEntry {
+                leader_id: Some(2),
+                leader_addr: Some("127.0.0.1:6143".to_string()),
+                ballot: 3,
+            },
+            NotLeaderEntry {
+                leader_id: Some(2),
+                leader_addr: None,
+                ballot: 3,
+            },
+            NotLeaderEntry {
+                leader_id: None,
+                leader_addr: None,
+                ballot: 0,
+            },
+        ];
+        for hint in hints {
+            assert_eq!(*NotLeaderEntry::from_frame(&hint.to_frame()).unwrap(), hint);
+        }
+        let reply = MessageEntry::Error {
+            err_msg: "not leader".to_string(),
+        };
+        assert!(NotLeaderEntry::from_frame(&reply.to_frame()).is_err());
+    }
+
+    #[test]
+    fn test_list_children_command() {
+        let cmd = CommandEntry::ListChildren {
+            path: "/app/config".to_string(),
+        };
+        match *CommandEntry::from_frame(&cmd.to_frame()).unwrap() {
+            CommandEntry::ListChildren { path } => assert_eq!(path, "/app/config"),
+            other => panic!("unexpected command: {:?}", other),
+        }
+        let data = DataEntry::Children {
+            children: vec!["db".to_string(), "http".to_string()],
+        };
+        match *DataEntry::from_frame(&data.to_frame()).unwrap() {
+            DataEntry::Children { children } => assert_eq!(children, vec!["db", "http"]),
+            other => panic!("unexpected data: {:?}", other),
+        }
     }
 }
@@ -0,0 +1,186 @@
+use std::io;
+use std::num::TryFromIntError;
+use std::string::FromUtf8Error;
+use thiserror::Error as ThisError;
+
+use crate::frame;
+
+/// Errors of ddbb, shared by the server, the transport and the client.
+/// Callers branch on the kind, e.g. to retry only what `is_retryable`.
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("io error: {0}")]
+    IoError(#[from] io::Error),
+
+    /// A frame that could not be parsed, or not into the expected type
+    #[error("frame decode error: {0}")]
+    FrameDecode(String),
+
+    /// A frame over the maximum frame size of the connection
+    #[error("frame too large: {size} bytes, max {max}")]
+    FrameTooLarge { size: usize, max: usize },
+
+    #[error("serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("connection closed by peer")]
+    ConnectionClosed,
+
+    /// The request has to go to the leader
+    #[error("not leader")]
+    NotLeader,
+
+    /// The request may still succeed, e.g. a proposal may still be decided
+    #[error("timeout: {0}")]
+    Timeout(String),
+
+    #[error("quorum lost")]
+    QuorumLost,
+
+    /// A precondition of the request failed, e.g. a stale revision
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    /// Shed by a loaded node
+    #[error("overloaded: {0}")]
+    Overloaded(String),
+
+    /// The node can not serve the request yet, e.g. while catching up
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+
+    /// A client without the token required by the server
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// A write over the quota of its namespace
+    #[error("quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    /// A watch resumed from a revision whose events are no longer retained
+    #[error("compacted: {0}")]
+    Compacted(String),
+
+    /// A write to a node that stopped taking writes, e.g. with its disk almost full
+    #[error("read only: {0}")]
+    ReadOnly(String),
+
+    #[error("{0}")]
+    Other(String),
+}
+
+impl Error {
+    /// Whether the same request can be sent again, to this or another node.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Error::IoError(_)
+                | Error::ConnectionClosed
+                | Error::NotLeader
+                | Error::QuorumLost
+                | Error::Overloaded(_)
+                | Error::Unavailable(_)
+        )
+    }
+
+    /// #Descriptions: the error carried by a `MessageEntry::Error` reply, which is
+    /// the `Display` of the error raised by the server.
+    pub fn from_message(err_msg: &str) -> Error {
+        let prefixed = |prefix: &str| err_msg.strip_prefix(prefix).map(|msg| msg.to_string());
+        if let Some(msg) = prefixed("io error: ") {
+            Error::IoError(io::Error::new(io::ErrorKind::Other, msg))
+        } else if let Some(msg) = prefixed("frame decode error: ") {
+            Error::FrameDecode(msg)
+        } else if let Some(msg) = prefixed("serialization error: ") {
+            Error::Other(msg)
+        } else if err_msg == "connection closed by peer" {
+            Error::ConnectionClosed
+        } else if err_msg == "not leader" {
+            Error::NotLeader
+        } else if let Some(msg) = prefixed("timeout: ") {
+            Error::Timeout(msg)
+        } else if err_msg == "quorum lost" {
+            Error::QuorumLost
+        } else if let Some(msg) = prefixed("conflict: ") {
+            Error::Conflict(msg)
+        } else if let Some(msg) = prefixed("overloaded: ") {
+            Error::Overloaded(msg)
+        } else if let Some(msg) = prefixed("unavailable: ") {
+            Error::Unavailable(msg)
+        } else if let Some(msg) = prefixed("unauthorized: ") {
+            Error::Unauthorized(msg)
+        } else if let Some(msg) = prefixed("quota exceeded: ") {
+            Error::QuotaExceeded(msg)
+        } else if let Some(msg) = prefixed("compacted: ") {
+            Error::Compacted(msg)
+        } else if let Some(msg) = prefixed("read only: ") {
+            Error::ReadOnly(msg)
+        } else {
+            Error::Other(err_msg.to_string())
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(src: String) -> Error {
+        Error::Other(src)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(src: &str) -> Error {
+        Error::Other(src.to_string())
+    }
+}
+
+impl From<frame::Error> for Error {
+    fn from(src: frame::Error) -> Error {
+        match src {
+            frame::Error::Incomplete => Error::FrameDecode("stream ended early".to_string()),
+            frame::Error::Other(err) => err,
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(src: FromUtf8Error) -> Error {
+        Error::FrameDecode(src.to_string())
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(src: TryFromIntError) -> Error {
+        Error::FrameDecode(src.to_string())
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for Error {
+    fn from(src: tokio::time::error::Elapsed) -> Error {
+        Error::Timeout(src.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_from_message() {
+        let errors = vec![
+            Error::NotLeader,
+            Error::Timeout("proposal timed out".to_string()),
+            Error::Conflict("stale revision, current revision 3".to_string()),
+            Error::Overloaded("outgoing buffer full, retry later".to_string()),
+            Error::Unauthorized("bad token".to_string()),
+            Error::Compacted("revision 3 compacted, oldest retained 10".to_string()),
+            Error::ReadOnly("1024 bytes free, under the low watermark".to_string()),
+            Error::Other("key not found: k1".to_string()),
+        ];
+        for error in errors {
+            let parsed = Error::from_message(&error.to_string());
+            assert_eq!(parsed.to_string(), error.to_string());
+            assert_eq!(parsed.is_retryable(), error.is_retryable());
+        }
+        assert!(matches!(Error::from_message("quorum lost"), Error::QuorumLost));
+    }
+}
@@ -21,6 +21,10 @@ pub enum Frame {
     Array(Vec<Frame>),
 }
 
+/// Arrays nested deeper than this are rejected, so that a malicious frame can
+/// not overflow the stack of `check` and `parse`.
+pub const MAX_ARRAY_DEPTH: usize = 32;
+
 #[derive(Debug)]
 pub enum Error {
     /// Not enough data is available to parse a message
@@ -135,6 +139,16 @@ impl Frame {
 
     /// Checks if an entire message can be decoded from `src`
     pub fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        Frame::check_nested(src, 0, usize::MAX)
+    }
+
+    /// Like `check`, but a frame longer than `max_len` bytes is rejected as soon as
+    /// a bulk length says so, before the bulk is buffered.
+    pub fn check_bounded(src: &mut Cursor<&[u8]>, max_len: usize) -> Result<(), Error> {
+        Frame::check_nested(src, 0, max_len)
+    }
+
+    fn check_nested(src: &mut Cursor<&[u8]>, depth: usize, max_len: usize) -> Result<(), Error> {
         match get_u8(src)? {
             b'+' => {
                 get_line(src)?;
@@ -155,16 +169,29 @@ impl Frame {
                 } else {
                     // Read the bulk string
                     let len: usize = get_decimal(src)?.try_into()?;
+                    let n = len
+                        .checked_add(2)
+                        .ok_or("protocol error; invalid frame format")?;
+                    let size = (src.position() as usize).saturating_add(n);
+                    if size > max_len {
+                        return Err(Error::Other(crate::Error::FrameTooLarge {
+                            size,
+                            max: max_len,
+                        }));
+                    }
 
                     // skip that number of bytes + 2 (\r\n).
-                    skip(src, len + 2)
+                    skip(src, n)
                 }
             }
             b'*' => {
+                if depth >= MAX_ARRAY_DEPTH {
+                    return Err("protocol error; arrays nested too deep".into());
+                }
                 let len = get_decimal(src)?;
 
                 for _ in 0..len {
-                    Frame::check(src)?;
+                    Frame::check_nested(src, depth + 1, max_len)?;
                 }
 
                 Ok(())
@@ -209,8 +236,10 @@ impl Frame {
                     Ok(Frame::Null)
                 } else {
                     // Read the bulk string
-                    let len = get_decimal(src)?.try_into()?;
-                    let n = len + 2;
+                    let len: usize = get_decimal(src)?.try_into()?;
+                    let n = len
+                        .checked_add(2)
+                        .ok_or("protocol error; invalid frame format")?;
 
                     if src.remaining() < n {
                         return Err(Error::Incomplete);
@@ -234,13 +263,28 @@ impl Frame {
 
                 Ok(Frame::Array(out))
             }
-            _ => unimplemented!(),
+            actual => Err(format!("protocol error; invalid frame type byte `{}`", actual).into()),
+        }
+    }
+
+    /// The number of bytes of the encoded frame, computed without encoding it.
+    pub fn encoded_len(&self) -> usize {
+        let decimal_len = |val: u64| val.to_string().len() + 2;
+        match self {
+            Frame::Simple(val) | Frame::Error(val) => 1 + val.len() + 2,
+            Frame::Integer(val) => 1 + decimal_len(*val),
+            Frame::Null => 5,
+            Frame::Bulk(val) => 1 + decimal_len(val.len() as u64) + val.len() + 2,
+            Frame::Array(val) => {
+                let entries: usize = val.iter().map(Frame::encoded_len).sum();
+                1 + decimal_len(val.len() as u64) + entries
+            }
         }
     }
 
     /// Converts the frame to an "unexpected frame" error
     pub fn to_error(&self) -> crate::Error {
-        format!("unexpected frame: {}", self).into()
+        crate::Error::FrameDecode(format!("unexpected frame: {}", self))
     }
 }
 
@@ -0,0 +1,723 @@
+//! Golden frames: the encoding of sample values checked into `testdata/golden`, so that
+//! a change of the wire format, e.g. a renamed variant or a field added in the middle,
+//! fails a test instead of breaking the nodes and clients of another version.
+//!
+//! A format changed on purpose is written again with `DDBB_UPDATE_GOLDEN=1 cargo test`.
+//! Frames of older versions, named `*_v<n>`, are never written again and must keep
+//! decoding.
+
+use bytes::BytesMut;
+use std::fmt::Debug;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use crate::connection::encode_frame;
+use crate::data_structure::FrameCast;
+use crate::frame::Frame;
+
+/// Write the golden frames of the current format instead of checking them.
+pub const UPDATE_ENV: &str = "DDBB_UPDATE_GOLDEN";
+
+/// #Descriptions: `value` encodes to the frame in `dir/name.resp` and decodes from it,
+/// or the file is written when `DDBB_UPDATE_GOLDEN` is `1`.
+pub fn assert_golden<T: FrameCast + Debug>(dir: &Path, name: &str, value: &T) {
+    let path = golden_path(dir, name);
+    let encoded = encode(&value.to_frame());
+    if env::var(UPDATE_ENV).map_or(false, |update| update == "1") {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(&path, &encoded).unwrap();
+        return;
+    }
+    let golden = read(&path);
+    assert_eq!(
+        String::from_utf8_lossy(&encoded),
+        String::from_utf8_lossy(&golden),
+        "{} is encoded differently",
+        name
+    );
+    assert_decodes(dir, name, value);
+}
+
+/// #Descriptions: the frame in `dir/name.resp`, e.g. as sent by an older version,
+/// decodes to `value`.
+pub fn assert_decodes<T: FrameCast + Debug>(dir: &Path, name: &str, value: &T) {
+    let golden = read(&golden_path(dir, name));
+    let mut cursor = Cursor::new(&golden[..]);
+    Frame::check(&mut cursor).unwrap_or_else(|e| panic!("{} is not a frame: {}", name, e));
+    assert_eq!(
+        cursor.position() as usize,
+        golden.len(),
+        "{} is more than a frame",
+        name
+    );
+    cursor.set_position(0);
+    let frame = Frame::parse(&mut cursor).unwrap();
+    let decoded =
+        T::from_frame(&frame).unwrap_or_else(|e| panic!("{} does not decode: {}", name, e));
+    assert_eq!(
+        format!("{:?}", decoded),
+        format!("{:?}", value),
+        "{} is decoded differently",
+        name
+    );
+}
+
+fn golden_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.resp", name))
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+fn encode(frame: &Frame) -> Vec<u8> {
+    let mut encoded = BytesMut::new();
+    encode_frame(frame, &mut encoded);
+    encoded.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structure::{
+        AdminEntry, AuthEntry, CommandEntry, DataEntry, ElectionEventEntry, KeepAliveEntry,
+        KeyMeta, LogEntry, MessageEntry, NotLeaderEntry, WatchBatchEntry, WatchDelivery,
+        WatchEventEntry,
+    };
+    use crate::protocol::{Op, Request, Response, ResponseBody};
+    use bytes::Bytes;
+
+    fn golden_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden")
+    }
+
+    fn opid() -> (String, u64) {
+        ("127.0.0.1:6550".to_string(), 1)
+    }
+
+    fn meta() -> KeyMeta {
+        KeyMeta {
+            create_rev: 1,
+            mod_rev: 2,
+            version: 2,
+            value_len: 1,
+            lease: None,
+        }
+    }
+
+    fn get(key: &str) -> Box<CommandEntry> {
+        Box::new(CommandEntry::GetValue {
+            key: key.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_golden_log_entries() {
+        let logs = vec![
+            (
+                "log_set_value",
+                LogEntry::SetValue {
+                    key: "k".to_string(),
+                    value: Vec::from("v"),
+                },
+            ),
+            (
+                "log_lin_read",
+                LogEntry::LINRead {
+                    opid: opid(),
+                    key: "k".to_string(),
+                    value: None,
+                },
+            ),
+            (
+                "log_lin_write",
+                LogEntry::LINWrite {
+                    opid: opid(),
+                    key: "k".to_string(),
+                    value: Vec::from("v"),
+                },
+            ),
+            (
+                "log_lin_stat",
+                LogEntry::LINStat {
+                    opid: opid(),
+                    key: "k".to_string(),
+                    value: Some(Vec::from("v")),
+                    meta: Some(meta()),
+                },
+            ),
+            ("log_compact", LogEntry::Compact),
+            (
+                "log_sem_acquire",
+                LogEntry::SemAcquire {
+                    opid: opid(),
+                    name: "s".to_string(),
+                    permits: 2,
+                    session: 7,
+                    now: 5,
+                    acquired: true,
+                },
+            ),
+            (
+                "log_sem_release",
+                LogEntry::SemRelease {
+                    opid: opid(),
+                    name: "s".to_string(),
+                    session: 7,
+                },
+            ),
+            (
+                "log_put_if_revision",
+                LogEntry::PutIfRevision {
+                    opid: opid(),
+                    key: "k".to_string(),
+                    value: Vec::from("v"),
+                    expected_mod_rev: 2,
+                    succeeded: true,
+                    mod_rev: 3,
+                },
+            ),
+            (
+                "log_create_namespace",
+                LogEntry::CreateNamespace {
+                    opid: opid(),
+                    name: "app".to_string(),
+                    max_keys: 100,
+                    token: Some("t".to_string()),
+                    created: true,
+                },
+            ),
+            (
+                "log_delete_namespace",
+                LogEntry::DeleteNamespace {
+                    opid: opid(),
+                    name: "app".to_string(),
+                    deleted: true,
+                },
+            ),
+            (
+                "log_bulk_set",
+                LogEntry::BulkSet {
+                    opid: opid(),
+                    pairs: vec![
+                        ("a".to_string(), Vec::from("1")),
+                        ("b".to_string(), Vec::from("2")),
+                    ],
+                },
+            ),
+            (
+                "log_delete_tree",
+                LogEntry::DeleteTree {
+                    opid: opid(),
+                    path: "/app".to_string(),
+                    deleted: vec!["/app/a".to_string()],
+                },
+            ),
+            (
+                "log_delete_prefix",
+                LogEntry::DeletePrefix {
+                    opid: opid(),
+                    prefix: "/app/".to_string(),
+                    limit: 1000,
+                    deleted: vec!["/app/a".to_string()],
+                },
+            ),
+            (
+                "log_compact_revision",
+                LogEntry::CompactRevision {
+                    opid: opid(),
+                    revision: 100,
+                    compacted: 100,
+                },
+            ),
+            (
+                "log_backup",
+                LogEntry::Backup {
+                    opid: opid(),
+                    node: 2,
+                    revision: Some(100),
+                },
+            ),
+            (
+                "log_campaign",
+                LogEntry::Campaign {
+                    opid: opid(),
+                    election: "e".to_string(),
+                    candidate: "c".to_string(),
+                    ttl: 1000,
+                    now: 5,
+                    leader: Some("c".to_string()),
+                },
+            ),
+            (
+                "log_resign",
+                LogEntry::Resign {
+                    opid: opid(),
+                    election: "e".to_string(),
+                    candidate: "c".to_string(),
+                    leader: None,
+                },
+            ),
+            (
+                "log_fenced_write",
+                LogEntry::FencedWrite {
+                    opid: opid(),
+                    key: "k".to_string(),
+                    value: Vec::from("v"),
+                    election: "e".to_string(),
+                    token: 4,
+                    succeeded: true,
+                    fence: 4,
+                },
+            ),
+            (
+                "log_value_chunk",
+                LogEntry::ValueChunk {
+                    opid: opid(),
+                    upload: 0,
+                    seq: 1,
+                    data: Vec::from("v"),
+                    now: 5,
+                },
+            ),
+            (
+                "log_chunked_set",
+                LogEntry::ChunkedSet {
+                    opid: opid(),
+                    key: "k".to_string(),
+                    chunks: 2,
+                    written: true,
+                },
+            ),
+            (
+                "log_open_session",
+                LogEntry::OpenSession {
+                    opid: opid(),
+                    ttl: 1000,
+                    now: 5,
+                    session: 1,
+                },
+            ),
+            (
+                "log_keep_alive",
+                LogEntry::KeepAlive {
+                    opid: opid(),
+                    session: 1,
+                    now: 5,
+                    alive: true,
+                },
+            ),
+            (
+                "log_ephemeral_set",
+                LogEntry::EphemeralSet {
+                    opid: opid(),
+                    session: 1,
+                    key: "k".to_string(),
+                    value: Vec::from("v"),
+                    written: true,
+                },
+            ),
+            (
+                "log_close_session",
+                LogEntry::CloseSession {
+                    opid: opid(),
+                    session: 1,
+                    now: 5,
+                    only_expired: false,
+                    closed: true,
+                    deleted: vec!["k".to_string()],
+                },
+            ),
+        ];
+        for (name, log) in logs {
+            assert_golden(&golden_dir(), name, &log);
+        }
+    }
+
+    #[test]
+    fn test_golden_commands() {
+        let scan = || {
+            Box::new(CommandEntry::Scan {
+                prefix: "/app".to_string(),
+            })
+        };
+        let commands = vec![
+            (
+                "command_set_value",
+                CommandEntry::SetValue {
+                    key: "k".to_string(),
+                    value: Bytes::from("v"),
+                },
+            ),
+            ("command_get_value", *get("k")),
+            (
+                "command_stale_get",
+                CommandEntry::StaleGet {
+                    key: "k".to_string(),
+                },
+            ),
+            (
+                "command_stat",
+                CommandEntry::Stat {
+                    key: "k".to_string(),
+                },
+            ),
+            (
+                "command_put_if_revision",
+                CommandEntry::PutIfRevision {
+                    key: "k".to_string(),
+                    value: Bytes::from("v"),
+                    expected_mod_rev: 3,
+                },
+            ),
+            (
+                "command_deadline",
+                CommandEntry::Deadline {
+                    timeout_ms: 500,
+                    cmd: get("k"),
+                },
+            ),
+            (
+                "command_namespaced",
+                CommandEntry::Namespaced {
+                    namespace: "app".to_string(),
+                    token: "t".to_string(),
+                    cmd: Box::new(CommandEntry::StaleGet {
+                        key: "k".to_string(),
+                    }),
+                },
+            ),
+            (
+                "command_bulk_load",
+                CommandEntry::BulkLoad {
+                    pairs: vec![
+                        ("a".to_string(), Bytes::from("1")),
+                        ("b".to_string(), Bytes::from("2")),
+                    ],
+                },
+            ),
+            ("command_scan", *scan()),
+            (
+                "command_at_revision",
+                CommandEntry::AtRevision {
+                    revision: 7,
+                    cmd: scan(),
+                },
+            ),
+            ("command_revision", CommandEntry::Revision),
+            (
+                "command_list_children",
+                CommandEntry::ListChildren {
+                    path: "/app".to_string(),
+                },
+            ),
+            (
+                "command_delete_tree",
+                CommandEntry::DeleteTree {
+                    path: "/app".to_string(),
+                },
+            ),
+            (
+                "command_delete_prefix",
+                CommandEntry::DeletePrefix {
+                    prefix: "/app/".to_string(),
+                    limit: 1000,
+                },
+            ),
+            (
+                "command_watch",
+                CommandEntry::Watch {
+                    prefix: "/app".to_string(),
+                    after_revision: 9,
+                    delivery: WatchDelivery::Each,
+                },
+            ),
+            (
+                "command_watch_latest",
+                CommandEntry::Watch {
+                    prefix: "/app".to_string(),
+                    after_revision: 9,
+                    delivery: WatchDelivery::Latest,
+                },
+            ),
+            (
+                "command_elect",
+                CommandEntry::Elect {
+                    election: "e".to_string(),
+                    candidate: "c".to_string(),
+                    ttl_ms: 1000,
+                },
+            ),
+            (
+                "command_fenced_set",
+                CommandEntry::FencedSet {
+                    key: "k".to_string(),
+                    value: Bytes::from("v"),
+                    election: "e".to_string(),
+                    token: 4,
+                },
+            ),
+            ("command_open_session", CommandEntry::OpenSession { ttl_ms: 1000 }),
+            ("command_keep_alive", CommandEntry::KeepAlive { session: 1 }),
+            (
+                "command_ephemeral_set",
+                CommandEntry::EphemeralSet {
+                    session: 1,
+                    key: "k".to_string(),
+                    value: Bytes::from("v"),
+                },
+            ),
+            ("command_close_session", CommandEntry::CloseSession { session: 1 }),
+            (
+                "command_keep_alive_stream",
+                CommandEntry::KeepAliveStream { session: 1 },
+            ),
+        ];
+        for (name, command) in commands {
+            assert_golden(&golden_dir(), name, &command);
+        }
+    }
+
+    #[test]
+    fn test_golden_replies() {
+        let data = vec![
+            (
+                "data_key_value",
+                DataEntry::KeyValue {
+                    key: "k".to_string(),
+                    value: Bytes::from("v"),
+                },
+            ),
+            (
+                "data_key_values",
+                DataEntry::KeyValues {
+                    pairs: vec![
+                        ("a".to_string(), Bytes::from("1")),
+                        ("b".to_string(), Bytes::from("2")),
+                    ],
+                },
+            ),
+            (
+                "data_key_value_meta",
+                DataEntry::KeyValueMeta {
+                    key: "k".to_string(),
+                    value: Bytes::from("v"),
+                    meta: meta(),
+                },
+            ),
+            (
+                "data_stat",
+                DataEntry::Stat {
+                    key: "k".to_string(),
+                    meta: meta(),
+                },
+            ),
+            (
+                "data_children",
+                DataEntry::Children {
+                    children: vec!["a".to_string(), "b".to_string()],
+                },
+            ),
+        ];
+        for (name, data) in data {
+            assert_golden(&golden_dir(), name, &data);
+        }
+        let success = MessageEntry::Success {
+            msg: "ok".to_string(),
+        };
+        assert_golden(&golden_dir(), "message_success", &success);
+        let error = MessageEntry::Error {
+            err_msg: "not leader".to_string(),
+        };
+        assert_golden(&golden_dir(), "message_error", &error);
+        let not_leader = NotLeaderEntry {
+            leader_id: Some(2),
+            leader_addr: Some("127.0.0.1:6142".to_string()),
+            ballot: 3,
+        };
+        assert_golden(&golden_dir(), "not_leader", &not_leader);
+        let unknown = NotLeaderEntry {
+            leader_id: None,
+            leader_addr: None,
+            ballot: 0,
+        };
+        assert_golden(&golden_dir(), "not_leader_unknown", &unknown);
+        let watch_event = WatchEventEntry {
+            revision: 5,
+            key: "k".to_string(),
+            value: Bytes::from("v"),
+            deleted: false,
+        };
+        assert_golden(&golden_dir(), "watch_event", &watch_event);
+        let watch_batch = WatchBatchEntry {
+            revision: 6,
+            coalesced: true,
+            events: vec![WatchEventEntry {
+                revision: 6,
+                ..watch_event
+            }],
+        };
+        assert_golden(&golden_dir(), "watch_batch", &watch_batch);
+        let election_event = ElectionEventEntry {
+            election: "e".to_string(),
+            leader: Some("c".to_string()),
+            elected: true,
+            fencing_token: Some(4),
+        };
+        assert_golden(&golden_dir(), "election_event", &election_event);
+        let no_leader = ElectionEventEntry {
+            election: "e".to_string(),
+            leader: None,
+            elected: false,
+            fencing_token: None,
+        };
+        assert_golden(&golden_dir(), "election_event_no_leader", &no_leader);
+        let keep_alive = KeepAliveEntry {
+            session: 1,
+            ttl_ms: 1000,
+        };
+        assert_golden(&golden_dir(), "keep_alive_event", &keep_alive);
+    }
+
+    #[test]
+    fn test_golden_admin() {
+        let auth = AuthEntry {
+            token: "secret".to_string(),
+        };
+        assert_golden(&golden_dir(), "auth", &auth);
+        let admin = vec![
+            ("admin_slow_log", AdminEntry::SlowLog),
+            ("admin_metrics", AdminEntry::Metrics),
+            ("admin_catch_up", AdminEntry::CatchUp),
+            ("admin_status", AdminEntry::Status),
+            (
+                "admin_create_namespace",
+                AdminEntry::CreateNamespace {
+                    name: "app".to_string(),
+                    max_keys: 100,
+                    token: Some("t".to_string()),
+                },
+            ),
+            (
+                "admin_create_namespace_no_token",
+                AdminEntry::CreateNamespace {
+                    name: "app".to_string(),
+                    max_keys: 0,
+                    token: None,
+                },
+            ),
+            (
+                "admin_delete_namespace",
+                AdminEntry::DeleteNamespace {
+                    name: "app".to_string(),
+                },
+            ),
+            ("admin_config", AdminEntry::Config),
+            (
+                "admin_set_config",
+                AdminEntry::SetConfig {
+                    name: "compact_every".to_string(),
+                    value: "100".to_string(),
+                },
+            ),
+            (
+                "admin_export_snapshot",
+                AdminEntry::ExportSnapshot {
+                    path: "/tmp/snapshot".to_string(),
+                },
+            ),
+            ("admin_list_backups", AdminEntry::ListBackups),
+            (
+                "admin_restore_backup",
+                AdminEntry::RestoreBackup {
+                    name: "backup-1".to_string(),
+                },
+            ),
+            ("admin_events", AdminEntry::Events),
+            ("admin_cluster_status", AdminEntry::ClusterStatus),
+            ("admin_step_down", AdminEntry::StepDown),
+            ("admin_cluster_topology", AdminEntry::ClusterTopology),
+            (
+                "admin_set_link_latency",
+                AdminEntry::SetLinkLatency {
+                    peer: 2,
+                    delay_ms: 100,
+                    jitter_ms: 20,
+                },
+            ),
+            ("admin_keyspace", AdminEntry::Keyspace),
+            (
+                "admin_compact_revision",
+                AdminEntry::CompactRevision { revision: 100 },
+            ),
+            ("admin_backup", AdminEntry::Backup { node: 2 }),
+        ];
+        for (name, admin) in admin {
+            assert_golden(&golden_dir(), name, &admin);
+        }
+    }
+
+    #[test]
+    fn test_golden_protocol() {
+        let request = Request::new(
+            1,
+            Op::Get {
+                key: "k".to_string(),
+            },
+        );
+        assert_golden(&golden_dir(), "request_get", &request);
+        let response = Response {
+            id: 1,
+            body: ResponseBody::Value {
+                key: "k".to_string(),
+                value: Vec::from("v"),
+                meta: None,
+            },
+        };
+        assert_golden(&golden_dir(), "response_value", &response);
+    }
+
+    #[test]
+    fn test_golden_older_versions() {
+        // the metadata before keys had a lease
+        let stat = DataEntry::Stat {
+            key: "k".to_string(),
+            meta: meta(),
+        };
+        assert_decodes(&golden_dir(), "data_stat_v0", &stat);
+        // the election events before fencing tokens
+        let election_event = ElectionEventEntry {
+            election: "e".to_string(),
+            leader: Some("c".to_string()),
+            elected: true,
+            fencing_token: None,
+        };
+        assert_decodes(&golden_dir(), "election_event_v0", &election_event);
+        // the semaphore logs before permits were held by sessions, never acquiring
+        let sem_acquire = LogEntry::SemAcquire {
+            opid: opid(),
+            name: "s".to_string(),
+            permits: 2,
+            session: 0,
+            now: 5,
+            acquired: true,
+        };
+        assert_decodes(&golden_dir(), "log_sem_acquire_v0", &sem_acquire);
+        let sem_release = LogEntry::SemRelease {
+            opid: opid(),
+            name: "s".to_string(),
+            session: 0,
+        };
+        assert_decodes(&golden_dir(), "log_sem_release_v0", &sem_release);
+        // the value chunks before uploads expired
+        let value_chunk = LogEntry::ValueChunk {
+            opid: opid(),
+            upload: 0,
+            seq: 1,
+            data: Vec::from("v"),
+            now: 0,
+        };
+        assert_decodes(&golden_dir(), "log_value_chunk_v0", &value_chunk);
+    }
+}
@@ -1,14 +1,21 @@
 #![allow(unused)]
 
+pub mod clock;
 pub mod frame;
 pub mod connection;
 pub mod data_structure;
+pub mod error;
+pub mod golden;
+pub mod protocol;
 
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub use error::Error;
 
 /// Just for convenience.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Version of the client protocol crate, reported by the servers built with it.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
@@ -0,0 +1,611 @@
+//! The client protocol: a `Request` carries an id, an opcode, a consistency level and
+//! the payload of the opcode, and is answered by a `Response` with the same id. Both
+//! are encoded with the `PROTOCOL_VERSION` they were written in, independently of the
+//! `CommandEntry` and `LogEntry` frames the nodes use between themselves, so that the
+//! internal frames can change without breaking the clients.
+//!
+//! A request frame is `[+Request, :version, :id, +opcode, $consistency, $options,
+//! $payload]` and a response frame `[+Response, :version, :id, $body]`, with the `$`
+//! fields in json. Fields added to them later are optional, so a node decodes the
+//! frames of any version up to its own, ignoring the fields it does not know.
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::data_structure::{
+    CommandEntry, DataEntry, FrameCast, KeyMeta, MessageEntry, NotLeaderEntry,
+};
+use crate::frame::Frame;
+use crate::{Error, Result};
+
+/// Version of the frames written, frames of a higher version are refused.
+pub const PROTOCOL_VERSION: u64 = 1;
+
+const REQUEST_TAG: &str = "Request";
+const RESPONSE_TAG: &str = "Response";
+
+/// How fresh the read of a request must be, writes are always linearizable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "level", rename_all = "snake_case")]
+pub enum Consistency {
+    /// Read through the leader, observing every write answered before it
+    Linearizable,
+    /// Read from the node serving the client, possibly stale
+    Stale,
+    /// Read the store as it was at `revision`, from any node that applied it
+    AtRevision { revision: u64 },
+}
+
+impl Default for Consistency {
+    fn default() -> Self {
+        Consistency::Linearizable
+    }
+}
+
+/// The namespace the keys of a request are in, see `nscreate`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceRef {
+    pub name: String,
+    /// empty for a namespace without a token
+    #[serde(default)]
+    pub token: String,
+}
+
+/// What a request applies to besides its payload, all optional.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestOptions {
+    /// the server gives up waiting for the request after this many ms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<NamespaceRef>,
+}
+
+/// The opcode of a request with its payload.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "opcode", content = "payload", rename_all = "snake_case")]
+pub enum Op {
+    Get {
+        key: String,
+    },
+    /// The metadata of `key`, without its value
+    Stat {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: Vec<u8>,
+    },
+    /// Write only if the key was last modified at `expected_mod_rev`, 0 for a missing key
+    PutIfRevision {
+        key: String,
+        value: Vec<u8>,
+        expected_mod_rev: u64,
+    },
+    /// Write with the fencing `token` of a leader of `election`
+    FencedSet {
+        key: String,
+        value: Vec<u8>,
+        election: String,
+        token: u64,
+    },
+    /// Write all `pairs` in a single proposal
+    BulkLoad {
+        pairs: Vec<(String, Vec<u8>)>,
+    },
+    /// The keys under `prefix`, never linearizable
+    Scan {
+        prefix: String,
+    },
+    /// The names right under `path`, never linearizable
+    ListChildren {
+        path: String,
+    },
+    DeleteTree {
+        path: String,
+    },
+    /// Delete the first `limit` keys under `prefix`, a batch of a bulk delete
+    DeletePrefix {
+        prefix: String,
+        limit: u64,
+    },
+    /// The revision of the latest write applied, to pin for `Consistency::AtRevision`
+    Revision,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    /// chosen by the client, echoed in the response
+    pub id: u64,
+    pub consistency: Consistency,
+    pub options: RequestOptions,
+    pub op: Op,
+}
+
+/// The body of a response, by the kind of reply.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseBody {
+    /// A request answered with a message only, e.g. a write
+    Done { msg: String },
+    Value {
+        key: String,
+        value: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<KeyMeta>,
+    },
+    Values { pairs: Vec<(String, Vec<u8>)> },
+    Stat { key: String, meta: KeyMeta },
+    Children { children: Vec<String> },
+    /// The request has to go to the leader, at `leader_addr` if known
+    NotLeader {
+        leader_id: Option<u64>,
+        leader_addr: Option<String>,
+        ballot: u64,
+    },
+    /// The `Display` of the error raised by the server, see `Error::from_message`
+    Error { message: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    /// id of the request answered
+    pub id: u64,
+    pub body: ResponseBody,
+}
+
+impl Request {
+    pub fn new(id: u64, op: Op) -> Self {
+        Self {
+            id,
+            consistency: Consistency::default(),
+            options: RequestOptions::default(),
+            op,
+        }
+    }
+
+    /// #Descriptions: whether `frame` is a request, of any version.
+    pub fn is_request(frame: &Frame) -> bool {
+        match frame {
+            Frame::Array(frame_vec) => frame_vec.first().map_or(false, |tag| *tag == REQUEST_TAG),
+            _ => false,
+        }
+    }
+
+    /// #Descriptions: the command the nodes run for the request.
+    pub fn into_command(self) -> CommandEntry {
+        let cmd = match self.op {
+            Op::Get { key } => match self.consistency {
+                Consistency::Linearizable => CommandEntry::GetValue { key },
+                _ => CommandEntry::StaleGet { key },
+            },
+            Op::Stat { key } => CommandEntry::Stat { key },
+            Op::Set { key, value } => CommandEntry::SetValue {
+                key,
+                value: Bytes::from(value),
+            },
+            Op::PutIfRevision {
+                key,
+                value,
+                expected_mod_rev,
+            } => CommandEntry::PutIfRevision {
+                key,
+                value: Bytes::from(value),
+                expected_mod_rev,
+            },
+            Op::FencedSet {
+                key,
+                value,
+                election,
+                token,
+            } => CommandEntry::FencedSet {
+                key,
+                value: Bytes::from(value),
+                election,
+                token,
+            },
+            Op::BulkLoad { pairs } => CommandEntry::BulkLoad {
+                pairs: pairs
+                    .into_iter()
+                    .map(|(key, value)| (key, Bytes::from(value)))
+                    .collect(),
+            },
+            Op::Scan { prefix } => CommandEntry::Scan { prefix },
+            Op::ListChildren { path } => CommandEntry::ListChildren { path },
+            Op::DeleteTree { path } => CommandEntry::DeleteTree { path },
+            Op::DeletePrefix { prefix, limit } => CommandEntry::DeletePrefix { prefix, limit },
+            Op::Revision => CommandEntry::Revision,
+        };
+        let cmd = match self.consistency {
+            Consistency::AtRevision { revision } => CommandEntry::AtRevision {
+                revision,
+                cmd: Box::new(cmd),
+            },
+            _ => cmd,
+        };
+        let cmd = match self.options.namespace {
+            Some(namespace) => CommandEntry::Namespaced {
+                namespace: namespace.name,
+                token: namespace.token,
+                cmd: Box::new(cmd),
+            },
+            None => cmd,
+        };
+        match self.options.deadline_ms {
+            Some(timeout_ms) => CommandEntry::Deadline {
+                timeout_ms,
+                cmd: Box::new(cmd),
+            },
+            None => cmd,
+        }
+    }
+}
+
+impl Response {
+    /// #Descriptions: the response to request `id` answered with `reply`, a frame of
+    /// the nodes.
+    pub fn from_reply(id: u64, reply: &Frame) -> Self {
+        let body = if let Ok(data) = DataEntry::from_frame(reply) {
+            match *data {
+                DataEntry::KeyValue { key, value } => ResponseBody::Value {
+                    key,
+                    value: value.to_vec(),
+                    meta: None,
+                },
+                DataEntry::KeyValueMeta { key, value, meta } => ResponseBody::Value {
+                    key,
+                    value: value.to_vec(),
+                    meta: Some(meta),
+                },
+                DataEntry::KeyValues { pairs } => ResponseBody::Values {
+                    pairs: pairs
+                        .into_iter()
+                        .map(|(key, value)| (key, value.to_vec()))
+                        .collect(),
+                },
+                DataEntry::Stat { key, meta } => ResponseBody::Stat { key, meta },
+                DataEntry::Children { children } => ResponseBody::Children { children },
+            }
+        } else if let Ok(not_leader) = NotLeaderEntry::from_frame(reply) {
+            ResponseBody::NotLeader {
+                leader_id: not_leader.leader_id,
+                leader_addr: not_leader.leader_addr,
+                ballot: not_leader.ballot,
+            }
+        } else {
+            match MessageEntry::from_frame(reply).map(|msg| *msg) {
+                Ok(MessageEntry::Success { msg }) => ResponseBody::Done { msg },
+                Ok(MessageEntry::Error { err_msg }) => ResponseBody::Error { message: err_msg },
+                Err(e) => ResponseBody::Error {
+                    message: e.to_string(),
+                },
+            }
+        };
+        Self { id, body }
+    }
+
+    /// #Descriptions: the body, or the error the server answered with.
+    pub fn into_result(self) -> Result<ResponseBody> {
+        match self.body {
+            ResponseBody::Error { message } => Err(Error::from_message(&message)),
+            ResponseBody::NotLeader { .. } => Err(Error::NotLeader),
+            body => Ok(body),
+        }
+    }
+}
+
+fn json_frame<T: Serialize>(value: &T) -> Frame {
+    Frame::Bulk(serde_json::to_vec(value).unwrap().into())
+}
+
+fn json_value(frame: &Frame, whole: &Frame) -> Result<Value> {
+    match frame {
+        Frame::Bulk(json) => Ok(serde_json::from_slice(json)?),
+        _ => Err(whole.to_error()),
+    }
+}
+
+fn check_version(version: u64) -> Result<()> {
+    if version > PROTOCOL_VERSION {
+        return Err(Error::FrameDecode(format!(
+            "protocol version {} not supported, at most {}",
+            version, PROTOCOL_VERSION
+        )));
+    }
+    Ok(())
+}
+
+impl FrameCast for Request {
+    fn to_frame(&self) -> Frame {
+        let mut op = serde_json::to_value(&self.op).unwrap();
+        let opcode = op["opcode"].as_str().unwrap_or_default().to_string();
+        let payload = op
+            .as_object_mut()
+            .and_then(|op| op.remove("payload"))
+            .unwrap_or(Value::Null);
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple(REQUEST_TAG.to_string()),
+            Frame::Integer(PROTOCOL_VERSION),
+            Frame::Integer(self.id),
+            Frame::Simple(opcode),
+            json_frame(&self.consistency),
+            json_frame(&self.options),
+            json_frame(&payload),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Integer(version), Frame::Integer(id), opcode, consistency, options, payload]
+                    if *begin_tag == REQUEST_TAG =>
+                {
+                    check_version(*version)?;
+                    let mut op = Map::new();
+                    op.insert("opcode".to_string(), Value::String(opcode.to_string()));
+                    let payload = json_value(payload, frame)?;
+                    if !payload.is_null() {
+                        op.insert("payload".to_string(), payload);
+                    }
+                    let op = serde_json::from_value(Value::Object(op)).map_err(|e| {
+                        Error::FrameDecode(format!("opcode {}: {}", opcode, e))
+                    })?;
+                    Ok(Box::new(Request {
+                        id: *id,
+                        consistency: serde_json::from_value(json_value(consistency, frame)?)?,
+                        options: serde_json::from_value(json_value(options, frame)?)?,
+                        op,
+                    }))
+                }
+                _ => Err(frame.to_error()),
+            },
+            _ => Err(frame.to_error()),
+        }
+    }
+}
+
+impl FrameCast for Response {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple(RESPONSE_TAG.to_string()),
+            Frame::Integer(PROTOCOL_VERSION),
+            Frame::Integer(self.id),
+            json_frame(&self.body),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Integer(version), Frame::Integer(id), body]
+                    if *begin_tag == RESPONSE_TAG =>
+                {
+                    check_version(*version)?;
+                    Ok(Box::new(Response {
+                        id: *id,
+                        body: serde_json::from_value(json_value(body, frame)?)?,
+                    }))
+                }
+                _ => Err(frame.to_error()),
+            },
+            _ => Err(frame.to_error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::encode_frame;
+    use bytes::BytesMut;
+    use std::io::Cursor;
+
+    fn ops() -> Vec<Op> {
+        let key = || "k".to_string();
+        let value = || Vec::from("v");
+        vec![
+            Op::Get { key: key() },
+            Op::Stat { key: key() },
+            Op::Set {
+                key: key(),
+                value: value(),
+            },
+            Op::PutIfRevision {
+                key: key(),
+                value: value(),
+                expected_mod_rev: 3,
+            },
+            Op::FencedSet {
+                key: key(),
+                value: value(),
+                election: "e".to_string(),
+                token: 7,
+            },
+            Op::BulkLoad {
+                pairs: vec![(key(), value()), ("k2".to_string(), Vec::new())],
+            },
+            Op::Scan {
+                prefix: "a/".to_string(),
+            },
+            Op::ListChildren {
+                path: "a".to_string(),
+            },
+            Op::DeleteTree {
+                path: "a".to_string(),
+            },
+            Op::DeletePrefix {
+                prefix: "a/".to_string(),
+                limit: 100,
+            },
+            Op::Revision,
+        ]
+    }
+
+    fn bodies() -> Vec<ResponseBody> {
+        let meta = KeyMeta {
+            create_rev: 1,
+            mod_rev: 2,
+            version: 2,
+            value_len: 1,
+            lease: None,
+        };
+        vec![
+            ResponseBody::Done {
+                msg: "ok".to_string(),
+            },
+            ResponseBody::Value {
+                key: "k".to_string(),
+                value: Vec::from("v"),
+                meta: None,
+            },
+            ResponseBody::Value {
+                key: "k".to_string(),
+                value: Vec::from("v"),
+                meta: Some(meta.clone()),
+            },
+            ResponseBody::Values {
+                pairs: vec![("k".to_string(), Vec::from("v"))],
+            },
+            ResponseBody::Stat {
+                key: "k".to_string(),
+                meta,
+            },
+            ResponseBody::Children {
+                children: vec!["a".to_string(), "b".to_string()],
+            },
+            ResponseBody::NotLeader {
+                leader_id: Some(2),
+                leader_addr: None,
+                ballot: 4,
+            },
+            ResponseBody::Error {
+                message: "conflict: stale revision".to_string(),
+            },
+        ]
+    }
+
+    /// Through the bytes sent on a connection and back.
+    fn round_trip<T: FrameCast>(value: &T) -> T {
+        let mut encoded = BytesMut::new();
+        encode_frame(&value.to_frame(), &mut encoded);
+        let frame = Frame::parse(&mut Cursor::new(&encoded[..])).unwrap();
+        *T::from_frame(&frame).unwrap()
+    }
+
+    #[test]
+    fn test_request_round_trip() {
+        let consistencies = [
+            Consistency::Linearizable,
+            Consistency::Stale,
+            Consistency::AtRevision { revision: 9 },
+        ];
+        let options = [
+            RequestOptions::default(),
+            RequestOptions {
+                deadline_ms: Some(500),
+                namespace: Some(NamespaceRef {
+                    name: "ns".to_string(),
+                    token: "t".to_string(),
+                }),
+            },
+        ];
+        for (id, op) in ops().into_iter().enumerate() {
+            for consistency in consistencies {
+                for options in options.clone() {
+                    let request = Request {
+                        id: id as u64,
+                        consistency,
+                        options,
+                        op: op.clone(),
+                    };
+                    assert_eq!(round_trip(&request), request);
+                    let json = serde_json::to_string(&request).unwrap();
+                    assert_eq!(serde_json::from_str::<Request>(&json).unwrap(), request);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        for (id, body) in bodies().into_iter().enumerate() {
+            let response = Response {
+                id: id as u64,
+                body,
+            };
+            assert_eq!(round_trip(&response), response);
+            let json = serde_json::to_string(&response).unwrap();
+            assert_eq!(serde_json::from_str::<Response>(&json).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn test_protocol_versions() {
+        let request = Request::new(1, Op::Revision);
+        let mut frame_vec = match request.to_frame() {
+            Frame::Array(frame_vec) => frame_vec,
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+        assert!(Request::is_request(&Frame::Array(frame_vec.clone())));
+        // a newer client is refused instead of misread
+        frame_vec[1] = Frame::Integer(PROTOCOL_VERSION + 1);
+        assert!(matches!(
+            Request::from_frame(&Frame::Array(frame_vec.clone())),
+            Err(Error::FrameDecode(_))
+        ));
+        // fields added by a newer minor change are ignored
+        frame_vec[1] = Frame::Integer(PROTOCOL_VERSION);
+        frame_vec[5] = Frame::Bulk(Bytes::from(r#"{"deadline_ms":5,"priority":1}"#));
+        let decoded = Request::from_frame(&Frame::Array(frame_vec.clone())).unwrap();
+        assert_eq!(decoded.options.deadline_ms, Some(5));
+        frame_vec[3] = Frame::Simple("unknown".to_string());
+        assert!(Request::from_frame(&Frame::Array(frame_vec)).is_err());
+    }
+
+    #[test]
+    fn test_request_into_command() {
+        let mut request = Request::new(
+            1,
+            Op::Get {
+                key: "k".to_string(),
+            },
+        );
+        assert!(matches!(request.clone().into_command(), CommandEntry::GetValue { .. }));
+        request.consistency = Consistency::Stale;
+        assert!(matches!(request.clone().into_command(), CommandEntry::StaleGet { .. }));
+        request.consistency = Consistency::AtRevision { revision: 3 };
+        request.options.deadline_ms = Some(100);
+        match request.into_command() {
+            CommandEntry::Deadline { timeout_ms, cmd } => {
+                assert_eq!(timeout_ms, 100);
+                assert!(matches!(*cmd, CommandEntry::AtRevision { revision: 3, .. }));
+            }
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn test_response_from_reply() {
+        let reply = MessageEntry::Error {
+            err_msg: Error::Overloaded("retry later".to_string()).to_string(),
+        };
+        let response = Response::from_reply(4, &reply.to_frame());
+        assert_eq!(response.id, 4);
+        assert!(matches!(response.into_result(), Err(Error::Overloaded(_))));
+        let reply = DataEntry::KeyValue {
+            key: "k".to_string(),
+            value: Bytes::from("v"),
+        };
+        assert_eq!(
+            Response::from_reply(5, &reply.to_frame()).body,
+            ResponseBody::Value {
+                key: "k".to_string(),
+                value: Vec::from("v"),
+                meta: None,
+            }
+        );
+    }
+}
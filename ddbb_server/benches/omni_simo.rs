@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use omnipaxos_core::messages::sequence_paxos::{PaxosMessage, PaxosMsg};
+use omnipaxos_core::util::NodeId;
+
+use ddbb_server::omni_paxos_server::op_connection::OmniSIMO;
+use ddbb_server::omni_paxos_server::op_data_structure::LogEntry;
+use ddbb_server::omni_paxos_server::OmniMessage;
+use ddbb_server::proposal_queue::ProposalQueue;
+
+const PEERS: [NodeId; 3] = [2, 3, 4];
+
+fn omni_simo() -> OmniSIMO {
+    let peers: HashMap<NodeId, String> = PEERS
+        .iter()
+        .map(|peer| (*peer, format!("127.0.0.1:{}", 6550 + peer)))
+        .collect();
+    OmniSIMO::new("127.0.0.1:6551".to_string(), peers)
+}
+
+fn msg(to: NodeId) -> OmniMessage {
+    OmniMessage::SequencePaxos(PaxosMessage {
+        from: 1,
+        to,
+        msg: PaxosMsg::PrepareReq,
+    })
+}
+
+/// Queueing msgs for the senders, the path of every msg omnipaxos produces.
+fn bench_send_message(c: &mut Criterion) {
+    let simo = omni_simo();
+    let msgs: Vec<OmniMessage> = (0..256).map(|i| msg(PEERS[i % PEERS.len()])).collect();
+    c.bench_function("omni_simo/send_message_256", |b| {
+        b.iter(|| {
+            for msg in &msgs {
+                simo.send_message(black_box(msg));
+            }
+            simo.outgoing_buffer.lock().unwrap().clear();
+        })
+    });
+}
+
+/// The per peer stats of the status admin command, with msgs queued.
+fn bench_peer_stats(c: &mut Criterion) {
+    let simo = omni_simo();
+    for i in 0..1000 {
+        simo.send_message(&msg(PEERS[i % PEERS.len()]));
+    }
+    c.bench_function("omni_simo/peer_stats_1000_queued", |b| {
+        b.iter(|| black_box(simo.peer_stats()))
+    });
+}
+
+/// Queueing proposals while there is no leader, and taking them once there is.
+fn bench_proposal_queue(c: &mut Criterion) {
+    let deadline = Instant::now() + Duration::from_secs(3600);
+    c.bench_function("proposal_queue/push_take_1000", |b| {
+        b.iter_batched(
+            || ProposalQueue::new(1000),
+            |mut queue| {
+                for ts in 0..1000 {
+                    let log = LogEntry::LINWrite {
+                        opid: ("127.0.0.1:6550".to_string(), ts),
+                        key: "bench/key".to_string(),
+                        value: Vec::from("v"),
+                    };
+                    queue.push(log, deadline).unwrap();
+                }
+                queue.take(Instant::now())
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_send_message,
+    bench_peer_stats,
+    bench_proposal_queue
+);
+criterion_main!(benches);
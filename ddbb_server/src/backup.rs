@@ -0,0 +1,131 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::export::SnapshotExport;
+use ddbb_libs::Result;
+
+const BACKUP_PREFIX: &str = "backup-";
+const BACKUP_EXTENSION: &str = "json";
+
+/// A backup in the backup directory, served as json by the admin API.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub name: String,
+    /// unix ms at which it was taken
+    pub created_ms: u64,
+    pub applied_idx: u64,
+    pub size: u64,
+}
+
+/// Periodic exports of the state machine to a directory, of which only the
+/// latest `retention` are kept.
+#[derive(Clone, Debug)]
+pub struct Backups {
+    dir: String,
+    retention: usize,
+}
+
+impl Backups {
+    pub fn new(dir: String, retention: usize) -> Self {
+        Self { dir, retention }
+    }
+
+    /// #Descriptions: write `export` as a new backup and drop the backups over
+    /// the retention. Returns the name of the new backup.
+    pub fn write(&self, export: &SnapshotExport, created_ms: u64) -> Result<String> {
+        // sorted by name is sorted by time
+        let name = format!(
+            "{}{:016}-{:020}.{}",
+            BACKUP_PREFIX, created_ms, export.applied_idx, BACKUP_EXTENSION
+        );
+        export.write(self.path(&name).to_str().ok_or("invalid backup dir")?)?;
+        self.prune()?;
+        Ok(name)
+    }
+
+    /// #Descriptions: the backups in the directory, oldest first.
+    pub fn list(&self) -> Result<Vec<BackupInfo>> {
+        if !Path::new(&self.dir).exists() {
+            return Ok(Vec::new());
+        }
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some((created_ms, applied_idx)) = parse_name(&name) {
+                backups.push(BackupInfo {
+                    name,
+                    created_ms,
+                    applied_idx,
+                    size: entry.metadata()?.len(),
+                });
+            }
+        }
+        backups.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(backups)
+    }
+
+    /// #Descriptions: read and check the backup `name`.
+    pub fn read(&self, name: &str) -> Result<SnapshotExport> {
+        if parse_name(name).is_none() {
+            return Err(format!("not a backup: {}", name).into());
+        }
+        SnapshotExport::read(self.path(name).to_str().ok_or("invalid backup dir")?)
+    }
+
+    fn prune(&self) -> Result<()> {
+        let backups = self.list()?;
+        let expired = backups.len().saturating_sub(self.retention);
+        for backup in &backups[..expired] {
+            fs::remove_file(self.path(&backup.name))?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        Path::new(&self.dir).join(name)
+    }
+}
+
+/// `(created_ms, applied_idx)` of a backup name written by `Backups::write`.
+fn parse_name(name: &str) -> Option<(u64, u64)> {
+    let stem = name
+        .strip_prefix(BACKUP_PREFIX)?
+        .strip_suffix(BACKUP_EXTENSION)?
+        .strip_suffix('.')?;
+    let (created_ms, applied_idx) = stem.split_once('-')?;
+    Some((created_ms.parse().ok()?, applied_idx.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic_config::DynamicConfig;
+
+    #[test]
+    fn test_backup_retention() {
+        let dir = std::env::temp_dir().join(format!("ddbb_test_backup_{}", std::process::id()));
+        let dir = dir.to_str().unwrap().to_string();
+        let _ = fs::remove_dir_all(&dir);
+        let backups = Backups::new(dir.clone(), 2);
+        for applied_idx in 1..=3 {
+            let state = format!("state {}", applied_idx).into_bytes();
+            let config = DynamicConfig::default();
+            let export = SnapshotExport::new(1, applied_idx, applied_idx, config, state);
+            backups.write(&export, 1000 + applied_idx).unwrap();
+        }
+
+        let listed: Vec<(u64, u64)> = backups
+            .list()
+            .unwrap()
+            .iter()
+            .map(|backup| (backup.created_ms, backup.applied_idx))
+            .collect();
+        assert_eq!(listed, vec![(1002, 2), (1003, 3)]);
+        let latest = &backups.list().unwrap()[1].name;
+        assert_eq!(backups.read(latest).unwrap().state, Vec::from("state 3"));
+        assert!(backups.read("../state_snapshot").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,605 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::{env, fs};
+use uuid::Uuid;
+use tokio::time::{sleep, timeout};
+
+use omnipaxos_core::util::NodeId;
+
+use crate::build_info::BuildInfo;
+use crate::config::{BOOTSTRAP_RETRY_INTERVAL, KEEPALIVE_TIMEOUT, STATEFULSET_PORT};
+use crate::net::{bind_listener, ListenerOptions};
+use crate::tasks::spawn_named;
+use ddbb_libs::connection::Connection;
+use ddbb_libs::data_structure::FrameCast;
+use ddbb_libs::frame::Frame;
+use ddbb_libs::Result;
+
+/// Environment of a StatefulSet pod, usually set from the downward API.
+pub const POD_NAME_ENV: &str = "POD_NAME";
+pub const SERVICE_DOMAIN_ENV: &str = "DDBB_SERVICE_DOMAIN";
+pub const REPLICAS_ENV: &str = "DDBB_REPLICAS";
+pub const PORT_ENV: &str = "DDBB_PORT";
+
+const CLUSTER_UUID_FILE: &str = "cluster_uuid";
+
+/// The identity of this node and its peers.
+#[derive(Debug, PartialEq)]
+pub struct Bootstrap {
+    pub node_id: NodeId,
+    pub node_addr: String,
+    pub peers: HashMap<NodeId, String>,
+}
+
+impl Bootstrap {
+    /// #Descriptions: derive the node from a StatefulSet-style environment,
+    /// see `from_statefulset`.
+    pub fn from_env() -> Result<Self> {
+        let pod_name = env::var(POD_NAME_ENV).map_err(|_| format!("{} is not set", POD_NAME_ENV))?;
+        let domain =
+            env::var(SERVICE_DOMAIN_ENV).map_err(|_| format!("{} is not set", SERVICE_DOMAIN_ENV))?;
+        let replicas = env::var(REPLICAS_ENV)
+            .map_err(|_| format!("{} is not set", REPLICAS_ENV))?
+            .parse::<u64>()
+            .map_err(|e| format!("{}: {}", REPLICAS_ENV, e))?;
+        let port = match env::var(PORT_ENV) {
+            Ok(port) => port.parse::<u16>().map_err(|e| format!("{}: {}", PORT_ENV, e))?,
+            Err(_) => STATEFULSET_PORT,
+        };
+        Self::from_statefulset(&pod_name, &domain, replicas, port)
+    }
+
+    /// #Descriptions: pod `ddbb-2` of a StatefulSet with `replicas` pods behind the
+    /// headless service `domain` becomes node 3 (ordinals start at 0, node ids at 1),
+    /// and pod `ddbb-i` is reached at `ddbb-i.domain:port`.
+    pub fn from_statefulset(pod_name: &str, domain: &str, replicas: u64, port: u16) -> Result<Self> {
+        let (set_name, ordinal) = pod_name
+            .rsplit_once('-')
+            .ok_or(format!("{} is not a StatefulSet pod name", pod_name))?;
+        let ordinal = ordinal
+            .parse::<u64>()
+            .map_err(|_| format!("{} is not a StatefulSet pod name", pod_name))?;
+        if ordinal >= replicas {
+            return Err(format!("pod ordinal {} out of {} replicas", ordinal, replicas).into());
+        }
+        let addr = |ordinal: u64| format!("{}-{}.{}:{}", set_name, ordinal, domain, port);
+        let peers = (0..replicas)
+            .filter(|&i| i != ordinal)
+            .map(|i| (i + 1, addr(i)))
+            .collect();
+        Ok(Self {
+            node_id: ordinal + 1,
+            node_addr: addr(ordinal),
+            peers,
+        })
+    }
+}
+
+/// #Descriptions: check the node ids given on the command line before joining. Ids
+/// start at 1 and every node needs its own id and address, else two processes would
+/// count as the same replica in the quorums.
+pub fn check_node_ids(
+    node_id: NodeId,
+    node_addr: &str,
+    peer_ids: &[NodeId],
+    peers_addrs: &[String],
+) -> Result<()> {
+    if peer_ids.len() != peers_addrs.len() {
+        return Err(format!(
+            "{} peer ids for {} peer addresses",
+            peer_ids.len(),
+            peers_addrs.len()
+        )
+        .into());
+    }
+    let mut ids = HashSet::new();
+    let mut addrs = HashSet::new();
+    let nodes = peer_ids
+        .iter()
+        .zip(peers_addrs.iter().map(|addr| addr.as_str()));
+    for (id, addr) in [(&node_id, node_addr)].into_iter().chain(nodes) {
+        if *id == 0 {
+            return Err("node ids start at 1".into());
+        }
+        if !ids.insert(*id) {
+            return Err(format!("node id {} given twice", id).into());
+        }
+        if !addrs.insert(addr) {
+            return Err(format!("address {} given twice", addr).into());
+        }
+    }
+    Ok(())
+}
+
+/// The initial configuration of a cluster, which every member must agree on
+/// before its OmniPaxos instance is created.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClusterManifest {
+    pub cluster_id: String,
+    /// used as the OmniPaxos configuration id
+    pub epoch: u32,
+    /// every node of the cluster, including learners
+    pub members: BTreeMap<NodeId, String>,
+    pub learners: Vec<NodeId>,
+    /// generated once by a member, then persisted by every member, see `agree_manifest`
+    #[serde(default)]
+    pub cluster_uuid: Option<String>,
+}
+
+impl ClusterManifest {
+    pub fn new(
+        cluster_id: String,
+        epoch: u32,
+        node_id: NodeId,
+        node_addr: String,
+        peers: &HashMap<NodeId, String>,
+        learners: &[NodeId],
+    ) -> Self {
+        let mut members: BTreeMap<NodeId, String> =
+            peers.iter().map(|(id, addr)| (*id, addr.clone())).collect();
+        members.insert(node_id, node_addr);
+        let mut learners = learners.to_vec();
+        learners.sort();
+        learners.dedup();
+        Self {
+            cluster_id,
+            epoch,
+            members,
+            learners,
+            cluster_uuid: None,
+        }
+    }
+
+    /// Whether `other` describes the same cluster, a member that has no uuid
+    /// yet agrees with any uuid.
+    pub fn agrees_with(&self, other: &ClusterManifest) -> bool {
+        let same_uuid = match (&self.cluster_uuid, &other.cluster_uuid) {
+            (Some(uuid), Some(other_uuid)) => uuid == other_uuid,
+            _ => true,
+        };
+        same_uuid
+            && self.cluster_id == other.cluster_id
+            && self.epoch == other.epoch
+            && self.members == other.members
+            && self.learners == other.learners
+    }
+}
+
+/// The node id a process runs as, with an id generated at its start that tells apart
+/// two processes configured with the same node id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeIdentity {
+    pub node_id: NodeId,
+    pub instance_id: String,
+}
+
+impl NodeIdentity {
+    /// The identity of a new process running as `node_id`.
+    pub fn new(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            instance_id: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+/// First frame of every OmniSIMO connection, a listener drops connections
+/// from nodes of another cluster, and from a process running as a node id
+/// another process is connected as.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Handshake {
+    pub cluster_uuid: String,
+    /// build of the node connecting, `None` from nodes older than `BuildInfo`
+    pub build: Option<BuildInfo>,
+    /// the node connecting, `None` from nodes older than `NodeIdentity`
+    pub node: Option<NodeIdentity>,
+    /// which of the connections of the node this is, e.g. `live`: a new one closes the
+    /// one it replaces. Only sent to peers with `Capabilities::SUPERSEDE`
+    pub channel: Option<String>,
+}
+
+impl FrameCast for Handshake {
+    fn to_frame(&self) -> Frame {
+        let mut frame = vec![
+            // begin tag
+            Frame::Simple("Handshake".to_string()),
+            Frame::Simple(self.cluster_uuid.clone()),
+        ];
+        if let Some(build) = &self.build {
+            let build = serde_json::to_vec(build).unwrap_or_default();
+            frame.push(Frame::Bulk(build.into()));
+            // only after a build, every node sending its identity sends its build too
+            if let Some(node) = &self.node {
+                frame.push(Frame::Integer(node.node_id));
+                frame.push(Frame::Simple(node.instance_id.clone()));
+                if let Some(channel) = &self.channel {
+                    frame.push(Frame::Simple(channel.clone()));
+                }
+            }
+        }
+        Frame::Array(frame)
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Simple(cluster_uuid)] if *begin_tag == "Handshake" => {
+                    Ok(Box::new(Handshake {
+                        cluster_uuid: cluster_uuid.clone(),
+                        build: None,
+                        node: None,
+                        channel: None,
+                    }))
+                }
+                [begin_tag, Frame::Simple(cluster_uuid), Frame::Bulk(build)]
+                    if *begin_tag == "Handshake" =>
+                {
+                    let build: BuildInfo =
+                        serde_json::from_slice(build).map_err(|e| e.to_string())?;
+                    Ok(Box::new(Handshake {
+                        cluster_uuid: cluster_uuid.clone(),
+                        build: Some(build),
+                        node: None,
+                        channel: None,
+                    }))
+                }
+                [begin_tag, Frame::Simple(cluster_uuid), Frame::Bulk(build), Frame::Integer(node_id), Frame::Simple(instance_id), channel @ ..]
+                    if *begin_tag == "Handshake" && channel.len() <= 1 =>
+                {
+                    let build: BuildInfo =
+                        serde_json::from_slice(build).map_err(|e| e.to_string())?;
+                    let channel = match channel {
+                        [Frame::Simple(channel)] => Some(channel.clone()),
+                        [] => None,
+                        _ => return Err(frame.to_error()),
+                    };
+                    Ok(Box::new(Handshake {
+                        cluster_uuid: cluster_uuid.clone(),
+                        build: Some(build),
+                        node: Some(NodeIdentity {
+                            node_id: *node_id,
+                            instance_id: instance_id.clone(),
+                        }),
+                        channel,
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+/// #Descriptions: the cluster uuid persisted under `data_dir`, if any.
+pub fn load_cluster_uuid(data_dir: &str) -> Result<Option<String>> {
+    let path = Path::new(data_dir).join(CLUSTER_UUID_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?.trim().to_string()))
+}
+
+pub fn persist_cluster_uuid(data_dir: &str, cluster_uuid: &str) -> Result<()> {
+    fs::create_dir_all(data_dir)?;
+    fs::write(Path::new(data_dir).join(CLUSTER_UUID_FILE), cluster_uuid)?;
+    Ok(())
+}
+
+impl FrameCast for ClusterManifest {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("ClusterManifest".to_string()),
+            Frame::Bulk(serde_json::to_vec(self).unwrap().into()),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Bulk(manifest)] if *begin_tag == "ClusterManifest" => {
+                    let manifest: ClusterManifest =
+                        serde_json::from_slice(manifest).map_err(|e| e.to_string())?;
+                    Ok(Box::new(manifest))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+/// #Descriptions: the joining state of a node. Serve `manifest` on the node's own
+/// address and exchange it with the other members until a majority of the members,
+/// this node included, hold the same manifest. Fails as soon as a member holds a
+/// different one, so that two differently configured subsets never form two clusters.
+/// Started nodes keep answering on their OmniSIMO listener, see `OmniSIMO::set_manifest`.
+/// Returns the manifest with the cluster uuid once a majority of the members hold it: the
+/// lowest id among the members agreeing generates it, so that any majority of them up
+/// gets one, and the others take it over from the members holding it.
+pub async fn agree_manifest(
+    node_id: NodeId,
+    manifest: &ClusterManifest,
+    options: &ListenerOptions,
+) -> Result<ClusterManifest> {
+    let node_addr = manifest
+        .members
+        .get(&node_id)
+        .ok_or(format!("node {} is not a member of the manifest", node_id))?
+        .clone();
+    let listener = bind_listener(&node_addr, options).await?;
+    let manifest = Arc::new(Mutex::new(manifest.clone()));
+    let served = manifest.clone();
+    let server = spawn_named("bootstrap manifest listener", async move {
+        loop {
+            if let Ok((stream, _)) = listener.accept().await {
+                let manifest = served.lock().unwrap().clone();
+                tokio::spawn(async move {
+                    if let Err(e) = answer_manifest(Connection::new(stream), &manifest).await {
+                        error!("Manifest exchange failed: {:?}", e);
+                    }
+                });
+            }
+        }
+    });
+
+    let result = exchange_with_members(node_id, &manifest).await;
+    // the OmniSIMO listener binds the same address next
+    server.abort();
+    let _ = server.await;
+    result?;
+    let manifest = manifest.lock().unwrap().clone();
+    Ok(manifest)
+}
+
+/// #Descriptions: ask the members for their manifest until a majority hold the same one
+/// as this node, uuid included. A uuid, once held, is never changed, so no two
+/// majorities hold different ones: a member that generated its uuid at the same time as
+/// another fails on meeting a member that holds the other, and takes it over once
+/// started again.
+async fn exchange_with_members(node_id: NodeId, manifest: &Arc<Mutex<ClusterManifest>>) -> Result<()> {
+    let members = manifest.lock().unwrap().members.clone();
+    let majority = members.len() / 2 + 1;
+    // the members that agreed on the manifest, with the uuid they held
+    let mut agreed: BTreeMap<NodeId, Option<String>> = BTreeMap::new();
+    agreed.insert(node_id, manifest.lock().unwrap().cluster_uuid.clone());
+    loop {
+        for (peer_id, peer_addr) in members.iter() {
+            // members that agreed are asked again for the uuid
+            if *peer_id == node_id || agreed.get(peer_id).map_or(false, |uuid| uuid.is_some()) {
+                continue;
+            }
+            let local = manifest.lock().unwrap().clone();
+            match request_manifest(peer_addr, &local).await {
+                Ok(peer_manifest) if local.agrees_with(&peer_manifest) => {
+                    if agreed.insert(*peer_id, peer_manifest.cluster_uuid.clone()).is_none() {
+                        info!("Node {} agreed on the cluster manifest", peer_id);
+                    }
+                    if local.cluster_uuid.is_none() && peer_manifest.cluster_uuid.is_some() {
+                        agreed.insert(node_id, peer_manifest.cluster_uuid.clone());
+                        manifest.lock().unwrap().cluster_uuid = peer_manifest.cluster_uuid;
+                    }
+                }
+                Ok(peer_manifest) => {
+                    return Err(format!(
+                        "node {} holds a different cluster manifest: {:?}",
+                        peer_id, peer_manifest
+                    )
+                    .into())
+                }
+                // not started yet
+                Err(_) => {}
+            }
+        }
+        let cluster_uuid = manifest.lock().unwrap().cluster_uuid.clone();
+        match cluster_uuid {
+            Some(cluster_uuid) => {
+                let holding = agreed
+                    .values()
+                    .filter(|uuid| uuid.as_ref() == Some(&cluster_uuid))
+                    .count();
+                if holding >= majority {
+                    return Ok(());
+                }
+            }
+            // the others wait for the lowest id agreeing
+            None if agreed.len() >= majority && agreed.keys().next() == Some(&node_id) => {
+                let cluster_uuid = Uuid::new_v4().to_string();
+                info!("Generated cluster uuid {}", cluster_uuid);
+                agreed.insert(node_id, Some(cluster_uuid.clone()));
+                manifest.lock().unwrap().cluster_uuid = Some(cluster_uuid);
+                continue;
+            }
+            None => {}
+        }
+        sleep(BOOTSTRAP_RETRY_INTERVAL).await;
+    }
+}
+
+async fn request_manifest(addr: &str, manifest: &ClusterManifest) -> Result<ClusterManifest> {
+    let mut connection = Connection::new(Connection::connect(addr).await?);
+    connection.write_frame(&manifest.to_frame()).await?;
+    match timeout(KEEPALIVE_TIMEOUT, connection.read_frame()).await {
+        Ok(Ok(Some(frame))) => Ok(*ClusterManifest::from_frame(&frame)?),
+        _ => Err(format!("no manifest from {}", addr).into()),
+    }
+}
+
+async fn answer_manifest(mut connection: Connection, manifest: &ClusterManifest) -> Result<()> {
+    if let Some(frame) = connection.read_frame().await? {
+        ClusterManifest::from_frame(&frame)?;
+        connection.write_frame(&manifest.to_frame()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_from_statefulset() {
+        let bootstrap =
+            Bootstrap::from_statefulset("ddbb-1", "ddbb.default.svc.cluster.local", 3, 6550).unwrap();
+        assert_eq!(bootstrap.node_id, 2);
+        assert_eq!(bootstrap.node_addr, "ddbb-1.ddbb.default.svc.cluster.local:6550");
+        let mut peer_ids: Vec<&NodeId> = bootstrap.peers.keys().collect();
+        peer_ids.sort();
+        assert_eq!(peer_ids, vec![&1, &3]);
+        assert_eq!(bootstrap.peers[&3], "ddbb-2.ddbb.default.svc.cluster.local:6550");
+
+        assert!(Bootstrap::from_statefulset("ddbb-3", "ddbb", 3, 6550).is_err());
+        assert!(Bootstrap::from_statefulset("ddbb", "ddbb", 3, 6550).is_err());
+    }
+
+    #[test]
+    fn test_manifest_frame() {
+        let mut peers = HashMap::new();
+        peers.insert(2, "127.0.0.1:6551".to_string());
+        peers.insert(3, "127.0.0.1:6552".to_string());
+        let manifest =
+            ClusterManifest::new("ddbb".to_string(), 1, 1, "127.0.0.1:6550".to_string(), &peers, &[3]);
+        assert_eq!(manifest.members.len(), 3);
+        let decoded = ClusterManifest::from_frame(&manifest.to_frame()).unwrap();
+        assert_eq!(*decoded, manifest);
+
+        let mut other = manifest.clone();
+        other.cluster_uuid = Some("a".to_string());
+        assert!(manifest.agrees_with(&other));
+        let mut another = manifest.clone();
+        another.cluster_uuid = Some("b".to_string());
+        assert!(!other.agrees_with(&another));
+        another.cluster_uuid = Some("a".to_string());
+        another.epoch = 2;
+        assert!(!other.agrees_with(&another));
+    }
+
+    #[test]
+    fn test_handshake_frame() {
+        let handshake = Handshake {
+            cluster_uuid: "a".to_string(),
+            build: Some(BuildInfo::current()),
+            node: Some(NodeIdentity::new(1)),
+            channel: Some("live".to_string()),
+        };
+        assert_eq!(
+            *Handshake::from_frame(&handshake.to_frame()).unwrap(),
+            handshake
+        );
+        // as sent by a node without a build
+        let old = Handshake {
+            cluster_uuid: "a".to_string(),
+            build: None,
+            node: None,
+            channel: None,
+        };
+        assert_eq!(*Handshake::from_frame(&old.to_frame()).unwrap(), old);
+    }
+
+    #[test]
+    fn test_check_node_ids() {
+        let addrs =
+            |addrs: &[&str]| -> Vec<String> { addrs.iter().map(|addr| addr.to_string()).collect() };
+        assert!(check_node_ids(1, "a:1", &[2, 3], &addrs(&["a:2", "a:3"])).is_ok());
+        // this node listed as its own peer
+        assert!(check_node_ids(1, "a:1", &[1, 3], &addrs(&["a:2", "a:3"])).is_err());
+        assert!(check_node_ids(1, "a:1", &[2, 2], &addrs(&["a:2", "a:3"])).is_err());
+        assert!(check_node_ids(1, "a:1", &[2, 3], &addrs(&["a:2", "a:2"])).is_err());
+        assert!(check_node_ids(0, "a:1", &[2, 3], &addrs(&["a:2", "a:3"])).is_err());
+        assert!(check_node_ids(1, "a:1", &[2, 3], &addrs(&["a:2"])).is_err());
+    }
+
+    #[test]
+    fn test_golden_frames() {
+        use crate::build_info::Capabilities;
+        use ddbb_libs::golden::{assert_decodes, assert_golden};
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden");
+        let build = BuildInfo {
+            version: "0.1.0".to_string(),
+            git_commit: "abc1234".to_string(),
+            libs_version: "0.1.0".to_string(),
+            protocol_version: 1,
+            features: vec![],
+            storage_backend: "Memory".to_string(),
+            capabilities: Capabilities(Capabilities::BATCHING),
+        };
+        let handshake = |build, node, channel: Option<&str>| Handshake {
+            cluster_uuid: "u".to_string(),
+            build,
+            node,
+            channel: channel.map(|channel| channel.to_string()),
+        };
+        let node = NodeIdentity {
+            node_id: 1,
+            instance_id: "i".to_string(),
+        };
+        let manifest = |cluster_uuid| ClusterManifest {
+            cluster_id: "ddbb".to_string(),
+            epoch: 1,
+            members: BTreeMap::from([
+                (1, "127.0.0.1:6550".to_string()),
+                (2, "127.0.0.1:6551".to_string()),
+            ]),
+            learners: vec![2],
+            cluster_uuid,
+        };
+        let current = handshake(Some(build.clone()), Some(node.clone()), Some("live"));
+        assert_golden(&dir, "handshake", &current);
+        assert_golden(&dir, "manifest", &manifest(Some("u".to_string())));
+
+        // as sent by older versions
+        assert_decodes(&dir, "handshake_v0", &handshake(None, None, None));
+        let build_v1 = BuildInfo {
+            capabilities: Capabilities::default(),
+            ..build.clone()
+        };
+        assert_decodes(&dir, "handshake_v1", &handshake(Some(build_v1), None, None));
+        assert_decodes(&dir, "handshake_v2", &handshake(Some(build.clone()), None, None));
+        assert_decodes(&dir, "handshake_v3", &handshake(Some(build), Some(node), None));
+        assert_decodes(&dir, "manifest_v0", &manifest(None));
+    }
+
+    /// #Descriptions: answer the manifest exchange at `addr` with `manifest` for good,
+    /// as the OmniSIMO listener of a started node does.
+    async fn serve_started(addr: &str, manifest: ClusterManifest) {
+        let listener = bind_listener(addr, &ListenerOptions::default()).await.unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let _ = answer_manifest(Connection::new(stream), &manifest).await;
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_agree_manifest_without_lowest_id() {
+        let mut peers = HashMap::new();
+        peers.insert(2, "127.0.0.1:5694".to_string());
+        peers.insert(3, "127.0.0.1:5695".to_string());
+        // node 1 is down
+        let manifest =
+            ClusterManifest::new("ddbb".to_string(), 1, 1, "127.0.0.1:5693".to_string(), &peers, &[]);
+        let start = |node_id: NodeId| {
+            let manifest = manifest.clone();
+            async move {
+                let agreed = agree_manifest(node_id, &manifest, &ListenerOptions::default())
+                    .await
+                    .unwrap();
+                serve_started(&agreed.members[&node_id], agreed.clone()).await;
+                agreed
+            }
+        };
+        let (second, third) = timeout(Duration::from_secs(10), async {
+            tokio::join!(start(2), start(3))
+        })
+        .await
+        .unwrap();
+        assert!(second.cluster_uuid.is_some());
+        assert_eq!(second.cluster_uuid, third.cluster_uuid);
+    }
+}
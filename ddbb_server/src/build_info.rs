@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::STORAGE_BACKEND;
+
+/// Version of the msgs between nodes, bumped whenever a node of the previous one can
+/// not read them anymore.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional msg variants a node reads, a bit each. A node sends a variant to a peer only
+/// once both advertised its bit, so a cluster is upgraded node by node while the nodes
+/// of the previous build keep getting the msgs they know.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities(pub u64);
+
+impl Capabilities {
+    /// several msgs in one `OmniMessageBatch` frame
+    pub const BATCHING: u64 = 1 << 0;
+    /// a small `AcceptDecide` and a heartbeat in one `OmniMessagePiggyback` frame
+    pub const PIGGYBACK: u64 = 1 << 1;
+    /// the channel of the connection in the handshake, see `Handshake::channel`
+    pub const SUPERSEDE: u64 = 1 << 2;
+    /// msgs in `OmniMessageSequenced` frames
+    pub const SEQUENCED: u64 = 1 << 3;
+
+    /// the variants this build reads
+    pub fn supported() -> Self {
+        Capabilities(Self::BATCHING | Self::PIGGYBACK | Self::SUPERSEDE | Self::SEQUENCED)
+    }
+
+    pub fn has(&self, capability: u64) -> bool {
+        self.0 & capability == capability
+    }
+
+    /// the variants both ends read
+    pub fn common(&self, other: &Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+/// What a ddbb_server binary is built from and with, reported by `status` and sent in
+/// the OmniSIMO handshake, so the nodes of a mixed-version cluster can be told apart
+/// during a rolling upgrade.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    /// "unknown" when not built from a git checkout
+    pub git_commit: String,
+    pub libs_version: String,
+    pub protocol_version: u32,
+    /// the cargo features enabled, e.g. `rocksdb`
+    pub features: Vec<String>,
+    pub storage_backend: String,
+    /// none from nodes built before capabilities were negotiated
+    #[serde(default)]
+    pub capabilities: Capabilities,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let mut features = Vec::new();
+        if cfg!(feature = "rocksdb") {
+            features.push("rocksdb".to_string());
+        }
+        if cfg!(feature = "console") {
+            features.push("console".to_string());
+        }
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: env!("DDBB_GIT_COMMIT").to_string(),
+            libs_version: ddbb_libs::VERSION.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            features,
+            storage_backend: format!("{:?}", STORAGE_BACKEND),
+            capabilities: Capabilities::supported(),
+        }
+    }
+
+    /// e.g. `0.1.0 (3f2a9c1)`, nodes with the same one run the same code
+    pub fn version_string(&self) -> String {
+        format!("{} ({})", self.version, self.git_commit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let (old, new) = (Capabilities::default(), Capabilities::supported());
+        assert!(!old.common(&new).has(Capabilities::BATCHING));
+        assert!(new.common(&new).has(Capabilities::BATCHING));
+        let batching = Capabilities(Capabilities::BATCHING);
+        assert!(!batching.common(&new).has(Capabilities::PIGGYBACK));
+
+        // the build of a node without capabilities
+        let mut json = serde_json::to_value(BuildInfo::current()).unwrap();
+        json.as_object_mut().unwrap().remove("capabilities");
+        let build: BuildInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(build.capabilities, old);
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Serialize;
+use std::time::Instant;
+
+use omnipaxos_core::messages::{sequence_paxos::PaxosMsg, Message};
+
+use crate::config::CAUGHT_UP_LAG;
+use crate::omni_paxos_server::OmniMessage;
+
+/// Progress of a node syncing the decided log from the leader, e.g. after a restart,
+/// served as json by the admin API.
+#[derive(Clone, Debug, Serialize)]
+pub struct CatchUpProgress {
+    pub decided_idx: u64,
+    /// the highest decided index heard from the leader
+    pub leader_decided_idx: u64,
+    pub entries_behind: u64,
+    /// received in `AcceptSync` and `AcceptDecide` since the node fell behind
+    pub bytes_received: u64,
+    /// estimated from the catch up rate so far, unknown until some entries are decided
+    pub eta_ms: Option<u64>,
+    pub caught_up: bool,
+}
+
+/// Tracks how far the decided log of this node is behind the leader.
+#[derive(Debug)]
+pub struct CatchUp {
+    decided_idx: u64,
+    leader_decided_idx: u64,
+    bytes_received: u64,
+    /// when this node fell behind, and its decided index then
+    behind_since: Option<(Instant, u64)>,
+}
+
+impl CatchUp {
+    pub fn new() -> Self {
+        Self {
+            decided_idx: 0,
+            leader_decided_idx: 0,
+            bytes_received: 0,
+            behind_since: None,
+        }
+    }
+
+    /// #Descriptions: learn the decided index of the leader from an incoming message.
+    pub fn observe(&mut self, msg: &OmniMessage) {
+        let msg = match msg {
+            Message::SequencePaxos(paxos_msg) => &paxos_msg.msg,
+            Message::BLE(_) => return,
+        };
+        let leader_decided_idx = match msg {
+            PaxosMsg::AcceptSync(acc_sync) => {
+                self.count_bytes(&acc_sync.suffix);
+                acc_sync.decided_idx
+            }
+            PaxosMsg::AcceptDecide(acc) => {
+                self.count_bytes(&acc.entries);
+                acc.decided_idx
+            }
+            PaxosMsg::Decide(d) => d.decided_idx,
+            _ => return,
+        };
+        if leader_decided_idx > self.leader_decided_idx {
+            self.leader_decided_idx = leader_decided_idx;
+            self.update_behind();
+        }
+    }
+
+    pub fn set_decided(&mut self, decided_idx: u64) {
+        self.decided_idx = decided_idx;
+        self.update_behind();
+    }
+
+    fn count_bytes<T: Serialize>(&mut self, entries: &T) {
+        // only paid while catching up
+        if self.behind_since.is_some() {
+            if let Ok(bytes) = serde_json::to_vec(entries) {
+                self.bytes_received += bytes.len() as u64;
+            }
+        }
+    }
+
+    fn update_behind(&mut self) {
+        if self.is_caught_up() {
+            self.behind_since = None;
+        } else if self.behind_since.is_none() {
+            self.behind_since = Some((Instant::now(), self.decided_idx));
+            self.bytes_received = 0;
+        }
+    }
+
+    pub fn entries_behind(&self) -> u64 {
+        self.leader_decided_idx.saturating_sub(self.decided_idx)
+    }
+
+    pub fn is_caught_up(&self) -> bool {
+        self.entries_behind() <= CAUGHT_UP_LAG
+    }
+
+    pub fn progress(&self) -> CatchUpProgress {
+        self.progress_at(Instant::now())
+    }
+
+    pub fn progress_at(&self, now: Instant) -> CatchUpProgress {
+        let eta_ms = match self.behind_since {
+            Some((since, decided_idx)) if self.decided_idx > decided_idx => {
+                let elapsed_ms = now.saturating_duration_since(since).as_millis() as u64;
+                let caught_up = self.decided_idx - decided_idx;
+                Some(self.entries_behind() * elapsed_ms / caught_up)
+            }
+            Some(_) => None,
+            None => Some(0),
+        };
+        CatchUpProgress {
+            decided_idx: self.decided_idx,
+            leader_decided_idx: self.leader_decided_idx,
+            entries_behind: self.entries_behind(),
+            bytes_received: self.bytes_received,
+            eta_ms,
+            caught_up: self.is_caught_up(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use omnipaxos_core::ballot_leader_election::Ballot;
+    use omnipaxos_core::messages::sequence_paxos::{Decide, PaxosMessage};
+    use std::time::Duration;
+
+    fn decide(decided_idx: u64) -> OmniMessage {
+        Message::SequencePaxos(PaxosMessage {
+            from: 1,
+            to: 2,
+            msg: PaxosMsg::Decide(Decide {
+                n: Ballot::default(),
+                decided_idx,
+            }),
+        })
+    }
+
+    #[test]
+    fn test_catch_up_progress() {
+        let mut catch_up = CatchUp::new();
+        assert!(catch_up.is_caught_up());
+
+        catch_up.observe(&decide(100));
+        let start = catch_up.behind_since.unwrap().0;
+        assert!(!catch_up.is_caught_up());
+        assert_eq!(catch_up.progress_at(start).eta_ms, None);
+
+        // 20 entries in 100ms, 80 left
+        catch_up.set_decided(20);
+        let progress = catch_up.progress_at(start + Duration::from_millis(100));
+        assert_eq!(progress.entries_behind, 80);
+        assert_eq!(progress.eta_ms, Some(400));
+
+        catch_up.set_decided(100);
+        assert!(catch_up.progress().caught_up);
+    }
+}
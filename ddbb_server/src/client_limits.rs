@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ddbb_libs::{Error, Result};
+
+use crate::config::{
+    CLIENT_IDLE_TIMEOUT, CLIENT_MAX_CONNECTIONS, CLIENT_MAX_CONNECTIONS_PER_IP,
+};
+
+/// Limits on the connections of the client listener, 0 for no limit.
+#[derive(Clone, Debug)]
+pub struct ClientLimits {
+    pub max_connections: usize,
+    /// connections open from one client address
+    pub max_connections_per_ip: usize,
+    /// a connection sending no command for this long is closed, never if `None`;
+    /// watches and campaigns stream to the client and are never idle
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for ClientLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: CLIENT_MAX_CONNECTIONS,
+            max_connections_per_ip: CLIENT_MAX_CONNECTIONS_PER_IP,
+            idle_timeout: CLIENT_IDLE_TIMEOUT,
+        }
+    }
+}
+
+/// The client connections, served with the metrics.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientConnectionStats {
+    pub open: u64,
+    /// connections refused because `max_connections` were open
+    pub rejected: u64,
+    /// connections refused because `max_connections_per_ip` were open from their address
+    pub rejected_per_ip: u64,
+    /// connections closed after `idle_timeout`
+    pub evicted_idle: u64,
+}
+
+#[derive(Debug, Default)]
+struct Tracked {
+    per_ip: HashMap<IpAddr, usize>,
+    stats: ClientConnectionStats,
+}
+
+/// Counts the open client connections against the `ClientLimits`, shared by the
+/// client listener and the connections it serves.
+#[derive(Clone, Debug)]
+pub struct ClientConnections {
+    limits: ClientLimits,
+    tracked: Arc<Mutex<Tracked>>,
+}
+
+impl ClientConnections {
+    pub fn new(limits: ClientLimits) -> Self {
+        Self {
+            limits,
+            tracked: Arc::new(Mutex::new(Tracked::default())),
+        }
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.limits.idle_timeout
+    }
+
+    /// #Descriptions: count a connection from `ip`, until the returned permit is dropped.
+    /// Fails with `Overloaded` if it would go over a limit.
+    pub fn open(&self, ip: IpAddr) -> Result<ConnectionPermit> {
+        let mut tracked = self.tracked.lock().unwrap();
+        let max = self.limits.max_connections;
+        if max != 0 && tracked.stats.open >= max as u64 {
+            tracked.stats.rejected += 1;
+            return Err(Error::Overloaded(format!(
+                "{} client connections open, retry later",
+                max
+            )));
+        }
+        let from_ip = tracked.per_ip.get(&ip).copied().unwrap_or(0);
+        let max_per_ip = self.limits.max_connections_per_ip;
+        if max_per_ip != 0 && from_ip >= max_per_ip {
+            tracked.stats.rejected_per_ip += 1;
+            return Err(Error::Overloaded(format!(
+                "{} client connections open from {}, retry later",
+                max_per_ip, ip
+            )));
+        }
+        tracked.per_ip.insert(ip, from_ip + 1);
+        tracked.stats.open += 1;
+        Ok(ConnectionPermit {
+            ip,
+            tracked: self.tracked.clone(),
+        })
+    }
+
+    pub fn record_evicted(&self) {
+        self.tracked.lock().unwrap().stats.evicted_idle += 1;
+    }
+
+    pub fn stats(&self) -> ClientConnectionStats {
+        self.tracked.lock().unwrap().stats.clone()
+    }
+}
+
+/// An open client connection, counted until dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    ip: IpAddr,
+    tracked: Arc<Mutex<Tracked>>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut tracked = self.tracked.lock().unwrap();
+        tracked.stats.open = tracked.stats.open.saturating_sub(1);
+        if let Some(from_ip) = tracked.per_ip.get_mut(&self.ip) {
+            *from_ip -= 1;
+            if *from_ip == 0 {
+                tracked.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_connection_limits() {
+        let connections = ClientConnections::new(ClientLimits {
+            max_connections: 3,
+            max_connections_per_ip: 2,
+            idle_timeout: None,
+        });
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let a1 = connections.open(a).unwrap();
+        let a2 = connections.open(a).unwrap();
+        assert!(matches!(connections.open(a), Err(Error::Overloaded(_))));
+        let b1 = connections.open(b).unwrap();
+        assert!(matches!(connections.open(b), Err(Error::Overloaded(_))));
+        assert_eq!(
+            connections.stats(),
+            ClientConnectionStats {
+                open: 3,
+                rejected: 1,
+                rejected_per_ip: 1,
+                evicted_idle: 0,
+            }
+        );
+
+        // a closed connection frees its slot
+        drop(a1);
+        let a3 = connections.open(a).unwrap();
+        drop((a2, a3, b1));
+        assert_eq!(connections.stats().open, 0);
+        assert!(connections.tracked.lock().unwrap().per_ip.is_empty());
+    }
+}
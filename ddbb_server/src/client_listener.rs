@@ -0,0 +1,1340 @@
+use bytes::Bytes;
+use log::{debug, error, info};
+use serde::Serialize;
+use std::future::Future;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+
+use std::sync::{Arc, Mutex};
+
+use ddbb_libs::clock::SharedClock;
+use ddbb_libs::connection::Connection;
+use ddbb_libs::data_structure::{
+    AdminEntry, AuthEntry, CommandEntry, DataEntry, FrameCast, KeepAliveEntry, KeyMeta,
+    MessageEntry, NotLeaderEntry, WatchBatchEntry, WatchDelivery,
+};
+use ddbb_libs::frame::Frame;
+use ddbb_libs::protocol::{Request, Response, ResponseBody};
+use ddbb_libs::{Error, Result};
+
+use crate::client_limits::{ClientConnections, ClientLimits};
+use crate::config::{
+    BULK_LOAD_MAX_KEYS, CLIENT_AUTH_TIMEOUT, CLIENT_MAX_FRAME_SIZE, CLUSTER_STATUS_TIMEOUT,
+    DELETE_PREFIX_MAX_KEYS, PREFIX_WRITE_LIMITS, REFUSE_READS_WHILE_CATCHING_UP, WATCH_BATCH_MAX_EVENTS,
+};
+use crate::ddbb_server::DDBB;
+use crate::dynamic_config::{ReadMode, CONFIG_KEY_PREFIX};
+use crate::metrics::{ClusterStatus, NodeStatus};
+use crate::namespace::{scoped_key, NAMESPACE_KEY_PREFIX};
+use crate::net::{bind_listener, set_socket_options, ListenerOptions};
+use crate::rate_limiter::{PrefixLimiter, TokenBucket};
+use crate::state_machine::{child_names, tree_prefix};
+use crate::tasks::spawn_named;
+use crate::watch::{self, WatchBatch};
+
+/// #Descriptions: accept ddbb_client connections on `addr`, every command is
+/// proposed through omnipaxos and answered once it is decided. With an
+/// `auth_token`, a connection is served only once it sent that token. Connections
+/// over the `limits` are refused, and idle ones closed.
+pub async fn start_client_listener(
+    ddbb: Arc<Mutex<DDBB>>,
+    addr: String,
+    options: ListenerOptions,
+    auth_token: Option<String>,
+    limits: ClientLimits,
+) -> Result<()> {
+    let listener = bind_listener(&addr, &options).await?;
+    info!("Client listener started at: {:?}", addr);
+    let prefix_limiter = Arc::new(Mutex::new(PrefixLimiter::new(PREFIX_WRITE_LIMITS)));
+    let connections = ClientConnections::new(limits);
+    ddbb.lock().unwrap().track_client_connections(connections.clone());
+    spawn_named("client listener", async move {
+        loop {
+            match listener.accept().await {
+                Ok((tcp_stream, client_addr)) => {
+                    debug!("New client connection: {:?}", client_addr);
+                    set_socket_options(&tcp_stream, &options);
+                    let ddbb = ddbb.clone();
+                    let prefix_limiter = prefix_limiter.clone();
+                    let auth_token = auth_token.clone();
+                    let connections = connections.clone();
+                    let name = format!("client connection from {}", client_addr);
+                    spawn_named(&name, async move {
+                        let mut connection = Connection::new(tcp_stream);
+                        connection.set_max_frame_size(CLIENT_MAX_FRAME_SIZE);
+                        // held until the connection is closed
+                        let _permit = match connections.open(client_addr.ip()) {
+                            Ok(permit) => permit,
+                            Err(e) => {
+                                info!("Client {:?} refused: {}", client_addr, e);
+                                let reply = MessageEntry::Error {
+                                    err_msg: e.to_string(),
+                                };
+                                let _ = connection.write_frame(&reply.to_frame()).await;
+                                return;
+                            }
+                        };
+                        if let Some(token) = &auth_token {
+                            if let Err(e) = authenticate(&mut connection, token).await {
+                                info!("Client {:?} not authenticated: {}", client_addr, e);
+                                let reply = MessageEntry::Error {
+                                    err_msg: e.to_string(),
+                                };
+                                let _ = connection.write_frame(&reply.to_frame()).await;
+                                return;
+                            }
+                        }
+                        let served = process_client(
+                            ddbb,
+                            connection,
+                            prefix_limiter,
+                            auth_token,
+                            connections,
+                        );
+                        if let Err(e) = served.await {
+                            error!("Client connection {:?} failed: {:?}", client_addr, e);
+                        }
+                    });
+                }
+                Err(e) => error!("Accept client connection failed: {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn process_client(
+    ddbb: Arc<Mutex<DDBB>>,
+    mut connection: Connection,
+    prefix_limiter: Arc<Mutex<PrefixLimiter>>,
+    auth_token: Option<String>,
+    connections: ClientConnections,
+) -> Result<()> {
+    let config = ddbb.lock().unwrap().dynamic_config();
+    let mut write_bucket = TokenBucket::new(config.client_write_rate, config.client_write_burst);
+    loop {
+        let read = match connections.idle_timeout() {
+            Some(idle_timeout) => match timeout(idle_timeout, connection.read_frame()).await {
+                Ok(read) => read,
+                Err(_) => {
+                    // e.g. leaked by the client application, free its slot
+                    debug!("Client connection idle for {:?}, closed", idle_timeout);
+                    connections.record_evicted();
+                    break;
+                }
+            },
+            None => connection.read_frame().await,
+        };
+        let frame = match read {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                debug!("Client connection closed: {:?}", e);
+                break;
+            }
+        };
+        // a request of the client protocol runs as the command it stands for, and its
+        // reply is sent back as a response to it
+        let (request_id, frame) = if Request::is_request(&frame) {
+            match Request::from_frame(&frame) {
+                Ok(request) => (Some(request.id), request.into_command().to_frame()),
+                Err(e) => {
+                    let reply = Response {
+                        id: 0,
+                        body: ResponseBody::Error {
+                            message: e.to_string(),
+                        },
+                    };
+                    connection.write_frame(&reply.to_frame()).await?;
+                    continue;
+                }
+            }
+        } else {
+            (None, frame)
+        };
+        let respond = |reply: Frame| match request_id {
+            Some(id) => Response::from_reply(id, &reply).to_frame(),
+            None => reply,
+        };
+        // the limits may have been changed through the log meanwhile
+        let config = ddbb.lock().unwrap().dynamic_config();
+        write_bucket.set_limits(config.client_write_rate, config.client_write_burst);
+        // a command that can not be scoped to its namespace is answered with the error
+        let cmd = CommandEntry::from_frame(&frame)
+            .and_then(|cmd| scope_command(&ddbb.lock().unwrap(), *cmd).map(Box::new));
+        if let Ok(CommandEntry::Watch {
+            prefix,
+            after_revision,
+            delivery,
+        }) = cmd.as_deref()
+        {
+            // the connection only streams events from now on
+            let prefix = prefix.clone();
+            return serve_watch(ddbb, connection, prefix, *after_revision, *delivery).await;
+        }
+        if let Ok(CommandEntry::Elect {
+            election,
+            candidate,
+            ttl_ms,
+        }) = cmd.as_deref()
+        {
+            // the campaign runs as long as the connection
+            let ttl = Duration::from_millis(*ttl_ms);
+            return serve_election(ddbb, connection, election.clone(), candidate.clone(), ttl)
+                .await;
+        }
+        if let Ok(CommandEntry::KeepAliveStream { session }) = cmd.as_deref() {
+            // the session is kept alive as long as the client sends keepalives on it
+            return serve_keep_alive(ddbb, connection, *session).await;
+        }
+        if let Some(not_leader) = cmd.as_deref().ok().and_then(|cmd| redirect(&ddbb, cmd)) {
+            connection.write_frame(&respond(not_leader.to_frame())).await?;
+            continue;
+        }
+        let reply = match cmd {
+            Ok(cmd) if !may_write(&cmd, &mut write_bucket, &prefix_limiter) => MessageEntry::Error {
+                err_msg: Error::Overloaded("write rate limit exceeded, retry later".to_string())
+                    .to_string(),
+            }
+            .to_frame(),
+            Ok(cmd) => match *cmd {
+                CommandEntry::Deadline { timeout_ms, cmd } => {
+                    // dropping the command once the deadline passed unregisters its proposal
+                    let deadline = Duration::from_millis(timeout_ms);
+                    match timeout(deadline, handle_command(ddbb.clone(), *cmd)).await {
+                        Ok(reply) => reply,
+                        Err(_) => MessageEntry::Error {
+                            err_msg: Error::Timeout(
+                                "deadline exceeded, the command may still be applied".to_string(),
+                            )
+                            .to_string(),
+                        }
+                        .to_frame(),
+                    }
+                }
+                cmd => handle_command(ddbb.clone(), cmd).await,
+            },
+            Err(e) => match AdminEntry::from_frame(&frame) {
+                Ok(admin) => handle_admin(ddbb.clone(), *admin, &auth_token).await,
+                Err(_) => MessageEntry::Error {
+                    err_msg: e.to_string(),
+                }
+                .to_frame(),
+            },
+        };
+        connection.write_frame(&respond(reply)).await?;
+    }
+    Ok(())
+}
+
+/// #Descriptions: answer a `Watch` with the current revision, then stream the retained
+/// writes after `after_revision` and every write from then on, as `delivery` tells,
+/// until the client goes.
+async fn serve_watch(
+    ddbb: Arc<Mutex<DDBB>>,
+    mut connection: Connection,
+    prefix: String,
+    after_revision: u64,
+    delivery: WatchDelivery,
+) -> Result<()> {
+    let watch = ddbb.lock().unwrap().watch(&prefix, after_revision, delivery);
+    let mut watch = match watch {
+        Ok(watch) => watch,
+        Err(e) => {
+            let reply = MessageEntry::Error {
+                err_msg: e.to_string(),
+            };
+            connection.write_frame(&reply.to_frame()).await?;
+            return Ok(());
+        }
+    };
+    let reply = MessageEntry::Success {
+        msg: format!("watching from revision {}", watch.revision),
+    };
+    connection.write_frame(&reply.to_frame()).await?;
+    let replay = WatchBatch {
+        events: std::mem::take(&mut watch.replay),
+        coalesced: false,
+    };
+    if !replay.events.is_empty() {
+        write_watch_batch(&mut connection, replay, delivery).await?;
+    }
+    loop {
+        let batch = match watch.next_batch().await {
+            Ok(batch) => batch,
+            // the client fell behind, it resumes from the revision told
+            Err(e) => {
+                let reply = MessageEntry::Error {
+                    err_msg: e.to_string(),
+                };
+                connection.write_frame(&reply.to_frame()).await?;
+                break;
+            }
+        };
+        if let Err(e) = write_watch_batch(&mut connection, batch, delivery).await {
+            debug!("Watch on {:?} closed: {:?}", prefix, e);
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// #Descriptions: stream the writes of a batch as `delivery` tells, in
+/// `WatchBatchEntry`s of at most `WATCH_BATCH_MAX_EVENTS` writes.
+async fn write_watch_batch(
+    connection: &mut Connection,
+    batch: WatchBatch,
+    delivery: WatchDelivery,
+) -> Result<()> {
+    let (events, coalesced) = match delivery {
+        // never coalesced by the hub
+        WatchDelivery::Each => {
+            for event in batch.events {
+                connection.write_frame(&event.to_frame()).await?;
+            }
+            return Ok(());
+        }
+        WatchDelivery::Batched => (batch.events, batch.coalesced),
+        WatchDelivery::Latest => {
+            let (events, coalesced) = watch::coalesce(batch.events);
+            (events, batch.coalesced || coalesced)
+        }
+    };
+    for chunk in events.chunks(WATCH_BATCH_MAX_EVENTS) {
+        let batch = WatchBatchEntry {
+            revision: chunk.last().map_or(0, |event| event.revision),
+            coalesced,
+            events: chunk.to_vec(),
+        };
+        connection.write_frame(&batch.to_frame()).await?;
+    }
+    Ok(())
+}
+
+/// #Descriptions: campaign in `election` as `candidate`, streaming each change of its
+/// leader to the client, and resign once the client goes.
+async fn serve_election(
+    ddbb: Arc<Mutex<DDBB>>,
+    mut connection: Connection,
+    election: String,
+    candidate: String,
+    ttl: Duration,
+) -> Result<()> {
+    let events = if candidate.is_empty() {
+        Err("empty candidate".into())
+    } else {
+        DDBB::elect(ddbb, election.clone(), candidate, ttl)
+    };
+    let mut events = match events {
+        Ok(events) => events,
+        Err(e) => {
+            let reply = MessageEntry::Error {
+                err_msg: e.to_string(),
+            };
+            connection.write_frame(&reply.to_frame()).await?;
+            return Ok(());
+        }
+    };
+    let reply = MessageEntry::Success {
+        msg: format!("campaigning in {}", election),
+    };
+    connection.write_frame(&reply.to_frame()).await?;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => connection.write_frame(&event.to_frame()).await?,
+                None => break,
+            },
+            // the client sends nothing more, so any frame read ends the campaign too
+            frame = connection.read_frame() => {
+                debug!("Campaign in {:?} closed: {:?}", election, frame.map(|_| ()));
+                break;
+            }
+        }
+    }
+    // dropping `events` resigns
+    Ok(())
+}
+
+/// #Descriptions: keep `session` alive for as long as the client sends a `KeepAlive` on
+/// the stream within every ttl, see `keep_alive_frames`.
+async fn serve_keep_alive(
+    ddbb: Arc<Mutex<DDBB>>,
+    mut connection: Connection,
+    session: u64,
+) -> Result<()> {
+    let (ttl_ms, clock) = {
+        let ddbb = ddbb.lock().unwrap();
+        (ddbb.session_ttl(session), ddbb.clock())
+    };
+    let ttl_ms = match ttl_ms {
+        Some(ttl_ms) => ttl_ms,
+        None => {
+            let reply = MessageEntry::Error {
+                err_msg: format!("session not found: {}", session),
+            };
+            connection.write_frame(&reply.to_frame()).await?;
+            return Ok(());
+        }
+    };
+    let reply = MessageEntry::Success {
+        msg: format!("keeping session {} alive", session),
+    };
+    connection.write_frame(&reply.to_frame()).await?;
+    let refresh = || DDBB::keep_alive(ddbb.clone(), session);
+    keep_alive_frames(&mut connection, session, ttl_ms, clock, refresh).await
+}
+
+/// #Descriptions: `refresh` the session once per `KeepAlive` of `session` read from the
+/// client, which refreshes its ephemeral keys and permits with it, answered with a
+/// `KeepAliveEntry`. The stream ends on any other frame, on a refresh failing, e.g. as
+/// the session expired, and once the client sent nothing for `ttl_ms` of `clock`: a
+/// client hung or cut off behind a half-open connection does not keep its session.
+async fn keep_alive_frames<F, R>(
+    connection: &mut Connection,
+    session: u64,
+    ttl_ms: u64,
+    clock: SharedClock,
+    mut refresh: F,
+) -> Result<()>
+where
+    F: FnMut() -> R,
+    R: Future<Output = Result<()>>,
+{
+    let ttl = Duration::from_millis(ttl_ms);
+    loop {
+        let frame = tokio::select! {
+            frame = connection.read_frame() => frame?,
+            _ = clock.sleep(ttl) => {
+                debug!("Keepalive of session {} silent for {:?}, closed", session, ttl);
+                return Ok(());
+            }
+        };
+        let cmd = frame.and_then(|frame| CommandEntry::from_frame(&frame).ok());
+        match cmd.as_deref() {
+            Some(CommandEntry::KeepAlive { session: id }) if *id == session => {}
+            other => {
+                debug!("Keepalive of session {} closed by {:?}", session, other);
+                return Ok(());
+            }
+        }
+        if let Err(e) = refresh().await {
+            let reply = MessageEntry::Error {
+                err_msg: e.to_string(),
+            };
+            connection.write_frame(&reply.to_frame()).await?;
+            return Ok(());
+        }
+        connection
+            .write_frame(&KeepAliveEntry { session, ttl_ms }.to_frame())
+            .await?;
+    }
+}
+
+/// #Descriptions: wait for the `AuthEntry` that has to be the first frame of the
+/// connection and check it carries `token`.
+async fn authenticate(connection: &mut Connection, token: &str) -> Result<()> {
+    let frame = match timeout(CLIENT_AUTH_TIMEOUT, connection.read_frame()).await {
+        Ok(frame) => frame?.ok_or(Error::ConnectionClosed)?,
+        Err(_) => return Err(Error::Unauthorized("no token sent".to_string())),
+    };
+    let auth = AuthEntry::from_frame(&frame)
+        .map_err(|_| Error::Unauthorized("token required".to_string()))?;
+    if !tokens_match(auth.token.as_bytes(), token.as_bytes()) {
+        return Err(Error::Unauthorized("bad token".to_string()));
+    }
+    let reply = MessageEntry::Success {
+        msg: "authenticated".to_string(),
+    };
+    connection.write_frame(&reply.to_frame()).await?;
+    Ok(())
+}
+
+/// Compares in time independent of where the tokens differ.
+fn tokens_match(sent: &[u8], expected: &[u8]) -> bool {
+    sent.len() == expected.len()
+        && sent
+            .iter()
+            .zip(expected)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Takes a token per key written, other commands are not limited.
+fn may_write(
+    cmd: &CommandEntry,
+    write_bucket: &mut TokenBucket,
+    prefix_limiter: &Mutex<PrefixLimiter>,
+) -> bool {
+    let keys = write_keys(cmd);
+    if keys.is_empty() {
+        return true;
+    }
+    write_bucket.try_take_n(keys.len() as f64) && prefix_limiter.lock().unwrap().try_take_keys(&keys)
+}
+
+/// #Descriptions: the `NotLeaderEntry` answering `cmd` on a follower that does not
+/// forward the commands served by the leader.
+fn redirect(ddbb: &Arc<Mutex<DDBB>>, cmd: &CommandEntry) -> Option<NotLeaderEntry> {
+    let ddbb = ddbb.lock().unwrap();
+    let local_reads = ddbb.dynamic_config().read_mode == ReadMode::Local;
+    if needs_leader(cmd, local_reads) {
+        ddbb.not_leader()
+    } else {
+        None
+    }
+}
+
+/// Whether `cmd` is proposed through the leader, reads are not with `local_reads`.
+fn needs_leader(cmd: &CommandEntry, local_reads: bool) -> bool {
+    match cmd {
+        CommandEntry::SetValue { .. }
+        | CommandEntry::PutIfRevision { .. }
+        | CommandEntry::FencedSet { .. }
+        | CommandEntry::DeleteTree { .. }
+        | CommandEntry::DeletePrefix { .. }
+        | CommandEntry::BulkLoad { .. } => true,
+        CommandEntry::OpenSession { .. }
+        | CommandEntry::KeepAlive { .. }
+        | CommandEntry::EphemeralSet { .. }
+        | CommandEntry::CloseSession { .. } => true,
+        // the revision of the latest write, known by the leader
+        CommandEntry::Revision => true,
+        CommandEntry::GetValue { .. } | CommandEntry::Stat { .. } => !local_reads,
+        CommandEntry::Deadline { cmd, .. } => needs_leader(cmd, local_reads),
+        _ => false,
+    }
+}
+
+fn write_key(cmd: &CommandEntry) -> Option<&str> {
+    match cmd {
+        CommandEntry::SetValue { key, .. }
+        | CommandEntry::PutIfRevision { key, .. }
+        | CommandEntry::FencedSet { key, .. }
+        | CommandEntry::EphemeralSet { key, .. } => Some(key),
+        CommandEntry::DeleteTree { path } => Some(path),
+        CommandEntry::DeletePrefix { prefix, .. } => Some(prefix),
+        CommandEntry::Deadline { cmd, .. } | CommandEntry::Namespaced { cmd, .. } => write_key(cmd),
+        _ => None,
+    }
+}
+
+/// The keys `cmd` writes, each pair of a bulk load counting as a write.
+fn write_keys(cmd: &CommandEntry) -> Vec<&str> {
+    match cmd {
+        CommandEntry::BulkLoad { pairs } => pairs.iter().map(|(key, _)| key.as_str()).collect(),
+        CommandEntry::Deadline { cmd, .. } | CommandEntry::Namespaced { cmd, .. } => write_keys(cmd),
+        cmd => write_key(cmd).into_iter().collect(),
+    }
+}
+
+fn command_key(cmd: &CommandEntry) -> Option<&str> {
+    match cmd {
+        CommandEntry::GetValue { key }
+        | CommandEntry::StaleGet { key }
+        | CommandEntry::Stat { key } => Some(key),
+        CommandEntry::Watch { prefix, .. } | CommandEntry::Scan { prefix } => Some(prefix),
+        CommandEntry::ListChildren { path } => Some(path),
+        CommandEntry::AtRevision { cmd, .. } => command_key(cmd),
+        cmd => write_key(cmd),
+    }
+}
+
+/// #Descriptions: scope the keys of a `Namespaced` command to its namespace, once its
+/// token and quota are checked. Keys sent without a namespace may not reach into one.
+fn scope_command(ddbb: &DDBB, cmd: CommandEntry) -> Result<CommandEntry> {
+    match cmd {
+        CommandEntry::Deadline { timeout_ms, cmd } => Ok(CommandEntry::Deadline {
+            timeout_ms,
+            cmd: Box::new(scope_command(ddbb, *cmd)?),
+        }),
+        CommandEntry::Namespaced {
+            namespace,
+            token,
+            cmd,
+        } => {
+            let ns = ddbb
+                .namespace(&namespace)
+                .ok_or_else(|| Error::Other(format!("namespace not found: {}", namespace)))?;
+            if let Some(expected) = &ns.token {
+                if !tokens_match(token.as_bytes(), expected.as_bytes()) {
+                    return Err(Error::Unauthorized(format!("bad token for {}", namespace)));
+                }
+            }
+            let cmd = scope_keys(&namespace, *cmd)?;
+            // a deletion never takes quota
+            if let Some(key) = write_key(&cmd).filter(|_| !is_delete(&cmd)) {
+                // applying refuses the write anyway, this saves proposing it
+                if !ns.has_room() && ddbb.get(key.to_string()).is_none() {
+                    return Err(Error::QuotaExceeded(format!(
+                        "namespace {} holds {} keys",
+                        namespace, ns.max_keys
+                    )));
+                }
+            }
+            Ok(cmd)
+        }
+        CommandEntry::BulkLoad { pairs } => {
+            for (key, _) in pairs.iter() {
+                check_unscoped(key)?;
+            }
+            Ok(CommandEntry::BulkLoad { pairs })
+        }
+        // an empty prefix is fine, the reserved keys are only reached from a reserved one
+        CommandEntry::DeletePrefix { prefix, limit } => {
+            if prefix.starts_with('\u{0}') {
+                return Err(Error::Unauthorized("reserved prefix".to_string()));
+            }
+            Ok(CommandEntry::DeletePrefix { prefix, limit })
+        }
+        cmd => {
+            if let Some(key) = command_key(&cmd) {
+                check_unscoped(key)?;
+            }
+            Ok(cmd)
+        }
+    }
+}
+
+fn is_delete(cmd: &CommandEntry) -> bool {
+    match cmd {
+        CommandEntry::DeleteTree { .. } | CommandEntry::DeletePrefix { .. } => true,
+        CommandEntry::Deadline { cmd, .. } => is_delete(cmd),
+        _ => false,
+    }
+}
+
+/// Keys sent without a namespace may neither be in one nor be reserved.
+fn check_unscoped(key: &str) -> Result<()> {
+    if key.starts_with(NAMESPACE_KEY_PREFIX) {
+        return Err(Error::Unauthorized("key in a namespace".to_string()));
+    }
+    // settings are only written by `AdminEntry::SetConfig`
+    if key.starts_with(CONFIG_KEY_PREFIX) {
+        return Err(Error::Unauthorized("reserved key".to_string()));
+    }
+    Ok(())
+}
+
+fn scope_keys(namespace: &str, cmd: CommandEntry) -> Result<CommandEntry> {
+    let cmd = match cmd {
+        CommandEntry::SetValue { key, value } => CommandEntry::SetValue {
+            key: scoped_key(namespace, &key),
+            value,
+        },
+        CommandEntry::GetValue { key } => CommandEntry::GetValue {
+            key: scoped_key(namespace, &key),
+        },
+        CommandEntry::StaleGet { key } => CommandEntry::StaleGet {
+            key: scoped_key(namespace, &key),
+        },
+        CommandEntry::Stat { key } => CommandEntry::Stat {
+            key: scoped_key(namespace, &key),
+        },
+        CommandEntry::PutIfRevision {
+            key,
+            value,
+            expected_mod_rev,
+        } => CommandEntry::PutIfRevision {
+            key: scoped_key(namespace, &key),
+            value,
+            expected_mod_rev,
+        },
+        // the elections are not scoped, only the key written
+        CommandEntry::FencedSet {
+            key,
+            value,
+            election,
+            token,
+        } => CommandEntry::FencedSet {
+            key: scoped_key(namespace, &key),
+            value,
+            election,
+            token,
+        },
+        CommandEntry::Deadline { timeout_ms, cmd } => CommandEntry::Deadline {
+            timeout_ms,
+            cmd: Box::new(scope_keys(namespace, *cmd)?),
+        },
+        CommandEntry::Namespaced { .. } => return Err("nested namespace".into()),
+        CommandEntry::BulkLoad { pairs } => CommandEntry::BulkLoad {
+            pairs: pairs
+                .into_iter()
+                .map(|(key, value)| (scoped_key(namespace, &key), value))
+                .collect(),
+        },
+        CommandEntry::AtRevision { revision, cmd } => CommandEntry::AtRevision {
+            revision,
+            cmd: Box::new(scope_keys(namespace, *cmd)?),
+        },
+        CommandEntry::Revision => CommandEntry::Revision,
+        // the names listed are relative to the path, so they need no unscoping
+        CommandEntry::ListChildren { path } => CommandEntry::ListChildren {
+            path: scoped_key(namespace, &path),
+        },
+        CommandEntry::DeleteTree { path } => CommandEntry::DeleteTree {
+            path: scoped_key(namespace, &path),
+        },
+        CommandEntry::DeletePrefix { prefix, limit } => CommandEntry::DeletePrefix {
+            prefix: scoped_key(namespace, &prefix),
+            limit,
+        },
+        // the sessions are not scoped, only the key written
+        CommandEntry::EphemeralSet {
+            session,
+            key,
+            value,
+        } => CommandEntry::EphemeralSet {
+            session,
+            key: scoped_key(namespace, &key),
+            value,
+        },
+        cmd @ (CommandEntry::OpenSession { .. }
+        | CommandEntry::KeepAlive { .. }
+        | CommandEntry::KeepAliveStream { .. }
+        | CommandEntry::CloseSession { .. }) => cmd,
+        // the events would carry the keys of the namespace as stored
+        CommandEntry::Watch { .. } => return Err("watch in a namespace".into()),
+        CommandEntry::Elect { .. } => return Err("election in a namespace".into()),
+        CommandEntry::Scan { .. } => return Err("scan in a namespace".into()),
+        CommandEntry::Empty => CommandEntry::Empty,
+    };
+    Ok(cmd)
+}
+
+fn local_read(ddbb: &Arc<Mutex<DDBB>>, key: String) -> Frame {
+    let (value, meta) = {
+        let ddbb = ddbb.lock().unwrap();
+        (ddbb.get(key.clone()), ddbb.stat(&key))
+    };
+    read_reply(key, value, meta)
+}
+
+/// #Descriptions: the reply to a read, carrying the metadata of the key if the
+/// state machine keeps it.
+fn read_reply(key: String, value: Option<Vec<u8>>, meta: Option<KeyMeta>) -> Frame {
+    match (value, meta) {
+        (Some(value), Some(meta)) => DataEntry::KeyValueMeta {
+            key,
+            value: Bytes::from(value),
+            meta,
+        }
+        .to_frame(),
+        (Some(value), None) => DataEntry::KeyValue {
+            key,
+            value: Bytes::from(value),
+        }
+        .to_frame(),
+        (None, _) => MessageEntry::Error {
+            err_msg: format!("key not found: {}", key),
+        }
+        .to_frame(),
+    }
+}
+
+fn stat_reply(key: String, meta: Option<KeyMeta>) -> Frame {
+    match meta {
+        Some(meta) => DataEntry::Stat { key, meta }.to_frame(),
+        None => MessageEntry::Error {
+            err_msg: format!("key not found: {}", key),
+        }
+        .to_frame(),
+    }
+}
+
+/// #Descriptions: run the read `cmd` against the store as it was at `revision`.
+fn read_at(ddbb: &Arc<Mutex<DDBB>>, revision: u64, cmd: CommandEntry) -> Frame {
+    let ddbb = ddbb.lock().unwrap();
+    let result = match cmd {
+        CommandEntry::GetValue { key } | CommandEntry::StaleGet { key } => {
+            match ddbb.get_at(&key, revision) {
+                Ok(Some(value)) => Ok(DataEntry::KeyValue {
+                    key,
+                    value: Bytes::from(value),
+                }),
+                Ok(None) => Err(format!("key not found: {}", key).into()),
+                Err(e) => Err(e),
+            }
+        }
+        CommandEntry::Scan { prefix } => {
+            ddbb.scan_at(&prefix, revision)
+                .map(|pairs| DataEntry::KeyValues {
+                    pairs: pairs
+                        .into_iter()
+                        .map(|(key, value)| (key, Bytes::from(value)))
+                        .collect(),
+                })
+        }
+        CommandEntry::ListChildren { path } => {
+            let prefix = tree_prefix(&path);
+            ddbb.scan_at(&prefix, revision)
+                .map(|pairs| DataEntry::Children {
+                    children: child_names(&prefix, pairs.iter().map(|(key, _)| key.as_str())),
+                })
+        }
+        _ => Err("only reads run at a revision".into()),
+    };
+    match result {
+        Ok(data) => data.to_frame(),
+        Err(e) => MessageEntry::Error {
+            err_msg: e.to_string(),
+        }
+        .to_frame(),
+    }
+}
+
+async fn handle_command(ddbb: Arc<Mutex<DDBB>>, cmd: CommandEntry) -> Frame {
+    match cmd {
+        CommandEntry::SetValue { key, value } => {
+            match DDBB::lin_write(ddbb, key, value.to_vec()).await {
+                Ok(idx) => MessageEntry::Success {
+                    msg: format!("decided at {}", idx),
+                }
+                .to_frame(),
+                Err(e) => MessageEntry::Error {
+                    err_msg: e.to_string(),
+                }
+                .to_frame(),
+            }
+        }
+        CommandEntry::BulkLoad { pairs } if pairs.len() > BULK_LOAD_MAX_KEYS => {
+            MessageEntry::Error {
+                err_msg: format!(
+                    "bulk load of {} pairs, at most {} per chunk",
+                    pairs.len(),
+                    BULK_LOAD_MAX_KEYS
+                ),
+            }
+            .to_frame()
+        }
+        CommandEntry::BulkLoad { pairs } => {
+            let pairs = pairs
+                .into_iter()
+                .map(|(key, value)| (key, value.to_vec()))
+                .collect();
+            // answered once decided, so a client sending the next chunk only then is
+            // held back by a slow cluster
+            match DDBB::bulk_set(ddbb, pairs).await {
+                Ok((idx, written)) => MessageEntry::Success {
+                    msg: format!("{} pairs written, decided at {}", written, idx),
+                }
+                .to_frame(),
+                Err(e) => MessageEntry::Error {
+                    err_msg: e.to_string(),
+                }
+                .to_frame(),
+            }
+        }
+        CommandEntry::GetValue { .. }
+        | CommandEntry::StaleGet { .. }
+        | CommandEntry::Stat { .. }
+        | CommandEntry::Scan { .. }
+        | CommandEntry::ListChildren { .. }
+            if REFUSE_READS_WHILE_CATCHING_UP && !ddbb.lock().unwrap().is_caught_up() =>
+        {
+            let behind = ddbb.lock().unwrap().catch_up_progress().entries_behind;
+            MessageEntry::Error {
+                err_msg: Error::Unavailable(format!(
+                    "catching up, {} entries behind, retry later",
+                    behind
+                ))
+                .to_string(),
+            }
+            .to_frame()
+        }
+        CommandEntry::GetValue { key }
+            if ddbb.lock().unwrap().dynamic_config().read_mode == ReadMode::Local =>
+        {
+            local_read(&ddbb, key)
+        }
+        CommandEntry::Stat { key }
+            if ddbb.lock().unwrap().dynamic_config().read_mode == ReadMode::Local =>
+        {
+            let meta = ddbb.lock().unwrap().stat(&key);
+            stat_reply(key, meta)
+        }
+        CommandEntry::Stat { key } => match DDBB::lin_stat(ddbb, key.clone()).await {
+            Ok((_, meta)) => stat_reply(key, meta),
+            Err(e) => MessageEntry::Error {
+                err_msg: e.to_string(),
+            }
+            .to_frame(),
+        },
+        CommandEntry::StaleGet { key } => local_read(&ddbb, key),
+        CommandEntry::Scan { prefix } => {
+            let pairs = ddbb.lock().unwrap().scan(&prefix);
+            DataEntry::KeyValues {
+                pairs: pairs
+                    .into_iter()
+                    .map(|(key, value)| (key, Bytes::from(value)))
+                    .collect(),
+            }
+            .to_frame()
+        }
+        CommandEntry::ListChildren { path } => {
+            let children = ddbb.lock().unwrap().list_children(&path);
+            DataEntry::Children { children }.to_frame()
+        }
+        CommandEntry::DeleteTree { path } => match DDBB::delete_tree(ddbb, path).await {
+            Ok((idx, deleted)) => MessageEntry::Success {
+                msg: format!("{} keys deleted, decided at {}", deleted, idx),
+            }
+            .to_frame(),
+            Err(e) => MessageEntry::Error {
+                err_msg: e.to_string(),
+            }
+            .to_frame(),
+        },
+        CommandEntry::DeletePrefix { prefix, limit } => {
+            let limit = limit.min(DELETE_PREFIX_MAX_KEYS);
+            match DDBB::delete_prefix(ddbb, prefix, limit).await {
+                Ok((idx, deleted, left)) => MessageEntry::Success {
+                    msg: format!("{} keys deleted, {} left, decided at {}", deleted, left, idx),
+                }
+                .to_frame(),
+                Err(e) => MessageEntry::Error {
+                    err_msg: e.to_string(),
+                }
+                .to_frame(),
+            }
+        }
+        // a node behind the pinned revision refuses with a retryable error
+        CommandEntry::AtRevision { revision, cmd } => read_at(&ddbb, revision, *cmd),
+        CommandEntry::Revision => {
+            let revision = ddbb.lock().unwrap().revision();
+            MessageEntry::Success {
+                msg: format!("revision {}", revision),
+            }
+            .to_frame()
+        }
+        CommandEntry::GetValue { key } => match DDBB::lin_stat(ddbb, key.clone()).await {
+            Ok((value, meta)) => read_reply(key, value, meta),
+            Err(e) => MessageEntry::Error {
+                err_msg: e.to_string(),
+            }
+            .to_frame(),
+        },
+        CommandEntry::PutIfRevision {
+            key,
+            value,
+            expected_mod_rev,
+        } => match DDBB::put_if_revision(ddbb, key, value.to_vec(), expected_mod_rev).await {
+            Ok((true, mod_rev)) => MessageEntry::Success {
+                msg: format!("revision {}", mod_rev),
+            }
+            .to_frame(),
+            Ok((false, mod_rev)) => MessageEntry::Error {
+                err_msg: Error::Conflict(format!("stale revision, current revision {}", mod_rev))
+                    .to_string(),
+            }
+            .to_frame(),
+            Err(e) => MessageEntry::Error {
+                err_msg: e.to_string(),
+            }
+            .to_frame(),
+        },
+        CommandEntry::FencedSet {
+            key,
+            value,
+            election,
+            token,
+        } => match DDBB::fenced_write(ddbb, key, value.to_vec(), election, token).await {
+            Ok((true, fence)) => MessageEntry::Success {
+                msg: format!("fenced with {}", fence),
+            }
+            .to_frame(),
+            Ok((false, fence)) => MessageEntry::Error {
+                err_msg: Error::Conflict(format!(
+                    "stale fencing token {}, fenced with {}",
+                    token, fence
+                ))
+                .to_string(),
+            }
+            .to_frame(),
+            Err(e) => MessageEntry::Error {
+                err_msg: e.to_string(),
+            }
+            .to_frame(),
+        },
+        CommandEntry::OpenSession { ttl_ms } => {
+            match DDBB::open_session(ddbb, Duration::from_millis(ttl_ms)).await {
+                Ok(session) => MessageEntry::Success {
+                    msg: format!("session {}", session),
+                }
+                .to_frame(),
+                Err(e) => MessageEntry::Error {
+                    err_msg: e.to_string(),
+                }
+                .to_frame(),
+            }
+        }
+        CommandEntry::KeepAlive { session } => match DDBB::keep_alive(ddbb, session).await {
+            Ok(()) => MessageEntry::Success {
+                msg: format!("session {} kept alive", session),
+            }
+            .to_frame(),
+            Err(e) => MessageEntry::Error {
+                err_msg: e.to_string(),
+            }
+            .to_frame(),
+        },
+        CommandEntry::EphemeralSet {
+            session,
+            key,
+            value,
+        } => match DDBB::ephemeral_write(ddbb, session, key, value.to_vec()).await {
+            Ok(idx) => MessageEntry::Success {
+                msg: format!("decided at {}", idx),
+            }
+            .to_frame(),
+            Err(e) => MessageEntry::Error {
+                err_msg: e.to_string(),
+            }
+            .to_frame(),
+        },
+        CommandEntry::CloseSession { session } => {
+            match DDBB::close_session(ddbb, session).await {
+                Ok(Some(deleted)) => MessageEntry::Success {
+                    msg: format!("session {} closed, {} keys deleted", session, deleted.len()),
+                }
+                .to_frame(),
+                Ok(None) => MessageEntry::Error {
+                    err_msg: format!("session not found: {}", session),
+                }
+                .to_frame(),
+                Err(e) => MessageEntry::Error {
+                    err_msg: e.to_string(),
+                }
+                .to_frame(),
+            }
+        }
+        CommandEntry::Deadline { .. } => MessageEntry::Error {
+            err_msg: "nested deadline".to_string(),
+        }
+        .to_frame(),
+        // unwrapped by `scope_command`
+        CommandEntry::Namespaced { .. } => MessageEntry::Error {
+            err_msg: "unscoped namespace".to_string(),
+        }
+        .to_frame(),
+        // served by `serve_watch`, unless sent within another command
+        CommandEntry::Watch { .. } => MessageEntry::Error {
+            err_msg: "nested watch".to_string(),
+        }
+        .to_frame(),
+        // served by `serve_election`, unless sent within another command
+        CommandEntry::Elect { .. } => MessageEntry::Error {
+            err_msg: "nested election".to_string(),
+        }
+        .to_frame(),
+        // served by `serve_keep_alive`, unless sent within another command
+        CommandEntry::KeepAliveStream { .. } => MessageEntry::Error {
+            err_msg: "nested keepalive stream".to_string(),
+        }
+        .to_frame(),
+        CommandEntry::Empty => MessageEntry::Error {
+            err_msg: "empty command".to_string(),
+        }
+        .to_frame(),
+    }
+}
+
+/// #Descriptions: answer `admin`, asking the peers with `auth_token` for a
+/// `ClusterStatus`.
+async fn handle_admin(
+    ddbb: Arc<Mutex<DDBB>>,
+    admin: AdminEntry,
+    auth_token: &Option<String>,
+) -> Frame {
+    let result = match admin {
+        AdminEntry::SlowLog => to_json(&ddbb.lock().unwrap().slow_log()),
+        AdminEntry::Metrics => to_json(&ddbb.lock().unwrap().metrics()),
+        AdminEntry::CatchUp => to_json(&ddbb.lock().unwrap().catch_up_progress()),
+        AdminEntry::Status => to_json(&ddbb.lock().unwrap().status()),
+        AdminEntry::Keyspace => to_json(&ddbb.lock().unwrap().keyspace()),
+        AdminEntry::Events => to_json(&ddbb.lock().unwrap().events()),
+        AdminEntry::ClusterStatus => to_json(&cluster_status(&ddbb, auth_token).await),
+        AdminEntry::ClusterTopology => Ok(cluster_status(&ddbb, auth_token).await.to_dot()),
+        AdminEntry::CreateNamespace {
+            name,
+            max_keys,
+            token,
+        } => match DDBB::create_namespace(ddbb, name.clone(), max_keys, token).await {
+            Ok(true) => Ok(format!("namespace {} created", name)),
+            Ok(false) => Err(Error::Conflict(format!("namespace {} exists", name))),
+            Err(e) => Err(e),
+        },
+        AdminEntry::Config => to_json(&ddbb.lock().unwrap().dynamic_config()),
+        AdminEntry::ListBackups => match ddbb.lock().unwrap().list_backups() {
+            Ok(backups) => to_json(&backups),
+            Err(e) => Err(e),
+        },
+        AdminEntry::RestoreBackup { name } => match ddbb.lock().unwrap().stage_restore(&name) {
+            Ok(export) => Ok(format!(
+                "backup {} at applied index {} restored at the next start",
+                name, export.applied_idx
+            )),
+            Err(e) => Err(e),
+        },
+        AdminEntry::ExportSnapshot { path } => {
+            match ddbb.lock().unwrap().export_snapshot(&path) {
+                Ok(export) => Ok(format!(
+                    "exported applied index {} (decided {}) to {}",
+                    export.applied_idx, export.decided_idx, path
+                )),
+                Err(e) => Err(e),
+            }
+        }
+        AdminEntry::SetConfig { name, value } => {
+            match DDBB::set_config(ddbb, name.clone(), value).await {
+                Ok(idx) => Ok(format!("{} set, decided at {}", name, idx)),
+                Err(e) => Err(e),
+            }
+        }
+        AdminEntry::DeleteNamespace { name } => {
+            match DDBB::delete_namespace(ddbb, name.clone()).await {
+                Ok(true) => Ok(format!("namespace {} deleted", name)),
+                Ok(false) => Err(format!("namespace not found: {}", name).into()),
+                Err(e) => Err(e),
+            }
+        }
+        AdminEntry::SetLinkLatency {
+            peer,
+            delay_ms,
+            jitter_ms,
+        } => match ddbb.lock().unwrap().set_link_latency(peer, delay_ms, jitter_ms) {
+            Ok(()) => Ok(format!(
+                "msgs to node {} delayed by {}±{} ms",
+                peer, delay_ms, jitter_ms
+            )),
+            Err(e) => Err(e),
+        },
+        AdminEntry::CompactRevision { revision } => {
+            match DDBB::compact_revision(ddbb, revision).await {
+                Ok((idx, compacted)) => Ok(format!(
+                    "history compacted up to revision {}, decided at {}",
+                    compacted, idx
+                )),
+                Err(e) => Err(e),
+            }
+        }
+        AdminEntry::Backup { node } => {
+            let node = Some(node).filter(|node| *node != 0);
+            match DDBB::consistent_backup(ddbb, node).await {
+                Ok((idx, revision, Some(name))) => Ok(format!(
+                    "backup {} taken at revision {}, decided at {}",
+                    name, revision, idx
+                )),
+                Ok((idx, revision, None)) => Ok(format!(
+                    "backup pinned at revision {}, decided at {}, see the events of node {}",
+                    revision,
+                    idx,
+                    node.unwrap_or_default()
+                )),
+                Err(e) => Err(e),
+            }
+        }
+        AdminEntry::StepDown => match DDBB::step_down(ddbb).await {
+            Ok(leader) => Ok(format!("leadership handed over to node {}", leader)),
+            Err(e) => Err(e),
+        },
+    };
+    match result {
+        Ok(msg) => MessageEntry::Success { msg }.to_frame(),
+        Err(e) => MessageEntry::Error {
+            err_msg: e.to_string(),
+        }
+        .to_frame(),
+    }
+}
+
+/// #Descriptions: the status of this node and of every peer, asked on its client port.
+/// A peer whose client address is not known, see `--peer-client-addrs`, or not
+/// answering within `CLUSTER_STATUS_TIMEOUT` is reported unreachable.
+async fn cluster_status(ddbb: &Arc<Mutex<DDBB>>, auth_token: &Option<String>) -> ClusterStatus {
+    let (status, peers) = {
+        let ddbb = ddbb.lock().unwrap();
+        (ddbb.status(), ddbb.peer_client_addrs())
+    };
+    let mut cluster = ClusterStatus::default();
+    cluster.add(status);
+    // asked all at once, a slow peer delays the answer by one timeout at most
+    let mut queries = Vec::new();
+    for (peer_id, addr) in peers {
+        match addr {
+            Some(addr) => {
+                let query = timeout(
+                    CLUSTER_STATUS_TIMEOUT,
+                    peer_status(addr, auth_token.clone()),
+                );
+                let name = format!("cluster-status of {}", peer_id);
+                queries.push((peer_id, spawn_named(&name, query)));
+            }
+            None => cluster.add_unreachable(peer_id, "client address not known".to_string()),
+        }
+    }
+    for (peer_id, query) in queries {
+        match query.await {
+            Ok(Ok(Ok(status))) => cluster.add(status),
+            Ok(Ok(Err(e))) => cluster.add_unreachable(peer_id, e.to_string()),
+            Ok(Err(_)) => cluster.add_unreachable(
+                peer_id,
+                format!("no answer within {:?}", CLUSTER_STATUS_TIMEOUT),
+            ),
+            Err(e) => cluster.add_unreachable(peer_id, e.to_string()),
+        }
+    }
+    cluster
+}
+
+/// #Descriptions: the `NodeStatus` of the node serving clients at `addr`.
+async fn peer_status(addr: String, auth_token: Option<String>) -> Result<NodeStatus> {
+    let mut connection = Connection::new(TcpStream::connect(&addr).await?);
+    if let Some(token) = auth_token {
+        connection
+            .write_frame(&AuthEntry { token }.to_frame())
+            .await?;
+        let reply = connection
+            .read_frame()
+            .await?
+            .ok_or(Error::ConnectionClosed)?;
+        if let MessageEntry::Error { err_msg } = *MessageEntry::from_frame(&reply)? {
+            return Err(Error::from_message(&err_msg));
+        }
+    }
+    connection
+        .write_frame(&AdminEntry::Status.to_frame())
+        .await?;
+    let reply = connection
+        .read_frame()
+        .await?
+        .ok_or(Error::ConnectionClosed)?;
+    match *MessageEntry::from_frame(&reply)? {
+        MessageEntry::Success { msg } => Ok(serde_json::from_str(&msg)?),
+        MessageEntry::Error { err_msg } => Err(Error::from_message(&err_msg)),
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddbb_libs::clock::{system_clock, MockClock};
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secreT", b"secret"));
+        assert!(!tokens_match(b"secret!", b"secret"));
+        assert!(!tokens_match(b"", b"secret"));
+    }
+
+    #[test]
+    fn test_needs_leader() {
+        let get = CommandEntry::GetValue {
+            key: "k1".to_string(),
+        };
+        let set = CommandEntry::Deadline {
+            timeout_ms: 1000,
+            cmd: Box::new(CommandEntry::SetValue {
+                key: "k1".to_string(),
+                value: Bytes::from("v1"),
+            }),
+        };
+        assert!(needs_leader(&set, true));
+        assert!(needs_leader(&get, false));
+        assert!(!needs_leader(&get, true));
+        let stale_get = CommandEntry::StaleGet {
+            key: "k1".to_string(),
+        };
+        assert!(!needs_leader(&stale_get, false));
+    }
+
+    #[test]
+    fn test_bulk_load_rate_limited() {
+        let mut write_bucket = TokenBucket::new(1.0, 2.0);
+        let prefix_limiter = Mutex::new(PrefixLimiter::new(&[]));
+        let pairs = (0..5)
+            .map(|i| (format!("k{}", i), Bytes::from("v")))
+            .collect();
+        let load = CommandEntry::Namespaced {
+            namespace: "app1".to_string(),
+            token: String::new(),
+            cmd: Box::new(CommandEntry::BulkLoad { pairs }),
+        };
+        assert_eq!(write_keys(&load).len(), 5);
+        assert!(may_write(&load, &mut write_bucket, &prefix_limiter));
+        // the 5 pairs were charged, more than the burst
+        let set = CommandEntry::SetValue {
+            key: "k1".to_string(),
+            value: Bytes::from("v1"),
+        };
+        assert!(!may_write(&set, &mut write_bucket, &prefix_limiter));
+        assert!(!may_write(&load, &mut write_bucket, &prefix_limiter));
+    }
+
+    /// #Descriptions: a connected pair of connections, the client's and the server's.
+    async fn connection_pair() -> (Connection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let (client, (server, _)) = tokio::join!(client, listener.accept());
+        (
+            Connection::new(client.unwrap()),
+            Connection::new(server.unwrap().0),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_refreshes_per_frame() {
+        let (mut client, mut server) = connection_pair().await;
+        let refreshes = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let counted = refreshes.clone();
+        let refresh = move || {
+            counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::future::ready(Ok(()))
+        };
+        let stream = tokio::spawn(async move {
+            keep_alive_frames(&mut server, 1, 60_000, system_clock(), refresh).await
+        });
+
+        for _ in 0..2 {
+            let frame = CommandEntry::KeepAlive { session: 1 }.to_frame();
+            client.write_frame(&frame).await.unwrap();
+            let reply = client.read_frame().await.unwrap().unwrap();
+            let reply = KeepAliveEntry::from_frame(&reply).unwrap();
+            assert_eq!((reply.session, reply.ttl_ms), (1, 60_000));
+        }
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // not a keepalive of the session, no refresh
+        let frame = CommandEntry::KeepAlive { session: 2 }.to_frame();
+        client.write_frame(&frame).await.unwrap();
+        stream.await.unwrap().unwrap();
+        assert!(client.read_frame().await.unwrap().is_none());
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_silent_client_expires() {
+        let (mut client, mut server) = connection_pair().await;
+        let clock = MockClock::new(0);
+        let shared = clock.shared();
+        let refresh = || std::future::ready(Ok(()));
+        let mut stream = tokio::spawn(async move {
+            keep_alive_frames(&mut server, 1, 1000, shared, refresh).await
+        });
+
+        // the connection stays open, but the client sends nothing
+        clock.advance(Duration::from_millis(999));
+        assert!(timeout(Duration::from_millis(50), &mut stream).await.is_err());
+        while !stream.is_finished() {
+            clock.advance(Duration::from_millis(1000));
+            tokio::task::yield_now().await;
+        }
+        stream.await.unwrap().unwrap();
+        assert!(client.read_frame().await.unwrap().is_none());
+    }
+}
@@ -1,16 +1,225 @@
 use std::time::Duration;
 
+use crate::omni_paxos_server::op_connection::HeartbeatTransport;
+use crate::storage::{StorageBackend, SyncMode};
+use crate::watch::SlowWatcherPolicy;
+
 /// OmniSIMO configs
-pub const RETRIEVE_INTERVAL: u64 = 1;
 pub const RECONNECT_INTERVAL: u64 = 200;
+/// msgs to one peer written to the socket together
+pub const MAX_SEND_BATCH: usize = 256;
+/// an `AcceptDecide` of up to this many bytes goes in one frame with a heartbeat to the
+/// same peer, see `Capabilities::PIGGYBACK`
+pub const PIGGYBACK_MAX_BYTES: u64 = 4096;
+/// ping a peer not heard from for this long
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(1000);
+/// a peer not answering a ping or accepting a write within this is considered dead
+pub const KEEPALIVE_TIMEOUT: Duration = Duration::from_millis(500);
+/// drop incoming connections silent for this long
+pub const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_millis(5000);
+/// a connection to the OmniSIMO listener sending no valid handshake, TLS included,
+/// within this is closed
+pub const PEER_HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(2000);
+/// connections the OmniSIMO listener accepts per second on average and in a burst,
+/// 0 for no limit; peers reconnect every `RECONNECT_INTERVAL` at most
+pub const PEER_ACCEPT_RATE: f64 = 100.0;
+pub const PEER_ACCEPT_BURST: f64 = 100.0;
+/// connections open to the OmniSIMO listener at once, and as one node, 0 for no limit
+pub const PEER_MAX_CONNECTIONS: usize = 1024;
+pub const PEER_MAX_CONNECTIONS_PER_NODE: usize = 8;
+pub const TCP_KEEPALIVE_TIME: Duration = Duration::from_secs(10);
+/// frames between peers carry batches of log entries and snapshots
+pub const PEER_MAX_FRAME_SIZE: usize = 256 * 1024 * 1024;
+/// sync msgs to a peer catching up go on a connection of their own, so live msgs and
+/// heartbeats do not wait behind them
+pub const CATCH_UP_CONNECTION: bool = true;
+/// how the heartbeats of the leader election go to the peers, see `--heartbeat-transport`
+pub const HEARTBEAT_TRANSPORT: HeartbeatTransport = HeartbeatTransport::Shared;
+/// the largest heartbeat datagram read with `--heartbeat-transport udp`
+pub const MAX_HEARTBEAT_DATAGRAM: usize = 64 * 1024;
+/// a sync is delivered once the peer answers a ping sent after it within this
+pub const CATCH_UP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Listener configs, of both OmniSIMO and the client listener
+pub const LISTEN_REUSEADDR: bool = true;
+/// small consensus msgs are sent at once instead of waiting for Nagle
+pub const TCP_NODELAY: bool = true;
+/// SO_SNDBUF and SO_RCVBUF of the connections, `None` keeps the kernel default and its
+/// autotuning; set them for a high bandwidth-delay link between data centers
+pub const SOCKET_SEND_BUFFER_SIZE: Option<usize> = None;
+pub const SOCKET_RECV_BUFFER_SIZE: Option<usize> = None;
+/// an outgoing connection not established within this is attempted again
+pub const CONNECT_TIMEOUT: Duration = Duration::from_millis(1000);
+pub const LISTEN_BACKLOG: i32 = 1024;
+/// an IPv6 listener also accepts IPv4
+pub const LISTEN_DUAL_STACK: bool = true;
+
+/// Bootstrap configs
+/// OmniSIMO port of StatefulSet pods when `DDBB_PORT` is not set
+pub const STATEFULSET_PORT: u16 = 6550;
+/// where a node keeps the cluster uuid, under a directory per node id
+pub const DATA_DIR: &str = "ddbb_data";
+/// the state machine snapshot in the data directory, written at every compaction
+pub const STATE_SNAPSHOT_FILE: &str = "state_snapshot";
+/// compactions in between complete snapshots only persist the logs applied since the
+/// previous one, as deltas named after the applied index they end at
+pub const STATE_DELTA_PREFIX: &str = "state_delta-";
+/// a complete snapshot every this many compactions, bounding the deltas to replay
+pub const FULL_SNAPSHOT_EVERY: u64 = 8;
+/// a backup staged by the restore admin command, imported at the next start
+pub const STAGED_RESTORE_FILE: &str = "staged_restore";
+/// how often a joining node retries members that have not agreed on the manifest yet
+pub const BOOTSTRAP_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+/// ballot priority of the nodes in the primary zone, see `--primary-zone`
+pub const PRIMARY_ZONE_PRIORITY: u64 = 1;
+
+/// Storage configs
+/// where omnipaxos keeps its log, a persistent backend keeps it across restarts
+pub const STORAGE_BACKEND: StorageBackend = StorageBackend::Memory;
+/// the databases of the persistent backends in the data directory
+pub const SLED_STORAGE_DIR: &str = "omnipaxos_log";
+pub const ROCKSDB_STORAGE_DIR: &str = "omnipaxos_rocksdb";
+pub const SEGMENT_STORAGE_DIR: &str = "omnipaxos_segments";
+/// the segment files of the log, and the spares kept preallocated for the next ones
+pub const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+pub const SEGMENT_SPARES: usize = 2;
+/// when the persistent backends sync their writes, unless given by `--fsync`
+pub const STORAGE_SYNC_MODE: SyncMode = SyncMode::EveryWrite;
+/// the writes of a tick are written as one batch, or once the oldest waited this long
+pub const STORAGE_MAX_BATCH_LATENCY: Duration = Duration::from_millis(5);
+/// memtable of the rocksdb log column family, large enough for bursts of entries
+pub const ROCKSDB_LOG_WRITE_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+pub const ROCKSDB_BACKGROUND_JOBS: i32 = 4;
 
 /// DDBB configs
-pub const LOG_RETRIEVE_INTERVAL: u64 = 20;
-pub const LIN_WRITE_TIMES_OUT: u64 = 10;
+pub const PROPOSAL_TIMEOUT: Duration = Duration::from_millis(500);
+pub const SLOW_LOG_THRESHOLD: Duration = Duration::from_millis(100);
+pub const SLOW_LOG_CAPACITY: usize = 128;
+/// cluster events kept for the admin API
+pub const EVENT_LOG_CAPACITY: usize = 256;
+/// latest writes kept for watches resuming after a disconnect
+pub const WATCH_HISTORY: usize = 10000;
+/// decided logs applied at most before their writes are streamed to the watches,
+/// as one batch
+pub const WATCH_BATCH_MAX_LOGS: usize = 100;
+/// writes at most in a `WatchBatchEntry`, a larger batch is sent in several
+pub const WATCH_BATCH_MAX_EVENTS: usize = 1000;
+/// writes queued at most for a watch whose client reads slower than they are
+/// applied, 0 for no limit, and what happens to the watch beyond
+pub const WATCH_QUEUE_CAPACITY: usize = 10000;
+pub const SLOW_WATCHERS: SlowWatcherPolicy = SlowWatcherPolicy::Disconnect;
+/// writes are shed once any of these is reached, the defaults of the
+/// `max_outgoing_messages`, `max_pending_proposals` and `max_apply_backlog` settings
+pub const MAX_OUTGOING_MESSAGES: usize = 5000;
+pub const MAX_PENDING_PROPOSALS: usize = 1000;
+pub const MAX_APPLY_BACKLOG: u64 = 1000;
+/// bytes the outgoing and incoming buffers, pending proposals and watch queues may
+/// hold together, writes are shed over it
+pub const MEMORY_BUDGET: u64 = 1024 * 1024 * 1024;
+/// how often the bytes held against `MEMORY_BUDGET` are counted
+pub const MEMORY_SAMPLE_PERIOD: Duration = Duration::from_millis(100);
+/// a node with less free space than this in its data directory stops taking writes,
+/// so that a full disk does not cut a log or snapshot file short, 0 for never; off
+/// unless set with `--disk-low-watermark`, nodes on small disks keep taking writes
+pub const DISK_LOW_WATERMARK: u64 = 0;
+/// how often the free space of the data directory is checked
+pub const DISK_CHECK_PERIOD: Duration = Duration::from_secs(1);
+/// proposals held while no leader is established, each until its `PROPOSAL_TIMEOUT`
+pub const MAX_QUEUED_PROPOSALS: usize = 1000;
+/// how often the queued proposals are retried
+pub const QUEUED_PROPOSAL_RETRY_PERIOD: Duration = Duration::from_millis(10);
+/// a leader stepping down waits this long for another leader, then leads again
+pub const STEP_DOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// a node out of reach of a majority of the cluster for this long fails the proposals
+/// waiting on it, and the new ones, with `QuorumLost` instead of holding them
+pub const QUORUM_LOSS_TIMEOUT: Duration = Duration::from_millis(300);
+/// how often a node checks that it reaches a majority
+pub const QUORUM_CHECK_PERIOD: Duration = Duration::from_millis(50);
+/// how often the leader checks it is on the node preferred, see `--leader-balance`
+pub const LEADER_BALANCE_INTERVAL: Duration = Duration::from_secs(5);
+/// a peer connected for less is not handed the leadership, e.g. just restarted
+pub const LEADER_BALANCE_SETTLE: Duration = Duration::from_secs(30);
+/// how often the leader checks how far behind each follower is
+pub const FOLLOWER_LAG_CHECK_PERIOD: Duration = Duration::from_secs(1);
+/// keys per chunk of a state snapshot written to disk
+pub const SNAPSHOT_CHUNK_KEYS: usize = 1024;
+/// opids of each node remembered by the state machine, a log proposed again within
+/// them is applied once
+pub const DEDUP_WINDOW: usize = 10000;
+/// a campaign refreshes its candidate this many times per ttl
+pub const CAMPAIGN_REFRESHES_PER_TTL: u32 = 3;
+/// the shortest ttl of a client session, long enough for its client to reach the
+/// next leader after a failover
+pub const SESSION_MIN_TTL: Duration = Duration::from_millis(1000);
+/// how often the leader closes the sessions that expired
+pub const SESSION_EXPIRY_PERIOD: Duration = Duration::from_millis(100);
+/// pairs in a chunk of a bulk load, proposed as a single log
+pub const BULK_LOAD_MAX_KEYS: usize = 10000;
+/// keys deleted at most by a single log of a bulk delete by prefix
+pub const DELETE_PREFIX_MAX_KEYS: u64 = 10000;
+/// a value over this is proposed in pieces of this size, reassembled by the state
+/// machine, so that a batch of `MAX_SEND_BATCH` msgs stays under `PEER_MAX_FRAME_SIZE`
+pub const MAX_LOG_VALUE_SIZE: usize = 512 * 1024;
+/// the pieces of a value whose `ChunkedSet` did not follow within this are dropped
+pub const UPLOAD_TTL: Duration = Duration::from_secs(60);
+/// decided logs queued for the apply thread
+pub const APPLY_QUEUE_SIZE: usize = 1024;
+/// a node is caught up once at most this many entries behind the leader
+pub const CAUGHT_UP_LAG: u64 = 10;
+/// answer client reads only once caught up
+pub const REFUSE_READS_WHILE_CATCHING_UP: bool = true;
+
+// defaults of the settings replicated under `CONFIG_KEY_PREFIX`, changed at runtime
+/// the leader proposes a compaction every this many applied logs, 0 for never
+pub const COMPACT_EVERY: u64 = 0;
+/// answer client reads from the local state machine instead of through the log
+pub const LOCAL_READS: bool = false;
+/// the leader bundles the writes arriving within this many ms into one accept and one
+/// storage flush, 0 for none
+pub const GROUP_COMMIT_WINDOW_MS: u64 = 0;
+/// the leader logs a warning for a follower this many logs behind it, and an error
+/// for one that many behind, 0 for never
+pub const FOLLOWER_LAG_WARN: u64 = 1000;
+pub const FOLLOWER_LAG_CRITICAL: u64 = 10_000;
+/// a group commit is proposed at once when it holds this many writes
+pub const GROUP_COMMIT_MAX_LOGS: usize = 1000;
+
+/// Backup configs, backups are only taken with `--backup-dir`
+pub const BACKUP_INTERVAL: Duration = Duration::from_secs(600);
+/// backups kept, older ones are deleted
+pub const BACKUP_RETENTION: usize = 24;
+
+/// Client listener configs
+/// writes per second allowed on a client connection, and the burst above it
+pub const CLIENT_WRITE_RATE: f64 = 1000.0;
+pub const CLIENT_WRITE_BURST: f64 = 100.0;
+/// (key prefix, writes per second, burst), shared by all client connections
+pub const PREFIX_WRITE_LIMITS: &[(&str, f64, f64)] = &[];
+/// a client frame over this closes the connection
+pub const CLIENT_MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+/// a client must send its token within this when the server requires one
+pub const CLIENT_AUTH_TIMEOUT: Duration = Duration::from_millis(1000);
+/// client connections open at once, in total and from one address, 0 for no limit
+pub const CLIENT_MAX_CONNECTIONS: usize = 10000;
+pub const CLIENT_MAX_CONNECTIONS_PER_IP: usize = 1000;
+/// a client connection sending no command for this long is closed, never if `None`
+pub const CLIENT_IDLE_TIMEOUT: Option<Duration> = Some(Duration::from_secs(300));
+/// a peer not sending its status within this is reported unreachable by `cluster-status`
+pub const CLUSTER_STATUS_TIMEOUT: Duration = Duration::from_millis(500);
 
 /// OmniPaxos configs
 pub const BUFFER_SIZE: usize = 10000;
+/// period of the ballot leader election tick, a leader not heard from within a tick
+/// is suspected
 pub const ELECTION_TIMEOUT: Duration = Duration::from_millis(100);
+/// with `--failure-detector phi`, a peer is suspected at this phi, a chance of about
+/// 10^-PHI_THRESHOLD that it is up
+pub const PHI_THRESHOLD: f64 = 8.0;
+/// the latest intervals between the heartbeats of a peer its phi is computed from
+pub const PHI_WINDOW: usize = 100;
+/// lower bound of the deviation of the intervals, a steady link is not made too sensitive
+pub const PHI_MIN_STD_DEV_MS: f64 = 50.0;
+/// period of the tick sending the msgs omnipaxos produced since the previous one
 pub const OUTGOING_MESSAGE_PERIOD: Duration = Duration::from_millis(1);
 pub const WAIT_LEADER_TIMEOUT: Duration = Duration::from_millis(500);
 pub const WAIT_DECIDED_TIMEOUT: Duration = Duration::from_millis(50);
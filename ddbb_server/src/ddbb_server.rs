@@ -1,30 +1,202 @@
-use log::{debug, info};
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use omnipaxos_core::ballot_leader_election::Ballot;
 use omnipaxos_core::{omni_paxos::OmniPaxos, util::LogEntry as OmniLogEntry, util::NodeId};
+use omnipaxos_core::storage::Snapshot as OmniSnapshot;
 use serde_json::Map;
+use tokio_stream::StreamExt;
 use tokio::{
     runtime::Handle,
-    time::{sleep, Duration},
+    sync::{mpsc, oneshot, Notify},
+    task,
+    time::{timeout, Duration, MissedTickBehavior},
 };
 
 use std::{
     clone,
     collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::config::{LIN_WRITE_TIMES_OUT, LOG_RETRIEVE_INTERVAL, WAIT_DECIDED_TIMEOUT};
+use crate::backup::{BackupInfo, Backups};
+use crate::build_info::BuildInfo;
+use crate::catch_up::{CatchUp, CatchUpProgress};
+use crate::client_limits::ClientConnections;
+use crate::config::{
+    APPLY_QUEUE_SIZE, BACKUP_INTERVAL, CAMPAIGN_REFRESHES_PER_TTL, DISK_CHECK_PERIOD,
+    DISK_LOW_WATERMARK, EVENT_LOG_CAPACITY,
+    FOLLOWER_LAG_CHECK_PERIOD, FULL_SNAPSHOT_EVERY, LEADER_BALANCE_INTERVAL, LEADER_BALANCE_SETTLE, GROUP_COMMIT_MAX_LOGS, MAX_LOG_VALUE_SIZE,
+    MAX_QUEUED_PROPOSALS, MEMORY_BUDGET, MEMORY_SAMPLE_PERIOD, PROPOSAL_TIMEOUT, QUEUED_PROPOSAL_RETRY_PERIOD, QUORUM_CHECK_PERIOD, QUORUM_LOSS_TIMEOUT, SESSION_EXPIRY_PERIOD, SESSION_MIN_TTL, SLOW_LOG_CAPACITY,
+    SLOW_LOG_THRESHOLD, STAGED_RESTORE_FILE, STATE_DELTA_PREFIX, STATE_SNAPSHOT_FILE,
+    OUTGOING_MESSAGE_PERIOD, STEP_DOWN_TIMEOUT, WAIT_DECIDED_TIMEOUT, WATCH_BATCH_MAX_LOGS,
+    WATCH_HISTORY,
+};
+use crate::disk::{self, DiskChange, DiskWatch};
+use crate::dynamic_config::{self, DynamicConfig};
+use crate::election::LeaderWatchers;
+use crate::event_log::{ClusterEvent, EventLog, EventLogEntry, SharedEventLog};
+use crate::export::SnapshotExport;
+use crate::follower_lag::{FollowerLag, LagLevel, LagThresholds};
+use crate::keyspace::KeyspaceStats;
+use crate::leader_balance::LeaderBalance;
+use crate::memory::{self, MemoryUsage};
+use crate::metrics::{Metrics, NodeRole, NodeStatus};
+use crate::namespace::{self, Namespace};
+use crate::omni_paxos_server::op_events::{ServerEvents, SharedServerEvents, SnapshotInstalled};
+use crate::omni_paxos_server::op_latency::LinkLatency;
 use crate::omni_paxos_server::{op_connection::OmniSIMO, OmniPaxosInstance, OmniPaxosServer};
-use crate::op_data_structure::LogEntry;
+use crate::op_data_structure::{LogEntry, Snapshot};
+use crate::proposal_queue::ProposalQueue;
+use crate::quorum::{QuorumChange, QuorumWatch};
+use crate::slow_log::{log_kind, SlowLog, SlowLogEntry};
+use crate::snapshot_stream::{SnapshotFile, SnapshotReader};
+use crate::state_machine::{in_bulk_delete, tree_prefix, KVStore, StateMachine};
+use crate::segments::Corruption;
+use crate::storage::StorageFlusher;
+use crate::tasks::spawn_named;
+use crate::watch::{SlowWatcherPolicy, Watch, WatchHub};
+use crate::zones::Zones;
+use ddbb_libs::clock::{system_clock, SharedClock, Ticker};
+use ddbb_libs::data_structure::{
+    ElectionEventEntry, KeyMeta, NotLeaderEntry, WatchDelivery, WatchEventEntry,
+};
 use ddbb_libs::{Error, Result};
 
 pub struct DDBB {
     node_info: NodeInfo,
     wal_store: Arc<Mutex<WALStore>>,
-    kv_store: KVStore,
+    state_machine: Box<dyn StateMachine>,
     peers: Arc<Mutex<HashMap<NodeId, String>>>,
     simo: Arc<Mutex<OmniSIMO>>,
     omni: Arc<Mutex<OmniPaxosInstance>>,
     timestamp: u64,
+    /// proposals waiting to be decided, keyed by opid
+    proposal_callbacks: HashMap<(String, u64), PendingProposal>,
+    /// proposals waiting for a leader to be established
+    proposal_queue: ProposalQueue,
+    /// leader the proposals in flight were last proposed to
+    proposal_ballot: Option<Ballot>,
+    /// handing the leadership over, new proposals are queued meanwhile
+    stepping_down: bool,
+    /// whether a majority is in reach, the proposals fail with `QuorumLost` while not
+    quorum: QuorumWatch,
+    /// the levels the followers were alerted at, on the leader
+    follower_lag: FollowerLag,
+    /// writes held by the leader for the group commit window, and when the first came
+    commit_batch: Vec<LogEntry>,
+    commit_batch_opened: Option<Instant>,
+    slow_log: SlowLog,
+    /// shared with OmniSIMO and the OmniPaxos server
+    events: SharedEventLog,
+    /// the leader changes, reconfigurations and snapshots installed, told to the
+    /// subscribers by the OmniPaxos server and this node
+    server_events: SharedServerEvents,
+    /// woken on every leader change, e.g. for a step down to see the next leader
+    leader_changed: Arc<Notify>,
+    metrics: Metrics,
+    /// writes are shed while the queues hold more bytes than this
+    memory_budget: u64,
+    /// the free space of `data_dir`, writes are rejected while under its low watermark
+    disk: DiskWatch,
+    /// the connections of the client listener, none if it is not started
+    client_connections: Option<ClientConnections>,
+    /// faults may be injected through the admin API, never on by default
+    fault_injection: bool,
+    catch_up: Arc<Mutex<CatchUp>>,
+    /// where the state snapshot is persisted, not persisted if `None`
+    data_dir: Option<String>,
+    /// the settings replicated under `CONFIG_KEY_PREFIX`, as of the applied logs
+    dynamic_config: DynamicConfig,
+    /// applied index of the last compaction, proposed or applied
+    compacted_idx: u64,
+    /// where backups are taken every `BACKUP_INTERVAL`, none if `None`
+    backups: Option<Backups>,
+    /// the backups this node took for a `Backup` log, by opid, until its proposer
+    /// on this node takes the result
+    backups_taken: HashMap<(String, u64), Result<String>>,
+    /// logs applied since the last persisted snapshot, the next delta
+    delta: Snapshot,
+    /// applied index of the last persisted snapshot, complete or delta
+    persisted_idx: u64,
+    /// deltas persisted since the last complete snapshot
+    deltas_since_full: u64,
+    /// the latest writes applied, streamed to the watching clients
+    watches: WatchHub,
+    /// the campaigns running on this node
+    leaders: LeaderWatchers,
+    /// flushes the batched writes of the omnipaxos storage, none to flush if `None`
+    storage_flusher: Option<StorageFlusher>,
+    /// forward client commands to the leader, or send the clients to it
+    forward_to_leader: bool,
+    /// client addresses of the peers, named to the clients sent to the leader
+    peer_client_addrs: HashMap<NodeId, String>,
+    /// the zones of this node and its peers
+    zones: Zones,
+    /// hands the leadership over to the node preferred, none if `None`
+    leader_balance: Option<LeaderBalance>,
+    /// a learner serving only stale reads and watches, sending its clients to the
+    /// leader for the rest
+    observer: bool,
+    /// stamps the ttls of campaigns and sessions, shared with OmniSIMO and the
+    /// OmniPaxos server
+    clock: SharedClock,
+}
+
+/// The first chunk of the state snapshot, the state machine streams the next ones.
+#[derive(Debug, Serialize, Deserialize)]
+struct StateSnapshotHeader {
+    /// the logs applied to the state machine
+    applied_idx: u64,
+}
+
+/// The logs applied from `base_idx` to `applied_idx`, replayed on top of the
+/// snapshot persisted before it.
+#[derive(Debug, Serialize, Deserialize)]
+struct DeltaSnapshot {
+    base_idx: u64,
+    applied_idx: u64,
+    delta: Snapshot,
+}
+
+struct PendingProposal {
+    /// an error once the proposal can not be decided, e.g. `QuorumLost`
+    callback: oneshot::Sender<Result<Decided>>,
+    /// proposed again if its leader changes before it is decided
+    log: LogEntry,
+    /// leader it was proposed to, `None` while queued
+    ballot: Option<Ballot>,
+    proposed_at: Instant,
+    appended_at: Instant,
+}
+
+/// A proposal that has been decided by OmniPaxos and applied locally.
+#[derive(Clone, Debug)]
+pub struct Decided {
+    /// index of the log in the decided sequence
+    pub idx: u64,
+    /// the log as applied, e.g. `LINRead` carries the value read
+    pub log: LogEntry,
+}
+
+/// Unregisters the callback of a proposal once nobody waits for it anymore,
+/// e.g. when the client deadline passed and the waiting future was dropped.
+struct ProposalGuard {
+    ddbb: Arc<Mutex<DDBB>>,
+    opid: (String, u64),
+}
+
+impl Drop for ProposalGuard {
+    fn drop(&mut self) {
+        if let Ok(mut ddbb) = self.ddbb.lock() {
+            ddbb.proposal_callbacks.remove(&self.opid);
+            ddbb.proposal_queue.remove(&self.opid);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -55,230 +227,2186 @@ impl WALStore {
     pub fn diceded(&self) -> u64 {
         self.idx
     }
-}
-
-#[derive(Debug)]
-struct KVStore {
-    store: HashMap<String, Vec<u8>>,
-}
+}
+
+impl DDBB {
+    pub fn new(
+        id: NodeId,
+        self_addr: String,
+        peers: HashMap<NodeId, String>,
+        simo: OmniSIMO,
+        omni: OmniPaxosInstance,
+    ) -> Self {
+        Self::with_state_machine(id, self_addr, peers, simo, omni, Box::new(KVStore::new()))
+    }
+
+    /// #Descriptions: replicate `state_machine` instead of the default key-value store.
+    pub fn with_state_machine(
+        id: NodeId,
+        self_addr: String,
+        peers: HashMap<NodeId, String>,
+        simo: OmniSIMO,
+        omni: OmniPaxosInstance,
+        state_machine: Box<dyn StateMachine>,
+    ) -> Self {
+        let events = EventLog::shared(EVENT_LOG_CAPACITY);
+        let mut simo = simo;
+        simo.set_event_log(events.clone());
+        let server_events = ServerEvents::shared();
+        server_events.lock().unwrap().record_in(events.clone());
+        let leader_changed = Arc::new(Notify::new());
+        let notify = leader_changed.clone();
+        server_events
+            .lock()
+            .unwrap()
+            .on_leader_change(move |_| notify.notify_waiters());
+        let mut peers = Arc::new(Mutex::new(peers));
+        let mut simo = Arc::new(Mutex::new(simo));
+        let mut omni = Arc::new(Mutex::new(omni));
+        DDBB {
+            node_info: NodeInfo {
+                id,
+                addr: self_addr,
+            },
+            peers,
+            simo,
+            omni,
+            wal_store: Arc::new(Mutex::new(WALStore::new())) ,
+            state_machine,
+            // opids must not repeat across restarts, the state machine dedups on them
+            timestamp: unix_millis() * 1000,
+            proposal_callbacks: HashMap::new(),
+            proposal_queue: ProposalQueue::new(MAX_QUEUED_PROPOSALS),
+            proposal_ballot: None,
+            stepping_down: false,
+            quorum: QuorumWatch::new(QUORUM_LOSS_TIMEOUT),
+            follower_lag: FollowerLag::default(),
+            commit_batch: Vec::new(),
+            commit_batch_opened: None,
+            slow_log: SlowLog::new(SLOW_LOG_THRESHOLD, SLOW_LOG_CAPACITY),
+            events,
+            server_events,
+            leader_changed,
+            metrics: Metrics::default(),
+            memory_budget: MEMORY_BUDGET,
+            disk: DiskWatch::new(DISK_LOW_WATERMARK),
+            client_connections: None,
+            fault_injection: false,
+            catch_up: Arc::new(Mutex::new(CatchUp::new())),
+            data_dir: None,
+            dynamic_config: DynamicConfig::default(),
+            compacted_idx: 0,
+            backups: None,
+            backups_taken: HashMap::new(),
+            delta: Snapshot::default(),
+            persisted_idx: 0,
+            deltas_since_full: 0,
+            watches: WatchHub::new(WATCH_HISTORY),
+            leaders: LeaderWatchers::default(),
+            storage_flusher: None,
+            forward_to_leader: true,
+            peer_client_addrs: HashMap::new(),
+            zones: Zones::default(),
+            leader_balance: None,
+            observer: false,
+            clock: system_clock(),
+        }
+    }
+
+    pub async fn start(ddbb: Arc<Mutex<DDBB>>) -> Result<()> {
+        let mut simo: Arc<Mutex<OmniSIMO>>;
+        let mut op_server: OmniPaxosServer;
+        {
+            simo = ddbb.lock().unwrap().simo.clone();
+            let omni = ddbb.lock().unwrap().omni.clone();
+            op_server = OmniPaxosServer::new(omni.clone(), simo.clone());
+            op_server.track_catch_up(ddbb.lock().unwrap().catch_up.clone());
+            op_server.set_server_events(ddbb.lock().unwrap().server_events.clone());
+            op_server.set_clock(ddbb.lock().unwrap().clock.clone());
+            if let Some(flusher) = ddbb.lock().unwrap().storage_flusher.clone() {
+                let clock = ddbb.lock().unwrap().clock.clone();
+                spawn_named("ddbb storage sync", flusher.clone().sync_periodically(clock));
+                op_server.flush_storage_with(flusher);
+            }
+            // logs below the restored applied index are already in the state machine
+            op_server.start_from(ddbb.lock().unwrap().applied_idx());
+
+            // apply logs as soon as they are decided, on a dedicated thread so that a
+            // slow apply or snapshot can not hold up heartbeats and sends
+            let mut decided_stream = op_server.decided_stream();
+            let (apply_sender, apply_receiver) = mpsc::channel(APPLY_QUEUE_SIZE);
+            spawn_named("ddbb apply queue", async move {
+                while let Some((idx, log)) = decided_stream.next().await {
+                    // waits while the queue is full, the logs wait in the stream meanwhile
+                    if apply_sender.send((idx, log, Instant::now())).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            let ddbb = ddbb.clone();
+            thread::Builder::new()
+                .name("ddbb-apply".to_string())
+                .spawn(move || Self::apply_loop(ddbb, apply_receiver))?;
+        }
+
+        Self::start_simo(simo).await?;
+        Self::start_backups(ddbb.clone());
+        Self::start_proposal_queue(ddbb.clone());
+        Self::start_group_commit(ddbb.clone());
+        Self::start_memory_accounting(ddbb.clone());
+        Self::start_session_expiry(ddbb.clone());
+        Self::start_leader_balance(ddbb.clone());
+        Self::start_quorum_watch(ddbb.clone());
+        Self::start_follower_lag(ddbb.clone());
+        Self::start_disk_watch(ddbb.clone());
+        op_server.run().await;
+        return Ok(());
+    }
+
+    fn apply_loop(ddbb: Arc<Mutex<DDBB>>, mut receiver: mpsc::Receiver<(u64, LogEntry, Instant)>) {
+        while let Some((idx, log, decided_at)) = receiver.blocking_recv() {
+            ddbb.lock().unwrap().apply_decided(idx, log, decided_at);
+            // the logs decided together reach the watches as one batch
+            let mut applied = 1;
+            while applied < WATCH_BATCH_MAX_LOGS {
+                match receiver.try_recv() {
+                    Ok((idx, log, decided_at)) => {
+                        ddbb.lock().unwrap().apply_decided(idx, log, decided_at)
+                    }
+                    Err(_) => break,
+                }
+                applied += 1;
+            }
+            ddbb.lock().unwrap().watches.flush();
+        }
+    }
+
+    fn start_backups(ddbb: Arc<Mutex<DDBB>>) {
+        if ddbb.lock().unwrap().backups.is_none() {
+            return;
+        }
+        spawn_named("ddbb backups", async move {
+            let mut backups = tokio::time::interval(BACKUP_INTERVAL);
+            backups.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes at once
+            backups.tick().await;
+            loop {
+                backups.tick().await;
+                // off the runtime threads, it writes the whole state
+                let ddbb = ddbb.clone();
+                let backup = task::spawn_blocking(move || ddbb.lock().unwrap().backup()).await;
+                match backup {
+                    Ok(Ok(name)) => info!("Backup {} taken", name),
+                    Ok(Err(e)) => error!("Backup failed: {:?}", e),
+                    Err(e) => error!("Backup task failed: {:?}", e),
+                }
+            }
+        });
+    }
+
+    fn start_proposal_queue(ddbb: Arc<Mutex<DDBB>>) {
+        spawn_named("ddbb proposal queue", async move {
+            let mut retry = tokio::time::interval(QUEUED_PROPOSAL_RETRY_PERIOD);
+            retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                retry.tick().await;
+                let mut ddbb = ddbb.lock().unwrap();
+                ddbb.repropose_in_flight();
+                ddbb.propose_queued();
+            }
+        });
+    }
+
+    fn start_group_commit(ddbb: Arc<Mutex<DDBB>>) {
+        spawn_named("ddbb group commit", async move {
+            let mut commit = tokio::time::interval(OUTGOING_MESSAGE_PERIOD);
+            commit.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                commit.tick().await;
+                let mut ddbb = ddbb.lock().unwrap();
+                let window = Duration::from_millis(ddbb.dynamic_config.group_commit_window_ms);
+                let due = ddbb
+                    .commit_batch_opened
+                    .map_or(false, |opened| opened.elapsed() >= window);
+                if due {
+                    ddbb.flush_commit_batch();
+                }
+            }
+        });
+    }
+
+    fn start_memory_accounting(ddbb: Arc<Mutex<DDBB>>) {
+        spawn_named("ddbb memory accounting", async move {
+            let mut sample = tokio::time::interval(MEMORY_SAMPLE_PERIOD);
+            sample.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                sample.tick().await;
+                let mut ddbb = ddbb.lock().unwrap();
+                ddbb.metrics.memory = ddbb.memory_usage();
+            }
+        });
+    }
+
+    fn start_quorum_watch(ddbb: Arc<Mutex<DDBB>>) {
+        spawn_named("ddbb quorum watch", async move {
+            let mut check = tokio::time::interval(QUORUM_CHECK_PERIOD);
+            check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                check.tick().await;
+                let mut ddbb = ddbb.lock().unwrap();
+                let in_reach = ddbb.has_quorum();
+                ddbb.check_quorum(in_reach, Instant::now());
+            }
+        });
+    }
+
+    /// whether this node reaches a majority of the cluster: its outgoing connections
+    /// are up to one, and it received the heartbeats of one in the latest round
+    fn has_quorum(&self) -> bool {
+        self.simo.lock().unwrap().connectivity().has_quorum()
+            && self.omni.lock().unwrap().is_quorum_connected()
+    }
+
+    /// #Descriptions: once out of reach of a majority for `QUORUM_LOSS_TIMEOUT`, fail the
+    /// proposals waiting on this node with `QuorumLost`, and the new ones until it is in
+    /// reach again. A proposal already handed to omnipaxos may still be decided.
+    fn check_quorum(&mut self, in_reach: bool, now: Instant) {
+        match self.quorum.observe(in_reach, now) {
+            Some(QuorumChange::Lost) => {
+                info!(
+                    "Node {} lost its quorum, failing {} proposals",
+                    self.node_info.id,
+                    self.proposal_callbacks.len()
+                );
+                self.events.lock().unwrap().record(ClusterEvent::QuorumLost);
+                self.metrics.quorum_lost = true;
+                // never proposed, it is not sent once the quorum is back
+                self.commit_batch.clear();
+                self.commit_batch_opened = None;
+                // the queued ones leave the queue with their guards
+                for (_, pending) in self.proposal_callbacks.drain() {
+                    self.metrics.failed_quorum_lost += 1;
+                    let _ = pending.callback.send(Err(Error::QuorumLost));
+                }
+            }
+            Some(QuorumChange::Regained) => {
+                info!("Node {} regained its quorum", self.node_info.id);
+                self.events.lock().unwrap().record(ClusterEvent::QuorumRegained);
+                self.metrics.quorum_lost = false;
+            }
+            None => {}
+        }
+    }
+
+    fn start_follower_lag(ddbb: Arc<Mutex<DDBB>>) {
+        spawn_named("ddbb follower lag", async move {
+            let mut check = tokio::time::interval(FOLLOWER_LAG_CHECK_PERIOD);
+            check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                check.tick().await;
+                ddbb.lock().unwrap().check_follower_lag();
+            }
+        });
+    }
+
+    /// #Descriptions: on the leader, measure how far behind it each follower is and log
+    /// the followers going over the `follower_lag_warn` and `follower_lag_critical`
+    /// settings, or back under them.
+    fn check_follower_lag(&mut self) {
+        let (leader_idx, followers) = {
+            let omni = self.omni.lock().unwrap();
+            (omni.get_log_len(), omni.get_follower_accepted_idx())
+        };
+        let followers = match followers {
+            Some(followers) => followers,
+            None => {
+                self.follower_lag.reset();
+                self.metrics.follower_lag.clear();
+                return;
+            }
+        };
+        let thresholds = LagThresholds {
+            warn: self.dynamic_config.follower_lag_warn,
+            critical: self.dynamic_config.follower_lag_critical,
+        };
+        let (lags, changes) = self.follower_lag.observe(leader_idx, &followers, thresholds);
+        for (follower, level, lag) in changes {
+            match level {
+                LagLevel::Critical => error!(
+                    "Follower {} is {} logs behind the leader, over {}",
+                    follower, lag, thresholds.critical
+                ),
+                LagLevel::Warn => warn!(
+                    "Follower {} is {} logs behind the leader, over {}",
+                    follower, lag, thresholds.warn
+                ),
+                LagLevel::Ok => info!("Follower {} is back to {} logs behind the leader", follower, lag),
+            }
+            if level != LagLevel::Ok {
+                self.metrics.follower_lag_alerts += 1;
+            }
+        }
+        self.metrics.follower_lag = lags;
+    }
+
+    fn start_disk_watch(ddbb: Arc<Mutex<DDBB>>) {
+        let data_dir = {
+            let ddbb = ddbb.lock().unwrap();
+            match &ddbb.data_dir {
+                Some(data_dir) if ddbb.disk.low_watermark() != 0 => PathBuf::from(data_dir),
+                _ => return,
+            }
+        };
+        spawn_named("ddbb disk watch", async move {
+            let mut check = tokio::time::interval(DISK_CHECK_PERIOD);
+            check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                check.tick().await;
+                match disk::free_bytes(&data_dir) {
+                    Ok(free_bytes) => ddbb.lock().unwrap().check_disk(free_bytes),
+                    Err(e) => error!("Free space of {:?} unknown: {:?}", data_dir, e),
+                }
+            }
+        });
+    }
+
+    /// #Descriptions: take `free_bytes` in the data directory; under the low watermark
+    /// the node rejects the writes with `ReadOnly`, until enough is free again. Reads,
+    /// and the logs replicated from the leader, are still taken.
+    fn check_disk(&mut self, free_bytes: u64) {
+        self.metrics.disk_free_bytes = free_bytes;
+        let low_watermark = self.disk.low_watermark();
+        match self.disk.observe(free_bytes) {
+            Some(DiskChange::Low) => {
+                error!(
+                    "Node {} has {} bytes free in its data directory, under {}, rejecting writes",
+                    self.node_info.id, free_bytes, low_watermark
+                );
+                self.events.lock().unwrap().record(ClusterEvent::DiskLow {
+                    free_bytes,
+                    low_watermark,
+                });
+                self.metrics.read_only = true;
+            }
+            Some(DiskChange::Recovered) => {
+                info!(
+                    "Node {} has {} bytes free in its data directory, taking writes again",
+                    self.node_info.id, free_bytes
+                );
+                self.events
+                    .lock()
+                    .unwrap()
+                    .record(ClusterEvent::DiskRecovered { free_bytes });
+                self.metrics.read_only = false;
+            }
+            None => {}
+        }
+    }
+
+    /// #Descriptions: count the bytes held by the OmniSIMO buffers, the pending
+    /// proposals and the watch queues.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let (outgoing_bytes, incoming_bytes) = {
+            let simo = self.simo.lock().unwrap();
+            let outgoing = simo.outgoing_buffer.lock().unwrap();
+            let incoming = simo.incoming_buffer.lock().unwrap();
+            (
+                outgoing.iter().map(memory::msg_size).sum(),
+                incoming.iter().map(memory::msg_size).sum(),
+            )
+        };
+        MemoryUsage {
+            outgoing_bytes,
+            incoming_bytes,
+            pending_proposal_bytes: self
+                .proposal_callbacks
+                .values()
+                .map(|pending| memory::log_size(&pending.log))
+                .sum(),
+            watch_queue_bytes: self.watches.queued_bytes(),
+        }
+    }
+
+    async fn start_simo(simo: Arc<Mutex<OmniSIMO>>) -> Result<()> {
+        let omni_simo_copy1 = simo.clone();
+        let omni_simo_copy2 = simo.clone();
+        OmniSIMO::start_incoming_listener(omni_simo_copy1).await?;
+        OmniSIMO::start_sender(omni_simo_copy2).await?;
+        return Ok(());
+    }
+
+    pub fn add_ts(&mut self) {
+        self.timestamp += 1;
+    }
+
+    fn next_opid(&mut self) -> (String, u64) {
+        self.add_ts();
+        (self.node_info.addr.clone(), self.timestamp)
+    }
+
+    pub fn set(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        // applied once decided, applying it here too would bump revisions twice
+        let log = LogEntry::SetValue { key, value };
+        self.put_log_into_omni(log)
+    }
+
+    pub fn get(&self, key: String) -> Option<Vec<u8>> {
+        self.state_machine.get(&key)
+    }
+
+    /// Local, possibly stale read of the metadata of `key`.
+    pub fn stat(&self, key: &str) -> Option<KeyMeta> {
+        self.state_machine.stat(key)
+    }
+
+    /// #Descriptions: propose a log and wait until it is decided and applied locally.
+    /// Only logs carrying an opid can be tracked. Dropping the returned future stops
+    /// waiting, but the log may still be decided. A log proposed while no leader is
+    /// established is queued and proposed once one is, see `propose_queued`. Fails
+    /// with `QuorumLost` while this node is out of reach of a majority, see `check_quorum`.
+    pub async fn propose(ddbb: Arc<Mutex<DDBB>>, log: LogEntry) -> Result<Decided> {
+        let opid = match log.opid() {
+            Some(opid) => opid.clone(),
+            None => return Err("log without opid can not be tracked".into()),
+        };
+        let proposed_at = Instant::now();
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut ddbb = ddbb.lock().unwrap();
+            // its clients go to the leader, see `not_leader`
+            if ddbb.observer {
+                return Err(Error::NotLeader);
+            }
+            // failed at once rather than held until a majority is back
+            if ddbb.quorum.is_lost() {
+                ddbb.metrics.failed_quorum_lost += 1;
+                return Err(Error::QuorumLost);
+            }
+            let read = matches!(log, LogEntry::LINRead { .. } | LogEntry::LINStat { .. });
+            if !read && !keeps_or_releases_lease(&log) {
+                ddbb.admit_write()?;
+            }
+            // behind the queued ones, in order
+            let ballot = ddbb
+                .accepting_ballot()
+                .filter(|_| ddbb.proposal_queue.is_empty());
+            if ballot.is_some() && ddbb.holds_for_group_commit(&log, ballot) {
+                ddbb.commit_batch.push(log.clone());
+                ddbb.commit_batch_opened.get_or_insert(proposed_at);
+                if ddbb.commit_batch.len() >= GROUP_COMMIT_MAX_LOGS {
+                    ddbb.flush_commit_batch();
+                }
+            } else if ballot.is_some() {
+                ddbb.put_log_into_omni(log.clone())?;
+            } else if let Err(e) = ddbb
+                .proposal_queue
+                .push(log.clone(), proposed_at + PROPOSAL_TIMEOUT)
+            {
+                ddbb.metrics.shed_proposal_queue += 1;
+                return Err(e);
+            } else {
+                ddbb.metrics.queued_proposals += 1;
+            }
+            // the log can not be applied before the lock is released
+            let pending = PendingProposal {
+                callback: sender,
+                ballot,
+                log,
+                proposed_at,
+                appended_at: Instant::now(),
+            };
+            ddbb.proposal_callbacks.insert(opid.clone(), pending);
+        }
+        let _guard = ProposalGuard { ddbb, opid };
+
+        match timeout(PROPOSAL_TIMEOUT, receiver).await {
+            Ok(Ok(decided)) => decided,
+            Ok(Err(_)) => Err("proposal dropped".into()),
+            Err(_) => Err(Error::Timeout("proposal, it may still be decided".to_string())),
+        }
+    }
+
+    /// #Descriptions: returns the decided index of the write.
+    pub async fn lin_write(ddbb: Arc<Mutex<DDBB>>, key: String, value: Vec<u8>) -> Result<u64> {
+        if value.len() > MAX_LOG_VALUE_SIZE {
+            return Self::chunked_write(ddbb, key, value).await;
+        }
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::LINWrite { opid, key, value };
+        let decided = Self::propose(ddbb, log).await?;
+        Ok(decided.idx)
+    }
+
+    /// #Descriptions: write a value over `MAX_LOG_VALUE_SIZE` as `ValueChunk`s of that
+    /// size, each decided before the next is proposed, then a `ChunkedSet` the state
+    /// machine reassembles them at. Returns the decided index of the `ChunkedSet`.
+    async fn chunked_write(ddbb: Arc<Mutex<DDBB>>, key: String, value: Vec<u8>) -> Result<u64> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let pieces = value.chunks(MAX_LOG_VALUE_SIZE);
+        let chunks = pieces.len() as u32;
+        for (seq, data) in pieces.enumerate() {
+            let (chunk_opid, now) = {
+                let mut ddbb = ddbb.lock().unwrap();
+                (ddbb.next_opid(), ddbb.clock.unix_millis())
+            };
+            let log = LogEntry::ValueChunk {
+                opid: chunk_opid,
+                upload: opid.1,
+                seq: seq as u32,
+                data: data.to_vec(),
+                now,
+            };
+            Self::propose(ddbb.clone(), log).await?;
+        }
+        let log = LogEntry::ChunkedSet {
+            opid,
+            key,
+            chunks,
+            written: false,
+        };
+        let decided = Self::propose(ddbb, log).await?;
+        match decided.log {
+            LogEntry::ChunkedSet { written: true, .. } => Ok(decided.idx),
+            _ => Err("chunked write failed".into()),
+        }
+    }
+
+    pub async fn lin_read(ddbb: Arc<Mutex<DDBB>>, key: String) -> Result<Option<Vec<u8>>> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::LINRead {
+            opid,
+            key,
+            value: None,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::LINRead { value, .. } => Ok(value),
+            _ => Err("Lin read failed".into()),
+        }
+    }
+
+    /// #Descriptions: linearizable read of `key` with its metadata, `None` for a
+    /// state machine without metadata.
+    pub async fn lin_stat(
+        ddbb: Arc<Mutex<DDBB>>,
+        key: String,
+    ) -> Result<(Option<Vec<u8>>, Option<KeyMeta>)> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::LINStat {
+            opid,
+            key,
+            value: None,
+            meta: None,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::LINStat { value, meta, .. } => Ok((value, meta)),
+            _ => Err("Lin stat failed".into()),
+        }
+    }
+
+    /// #Descriptions: write only if `key` was last modified at `expected_mod_rev`,
+    /// 0 meaning the key does not exist. Returns whether the write succeeded, with
+    /// the new revision on success or the current revision on failure.
+    pub async fn put_if_revision(
+        ddbb: Arc<Mutex<DDBB>>,
+        key: String,
+        value: Vec<u8>,
+        expected_mod_rev: u64,
+    ) -> Result<(bool, u64)> {
+        check_value_size(&value)?;
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::PutIfRevision {
+            opid,
+            key,
+            value,
+            expected_mod_rev,
+            succeeded: false,
+            mod_rev: 0,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::PutIfRevision {
+                succeeded, mod_rev, ..
+            } => Ok((succeeded, mod_rev)),
+            _ => Err("Put if revision failed".into()),
+        }
+    }
+
+    /// #Descriptions: write `key` with `token`, the fencing token of a leader of
+    /// `election`. Returns whether the write succeeded, with the token the key is
+    /// fenced with, newer than `token` if it was rejected as stale.
+    pub async fn fenced_write(
+        ddbb: Arc<Mutex<DDBB>>,
+        key: String,
+        value: Vec<u8>,
+        election: String,
+        token: u64,
+    ) -> Result<(bool, u64)> {
+        check_value_size(&value)?;
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::FencedWrite {
+            opid,
+            key,
+            value,
+            election,
+            token,
+            succeeded: false,
+            fence: 0,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::FencedWrite {
+                succeeded, fence, ..
+            } => Ok((succeeded, fence)),
+            _ => Err("Fenced write failed".into()),
+        }
+    }
+
+    /// #Descriptions: write all `pairs` in a single proposal, a chunk of a bulk load.
+    /// Returns the decided index and how many pairs were written, those the state
+    /// machine dropped, e.g. over a namespace quota, are not.
+    pub async fn bulk_set(
+        ddbb: Arc<Mutex<DDBB>>,
+        pairs: Vec<(String, Vec<u8>)>,
+    ) -> Result<(u64, usize)> {
+        for (_, value) in pairs.iter() {
+            check_value_size(value)?;
+        }
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::BulkSet { opid, pairs };
+        let decided = Self::propose(ddbb, log).await?;
+        match decided.log {
+            LogEntry::BulkSet { pairs, .. } => Ok((decided.idx, pairs.len())),
+            _ => Err("Bulk set failed".into()),
+        }
+    }
+
+    /// #Descriptions: delete `path` and every key under it in a single proposal.
+    /// Returns the decided index and how many keys were deleted.
+    pub async fn delete_tree(ddbb: Arc<Mutex<DDBB>>, path: String) -> Result<(u64, usize)> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::DeleteTree {
+            opid,
+            path,
+            deleted: Vec::new(),
+        };
+        let decided = Self::propose(ddbb, log).await?;
+        match decided.log {
+            LogEntry::DeleteTree { deleted, .. } => Ok((decided.idx, deleted.len())),
+            _ => Err("Delete tree failed".into()),
+        }
+    }
+
+    /// #Descriptions: delete up to `limit` keys under `prefix`, in order, in a single
+    /// proposal, so that a large prefix is deleted in several of them without holding
+    /// up the other writes. Returns the decided index, how many keys were deleted and
+    /// how many are left under `prefix`, as of the local state machine.
+    pub async fn delete_prefix(
+        ddbb: Arc<Mutex<DDBB>>,
+        prefix: String,
+        limit: u64,
+    ) -> Result<(u64, usize, usize)> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::DeletePrefix {
+            opid,
+            prefix: prefix.clone(),
+            limit,
+            deleted: Vec::new(),
+        };
+        let decided = Self::propose(ddbb.clone(), log).await?;
+        let deleted = match decided.log {
+            LogEntry::DeletePrefix { deleted, .. } => deleted.len(),
+            _ => return Err("Delete prefix failed".into()),
+        };
+        let left = ddbb
+            .lock()
+            .unwrap()
+            .state_machine
+            .scan(&prefix)
+            .iter()
+            .filter(|(key, _)| in_bulk_delete(&prefix, key))
+            .count();
+        Ok((decided.idx, deleted, left))
+    }
+
+    /// #Descriptions: join `election` as `candidate`, or stay in it, for `ttl`.
+    /// Returns the candidate leading it.
+    pub async fn campaign(
+        ddbb: Arc<Mutex<DDBB>>,
+        election: String,
+        candidate: String,
+        ttl: Duration,
+    ) -> Result<Option<String>> {
+        let (opid, now) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            (ddbb.next_opid(), ddbb.clock.unix_millis())
+        };
+        let log = LogEntry::Campaign {
+            opid,
+            election,
+            candidate,
+            ttl: ttl.as_millis() as u64,
+            now,
+            leader: None,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::Campaign { leader, .. } => Ok(leader),
+            _ => Err("Campaign failed".into()),
+        }
+    }
+
+    pub async fn resign(ddbb: Arc<Mutex<DDBB>>, election: String, candidate: String) -> Result<()> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::Resign {
+            opid,
+            election,
+            candidate,
+            leader: None,
+        };
+        Self::propose(ddbb, log).await?;
+        Ok(())
+    }
+
+    /// #Descriptions: campaign in `election` as `candidate` until the returned stream
+    /// is dropped, then resign. The stream gets an event whenever the leader changes.
+    /// A candidate not refreshed for half its `ttl` reports it is no longer leader,
+    /// before the others can take over once the ttl passed, given clocks that agree.
+    /// The `ttl` is at least `SESSION_MIN_TTL`, as for a session.
+    pub fn elect(
+        ddbb: Arc<Mutex<DDBB>>,
+        election: String,
+        candidate: String,
+        ttl: Duration,
+    ) -> Result<mpsc::UnboundedReceiver<ElectionEventEntry>> {
+        // refreshed several times per ttl, through a leader that may have just failed
+        if ttl < SESSION_MIN_TTL {
+            let e = format!("ttl of {:?} too short, at least {:?}", ttl, SESSION_MIN_TTL);
+            return Err(e.into());
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let name = format!("ddbb campaign of {} in {}", candidate, election);
+        spawn_named(&name, Self::run_campaign(ddbb, election, candidate, ttl, sender));
+        Ok(receiver)
+    }
+
+    async fn run_campaign(
+        ddbb: Arc<Mutex<DDBB>>,
+        election: String,
+        candidate: String,
+        ttl: Duration,
+        events: mpsc::UnboundedSender<ElectionEventEntry>,
+    ) {
+        let (mut leaders, clock) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            (ddbb.leaders.subscribe(&election), ddbb.clock.clone())
+        };
+        let mut refresh = Ticker::new(clock.clone(), ttl / CAMPAIGN_REFRESHES_PER_TTL);
+        // when the latest refresh decided was proposed
+        let mut refreshed_at: Option<Instant> = None;
+        let mut leader: Option<String> = None;
+        let mut last_event: Option<ElectionEventEntry> = None;
+        loop {
+            tokio::select! {
+                _ = events.closed() => break,
+                _ = refresh.tick() => {
+                    let proposed_at = clock.now();
+                    let campaign = Self::campaign(ddbb.clone(), election.clone(), candidate.clone(), ttl);
+                    match campaign.await {
+                        Ok(current) => {
+                            refreshed_at = Some(proposed_at);
+                            leader = current;
+                        }
+                        Err(e) => debug!("Campaign of {} in {} failed: {}", candidate, election, e),
+                    }
+                }
+                Some(current) = leaders.recv() => leader = current,
+            }
+            let confirmed = refreshed_at
+                .map_or(false, |at| clock.now().saturating_duration_since(at) < ttl / 2);
+            let elected = confirmed && leader.as_deref() == Some(candidate.as_str());
+            let fencing_token = if elected {
+                let ddbb = ddbb.lock().unwrap();
+                ddbb.state_machine.fencing_token(&election, &candidate)
+            } else {
+                None
+            };
+            let event = ElectionEventEntry {
+                election: election.clone(),
+                leader: leader.clone().filter(|_| confirmed),
+                elected,
+                fencing_token,
+            };
+            if last_event.as_ref() != Some(&event) {
+                if events.send(event.clone()).is_err() {
+                    break;
+                }
+                last_event = Some(event);
+            }
+        }
+        // otherwise it is dropped once its ttl passed
+        if let Err(e) = Self::resign(ddbb, election.clone(), candidate.clone()).await {
+            debug!("Resign of {} from {} failed: {}", candidate, election, e);
+        }
+    }
+
+    /// #Descriptions: take one of the `permits` permits of semaphore `name` for `session`,
+    /// held until released or until the session closes or expires, so it is kept alive
+    /// with the session. Returns whether the session holds a permit.
+    pub async fn acquire(
+        ddbb: Arc<Mutex<DDBB>>,
+        name: String,
+        permits: u64,
+        session: u64,
+    ) -> Result<bool> {
+        let (opid, now) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            (ddbb.next_opid(), ddbb.clock.unix_millis())
+        };
+        let log = LogEntry::SemAcquire {
+            opid,
+            name,
+            permits,
+            session,
+            now,
+            acquired: false,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::SemAcquire { acquired, .. } => Ok(acquired),
+            _ => Err("Acquire failed".into()),
+        }
+    }
+
+    pub async fn release(ddbb: Arc<Mutex<DDBB>>, name: String, session: u64) -> Result<()> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::SemRelease {
+            opid,
+            name,
+            session,
+        };
+        Self::propose(ddbb, log).await?;
+        Ok(())
+    }
+
+    /// #Descriptions: open a client session kept while it is kept alive within `ttl`,
+    /// through any node. Returns its id.
+    pub async fn open_session(ddbb: Arc<Mutex<DDBB>>, ttl: Duration) -> Result<u64> {
+        if ttl < SESSION_MIN_TTL {
+            let e = format!("ttl of {:?} too short, at least {:?}", ttl, SESSION_MIN_TTL);
+            return Err(e.into());
+        }
+        let (opid, now) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            (ddbb.next_opid(), ddbb.clock.unix_millis())
+        };
+        let log = LogEntry::OpenSession {
+            opid,
+            ttl: ttl.as_millis() as u64,
+            now,
+            session: 0,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::OpenSession { session, .. } => Ok(session),
+            _ => Err("Open session failed".into()),
+        }
+    }
+
+    /// #Descriptions: keep `session` alive for another ttl, fails with `Conflict` once
+    /// it expired.
+    pub async fn keep_alive(ddbb: Arc<Mutex<DDBB>>, session: u64) -> Result<()> {
+        let (opid, now) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            (ddbb.next_opid(), ddbb.clock.unix_millis())
+        };
+        let log = LogEntry::KeepAlive {
+            opid,
+            session,
+            now,
+            alive: false,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::KeepAlive { alive: true, .. } => Ok(()),
+            LogEntry::KeepAlive { .. } => {
+                Err(Error::Conflict(format!("session {} expired", session)))
+            }
+            _ => Err("Keep alive failed".into()),
+        }
+    }
+
+    /// #Descriptions: the ttl of `session` in ms, while open.
+    pub fn session_ttl(&self, session: u64) -> Option<u64> {
+        self.state_machine.session(session).map(|open| open.ttl)
+    }
+
+    /// #Descriptions: write `key` attached to `session`, deleted once it closes or
+    /// expires. Returns the decided index, fails with `Conflict` if it expired.
+    pub async fn ephemeral_write(
+        ddbb: Arc<Mutex<DDBB>>,
+        session: u64,
+        key: String,
+        value: Vec<u8>,
+    ) -> Result<u64> {
+        check_value_size(&value)?;
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::EphemeralSet {
+            opid,
+            session,
+            key,
+            value,
+            written: false,
+        };
+        let decided = Self::propose(ddbb, log).await?;
+        match decided.log {
+            LogEntry::EphemeralSet { written: true, .. } => Ok(decided.idx),
+            LogEntry::EphemeralSet { .. } => Err(Error::Conflict(format!(
+                "session {} expired or key not writable",
+                session
+            ))),
+            _ => Err("Ephemeral write failed".into()),
+        }
+    }
+
+    /// #Descriptions: close `session`, returns the ephemeral keys deleted with it, `None`
+    /// if it was not open.
+    pub async fn close_session(
+        ddbb: Arc<Mutex<DDBB>>,
+        session: u64,
+    ) -> Result<Option<Vec<String>>> {
+        Self::end_session(ddbb, session, false).await
+    }
+
+    async fn end_session(
+        ddbb: Arc<Mutex<DDBB>>,
+        session: u64,
+        only_expired: bool,
+    ) -> Result<Option<Vec<String>>> {
+        let (opid, now) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            (ddbb.next_opid(), ddbb.clock.unix_millis())
+        };
+        let log = LogEntry::CloseSession {
+            opid,
+            session,
+            now,
+            only_expired,
+            closed: false,
+            deleted: Vec::new(),
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::CloseSession {
+                closed: true,
+                deleted,
+                ..
+            } => Ok(Some(deleted)),
+            LogEntry::CloseSession { .. } => Ok(None),
+            _ => Err("Close session failed".into()),
+        }
+    }
+
+    /// #Descriptions: on the leader, close the sessions not kept alive within their
+    /// ttl, deleting their ephemeral keys. Any leader does, so sessions expire across
+    /// failovers, and a keepalive decided first keeps its session.
+    fn start_session_expiry(ddbb: Arc<Mutex<DDBB>>) {
+        spawn_named("ddbb session expiry", async move {
+            let mut sweep = tokio::time::interval(SESSION_EXPIRY_PERIOD);
+            sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                sweep.tick().await;
+                let expired: Vec<u64> = {
+                    let ddbb = ddbb.lock().unwrap();
+                    let leader = ddbb.omni.lock().unwrap().get_current_leader();
+                    if leader != Some(ddbb.node_info.id) {
+                        continue;
+                    }
+                    let now = ddbb.clock.unix_millis();
+                    ddbb.state_machine
+                        .sessions()
+                        .into_iter()
+                        .filter(|session| session.expires_at <= now)
+                        .map(|session| session.id)
+                        .collect()
+                };
+                for session in expired {
+                    match Self::end_session(ddbb.clone(), session, true).await {
+                        Ok(Some(deleted)) => {
+                            info!("Session {} expired, {} keys deleted", session, deleted.len())
+                        }
+                        Ok(None) => {}
+                        Err(e) => debug!("Expiring session {} failed: {}", session, e),
+                    }
+                }
+            }
+        });
+    }
+
+    /// #Descriptions: on the leader, every `LEADER_BALANCE_INTERVAL`, hand the leadership
+    /// over if it is on a drained node or a node of a lower priority than a connected
+    /// peer, see `LeaderBalance`. The next leader is elected by priority.
+    fn start_leader_balance(ddbb: Arc<Mutex<DDBB>>) {
+        ddbb.lock().unwrap().apply_leader_priority();
+        if ddbb.lock().unwrap().leader_balance.is_none() {
+            return;
+        }
+        spawn_named("ddbb leader balance", async move {
+            let mut check = tokio::time::interval(LEADER_BALANCE_INTERVAL);
+            check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                check.tick().await;
+                let (id, target) = {
+                    let mut ddbb = ddbb.lock().unwrap();
+                    let id = ddbb.node_info.id;
+                    let leader = ddbb.omni.lock().unwrap().get_current_leader();
+                    if leader != Some(id) || ddbb.stepping_down {
+                        continue;
+                    }
+                    let connected = ddbb.simo.lock().unwrap().connected.lock().unwrap().clone();
+                    let connected: Vec<(NodeId, Option<u64>)> = connected
+                        .into_iter()
+                        .map(|peer| (peer, ddbb.leader_priority_of(peer)))
+                        .collect();
+                    let priority = ddbb.leader_priority_of(id);
+                    let now = ddbb.clock.now();
+                    let balance = ddbb.leader_balance.as_mut().unwrap();
+                    match balance.evaluate(now, priority, &connected) {
+                        Some(target) => {
+                            ddbb.events.lock().unwrap().record(ClusterEvent::LeaderRebalanced {
+                                from: id,
+                                towards: target,
+                            });
+                            (id, target)
+                        }
+                        None => continue,
+                    }
+                };
+                info!("Node {} hands the leadership over, node {} is preferred", id, target);
+                if let Err(e) = Self::step_down(ddbb.clone()).await {
+                    error!("Leader balance step down failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// #Descriptions: change setting `name` on every node, once the write is decided.
+    /// Returns the decided index of the write.
+    pub async fn set_config(ddbb: Arc<Mutex<DDBB>>, name: String, value: String) -> Result<u64> {
+        // validated here, so that every node takes the replicated value
+        ddbb.lock().unwrap().dynamic_config.clone().set(&name, &value)?;
+        Self::lin_write(ddbb, dynamic_config::config_key(&name), value.into_bytes()).await
+    }
+
+    pub fn dynamic_config(&self) -> DynamicConfig {
+        self.dynamic_config.clone()
+    }
+
+    /// #Descriptions: create namespace `name` holding at most `max_keys` keys, 0 for
+    /// no limit. Returns false if it already exists.
+    pub async fn create_namespace(
+        ddbb: Arc<Mutex<DDBB>>,
+        name: String,
+        max_keys: u64,
+        token: Option<String>,
+    ) -> Result<bool> {
+        if !namespace::is_valid_name(&name) {
+            return Err(format!("invalid namespace name: {:?}", name).into());
+        }
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::CreateNamespace {
+            opid,
+            name,
+            max_keys,
+            token,
+            created: false,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::CreateNamespace { created, .. } => Ok(created),
+            _ => Err("Create namespace failed".into()),
+        }
+    }
+
+    /// #Descriptions: delete namespace `name` with all its keys. Returns false if it
+    /// does not exist.
+    pub async fn delete_namespace(ddbb: Arc<Mutex<DDBB>>, name: String) -> Result<bool> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::DeleteNamespace {
+            opid,
+            name,
+            deleted: false,
+        };
+        match Self::propose(ddbb, log).await?.log {
+            LogEntry::DeleteNamespace { deleted, .. } => Ok(deleted),
+            _ => Err("Delete namespace failed".into()),
+        }
+    }
+
+    /// #Descriptions: hand the leadership over to another node, e.g. before a planned
+    /// restart. New proposals are queued until another leader is elected, after the
+    /// logs accepted so far are decided, and are then proposed to it. Returns the new
+    /// leader; if none is elected within `STEP_DOWN_TIMEOUT` this node leads again.
+    pub async fn step_down(ddbb: Arc<Mutex<DDBB>>) -> Result<NodeId> {
+        let (id, omni, clock, leader_changed) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            let id = ddbb.node_info.id;
+            let leader = ddbb.omni.lock().unwrap().get_current_leader();
+            if leader != Some(id) || ddbb.stepping_down {
+                return Err(Error::NotLeader);
+            }
+            ddbb.stepping_down = true;
+            ddbb.flush_commit_batch();
+            (id, ddbb.omni.clone(), ddbb.clock.clone(), ddbb.leader_changed.clone())
+        };
+        info!("Node {} stepping down", id);
+
+        // the logs in flight are decided by this leader, instead of synced by the next
+        let accepted_idx = omni.lock().unwrap().get_log_len();
+        let drain_deadline = clock.now() + PROPOSAL_TIMEOUT;
+        loop {
+            let decided_idx = omni.lock().unwrap().get_decided_idx();
+            if decided_idx >= accepted_idx || clock.now() >= drain_deadline {
+                break;
+            }
+            clock.sleep(QUEUED_PROPOSAL_RETRY_PERIOD).await;
+        }
+
+        omni.lock().unwrap().step_down();
+        let deadline = clock.now() + STEP_DOWN_TIMEOUT;
+        let new_leader = loop {
+            // registered before the check, a change in between is not missed
+            let changed = leader_changed.notified();
+            let leader = omni.lock().unwrap().get_current_leader();
+            match leader {
+                Some(leader) if leader != id => break Some(leader),
+                _ if clock.now() >= deadline => break None,
+                _ => {
+                    tokio::select! {
+                        _ = changed => {}
+                        _ = clock.sleep(QUEUED_PROPOSAL_RETRY_PERIOD) => {}
+                    }
+                }
+            }
+        };
+        ddbb.lock().unwrap().stepping_down = false;
+        match new_leader {
+            Some(leader) => {
+                info!("Node {} handed the leadership over to {}", id, leader);
+                Ok(leader)
+            }
+            None => {
+                omni.lock().unwrap().cancel_step_down();
+                Err(Error::Timeout("no other leader elected".to_string()))
+            }
+        }
+    }
+
+    pub fn namespace(&self, name: &str) -> Option<Namespace> {
+        self.state_machine.namespace(name)
+    }
+
+    /// Local, possibly stale statistics of the keys, counted as they are applied.
+    pub fn keyspace(&self) -> KeyspaceStats {
+        self.state_machine.keyspace()
+    }
+
+    /// #Descriptions: shed writes while the node is overloaded, instead of letting
+    /// the queues grow. The error is retryable, unlike the `ReadOnly` of a node low on
+    /// disk space.
+    fn admit_write(&mut self) -> Result<()> {
+        if self.disk.is_read_only() {
+            self.metrics.rejected_read_only += 1;
+            return Err(Error::ReadOnly(format!(
+                "{} bytes free, under the low watermark of {}",
+                self.metrics.disk_free_bytes,
+                self.disk.low_watermark()
+            )));
+        }
+        let outgoing = self.simo.lock().unwrap().outgoing_buffer.lock().unwrap().len();
+        if outgoing >= self.dynamic_config.max_outgoing_messages {
+            self.metrics.shed_outgoing_buffer += 1;
+            return Err(Error::Overloaded("outgoing buffer full, retry later".to_string()));
+        }
+        if self.proposal_callbacks.len() >= self.dynamic_config.max_pending_proposals {
+            self.metrics.shed_pending_proposals += 1;
+            return Err(Error::Overloaded("too many pending proposals, retry later".to_string()));
+        }
+        let decided_idx = self.omni.lock().unwrap().get_decided_idx();
+        let applied_idx = self.wal_store.lock().unwrap().idx;
+        if decided_idx.saturating_sub(applied_idx) >= self.dynamic_config.max_apply_backlog {
+            self.metrics.shed_apply_backlog += 1;
+            return Err(Error::Overloaded("apply backlog too long, retry later".to_string()));
+        }
+        if self.metrics.memory.total() >= self.memory_budget {
+            self.metrics.shed_memory_budget += 1;
+            return Err(Error::Overloaded("memory budget exceeded, retry later".to_string()));
+        }
+        Ok(())
+    }
+
+    /// #Descriptions: persist the state snapshot under `data_dir` at every compaction.
+    pub fn set_data_dir(&mut self, data_dir: String) {
+        self.data_dir = Some(data_dir);
+    }
+
+    /// #Descriptions: flush the writes `omni` batched in its storage every tick.
+    pub fn set_storage_flusher(&mut self, flusher: StorageFlusher) {
+        self.storage_flusher = Some(flusher);
+    }
+
+    /// #Descriptions: report the log of the storage cut short at a corrupted record
+    /// when opened, see `DDBBStorage::corruption`.
+    pub fn report_storage_corruption(&mut self, corruption: &Corruption) {
+        let file = corruption.segment.to_string_lossy().to_string();
+        self.storage_corrupted(file, corruption.position);
+    }
+
+    fn storage_corrupted(&mut self, file: String, truncated_at: u64) {
+        self.metrics.storage_corruptions += 1;
+        self.events
+            .lock()
+            .unwrap()
+            .record(ClusterEvent::StorageCorrupted { file, truncated_at });
+    }
+
+    pub fn decided_idx(&self) -> u64 {
+        self.omni.lock().unwrap().get_decided_idx()
+    }
+
+    pub fn applied_idx(&self) -> u64 {
+        self.wal_store.lock().unwrap().idx
+    }
+
+    pub fn status(&self) -> NodeStatus {
+        let decided_idx = self.decided_idx();
+        let applied_idx = self.applied_idx();
+        let (ballot, learner) = {
+            let omni = self.omni.lock().unwrap();
+            (omni.get_current_leader_ballot(), omni.is_learner())
+        };
+        let role = match ballot {
+            _ if self.observer => NodeRole::Observer,
+            _ if learner => NodeRole::Learner,
+            Some(ballot) if ballot.pid == self.node_info.id => NodeRole::Leader,
+            _ => NodeRole::Follower,
+        };
+        let keyspace = self.state_machine.keyspace();
+        NodeStatus {
+            node_id: self.node_info.id,
+            zone: self.zones.zone().map(|zone| zone.to_string()),
+            build: BuildInfo::current(),
+            role,
+            leader: ballot.map(|ballot| ballot.pid),
+            ballot,
+            decided_idx,
+            applied_idx,
+            apply_lag: decided_idx.saturating_sub(applied_idx),
+            keys: keyspace.keys,
+            key_bytes: keyspace.bytes,
+            peers: self.simo.lock().unwrap().peer_stats(),
+        }
+    }
+
+    /// #Descriptions: write the state machine and the applied index to the data directory,
+    /// replacing the deltas persisted before.
+    pub fn persist_snapshot(&mut self) -> Result<()> {
+        let data_dir = match &self.data_dir {
+            Some(data_dir) => Path::new(data_dir).to_path_buf(),
+            None => return Ok(()),
+        };
+        let applied_idx = self.applied_idx();
+        fs::create_dir_all(&data_dir)?;
+        // streamed to disk, never leaving a half written snapshot behind
+        let mut file = SnapshotFile::create(&data_dir.join(STATE_SNAPSHOT_FILE))?;
+        file.writer().write_chunk(&StateSnapshotHeader { applied_idx })?;
+        self.state_machine.write_snapshot(file.writer())?;
+        file.finish()?;
+        for (_, delta_path) in Self::delta_snapshots(&data_dir)? {
+            fs::remove_file(delta_path)?;
+        }
+        self.delta = Snapshot::default();
+        self.persisted_idx = applied_idx;
+        self.deltas_since_full = 0;
+        Ok(())
+    }
+
+    /// #Descriptions: persist the state at a compaction. Only the logs applied since the
+    /// previous one are written, as a delta, unless `FULL_SNAPSHOT_EVERY` deltas are
+    /// persisted already or there is no complete snapshot to apply them to.
+    fn persist_compaction(&mut self) -> Result<()> {
+        let data_dir = match &self.data_dir {
+            Some(data_dir) => Path::new(data_dir).to_path_buf(),
+            None => return Ok(()),
+        };
+        if !data_dir.join(STATE_SNAPSHOT_FILE).exists()
+            || self.deltas_since_full + 1 >= FULL_SNAPSHOT_EVERY
+        {
+            return self.persist_snapshot();
+        }
+        let applied_idx = self.applied_idx();
+        let delta = DeltaSnapshot {
+            base_idx: self.persisted_idx,
+            applied_idx,
+            delta: self.delta.clone(),
+        };
+        let path = data_dir.join(format!("{}{:020}", STATE_DELTA_PREFIX, applied_idx));
+        // on error the logs stay in the next delta
+        let mut file = SnapshotFile::create(&path)?;
+        file.writer().write_chunk(&delta)?;
+        file.finish()?;
+        self.delta = Snapshot::default();
+        self.persisted_idx = applied_idx;
+        self.deltas_since_full += 1;
+        Ok(())
+    }
+
+    /// #Descriptions: the deltas in `data_dir`, by the applied index they end at.
+    fn delta_snapshots(data_dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+        let mut deltas = Vec::new();
+        for entry in fs::read_dir(data_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let applied_idx = name
+                .strip_prefix(STATE_DELTA_PREFIX)
+                .and_then(|idx| idx.parse::<u64>().ok());
+            if let Some(applied_idx) = applied_idx {
+                deltas.push((applied_idx, entry.path()));
+            }
+        }
+        deltas.sort();
+        Ok(deltas)
+    }
+
+    /// #Descriptions: restore the state machine persisted by `persist_snapshot` and the
+    /// deltas after it, to call before `start`. Returns the restored applied index, 0
+    /// without a snapshot.
+    pub fn restore_snapshot(&mut self) -> Result<u64> {
+        let data_dir = match &self.data_dir {
+            Some(data_dir) => Path::new(data_dir).to_path_buf(),
+            None => return Ok(0),
+        };
+        let path = data_dir.join(STATE_SNAPSHOT_FILE);
+        if !path.exists() {
+            return Ok(0);
+        }
+        let mut reader = SnapshotReader::open(&path)?;
+        let header: StateSnapshotHeader = reader.next_chunk()?.ok_or("empty state snapshot")?;
+        self.state_machine.read_snapshot(&mut reader)?;
+        let mut applied_idx = header.applied_idx;
+        let deltas = Self::delta_snapshots(&data_dir)?;
+        for (i, (delta_idx, delta_path)) in deltas.iter().enumerate() {
+            if *delta_idx <= applied_idx {
+                continue;
+            }
+            let read = SnapshotReader::open(delta_path).and_then(|mut reader| {
+                reader
+                    .next_chunk::<DeltaSnapshot>()?
+                    .ok_or_else(|| Error::from("empty delta"))
+            });
+            let delta = match read {
+                Ok(delta) => delta,
+                Err(e) => {
+                    // truncated there, the logs after are synced from the peers again
+                    error!(
+                        "Delta {:?} is corrupted: {}, restored up to applied index {}, dropping it and the {} after it",
+                        delta_path,
+                        e,
+                        applied_idx,
+                        deltas.len() - i - 1
+                    );
+                    for (_, path) in &deltas[i..] {
+                        fs::remove_file(path)?;
+                    }
+                    self.storage_corrupted(delta_path.to_string_lossy().to_string(), applied_idx);
+                    break;
+                }
+            };
+            if delta.base_idx != applied_idx {
+                // the logs after are synced from the peers again
+                error!(
+                    "Delta {:?} does not follow applied index {}, not restored",
+                    delta_path, applied_idx
+                );
+                break;
+            }
+            for log in delta.delta.logs {
+                self.state_machine.apply(log);
+            }
+            applied_idx = delta.applied_idx;
+            self.deltas_since_full += 1;
+        }
+        self.wal_store.lock().unwrap().idx = applied_idx;
+        self.compacted_idx = applied_idx;
+        self.persisted_idx = applied_idx;
+        self.watches.reset(self.state_machine.revision());
+        let state_machine = &self.state_machine;
+        self.dynamic_config = DynamicConfig::load(|key| state_machine.get(key));
+        info!(
+            "Restored snapshot at applied index {}, with {} deltas",
+            applied_idx, self.deltas_since_full
+        );
+        self.server_events.lock().unwrap().snapshot_installed(SnapshotInstalled {
+            applied_idx,
+            source: data_dir.to_string_lossy().to_string(),
+        });
+        Ok(applied_idx)
+    }
+
+    /// #Descriptions: export the state machine with its applied index to `path`,
+    /// for disaster recovery with `import_snapshot`.
+    pub fn export_snapshot(&self, path: &str) -> Result<SnapshotExport> {
+        let export = SnapshotExport::new(
+            self.node_info.id,
+            self.decided_idx(),
+            self.applied_idx(),
+            self.dynamic_config(),
+            self.state_machine.snapshot()?,
+        );
+        export.write(path)?;
+        info!("Exported snapshot at applied index {} to {}", export.applied_idx, path);
+        Ok(export)
+    }
+
+    /// #Descriptions: start from the state exported by `export_snapshot`, to call
+    /// before `start` on every node of a new cluster. The state is the base of the
+    /// new, empty log, so the applied index starts again from 0.
+    pub fn import_snapshot(&mut self, path: &str) -> Result<SnapshotExport> {
+        if let Some(data_dir) = &self.data_dir {
+            // e.g. restarted with the import still on the command line
+            if Path::new(data_dir).join(STATE_SNAPSHOT_FILE).exists() {
+                return Err(format!("{} already holds a snapshot, not importing", data_dir).into());
+            }
+        }
+        let export = SnapshotExport::read(path)?;
+        self.state_machine.restore(&export.state)?;
+        self.wal_store.lock().unwrap().idx = 0;
+        self.compacted_idx = 0;
+        self.watches.reset(self.state_machine.revision());
+        let state_machine = &self.state_machine;
+        self.dynamic_config = DynamicConfig::load(|key| state_machine.get(key));
+        // a restart must not restore an older snapshot over it
+        self.persist_snapshot()?;
+        self.server_events.lock().unwrap().snapshot_installed(SnapshotInstalled {
+            applied_idx: 0,
+            source: path.to_string(),
+        });
+        info!(
+            "Imported snapshot of node {} at applied index {} from {}",
+            export.node_id, export.applied_idx, path
+        );
+        Ok(export)
+    }
+
+    /// #Descriptions: take a backup to `dir` every `BACKUP_INTERVAL`, keeping the
+    /// latest `retention` ones.
+    pub fn set_backups(&mut self, dir: String, retention: usize) {
+        self.backups = Some(Backups::new(dir, retention));
+    }
+
+    fn backups(&self) -> Result<&Backups> {
+        self.backups
+            .as_ref()
+            .ok_or_else(|| "backups are not enabled, see --backup-dir".into())
+    }
+
+    /// #Descriptions: export the state machine as a new backup, returns its name.
+    pub fn backup(&self) -> Result<String> {
+        let export = SnapshotExport::new(
+            self.node_info.id,
+            self.decided_idx(),
+            self.applied_idx(),
+            self.dynamic_config(),
+            self.state_machine.snapshot()?,
+        );
+        self.backups()?.write(&export, unix_millis())
+    }
+
+    /// #Descriptions: take a backup of every node's state at one point of the log: the
+    /// leader proposes a `Backup` log, and `node`, the leader if `None`, exports its
+    /// state machine as it applies it, at the revision the log pins. Returns the decided
+    /// index, that revision, and the name of the backup if this node took it.
+    pub async fn consistent_backup(
+        ddbb: Arc<Mutex<DDBB>>,
+        node: Option<NodeId>,
+    ) -> Result<(u64, u64, Option<String>)> {
+        let (opid, node) = {
+            let mut ddbb = ddbb.lock().unwrap();
+            let id = ddbb.node_info.id;
+            if ddbb.omni.lock().unwrap().get_current_leader() != Some(id) {
+                return Err(Error::NotLeader);
+            }
+            let node = node.unwrap_or(id);
+            // refused at once rather than once decided
+            if node == id {
+                ddbb.backups()?;
+            }
+            (ddbb.next_opid(), node)
+        };
+        let log = LogEntry::Backup {
+            opid: opid.clone(),
+            node,
+            revision: None,
+        };
+        let decided = Self::propose(ddbb.clone(), log).await?;
+        let revision = match decided.log {
+            LogEntry::Backup {
+                revision: Some(revision),
+                ..
+            } => revision,
+            _ => return Err("Backup failed".into()),
+        };
+        let taken = ddbb.lock().unwrap().backups_taken.remove(&opid);
+        match taken {
+            Some(Ok(name)) => Ok((decided.idx, revision, Some(name))),
+            Some(Err(e)) => Err(e),
+            None => Ok((decided.idx, revision, None)),
+        }
+    }
+
+    /// #Descriptions: export the state machine as a backup while applying the `Backup`
+    /// log of `opid`, recording it in the event log.
+    fn take_pinned_backup(&mut self, opid: (String, u64), applied_idx: u64, revision: u64) {
+        let taken = self.backup();
+        match &taken {
+            Ok(name) => {
+                info!("Backup {} taken at revision {}", name, revision);
+                self.events.lock().unwrap().record(ClusterEvent::BackupTaken {
+                    name: name.clone(),
+                    applied_idx,
+                    revision,
+                });
+            }
+            Err(e) => error!("Backup at revision {} failed: {:?}", revision, e),
+        }
+        // only a proposer on this node waits for it
+        if opid.0 == self.node_info.addr {
+            self.backups_taken.insert(opid, taken);
+        }
+    }
+
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        self.backups()?.list()
+    }
+
+    /// #Descriptions: check backup `name` and stage it to be imported at the next
+    /// start of the node, see `apply_staged_restore`.
+    pub fn stage_restore(&self, name: &str) -> Result<SnapshotExport> {
+        let export = self.backups()?.read(name)?;
+        let data_dir = self.data_dir.as_ref().ok_or("no data dir to stage the restore in")?;
+        fs::create_dir_all(data_dir)?;
+        fs::write(
+            Path::new(data_dir).join(STAGED_RESTORE_FILE),
+            serde_json::to_vec(&export)?,
+        )?;
+        info!("Staged backup {} to restore at the next start", name);
+        Ok(export)
+    }
+
+    /// #Descriptions: import the backup staged by `stage_restore` in place of the
+    /// persisted state, to call before `start`. Returns whether one was staged.
+    pub fn apply_staged_restore(&mut self) -> Result<bool> {
+        let data_dir = match &self.data_dir {
+            Some(data_dir) => Path::new(data_dir).to_path_buf(),
+            None => return Ok(false),
+        };
+        let staged = data_dir.join(STAGED_RESTORE_FILE);
+        if !staged.exists() {
+            return Ok(false);
+        }
+        let snapshot = data_dir.join(STATE_SNAPSHOT_FILE);
+        if snapshot.exists() {
+            fs::remove_file(snapshot)?;
+        }
+        self.import_snapshot(staged.to_str().ok_or("invalid data dir")?)?;
+        fs::remove_file(staged)?;
+        Ok(true)
+    }
+
+    /// #Descriptions: how far this node is behind the leader while syncing.
+    pub fn catch_up_progress(&self) -> CatchUpProgress {
+        self.catch_up.lock().unwrap().progress()
+    }
+
+    pub fn is_caught_up(&self) -> bool {
+        self.catch_up.lock().unwrap().is_caught_up()
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = self.metrics.clone();
+        metrics.memory_budget = self.memory_budget;
+        metrics.watches = self.watches.stats();
+        if let Some(connections) = &self.client_connections {
+            metrics.client_connections = connections.stats();
+        }
+        metrics.peer_connections = self.simo.lock().unwrap().inbound_stats();
+        metrics
+    }
+
+    /// #Descriptions: report the connections of the client listener with the metrics.
+    pub fn track_client_connections(&mut self, connections: ClientConnections) {
+        self.client_connections = Some(connections);
+    }
+
+    /// #Descriptions: shed writes while the queues hold more than `budget` bytes.
+    pub fn set_memory_budget(&mut self, budget: u64) {
+        self.memory_budget = budget;
+    }
+
+    /// #Descriptions: reject writes while the data directory has less than `low_watermark`
+    /// bytes free, 0 for never.
+    pub fn set_disk_low_watermark(&mut self, low_watermark: u64) {
+        self.disk = DiskWatch::new(low_watermark);
+    }
+
+    /// #Descriptions: queue at most `capacity` writes for a watch whose client reads
+    /// them slower than they are applied, treating it as `policy` tells beyond.
+    pub fn set_slow_watchers(&mut self, capacity: usize, policy: SlowWatcherPolicy) {
+        self.watches.set_slow_watchers(capacity, policy);
+    }
+
+    /// #Descriptions: accept the faults injected through the admin API, e.g. to rehearse
+    /// failovers on a test cluster.
+    pub fn set_fault_injection(&mut self, fault_injection: bool) {
+        self.fault_injection = fault_injection;
+    }
+
+    /// #Descriptions: delay the msgs this node sends to `peer` by `delay_ms` give or take
+    /// `jitter_ms`, 0 and 0 to stop. Fails unless fault injection is on.
+    pub fn set_link_latency(&self, peer: NodeId, delay_ms: u64, jitter_ms: u64) -> Result<()> {
+        if !self.fault_injection {
+            return Err(Error::Unauthorized(
+                "fault injection is off, start the node with --fault-injection".to_string(),
+            ));
+        }
+        if !self.peers.lock().unwrap().contains_key(&peer) {
+            return Err(format!("not a peer: {}", peer).into());
+        }
+        let latency = LinkLatency {
+            delay_ms,
+            jitter_ms,
+        };
+        self.simo.lock().unwrap().set_link_latency(peer, latency);
+        Ok(())
+    }
+
+    /// #Descriptions: proposals that took longer than the slow-log threshold, oldest first.
+    pub fn slow_log(&self) -> Vec<SlowLogEntry> {
+        self.slow_log.entries()
+    }
+
+    pub fn set_slow_log_threshold(&mut self, threshold: Duration) {
+        self.slow_log.set_threshold(threshold);
+    }
+
+    /// #Descriptions: the latest cluster events seen by this node, oldest first.
+    pub fn events(&self) -> Vec<EventLogEntry> {
+        self.events.lock().unwrap().entries()
+    }
+
+    /// #Descriptions: the leader changes, reconfigurations and snapshots installed to
+    /// subscribe to, see `ServerEvents`.
+    pub fn server_events(&self) -> SharedServerEvents {
+        self.server_events.clone()
+    }
+
+    /// #Descriptions: also write every cluster event as a json log line.
+    pub fn set_log_events(&mut self, log_events: bool) {
+        self.events.lock().unwrap().set_log_lines(log_events);
+    }
+
+    /// #Descriptions: with `forward_to_leader` off, a follower answers the commands
+    /// served by the leader with a `NotLeaderEntry` instead of forwarding them.
+    pub fn set_forward_to_leader(&mut self, forward_to_leader: bool) {
+        self.forward_to_leader = forward_to_leader;
+    }
+
+    /// #Descriptions: time the elections, ttls and reconnects of the node on `clock`
+    /// instead of the system clock, e.g. a `MockClock` in tests. Set before starting.
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.simo.lock().unwrap().set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    /// #Descriptions: the clock timing the node, see `set_clock`.
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    pub fn set_peer_client_addrs(&mut self, peer_client_addrs: HashMap<NodeId, String>) {
+        self.peer_client_addrs = peer_client_addrs;
+    }
+
+    /// #Descriptions: the zones of the nodes, the priority of this one in the elections
+    /// is set by its omnipaxos config, see `Zones::leader_priority`.
+    pub fn set_zones(&mut self, zones: Zones) {
+        self.zones = zones;
+    }
+
+    pub fn zones(&self) -> &Zones {
+        &self.zones
+    }
+
+    /// #Descriptions: have the leader hand the leadership over when a node it should
+    /// go to is up, see `LeaderBalance` and the `leader_priorities` and `drained_nodes`
+    /// settings.
+    pub fn set_leader_balance(&mut self, enabled: bool) {
+        self.leader_balance = Some(LeaderBalance::new(LEADER_BALANCE_SETTLE)).filter(|_| enabled);
+    }
+
+    /// #Descriptions: the ballot priority of `node` by the settings and its zone, `None`
+    /// once drained.
+    fn leader_priority_of(&self, node: NodeId) -> Option<u64> {
+        if self.dynamic_config.drained_nodes.contains(&node) {
+            return None;
+        }
+        let zone_priority = if node == self.node_info.id {
+            self.zones.leader_priority()
+        } else {
+            self.zones.peer_priority(node)
+        };
+        Some(self.dynamic_config.leader_priority(node, zone_priority))
+    }
+
+    /// #Descriptions: run the elections with the priority of this node by the settings,
+    /// 0 once drained, so that the preferred nodes win them.
+    fn apply_leader_priority(&self) {
+        let priority = self.leader_priority_of(self.node_info.id).unwrap_or(0);
+        self.omni.lock().unwrap().set_priority(priority);
+    }
+
+    /// #Descriptions: serve only stale reads and watches, e.g. to the clients of a
+    /// remote region; the writes and linearizable reads are answered with a
+    /// `NotLeaderEntry`, forwarding or not. The node must be a learner, so that it
+    /// never joins a quorum.
+    pub fn set_observer(&mut self, observer: bool) -> Result<()> {
+        if observer && !self.omni.lock().unwrap().is_learner() {
+            return Err("an observer must be one of the learners".into());
+        }
+        self.observer = observer;
+        Ok(())
+    }
+
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    /// #Descriptions: the peers, with their client address if it is known.
+    pub fn peer_client_addrs(&self) -> Vec<(NodeId, Option<String>)> {
+        let mut peers: Vec<NodeId> = self.peers.lock().unwrap().keys().copied().collect();
+        peers.sort();
+        peers
+            .into_iter()
+            .map(|peer_id| (peer_id, self.peer_client_addrs.get(&peer_id).cloned()))
+            .collect()
+    }
+
+    /// #Descriptions: the leader to send a client to, `None` if this node serves the
+    /// commands of the leader itself, forwarding them or being the leader.
+    pub fn not_leader(&self) -> Option<NotLeaderEntry> {
+        if self.forward_to_leader && !self.observer {
+            return None;
+        }
+        match self.omni.lock().unwrap().get_current_leader_ballot() {
+            Some(ballot) if ballot.pid == self.node_info.id => None,
+            Some(ballot) => Some(NotLeaderEntry {
+                leader_id: Some(ballot.pid),
+                leader_addr: self.peer_client_addrs.get(&ballot.pid).cloned(),
+                ballot: ballot.n as u64,
+            }),
+            None => Some(NotLeaderEntry {
+                leader_id: None,
+                leader_addr: None,
+                ballot: 0,
+            }),
+        }
+    }
 
-impl KVStore {
-    pub fn new() -> Self {
-        Self {
-            store: HashMap::new(),
+    // temp: for debug
+    pub fn show_wal_store(&self) {
+        info!("Wal of {:?}:", self.node_info.id);
+        for log in self.wal_store.lock().unwrap().store.iter() {
+            info!("\t{:?}", log);
         }
+        info!("\tstate machine: {:?}", self.state_machine);
     }
 
-    pub fn put(&mut self, key: String, value: Vec<u8>) {
-        self.store.insert(key, value);
+    fn apply_decided(&mut self, idx: u64, log: LogEntry, decided_at: Instant) {
+        // already in the state machine, e.g. replayed after restoring a snapshot
+        if idx < self.applied_idx() {
+            debug!("Skip applied log {}: {:?}", idx, log);
+            return;
+        }
+        self.wal_store.lock().unwrap().idx = idx + 1;
+        let revision = self.state_machine.revision();
+        let prev = self.prev_values(&log);
+        // a plain write carries no result, its proposer is told why it was dropped
+        let refusal = match &log {
+            LogEntry::LINWrite { key, .. } => self.state_machine.refusal(key),
+            _ => None,
+        };
+        let applied = self.state_machine.apply(log);
+        self.wal_store.lock().unwrap().append(applied.clone());
+        if self.data_dir.is_some() {
+            self.delta.merge(Snapshot::create(std::slice::from_ref(&applied)));
+        }
+        self.watch_config(&applied);
+        self.publish_write(revision, prev, &applied);
+        self.publish_leader(&applied);
+        if let LogEntry::CompactRevision { compacted, .. } = applied {
+            self.watches.compact(compacted);
+        }
+        if let LogEntry::Backup {
+            opid,
+            node,
+            revision: Some(revision),
+        } = &applied
+        {
+            if *node == self.node_info.id {
+                self.take_pinned_backup(opid.clone(), idx + 1, *revision);
+            }
+        }
+        if let LogEntry::Compact = applied {
+            self.compacted_idx = idx + 1;
+            self.snapshot();
+            if let Err(e) = self.persist_compaction() {
+                error!("Persist snapshot failed: {:?}", e);
+            }
+        } else {
+            self.maybe_compact(idx + 1);
+        }
+        self.notify_decided(idx, applied, refusal, decided_at);
     }
 
-    pub fn get(&self, key: String) -> Option<&Vec<u8>> {
-        self.store.get(&key)
+    /// #Descriptions: take a setting written to the log, from the state machine so that
+    /// a write dropped by it does not change the setting.
+    fn watch_config(&mut self, applied: &LogEntry) {
+        let key = match written_key(applied) {
+            Some(key) => key,
+            None => return,
+        };
+        if let Some(name) = dynamic_config::setting_of(key) {
+            if let Some(value) = self.state_machine.get(key) {
+                self.dynamic_config.reload(name, &value);
+                info!("Setting {} changed: {:?}", name, self.dynamic_config);
+                if name == "leader_priorities" || name == "drained_nodes" {
+                    self.apply_leader_priority();
+                }
+            }
+        }
     }
-}
 
-impl DDBB {
-    pub fn new(
-        id: NodeId,
-        self_addr: String,
-        peers: HashMap<NodeId, String>,
-        simo: OmniSIMO,
-        omni: OmniPaxosInstance,
-    ) -> Self {
-        let mut peers = Arc::new(Mutex::new(peers));
-        let mut simo = Arc::new(Mutex::new(simo));
-        let mut omni = Arc::new(Mutex::new(omni));
-        DDBB {
-            node_info: NodeInfo {
-                id,
-                addr: self_addr,
-            },
-            peers,
-            simo,
-            omni,
-            wal_store: Arc::new(Mutex::new(WALStore::new())) ,
-            kv_store: KVStore::new(),
-            timestamp: 0,
+    /// #Descriptions: the values of the keys `log` writes, before it is applied.
+    fn prev_values(&self, log: &LogEntry) -> HashMap<String, Option<Vec<u8>>> {
+        // the keys deleted are only known once applied, take all it may delete
+        if let LogEntry::DeleteTree { path, .. } = log {
+            let mut prev: HashMap<String, Option<Vec<u8>>> = self
+                .state_machine
+                .scan(&tree_prefix(path))
+                .into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect();
+            prev.insert(path.clone(), self.state_machine.get(path));
+            return prev;
+        }
+        if let LogEntry::DeletePrefix { prefix, limit, .. } = log {
+            return self
+                .state_machine
+                .scan(prefix)
+                .into_iter()
+                .filter(|(key, _)| in_bulk_delete(prefix, key))
+                .take(*limit as usize)
+                .map(|(key, value)| (key, Some(value)))
+                .collect();
         }
+        if let LogEntry::CloseSession { session, .. } = log {
+            let keys = self
+                .state_machine
+                .session(*session)
+                .map(|session| session.keys)
+                .unwrap_or_default();
+            return keys
+                .into_iter()
+                .map(|key| {
+                    let value = self.state_machine.get(&key);
+                    (key, value)
+                })
+                .collect();
+        }
+        let mut prev = HashMap::new();
+        let keys: Vec<&str> = match log {
+            LogEntry::BulkSet { pairs, .. } => pairs.iter().map(|(key, _)| key.as_str()).collect(),
+            log => written_key(log).into_iter().collect(),
+        };
+        for key in keys {
+            prev.entry(key.to_string())
+                .or_insert_with(|| self.state_machine.get(key));
+        }
+        prev
     }
 
-    pub async fn start(ddbb: Arc<Mutex<DDBB>>) -> Result<()> {
-        let mut simo: Arc<Mutex<OmniSIMO>>;
-        let mut op_server: OmniPaxosServer;
+    /// #Descriptions: stream a write to the watches, if the state machine applied it,
+    /// i.e. moved on from `revision`. `prev` holds the values it replaced.
+    fn publish_write(
+        &mut self,
+        revision: u64,
+        mut prev: HashMap<String, Option<Vec<u8>>>,
+        applied: &LogEntry,
+    ) {
+        let new_revision = self.state_machine.revision();
+        if new_revision == revision {
+            return;
+        }
+        if let LogEntry::BulkSet { pairs, .. } = applied {
+            // one revision per pair written, in order
+            for (i, (key, value)) in pairs.iter().enumerate() {
+                let replaced = prev.insert(key.clone(), Some(value.clone())).flatten();
+                let event = WatchEventEntry {
+                    revision: revision + 1 + i as u64,
+                    key: key.clone(),
+                    value: Bytes::from(value.clone()),
+                    deleted: false,
+                };
+                self.watches.publish(event, replaced);
+            }
+            return;
+        }
+        if let LogEntry::DeleteTree { deleted, .. }
+        | LogEntry::DeletePrefix { deleted, .. }
+        | LogEntry::CloseSession { deleted, .. } = applied
         {
-            simo = ddbb.lock().unwrap().simo.clone();
-            let omni = ddbb.lock().unwrap().omni.clone();
-            op_server = OmniPaxosServer {
-                omni_paxos_instance: omni.clone(),
-                omni_simo: simo.clone(),
-            };
+            // one revision per key deleted, in order
+            for (i, key) in deleted.iter().enumerate() {
+                let event = WatchEventEntry {
+                    revision: revision + 1 + i as u64,
+                    key: key.clone(),
+                    value: Bytes::new(),
+                    deleted: true,
+                };
+                self.watches.publish(event, prev.remove(key).flatten());
+            }
+            return;
+        }
+        if let Some(key) = written_key(applied) {
+            if let Some(value) = self.state_machine.get(key) {
+                let event = WatchEventEntry {
+                    revision: new_revision,
+                    key: key.to_string(),
+                    value: Bytes::from(value),
+                    deleted: false,
+                };
+                self.watches.publish(event, prev.remove(key).flatten());
+            }
+        }
+    }
 
-            // start log retrieval
-            tokio::spawn(async move {
-                loop {
-                    ddbb.lock().unwrap().retrieve_logs_from_omni();
-                    sleep(Duration::from_millis(LOG_RETRIEVE_INTERVAL)).await;
-                }
-            });
+    /// #Descriptions: tell the campaigns on this node the leader of the election of an
+    /// applied `Campaign` or `Resign`.
+    fn publish_leader(&mut self, applied: &LogEntry) {
+        match applied {
+            LogEntry::Campaign {
+                election, leader, ..
+            }
+            | LogEntry::Resign {
+                election, leader, ..
+            } => self.leaders.publish(election, leader.clone()),
+            _ => {}
         }
+    }
 
-        Self::start_simo(simo).await?;
-        op_server.run().await;
-        return Ok(());
+    /// Revision of the latest write applied, to pin for reads at a revision.
+    pub fn revision(&self) -> u64 {
+        self.state_machine.revision()
     }
 
-    async fn start_simo(simo: Arc<Mutex<OmniSIMO>>) -> Result<()> {
-        let omni_simo_copy1 = simo.clone();
-        let omni_simo_copy2 = simo.clone();
-        OmniSIMO::start_incoming_listener(omni_simo_copy1).await?;
-        OmniSIMO::start_sender(omni_simo_copy2).await?;
-        return Ok(());
+    /// #Descriptions: local, possibly stale read of the keys under `prefix`, but the
+    /// reserved ones, all starting with `\u{0}`.
+    pub fn scan(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        let mut pairs = self.state_machine.scan(prefix);
+        pairs.retain(|(key, _)| !key.starts_with('\u{0}'));
+        pairs
     }
 
-    pub fn add_ts(&mut self) {
-        self.timestamp += 1;
+    /// #Descriptions: local, possibly stale listing of the names right under `path`.
+    pub fn list_children(&self, path: &str) -> Vec<String> {
+        self.state_machine.list_children(path)
     }
 
-    fn find_log_by_opid(&self, addr: String, ts: u64) -> Option<LogEntry> {
-        let mut opid_temp: (String, u64);
-        let mut ts_temp: u64;
-        for log in self.wal_store.lock().unwrap().store.iter() {
-            match log.clone() {
-                LogEntry::LINRead { opid, key, value } => opid_temp = opid,
-                LogEntry::LINWrite { opid, key, value } => opid_temp = opid,
-                _ => break,
-            };
-            if opid_temp.0.eq(&addr) && opid_temp.1 == ts {
-                return Some(log.clone());
-            }
-        }
-        return None;
+    /// #Descriptions: `key` as it was at `revision`, from the retained writes.
+    pub fn get_at(&self, key: &str, revision: u64) -> Result<Option<Vec<u8>>> {
+        self.watches
+            .value_at(key, revision, self.state_machine.get(key))
     }
 
-    pub fn set(&mut self, key: String, value: Vec<u8>) -> Result<()> {
-        self.kv_store.store.insert(key.clone(), value.clone());
-        let log = LogEntry::SetValue { key, value };
-        self.put_log_into_omni(log)
+    /// #Descriptions: the keys under `prefix` as they were at `revision`.
+    pub fn scan_at(&self, prefix: &str, revision: u64) -> Result<Vec<(String, Vec<u8>)>> {
+        self.watches.scan_at(prefix, revision, self.scan(prefix))
     }
 
-    pub fn get(&self, key: String) -> Option<Vec<u8>> {
-        if let Some(value) = self.kv_store.get(key) {
-            return Some(value.clone());
-        } else {
-            return None;
+    /// #Descriptions: discard the history of the writes up to `revision` on every node,
+    /// through the log, so that they all refuse the same older reads. Returns the
+    /// decided index and the revision compacted up to, at most the latest one.
+    pub async fn compact_revision(ddbb: Arc<Mutex<DDBB>>, revision: u64) -> Result<(u64, u64)> {
+        let opid = ddbb.lock().unwrap().next_opid();
+        let log = LogEntry::CompactRevision {
+            opid,
+            revision,
+            compacted: 0,
+        };
+        let decided = Self::propose(ddbb, log).await?;
+        match decided.log {
+            LogEntry::CompactRevision { compacted, .. } => Ok((decided.idx, compacted)),
+            _ => Err("Compact revision failed".into()),
         }
     }
 
-    pub async fn lin_write(ddbb: Arc<Mutex<DDBB>>, key: String, value: Vec<u8>) -> Result<()> {
-        let ts: u64;
-        let self_addr: String;
-        {
-            let mut ddbb = ddbb.lock().unwrap();
-            ddbb.add_ts();
-            ts = ddbb.timestamp;
-            self_addr = ddbb.node_info.addr.clone()
+    /// #Descriptions: watch the keys under `prefix`, resuming after `after_revision`.
+    pub fn watch(
+        &mut self,
+        prefix: &str,
+        after_revision: u64,
+        delivery: WatchDelivery,
+    ) -> Result<Watch> {
+        self.watches.watch(prefix, after_revision, delivery)
+    }
+
+    /// #Descriptions: the leader proposes a compaction every `compact_every` applied logs.
+    fn maybe_compact(&mut self, applied_idx: u64) {
+        let compact_every = self.dynamic_config.compact_every;
+        if compact_every == 0 || applied_idx < self.compacted_idx + compact_every {
+            return;
+        }
+        let leader = self.omni.lock().unwrap().get_current_leader();
+        if leader == Some(self.node_info.id) {
+            // not again until this one is applied
+            self.compacted_idx = applied_idx;
+            self.compact();
         }
+    }
 
-        let log = LogEntry::LINWrite {
-            opid: (self_addr.clone(), ts),
-            key,
-            value,
+    /// #Descriptions: answer the proposer of `log` waiting on this node, with `refusal`
+    /// if the state machine dropped it.
+    fn notify_decided(
+        &mut self,
+        idx: u64,
+        log: LogEntry,
+        refusal: Option<Error>,
+        decided_at: Instant,
+    ) {
+        let opid = match log.opid() {
+            Some(opid) => opid.clone(),
+            None => return,
         };
-        ddbb.lock().unwrap().put_log_into_omni(log.clone());
-        sleep(WAIT_DECIDED_TIMEOUT).await;
-        let mut times: u64 = 0;
-        loop {
-            if let Some(_) = ddbb.lock().unwrap().find_log_by_opid(self_addr.clone(), ts) {
-                // debug!("tried times: {:?}", times);
-                return Ok(());
-            };
-            times += 1;
-            if times >= LIN_WRITE_TIMES_OUT {
-                return Err("Lin write failed".into());
+        if let Some(pending) = self.proposal_callbacks.remove(&opid) {
+            let applied_at = Instant::now();
+            let total = applied_at - pending.proposed_at;
+            if self.slow_log.is_slow(total) {
+                self.slow_log.record(SlowLogEntry {
+                    opid,
+                    kind: log_kind(&log).to_string(),
+                    idx,
+                    queue_us: (pending.appended_at - pending.proposed_at).as_micros() as u64,
+                    replication_us: decided_at
+                        .saturating_duration_since(pending.appended_at)
+                        .as_micros() as u64,
+                    apply_us: applied_at
+                        .saturating_duration_since(decided_at)
+                        .as_micros() as u64,
+                    total_us: total.as_micros() as u64,
+                });
             }
-
-            sleep(Duration::from_millis(LOG_RETRIEVE_INTERVAL)).await;
+            // the proposer may have timed out already
+            let _ = pending.callback.send(match refusal {
+                Some(e) => Err(e),
+                None => Ok(Decided { idx, log }),
+            });
         }
     }
 
-    pub async fn lin_read(ddbb: Arc<Mutex<DDBB>>, key: String) -> Result<Option<Vec<u8>>> {
-        let ts: u64;
-        let self_addr: String;
-        {
-            let mut ddbb = ddbb.lock().unwrap();
-            ddbb.add_ts();
-            ts = ddbb.timestamp;
-            self_addr = ddbb.node_info.addr.clone()
+    /// ballot of the leader accepting proposals, `None` while none is established or
+    /// this node steps down
+    fn accepting_ballot(&self) -> Option<Ballot> {
+        if self.stepping_down {
+            return None;
         }
+        let omni = self.omni.lock().unwrap();
+        omni.get_current_leader_ballot()
+            .filter(|_| omni.is_accepting())
+    }
 
-        let log = LogEntry::LINRead {
-            opid: (self_addr.clone(), ts),
-            key,
-            value: None,
-        };
-        ddbb.lock().unwrap().put_log_into_omni(log.clone());
-        sleep(WAIT_DECIDED_TIMEOUT).await;
-        let mut times: u64 = 0;
-        loop {
-            {
-                let ddbb = ddbb.lock().unwrap();
-                if let Some(log) = ddbb.find_log_by_opid(self_addr.clone(), ts) {
-                    // debug!("tried times: {:?}", times);
-                    if let LogEntry::LINRead { opid, key, value } = log {
-                        return Ok(value);
-                    }
-                };
+    /// #Descriptions: propose the queued proposals once a leader is established, and
+    /// drop the ones nobody waits for anymore.
+    fn propose_queued(&mut self) {
+        if self.proposal_queue.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        self.metrics.expired_queued_proposals += self.proposal_queue.expire(now) as u64;
+        let ballot = self.accepting_ballot();
+        if ballot.is_none() {
+            return;
+        }
+        for log in self.proposal_queue.take(now) {
+            let pending = log
+                .opid()
+                .and_then(|opid| self.proposal_callbacks.get_mut(opid));
+            if let Some(pending) = pending {
+                pending.ballot = ballot;
+                pending.appended_at = now;
             }
-            times += 1;
-            if times >= LIN_WRITE_TIMES_OUT {
-                return Err("Lin read failed".into());
+            if let Err(e) = self.put_log_into_omni(log) {
+                error!("Queued proposal failed: {:?}", e);
             }
-
-            sleep(Duration::from_millis(LOG_RETRIEVE_INTERVAL)).await;
         }
     }
 
-    // temp: for debug
-    pub fn show_wal_store(&self) {
-        info!("Wal of {:?}:", self.node_info.id);
-        for log in self.wal_store.lock().unwrap().store.iter() {
-            info!("\t{:?}", log);
+    /// #Descriptions: propose again the proposals in flight to an older leader once a
+    /// new one is established, the old one may have died before deciding them. The
+    /// state machine applies a log decided twice only once.
+    fn repropose_in_flight(&mut self) {
+        let ballot = self.accepting_ballot();
+        if ballot.is_none() {
+            return;
+        }
+        if ballot == self.proposal_ballot {
+            return;
+        }
+        self.proposal_ballot = ballot;
+        let mut stale: Vec<((String, u64), LogEntry)> = self
+            .proposal_callbacks
+            .iter_mut()
+            .filter(|(_, pending)| pending.ballot.is_some() && pending.ballot < ballot)
+            .map(|(opid, pending)| {
+                pending.ballot = ballot;
+                (opid.clone(), pending.log.clone())
+            })
+            .collect();
+        // in the order they were proposed
+        stale.sort_by_key(|(opid, _)| opid.1);
+        for (_, log) in stale {
+            self.metrics.reproposed_proposals += 1;
+            if let Err(e) = self.put_log_into_omni(log) {
+                error!("Proposing again failed: {:?}", e);
+            }
         }
-        info!("\tkv store: {:?}", self.kv_store);
     }
 
-    fn retrieve_logs_from_omni(&mut self) {
-        let committed_ents = self
-            .omni
-            .lock()
-            .unwrap()
-            .read_decided_suffix(self.wal_store.lock().unwrap().diceded());
-        if let Some(entrys) = committed_ents {
-            for entry in entrys {
-                self.wal_store.lock().unwrap().idx += 1;
-                match entry {
-                    OmniLogEntry::Decided(log) => match log.clone() {
-                        LogEntry::SetValue { key, value } => {
-                            self.wal_store.lock().unwrap().append(log.clone());
-                            self.kv_store.store.insert(key.clone(), value.clone());
-                        }
-                        LogEntry::LINRead { key, opid, value } => {
-                            let value = self.get(key.clone());
-                            self.wal_store.lock().unwrap()
-                                .append(LogEntry::LINRead { opid, key, value });
-                        }
-                        LogEntry::LINWrite { opid, key, value } => {
-                            self.kv_store.store.insert(key, value);
-                            self.wal_store.lock().unwrap().append(log.clone());
-                        }
-                        LogEntry::Compact => {
-                            self.wal_store.lock().unwrap().append(log.clone());
-                            self.snapshot();
-                        }
-                    },
-                    _ => {}
-                }
+    /// whether `log` waits for the group commit window instead of being proposed at
+    /// once, only writes on the leader do
+    fn holds_for_group_commit(&self, log: &LogEntry, ballot: Option<Ballot>) -> bool {
+        self.dynamic_config.group_commit_window_ms > 0
+            && ballot.map_or(false, |ballot| ballot.pid == self.node_info.id)
+            && !matches!(log, LogEntry::LINRead { .. } | LogEntry::LINStat { .. })
+    }
+
+    /// #Descriptions: propose the writes held for the group commit window together,
+    /// under one lock of omnipaxos, so that they go to each follower in one
+    /// `AcceptDecide` and to the storage in one flush.
+    fn flush_commit_batch(&mut self) {
+        let opened = match self.commit_batch_opened.take() {
+            Some(opened) => opened,
+            None => return,
+        };
+        let logs = std::mem::take(&mut self.commit_batch);
+        self.metrics.group_commits += 1;
+        self.metrics.group_commit_logs += logs.len() as u64;
+        self.metrics.group_commit_max_logs = self.metrics.group_commit_max_logs.max(logs.len() as u64);
+        self.metrics.group_commit_wait_us += opened.elapsed().as_micros() as u64;
+        let mut omni = self.omni.lock().unwrap();
+        for log in logs {
+            if omni.append(log).is_err() {
+                error!("Group commit proposal failed");
             }
         }
     }
@@ -321,7 +2449,29 @@ impl DDBB {
                         }
                     }
                 }
-                LogEntry::LINRead { opid, key, value } => {
+                // semaphore, revision, namespace, election and session state is in the state
+                // machine snapshot, these go at compaction like the reads
+                LogEntry::LINRead { .. }
+                | LogEntry::LINStat { .. }
+                | LogEntry::SemAcquire { .. }
+                | LogEntry::SemRelease { .. }
+                | LogEntry::PutIfRevision { .. }
+                | LogEntry::CreateNamespace { .. }
+                | LogEntry::DeleteNamespace { .. }
+                | LogEntry::BulkSet { .. }
+                | LogEntry::DeleteTree { .. }
+                | LogEntry::DeletePrefix { .. }
+                | LogEntry::Campaign { .. }
+                | LogEntry::Resign { .. }
+                | LogEntry::FencedWrite { .. }
+                | LogEntry::ValueChunk { .. }
+                | LogEntry::ChunkedSet { .. }
+                | LogEntry::OpenSession { .. }
+                | LogEntry::KeepAlive { .. }
+                | LogEntry::EphemeralSet { .. }
+                | LogEntry::CloseSession { .. }
+                | LogEntry::CompactRevision { .. }
+                | LogEntry::Backup { .. } => {
                     if befor_first_compact && befor_second_compact {
                         new_log_vec.insert(new_log_vec.len(), log.clone());
                     } else if !befor_first_compact && befor_second_compact {
@@ -370,6 +2520,639 @@ impl DDBB {
     }
 }
 
+/// The key a log writes, if it is a write.
+fn written_key(log: &LogEntry) -> Option<&str> {
+    match log {
+        LogEntry::SetValue { key, .. }
+        | LogEntry::LINWrite { key, .. }
+        | LogEntry::PutIfRevision { key, .. }
+        | LogEntry::FencedWrite { key, .. }
+        | LogEntry::ChunkedSet { key, .. }
+        | LogEntry::EphemeralSet { key, .. } => Some(key),
+        _ => None,
+    }
+}
+
+/// Whether `log` keeps a session, permit or leadership alive or gives it back: never
+/// shed nor refused while read only, else they would all expire when the node is loaded.
+fn keeps_or_releases_lease(log: &LogEntry) -> bool {
+    matches!(
+        log,
+        LogEntry::KeepAlive { .. }
+            | LogEntry::CloseSession { .. }
+            | LogEntry::SemRelease { .. }
+            | LogEntry::Campaign { .. }
+            | LogEntry::Resign { .. }
+    )
+}
+
+/// Only `lin_write` proposes a value over `MAX_LOG_VALUE_SIZE`, in chunks; the other
+/// writes carry it whole in their log, which must fit in a frame between the nodes.
+fn check_value_size(value: &[u8]) -> Result<()> {
+    if value.len() > MAX_LOG_VALUE_SIZE {
+        return Err(Error::Other(format!(
+            "value of {} bytes, at most {} for this write",
+            value.len(),
+            MAX_LOG_VALUE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
 mod test {
     use super::*;
+    use omnipaxos_core::messages::sequence_paxos::{AcceptSync, PaxosMessage, PaxosMsg, Prepare};
+    use omnipaxos_core::messages::Message;
+    use omnipaxos_core::omni_paxos::OmniPaxosConfig;
+    use crate::storage::DDBBStorage;
+    use ddbb_libs::clock::MockClock;
+
+    fn test_ddbb(data_dir: &str) -> DDBB {
+        let op_config = OmniPaxosConfig {
+            pid: 1,
+            configuration_id: 1,
+            peers: vec![2, 3],
+            ..Default::default()
+        };
+        let simo = OmniSIMO::new("127.0.0.1:6650".to_string(), HashMap::new());
+        let mut ddbb = DDBB::new(
+            1,
+            "127.0.0.1:6650".to_string(),
+            HashMap::new(),
+            simo,
+            op_config.build(DDBBStorage::default()),
+        );
+        ddbb.set_data_dir(data_dir.to_string());
+        ddbb
+    }
+
+    fn cas(ts: u64, value: &str, expected_mod_rev: u64) -> LogEntry {
+        LogEntry::PutIfRevision {
+            opid: ("127.0.0.1:6650".to_string(), ts),
+            key: "k1".to_string(),
+            value: Vec::from(value),
+            expected_mod_rev,
+            succeeded: false,
+            mod_rev: 0,
+        }
+    }
+
+    fn last_applied(ddbb: &DDBB) -> LogEntry {
+        ddbb.wal_store.lock().unwrap().store[0].clone()
+    }
+
+    #[test]
+    fn test_replay_after_restart_is_idempotent() {
+        let data_dir = std::env::temp_dir().join(format!("ddbb_test_replay_{}", std::process::id()));
+        let data_dir = data_dir.to_str().unwrap().to_string();
+        let _ = fs::remove_dir_all(&data_dir);
+        let logs = vec![cas(1, "v1", 0), cas(2, "v2", 1), LogEntry::Compact, cas(3, "v3", 2)];
+
+        // crash after the snapshot, before compacting again
+        let mut ddbb = test_ddbb(&data_dir);
+        for (idx, log) in logs.iter().enumerate() {
+            ddbb.apply_decided(idx as u64, log.clone(), Instant::now());
+        }
+        assert_eq!(ddbb.applied_idx(), 4);
+
+        let mut restarted = test_ddbb(&data_dir);
+        assert_eq!(restarted.restore_snapshot().unwrap(), 3);
+        // the whole log is synced again, only the last log is applied
+        for (idx, log) in logs.iter().enumerate() {
+            restarted.apply_decided(idx as u64, log.clone(), Instant::now());
+        }
+        assert_eq!(restarted.applied_idx(), 4);
+        assert_eq!(restarted.get("k1".to_string()), Some(Vec::from("v3")));
+        match last_applied(&restarted) {
+            LogEntry::PutIfRevision {
+                succeeded, mod_rev, ..
+            } => assert_eq!((succeeded, mod_rev), (true, 3)),
+            other => panic!("unexpected log: {:?}", other),
+        }
+
+        // revisions were not bumped twice
+        restarted.apply_decided(4, cas(4, "v4", 3), Instant::now());
+        match last_applied(&restarted) {
+            LogEntry::PutIfRevision { succeeded, .. } => assert!(succeeded),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn test_compaction_drops_applied_logs() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_wal_compact_{}", std::process::id()));
+        let data_dir = data_dir.to_str().unwrap().to_string();
+        let _ = fs::remove_dir_all(&data_dir);
+        let keep_alive = |ts| LogEntry::KeepAlive {
+            opid: ("127.0.0.1:6650".to_string(), ts),
+            session: 1,
+            now: ts,
+            alive: false,
+        };
+        let logs = vec![
+            cas(1, "v1", 0),
+            keep_alive(2),
+            LogEntry::Compact,
+            keep_alive(3),
+            LogEntry::Compact,
+        ];
+
+        let mut ddbb = test_ddbb(&data_dir);
+        for (idx, log) in logs.iter().enumerate() {
+            ddbb.apply_decided(idx as u64, log.clone(), Instant::now());
+        }
+        // their state is in the snapshot, only the latest compaction is left
+        let store = ddbb.wal_store.lock().unwrap().store.clone();
+        assert!(matches!(store.as_slice(), [LogEntry::Compact]));
+        assert_eq!(ddbb.get("k1".to_string()), Some(Vec::from("v1")));
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn test_restore_from_deltas() {
+        let data_dir = std::env::temp_dir().join(format!("ddbb_test_deltas_{}", std::process::id()));
+        let data_dir = data_dir.to_str().unwrap().to_string();
+        let _ = fs::remove_dir_all(&data_dir);
+        let set = |key: &str, value: &str| LogEntry::SetValue {
+            key: key.to_string(),
+            value: Vec::from(value),
+        };
+        let logs = vec![
+            set("k1", "v1"),
+            LogEntry::Compact,
+            set("k2", "v2"),
+            LogEntry::Compact,
+            set("k1", "v3"),
+            LogEntry::Compact,
+            set("k3", "v4"),
+        ];
+
+        let mut ddbb = test_ddbb(&data_dir);
+        for (idx, log) in logs.iter().enumerate() {
+            ddbb.apply_decided(idx as u64, log.clone(), Instant::now());
+        }
+        // complete at the first compaction, deltas at the next ones
+        let deltas: Vec<u64> = DDBB::delta_snapshots(Path::new(&data_dir))
+            .unwrap()
+            .iter()
+            .map(|(applied_idx, _)| *applied_idx)
+            .collect();
+        assert_eq!(deltas, vec![4, 6]);
+
+        let mut restarted = test_ddbb(&data_dir);
+        assert_eq!(restarted.restore_snapshot().unwrap(), 6);
+        assert_eq!(restarted.get("k1".to_string()), Some(Vec::from("v3")));
+        assert_eq!(restarted.get("k2".to_string()), Some(Vec::from("v2")));
+        assert_eq!(restarted.get("k3".to_string()), None);
+
+        // a delta damaged on disk is dropped, the state restored up to it
+        let (_, last_delta) = DDBB::delta_snapshots(Path::new(&data_dir)).unwrap().pop().unwrap();
+        let mut bytes = fs::read(&last_delta).unwrap();
+        let len = bytes.len();
+        bytes[len - 3] ^= 1;
+        fs::write(&last_delta, bytes).unwrap();
+        let mut corrupted = test_ddbb(&data_dir);
+        assert_eq!(corrupted.restore_snapshot().unwrap(), 4);
+        assert_eq!(corrupted.get("k1".to_string()), Some(Vec::from("v1")));
+        assert_eq!(corrupted.metrics().storage_corruptions, 1);
+        assert!(!last_delta.exists());
+
+        // a complete snapshot replaces the deltas
+        restarted.persist_snapshot().unwrap();
+        assert!(DDBB::delta_snapshots(Path::new(&data_dir)).unwrap().is_empty());
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+    /// #Descriptions: make `pid` the leader of round `n` that `ddbb` accepts proposals from.
+    fn elect(ddbb: &Arc<Mutex<DDBB>>, n: u32, pid: NodeId) {
+        let leader = Ballot {
+            n,
+            priority: 0,
+            pid,
+        };
+        let omni = ddbb.lock().unwrap().omni.clone();
+        let mut omni = omni.lock().unwrap();
+        let prepare = PaxosMsg::Prepare(Prepare {
+            n: leader,
+            decided_idx: 0,
+            n_accepted: Ballot::default(),
+            accepted_idx: 0,
+        });
+        let accept_sync = PaxosMsg::AcceptSync(AcceptSync {
+            n: leader,
+            decided_snapshot: None,
+            suffix: vec![],
+            sync_idx: 0,
+            decided_idx: 0,
+            stopsign: None,
+        });
+        for msg in [prepare, accept_sync] {
+            omni.handle_incoming(Message::SequencePaxos(PaxosMessage {
+                from: leader.pid,
+                to: 1,
+                msg,
+            }));
+        }
+        omni.outgoing_messages();
+    }
+
+    fn forwarded(ddbb: &Arc<Mutex<DDBB>>, to: NodeId) -> Vec<(String, u64)> {
+        let omni = ddbb.lock().unwrap().omni.clone();
+        let messages = omni.lock().unwrap().outgoing_messages();
+        messages
+            .into_iter()
+            .filter_map(|msg| match msg {
+                Message::SequencePaxos(PaxosMessage {
+                    to: receiver,
+                    msg: PaxosMsg::ProposalForward(logs),
+                    ..
+                }) if receiver == to => Some(logs),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|log| log.opid().cloned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_repropose_after_failover() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_failover_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        elect(&ddbb, 1, 2);
+
+        let write = tokio::spawn(DDBB::lin_write(
+            ddbb.clone(),
+            "k1".to_string(),
+            Vec::from("v1"),
+        ));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let opids = forwarded(&ddbb, 2);
+        assert_eq!(opids.len(), 1);
+
+        // node 2 dies before deciding the write, and node 3 takes over
+        elect(&ddbb, 2, 3);
+        ddbb.lock().unwrap().repropose_in_flight();
+        assert_eq!(forwarded(&ddbb, 3), opids);
+        assert_eq!(ddbb.lock().unwrap().metrics.reproposed_proposals, 1);
+
+        // already proposed to node 3
+        ddbb.lock().unwrap().repropose_in_flight();
+        assert!(forwarded(&ddbb, 3).is_empty());
+        write.abort();
+    }
+
+    #[tokio::test]
+    async fn test_step_down() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_step_down_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        elect(&ddbb, 1, 2);
+        // only the leader steps down
+        assert!(matches!(
+            DDBB::step_down(ddbb.clone()).await,
+            Err(Error::NotLeader)
+        ));
+
+        // proposals are held while stepping down
+        ddbb.lock().unwrap().stepping_down = true;
+        let write = tokio::spawn(DDBB::lin_write(
+            ddbb.clone(),
+            "k1".to_string(),
+            Vec::from("v1"),
+        ));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(forwarded(&ddbb, 2).is_empty());
+
+        // and proposed to the next leader
+        ddbb.lock().unwrap().stepping_down = false;
+        ddbb.lock().unwrap().propose_queued();
+        assert_eq!(forwarded(&ddbb, 2).len(), 1);
+        write.abort();
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_group_commit_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        elect(&ddbb, 1, 2);
+        let write = |ts| LogEntry::LINWrite {
+            opid: ("127.0.0.1:6650".to_string(), ts),
+            key: "k1".to_string(),
+            value: Vec::from("v1"),
+        };
+        {
+            let mut ddbb = ddbb.lock().unwrap();
+            ddbb.dynamic_config.group_commit_window_ms = 5;
+            let ballot = ddbb.accepting_ballot();
+            // only the leader holds writes
+            assert!(!ddbb.holds_for_group_commit(&write(1), ballot));
+            ddbb.commit_batch = vec![write(1), write(2), write(3)];
+            ddbb.commit_batch_opened = Some(Instant::now());
+            ddbb.flush_commit_batch();
+            assert!(ddbb.commit_batch.is_empty());
+            assert_eq!(ddbb.metrics.group_commits, 1);
+            assert_eq!(ddbb.metrics.group_commit_max_logs, 3);
+        }
+        assert_eq!(forwarded(&ddbb, 2).len(), 3);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_memory_budget_{}", std::process::id()));
+        let mut ddbb = test_ddbb(data_dir.to_str().unwrap());
+        let log = LogEntry::SetValue {
+            key: "k1".to_string(),
+            value: vec![0; 1000],
+        };
+        ddbb.simo.lock().unwrap().outgoing_buffer.lock().unwrap().push_back(
+            Message::SequencePaxos(PaxosMessage {
+                from: 1,
+                to: 2,
+                msg: PaxosMsg::ProposalForward(vec![log]),
+            }),
+        );
+        let usage = ddbb.memory_usage();
+        assert!(usage.outgoing_bytes > 1000);
+        assert_eq!(usage.total(), usage.outgoing_bytes);
+
+        ddbb.metrics.memory = usage;
+        assert!(ddbb.admit_write().is_ok());
+        ddbb.set_memory_budget(1000);
+        assert!(matches!(ddbb.admit_write(), Err(Error::Overloaded(_))));
+        assert_eq!(ddbb.metrics().shed_memory_budget, 1);
+        assert_eq!(ddbb.metrics().memory_budget, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_ttls_follow_the_clock() {
+        let data_dir = std::env::temp_dir().join(format!("ddbb_test_clock_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        let clock = MockClock::new(5000);
+        ddbb.lock().unwrap().set_clock(clock.shared());
+        elect(&ddbb, 1, 2);
+
+        clock.advance(Duration::from_millis(250));
+        let ttl = Duration::from_millis(1000);
+        let campaign = tokio::spawn(DDBB::campaign(
+            ddbb.clone(),
+            "e".to_string(),
+            "c1".to_string(),
+            ttl,
+        ));
+        let acquire = tokio::spawn(DDBB::acquire(ddbb.clone(), "s".to_string(), 1, 1));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let omni = ddbb.lock().unwrap().omni.clone();
+        let messages = omni.lock().unwrap().outgoing_messages();
+        let stamped: Vec<u64> = messages
+            .into_iter()
+            .filter_map(|msg| match msg {
+                Message::SequencePaxos(PaxosMessage {
+                    msg: PaxosMsg::ProposalForward(logs),
+                    ..
+                }) => Some(logs),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|log| match log {
+                LogEntry::Campaign { now, .. } | LogEntry::SemAcquire { now, .. } => Some(now),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stamped, vec![5250, 5250]);
+        campaign.abort();
+        acquire.abort();
+    }
+
+    #[test]
+    fn test_not_leader() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_not_leader_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        let not_leader = || ddbb.lock().unwrap().not_leader();
+        assert_eq!(not_leader(), None);
+
+        {
+            let mut ddbb = ddbb.lock().unwrap();
+            ddbb.set_forward_to_leader(false);
+            ddbb.set_peer_client_addrs(HashMap::from([(2, "127.0.0.1:6143".to_string())]));
+        }
+        let no_leader = NotLeaderEntry {
+            leader_id: None,
+            leader_addr: None,
+            ballot: 0,
+        };
+        assert_eq!(not_leader(), Some(no_leader));
+        elect(&ddbb, 2, 2);
+        let hint = NotLeaderEntry {
+            leader_id: Some(2),
+            leader_addr: Some("127.0.0.1:6143".to_string()),
+            ballot: 2,
+        };
+        assert_eq!(not_leader(), Some(hint));
+    }
+
+    #[tokio::test]
+    async fn test_observer() {
+        let op_config = OmniPaxosConfig {
+            pid: 3,
+            configuration_id: 1,
+            peers: vec![1, 2],
+            learners: vec![3],
+            ..Default::default()
+        };
+        let simo = OmniSIMO::new("127.0.0.1:6652".to_string(), HashMap::new());
+        let omni = op_config.build(DDBBStorage::default());
+        let mut observer = DDBB::new(3, "127.0.0.1:6652".to_string(), HashMap::new(), simo, omni);
+        observer.set_observer(true).unwrap();
+        assert_eq!(observer.status().role, NodeRole::Observer);
+        let observer = Arc::new(Mutex::new(observer));
+
+        // forwarding or not, its clients are sent to the leader
+        assert!(observer.lock().unwrap().not_leader().is_some());
+        let write = DDBB::lin_write(observer.clone(), "k1".to_string(), Vec::from("v1"));
+        assert!(matches!(write.await, Err(Error::NotLeader)));
+
+        // only a learner observes
+        let data_dir = std::env::temp_dir().join(format!("ddbb_test_observer_{}", std::process::id()));
+        assert!(test_ddbb(data_dir.to_str().unwrap()).set_observer(true).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quorum_lost() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_quorum_lost_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        // no leader, the write waits in the queue
+        let write = tokio::spawn(DDBB::lin_write(ddbb.clone(), "k1".to_string(), Vec::from("v1")));
+        while ddbb.lock().unwrap().proposal_callbacks.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let now = Instant::now();
+        ddbb.lock().unwrap().check_quorum(false, now);
+        assert!(!ddbb.lock().unwrap().metrics().quorum_lost);
+        ddbb.lock().unwrap().check_quorum(false, now + QUORUM_LOSS_TIMEOUT);
+        assert!(matches!(write.await.unwrap(), Err(Error::QuorumLost)));
+        assert!(ddbb.lock().unwrap().proposal_queue.is_empty());
+
+        // failed at once while the quorum is lost
+        let write = DDBB::lin_write(ddbb.clone(), "k1".to_string(), Vec::from("v1"));
+        assert!(matches!(write.await, Err(Error::QuorumLost)));
+        let metrics = ddbb.lock().unwrap().metrics();
+        assert!(metrics.quorum_lost);
+        assert_eq!(metrics.failed_quorum_lost, 2);
+
+        ddbb.lock().unwrap().check_quorum(true, now + QUORUM_LOSS_TIMEOUT * 2);
+        let write = tokio::spawn(DDBB::lin_write(ddbb.clone(), "k1".to_string(), Vec::from("v1")));
+        while ddbb.lock().unwrap().proposal_callbacks.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        write.abort();
+        let events: Vec<ClusterEvent> = ddbb
+            .lock()
+            .unwrap()
+            .events
+            .lock()
+            .unwrap()
+            .entries()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(events, vec![ClusterEvent::QuorumLost, ClusterEvent::QuorumRegained]);
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_read_only_{}", std::process::id()));
+        let mut ddbb = test_ddbb(data_dir.to_str().unwrap());
+        ddbb.set_disk_low_watermark(1000);
+        ddbb.check_disk(999);
+        assert!(ddbb.metrics().read_only);
+        let ddbb = Arc::new(Mutex::new(ddbb));
+        let write = DDBB::lin_write(ddbb.clone(), "k1".to_string(), Vec::from("v1"));
+        assert!(matches!(write.await, Err(Error::ReadOnly(_))));
+        // reads still go through the log, no leader to queue them for here
+        let read = tokio::spawn(DDBB::lin_read(ddbb.clone(), "k1".to_string()));
+        while ddbb.lock().unwrap().proposal_callbacks.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        read.abort();
+
+        ddbb.lock().unwrap().check_disk(1100);
+        assert!(ddbb.lock().unwrap().admit_write().is_ok());
+        let metrics = ddbb.lock().unwrap().metrics();
+        assert!(!metrics.read_only);
+        assert_eq!((metrics.disk_free_bytes, metrics.rejected_read_only), (1100, 1));
+    }
+
+    #[test]
+    fn test_elect_ttl_too_short() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_elect_ttl_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        let elect = |ttl| DDBB::elect(ddbb.clone(), "e".to_string(), "c1".to_string(), ttl);
+        assert!(elect(Duration::from_millis(3)).is_err());
+        assert!(elect(SESSION_MIN_TTL - Duration::from_millis(1)).is_err());
+        // nothing was proposed
+        assert!(ddbb.lock().unwrap().proposal_callbacks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_admitted_while_read_only() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_read_only_lease_{}", std::process::id()));
+        let mut ddbb = test_ddbb(data_dir.to_str().unwrap());
+        ddbb.set_disk_low_watermark(1000);
+        ddbb.check_disk(999);
+        let ddbb = Arc::new(Mutex::new(ddbb));
+        let keep_alive = tokio::spawn(DDBB::keep_alive(ddbb.clone(), 1));
+        // pending, no leader to queue it for here
+        while ddbb.lock().unwrap().proposal_callbacks.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(ddbb.lock().unwrap().metrics().rejected_read_only, 0);
+        keep_alive.abort();
+    }
+
+    #[tokio::test]
+    async fn test_writes_shed_over_threshold() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_shed_{}", std::process::id()));
+        let mut ddbb = test_ddbb(data_dir.to_str().unwrap());
+        ddbb.dynamic_config.set("max_pending_proposals", "1").unwrap();
+        let ddbb = Arc::new(Mutex::new(ddbb));
+        // a read pending, not shed itself, no leader to queue it for here
+        let read = tokio::spawn(DDBB::lin_read(ddbb.clone(), "k1".to_string()));
+        while ddbb.lock().unwrap().proposal_callbacks.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let write = DDBB::lin_write(ddbb.clone(), "k1".to_string(), Vec::from("v1")).await;
+        match write {
+            Err(e @ Error::Overloaded(_)) => assert!(e.is_retryable()),
+            other => panic!("not shed: {:?}", other),
+        }
+        assert_eq!(ddbb.lock().unwrap().metrics().shed_pending_proposals, 1);
+        read.abort();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_value_rejected() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_oversized_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        let value = vec![0; MAX_LOG_VALUE_SIZE + 1];
+        let cas = DDBB::put_if_revision(ddbb.clone(), "k1".to_string(), value.clone(), 0);
+        assert!(matches!(cas.await, Err(Error::Other(_))));
+        let pairs = vec![("k1".to_string(), Vec::from("v1")), ("k2".to_string(), value)];
+        assert!(matches!(DDBB::bulk_set(ddbb.clone(), pairs).await, Err(Error::Other(_))));
+        // nothing was proposed
+        assert!(ddbb.lock().unwrap().proposal_callbacks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_over_quota_refused() {
+        let data_dir =
+            std::env::temp_dir().join(format!("ddbb_test_quota_{}", std::process::id()));
+        let ddbb = Arc::new(Mutex::new(test_ddbb(data_dir.to_str().unwrap())));
+        let create = LogEntry::CreateNamespace {
+            opid: ("127.0.0.1:6651".to_string(), 1),
+            name: "app1".to_string(),
+            max_keys: 1,
+            token: None,
+            created: false,
+        };
+        let set = LogEntry::SetValue {
+            key: namespace::scoped_key("app1", "k1"),
+            value: Vec::from("v1"),
+        };
+        ddbb.lock().unwrap().apply_decided(0, create, Instant::now());
+        ddbb.lock().unwrap().apply_decided(1, set, Instant::now());
+
+        let write = tokio::spawn(DDBB::lin_write(
+            ddbb.clone(),
+            namespace::scoped_key("app1", "k2"),
+            Vec::from("v2"),
+        ));
+        let log = loop {
+            if let Some(pending) = ddbb.lock().unwrap().proposal_callbacks.values().next() {
+                break pending.log.clone();
+            }
+            tokio::task::yield_now().await;
+        };
+        ddbb.lock().unwrap().apply_decided(2, log, Instant::now());
+        assert!(matches!(write.await.unwrap(), Err(Error::QuotaExceeded(_))));
+        assert_eq!(ddbb.lock().unwrap().get(namespace::scoped_key("app1", "k2")), None);
+    }
 }
@@ -0,0 +1,99 @@
+use std::path::Path;
+
+/// A change of whether the node takes writes, by the free space of its data directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiskChange {
+    /// under the low watermark, the node is read-only
+    Low,
+    Recovered,
+}
+
+/// The free space of the data directory against a low watermark. Once under it, the
+/// node stays read-only until a tenth more than the watermark is free, so that it does
+/// not flip at every check while the free space hovers around the watermark.
+#[derive(Debug)]
+pub struct DiskWatch {
+    /// 0 for never read-only
+    low_watermark: u64,
+    free_bytes: Option<u64>,
+    read_only: bool,
+}
+
+impl DiskWatch {
+    pub fn new(low_watermark: u64) -> Self {
+        Self {
+            low_watermark,
+            free_bytes: None,
+            read_only: false,
+        }
+    }
+
+    pub fn low_watermark(&self) -> u64 {
+        self.low_watermark
+    }
+
+    /// #Descriptions: observe `free_bytes` in the data directory; the change, if the
+    /// node goes read-only or takes writes again with it.
+    pub fn observe(&mut self, free_bytes: u64) -> Option<DiskChange> {
+        self.free_bytes = Some(free_bytes);
+        if self.low_watermark == 0 {
+            return self.recover();
+        }
+        if !self.read_only && free_bytes < self.low_watermark {
+            self.read_only = true;
+            return Some(DiskChange::Low);
+        }
+        if self.read_only && free_bytes >= self.low_watermark + self.low_watermark / 10 {
+            return self.recover();
+        }
+        None
+    }
+
+    fn recover(&mut self) -> Option<DiskChange> {
+        if self.read_only {
+            self.read_only = false;
+            return Some(DiskChange::Recovered);
+        }
+        None
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// the free space as of the last `observe`, `None` before it
+    pub fn free_bytes(&self) -> Option<u64> {
+        self.free_bytes
+    }
+}
+
+/// #Descriptions: the bytes available to this process in the file system of `path`.
+pub fn free_bytes(path: &Path) -> std::io::Result<u64> {
+    fs2::available_space(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_watch() {
+        let mut watch = DiskWatch::new(1000);
+        assert_eq!(watch.observe(5000), None);
+        assert_eq!(watch.observe(999), Some(DiskChange::Low));
+        assert!(watch.is_read_only());
+        assert_eq!(watch.observe(500), None);
+        // not back at the watermark, only a tenth over it
+        assert_eq!(watch.observe(1050), None);
+        assert!(watch.is_read_only());
+        assert_eq!(watch.observe(1100), Some(DiskChange::Recovered));
+        assert!(!watch.is_read_only());
+        assert_eq!(watch.free_bytes(), Some(1100));
+
+        let mut never = DiskWatch::new(0);
+        assert_eq!(never.observe(0), None);
+        assert!(!never.is_read_only());
+
+        assert!(free_bytes(&std::env::temp_dir()).unwrap() > 0);
+    }
+}
@@ -0,0 +1,282 @@
+use log::error;
+use omnipaxos_core::util::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::{
+    CLIENT_WRITE_BURST, CLIENT_WRITE_RATE, COMPACT_EVERY, FOLLOWER_LAG_CRITICAL, FOLLOWER_LAG_WARN,
+    GROUP_COMMIT_WINDOW_MS, LOCAL_READS, MAX_APPLY_BACKLOG, MAX_OUTGOING_MESSAGES,
+    MAX_PENDING_PROPOSALS,
+};
+use ddbb_libs::Result;
+
+/// Settings are stored under this prefix, written through the log like any key,
+/// keys sent by clients may not start with it.
+pub const CONFIG_KEY_PREFIX: &str = "\u{0}config/";
+
+/// Names of the settings that can be changed at runtime.
+pub const SETTINGS: &[&str] = &[
+    "client_write_rate",
+    "client_write_burst",
+    "compact_every",
+    "read_mode",
+    "group_commit_window_ms",
+    "leader_priorities",
+    "drained_nodes",
+    "follower_lag_warn",
+    "follower_lag_critical",
+    "max_outgoing_messages",
+    "max_pending_proposals",
+    "max_apply_backlog",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadMode {
+    /// reads go through the log
+    Linearizable,
+    /// reads are answered from the local state machine, possibly stale
+    Local,
+}
+
+/// Server settings replicated through the log, so that every node changes them
+/// at the same point of the log, without a restart.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DynamicConfig {
+    /// writes per second allowed on a client connection, and the burst above it
+    pub client_write_rate: f64,
+    pub client_write_burst: f64,
+    /// the leader proposes a compaction every this many applied logs, 0 for never
+    pub compact_every: u64,
+    pub read_mode: ReadMode,
+    /// the leader holds the writes arriving within this many ms of the first one and
+    /// proposes them together, 0 proposes each at once
+    #[serde(default)]
+    pub group_commit_window_ms: u64,
+    /// the ballot priority of the nodes listed, over the one of their zone, see
+    /// `LeaderBalance`
+    #[serde(default)]
+    pub leader_priorities: BTreeMap<NodeId, u64>,
+    /// nodes that must not lead, e.g. before a maintenance
+    #[serde(default)]
+    pub drained_nodes: BTreeSet<NodeId>,
+    /// the leader logs a warning for a follower this many logs behind it, and an error
+    /// for one `follower_lag_critical` behind, 0 for never
+    #[serde(default = "default_follower_lag_warn")]
+    pub follower_lag_warn: u64,
+    #[serde(default = "default_follower_lag_critical")]
+    pub follower_lag_critical: u64,
+    /// writes are shed once the peer msgs queued, the proposals pending or the decided
+    /// logs not applied yet reach these
+    #[serde(default = "default_max_outgoing_messages")]
+    pub max_outgoing_messages: usize,
+    #[serde(default = "default_max_pending_proposals")]
+    pub max_pending_proposals: usize,
+    #[serde(default = "default_max_apply_backlog")]
+    pub max_apply_backlog: u64,
+}
+
+impl Default for DynamicConfig {
+    fn default() -> Self {
+        Self {
+            client_write_rate: CLIENT_WRITE_RATE,
+            client_write_burst: CLIENT_WRITE_BURST,
+            compact_every: COMPACT_EVERY,
+            read_mode: if LOCAL_READS {
+                ReadMode::Local
+            } else {
+                ReadMode::Linearizable
+            },
+            group_commit_window_ms: GROUP_COMMIT_WINDOW_MS,
+            leader_priorities: BTreeMap::new(),
+            drained_nodes: BTreeSet::new(),
+            follower_lag_warn: FOLLOWER_LAG_WARN,
+            follower_lag_critical: FOLLOWER_LAG_CRITICAL,
+            max_outgoing_messages: MAX_OUTGOING_MESSAGES,
+            max_pending_proposals: MAX_PENDING_PROPOSALS,
+            max_apply_backlog: MAX_APPLY_BACKLOG,
+        }
+    }
+}
+
+impl DynamicConfig {
+    /// #Descriptions: parse `value` into the setting `name`, the config is unchanged
+    /// on error.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let invalid = || format!("invalid value for {}: {:?}", name, value);
+        match name {
+            "client_write_rate" => {
+                self.client_write_rate = parse_positive(value).ok_or_else(invalid)?
+            }
+            "client_write_burst" => {
+                self.client_write_burst = parse_positive(value).ok_or_else(invalid)?
+            }
+            "compact_every" => self.compact_every = value.parse().map_err(|_| invalid())?,
+            "read_mode" => {
+                self.read_mode = match value {
+                    "linearizable" => ReadMode::Linearizable,
+                    "local" => ReadMode::Local,
+                    _ => return Err(invalid().into()),
+                }
+            }
+            "group_commit_window_ms" => {
+                self.group_commit_window_ms = value.parse().map_err(|_| invalid())?
+            }
+            // `1=2,2=2,3=0`
+            "leader_priorities" => {
+                self.leader_priorities = parse_list(value, |pair| {
+                    let (id, priority) = pair.split_once('=')?;
+                    Some((id.trim().parse().ok()?, priority.trim().parse().ok()?))
+                })
+                .ok_or_else(invalid)?
+            }
+            // `2,3`
+            "drained_nodes" => {
+                self.drained_nodes =
+                    parse_list(value, |id| id.trim().parse().ok()).ok_or_else(invalid)?
+            }
+            "follower_lag_warn" => {
+                self.follower_lag_warn = value.parse().map_err(|_| invalid())?
+            }
+            "follower_lag_critical" => {
+                self.follower_lag_critical = value.parse().map_err(|_| invalid())?
+            }
+            "max_outgoing_messages" => {
+                self.max_outgoing_messages = value.parse().map_err(|_| invalid())?
+            }
+            "max_pending_proposals" => {
+                self.max_pending_proposals = value.parse().map_err(|_| invalid())?
+            }
+            "max_apply_backlog" => {
+                self.max_apply_backlog = value.parse().map_err(|_| invalid())?
+            }
+            _ => return Err(format!("unknown setting: {}", name).into()),
+        }
+        Ok(())
+    }
+
+    /// #Descriptions: the settings stored in the state machine, read with `get`,
+    /// defaults for the ones never set.
+    pub fn load(get: impl Fn(&str) -> Option<Vec<u8>>) -> Self {
+        let mut config = Self::default();
+        for name in SETTINGS {
+            if let Some(value) = get(&config_key(name)) {
+                config.reload(name, &value);
+            }
+        }
+        config
+    }
+
+    /// #Descriptions: take a value written to the log, which was validated by the
+    /// proposer. A value that still does not parse keeps the previous setting.
+    pub fn reload(&mut self, name: &str, value: &[u8]) {
+        let value = String::from_utf8_lossy(value);
+        if let Err(e) = self.set(name, &value) {
+            error!("Ignored replicated setting: {}", e);
+        }
+    }
+
+    /// #Descriptions: the ballot priority of node `id`, `zone_priority` unless one is
+    /// set in `leader_priorities`, and 0 once drained.
+    pub fn leader_priority(&self, id: NodeId, zone_priority: u64) -> u64 {
+        if self.drained_nodes.contains(&id) {
+            return 0;
+        }
+        self.leader_priorities.get(&id).copied().unwrap_or(zone_priority)
+    }
+}
+
+fn default_follower_lag_warn() -> u64 {
+    FOLLOWER_LAG_WARN
+}
+
+fn default_follower_lag_critical() -> u64 {
+    FOLLOWER_LAG_CRITICAL
+}
+
+fn default_max_outgoing_messages() -> usize {
+    MAX_OUTGOING_MESSAGES
+}
+
+fn default_max_pending_proposals() -> usize {
+    MAX_PENDING_PROPOSALS
+}
+
+fn default_max_apply_backlog() -> u64 {
+    MAX_APPLY_BACKLOG
+}
+
+fn parse_positive(value: &str) -> Option<f64> {
+    value.parse::<f64>().ok().filter(|v| *v > 0.0)
+}
+
+/// #Descriptions: the comma separated items of `value`, each parsed by `item`, none
+/// for an empty value.
+fn parse_list<T, C>(value: &str, item: impl Fn(&str) -> Option<T>) -> Option<C>
+where
+    C: FromIterator<T>,
+{
+    value
+        .split(',')
+        .filter(|part| !part.trim().is_empty())
+        .map(item)
+        .collect()
+}
+
+/// #Descriptions: the key setting `name` is stored under.
+pub fn config_key(name: &str) -> String {
+    format!("{}{}", CONFIG_KEY_PREFIX, name)
+}
+
+/// #Descriptions: the setting a stored key holds, `None` for other keys.
+pub fn setting_of(key: &str) -> Option<&str> {
+    key.strip_prefix(CONFIG_KEY_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_dynamic_config_set_and_load() {
+        let mut config = DynamicConfig::default();
+        config.set("client_write_rate", "50").unwrap();
+        config.set("read_mode", "local").unwrap();
+        config.set("group_commit_window_ms", "2").unwrap();
+        assert_eq!(config.client_write_rate, 50.0);
+        assert_eq!(config.group_commit_window_ms, 2);
+        assert_eq!(config.read_mode, ReadMode::Local);
+        // rejected values keep the setting
+        assert!(config.set("client_write_rate", "-1").is_err());
+        assert!(config.set("compact_every", "often").is_err());
+        assert!(config.set("unknown", "1").is_err());
+        assert_eq!(config.client_write_rate, 50.0);
+
+        let mut store = HashMap::new();
+        store.insert(config_key("compact_every"), Vec::from("100"));
+        store.insert(config_key("read_mode"), Vec::from("bogus"));
+        let loaded = DynamicConfig::load(|key| store.get(key).cloned());
+        assert_eq!(loaded.compact_every, 100);
+        assert_eq!(loaded.read_mode, DynamicConfig::default().read_mode);
+        assert_eq!(setting_of(&config_key("read_mode")), Some("read_mode"));
+
+        config.set("leader_priorities", "1=2, 3=0").unwrap();
+        config.set("drained_nodes", "2").unwrap();
+        assert!(config.set("leader_priorities", "1").is_err());
+        assert_eq!(config.leader_priority(1, 0), 2);
+        assert_eq!(config.leader_priority(2, 1), 0);
+        assert_eq!(config.leader_priority(4, 1), 1);
+        config.set("drained_nodes", "").unwrap();
+        assert!(config.drained_nodes.is_empty());
+
+        config.set("follower_lag_warn", "0").unwrap();
+        assert!(config.set("follower_lag_critical", "far").is_err());
+        assert_eq!(config.follower_lag_warn, 0);
+        assert_eq!(config.follower_lag_critical, FOLLOWER_LAG_CRITICAL);
+
+        config.set("max_pending_proposals", "10").unwrap();
+        assert!(config.set("max_apply_backlog", "-1").is_err());
+        assert_eq!(config.max_pending_proposals, 10);
+        assert_eq!(config.max_apply_backlog, MAX_APPLY_BACKLOG);
+    }
+}
@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Candidate {
+    id: String,
+    /// taken from `next_seq` when the candidate joined
+    seq: u64,
+    expires_at: u64,
+}
+
+/// A leader election replicated through the log. A candidate joining gets the next
+/// sequence number and stays while it refreshes within its ttl, the live candidate
+/// with the lowest one leads. Time only advances with the `now` carried by applied
+/// logs, so every replica expires the same candidates at the same point of the log.
+/// The sequence number of a leader is its fencing token, newer leaders have higher ones.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Election {
+    next_seq: u64,
+    /// in the order they joined
+    candidates: Vec<Candidate>,
+}
+
+impl Election {
+    /// An election whose first candidate gets `seq`, to keep the tokens of an election
+    /// increasing once it was emptied and dropped.
+    pub fn starting_at(seq: u64) -> Self {
+        Election {
+            next_seq: seq,
+            candidates: Vec::new(),
+        }
+    }
+
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Drop the candidates that expired at `now`.
+    pub fn expire(&mut self, now: u64) {
+        self.candidates
+            .retain(|candidate| candidate.expires_at > now);
+    }
+
+    /// Join as `id` until `now + ttl`, campaigning again refreshes it.
+    pub fn campaign(&mut self, id: String, ttl: u64, now: u64) {
+        self.expire(now);
+        if let Some(candidate) = self.candidates.iter_mut().find(|c| c.id == id) {
+            candidate.expires_at = now + ttl;
+            return;
+        }
+        self.candidates.push(Candidate {
+            id,
+            seq: self.next_seq,
+            expires_at: now + ttl,
+        });
+        self.next_seq += 1;
+    }
+
+    pub fn resign(&mut self, id: &str) {
+        self.candidates.retain(|candidate| candidate.id != id);
+    }
+
+    pub fn leader(&self) -> Option<&str> {
+        self.candidates
+            .first()
+            .map(|candidate| candidate.id.as_str())
+    }
+
+    /// The fencing token of the leader.
+    pub fn fencing_token(&self) -> Option<u64> {
+        self.candidates.first().map(|candidate| candidate.seq)
+    }
+
+    /// The fencing token `id` gets once it leads.
+    pub fn token_of(&self, id: &str) -> Option<u64> {
+        self.candidates
+            .iter()
+            .find(|candidate| candidate.id == id)
+            .map(|candidate| candidate.seq)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+/// The campaigns running on this node, told the leader of their election every
+/// time a `Campaign` or `Resign` of it is applied.
+#[derive(Debug, Default)]
+pub struct LeaderWatchers {
+    watchers: HashMap<String, Vec<mpsc::UnboundedSender<Option<String>>>>,
+}
+
+impl LeaderWatchers {
+    pub fn subscribe(&mut self, election: &str) -> mpsc::UnboundedReceiver<Option<String>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.watchers
+            .entry(election.to_string())
+            .or_default()
+            .push(sender);
+        receiver
+    }
+
+    pub fn publish(&mut self, election: &str, leader: Option<String>) {
+        if let Some(watchers) = self.watchers.get_mut(election) {
+            // a campaign that ended is dropped
+            watchers.retain(|watcher| watcher.send(leader.clone()).is_ok());
+            if watchers.is_empty() {
+                self.watchers.remove(election);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_election() {
+        let mut election = Election::default();
+        election.campaign("c1".to_string(), 100, 0);
+        election.campaign("c2".to_string(), 100, 10);
+        assert_eq!(election.leader(), Some("c1"));
+
+        // c1 refreshed until 150, c2 expires at 110
+        election.campaign("c1".to_string(), 100, 50);
+        election.expire(120);
+        assert_eq!(election.leader(), Some("c1"));
+
+        // c2 joins again behind c3
+        election.campaign("c3".to_string(), 100, 130);
+        election.campaign("c2".to_string(), 100, 140);
+        election.resign("c1");
+        assert_eq!(election.leader(), Some("c3"));
+        election.expire(240);
+        assert!(election.is_empty());
+    }
+
+    #[test]
+    fn test_fencing_token() {
+        let mut election = Election::starting_at(5);
+        election.campaign("c1".to_string(), 100, 0);
+        election.campaign("c2".to_string(), 100, 0);
+        assert_eq!(election.fencing_token(), Some(5));
+        assert_eq!(election.token_of("c2"), Some(6));
+        // a refresh keeps the token, a newer leader has a higher one
+        election.campaign("c1".to_string(), 100, 50);
+        assert_eq!(election.fencing_token(), Some(5));
+        election.resign("c1");
+        assert_eq!(election.fencing_token(), Some(6));
+        assert_eq!(election.next_seq(), 7);
+    }
+}
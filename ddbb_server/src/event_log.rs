@@ -0,0 +1,131 @@
+use log::info;
+use omnipaxos_core::{ballot_leader_election::Ballot, util::NodeId};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type SharedEventLog = Arc<Mutex<EventLog>>;
+
+/// A change of the cluster as seen by this node.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(tag = "kind")]
+pub enum ClusterEvent {
+    /// the outgoing OmniSIMO connection to `peer` is up
+    PeerConnected { peer: NodeId },
+    PeerDisconnected { peer: NodeId },
+    /// a connection as `node` was rejected, from a process running as this node or while
+    /// another process was connected as `node`: two processes run with the same node id
+    NodeIdCollision { node: NodeId },
+    /// `lost` frames of `peer` never arrived, e.g. across a reconnect: omnipaxos
+    /// resyncs with it instead of reading the ones after the gap
+    SessionReset { peer: NodeId, lost: u64 },
+    /// out of reach of a majority for `QUORUM_LOSS_TIMEOUT`, the proposals fail meanwhile
+    QuorumLost,
+    QuorumRegained,
+    /// under `low_watermark` bytes free in the data directory, writes are rejected
+    DiskLow { free_bytes: u64, low_watermark: u64 },
+    DiskRecovered { free_bytes: u64 },
+    /// a record of `file` failed its checksum on start, the log or the state was
+    /// truncated at `truncated_at` and the rest is synced from the peers again
+    StorageCorrupted { file: String, truncated_at: u64 },
+    LeaderElected { leader: NodeId, ballot: Ballot },
+    /// a stopsign was decided, the cluster moves to configuration `config_id`
+    Reconfigured { config_id: u32, nodes: Vec<NodeId> },
+    /// the state machine was replaced at `applied_idx`, from `source`
+    SnapshotInstalled { applied_idx: u64, source: String },
+    /// backup `name` was taken at the `revision` pinned by a `Backup` log at `applied_idx`
+    BackupTaken {
+        name: String,
+        applied_idx: u64,
+        revision: u64,
+    },
+    /// leader `from` stepped down for `towards`, a node preferred by the leader balance
+    LeaderRebalanced { from: NodeId, towards: NodeId },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EventLogEntry {
+    /// unix ms at which it was recorded
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub event: ClusterEvent,
+}
+
+/// The latest cluster events, oldest dropped first, for post-incident analysis.
+#[derive(Debug)]
+pub struct EventLog {
+    capacity: usize,
+    /// also write every event as a json log line
+    log_lines: bool,
+    entries: VecDeque<EventLogEntry>,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            log_lines: false,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn shared(capacity: usize) -> SharedEventLog {
+        Arc::new(Mutex::new(Self::new(capacity)))
+    }
+
+    pub fn set_log_lines(&mut self, log_lines: bool) {
+        self.log_lines = log_lines;
+    }
+
+    pub fn record(&mut self, event: ClusterEvent) {
+        let entry = EventLogEntry {
+            at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            event,
+        };
+        if self.log_lines {
+            if let Ok(line) = serde_json::to_string(&entry) {
+                info!("EVENT {}", line);
+            }
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> Vec<EventLogEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log_capacity() {
+        let mut event_log = EventLog::new(2);
+        for peer in 1..=3 {
+            event_log.record(ClusterEvent::PeerConnected { peer });
+        }
+        let events: Vec<ClusterEvent> = event_log.entries().into_iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                ClusterEvent::PeerConnected { peer: 2 },
+                ClusterEvent::PeerConnected { peer: 3 }
+            ]
+        );
+
+        let json = serde_json::to_value(&event_log.entries()[0]).unwrap();
+        assert_eq!(json["kind"], "PeerConnected");
+        assert_eq!(json["peer"], 2);
+    }
+}
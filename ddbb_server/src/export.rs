@@ -0,0 +1,104 @@
+use omnipaxos_core::util::NodeId;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::dynamic_config::DynamicConfig;
+use ddbb_libs::Result;
+
+/// Bumped on incompatible changes of `SnapshotExport`.
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// A state machine snapshot exported to a file, to restore a cluster from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotExport {
+    pub format_version: u32,
+    /// the node it was exported from
+    pub node_id: NodeId,
+    pub decided_idx: u64,
+    /// the logs applied to `state`
+    pub applied_idx: u64,
+    /// the replicated settings at `applied_idx`, also part of `state`
+    pub config: DynamicConfig,
+    pub state: Vec<u8>,
+    /// crc32 of `state`
+    pub checksum: u32,
+}
+
+impl SnapshotExport {
+    pub fn new(
+        node_id: NodeId,
+        decided_idx: u64,
+        applied_idx: u64,
+        config: DynamicConfig,
+        state: Vec<u8>,
+    ) -> Self {
+        let checksum = crc32fast::hash(&state);
+        Self {
+            format_version: EXPORT_FORMAT_VERSION,
+            node_id,
+            decided_idx,
+            applied_idx,
+            config,
+            state,
+            checksum,
+        }
+    }
+
+    /// #Descriptions: write the export to `path`, replacing the file only once
+    /// it is complete.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let path = Path::new(path);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// #Descriptions: read an export written by `write`, checking its format version
+    /// and that the state is intact.
+    pub fn read(path: &str) -> Result<Self> {
+        let export: SnapshotExport = serde_json::from_slice(&fs::read(path)?)?;
+        if export.format_version != EXPORT_FORMAT_VERSION {
+            return Err(format!(
+                "unsupported export format {}, expected {}",
+                export.format_version, EXPORT_FORMAT_VERSION
+            )
+            .into());
+        }
+        let checksum = crc32fast::hash(&export.state);
+        if checksum != export.checksum {
+            return Err(format!(
+                "corrupted export {}: checksum {:08x}, expected {:08x}",
+                path, checksum, export.checksum
+            )
+            .into());
+        }
+        Ok(export)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_integrity() {
+        let dir = std::env::temp_dir().join(format!("ddbb_test_export_{}", std::process::id()));
+        let path = dir.join("export.json");
+        let path = path.to_str().unwrap();
+        let export = SnapshotExport::new(1, 5, 4, DynamicConfig::default(), Vec::from("state"));
+        export.write(path).unwrap();
+        let read = SnapshotExport::read(path).unwrap();
+        assert_eq!((read.applied_idx, read.state), (4, Vec::from("state")));
+
+        let mut corrupted = export;
+        corrupted.state[0] ^= 1;
+        fs::write(path, serde_json::to_vec(&corrupted).unwrap()).unwrap();
+        assert!(SnapshotExport::read(path).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
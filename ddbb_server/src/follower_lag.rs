@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, HashMap};
+
+use omnipaxos_core::util::NodeId;
+
+/// How far behind the leader a follower is, as of `LagThresholds`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LagLevel {
+    Ok,
+    Warn,
+    Critical,
+}
+
+/// Logs behind the leader a follower is alerted at, 0 for never.
+#[derive(Clone, Copy, Debug)]
+pub struct LagThresholds {
+    pub warn: u64,
+    pub critical: u64,
+}
+
+impl LagThresholds {
+    pub fn level(&self, lag: u64) -> LagLevel {
+        if self.critical != 0 && lag >= self.critical {
+            LagLevel::Critical
+        } else if self.warn != 0 && lag >= self.warn {
+            LagLevel::Warn
+        } else {
+            LagLevel::Ok
+        }
+    }
+}
+
+/// The replication lag of the followers, tracked on the leader so that a follower
+/// falling behind is alerted once per level it reaches, before it is so far behind
+/// that it needs a snapshot, rather than at every check.
+#[derive(Debug, Default)]
+pub struct FollowerLag {
+    /// the level each follower was alerted at, none for `Ok`
+    levels: HashMap<NodeId, LagLevel>,
+}
+
+impl FollowerLag {
+    /// #Descriptions: the logs each follower is behind `leader_idx`, from the log length
+    /// it accepted, and the followers whose level changed since the last `observe`.
+    pub fn observe(
+        &mut self,
+        leader_idx: u64,
+        followers: &[(NodeId, u64)],
+        thresholds: LagThresholds,
+    ) -> (BTreeMap<NodeId, u64>, Vec<(NodeId, LagLevel, u64)>) {
+        let mut lags = BTreeMap::new();
+        let mut changes = Vec::new();
+        for (follower, accepted_idx) in followers {
+            let lag = leader_idx.saturating_sub(*accepted_idx);
+            let level = thresholds.level(lag);
+            let previous = self.levels.get(follower).copied().unwrap_or(LagLevel::Ok);
+            if level != previous {
+                changes.push((*follower, level, lag));
+            }
+            if level == LagLevel::Ok {
+                self.levels.remove(follower);
+            } else {
+                self.levels.insert(*follower, level);
+            }
+            lags.insert(*follower, lag);
+        }
+        // no longer followers of this leader, e.g. reconfigured away
+        self.levels.retain(|follower, _| lags.contains_key(follower));
+        (lags, changes)
+    }
+
+    /// #Descriptions: forget the levels, once this node is no longer the leader.
+    pub fn reset(&mut self) {
+        self.levels.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follower_lag() {
+        let thresholds = LagThresholds {
+            warn: 100,
+            critical: 1000,
+        };
+        let mut lag = FollowerLag::default();
+        let (lags, changes) = lag.observe(500, &[(2, 500), (3, 450)], thresholds);
+        assert_eq!(lags, BTreeMap::from([(2, 0), (3, 50)]));
+        assert!(changes.is_empty());
+
+        let (_, changes) = lag.observe(700, &[(2, 690), (3, 450)], thresholds);
+        assert_eq!(changes, vec![(3, LagLevel::Warn, 250)]);
+        // alerted once per level
+        let (_, changes) = lag.observe(800, &[(2, 800), (3, 450)], thresholds);
+        assert!(changes.is_empty());
+        let (_, changes) = lag.observe(1500, &[(2, 1500), (3, 450)], thresholds);
+        assert_eq!(changes, vec![(3, LagLevel::Critical, 1050)]);
+        let (_, changes) = lag.observe(1500, &[(2, 1500), (3, 1490)], thresholds);
+        assert_eq!(changes, vec![(3, LagLevel::Ok, 10)]);
+
+        // 0 never alerts
+        let never = LagThresholds {
+            warn: 0,
+            critical: 0,
+        };
+        assert_eq!(never.level(u64::MAX), LagLevel::Ok);
+        lag.observe(2000, &[(2, 800)], thresholds);
+        lag.reset();
+        let (_, changes) = lag.observe(2000, &[(2, 800)], never);
+        assert!(changes.is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::namespace::namespace_of;
+
+/// Keys and bytes, of the keys and their values, held under a prefix.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixStats {
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// Statistics of the keyspace of a `KVStore`, kept up to date by every write so that
+/// they are read without a scan, for capacity planning.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyspaceStats {
+    /// every key, the reserved ones of the settings included
+    pub keys: u64,
+    pub bytes: u64,
+    /// the keys of each namespace
+    pub namespaces: BTreeMap<String, PrefixStats>,
+    /// the keys without namespace, by top-level prefix, see `top_level_prefix`
+    pub prefixes: BTreeMap<String, PrefixStats>,
+}
+
+impl KeyspaceStats {
+    /// #Descriptions: count `key` holding a value of `value_len` bytes.
+    pub fn add(&mut self, key: &str, value_len: usize) {
+        let bytes = (key.len() + value_len) as u64;
+        self.keys += 1;
+        self.bytes += bytes;
+        if let Some(stats) = self.stats_of(key) {
+            stats.keys += 1;
+            stats.bytes += bytes;
+        }
+    }
+
+    /// #Descriptions: no longer count `key` holding a value of `value_len` bytes.
+    pub fn remove(&mut self, key: &str, value_len: usize) {
+        let bytes = (key.len() + value_len) as u64;
+        self.keys = self.keys.saturating_sub(1);
+        self.bytes = self.bytes.saturating_sub(bytes);
+        let stats = match self.stats_of(key) {
+            Some(stats) => stats,
+            None => return,
+        };
+        stats.keys = stats.keys.saturating_sub(1);
+        stats.bytes = stats.bytes.saturating_sub(bytes);
+        // a prefix without keys is not listed
+        if stats.keys == 0 {
+            match namespace_of(key) {
+                Some(namespace) => self.namespaces.remove(namespace),
+                None => self.prefixes.remove(top_level_prefix(key).unwrap_or_default()),
+            };
+        }
+    }
+
+    /// #Descriptions: no longer count the keys of `namespace`, dropped all at once.
+    pub fn remove_namespace(&mut self, namespace: &str) {
+        if let Some(stats) = self.namespaces.remove(namespace) {
+            self.keys = self.keys.saturating_sub(stats.keys);
+            self.bytes = self.bytes.saturating_sub(stats.bytes);
+        }
+    }
+
+    fn stats_of(&mut self, key: &str) -> Option<&mut PrefixStats> {
+        match namespace_of(key) {
+            Some(namespace) => Some(self.namespaces.entry(namespace.to_string()).or_default()),
+            None => {
+                let prefix = top_level_prefix(key)?;
+                Some(self.prefixes.entry(prefix.to_string()).or_default())
+            }
+        }
+    }
+}
+
+/// #Descriptions: the top-level prefix of a key without namespace, its first `/`
+/// separated segment, e.g. `/app` for `/app/db/host` and `app` for `app/db`. Keys
+/// without a `/` after their first segment are under "", reserved keys under none.
+pub fn top_level_prefix(key: &str) -> Option<&str> {
+    if key.starts_with('\u{0}') {
+        return None;
+    }
+    let start = if key.starts_with('/') { 1 } else { 0 };
+    match key[start..].find('/') {
+        Some(end) => Some(&key[..start + end]),
+        None => Some(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::namespace::scoped_key;
+
+    #[test]
+    fn test_keyspace_stats() {
+        assert_eq!(top_level_prefix("/app/db/host"), Some("/app"));
+        assert_eq!(top_level_prefix("app/db"), Some("app"));
+        assert_eq!(top_level_prefix("k1"), Some(""));
+        assert_eq!(top_level_prefix("/k1"), Some(""));
+        assert_eq!(top_level_prefix("\u{0}config/compact_every"), None);
+
+        let mut stats = KeyspaceStats::default();
+        stats.add("/app/a", 2);
+        stats.add("/app/b", 4);
+        stats.add(&scoped_key("ns1", "k"), 1);
+        stats.add("\u{0}config/compact_every", 3);
+        assert_eq!((stats.keys, stats.bytes), (4, 52));
+        let app = PrefixStats { keys: 2, bytes: 18 };
+        assert_eq!(stats.prefixes.get("/app"), Some(&app));
+        assert_eq!(stats.namespaces["ns1"].keys, 1);
+
+        stats.remove(&scoped_key("ns1", "k"), 1);
+        stats.remove("/app/a", 2);
+        assert!(stats.namespaces.is_empty());
+        stats.add(&scoped_key("ns2", "k"), 1);
+        stats.remove_namespace("ns2");
+        assert_eq!(stats.prefixes["/app"], PrefixStats { keys: 1, bytes: 10 });
+        assert_eq!(stats.keys, 2);
+    }
+}
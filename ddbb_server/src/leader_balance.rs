@@ -0,0 +1,75 @@
+use omnipaxos_core::util::NodeId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Keeps the leadership off drained and low-priority nodes: after a restart or a
+/// failover the leader may be left on a node that should not lead, which no election
+/// fixes while it stays up. Run on the leader, it tells when to hand the leadership
+/// over, once a node it should go to has been connected long enough to be trusted.
+#[derive(Debug)]
+pub struct LeaderBalance {
+    /// a peer connected for less than this is left out, e.g. just restarted
+    settle: Duration,
+    /// when each connected peer was first seen connected
+    connected_since: HashMap<NodeId, Instant>,
+}
+
+impl LeaderBalance {
+    pub fn new(settle: Duration) -> Self {
+        Self {
+            settle,
+            connected_since: HashMap::new(),
+        }
+    }
+
+    /// #Descriptions: the peer the leader `leader` of priority `leader_priority` should
+    /// hand the leadership over to, given the `connected` peers with their priorities,
+    /// drained ones at `None`. A drained leader hands it to any peer that is not, the
+    /// others only to a peer of a higher priority; the peer with the highest is told.
+    pub fn evaluate(
+        &mut self,
+        now: Instant,
+        leader_priority: Option<u64>,
+        connected: &[(NodeId, Option<u64>)],
+    ) -> Option<NodeId> {
+        self.connected_since
+            .retain(|peer, _| connected.iter().any(|(id, _)| id == peer));
+        for (peer, _) in connected {
+            self.connected_since.entry(*peer).or_insert(now);
+        }
+        connected
+            .iter()
+            .filter(|(peer, _)| now.duration_since(self.connected_since[peer]) >= self.settle)
+            .filter_map(|(peer, priority)| priority.map(|priority| (priority, *peer)))
+            .filter(|(priority, _)| leader_priority.map_or(true, |leader| *priority > leader))
+            // the highest priority, then the lowest id
+            .max_by_key(|(priority, peer)| (*priority, std::cmp::Reverse(*peer)))
+            .map(|(_, peer)| peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leader_balance() {
+        let settle = Duration::from_secs(30);
+        let mut balance = LeaderBalance::new(settle);
+        let start = Instant::now();
+        let connected = [(2, Some(1)), (3, None)];
+        // not settled yet
+        assert_eq!(balance.evaluate(start, Some(0), &connected), None);
+        let settled = start + settle;
+        assert_eq!(balance.evaluate(settled, Some(0), &connected), Some(2));
+        assert_eq!(balance.evaluate(settled, Some(1), &connected), None);
+        // a drained leader hands over to any node not drained
+        assert_eq!(balance.evaluate(settled, None, &[(2, Some(0)), (3, Some(0))]), Some(2));
+        assert_eq!(balance.evaluate(settled, None, &[(3, None)]), None);
+
+        // a peer reconnecting settles again
+        assert_eq!(balance.evaluate(settled, Some(0), &[(3, None)]), None);
+        assert_eq!(balance.evaluate(settled, Some(0), &connected), None);
+        assert_eq!(balance.evaluate(settled + settle, Some(0), &connected), Some(2));
+    }
+}
@@ -1,7 +1,40 @@
 #![allow(unused)]
+pub mod backup;
+pub mod bootstrap;
+pub mod build_info;
+pub mod catch_up;
+pub mod client_limits;
+pub mod client_listener;
 pub mod config;
 pub mod ddbb_server;
+pub mod disk;
+pub mod dynamic_config;
+pub mod election;
+pub mod event_log;
+pub mod export;
+pub mod follower_lag;
+pub mod keyspace;
+pub mod leader_balance;
+pub mod memory;
+pub mod metrics;
+pub mod namespace;
+pub mod net;
 pub mod omni_paxos_server;
+pub mod proposal_queue;
+pub mod quorum;
+pub mod rate_limiter;
+pub mod restore;
+pub mod segments;
+pub mod semaphore;
+pub mod session;
+pub mod slow_log;
+pub mod snapshot_stream;
+pub mod state_machine;
+pub mod storage;
+pub mod tasks;
+pub mod tls;
+pub mod watch;
+pub mod zones;
 use ddbb_server::DDBB;
 use log::{debug, error, info, log_enabled, Level};
 use std::collections::HashMap;
@@ -0,0 +1,157 @@
+use omnipaxos_core::messages::{sequence_paxos::PaxosMsg, Message};
+use omnipaxos_core::storage::SnapshotType;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::omni_paxos_server::OmniMessage;
+use crate::op_data_structure::{LogEntry, Snapshot};
+
+/// bytes counted for a log or a msg on top of the keys and values it carries
+const LOG_OVERHEAD: u64 = 64;
+const MSG_OVERHEAD: u64 = 64;
+
+/// Bytes held by the queues of a node, sampled every `MEMORY_SAMPLE_PERIOD` and
+/// served with the metrics. Writes are shed once the total is over the budget.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    /// msgs OmniSIMO has not sent to the peers yet
+    pub outgoing_bytes: u64,
+    /// msgs received that omnipaxos has not handled yet
+    pub incoming_bytes: u64,
+    /// proposals waiting to be decided
+    pub pending_proposal_bytes: u64,
+    /// events streamed to the watches that their clients have not read yet
+    pub watch_queue_bytes: u64,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> u64 {
+        self.outgoing_bytes
+            + self.incoming_bytes
+            + self.pending_proposal_bytes
+            + self.watch_queue_bytes
+    }
+}
+
+/// Bytes queued somewhere the node can not look into, e.g. a channel, counted up by
+/// the producer and down by the consumer.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryGauge(Arc<AtomicU64>);
+
+impl MemoryGauge {
+    pub fn add(&self, bytes: u64) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn sub(&self, bytes: u64) {
+        // never below 0, whatever the order the two sides ran in
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |held| {
+                Some(held.saturating_sub(bytes))
+            });
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// #Descriptions: rough bytes `log` holds, its keys and values and a fixed overhead.
+pub fn log_size(log: &LogEntry) -> u64 {
+    let payload = match log {
+        LogEntry::SetValue { key, value }
+        | LogEntry::LINWrite { key, value, .. }
+        | LogEntry::PutIfRevision { key, value, .. }
+        | LogEntry::FencedWrite { key, value, .. }
+        | LogEntry::EphemeralSet { key, value, .. } => key.len() + value.len(),
+        LogEntry::LINRead { key, value, .. } | LogEntry::LINStat { key, value, .. } => {
+            key.len() + value.as_ref().map_or(0, Vec::len)
+        }
+        LogEntry::BulkSet { pairs, .. } => pairs
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum(),
+        LogEntry::DeleteTree { path, deleted, .. } => {
+            path.len() + deleted.iter().map(String::len).sum::<usize>()
+        }
+        LogEntry::DeletePrefix {
+            prefix, deleted, ..
+        } => {
+            prefix.len() + deleted.iter().map(String::len).sum::<usize>()
+        }
+        LogEntry::CloseSession { deleted, .. } => deleted.iter().map(String::len).sum(),
+        LogEntry::ValueChunk { data, .. } => data.len(),
+        LogEntry::ChunkedSet { key, .. } => key.len(),
+        _ => 0,
+    };
+    LOG_OVERHEAD + payload as u64
+}
+
+fn logs_size(logs: &[LogEntry]) -> u64 {
+    logs.iter().map(log_size).sum()
+}
+
+fn snapshot_size(snapshot: &Option<SnapshotType<LogEntry, Snapshot>>) -> u64 {
+    match snapshot {
+        Some(SnapshotType::Complete(snapshot)) | Some(SnapshotType::Delta(snapshot)) => {
+            logs_size(&snapshot.logs)
+        }
+        _ => 0,
+    }
+}
+
+/// #Descriptions: rough bytes `msg` holds, the logs it carries and a fixed overhead.
+pub fn msg_size(msg: &OmniMessage) -> u64 {
+    let logs = match msg {
+        Message::SequencePaxos(paxos) => match &paxos.msg {
+            PaxosMsg::AcceptDecide(accept) => logs_size(&accept.entries),
+            PaxosMsg::AcceptSync(sync) => {
+                logs_size(&sync.suffix) + snapshot_size(&sync.decided_snapshot)
+            }
+            PaxosMsg::Promise(promise) => {
+                logs_size(&promise.suffix) + snapshot_size(&promise.decided_snapshot)
+            }
+            PaxosMsg::ProposalForward(logs) => logs_size(logs),
+            _ => 0,
+        },
+        Message::BLE(_) => 0,
+    };
+    MSG_OVERHEAD + logs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use omnipaxos_core::ballot_leader_election::Ballot;
+    use omnipaxos_core::messages::sequence_paxos::{AcceptDecide, PaxosMessage};
+
+    #[test]
+    fn test_memory_sizes() {
+        let set = LogEntry::SetValue {
+            key: "k1".to_string(),
+            value: vec![0; 100],
+        };
+        assert_eq!(log_size(&set), LOG_OVERHEAD + 102);
+        assert_eq!(log_size(&LogEntry::Compact), LOG_OVERHEAD);
+
+        let accept = Message::SequencePaxos(PaxosMessage {
+            from: 1,
+            to: 2,
+            msg: PaxosMsg::AcceptDecide(AcceptDecide {
+                n: Ballot::with(1, 0, 1),
+                decided_idx: 0,
+                entries: vec![set.clone(), set],
+            }),
+        });
+        assert_eq!(msg_size(&accept), MSG_OVERHEAD + 2 * (LOG_OVERHEAD + 102));
+
+        let gauge = MemoryGauge::default();
+        gauge.add(10);
+        gauge.sub(4);
+        assert_eq!(gauge.get(), 6);
+        gauge.sub(10);
+        assert_eq!(gauge.get(), 0);
+    }
+}
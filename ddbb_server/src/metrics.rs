@@ -0,0 +1,307 @@
+use omnipaxos_core::ballot_leader_election::Ballot;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::build_info::BuildInfo;
+use crate::client_limits::ClientConnectionStats;
+use crate::memory::MemoryUsage;
+use crate::omni_paxos_server::op_accept::InboundStats;
+use crate::omni_paxos_server::op_latency::LinkLatency;
+use crate::watch::WatchStats;
+
+/// Counters of a DDBB node, served as json by the admin API.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Metrics {
+    /// writes rejected because the OmniSIMO outgoing buffer was too long
+    pub shed_outgoing_buffer: u64,
+    /// writes rejected because too many proposals were waiting to be decided
+    pub shed_pending_proposals: u64,
+    /// writes rejected because too many decided logs were waiting to be applied
+    pub shed_apply_backlog: u64,
+    /// writes rejected because the queues held more than the memory budget
+    pub shed_memory_budget: u64,
+    /// proposals queued while no leader was established
+    pub queued_proposals: u64,
+    /// proposals rejected because the queue was full
+    pub shed_proposal_queue: u64,
+    /// queued proposals dropped once their proposer gave up waiting
+    pub expired_queued_proposals: u64,
+    /// proposals in flight proposed again to a new leader
+    pub reproposed_proposals: u64,
+    /// group commits proposed by the leader, and the writes in them
+    pub group_commits: u64,
+    pub group_commit_logs: u64,
+    /// writes in the largest group commit
+    pub group_commit_max_logs: u64,
+    /// time the first write of each group commit was held, summed, in us
+    pub group_commit_wait_us: u64,
+    /// out of reach of a majority, see `QUORUM_LOSS_TIMEOUT`, and the proposals failed
+    /// with `QuorumLost`, in flight or new
+    pub quorum_lost: bool,
+    pub failed_quorum_lost: u64,
+    /// on the leader, the logs each follower is behind it, by node id, and the warnings
+    /// and errors logged for followers over `follower_lag_warn` or `follower_lag_critical`
+    pub follower_lag: BTreeMap<u64, u64>,
+    pub follower_lag_alerts: u64,
+    /// bytes free in the data directory as of the last check, whether the node is
+    /// read-only for being under `--disk-low-watermark`, and the writes it rejected
+    pub disk_free_bytes: u64,
+    pub read_only: bool,
+    pub rejected_read_only: u64,
+    /// records failing their checksum on start, see `ClusterEvent::StorageCorrupted`
+    pub storage_corruptions: u64,
+    /// bytes held by the queues as of the last sample, and the budget they are held to
+    pub memory: MemoryUsage,
+    pub memory_budget: u64,
+    /// connections of the client listener, open, rejected over the limits and evicted
+    pub client_connections: ClientConnectionStats,
+    /// connections of the OmniSIMO listener, accepted and rejected over the limits or
+    /// without a valid handshake
+    pub peer_connections: InboundStats,
+    /// watches ended or coalesced because their client fell behind
+    pub watches: WatchStats,
+}
+
+/// Where a DDBB node is in the log, served as json by the admin API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub node_id: u64,
+    /// the zone the node runs in, see `--zone`
+    #[serde(default)]
+    pub zone: Option<String>,
+    /// the ddbb_server the node runs
+    pub build: BuildInfo,
+    pub role: NodeRole,
+    /// the leader this node follows, if any
+    pub leader: Option<u64>,
+    /// ballot of that leader
+    pub ballot: Option<Ballot>,
+    /// logs decided by omnipaxos
+    pub decided_idx: u64,
+    /// logs applied to the state machine, restored from the snapshot after a restart
+    pub applied_idx: u64,
+    pub apply_lag: u64,
+    /// keys held by the state machine and their bytes, see `AdminEntry::Keyspace`
+    #[serde(default)]
+    pub keys: u64,
+    #[serde(default)]
+    pub key_bytes: u64,
+    /// traffic on the links to the peers, by node id
+    pub peers: BTreeMap<u64, PeerStats>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    Leader,
+    Follower,
+    /// replicates the log without voting
+    Learner,
+    /// a learner serving stale reads and watches only, see `--observer`
+    Observer,
+}
+
+/// Traffic on the OmniSIMO links to and from one peer, to spot a flaky link.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// the outgoing connection to the peer is up
+    pub connected: bool,
+    pub msgs_sent: u64,
+    pub bytes_sent: u64,
+    pub msgs_received: u64,
+    pub bytes_received: u64,
+    /// unix ms at which the peer was last heard from, 0 for never
+    pub last_seen_ms: u64,
+    /// times the outgoing connection was lost and connected again
+    pub reconnects: u64,
+    /// msgs to the peer waiting in the outgoing buffer
+    pub queue_depth: u64,
+    /// the build the peer sent in its handshake, once it connected to this node
+    pub build: Option<BuildInfo>,
+    /// latency injected on the link to the peer through the admin API
+    #[serde(default)]
+    pub injected_latency: Option<LinkLatency>,
+    /// heartbeat datagrams from the peer lost or reordered, with `--heartbeat-transport udp`
+    #[serde(default)]
+    pub heartbeats_lost: u64,
+    /// connections open from the peer to this node, live, catch-up and heartbeats
+    #[serde(default)]
+    pub inbound_connections: u64,
+    /// gaps in the frames from the peer, each followed by a resync with it
+    #[serde(default)]
+    pub session_resets: u64,
+    /// frames from the peer dropped as numbered before the ones read already
+    #[serde(default)]
+    pub stale_frames: u64,
+}
+
+/// The status of every node, gathered by the node asked, served as json by the admin API.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ClusterStatus {
+    pub nodes: BTreeMap<u64, NodeStatus>,
+    /// the nodes that did not answer, with why
+    pub unreachable: BTreeMap<u64, String>,
+    /// by node id, whether the connection of the node to each of its peers is up
+    pub connectivity: BTreeMap<u64, BTreeMap<u64, bool>>,
+    /// the nodes running each version, more than one during a rolling upgrade
+    pub versions: BTreeMap<String, Vec<u64>>,
+}
+
+impl ClusterStatus {
+    pub fn add(&mut self, status: NodeStatus) {
+        let links = status
+            .peers
+            .iter()
+            .map(|(peer_id, stats)| (*peer_id, stats.connected))
+            .collect();
+        self.connectivity.insert(status.node_id, links);
+        self.versions
+            .entry(status.build.version_string())
+            .or_default()
+            .push(status.node_id);
+        self.nodes.insert(status.node_id, status);
+    }
+
+    pub fn add_unreachable(&mut self, node_id: u64, error: String) {
+        self.unreachable.insert(node_id, error);
+    }
+
+    /// #Descriptions: the nodes and the links between them as a DOT graph, e.g. for
+    /// `dot -Tsvg`. The leader is bold, an unreachable node dashed, a link that is up
+    /// green and one that is down red. The lag of a node is behind the highest decided
+    /// index of the cluster.
+    pub fn to_dot(&self) -> String {
+        let decided_idx = self
+            .nodes
+            .values()
+            .map(|status| status.decided_idx)
+            .max()
+            .unwrap_or(0);
+        let mut dot = String::from("digraph ddbb {\n");
+        for (node_id, status) in &self.nodes {
+            let mut label = format!("node {}\\n{}", node_id, role_name(status.role));
+            if let Some(zone) = &status.zone {
+                label.push_str(&format!(" in {}", dot_escape(zone)));
+            }
+            label.push_str(&format!(
+                "\\napplied {}, lag {}",
+                status.applied_idx,
+                decided_idx.saturating_sub(status.applied_idx)
+            ));
+            let style = if status.role == NodeRole::Leader { "bold" } else { "solid" };
+            dot.push_str(&format!(
+                "  \"{}\" [label=\"{}\", style={}];\n",
+                node_id, label, style
+            ));
+        }
+        for (node_id, error) in &self.unreachable {
+            let label = format!("node {}\\nunreachable: {}", node_id, dot_escape(error));
+            dot.push_str(&format!(
+                "  \"{}\" [label=\"{}\", style=dashed];\n",
+                node_id, label
+            ));
+        }
+        for (node_id, links) in &self.connectivity {
+            for (peer_id, connected) in links {
+                let attrs = if *connected {
+                    "color=green"
+                } else {
+                    "color=red, style=dashed"
+                };
+                dot.push_str(&format!("  \"{}\" -> \"{}\" [{}];\n", node_id, peer_id, attrs));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn role_name(role: NodeRole) -> &'static str {
+    match role {
+        NodeRole::Leader => "leader",
+        NodeRole::Follower => "follower",
+        NodeRole::Learner => "learner",
+        NodeRole::Observer => "observer",
+    }
+}
+
+/// `text` within a DOT string, on one line.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(node_id: u64, connected: &[(u64, bool)]) -> NodeStatus {
+        NodeStatus {
+            node_id,
+            zone: None,
+            build: BuildInfo::current(),
+            role: NodeRole::Follower,
+            leader: None,
+            ballot: None,
+            decided_idx: 0,
+            applied_idx: 0,
+            apply_lag: 0,
+            keys: 0,
+            key_bytes: 0,
+            peers: connected
+                .iter()
+                .map(|(peer_id, connected)| {
+                    let stats = PeerStats {
+                        connected: *connected,
+                        ..PeerStats::default()
+                    };
+                    (*peer_id, stats)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_cluster_status() {
+        let mut cluster = ClusterStatus::default();
+        cluster.add(status(1, &[(2, true), (3, false)]));
+        // as sent by a peer over its admin port
+        let json = serde_json::to_string(&status(2, &[(1, true), (3, true)])).unwrap();
+        cluster.add(serde_json::from_str(&json).unwrap());
+        cluster.add_unreachable(3, "connection refused".to_string());
+
+        assert_eq!(cluster.nodes.len(), 2);
+        assert!(!cluster.connectivity[&1][&3]);
+        assert!(cluster.connectivity[&2][&3]);
+        assert!(cluster.unreachable.contains_key(&3));
+        assert_eq!(cluster.versions.len(), 1);
+        let mut upgraded = status(3, &[]);
+        upgraded.build.version = "0.2.0".to_string();
+        cluster.add(upgraded);
+        assert_eq!(cluster.versions.len(), 2);
+    }
+
+    #[test]
+    fn test_cluster_dot() {
+        let mut cluster = ClusterStatus::default();
+        let mut leader = status(1, &[(2, true), (3, false)]);
+        leader.role = NodeRole::Leader;
+        leader.decided_idx = 10;
+        leader.applied_idx = 10;
+        cluster.add(leader);
+        let mut follower = status(2, &[(1, true), (3, false)]);
+        follower.applied_idx = 7;
+        follower.zone = Some("eu \"1\"".to_string());
+        cluster.add(follower);
+        cluster.add_unreachable(3, "no answer".to_string());
+
+        let dot = cluster.to_dot();
+        assert!(dot.starts_with("digraph ddbb {\n"));
+        assert!(dot.contains(r#""1" [label="node 1\nleader\napplied 10, lag 0", style=bold];"#));
+        assert!(dot.contains(r#"follower in eu \"1\"\napplied 7, lag 3""#));
+        assert!(dot.contains(r#""3" [label="node 3\nunreachable: no answer", style=dashed];"#));
+        assert!(dot.contains(r#""1" -> "2" [color=green];"#));
+        assert!(dot.contains(r#""2" -> "3" [color=red, style=dashed];"#));
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+/// Keys of a namespace are stored under this prefix, keys sent without a
+/// namespace may not start with it.
+pub const NAMESPACE_KEY_PREFIX: &str = "\u{0}ns/";
+
+/// A prefix isolated keyspace, created and deleted through the log.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Namespace {
+    /// keys the namespace may hold, 0 for no limit
+    pub max_keys: u64,
+    /// required in the header of every command to the namespace
+    pub token: Option<String>,
+    /// keys currently held
+    pub keys: u64,
+}
+
+impl Namespace {
+    pub fn new(max_keys: u64, token: Option<String>) -> Self {
+        Self {
+            max_keys,
+            token,
+            keys: 0,
+        }
+    }
+
+    /// Whether one more key fits in the quota.
+    pub fn has_room(&self) -> bool {
+        self.max_keys == 0 || self.keys < self.max_keys
+    }
+}
+
+/// #Descriptions: a name may not contain the separator of scoped keys.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.contains('\u{0}')
+}
+
+/// #Descriptions: the key `key` of `namespace` is stored under.
+pub fn scoped_key(namespace: &str, key: &str) -> String {
+    format!("{}{}/{}", NAMESPACE_KEY_PREFIX, namespace, key)
+}
+
+/// #Descriptions: the namespace a stored key belongs to, `None` for keys without namespace.
+pub fn namespace_of(key: &str) -> Option<&str> {
+    key.strip_prefix(NAMESPACE_KEY_PREFIX)?.split('/').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scoped_key() {
+        let key = scoped_key("app1", "config/k1");
+        assert_eq!(namespace_of(&key), Some("app1"));
+        assert_eq!(namespace_of("config/k1"), None);
+        assert!(key.starts_with(&scoped_key("app1", "")));
+        assert!(!key.starts_with(&scoped_key("app", "")));
+
+        assert!(is_valid_name("app1"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("app/1"));
+    }
+}
@@ -0,0 +1,117 @@
+use log::error;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+
+use crate::config::{
+    CONNECT_TIMEOUT, LISTEN_BACKLOG, LISTEN_DUAL_STACK, LISTEN_REUSEADDR, SOCKET_RECV_BUFFER_SIZE,
+    SOCKET_SEND_BUFFER_SIZE, TCP_NODELAY,
+};
+use ddbb_libs::Result;
+
+/// Socket options of the OmniSIMO and client listeners, and of their connections.
+#[derive(Clone, Debug)]
+pub struct ListenerOptions {
+    pub reuseaddr: bool,
+    /// also set on accepted and outgoing connections
+    pub nodelay: bool,
+    pub backlog: i32,
+    /// accept IPv4 too when bound to an IPv6 address such as `[::]:6550`
+    pub dual_stack: bool,
+    /// SO_SNDBUF and SO_RCVBUF of the listeners and connections, the kernel default
+    /// and its autotuning if `None`
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// of outgoing connections, the OS timeout if `None`
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        Self {
+            reuseaddr: LISTEN_REUSEADDR,
+            nodelay: TCP_NODELAY,
+            backlog: LISTEN_BACKLOG,
+            dual_stack: LISTEN_DUAL_STACK,
+            send_buffer_size: SOCKET_SEND_BUFFER_SIZE,
+            recv_buffer_size: SOCKET_RECV_BUFFER_SIZE,
+            connect_timeout: Some(CONNECT_TIMEOUT),
+        }
+    }
+}
+
+/// #Descriptions: bind `addr`, either an IPv4 or IPv6 socket address such as
+/// `127.0.0.1:6550` and `[::1]:6550`, or a host name with a port.
+pub async fn bind_listener(addr: &str, options: &ListenerOptions) -> Result<TcpListener> {
+    let socket_addr = match addr.parse::<SocketAddr>() {
+        Ok(socket_addr) => socket_addr,
+        Err(_) => lookup_host(addr)
+            .await?
+            .next()
+            .ok_or(format!("can not resolve {}", addr))?,
+    };
+    let socket = Socket::new(
+        Domain::for_address(socket_addr),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if socket_addr.is_ipv6() {
+        socket.set_only_v6(!options.dual_stack)?;
+    }
+    socket.set_reuse_address(options.reuseaddr)?;
+    // before listening, accepted connections inherit them and the window scale they allow
+    set_buffer_sizes(&socket, options)?;
+    socket.bind(&socket_addr.into())?;
+    socket.listen(options.backlog)?;
+    socket.set_nonblocking(true)?;
+    let listener: std::net::TcpListener = socket.into();
+    Ok(TcpListener::from_std(listener)?)
+}
+
+fn set_buffer_sizes(socket: &Socket, options: &ListenerOptions) -> io::Result<()> {
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    Ok(())
+}
+
+/// #Descriptions: set TCP_NODELAY and the buffer sizes of `options` on an accepted or
+/// outgoing connection. Small consensus msgs are not held back by Nagle with nodelay.
+pub fn set_socket_options(tcp_stream: &TcpStream, options: &ListenerOptions) {
+    if let Err(e) = tcp_stream.set_nodelay(options.nodelay) {
+        error!("Set nodelay failed: {:?}", e);
+    }
+    if let Err(e) = set_buffer_sizes(&SockRef::from(tcp_stream), options) {
+        error!("Set socket buffer sizes failed: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socket_options() {
+        let options = ListenerOptions {
+            send_buffer_size: Some(256 * 1024),
+            recv_buffer_size: Some(256 * 1024),
+            ..ListenerOptions::default()
+        };
+        let listener = bind_listener("127.0.0.1:0", &options).await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        set_socket_options(&stream, &options);
+        assert!(stream.nodelay().unwrap());
+        let socket = SockRef::from(&stream);
+        // the kernel may round the sizes up, e.g. Linux doubles them
+        assert!(socket.send_buffer_size().unwrap() >= 256 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 256 * 1024);
+        let (accepted, _) = listener.accept().await.unwrap();
+        assert!(SockRef::from(&accepted).recv_buffer_size().unwrap() >= 256 * 1024);
+    }
+}
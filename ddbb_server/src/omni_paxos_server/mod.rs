@@ -2,59 +2,249 @@ use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
 };
-use log::debug;
-use tokio::{runtime::Builder, sync::mpsc, time};
+use log::{debug, info};
+use tokio::{
+    runtime::Builder,
+    sync::mpsc,
+    time::{self, MissedTickBehavior},
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use ddbb_libs::clock::{system_clock, SharedClock, Ticker};
 
 use omnipaxos_core::{
-    messages::Message, omni_paxos::*, util::LogEntry as OmniLogEntry, util::NodeId,
+    ballot_leader_election::Ballot, messages::Message, omni_paxos::*,
+    util::LogEntry as OmniLogEntry, util::NodeId,
 };
-use omnipaxos_storage::memory_storage::MemoryStorage;
 
+use self::op_events::{LeaderChange, Reconfig, ServerEvents, SharedServerEvents, SnapshotInstalled};
+use self::op_partition::PartitionTracker;
 use self::{op_connection::OmniSIMO, op_data_structure::Snapshot};
+use crate::catch_up::CatchUp;
 use crate::config::{ELECTION_TIMEOUT, OUTGOING_MESSAGE_PERIOD};
+use crate::event_log::SharedEventLog;
+use crate::storage::{DDBBStorage, StorageFlusher};
 use op_data_structure::LogEntry;
 
+pub mod op_accept;
 pub mod op_connection;
+pub mod op_events;
+pub mod op_latency;
+pub mod op_order;
+pub mod op_partition;
+pub mod op_udp;
 pub mod op_data_structure;
 
-pub type OmniPaxosInstance = OmniPaxos<LogEntry, Snapshot, MemoryStorage<LogEntry, ()>>;
+pub type OmniPaxosInstance = OmniPaxos<LogEntry, Snapshot, DDBBStorage>;
 pub type OmniMessage = Message<LogEntry, Snapshot>;
+/// A decided log together with its index in the log.
+pub type DecidedEntry = (u64, LogEntry);
 
 pub struct OmniPaxosServer {
     pub omni_paxos_instance: Arc<Mutex<OmniPaxosInstance>>,
     pub omni_simo: Arc<Mutex<OmniSIMO>>,
+    /// index up to which decided logs have been published
+    decided_idx: u64,
+    decided_subscribers: Vec<mpsc::UnboundedSender<DecidedEntry>>,
+    catch_up: Option<Arc<Mutex<CatchUp>>>,
+    /// told of the leader changes and reconfigurations
+    server_events: SharedServerEvents,
+    /// leader of the last leader change
+    leader_ballot: Option<Ballot>,
+    reconfigured: bool,
+    storage: Option<StorageFlusher>,
+    /// times the ballot leader election
+    clock: SharedClock,
+    /// resyncs omnipaxos once this node rejoins after a partition
+    partition: PartitionTracker,
 }
 
 impl OmniPaxosServer {
+    pub fn new(
+        omni_paxos_instance: Arc<Mutex<OmniPaxosInstance>>,
+        omni_simo: Arc<Mutex<OmniSIMO>>,
+    ) -> Self {
+        OmniPaxosServer {
+            omni_paxos_instance,
+            omni_simo,
+            decided_idx: 0,
+            decided_subscribers: Vec::new(),
+            catch_up: None,
+            server_events: ServerEvents::shared(),
+            leader_ballot: None,
+            reconfigured: false,
+            storage: None,
+            clock: system_clock(),
+            partition: PartitionTracker::default(),
+        }
+    }
+
+    /// #Descriptions: tick the ballot leader election on `clock`.
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    /// #Descriptions: record leader changes and reconfigurations in `events`.
+    pub fn track_events(&mut self, events: SharedEventLog) {
+        self.server_events.lock().unwrap().record_in(events);
+    }
+
+    /// #Descriptions: tell the subscribers of `server_events`, e.g. the ones of the
+    /// DDBB, which also tells them of the snapshots it installs.
+    pub fn set_server_events(&mut self, server_events: SharedServerEvents) {
+        self.server_events = server_events;
+    }
+
+    pub fn on_leader_change(&self, callback: impl Fn(&LeaderChange) + Send + 'static) {
+        self.server_events.lock().unwrap().on_leader_change(callback);
+    }
+
+    pub fn on_reconfig(&self, callback: impl Fn(&Reconfig) + Send + 'static) {
+        self.server_events.lock().unwrap().on_reconfig(callback);
+    }
+
+    pub fn on_snapshot_installed(&self, callback: impl Fn(&SnapshotInstalled) + Send + 'static) {
+        self.server_events.lock().unwrap().on_snapshot_installed(callback);
+    }
+
+    fn publish_events(&mut self) {
+        let (ballot, stopsign) = {
+            let omni = self.omni_paxos_instance.lock().unwrap();
+            (omni.get_current_leader_ballot(), omni.is_reconfigured())
+        };
+        if let Some(ballot) = ballot.filter(|ballot| Some(*ballot) != self.leader_ballot) {
+            let previous = self.leader_ballot.replace(ballot);
+            self.server_events.lock().unwrap().leader_changed(LeaderChange {
+                previous,
+                leader: ballot,
+            });
+        }
+        if let Some(stopsign) = stopsign.filter(|_| !self.reconfigured) {
+            self.reconfigured = true;
+            self.server_events.lock().unwrap().reconfigured(Reconfig {
+                config_id: stopsign.config_id,
+                nodes: stopsign.nodes,
+            });
+        }
+    }
+
+    /// #Descriptions: publish decided logs from `decided_idx` on, e.g. the applied
+    /// index restored from a snapshot, instead of replaying the whole log.
+    pub fn start_from(&mut self, decided_idx: u64) {
+        self.decided_idx = decided_idx;
+    }
+
+    /// #Descriptions: flush the writes batched by the storage of the instance before
+    /// sending the msgs, or publishing the logs decided, that depend on them.
+    pub fn flush_storage_with(&mut self, storage: StorageFlusher) {
+        self.storage = Some(storage);
+    }
+
+    fn flush_storage(&self) {
+        if let Some(storage) = &self.storage {
+            storage.flush();
+        }
+    }
+
+    /// #Descriptions: keep `catch_up` updated with the incoming and decided logs.
+    pub fn track_catch_up(&mut self, catch_up: Arc<Mutex<CatchUp>>) {
+        self.catch_up = Some(catch_up);
+    }
+
+    /// #Descriptions: subscribe to logs decided from now on, they are pushed by `run`
+    /// as soon as omnipaxos decides them.
+    pub fn decided_stream(&mut self) -> UnboundedReceiverStream<DecidedEntry> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.decided_subscribers.push(sender);
+        UnboundedReceiverStream::new(receiver)
+    }
+
+    fn publish_decided(&mut self) {
+        let decided_entries = {
+            let omni = self.omni_paxos_instance.lock().unwrap();
+            if omni.get_decided_idx() <= self.decided_idx {
+                return;
+            }
+            omni.read_decided_suffix(self.decided_idx)
+        };
+        // the logs are answered to clients once applied
+        self.flush_storage();
+        if let Some(entries) = decided_entries {
+            for entry in entries {
+                let idx = self.decided_idx;
+                self.decided_idx += 1;
+                if let OmniLogEntry::Decided(log) = entry {
+                    // drop subscribers whose stream is gone
+                    self.decided_subscribers
+                        .retain(|subscriber| subscriber.send((idx, log.clone())).is_ok());
+                }
+            }
+        }
+    }
+
+    /// Have omnipaxos ask the peers for a `Prepare` once a majority is in reach again
+    /// after a partition, to catch up at once: a follower of the leader it lost goes
+    /// back to recovering, and the leader of the cluster now syncs it.
+    fn resync_on_rejoin(&mut self) {
+        let connectivity = self.omni_simo.lock().unwrap().connectivity();
+        if let Some(peers) = self.partition.observe(&connectivity) {
+            info!("Rejoined the cluster with {:?}, resync", peers);
+            let mut omni = self.omni_paxos_instance.lock().unwrap();
+            for peer in peers {
+                omni.reconnected(peer);
+            }
+        }
+    }
+
     async fn send_outgoing_msgs(&mut self) {
+        // e.g. a promise or an accepted is only sent once on disk
+        self.flush_storage();
         let messages: Vec<OmniMessage> =
             self.omni_paxos_instance.lock().unwrap().outgoing_messages();
-        for msg in messages {
-            {
-                // debug!("SEND: {:?}", msg);
-                self.omni_simo.lock().unwrap().send_message(&msg);
-            }
-        }
+        // the heartbeats and accepts to a peer of this tick may go in one frame
+        self.omni_simo.lock().unwrap().send_messages(&messages);
     }
 
     pub(crate) async fn run(&mut self) {
         let mut outgoing_interval = time::interval(OUTGOING_MESSAGE_PERIOD);
-        let mut election_interval = time::interval(ELECTION_TIMEOUT);
+        outgoing_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // a stalled loop must not time out several elections at once
+        let mut election_ticker = Ticker::new(self.clock.clone(), ELECTION_TIMEOUT);
         loop {
             tokio::select! {
                 biased;
 
-                _ = election_interval.tick() => { self.omni_paxos_instance.lock().unwrap().election_timeout(); },
-                _ = outgoing_interval.tick() => { self.send_outgoing_msgs().await; },
+                _ = election_ticker.tick() => {
+                    let now = self.clock.now();
+                    self.omni_paxos_instance.lock().unwrap().election_timeout_at(now);
+                },
+                _ = outgoing_interval.tick() => {
+                    self.resync_on_rejoin();
+                    self.send_outgoing_msgs().await;
+                },
                 Ok(in_msg) = OmniSIMO::receive_message(self.omni_simo.clone()) => {
                     if let Message::SequencePaxos(msg) = in_msg.clone(){
                         debug!("RECEIVE: {:?}", msg);
                     } else {
                         // debug!("RECEIVE: {:?}", in_msg);
                     };
-                    self.omni_paxos_instance.lock().unwrap().handle_incoming(in_msg); },
+                    if let Some(catch_up) = &self.catch_up {
+                        catch_up.lock().unwrap().observe(&in_msg);
+                    }
+                    // the gap is before this msg, omnipaxos must not take it as the next one
+                    let resets = self.omni_simo.lock().unwrap().take_session_resets();
+                    let mut omni = self.omni_paxos_instance.lock().unwrap();
+                    for peer in resets {
+                        omni.reconnected(peer);
+                    }
+                    omni.handle_incoming_at(in_msg, self.clock.now()); },
                 else => { }
             }
+            self.publish_decided();
+            self.publish_events();
+            if let Some(catch_up) = &self.catch_up {
+                catch_up.lock().unwrap().set_decided(self.decided_idx);
+            }
         }
     }
 }
@@ -90,7 +280,7 @@ mod test {
                 ..Default::default()
             };
             let omni: Arc<Mutex<OmniPaxosInstance>> =
-                Arc::new(Mutex::new(op_config.build(MemoryStorage::default())));
+                Arc::new(Mutex::new(op_config.build(DDBBStorage::default())));
             let omni_simo = OmniSIMO::new(servers.get(&nodeid).unwrap().to_string(), peers);
             let omni_simo = Arc::new(Mutex::new(omni_simo));
 
@@ -103,10 +293,7 @@ mod test {
                 // OmniSIMO::start_sender(omni_simo_copy2).await;
             });
 
-            let mut op_server = OmniPaxosServer {
-                omni_paxos_instance: omni.clone(),
-                omni_simo,
-            };
+            let mut op_server = OmniPaxosServer::new(omni.clone(), omni_simo);
             let join_handle = tokio::spawn({
                 async move {
                     op_server.run().await;
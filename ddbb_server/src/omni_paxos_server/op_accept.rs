@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use omnipaxos_core::util::NodeId;
+
+use crate::bootstrap::NodeIdentity;
+use crate::config::{
+    PEER_ACCEPT_BURST, PEER_ACCEPT_RATE, PEER_HANDSHAKE_TIMEOUT, PEER_MAX_CONNECTIONS,
+    PEER_MAX_CONNECTIONS_PER_NODE,
+};
+use crate::rate_limiter::TokenBucket;
+
+/// Limits on the connections the OmniSIMO listener accepts, 0 for no limit.
+#[derive(Clone, Debug)]
+pub struct AcceptLimits {
+    /// connections accepted per second on average, and in a burst
+    pub accept_rate: f64,
+    pub accept_burst: f64,
+    /// connections open at once, from any address
+    pub max_connections: usize,
+    /// connections open at once as one node: live, catch-up and heartbeats
+    pub max_connections_per_node: usize,
+    /// a connection that did not send its handshake within this is closed, so an
+    /// unknown client does not hold a connection
+    pub handshake_timeout: Duration,
+}
+
+impl Default for AcceptLimits {
+    fn default() -> Self {
+        Self {
+            accept_rate: PEER_ACCEPT_RATE,
+            accept_burst: PEER_ACCEPT_BURST,
+            max_connections: PEER_MAX_CONNECTIONS,
+            max_connections_per_node: PEER_MAX_CONNECTIONS_PER_NODE,
+            handshake_timeout: PEER_HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+/// The incoming connections of the listener, served with the metrics.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundStats {
+    pub accepted: u64,
+    pub open: u64,
+    /// connections closed at once because they came faster than `accept_rate`
+    pub rejected_rate: u64,
+    /// connections closed at once because `max_connections` were open, or once they
+    /// sent their handshake because `max_connections_per_node` were open as the node
+    pub rejected_limit: u64,
+    /// connections closed without a valid handshake within `handshake_timeout`
+    pub rejected_handshake: u64,
+    /// connections closed because the same process opened a new one of their channel,
+    /// e.g. once it reconnected while the old one was still up at this end
+    #[serde(default)]
+    pub superseded: u64,
+}
+
+/// A connection attributed to a node.
+#[derive(Debug)]
+struct Attributed {
+    permit_id: u64,
+    addr: SocketAddr,
+    instance_id: String,
+    channel: Option<String>,
+    close: Arc<Notify>,
+}
+
+#[derive(Debug, Default)]
+struct Tracked {
+    /// the connections of each node
+    by_node: HashMap<NodeId, Vec<Attributed>>,
+    stats: InboundStats,
+    next_permit_id: u64,
+}
+
+/// The incoming connections counted against the `AcceptLimits`, each attributed to
+/// the node it sent the handshake as.
+#[derive(Clone, Debug)]
+pub struct InboundConnections {
+    limits: AcceptLimits,
+    rate: Arc<Mutex<TokenBucket>>,
+    tracked: Arc<Mutex<Tracked>>,
+}
+
+impl InboundConnections {
+    pub fn new(limits: AcceptLimits) -> Self {
+        Self {
+            rate: Arc::new(Mutex::new(TokenBucket::new(
+                limits.accept_rate,
+                limits.accept_burst,
+            ))),
+            limits,
+            tracked: Arc::new(Mutex::new(Tracked::default())),
+        }
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        self.limits.handshake_timeout
+    }
+
+    /// #Descriptions: count a connection accepted from `addr` at `now`, until the returned
+    /// permit is dropped. `None` if it goes over the accept rate or `max_connections`.
+    pub fn open(&self, addr: SocketAddr, now: Instant) -> Option<InboundPermit> {
+        let limited = self.limits.accept_rate > 0.0;
+        if limited && !self.rate.lock().unwrap().try_take_at(now) {
+            self.tracked.lock().unwrap().stats.rejected_rate += 1;
+            return None;
+        }
+        let mut tracked = self.tracked.lock().unwrap();
+        let max = self.limits.max_connections;
+        if max != 0 && tracked.stats.open >= max as u64 {
+            tracked.stats.rejected_limit += 1;
+            return None;
+        }
+        tracked.stats.accepted += 1;
+        tracked.stats.open += 1;
+        tracked.next_permit_id += 1;
+        Some(InboundPermit {
+            connections: self.clone(),
+            id: tracked.next_permit_id,
+            addr,
+            node: None,
+            superseded: Arc::new(Notify::new()),
+        })
+    }
+
+    pub fn rejected_handshake(&self) {
+        self.tracked.lock().unwrap().stats.rejected_handshake += 1;
+    }
+
+    pub fn stats(&self) -> InboundStats {
+        self.tracked.lock().unwrap().stats.clone()
+    }
+
+    /// #Descriptions: the addresses of the connections open as each node.
+    pub fn by_node(&self) -> BTreeMap<NodeId, Vec<SocketAddr>> {
+        self.tracked
+            .lock()
+            .unwrap()
+            .by_node
+            .iter()
+            .map(|(node, attributed)| {
+                (*node, attributed.iter().map(|conn| conn.addr).collect())
+            })
+            .collect()
+    }
+}
+
+/// An incoming connection counted by `InboundConnections`, until dropped.
+#[derive(Debug)]
+pub struct InboundPermit {
+    connections: InboundConnections,
+    id: u64,
+    addr: SocketAddr,
+    /// the node the connection sent the handshake as
+    node: Option<NodeId>,
+    /// notified once a newer connection of the node replaces this one
+    superseded: Arc<Notify>,
+}
+
+impl InboundPermit {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn node(&self) -> Option<NodeId> {
+        self.node
+    }
+
+    /// Notified once the connection is superseded, its reader must close it then.
+    pub fn superseded(&self) -> Arc<Notify> {
+        self.superseded.clone()
+    }
+
+    /// #Descriptions: attribute the connection to `node`, once its handshake is valid,
+    /// and supersede the connections of the same process on `channel`, if named.
+    /// False if `max_connections_per_node` are open as `node` already.
+    pub fn attribute(&mut self, node: &NodeIdentity, channel: Option<&str>) -> bool {
+        if self.node.is_some() {
+            return self.node == Some(node.node_id);
+        }
+        let mut tracked = self.connections.tracked.lock().unwrap();
+        let Tracked { by_node, stats, .. } = &mut *tracked;
+        let attributed = by_node.entry(node.node_id).or_default();
+        if let Some(channel) = channel {
+            // a process opens one connection per channel, the others are stale
+            attributed.retain(|conn| {
+                let stale = conn.instance_id == node.instance_id
+                    && conn.channel.as_deref() == Some(channel);
+                if stale {
+                    conn.close.notify_one();
+                    stats.superseded += 1;
+                }
+                !stale
+            });
+        }
+        let max = self.connections.limits.max_connections_per_node;
+        if max != 0 && attributed.len() >= max {
+            stats.rejected_limit += 1;
+            return false;
+        }
+        attributed.push(Attributed {
+            permit_id: self.id,
+            addr: self.addr,
+            instance_id: node.instance_id.clone(),
+            channel: channel.map(|channel| channel.to_string()),
+            close: self.superseded.clone(),
+        });
+        self.node = Some(node.node_id);
+        true
+    }
+}
+
+impl Drop for InboundPermit {
+    fn drop(&mut self) {
+        let mut tracked = self.connections.tracked.lock().unwrap();
+        tracked.stats.open = tracked.stats.open.saturating_sub(1);
+        if let Some(node) = self.node {
+            if let Some(attributed) = tracked.by_node.get_mut(&node) {
+                // gone already if superseded
+                attributed.retain(|conn| conn.permit_id != self.id);
+                if attributed.is_empty() {
+                    tracked.by_node.remove(&node);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_inbound_connections() {
+        let connections = InboundConnections::new(AcceptLimits {
+            accept_rate: 1.0,
+            accept_burst: 3.0,
+            max_connections: 2,
+            max_connections_per_node: 1,
+            handshake_timeout: Duration::from_secs(1),
+        });
+        let now = Instant::now();
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let mut first = connections.open(addr(7001), now).unwrap();
+        let mut second = connections.open(addr(7002), now).unwrap();
+        // over max_connections
+        assert!(connections.open(addr(7003), now).is_none());
+        assert!(first.attribute(&NodeIdentity::new(2), None));
+        // over max_connections_per_node
+        assert!(!second.attribute(&NodeIdentity::new(2), None));
+        assert!(second.attribute(&NodeIdentity::new(3), None));
+        assert_eq!(connections.by_node()[&2], vec![addr(7001)]);
+        drop(first);
+        assert!(!connections.by_node().contains_key(&2));
+        // the burst is spent
+        assert!(connections.open(addr(7004), now).is_none());
+        assert!(connections.open(addr(7004), now + Duration::from_secs(1)).is_some());
+        let stats = connections.stats();
+        assert_eq!((stats.accepted, stats.open), (3, 1));
+        assert_eq!((stats.rejected_rate, stats.rejected_limit), (1, 2));
+    }
+    #[tokio::test]
+    async fn test_reconnect_storm() {
+        let connections = InboundConnections::new(AcceptLimits {
+            accept_rate: 0.0,
+            max_connections_per_node: 3,
+            ..AcceptLimits::default()
+        });
+        let now = Instant::now();
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let node = NodeIdentity::new(2);
+        let mut catch_up = connections.open(addr(7000), now).unwrap();
+        assert!(catch_up.attribute(&node, Some("catch_up")));
+        // each reconnect replaces the live connection before, none hits the limit
+        let mut stale = Vec::new();
+        for port in 7001..7051 {
+            let mut live = connections.open(addr(port), now).unwrap();
+            assert!(live.attribute(&node, Some("live")));
+            stale.push(live);
+        }
+        let latest = stale.pop().unwrap();
+        assert_eq!(connections.by_node()[&2], vec![addr(7000), addr(7050)]);
+        for live in &stale {
+            let superseded = live.superseded();
+            assert!(timeout(Duration::from_millis(10), superseded.notified()).await.is_ok());
+        }
+        let superseded = latest.superseded();
+        assert!(timeout(Duration::from_millis(10), superseded.notified()).await.is_err());
+        // the stale ones closing do not unregister the latest
+        drop(stale);
+        assert_eq!(connections.by_node()[&2], vec![addr(7000), addr(7050)]);
+
+        // a connection without a channel, or of another process, replaces none
+        let mut old = connections.open(addr(7100), now).unwrap();
+        assert!(old.attribute(&node, None));
+        let mut other = connections.open(addr(7101), now).unwrap();
+        assert!(!other.attribute(&NodeIdentity::new(2), Some("catch_up")));
+        assert_eq!(connections.by_node()[&2].len(), 3);
+        let stats = connections.stats();
+        assert_eq!((stats.superseded, stats.rejected_limit, stats.open), (49, 1, 4));
+    }
+}
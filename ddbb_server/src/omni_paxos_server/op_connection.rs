@@ -1,22 +1,186 @@
 use log::{debug, error, info};
 use tokio::io;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
 use tokio::task::JoinHandle;
-use tokio::time::{sleep, Duration};
+use tokio::time::{self, timeout, Duration, MissedTickBehavior};
+use socket2::{SockRef, TcpKeepalive};
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
+use ddbb_libs::clock::{system_clock, SharedClock};
 use ddbb_libs::connection::{self, Connection};
 use ddbb_libs::data_structure::FrameCast;
+use ddbb_libs::frame::Frame;
 use ddbb_libs::{Error, Result};
+use omnipaxos_core::messages::{
+    sequence_paxos::{PaxosMessage, PaxosMsg},
+    Message,
+};
 use omnipaxos_core::util::NodeId;
 
-use super::op_data_structure::{LogEntry, OmniMessageEntry, Snapshot};
+use super::op_accept::{AcceptLimits, InboundConnections, InboundPermit, InboundStats};
+use super::op_data_structure::{
+    LogEntry, OmniMessageBatch, OmniMessageEntry, OmniMessagePiggyback, OmniMessageSequenced,
+    Snapshot,
+};
+use super::op_latency::{LinkLatency, NetworkPolicy};
+use super::op_order::{Delivery, FrameOrder};
+use super::op_partition::Connectivity;
+use super::op_udp::{receive_heartbeats, HeartbeatSender};
 use super::OmniMessage;
-use crate::config::{RECONNECT_INTERVAL, RETRIEVE_INTERVAL};
+use crate::bootstrap::{ClusterManifest, Handshake, NodeIdentity};
+use crate::build_info::{BuildInfo, Capabilities, PROTOCOL_VERSION};
+use crate::event_log::{ClusterEvent, EventLog, SharedEventLog};
+use crate::metrics::PeerStats;
+use crate::net::{bind_listener, set_socket_options, ListenerOptions};
+use crate::tasks::spawn_named;
+use crate::tls::{accept_with, connect_with, PeerTls};
+use crate::config::{
+    CATCH_UP_CONNECTION, CATCH_UP_TIMEOUT, IDLE_CONNECTION_TIMEOUT, KEEPALIVE_INTERVAL,
+    KEEPALIVE_TIMEOUT, MAX_SEND_BATCH, EVENT_LOG_CAPACITY, HEARTBEAT_TRANSPORT, PEER_MAX_FRAME_SIZE,
+    PEER_HANDSHAKE_TIMEOUT, PIGGYBACK_MAX_BYTES, RECONNECT_INTERVAL, TCP_KEEPALIVE_TIME,
+};
+use crate::memory;
 
 type OmniMessageBuf = Arc<Mutex<VecDeque<OmniMessage>>>;
+type PeerStatsMap = Arc<Mutex<HashMap<NodeId, PeerStats>>>;
+/// peers with a catch-up batch sent but not yet delivered
+type SyncingPeers = Arc<Mutex<HashSet<NodeId>>>;
+
+/// The connections to a peer: one for live msgs and heartbeats, and one for the
+/// bulk sync of a peer catching up, so the former are not queued behind the latter.
+/// The heartbeats may go on a connection of their own too, see `HeartbeatTransport`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Channel {
+    Live,
+    CatchUp,
+    Heartbeat,
+}
+
+/// How the heartbeats of the ballot leader election go to a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeartbeatTransport {
+    /// on the live connection, with the paxos msgs
+    Shared,
+    /// on a connection of their own, so a large accept or sync written to the live
+    /// connection does not hold them up and time out the leader
+    Connection,
+    /// in UDP datagrams to the address of the peer, never retransmitted, see
+    /// `op_udp::HeartbeatDatagram`
+    Udp,
+}
+
+impl std::str::FromStr for HeartbeatTransport {
+    type Err = String;
+
+    fn from_str(transport: &str) -> std::result::Result<Self, Self::Err> {
+        match transport {
+            "shared" => Ok(HeartbeatTransport::Shared),
+            "connection" => Ok(HeartbeatTransport::Connection),
+            "udp" => Ok(HeartbeatTransport::Udp),
+            other => Err(format!(
+                "the heartbeat transport is shared, connection or udp, not {}",
+                other
+            )),
+        }
+    }
+}
+
+impl Channel {
+    /// sent in the handshake, see `Handshake::channel`
+    fn name(self) -> &'static str {
+        match self {
+            Channel::Live => "live",
+            Channel::CatchUp => "catch_up",
+            Channel::Heartbeat => "heartbeat",
+        }
+    }
+
+    fn of(msg: &OmniMessage) -> Channel {
+        if !CATCH_UP_CONNECTION {
+            return Channel::Live;
+        }
+        match msg {
+            Message::SequencePaxos(PaxosMessage {
+                msg: PaxosMsg::AcceptSync(_),
+                ..
+            }) => Channel::CatchUp,
+            // the suffix a follower promises with, a new leader syncs from it
+            Message::SequencePaxos(PaxosMessage {
+                msg: PaxosMsg::Promise(promise),
+                ..
+            }) if !promise.suffix.is_empty() || promise.decided_snapshot.is_some() => {
+                Channel::CatchUp
+            }
+            _ => Channel::Live,
+        }
+    }
+}
+
+/// Wakes the sender of a channel to a peer once msgs are queued for it, a wake while
+/// the sender is busy is kept until it waits again.
+#[derive(Clone, Debug, Default)]
+struct Wakers(Arc<Mutex<HashMap<(NodeId, Channel), Arc<Notify>>>>);
+
+impl Wakers {
+    fn get(&self, peer: NodeId, channel: Channel) -> Arc<Notify> {
+        self.0
+            .lock()
+            .unwrap()
+            .entry((peer, channel))
+            .or_default()
+            .clone()
+    }
+
+    fn wake(&self, peer: NodeId, channel: Channel) {
+        self.get(peer, channel).notify_one();
+    }
+}
+
+/// The process connected as each peer with the connections open from it, a second
+/// process connecting as the same node id while the first is has a duplicate id.
+#[derive(Clone, Debug, Default)]
+struct PeerInstances(Arc<Mutex<HashMap<NodeId, (String, usize)>>>);
+
+impl PeerInstances {
+    /// Register a connection from `peer`, false if another process is connected as its id.
+    fn open(&self, peer: &NodeIdentity) -> bool {
+        let mut instances = self.0.lock().unwrap();
+        let (instance_id, connections) = instances
+            .entry(peer.node_id)
+            .or_insert_with(|| (peer.instance_id.clone(), 0));
+        if *connections > 0 && *instance_id != peer.instance_id {
+            return false;
+        }
+        *instance_id = peer.instance_id.clone();
+        *connections += 1;
+        true
+    }
+
+    fn close(&self, peer: &NodeIdentity) {
+        let mut instances = self.0.lock().unwrap();
+        if let Some((instance_id, connections)) = instances.get_mut(&peer.node_id) {
+            if *instance_id == peer.instance_id {
+                *connections = connections.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// A connection registered in `PeerInstances`, closed once dropped.
+struct PeerConnection {
+    instances: PeerInstances,
+    peer: NodeIdentity,
+}
+
+impl Drop for PeerConnection {
+    fn drop(&mut self) {
+        self.instances.close(&self.peer);
+    }
+}
 
 /// single incoming and multiple outgoing connection for OmniPaxos instances' communication
 #[derive(Clone, Debug)]
@@ -27,6 +191,31 @@ pub struct OmniSIMO {
     pub connected: Arc<Mutex<Vec<NodeId>>>,
     pub outgoing_buffer: OmniMessageBuf,
     pub incoming_buffer: OmniMessageBuf,
+    listener_options: ListenerOptions,
+    /// answered to nodes still joining the cluster
+    manifest: Option<ClusterManifest>,
+    peer_stats: PeerStatsMap,
+    events: SharedEventLog,
+    syncing: SyncingPeers,
+    wakers: Wakers,
+    /// wakes `receive_message` once a msg is pushed to `incoming_buffer`
+    received: Arc<Notify>,
+    /// paces the reconnect attempts
+    clock: SharedClock,
+    /// sent in the handshake, and connections as the same node id are rejected
+    identity: Option<NodeIdentity>,
+    instances: PeerInstances,
+    /// latencies injected on the links to the peers, for fault testing
+    network_policy: NetworkPolicy,
+    heartbeat_transport: HeartbeatTransport,
+    /// the heartbeats queued for their own connection, see `HeartbeatTransport`
+    heartbeat_buffer: OmniMessageBuf,
+    /// the connections to and from the peers are TLS sessions, plain TCP if `None`
+    tls: Option<PeerTls>,
+    /// the incoming connections, limited and attributed to the node they come from
+    inbound: InboundConnections,
+    /// the order of the frames read from each peer
+    order: FrameOrder,
 }
 
 impl OmniSIMO {
@@ -37,26 +226,181 @@ impl OmniSIMO {
             connected: Arc::new(Mutex::new(Vec::new())),
             self_addr,
             peers: Arc::new(Mutex::new(peers)),
+            listener_options: ListenerOptions::default(),
+            manifest: None,
+            peer_stats: Arc::new(Mutex::new(HashMap::new())),
+            events: EventLog::shared(EVENT_LOG_CAPACITY),
+            syncing: Arc::new(Mutex::new(HashSet::new())),
+            wakers: Wakers::default(),
+            received: Arc::new(Notify::new()),
+            clock: system_clock(),
+            identity: None,
+            instances: PeerInstances::default(),
+            network_policy: NetworkPolicy::default(),
+            heartbeat_transport: HEARTBEAT_TRANSPORT,
+            heartbeat_buffer: Arc::new(Mutex::new(VecDeque::new())),
+            tls: None,
+            inbound: InboundConnections::new(AcceptLimits::default()),
+            order: FrameOrder::default(),
         }
     }
 
-    pub fn send_message(&self, omni_message: &OmniMessage) {
-        self.outgoing_buffer
+    /// Where peers connecting and disconnecting are recorded, to set before starting.
+    pub fn set_event_log(&mut self, events: SharedEventLog) {
+        self.events = events;
+    }
+
+    /// #Descriptions: traffic to and from each peer, with the msgs still queued for it.
+    pub fn peer_stats(&self) -> BTreeMap<NodeId, PeerStats> {
+        let mut peer_stats: BTreeMap<NodeId, PeerStats> = self
+            .peers
             .lock()
             .unwrap()
-            .push_back(omni_message.clone());
+            .keys()
+            .map(|peer_id| (*peer_id, PeerStats::default()))
+            .collect();
+        for (peer_id, stats) in self.peer_stats.lock().unwrap().iter() {
+            peer_stats.insert(*peer_id, stats.clone());
+        }
+        for msg in self.outgoing_buffer.lock().unwrap().iter() {
+            if let Some(stats) = peer_stats.get_mut(&msg.get_receiver()) {
+                stats.queue_depth += 1;
+            }
+        }
+        for peer_id in self.connected.lock().unwrap().iter() {
+            if let Some(stats) = peer_stats.get_mut(peer_id) {
+                stats.connected = true;
+            }
+        }
+        for (peer_id, latency) in self.network_policy.latencies() {
+            if let Some(stats) = peer_stats.get_mut(&peer_id) {
+                stats.injected_latency = Some(latency);
+            }
+        }
+        for (peer_id, addrs) in self.inbound.by_node() {
+            if let Some(stats) = peer_stats.get_mut(&peer_id) {
+                stats.inbound_connections = addrs.len() as u64;
+            }
+        }
+        peer_stats
+    }
+
+    /// The manifest the cluster was bootstrapped with, to set before starting.
+    pub fn set_manifest(&mut self, manifest: ClusterManifest) {
+        self.manifest = Some(manifest);
+    }
+
+    /// The node id this process runs as, to set before starting. Without it the
+    /// connections from processes running as the same node id are not detected.
+    pub fn set_identity(&mut self, identity: NodeIdentity) {
+        self.identity = Some(identity);
+    }
+
+    /// The clock the reconnect attempts wait on, to set before starting.
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    /// How the heartbeats go to the peers, to set before starting.
+    pub fn set_heartbeat_transport(&mut self, transport: HeartbeatTransport) {
+        self.heartbeat_transport = transport;
+    }
+
+    /// The TLS sessions of the connections to and from the peers, to set before
+    /// starting, on every node of the cluster.
+    pub fn set_tls(&mut self, tls: PeerTls) {
+        self.tls = Some(tls);
+    }
+
+    /// Limits on the incoming connections, to set before starting.
+    pub fn set_accept_limits(&mut self, limits: AcceptLimits) {
+        self.inbound = InboundConnections::new(limits);
+    }
+
+    /// #Descriptions: the incoming connections accepted and rejected.
+    pub fn inbound_stats(&self) -> InboundStats {
+        self.inbound.stats()
+    }
+
+    /// #Descriptions: the peers some frames of which were lost since the last call,
+    /// omnipaxos must resync with them before reading their next msgs.
+    pub fn take_session_resets(&self) -> Vec<NodeId> {
+        self.order.take_resets()
+    }
+
+    /// #Descriptions: the peers the outgoing connections are up to.
+    pub fn connectivity(&self) -> Connectivity {
+        Connectivity {
+            connected: self.connected.lock().unwrap().clone(),
+            peers: self.peers.lock().unwrap().len(),
+        }
+    }
+
+    /// Options of the incoming listener and of the connections, to set before starting.
+    pub fn set_listener_options(&mut self, options: ListenerOptions) {
+        self.listener_options = options;
+    }
+
+    /// #Descriptions: delay the msgs to `peer` by `latency` before they are queued,
+    /// a zero latency stops delaying them.
+    pub fn set_link_latency(&self, peer: NodeId, latency: LinkLatency) {
+        let outgoing_buffer = self.outgoing_buffer.clone();
+        let heartbeat_buffer = self.heartbeat_buffer.clone();
+        let transport = self.heartbeat_transport;
+        let wakers = self.wakers.clone();
+        self.network_policy
+            .set_latency(peer, latency, self.clock.clone(), move |msg| {
+                let (receiver, channel) =
+                    queue_msg(&outgoing_buffer, &heartbeat_buffer, transport, msg);
+                wakers.wake(receiver, channel);
+            });
+    }
+
+    pub fn link_latencies(&self) -> BTreeMap<NodeId, LinkLatency> {
+        self.network_policy.latencies()
+    }
+
+    pub fn send_message(&self, omni_message: &OmniMessage) {
+        self.send_messages(std::slice::from_ref(omni_message));
+    }
+
+    /// #Descriptions: queue `omni_messages`, e.g. those of one tick, before waking the
+    /// senders, so that the msgs to a peer are taken in one batch.
+    pub fn send_messages(&self, omni_messages: &[OmniMessage]) {
+        let mut woken = HashSet::new();
+        for omni_message in omni_messages {
+            let omni_message = match self
+                .network_policy
+                .hold(omni_message.clone(), self.clock.now())
+            {
+                Some(msg) => msg,
+                // queued once due
+                None => continue,
+            };
+            woken.insert(queue_msg(
+                &self.outgoing_buffer,
+                &self.heartbeat_buffer,
+                self.heartbeat_transport,
+                omni_message,
+            ));
+        }
+        for (receiver, channel) in woken {
+            self.wakers.wake(receiver, channel);
+        }
     }
 
     pub async fn receive_message(simo: Arc<Mutex<OmniSIMO>>) -> Result<OmniMessage> {
-        let buf = simo.lock().unwrap().incoming_buffer.clone();
+        let (buf, received) = {
+            let simo = simo.lock().unwrap();
+            (simo.incoming_buffer.clone(), simo.received.clone())
+        };
         loop {
             {
                 if let Some(msg) = buf.lock().unwrap().pop_front() {
                     return Ok(msg);
                 }
             }
-            // async{let x =1;}.await;
-            sleep(Duration::from_millis(RETRIEVE_INTERVAL)).await;
+            received.notified().await;
         }
     }
 
@@ -65,64 +409,505 @@ impl OmniSIMO {
         outgoing_buffer: OmniMessageBuf,
         reveiver_addr: String,
         connected: Arc<Mutex<Vec<NodeId>>>,
+        options: ListenerOptions,
+        tls: Option<PeerTls>,
+        handshake: Handshake,
+        peer_stats: PeerStatsMap,
+        events: SharedEventLog,
+        syncing: SyncingPeers,
+        wakers: Wakers,
+        clock: SharedClock,
     ) -> Result<()> {
         // let mut tcp_stream = TcpStream::connect(reveiver_addr.clone()).await?;
-        let mut tcp_stream;
+        let mut connection;
         loop {
-            if let Ok(stream) = TcpStream::connect(reveiver_addr.clone()).await {
-                tcp_stream = stream;
+            let conn = Self::connect_peer(&reveiver_addr, &options, &tls).await;
+            if let Ok(conn) = Self::send_handshake(conn, &handshake, reveiver_id, Channel::Live, &peer_stats).await {
+                connection = conn;
                 break;
             }
-            sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
+            clock.sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
         }
         connected.lock().unwrap().insert(0, reveiver_id);
-        let mut connection = Connection::new(tcp_stream);
+        events.lock().unwrap().record(ClusterEvent::PeerConnected { peer: reveiver_id });
+        let waker = wakers.get(reveiver_id, Channel::Live);
+        // numbers the frames across reconnects, see `OmniMessageSequenced`
+        let mut seq = 0;
+        // the peer never writes on this connection unless pinged
+        let mut keepalive = time::interval(KEEPALIVE_INTERVAL);
+        keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        keepalive.reset();
         loop {
-            {
-                let mut can_send = false;
-                let mut can_discard = false;
-                {
-                    let mut buf = outgoing_buffer.lock().unwrap();
-                    if let Some(msg) = buf.front() {
-                        // debug!("SEND: {:?}", msg);
-                        // msg to lost receivers, discard it
-                        if !connected.lock().unwrap().contains(&msg.get_receiver()) {
-                            can_discard = true;
-                        } else if msg.get_receiver() == reveiver_id {
-                            // msg to current receiver
-                            can_send = true;
+            tokio::select! {
+                biased;
+
+                _ = keepalive.tick() => {
+                    if let Err(e) = connection.ping(KEEPALIVE_TIMEOUT).await {
+                        match e {
+                            Error::Timeout(_) => info!("Peer {:?} not answering ping", reveiver_id),
+                            e => info!("Peer {:?} lost: {}", reveiver_id, e),
+                        }
+                        Self::reconnect(&mut connection, reveiver_id, &reveiver_addr, &connected, &options, &tls, &handshake, &peer_stats, &events, &clock).await;
+                        peer_stats.lock().unwrap().entry(reveiver_id).or_default().reconnects += 1;
+                        keepalive.reset();
+                    } else {
+                        peer_stats.lock().unwrap().entry(reveiver_id).or_default().last_seen_ms = unix_millis();
+                    }
+                }
+                _ = waker.notified() => {
+                    let batch = Self::take_batch(reveiver_id, Channel::Live, &outgoing_buffer, &connected, &syncing);
+                    if batch.len() == MAX_SEND_BATCH {
+                        // more are queued
+                        waker.notify_one();
+                    }
+                    if batch.is_empty() {
+                        continue;
+                    }
+                    let (mut msgs_sent, mut bytes_sent) = (0, 0);
+                    let batching = Self::peer_supports(&peer_stats, reveiver_id, Capabilities::BATCHING);
+                    let piggyback = Self::peer_supports(&peer_stats, reveiver_id, Capabilities::PIGGYBACK);
+                    let sequenced = Self::peer_supports(&peer_stats, reveiver_id, Capabilities::SEQUENCED);
+                    let frames = Self::to_frames(batch, batching, piggyback);
+                    for (frame, msgs) in Self::sequence(frames, &mut seq, sequenced) {
+                        match connection.buffer_frame(&frame) {
+                            Ok(()) => {
+                                msgs_sent += msgs;
+                                bytes_sent += frame.encoded_len() as u64;
+                            }
+                            Err(e) => error!("Dropped msg to {:?}: {}", reveiver_id, e),
                         }
                     }
+                    // a write blocks once the socket buffer of a dead peer is full
+                    if let Ok(Ok(_)) = timeout(KEEPALIVE_TIMEOUT, connection.flush()).await {
+                        let mut peer_stats = peer_stats.lock().unwrap();
+                        let stats = peer_stats.entry(reveiver_id).or_default();
+                        stats.msgs_sent += msgs_sent;
+                        stats.bytes_sent += bytes_sent;
+                    } else {
+                        Self::reconnect(&mut connection, reveiver_id, &reveiver_addr, &connected, &options, &tls, &handshake, &peer_stats, &events, &clock).await;
+                        peer_stats.lock().unwrap().entry(reveiver_id).or_default().reconnects += 1;
+                        keepalive.reset();
+                    }
+                    // a catch-up queued behind the batch
+                    wakers.wake(reveiver_id, Channel::CatchUp);
+                }
+            }
+        }
+        Ok(())
+    }
 
-                    // discard msg
-                    if can_discard {
-                        let msg = buf.pop_front().unwrap();
-                        info!("DISCARD: {:?}", msg);
+    /// Send the catch-up msgs to `reveiver_id` on a connection of their own, opened while
+    /// the peer catches up. A batch is delivered once the peer answers a ping sent after
+    /// it, the paxos msgs queued after the batch wait on the live connection until then.
+    async fn process_catch_up_connection(
+        reveiver_id: NodeId,
+        outgoing_buffer: OmniMessageBuf,
+        reveiver_addr: String,
+        connected: Arc<Mutex<Vec<NodeId>>>,
+        options: ListenerOptions,
+        tls: Option<PeerTls>,
+        handshake: Handshake,
+        peer_stats: PeerStatsMap,
+        syncing: SyncingPeers,
+        wakers: Wakers,
+        clock: SharedClock,
+    ) {
+        let waker = wakers.get(reveiver_id, Channel::CatchUp);
+        let mut connection: Option<Connection> = None;
+        let mut seq = 0;
+        loop {
+            if connection.is_none() {
+                waker.notified().await;
+            } else if timeout(KEEPALIVE_INTERVAL, waker.notified()).await.is_err() {
+                // closed before the listener at the other end drops it as idle
+                connection = None;
+                continue;
+            }
+            let batch = Self::take_batch(
+                reveiver_id,
+                Channel::CatchUp,
+                &outgoing_buffer,
+                &connected,
+                &syncing,
+            );
+            if batch.len() == MAX_SEND_BATCH {
+                waker.notify_one();
+            }
+            if batch.is_empty() {
+                continue;
+            }
+            if connection.is_none() {
+                connection = Self::connect_catch_up(
+                    reveiver_id,
+                    &reveiver_addr,
+                    &connected,
+                    &options,
+                    &tls,
+                    &handshake,
+                    &peer_stats,
+                    &clock,
+                )
+                .await;
+            }
+            if let Some(conn) = connection.as_mut() {
+                let sequenced = Self::peer_supports(&peer_stats, reveiver_id, Capabilities::SEQUENCED);
+                match Self::send_catch_up(conn, batch, &mut seq, sequenced).await {
+                    Ok((msgs_sent, bytes_sent)) => {
+                        let mut peer_stats = peer_stats.lock().unwrap();
+                        let stats = peer_stats.entry(reveiver_id).or_default();
+                        stats.msgs_sent += msgs_sent;
+                        stats.bytes_sent += bytes_sent;
+                    }
+                    Err(e) => {
+                        error!("Catch-up of {:?} failed: {}", reveiver_id, e);
+                        connection = None;
                     }
                 }
+            } else {
+                info!("DISCARD: catch-up of lost peer {:?}", reveiver_id);
+            }
+            syncing.lock().unwrap().remove(&reveiver_id);
+            // the paxos msgs held behind the batch
+            wakers.wake(reveiver_id, Channel::Live);
+        }
+    }
 
-                {
-                    // send msg
-                    if can_send {
-                        let msg = outgoing_buffer.lock().unwrap().pop_front().unwrap();
-                        let omni_msg_entry = OmniMessageEntry { omni_msg: msg };
-                        // debug!("SEND: {:?}", omni_msg_entry);
-                        if let Ok(_) = connection.write_frame(&omni_msg_entry.to_frame()).await {
-                        } else {
-                            // RECONNECT
-                            connected.lock().unwrap().retain(|&x| x != reveiver_id);
-                            info!("Send connection lost");
-                            connection.reconnect(reveiver_addr.clone()).await;
-                            info!("RECONNECT");
-                            connected.lock().unwrap().insert(0, reveiver_id);
-                        }
+    /// Send the heartbeats to `reveiver_id` on a connection of their own. A heartbeat
+    /// that can not be sent at once is dropped, the leader election tolerates losing
+    /// some, while one held up would time the leader out.
+    async fn process_heartbeat_connection(
+        reveiver_id: NodeId,
+        heartbeat_buffer: OmniMessageBuf,
+        reveiver_addr: String,
+        options: ListenerOptions,
+        tls: Option<PeerTls>,
+        handshake: Handshake,
+        peer_stats: PeerStatsMap,
+        wakers: Wakers,
+    ) {
+        let waker = wakers.get(reveiver_id, Channel::Heartbeat);
+        let mut connection: Option<Connection> = None;
+        loop {
+            waker.notified().await;
+            let batch: Vec<OmniMessage> = {
+                let mut buf = heartbeat_buffer.lock().unwrap();
+                let (batch, remaining) = buf
+                    .drain(..)
+                    .partition(|msg| msg.get_receiver() == reveiver_id);
+                *buf = remaining;
+                batch
+            };
+            if batch.is_empty() {
+                continue;
+            }
+            if connection.is_none() {
+                let conn = Self::connect_peer(&reveiver_addr, &options, &tls).await;
+                connection = Self::send_handshake(conn, &handshake, reveiver_id, Channel::Heartbeat, &peer_stats)
+                    .await
+                    .ok();
+            }
+            let conn = match connection.as_mut() {
+                Some(conn) => conn,
+                None => {
+                    debug!("DISCARD: heartbeats to unreachable peer {:?}", reveiver_id);
+                    continue;
+                }
+            };
+            let (msgs_sent, mut bytes_sent) = (batch.len() as u64, 0);
+            for omni_msg in batch {
+                let frame = OmniMessageEntry { omni_msg }.to_frame();
+                bytes_sent += frame.encoded_len() as u64;
+                let _ = conn.buffer_frame(&frame);
+            }
+            if let Ok(Ok(_)) = timeout(KEEPALIVE_TIMEOUT, conn.flush()).await {
+                let mut peer_stats = peer_stats.lock().unwrap();
+                let stats = peer_stats.entry(reveiver_id).or_default();
+                stats.msgs_sent += msgs_sent;
+                stats.bytes_sent += bytes_sent;
+            } else {
+                info!("Heartbeat connection to {:?} lost", reveiver_id);
+                connection = None;
+            }
+        }
+    }
+
+    /// Send the heartbeats to `reveiver_id` in UDP datagrams, each once.
+    async fn process_udp_heartbeats(
+        reveiver_id: NodeId,
+        heartbeat_buffer: OmniMessageBuf,
+        reveiver_addr: String,
+        sender: Arc<HeartbeatSender>,
+        peer_stats: PeerStatsMap,
+        wakers: Wakers,
+    ) {
+        let waker = wakers.get(reveiver_id, Channel::Heartbeat);
+        loop {
+            waker.notified().await;
+            let batch: Vec<OmniMessage> = {
+                let mut buf = heartbeat_buffer.lock().unwrap();
+                let (batch, remaining) = buf
+                    .drain(..)
+                    .partition(|msg| msg.get_receiver() == reveiver_id);
+                *buf = remaining;
+                batch
+            };
+            for msg in batch {
+                match sender.send(&reveiver_addr, msg).await {
+                    Ok(bytes_sent) => {
+                        let mut peer_stats = peer_stats.lock().unwrap();
+                        let stats = peer_stats.entry(reveiver_id).or_default();
+                        stats.msgs_sent += 1;
+                        stats.bytes_sent += bytes_sent;
                     }
+                    Err(e) => debug!("DISCARD: heartbeat to {:?}: {}", reveiver_id, e),
                 }
             }
-            // async{let x =1;}.await;
-            sleep(Duration::from_millis(RETRIEVE_INTERVAL)).await;
         }
-        Ok(())
+    }
+
+    /// Connect to `reveiver_addr` for a catch-up, unless the live connection to the
+    /// peer is lost meanwhile.
+    async fn connect_catch_up(
+        reveiver_id: NodeId,
+        reveiver_addr: &str,
+        connected: &Arc<Mutex<Vec<NodeId>>>,
+        options: &ListenerOptions,
+        tls: &Option<PeerTls>,
+        handshake: &Handshake,
+        peer_stats: &PeerStatsMap,
+        clock: &SharedClock,
+    ) -> Option<Connection> {
+        loop {
+            if !connected.lock().unwrap().contains(&reveiver_id) {
+                return None;
+            }
+            let connection = Self::connect_peer(reveiver_addr, options, tls).await;
+            if let Ok(connection) = Self::send_handshake(connection, handshake, reveiver_id, Channel::CatchUp, peer_stats).await {
+                return Some(connection);
+            }
+            clock.sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
+        }
+    }
+
+    /// Connect to the listener of a peer at `addr`, in a TLS session if `tls` is set.
+    async fn connect_peer(
+        addr: &str,
+        options: &ListenerOptions,
+        tls: &Option<PeerTls>,
+    ) -> Result<Connection> {
+        let tcp_stream = Connection::connect_within(addr, options.connect_timeout).await?;
+        set_tcp_keepalive(&tcp_stream);
+        set_socket_options(&tcp_stream, options);
+        let mut connection = match timeout(PEER_HANDSHAKE_TIMEOUT, connect_with(tls, tcp_stream)).await {
+            Ok(connection) => connection?,
+            Err(_) => return Err(Error::Timeout("tls handshake".to_string())),
+        };
+        connection.set_max_frame_size(PEER_MAX_FRAME_SIZE);
+        Ok(connection)
+    }
+
+    /// Write `batch` and wait for the peer to have read it, returning the msgs and
+    /// bytes sent.
+    async fn send_catch_up(
+        connection: &mut Connection,
+        batch: Vec<OmniMessage>,
+        seq: &mut u64,
+        sequenced: bool,
+    ) -> Result<(u64, u64)> {
+        let (mut msgs_sent, mut bytes_sent) = (0, 0);
+        let frames = batch
+            .into_iter()
+            .map(|omni_msg| (OmniMessageEntry { omni_msg }.to_frame(), 1))
+            .collect();
+        for (frame, msgs) in Self::sequence(frames, seq, sequenced) {
+            connection.buffer_frame(&frame)?;
+            msgs_sent += msgs;
+            bytes_sent += frame.encoded_len() as u64;
+        }
+        match timeout(CATCH_UP_TIMEOUT, connection.flush()).await {
+            Ok(flushed) => flushed?,
+            Err(_) => return Err(Error::Timeout("catch-up".to_string())),
+        }
+        // the listener reads the frames of a connection in order
+        connection.ping(CATCH_UP_TIMEOUT).await?;
+        Ok((msgs_sent, bytes_sent))
+    }
+
+    /// Take up to `MAX_SEND_BATCH` msgs to `reveiver_id` on `channel` out of the
+    /// outgoing buffer, in order, and discard msgs to lost receivers. A paxos msg is
+    /// only taken once the ones queued before it to the same peer were, and on the live
+    /// channel once the catch-up in flight to the peer is delivered, as omnipaxos needs
+    /// them in order.
+    fn take_batch(
+        reveiver_id: NodeId,
+        channel: Channel,
+        outgoing_buffer: &OmniMessageBuf,
+        connected: &Arc<Mutex<Vec<NodeId>>>,
+        syncing: &SyncingPeers,
+    ) -> Vec<OmniMessage> {
+        let mut batch = Vec::new();
+        let mut buf = outgoing_buffer.lock().unwrap();
+        let connected = connected.lock().unwrap();
+        let mut syncing = syncing.lock().unwrap();
+        let mut blocked = channel == Channel::Live && syncing.contains(&reveiver_id);
+        let mut remaining = VecDeque::with_capacity(buf.len());
+        for msg in buf.drain(..) {
+            let receiver = msg.get_receiver();
+            if receiver == reveiver_id {
+                let paxos = matches!(msg, Message::SequencePaxos(_));
+                if Channel::of(&msg) == channel
+                    && !(paxos && blocked)
+                    && batch.len() < MAX_SEND_BATCH
+                {
+                    batch.push(msg);
+                } else {
+                    blocked |= paxos;
+                    remaining.push_back(msg);
+                }
+            } else if !connected.contains(&receiver) {
+                info!("DISCARD: {:?}", msg);
+            } else {
+                remaining.push_back(msg);
+            }
+        }
+        if channel == Channel::CatchUp && !batch.is_empty() {
+            syncing.insert(reveiver_id);
+        }
+        *buf = remaining;
+        batch
+    }
+
+    async fn reconnect(
+        connection: &mut Connection,
+        reveiver_id: NodeId,
+        reveiver_addr: &String,
+        connected: &Arc<Mutex<Vec<NodeId>>>,
+        options: &ListenerOptions,
+        tls: &Option<PeerTls>,
+        handshake: &Handshake,
+        peer_stats: &PeerStatsMap,
+        events: &SharedEventLog,
+        clock: &SharedClock,
+    ) {
+        connected.lock().unwrap().retain(|&x| x != reveiver_id);
+        info!("Send connection lost");
+        events.lock().unwrap().record(ClusterEvent::PeerDisconnected { peer: reveiver_id });
+        *connection = loop {
+            let conn = Self::connect_peer(reveiver_addr, options, tls).await;
+            if let Ok(conn) = Self::send_handshake(conn, handshake, reveiver_id, Channel::Live, peer_stats).await {
+                break conn;
+            }
+            clock.sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
+        };
+        info!("RECONNECT");
+        connected.lock().unwrap().insert(0, reveiver_id);
+        events.lock().unwrap().record(ClusterEvent::PeerConnected { peer: reveiver_id });
+    }
+
+    /// Whether `peer` sent `capability` in the handshake of its connection to this node,
+    /// and this build has it too. Unknown until the peer sent its first msg.
+    fn peer_supports(peer_stats: &PeerStatsMap, peer: NodeId, capability: u64) -> bool {
+        peer_stats
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .and_then(|stats| stats.build.as_ref())
+            .map_or(false, |build| {
+                build
+                    .capabilities
+                    .common(&Capabilities::supported())
+                    .has(capability)
+            })
+    }
+
+    /// The frames of `batch` with the msgs in each, a single frame when `batching`, and
+    /// a heartbeat combined with a small `AcceptDecide` when `piggyback`.
+    fn to_frames(mut batch: Vec<OmniMessage>, batching: bool, piggyback: bool) -> Vec<(Frame, u64)> {
+        let piggybacked = if piggyback {
+            Self::take_piggyback(&mut batch)
+        } else {
+            None
+        };
+        let mut frames: Vec<(Frame, u64)> = if batching && batch.len() > 1 {
+            let msgs = batch.len() as u64;
+            vec![(OmniMessageBatch { omni_msgs: batch }.to_frame(), msgs)]
+        } else {
+            batch
+                .into_iter()
+                .map(|omni_msg| (OmniMessageEntry { omni_msg }.to_frame(), 1))
+                .collect()
+        };
+        if let Some(piggybacked) = piggybacked {
+            frames.push((piggybacked.to_frame(), 2));
+        }
+        frames
+    }
+
+    /// The `frames` numbered from `seq` on in `OmniMessageSequenced` frames, if
+    /// `sequenced`. A frame keeps its number once taken, lost or not.
+    fn sequence(frames: Vec<(Frame, u64)>, seq: &mut u64, sequenced: bool) -> Vec<(Frame, u64)> {
+        if !sequenced {
+            return frames;
+        }
+        frames
+            .into_iter()
+            .map(|(frame, msgs)| {
+                *seq += 1;
+                (OmniMessageSequenced { seq: *seq, frame }.to_frame(), msgs)
+            })
+            .collect()
+    }
+
+    /// Take a heartbeat and an `AcceptDecide` of up to `PIGGYBACK_MAX_BYTES` out of
+    /// `batch` to send in one frame. The accept must be the last paxos msg of the batch,
+    /// as the frame goes after the others and omnipaxos needs them in order.
+    fn take_piggyback(batch: &mut Vec<OmniMessage>) -> Option<OmniMessagePiggyback> {
+        let accept = batch
+            .iter()
+            .rposition(|msg| matches!(msg, Message::SequencePaxos(_)))?;
+        let small = match &batch[accept] {
+            Message::SequencePaxos(PaxosMessage {
+                msg: PaxosMsg::AcceptDecide(_),
+                ..
+            }) => memory::msg_size(&batch[accept]) <= PIGGYBACK_MAX_BYTES,
+            _ => false,
+        };
+        if !small {
+            return None;
+        }
+        let heartbeat = batch.iter().position(|msg| matches!(msg, Message::BLE(_)))?;
+        // the later one first, the other keeps its index
+        let (accept, heartbeat) = if accept > heartbeat {
+            let accept = batch.remove(accept);
+            (accept, batch.remove(heartbeat))
+        } else {
+            let heartbeat = batch.remove(heartbeat);
+            (batch.remove(accept), heartbeat)
+        };
+        Some(OmniMessagePiggyback { heartbeat, accept })
+    }
+
+    /// Identify the cluster, the build and the node id of this process to the listener at
+    /// the other end of a `connection` just opened, with the `channel` of the connection if
+    /// `peer` reads it. Fails if the connection did or the handshake could not be written,
+    /// the caller connects again.
+    async fn send_handshake(
+        connection: Result<Connection>,
+        handshake: &Handshake,
+        peer: NodeId,
+        channel: Channel,
+        peer_stats: &PeerStatsMap,
+    ) -> Result<Connection> {
+        let mut connection = connection?;
+        let mut handshake = handshake.clone();
+        if Self::peer_supports(peer_stats, peer, Capabilities::SUPERSEDE) {
+            handshake.channel = Some(channel.name().to_string());
+        }
+        match timeout(PEER_HANDSHAKE_TIMEOUT, connection.write_frame(&handshake.to_frame())).await {
+            Ok(written) => written?,
+            Err(_) => return Err(Error::Timeout("handshake".to_string())),
+        }
+        Ok(connection)
     }
 
     /// #Descriptions: start the sender of an omni simo
@@ -130,18 +915,103 @@ impl OmniSIMO {
         let outgoing_buffer = simo.lock().unwrap().outgoing_buffer.clone();
         let peers = simo.lock().unwrap().peers.clone();
         let connected = simo.lock().unwrap().connected.clone();
+        let options = simo.lock().unwrap().listener_options.clone();
+        let tls = simo.lock().unwrap().tls.clone();
+        let peer_stats = simo.lock().unwrap().peer_stats.clone();
+        let events = simo.lock().unwrap().events.clone();
+        let syncing = simo.lock().unwrap().syncing.clone();
+        let wakers = simo.lock().unwrap().wakers.clone();
+        let clock = simo.lock().unwrap().clock.clone();
+        let heartbeat_transport = simo.lock().unwrap().heartbeat_transport;
+        let heartbeat_buffer = simo.lock().unwrap().heartbeat_buffer.clone();
+        let cluster_uuid = simo
+            .lock()
+            .unwrap()
+            .manifest
+            .as_ref()
+            .and_then(|manifest| manifest.cluster_uuid.clone());
+        // a listener of a cluster with a uuid rejects the empty one
+        let cluster_uuid = cluster_uuid.unwrap_or_default();
+        let udp_sender = match heartbeat_transport {
+            HeartbeatTransport::Udp => {
+                Some(Arc::new(HeartbeatSender::bind(cluster_uuid.clone()).await?))
+            }
+            _ => None,
+        };
+        let handshake = Handshake {
+            cluster_uuid,
+            build: Some(BuildInfo::current()),
+            node: simo.lock().unwrap().identity.clone(),
+            channel: None,
+        };
 
         for (peer_id, peer_addr) in peers.lock().unwrap().iter() {
             let outgoing_buffer_copy = outgoing_buffer.clone();
             let connected = connected.clone();
+            let options = options.clone();
+            let tls = tls.clone();
+            let handshake = handshake.clone();
+            let peer_stats = peer_stats.clone();
+            let events = events.clone();
+            let syncing = syncing.clone();
+            let wakers = wakers.clone();
+            let clock = clock.clone();
             let peer_id = peer_id.clone();
             let peer_addr = peer_addr.clone();
-            tokio::spawn(async move {
+            if CATCH_UP_CONNECTION {
+                let catch_up = OmniSIMO::process_catch_up_connection(
+                    peer_id,
+                    outgoing_buffer_copy.clone(),
+                    peer_addr.clone(),
+                    connected.clone(),
+                    options.clone(),
+                    tls.clone(),
+                    handshake.clone(),
+                    peer_stats.clone(),
+                    syncing.clone(),
+                    wakers.clone(),
+                    clock.clone(),
+                );
+                spawn_named(&format!("omni_simo catch-up to {}", peer_id), catch_up);
+            }
+            if heartbeat_transport == HeartbeatTransport::Connection {
+                let heartbeats = OmniSIMO::process_heartbeat_connection(
+                    peer_id,
+                    heartbeat_buffer.clone(),
+                    peer_addr.clone(),
+                    options.clone(),
+                    tls.clone(),
+                    handshake.clone(),
+                    peer_stats.clone(),
+                    wakers.clone(),
+                );
+                spawn_named(&format!("omni_simo heartbeats to {}", peer_id), heartbeats);
+            }
+            if let Some(udp_sender) = &udp_sender {
+                let heartbeats = OmniSIMO::process_udp_heartbeats(
+                    peer_id,
+                    heartbeat_buffer.clone(),
+                    peer_addr.clone(),
+                    udp_sender.clone(),
+                    peer_stats.clone(),
+                    wakers.clone(),
+                );
+                spawn_named(&format!("omni_simo udp heartbeats to {}", peer_id), heartbeats);
+            }
+            spawn_named(&format!("omni_simo sender to {}", peer_id), async move {
                 OmniSIMO::process_outgoing_connection(
                     peer_id.clone(),
                     outgoing_buffer_copy,
                     peer_addr,
                     connected,
+                    options,
+                    tls,
+                    handshake,
+                    peer_stats,
+                    events,
+                    syncing,
+                    wakers,
+                    clock,
                 )
                 .await;
             });
@@ -151,7 +1021,7 @@ impl OmniSIMO {
             if connected.lock().unwrap().len() >= (peers.lock().unwrap().len() + 1 ) / 2 + 1 {
                 return Ok(());
             }
-            sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
+            clock.sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
         }
     }
 
@@ -159,16 +1029,89 @@ impl OmniSIMO {
     pub async fn start_incoming_listener(simo: Arc<Mutex<OmniSIMO>>) -> Result<()> {
         let self_addr = simo.lock().unwrap().self_addr.clone();
         let incoming_buffer = simo.lock().unwrap().incoming_buffer.clone();
-        let listener = TcpListener::bind(&self_addr).await?;
+        let options = simo.lock().unwrap().listener_options.clone();
+        let manifest = simo.lock().unwrap().manifest.clone();
+        let peer_stats = simo.lock().unwrap().peer_stats.clone();
+        let received = simo.lock().unwrap().received.clone();
+        let clock = simo.lock().unwrap().clock.clone();
+        let identity = simo.lock().unwrap().identity.clone();
+        let instances = simo.lock().unwrap().instances.clone();
+        let events = simo.lock().unwrap().events.clone();
+        let heartbeat_transport = simo.lock().unwrap().heartbeat_transport;
+        let tls = simo.lock().unwrap().tls.clone();
+        let inbound = simo.lock().unwrap().inbound.clone();
+        let order = simo.lock().unwrap().order.clone();
+        let listener = bind_listener(&self_addr, &options).await?;
+        if heartbeat_transport == HeartbeatTransport::Udp {
+            let cluster_uuid = manifest
+                .as_ref()
+                .and_then(|manifest| manifest.cluster_uuid.clone());
+            let receiver = receive_heartbeats(
+                self_addr.clone(),
+                cluster_uuid,
+                incoming_buffer.clone(),
+                received.clone(),
+                peer_stats.clone(),
+            );
+            let self_addr = self_addr.clone();
+            spawn_named("omni_simo udp heartbeats", async move {
+                if let Err(e) = receiver.await {
+                    error!("Heartbeats on udp {} failed: {}", self_addr, e);
+                }
+            });
+        }
         // thread of incoming listener
-        tokio::spawn(async move {
+        spawn_named("omni_simo listener", async move {
             loop {
-                let (mut stream, addr) = listener.accept().await.unwrap();
-                let mut connection = Connection::new(stream);
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // e.g. out of file descriptors, keep serving the others
+                        error!("Accept connection failed: {:?}", e);
+                        clock.sleep(Duration::from_millis(RECONNECT_INTERVAL)).await;
+                        continue;
+                    }
+                };
+                // closed at once, before a task is spawned for it
+                let permit = match inbound.open(addr, Instant::now()) {
+                    Some(permit) => permit,
+                    None => {
+                        debug!("Reject connection from {}, over the accept limits", addr);
+                        continue;
+                    }
+                };
+                set_tcp_keepalive(&stream);
+                set_socket_options(&stream, &options);
                 let incoming_buffer_copy = incoming_buffer.clone();
+                let manifest = manifest.clone();
+                let peer_stats = peer_stats.clone();
+                let received = received.clone();
+                let identity = identity.clone();
+                let instances = instances.clone();
+                let events = events.clone();
+                let tls = tls.clone();
+                let inbound = inbound.clone();
+                let order = order.clone();
                 // thread of new connection
-                tokio::spawn(async move {
-                    Self::process_connection(incoming_buffer_copy, connection).await;
+                spawn_named(&format!("omni_simo connection from {}", addr), async move {
+                    let upgraded = timeout(inbound.handshake_timeout(), accept_with(&tls, stream)).await;
+                    let mut connection = match upgraded {
+                        Ok(Ok(connection)) => connection,
+                        Ok(Err(e)) => {
+                            inbound.rejected_handshake();
+                            error!("TLS handshake from {:?} failed: {}", addr, e);
+                            return;
+                        }
+                        Err(_) => {
+                            inbound.rejected_handshake();
+                            error!("TLS handshake from {:?} timed out", addr);
+                            return;
+                        }
+                    };
+                    connection.set_max_frame_size(PEER_MAX_FRAME_SIZE);
+                    if let Err(e) = Self::process_connection(incoming_buffer_copy, received, connection, manifest, peer_stats, identity, instances, events, inbound, permit, order).await {
+                        error!("Connection from {:?} failed: {}", addr, e);
+                    }
                 });
             }
         });
@@ -177,18 +1120,188 @@ impl OmniSIMO {
 
     async fn process_connection(
         incoming_buffer: OmniMessageBuf,
+        received: Arc<Notify>,
         mut connection: Connection,
+        manifest: Option<ClusterManifest>,
+        peer_stats: PeerStatsMap,
+        identity: Option<NodeIdentity>,
+        instances: PeerInstances,
+        events: SharedEventLog,
+        inbound: InboundConnections,
+        mut permit: InboundPermit,
+        order: FrameOrder,
     ) -> Result<()> {
+        let cluster_uuid = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.cluster_uuid.clone());
+        let mut verified = cluster_uuid.is_none();
+        // sent in the handshake, kept with the stats of the sender of the first msg
+        let mut peer_build: Option<BuildInfo> = None;
+        // the node the handshake came from, only its msgs are read
+        let mut peer: Option<PeerConnection> = None;
+        // the channel of the sender this connection is, its frames are numbered apart
+        let mut channel = String::new();
+        let handshake_deadline = Instant::now() + inbound.handshake_timeout();
+        let superseded = permit.superseded();
         loop {
-            if let Ok(Some(msg_frame)) = connection.read_frame().await {
-                let omni_message_entry = *OmniMessageEntry::from_frame(&msg_frame).unwrap();
-                incoming_buffer
-                    .lock()
-                    .unwrap()
-                    .push_back(omni_message_entry.omni_msg);
+            // the sender pings at least every KEEPALIVE_INTERVAL, and sends its
+            // handshake first
+            let wait = if verified {
+                IDLE_CONNECTION_TIMEOUT
             } else {
-                // connection droped
-                error!("An Connection drop");
+                handshake_deadline.saturating_duration_since(Instant::now())
+            };
+            let read = tokio::select! {
+                read = timeout(wait, connection.read_frame()) => read,
+                // its msgs come on the new connection now, and must not be mixed
+                // with the ones still read here
+                _ = superseded.notified() => {
+                    info!("Close connection from {}, superseded by a new one", permit.addr());
+                    break;
+                }
+            };
+            if read.is_err() && !verified {
+                inbound.rejected_handshake();
+                error!("Reject connection from {} without handshake in time", permit.addr());
+                break;
+            }
+            if let Ok(Ok(Some(msg_frame))) = read {
+                if Connection::got_ping_msg(&msg_frame) {
+                    connection.pong().await?;
+                    continue;
+                }
+                if Connection::got_reconnect_msg(&msg_frame) {
+                    continue;
+                }
+                // a node still joining the cluster
+                if let (Ok(_), Some(manifest)) = (ClusterManifest::from_frame(&msg_frame), &manifest) {
+                    connection.write_frame(&manifest.to_frame()).await?;
+                    break;
+                }
+                if let Ok(handshake) = Handshake::from_frame(&msg_frame) {
+                    if verified || Some(&handshake.cluster_uuid) == cluster_uuid.as_ref() {
+                        verified = true;
+                        if let Some(build) = &handshake.build {
+                            if build.protocol_version != PROTOCOL_VERSION {
+                                error!(
+                                    "Peer runs {} of protocol {}, this node {}",
+                                    build.version_string(),
+                                    build.protocol_version,
+                                    PROTOCOL_VERSION
+                                );
+                            }
+                        }
+                        peer_build = handshake.build;
+                        channel = handshake.channel.clone().unwrap_or_default();
+                        if let Some(node) = handshake.node {
+                            if !permit.attribute(&node, handshake.channel.as_deref()) {
+                                error!(
+                                    "Reject connection as node {}, too many are open as it",
+                                    node.node_id
+                                );
+                                break;
+                            }
+                            let own_id = identity.as_ref().map(|identity| identity.node_id);
+                            if own_id == Some(node.node_id) || !instances.open(&node) {
+                                error!(
+                                    "Reject connection as node {}, another process runs as it",
+                                    node.node_id
+                                );
+                                events
+                                    .lock()
+                                    .unwrap()
+                                    .record(ClusterEvent::NodeIdCollision { node: node.node_id });
+                                break;
+                            }
+                            peer = Some(PeerConnection {
+                                instances: instances.clone(),
+                                peer: node,
+                            });
+                        }
+                        continue;
+                    }
+                    inbound.rejected_handshake();
+                    error!("Reject connection from cluster {}", handshake.cluster_uuid);
+                    break;
+                }
+                if !verified {
+                    inbound.rejected_handshake();
+                    error!("Reject connection without handshake");
+                    break;
+                }
+                // sent by peers that number their frames
+                let (msg_frame, seq) = match OmniMessageSequenced::from_frame(&msg_frame) {
+                    Ok(sequenced) => (sequenced.frame, Some(sequenced.seq)),
+                    Err(_) => (msg_frame, None),
+                };
+                if let (Some(peer), Some(seq)) = (&peer, seq) {
+                    let node_id = peer.peer.node_id;
+                    match order.accept(&peer.peer, &channel, seq) {
+                        Delivery::InOrder => {}
+                        Delivery::Gap(lost) => {
+                            error!("Lost {} frames of node {}, resync with it", lost, node_id);
+                            peer_stats.lock().unwrap().entry(node_id).or_default().session_resets += 1;
+                            events
+                                .lock()
+                                .unwrap()
+                                .record(ClusterEvent::SessionReset { peer: node_id, lost });
+                        }
+                        Delivery::Stale => {
+                            debug!("Dropped stale frame {} of node {}", seq, node_id);
+                            peer_stats.lock().unwrap().entry(node_id).or_default().stale_frames += 1;
+                            continue;
+                        }
+                    }
+                }
+                let omni_msgs = match OmniMessageEntry::from_frame(&msg_frame) {
+                    Ok(omni_message_entry) => Ok(vec![omni_message_entry.omni_msg]),
+                    // sent by peers that read batches and piggybacked msgs too
+                    Err(e) => OmniMessageBatch::from_frame(&msg_frame)
+                        .map(|batch| batch.omni_msgs)
+                        .or_else(|_| {
+                            OmniMessagePiggyback::from_frame(&msg_frame)
+                                .map(|piggyback| piggyback.into_msgs())
+                        })
+                        .map_err(|_| e),
+                };
+                if let (Some(peer), Ok(omni_msgs)) = (&peer, &omni_msgs) {
+                    let node_id = peer.peer.node_id;
+                    if omni_msgs.iter().any(|msg| msg.get_sender() != node_id) {
+                        error!(
+                            "Reject msgs of another node on a connection as node {}",
+                            node_id
+                        );
+                        break;
+                    }
+                }
+                match omni_msgs {
+                    Ok(omni_msgs) => {
+                        if let Some(sender) = omni_msgs.first().map(|msg| msg.get_sender()) {
+                            let mut peer_stats = peer_stats.lock().unwrap();
+                            let stats = peer_stats.entry(sender).or_default();
+                            stats.msgs_received += omni_msgs.len() as u64;
+                            stats.bytes_received += msg_frame.encoded_len() as u64;
+                            stats.last_seen_ms = unix_millis();
+                            if let Some(build) = peer_build.take() {
+                                stats.build = Some(build);
+                            }
+                        }
+                        incoming_buffer.lock().unwrap().extend(omni_msgs);
+                        received.notify_one();
+                    }
+                    Err(e) => {
+                        // the stream can not be trusted anymore, the sender reconnects
+                        error!("Unexpected frame, close connection: {}", e);
+                        break;
+                    }
+                }
+            } else {
+                match read {
+                    Ok(Err(Error::ConnectionClosed)) => info!("Connection closed by peer"),
+                    Ok(Err(e)) => error!("An Connection drop: {}", e),
+                    // idle for too long
+                    _ => error!("An Connection drop"),
+                }
                 break;
             }
         }
@@ -196,6 +1309,40 @@ impl OmniSIMO {
     }
 }
 
+/// Queue `omni_message` for its receiver, the heartbeats apart unless `transport` shares
+/// the live connection. Returns the channel to wake.
+fn queue_msg(
+    outgoing_buffer: &OmniMessageBuf,
+    heartbeat_buffer: &OmniMessageBuf,
+    transport: HeartbeatTransport,
+    omni_message: OmniMessage,
+) -> (NodeId, Channel) {
+    let receiver = omni_message.get_receiver();
+    if transport != HeartbeatTransport::Shared {
+        if let Message::BLE(_) = omni_message {
+            heartbeat_buffer.lock().unwrap().push_back(omni_message);
+            return (receiver, Channel::Heartbeat);
+        }
+    }
+    let channel = Channel::of(&omni_message);
+    outgoing_buffer.lock().unwrap().push_back(omni_message);
+    (receiver, channel)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn set_tcp_keepalive(tcp_stream: &TcpStream) {
+    let keepalive = TcpKeepalive::new().with_time(TCP_KEEPALIVE_TIME);
+    if let Err(e) = SockRef::from(tcp_stream).set_tcp_keepalive(&keepalive) {
+        error!("Set tcp keepalive failed: {:?}", e);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -258,6 +1405,334 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_peer_stats_queue_depth() {
+        let mut peers: HashMap<NodeId, String> = HashMap::new();
+        peers.insert(2, "127.0.0.1:5680".to_string());
+        peers.insert(3, "127.0.0.1:5681".to_string());
+        let simo = OmniSIMO::new("127.0.0.1:5682".to_string(), peers);
+        for to in [2, 2, 3] {
+            simo.send_message(&OmniMessage::SequencePaxos(PaxosMessage {
+                from: 1,
+                to,
+                msg: PaxosMsg::PrepareReq,
+            }));
+        }
+        let peer_stats = simo.peer_stats();
+        assert_eq!(peer_stats.keys().copied().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(peer_stats[&2].queue_depth, 2);
+        assert_eq!(peer_stats[&3].queue_depth, 1);
+        assert_eq!(peer_stats[&2].msgs_sent, 0);
+    }
+
+    #[test]
+    fn test_catch_up_channel() {
+        use omnipaxos_core::messages::{
+            ballot_leader_election::{HeartbeatMsg, HeartbeatRequest},
+            sequence_paxos::{AcceptSync, Decide},
+        };
+
+        let outgoing_buffer: OmniMessageBuf = Arc::new(Mutex::new(VecDeque::new()));
+        let connected = Arc::new(Mutex::new(vec![2]));
+        let syncing: SyncingPeers = Arc::new(Mutex::new(HashSet::new()));
+        let paxos = |msg| {
+            OmniMessage::SequencePaxos(PaxosMessage {
+                from: 1,
+                to: 2,
+                msg,
+            })
+        };
+        let sync = paxos(PaxosMsg::AcceptSync(AcceptSync {
+            n: Default::default(),
+            decided_snapshot: None,
+            suffix: vec![],
+            sync_idx: 0,
+            decided_idx: 0,
+            stopsign: None,
+        }));
+        let heartbeat = OmniMessage::BLE(BLEMessage {
+            from: 1,
+            to: 2,
+            msg: HeartbeatMsg::Request(HeartbeatRequest { round: 1 }),
+        });
+        outgoing_buffer.lock().unwrap().extend([
+            paxos(PaxosMsg::PrepareReq),
+            sync,
+            paxos(PaxosMsg::Decide(Decide {
+                n: Default::default(),
+                decided_idx: 1,
+            })),
+            heartbeat,
+        ]);
+
+        // the paxos msg after the sync waits, the heartbeat does not
+        let live = OmniSIMO::take_batch(2, Channel::Live, &outgoing_buffer, &connected, &syncing);
+        assert_eq!(live.len(), 2);
+        assert!(matches!(live[1], OmniMessage::BLE(_)));
+        let catch_up =
+            OmniSIMO::take_batch(2, Channel::CatchUp, &outgoing_buffer, &connected, &syncing);
+        assert!(catch_up.len() == 1 && Channel::of(&catch_up[0]) == Channel::CatchUp);
+
+        // until the sync is delivered
+        assert!(
+            OmniSIMO::take_batch(2, Channel::Live, &outgoing_buffer, &connected, &syncing)
+                .is_empty()
+        );
+        syncing.lock().unwrap().remove(&2);
+        let live = OmniSIMO::take_batch(2, Channel::Live, &outgoing_buffer, &connected, &syncing);
+        assert!(matches!(
+            live[..],
+            [OmniMessage::SequencePaxos(PaxosMessage {
+                msg: PaxosMsg::Decide(_),
+                ..
+            })]
+        ));
+    }
+
+    #[test]
+    fn test_batching_negotiated() {
+        let peer_stats: PeerStatsMap = Arc::new(Mutex::new(HashMap::new()));
+        let mut old = BuildInfo::current();
+        old.capabilities = Capabilities::default();
+        peer_stats.lock().unwrap().entry(2).or_default().build = Some(old);
+        peer_stats.lock().unwrap().entry(3).or_default().build = Some(BuildInfo::current());
+        // no handshake seen from 4 yet
+        let batching = |peer| OmniSIMO::peer_supports(&peer_stats, peer, Capabilities::BATCHING);
+        assert!(!batching(2));
+        assert!(batching(3));
+        assert!(!batching(4));
+
+        let prepare_req = || {
+            OmniMessage::SequencePaxos(PaxosMessage {
+                from: 1,
+                to: 3,
+                msg: PaxosMsg::PrepareReq,
+            })
+        };
+        let batch = || vec![prepare_req(), prepare_req()];
+        let frames = OmniSIMO::to_frames(batch(), true, false);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].1, 2);
+        let decoded = OmniMessageBatch::from_frame(&frames[0].0).unwrap();
+        assert_eq!(decoded.omni_msgs.len(), 2);
+        let frames = OmniSIMO::to_frames(batch(), false, false);
+        assert!(frames.len() == 2 && OmniMessageEntry::from_frame(&frames[1].0).is_ok());
+    }
+
+    #[test]
+    fn test_piggyback() {
+        use omnipaxos_core::messages::ballot_leader_election::{HeartbeatMsg, HeartbeatRequest};
+        use omnipaxos_core::messages::sequence_paxos::AcceptDecide;
+
+        let heartbeat = || {
+            OmniMessage::BLE(BLEMessage {
+                from: 1,
+                to: 2,
+                msg: HeartbeatMsg::Request(HeartbeatRequest { round: 1 }),
+            })
+        };
+        let accept = |value_len: usize| {
+            OmniMessage::SequencePaxos(PaxosMessage {
+                from: 1,
+                to: 2,
+                msg: PaxosMsg::AcceptDecide(AcceptDecide {
+                    n: Default::default(),
+                    decided_idx: 0,
+                    entries: vec![LogEntry::SetValue {
+                        key: "k".to_string(),
+                        value: vec![0; value_len],
+                    }],
+                }),
+            })
+        };
+        let prepare_req = OmniMessage::SequencePaxos(PaxosMessage {
+            from: 1,
+            to: 2,
+            msg: PaxosMsg::PrepareReq,
+        });
+
+        let frames = OmniSIMO::to_frames(vec![accept(1), heartbeat()], false, true);
+        assert_eq!(frames.len(), 1);
+        let piggyback = OmniMessagePiggyback::from_frame(&frames[0].0).unwrap();
+        assert!(matches!(
+            piggyback.into_msgs()[..],
+            [OmniMessage::BLE(_), OmniMessage::SequencePaxos(_)]
+        ));
+
+        // the others go first, in a batch
+        let batch = vec![prepare_req.clone(), heartbeat(), accept(1), heartbeat()];
+        let frames = OmniSIMO::to_frames(batch, true, true);
+        assert_eq!(frames.iter().map(|(_, msgs)| *msgs).collect::<Vec<u64>>(), vec![2, 2]);
+        assert!(OmniMessagePiggyback::from_frame(&frames[1].0).is_ok());
+
+        // too large, or followed by another paxos msg
+        let large = accept(PIGGYBACK_MAX_BYTES as usize);
+        assert_eq!(OmniSIMO::to_frames(vec![large, heartbeat()], false, true).len(), 2);
+        let frames = OmniSIMO::to_frames(vec![heartbeat(), accept(1), prepare_req], false, true);
+        assert_eq!(frames.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_wakes_sender() {
+        let simo = OmniSIMO::new("127.0.0.1:5683".to_string(), HashMap::new());
+        let (live, catch_up) = (
+            simo.wakers.get(2, Channel::Live),
+            simo.wakers.get(2, Channel::CatchUp),
+        );
+        // queued before the sender waits
+        simo.send_message(&OmniMessage::SequencePaxos(PaxosMessage {
+            from: 1,
+            to: 2,
+            msg: PaxosMsg::PrepareReq,
+        }));
+        let wait = Duration::from_millis(50);
+        assert!(timeout(wait, live.notified()).await.is_ok());
+        assert!(timeout(wait, catch_up.notified()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_transport() {
+        use omnipaxos_core::messages::ballot_leader_election::{HeartbeatMsg, HeartbeatRequest};
+
+        assert_eq!("connection".parse(), Ok(HeartbeatTransport::Connection));
+        assert_eq!("udp".parse(), Ok(HeartbeatTransport::Udp));
+        assert!("tcp".parse::<HeartbeatTransport>().is_err());
+        let mut simo = OmniSIMO::new("127.0.0.1:5684".to_string(), HashMap::new());
+        simo.set_heartbeat_transport(HeartbeatTransport::Connection);
+        let heartbeats = simo.wakers.get(2, Channel::Heartbeat);
+        simo.send_messages(&[
+            OmniMessage::BLE(BLEMessage {
+                from: 1,
+                to: 2,
+                msg: HeartbeatMsg::Request(HeartbeatRequest { round: 1 }),
+            }),
+            OmniMessage::SequencePaxos(PaxosMessage {
+                from: 1,
+                to: 2,
+                msg: PaxosMsg::PrepareReq,
+            }),
+        ]);
+        assert!(timeout(Duration::from_millis(50), heartbeats.notified()).await.is_ok());
+        // the live connection only gets the paxos msg
+        assert_eq!(simo.heartbeat_buffer.lock().unwrap().len(), 1);
+        assert!(matches!(
+            simo.outgoing_buffer.lock().unwrap().iter().collect::<Vec<_>>()[..],
+            [OmniMessage::SequencePaxos(_)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_garbage_frames() {
+        use ddbb_libs::frame::Frame;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let simo = OmniSIMO::new("127.0.0.1:5670".to_string(), HashMap::new());
+        let simo = Arc::new(Mutex::new(simo));
+        OmniSIMO::start_incoming_listener(simo.clone()).await.unwrap();
+
+        // malformed bytes close the offending connection
+        let mut garbage = TcpStream::connect("127.0.0.1:5670").await.unwrap();
+        garbage.write_all(b"?garbage\r\n").await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = timeout(Duration::from_secs(1), garbage.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+
+        // so does a well formed frame that is not a message
+        let stream = Connection::connect("127.0.0.1:5670").await.unwrap();
+        let mut connection = Connection::new(stream);
+        let bad_msg = Frame::Array(vec![
+            Frame::Simple("OmniMessageEntry".to_string()),
+            Frame::Bulk("{".into()),
+        ]);
+        connection.write_frame(&bad_msg).await.unwrap();
+        let read = timeout(Duration::from_secs(1), connection.read_frame()).await;
+        assert!(matches!(read, Ok(Err(_))));
+
+        // and the listener keeps serving
+        let paxos_message: PaxosMessage<LogEntry, Snapshot> = PaxosMessage {
+            from: 1,
+            to: 2,
+            msg: PaxosMsg::ProposalForward(vec![]),
+        };
+        let msg = OmniMessage::SequencePaxos(paxos_message);
+        let stream = Connection::connect("127.0.0.1:5670").await.unwrap();
+        let mut connection = Connection::new(stream);
+        connection
+            .write_frame(&OmniMessageEntry { omni_msg: msg }.to_frame())
+            .await
+            .unwrap();
+        let received = timeout(Duration::from_secs(1), OmniSIMO::receive_message(simo))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.get_receiver(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_node_id_collision() {
+        let mut simo = OmniSIMO::new("127.0.0.1:5672".to_string(), HashMap::new());
+        simo.set_identity(NodeIdentity::new(1));
+        let events = simo.events.clone();
+        let simo = Arc::new(Mutex::new(simo));
+        OmniSIMO::start_incoming_listener(simo.clone()).await.unwrap();
+        let connect_as = |node: NodeIdentity| async move {
+            let stream = Connection::connect("127.0.0.1:5672").await.unwrap();
+            let mut connection = Connection::new(stream);
+            let handshake = Handshake {
+                cluster_uuid: String::new(),
+                build: Some(BuildInfo::current()),
+                node: Some(node),
+                channel: None,
+            };
+            connection.write_frame(&handshake.to_frame()).await.unwrap();
+            connection
+        };
+        let msg_from = |from: NodeId| OmniMessageEntry {
+            omni_msg: OmniMessage::SequencePaxos(PaxosMessage {
+                from,
+                to: 1,
+                msg: PaxosMsg::ProposalForward(vec![]),
+            }),
+        };
+        let closed = |read: std::result::Result<Result<Option<Frame>>, _>| {
+            matches!(read, Ok(Err(_)) | Ok(Ok(None)))
+        };
+
+        let node_2 = NodeIdentity::new(2);
+        let mut first = connect_as(node_2.clone()).await;
+        first.write_frame(&msg_from(2).to_frame()).await.unwrap();
+        let received = timeout(
+            Duration::from_secs(1),
+            OmniSIMO::receive_message(simo.clone()),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(received.get_sender(), 2);
+        // the same process may open several connections
+        let _catch_up = connect_as(node_2).await;
+
+        // another process as node 2, or as this node, is rejected
+        for node in [NodeIdentity::new(2), NodeIdentity::new(1)] {
+            let mut second = connect_as(node).await;
+            let read = timeout(Duration::from_secs(1), second.read_frame()).await;
+            assert!(closed(read));
+        }
+        let collisions = events
+            .lock()
+            .unwrap()
+            .entries()
+            .into_iter()
+            .filter(|entry| matches!(entry.event, ClusterEvent::NodeIdCollision { .. }))
+            .count();
+        assert_eq!(collisions, 2);
+
+        // and so are msgs of another node on the connection of node 2
+        first.write_frame(&msg_from(3).to_frame()).await.unwrap();
+        let read = timeout(Duration::from_secs(1), first.read_frame()).await;
+        assert!(closed(read));
+    }
+
     #[tokio::test]
     async fn test_omni_simo_peer() {
         let mut peers: HashMap<NodeId, String> = HashMap::new();
@@ -289,4 +1764,190 @@ mod test {
             _ = test_receive(omni_simo_copy4) => {}
         }
     }
+
+    #[tokio::test]
+    async fn test_accept_handshake() {
+        let mut simo = OmniSIMO::new("127.0.0.1:5674".to_string(), HashMap::new());
+        let mut manifest = ClusterManifest::new(
+            "ddbb".to_string(),
+            1,
+            1,
+            "127.0.0.1:5674".to_string(),
+            &HashMap::new(),
+            &[],
+        );
+        manifest.cluster_uuid = Some("uuid".to_string());
+        simo.set_manifest(manifest);
+        simo.set_accept_limits(AcceptLimits {
+            handshake_timeout: Duration::from_millis(200),
+            ..AcceptLimits::default()
+        });
+        let simo = Arc::new(Mutex::new(simo));
+        OmniSIMO::start_incoming_listener(simo.clone()).await.unwrap();
+
+        // silent, closed once the handshake timed out
+        let stream = Connection::connect("127.0.0.1:5674").await.unwrap();
+        let mut silent = Connection::new(stream);
+        let read = timeout(Duration::from_secs(1), silent.read_frame()).await;
+        assert!(matches!(read, Ok(Err(_)) | Ok(Ok(None))));
+        assert_eq!(simo.lock().unwrap().inbound_stats().rejected_handshake, 1);
+
+        // attributed to the node of its handshake
+        let stream = Connection::connect("127.0.0.1:5674").await.unwrap();
+        let mut connection = Connection::new(stream);
+        let handshake = Handshake {
+            cluster_uuid: "uuid".to_string(),
+            build: Some(BuildInfo::current()),
+            node: Some(NodeIdentity::new(2)),
+            channel: None,
+        };
+        connection.write_frame(&handshake.to_frame()).await.unwrap();
+        let msg = OmniMessage::SequencePaxos(PaxosMessage {
+            from: 2,
+            to: 1,
+            msg: PaxosMsg::ProposalForward(vec![]),
+        });
+        connection
+            .write_frame(&OmniMessageEntry { omni_msg: msg }.to_frame())
+            .await
+            .unwrap();
+        timeout(Duration::from_secs(1), OmniSIMO::receive_message(simo.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+        let simo = simo.lock().unwrap();
+        assert_eq!(simo.peer_stats()[&2].inbound_connections, 1);
+        assert_eq!(simo.inbound_stats().open, 1);
+    }
+    #[tokio::test]
+    async fn test_superseded_connections() {
+        let simo = OmniSIMO::new("127.0.0.1:5675".to_string(), HashMap::new());
+        let simo = Arc::new(Mutex::new(simo));
+        OmniSIMO::start_incoming_listener(simo.clone()).await.unwrap();
+        let node = NodeIdentity::new(2);
+        let handshake = Handshake {
+            cluster_uuid: String::new(),
+            build: Some(BuildInfo::current()),
+            node: Some(node),
+            channel: Some(Channel::Live.name().to_string()),
+        };
+        let msg = |round| OmniMessageEntry {
+            omni_msg: OmniMessage::SequencePaxos(PaxosMessage {
+                from: 2,
+                to: 1,
+                msg: PaxosMsg::ProposalForward(vec![LogEntry::SetValue {
+                    key: format!("{}", round),
+                    value: vec![],
+                }]),
+            }),
+        };
+
+        // a storm of reconnects, the earlier connections left open at the other end
+        let mut connections = Vec::new();
+        for round in 0..20 {
+            let stream = Connection::connect("127.0.0.1:5675").await.unwrap();
+            let mut connection = Connection::new(stream);
+            connection.write_frame(&handshake.to_frame()).await.unwrap();
+            connection.write_frame(&msg(round).to_frame()).await.unwrap();
+            timeout(Duration::from_secs(1), OmniSIMO::receive_message(simo.clone()))
+                .await
+                .unwrap()
+                .unwrap();
+            connections.push(connection);
+        }
+        let mut latest = connections.pop().unwrap();
+        for mut stale in connections {
+            let read = timeout(Duration::from_secs(1), stale.read_frame()).await;
+            assert!(matches!(read, Ok(Err(_)) | Ok(Ok(None))));
+            // a msg sent on a superseded connection is not read
+            let _ = stale.write_frame(&msg(100).to_frame()).await;
+        }
+        latest.write_frame(&msg(20).to_frame()).await.unwrap();
+        let received = timeout(Duration::from_secs(1), OmniSIMO::receive_message(simo.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(format!("{:?}", received), format!("{:?}", msg(20).omni_msg));
+        assert!(simo.lock().unwrap().incoming_buffer.lock().unwrap().is_empty());
+        let simo = simo.lock().unwrap();
+        assert_eq!(simo.inbound_stats().superseded, 19);
+        assert_eq!(simo.peer_stats()[&2].inbound_connections, 1);
+    }
+    #[tokio::test]
+    async fn test_session_reset() {
+        let simo = OmniSIMO::new("127.0.0.1:5676".to_string(), HashMap::new());
+        let events = simo.events.clone();
+        let simo = Arc::new(Mutex::new(simo));
+        OmniSIMO::start_incoming_listener(simo.clone()).await.unwrap();
+        let handshake = Handshake {
+            cluster_uuid: String::new(),
+            build: Some(BuildInfo::current()),
+            node: Some(NodeIdentity::new(2)),
+            channel: Some(Channel::Live.name().to_string()),
+        };
+        let msg = |to| {
+            OmniMessage::SequencePaxos(PaxosMessage {
+                from: 2,
+                to,
+                msg: PaxosMsg::PrepareReq,
+            })
+        };
+        let connect = || {
+            let handshake = handshake.to_frame();
+            async move {
+                let stream = Connection::connect("127.0.0.1:5676").await.unwrap();
+                let mut connection = Connection::new(stream);
+                connection.write_frame(&handshake).await.unwrap();
+                connection
+            }
+        };
+        // numbered from 1, and on across reconnects
+        let mut seq = 0;
+        let frames = |to_first, to_last, seq: &mut u64| {
+            let frames = (to_first..=to_last)
+                .map(|to| (OmniMessageEntry { omni_msg: msg(to) }.to_frame(), 1))
+                .collect();
+            OmniSIMO::sequence(frames, seq, true)
+        };
+        let receive = |simo: Arc<Mutex<OmniSIMO>>| async move {
+            timeout(Duration::from_secs(1), OmniSIMO::receive_message(simo))
+                .await
+                .unwrap()
+                .unwrap()
+                .get_receiver()
+        };
+
+        let mut first = connect().await;
+        for (frame, _) in frames(1, 2, &mut seq) {
+            first.write_frame(&frame).await.unwrap();
+        }
+        assert_eq!((receive(simo.clone()).await, receive(simo.clone()).await), (1, 2));
+        assert!(simo.lock().unwrap().take_session_resets().is_empty());
+
+        // frames 3 and 4 lost with the first connection
+        let lost = frames(3, 4, &mut seq);
+        let mut second = connect().await;
+        for (frame, _) in frames(5, 5, &mut seq) {
+            second.write_frame(&frame).await.unwrap();
+        }
+        assert_eq!(receive(simo.clone()).await, 5);
+        assert_eq!(simo.lock().unwrap().take_session_resets(), vec![2]);
+        // and read twice after all
+        for (frame, _) in lost.into_iter().chain(frames(6, 6, &mut seq)) {
+            second.write_frame(&frame).await.unwrap();
+        }
+        assert_eq!(receive(simo.clone()).await, 6);
+
+        let stats = simo.lock().unwrap().peer_stats()[&2].clone();
+        assert_eq!((stats.session_resets, stats.stale_frames), (1, 2));
+        let resets: Vec<ClusterEvent> = events
+            .lock()
+            .unwrap()
+            .entries()
+            .into_iter()
+            .map(|entry| entry.event)
+            .filter(|event| matches!(event, ClusterEvent::SessionReset { .. }))
+            .collect();
+        assert_eq!(resets, vec![ClusterEvent::SessionReset { peer: 2, lost: 2 }]);
+    }
 }
@@ -1,5 +1,7 @@
 use bytes::Bytes;
 use omnipaxos_core::messages::Message;
+use omnipaxos_core::storage::Snapshot as OmniSnapshot;
+use serde::{Deserialize, Serialize};
 use serde_json;
 
 use ddbb_libs::data_structure::FrameCast;
@@ -10,7 +12,48 @@ use super::OmniMessage;
 
 use ddbb_libs::{Error, Result};
 
-pub type Snapshot = ();
+/// The logs that changed the state machine, to replay in order. Created from the
+/// whole log it is complete, from the logs after a base it is a delta to replay on
+/// top of the base, as `SnapshotType::Delta`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub logs: Vec<LogEntry>,
+}
+
+impl Snapshot {
+    pub fn is_empty(&self) -> bool {
+        self.logs.is_empty()
+    }
+}
+
+impl OmniSnapshot<LogEntry> for Snapshot {
+    fn create(entries: &[LogEntry]) -> Self {
+        let logs = entries
+            .iter()
+            .filter(|log| {
+                !matches!(
+                    log,
+                    LogEntry::LINRead { .. }
+                        | LogEntry::LINStat { .. }
+                        | LogEntry::Compact
+                        | LogEntry::Backup { .. }
+                )
+            })
+            .cloned()
+            .collect();
+        Self { logs }
+    }
+
+    fn merge(&mut self, delta: Self) {
+        self.logs.extend(delta.logs);
+    }
+
+    /// DDBB persists its own snapshots, see `DDBB::persist_compaction`, the
+    /// omnipaxos log is never trimmed.
+    fn use_snapshots() -> bool {
+        false
+    }
+}
 
 /// for network transportation of omnipaxos_core::messages::Message
 #[derive(Clone, Debug)]
@@ -33,7 +76,7 @@ impl FrameCast for OmniMessageEntry {
                 /// MessageEntry::Success
                 [begin_tag, msg] if *begin_tag == "OmniMessageEntry" => {
                     if let Frame::Bulk(serialized_ble) = msg {
-                        let omni_msg: OmniMessage = serde_json::from_slice(&serialized_ble).unwrap();
+                        let omni_msg: OmniMessage = serde_json::from_slice(&serialized_ble)?;
                         Ok(Box::new(OmniMessageEntry { omni_msg }))
                     } else {
                         Err(frame.to_error()).into()
@@ -48,13 +91,209 @@ impl FrameCast for OmniMessageEntry {
     }
 }
 
+/// Several msgs to one peer in a single frame, only sent to peers advertising
+/// `Capabilities::BATCHING`.
+#[derive(Clone, Debug)]
+pub struct OmniMessageBatch {
+    pub(crate) omni_msgs: Vec<OmniMessage>,
+}
+
+impl FrameCast for OmniMessageBatch {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("OmniMessageBatch".to_string()),
+            Frame::Bulk(serde_json::to_vec(&self.omni_msgs).unwrap().into()),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Bulk(msgs)] if *begin_tag == "OmniMessageBatch" => {
+                    let omni_msgs: Vec<OmniMessage> = serde_json::from_slice(msgs)?;
+                    Ok(Box::new(OmniMessageBatch { omni_msgs }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+/// A heartbeat with a small `AcceptDecide` to the same peer in a single frame, only
+/// sent to peers advertising `Capabilities::PIGGYBACK`.
+#[derive(Clone, Debug)]
+pub struct OmniMessagePiggyback {
+    pub(crate) heartbeat: OmniMessage,
+    pub(crate) accept: OmniMessage,
+}
+
+impl OmniMessagePiggyback {
+    /// the msgs carried, the heartbeat first
+    pub fn into_msgs(self) -> Vec<OmniMessage> {
+        vec![self.heartbeat, self.accept]
+    }
+}
+
+impl FrameCast for OmniMessagePiggyback {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("OmniMessagePiggyback".to_string()),
+            Frame::Bulk(serde_json::to_vec(&(&self.heartbeat, &self.accept)).unwrap().into()),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Bulk(msgs)] if *begin_tag == "OmniMessagePiggyback" => {
+                    let (heartbeat, accept): (OmniMessage, OmniMessage) =
+                        serde_json::from_slice(msgs)?;
+                    Ok(Box::new(OmniMessagePiggyback { heartbeat, accept }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
+/// A frame of msgs numbered in the order it was sent on its channel to the peer, across
+/// reconnects, so the peer tells the frames lost in between from the ones it got twice.
+/// Only sent to peers advertising `Capabilities::SEQUENCED`.
+#[derive(Clone, Debug)]
+pub struct OmniMessageSequenced {
+    pub(crate) seq: u64,
+    /// an entry, a batch or a piggyback
+    pub(crate) frame: Frame,
+}
+
+impl FrameCast for OmniMessageSequenced {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            // begin tag
+            Frame::Simple("OmniMessageSequenced".to_string()),
+            Frame::Integer(self.seq),
+            self.frame.clone(),
+        ])
+    }
+
+    fn from_frame(frame: &Frame) -> Result<Box<Self>> {
+        match frame {
+            Frame::Array(ref frame_vec) => match frame_vec.as_slice() {
+                [begin_tag, Frame::Integer(seq), inner @ Frame::Array(_)]
+                    if *begin_tag == "OmniMessageSequenced" =>
+                {
+                    Ok(Box::new(OmniMessageSequenced {
+                        seq: *seq,
+                        frame: inner.clone(),
+                    }))
+                }
+                _ => Err(frame.to_error()).into(),
+            },
+            _ => Err(frame.to_error()).into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use omnipaxos_core::messages::{ballot_leader_election::BLEMessage, sequence_paxos::{PaxosMsg, PaxosMessage}};
+    use omnipaxos_core::ballot_leader_election::Ballot;
+    use omnipaxos_core::messages::ballot_leader_election::{HeartbeatMsg, HeartbeatReply, HeartbeatRequest};
+    use omnipaxos_core::messages::sequence_paxos::{AcceptDecide, Accepted, Decide};
+    use proptest::prelude::*;
 
     use super::*;
 
+    fn log_strategy() -> impl Strategy<Value = LogEntry> {
+        prop_oneof![
+            (".*", proptest::collection::vec(any::<u8>(), 0..32))
+                .prop_map(|(key, value)| LogEntry::SetValue { key, value }),
+            ("[a-z0-9.:]{1,16}", any::<u64>(), ".*", proptest::collection::vec(any::<u8>(), 0..32))
+                .prop_map(|(addr, ts, key, value)| LogEntry::LINWrite { opid: (addr, ts), key, value }),
+            Just(LogEntry::Compact),
+        ]
+    }
+
+    fn omni_message_strategy() -> impl Strategy<Value = OmniMessage> {
+        let ballot = (any::<u32>(), any::<u64>(), any::<u64>())
+            .prop_map(|(n, priority, pid)| Ballot { n, priority, pid });
+        let paxos_msg = prop_oneof![
+            Just(PaxosMsg::<LogEntry, Snapshot>::PrepareReq),
+            proptest::collection::vec(log_strategy(), 0..8).prop_map(PaxosMsg::ProposalForward),
+            (ballot.clone(), any::<u64>(), proptest::collection::vec(log_strategy(), 0..8)).prop_map(
+                |(n, decided_idx, entries)| PaxosMsg::AcceptDecide(AcceptDecide { n, decided_idx, entries })
+            ),
+            (ballot.clone(), any::<u64>())
+                .prop_map(|(n, accepted_idx)| PaxosMsg::Accepted(Accepted { n, accepted_idx })),
+            (ballot.clone(), any::<u64>())
+                .prop_map(|(n, decided_idx)| PaxosMsg::Decide(Decide { n, decided_idx })),
+        ];
+        let heartbeat = prop_oneof![
+            any::<u32>().prop_map(|round| HeartbeatMsg::Request(HeartbeatRequest { round })),
+            (any::<u32>(), ballot, any::<bool>()).prop_map(|(round, ballot, quorum_connected)| {
+                HeartbeatMsg::Reply(HeartbeatReply { round, ballot, quorum_connected })
+            }),
+        ];
+        prop_oneof![
+            (any::<u64>(), any::<u64>(), paxos_msg)
+                .prop_map(|(from, to, msg)| OmniMessage::SequencePaxos(PaxosMessage { from, to, msg })),
+            (any::<u64>(), any::<u64>(), heartbeat)
+                .prop_map(|(from, to, msg)| OmniMessage::BLE(BLEMessage { from, to, msg })),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_omni_message_entry_round_trip(omni_msg in omni_message_strategy()) {
+            let entry = OmniMessageEntry { omni_msg };
+            let decoded = OmniMessageEntry::from_frame(&entry.to_frame()).unwrap();
+            prop_assert_eq!(format!("{:?}", decoded.omni_msg), format!("{:?}", entry.omni_msg));
+        }
+
+        #[test]
+        fn prop_omni_message_batch_round_trip(omni_msgs in prop::collection::vec(omni_message_strategy(), 0..8)) {
+            let batch = OmniMessageBatch { omni_msgs };
+            let decoded = OmniMessageBatch::from_frame(&batch.to_frame()).unwrap();
+            prop_assert_eq!(format!("{:?}", decoded.omni_msgs), format!("{:?}", batch.omni_msgs));
+            // an entry is not a batch
+            prop_assert!(OmniMessageEntry::from_frame(&batch.to_frame()).is_err());
+        }
+
+        #[test]
+        fn prop_omni_message_piggyback_round_trip(heartbeat in omni_message_strategy(), accept in omni_message_strategy()) {
+            let piggyback = OmniMessagePiggyback { heartbeat, accept };
+            let decoded = OmniMessagePiggyback::from_frame(&piggyback.to_frame()).unwrap();
+            prop_assert_eq!(format!("{:?}", decoded.into_msgs()), format!("{:?}", piggyback.clone().into_msgs()));
+            prop_assert!(OmniMessageBatch::from_frame(&piggyback.to_frame()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_delta_snapshot() {
+        let write = |key: &str, value: &str| LogEntry::SetValue {
+            key: key.to_string(),
+            value: Vec::from(value),
+        };
+        let read = LogEntry::LINRead {
+            opid: ("127.0.0.1:6650".to_string(), 1),
+            key: "k1".to_string(),
+            value: None,
+        };
+        let mut snapshot = Snapshot::create(&[write("k1", "v1"), read, LogEntry::Compact]);
+        assert_eq!(snapshot.logs, vec![write("k1", "v1")]);
+        snapshot.merge(Snapshot::create(&[write("k2", "v2"), write("k1", "v3")]));
+        assert_eq!(
+            snapshot.logs,
+            vec![write("k1", "v1"), write("k2", "v2"), write("k1", "v3")]
+        );
+        assert!(Snapshot::create(&[LogEntry::Compact]).is_empty());
+    }
+
     #[test]
     fn test_serialize() {
         let log = LogEntry::SetValue {
@@ -89,4 +328,146 @@ mod tests {
         let omni_deserialized = OmniMessageEntry::from_frame(&omni_frame).unwrap();
         println!("deframe: {:?}", omni_deserialized);
     }
+
+    #[test]
+    fn test_golden_frames() {
+        use ddbb_libs::golden::assert_golden;
+        use omnipaxos_core::messages::sequence_paxos::{
+            AcceptStopSign, AcceptSync, AcceptedStopSign, Compaction, DecideStopSign, FirstAccept,
+            Prepare, Promise,
+        };
+        use omnipaxos_core::storage::{SnapshotType, StopSign};
+        use std::path::Path;
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden");
+        let (n, n_accepted) = (Ballot::with(2, 0, 1), Ballot::with(1, 0, 1));
+        let log = || LogEntry::SetValue {
+            key: "k".to_string(),
+            value: Vec::from("v"),
+        };
+        let stopsign = |metadata| StopSign::with(2, vec![1, 2, 3], metadata);
+        let paxos = |msg| {
+            OmniMessage::SequencePaxos(PaxosMessage {
+                from: 1,
+                to: 2,
+                msg,
+            })
+        };
+        let ble = |msg| {
+            OmniMessage::BLE(BLEMessage {
+                from: 1,
+                to: 2,
+                msg,
+            })
+        };
+        let msgs = vec![
+            ("paxos_prepare_req", paxos(PaxosMsg::PrepareReq)),
+            (
+                "paxos_prepare",
+                paxos(PaxosMsg::Prepare(Prepare {
+                    n,
+                    decided_idx: 3,
+                    n_accepted,
+                    accepted_idx: 4,
+                })),
+            ),
+            (
+                "paxos_promise",
+                paxos(PaxosMsg::Promise(Promise {
+                    n,
+                    n_accepted,
+                    decided_snapshot: None,
+                    suffix: vec![log()],
+                    decided_idx: 3,
+                    accepted_idx: 4,
+                    stopsign: None,
+                })),
+            ),
+            (
+                "paxos_accept_sync",
+                paxos(PaxosMsg::AcceptSync(AcceptSync {
+                    n,
+                    decided_snapshot: Some(SnapshotType::Complete(Snapshot { logs: vec![log()] })),
+                    suffix: vec![],
+                    sync_idx: 3,
+                    decided_idx: 3,
+                    stopsign: None,
+                })),
+            ),
+            (
+                "paxos_first_accept",
+                paxos(PaxosMsg::FirstAccept(FirstAccept { n })),
+            ),
+            (
+                "paxos_accept_decide",
+                paxos(PaxosMsg::AcceptDecide(AcceptDecide {
+                    n,
+                    decided_idx: 3,
+                    entries: vec![log()],
+                })),
+            ),
+            (
+                "paxos_accepted",
+                paxos(PaxosMsg::Accepted(Accepted { n, accepted_idx: 4 })),
+            ),
+            (
+                "paxos_decide",
+                paxos(PaxosMsg::Decide(Decide { n, decided_idx: 4 })),
+            ),
+            (
+                "paxos_proposal_forward",
+                paxos(PaxosMsg::ProposalForward(vec![log()])),
+            ),
+            (
+                "paxos_compaction",
+                paxos(PaxosMsg::Compaction(Compaction::Trim(3))),
+            ),
+            (
+                "paxos_accept_stopsign",
+                paxos(PaxosMsg::AcceptStopSign(AcceptStopSign {
+                    n,
+                    ss: stopsign(None),
+                })),
+            ),
+            (
+                "paxos_accepted_stopsign",
+                paxos(PaxosMsg::AcceptedStopSign(AcceptedStopSign { n })),
+            ),
+            (
+                "paxos_decide_stopsign",
+                paxos(PaxosMsg::DecideStopSign(DecideStopSign { n })),
+            ),
+            (
+                "paxos_forward_stopsign",
+                paxos(PaxosMsg::ForwardStopSign(stopsign(Some(vec![1])))),
+            ),
+            (
+                "ble_request",
+                ble(HeartbeatMsg::Request(HeartbeatRequest { round: 5 })),
+            ),
+            (
+                "ble_reply",
+                ble(HeartbeatMsg::Reply(HeartbeatReply {
+                    round: 5,
+                    ballot: n,
+                    quorum_connected: true,
+                })),
+            ),
+        ];
+        let batch = OmniMessageBatch {
+            omni_msgs: vec![msgs[0].1.clone(), msgs[14].1.clone()],
+        };
+        let sequenced = OmniMessageSequenced {
+            seq: 7,
+            frame: OmniMessageEntry {
+                omni_msg: msgs[0].1.clone(),
+            }
+            .to_frame(),
+        };
+        for (name, omni_msg) in msgs {
+            assert_golden(&dir, name, &OmniMessageEntry { omni_msg });
+        }
+        assert_golden(&dir, "batch", &batch);
+        assert_golden(&dir, "sequenced", &sequenced);
+    }
 }
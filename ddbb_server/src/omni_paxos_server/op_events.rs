@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex};
+
+use omnipaxos_core::{ballot_leader_election::Ballot, util::NodeId};
+
+use crate::event_log::{ClusterEvent, SharedEventLog};
+
+pub type SharedServerEvents = Arc<Mutex<ServerEvents>>;
+
+/// A new leader was elected, `previous` is the leader seen before, if any.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeaderChange {
+    pub previous: Option<Ballot>,
+    pub leader: Ballot,
+}
+
+/// A stopsign was decided, the cluster moves to configuration `config_id`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reconfig {
+    pub config_id: u32,
+    pub nodes: Vec<NodeId>,
+}
+
+/// The state machine was replaced at `applied_idx`, from `source`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotInstalled {
+    pub applied_idx: u64,
+    pub source: String,
+}
+
+type Callback<T> = Box<dyn Fn(&T) + Send>;
+
+/// The changes of the OmniPaxos server other subsystems subscribe to, instead of each
+/// polling omnipaxos for them. The callbacks run on the task that saw the change,
+/// with this locked, so they must be quick and must not lock the DDBB, which may be
+/// the one installing a snapshot: they hand the change over, e.g. to a channel.
+#[derive(Default)]
+pub struct ServerEvents {
+    leader_change: Vec<Callback<LeaderChange>>,
+    reconfig: Vec<Callback<Reconfig>>,
+    snapshot_installed: Vec<Callback<SnapshotInstalled>>,
+}
+
+impl std::fmt::Debug for ServerEvents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerEvents")
+            .field("leader_change", &self.leader_change.len())
+            .field("reconfig", &self.reconfig.len())
+            .field("snapshot_installed", &self.snapshot_installed.len())
+            .finish()
+    }
+}
+
+impl ServerEvents {
+    pub fn shared() -> SharedServerEvents {
+        Arc::new(Mutex::new(Self::default()))
+    }
+
+    pub fn on_leader_change(&mut self, callback: impl Fn(&LeaderChange) + Send + 'static) {
+        self.leader_change.push(Box::new(callback));
+    }
+
+    pub fn on_reconfig(&mut self, callback: impl Fn(&Reconfig) + Send + 'static) {
+        self.reconfig.push(Box::new(callback));
+    }
+
+    pub fn on_snapshot_installed(&mut self, callback: impl Fn(&SnapshotInstalled) + Send + 'static) {
+        self.snapshot_installed.push(Box::new(callback));
+    }
+
+    pub fn leader_changed(&self, change: LeaderChange) {
+        self.leader_change.iter().for_each(|callback| callback(&change));
+    }
+
+    pub fn reconfigured(&self, reconfig: Reconfig) {
+        self.reconfig.iter().for_each(|callback| callback(&reconfig));
+    }
+
+    pub fn snapshot_installed(&self, installed: SnapshotInstalled) {
+        self.snapshot_installed
+            .iter()
+            .for_each(|callback| callback(&installed));
+    }
+
+    /// #Descriptions: record every change in `events` as a `ClusterEvent`.
+    pub fn record_in(&mut self, events: SharedEventLog) {
+        let log = events.clone();
+        self.on_leader_change(move |change| {
+            log.lock().unwrap().record(ClusterEvent::LeaderElected {
+                leader: change.leader.pid,
+                ballot: change.leader,
+            })
+        });
+        let log = events.clone();
+        self.on_reconfig(move |reconfig| {
+            log.lock().unwrap().record(ClusterEvent::Reconfigured {
+                config_id: reconfig.config_id,
+                nodes: reconfig.nodes.clone(),
+            })
+        });
+        self.on_snapshot_installed(move |installed| {
+            events.lock().unwrap().record(ClusterEvent::SnapshotInstalled {
+                applied_idx: installed.applied_idx,
+                source: installed.source.clone(),
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_log::EventLog;
+
+    #[test]
+    fn test_server_events() {
+        let mut server_events = ServerEvents::default();
+        let events = EventLog::shared(10);
+        server_events.record_in(events.clone());
+        let leaders = Arc::new(Mutex::new(Vec::new()));
+        let seen = leaders.clone();
+        server_events.on_leader_change(move |change| seen.lock().unwrap().push(change.leader.pid));
+
+        let leader = Ballot::with(1, 0, 2);
+        server_events.leader_changed(LeaderChange {
+            previous: None,
+            leader,
+        });
+        server_events.snapshot_installed(SnapshotInstalled {
+            applied_idx: 7,
+            source: "backup".to_string(),
+        });
+        assert_eq!(*leaders.lock().unwrap(), vec![2]);
+        let recorded: Vec<ClusterEvent> = events
+            .lock()
+            .unwrap()
+            .entries()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            recorded,
+            vec![
+                ClusterEvent::LeaderElected { leader: 2, ballot: leader },
+                ClusterEvent::SnapshotInstalled {
+                    applied_idx: 7,
+                    source: "backup".to_string()
+                },
+            ]
+        );
+    }
+}
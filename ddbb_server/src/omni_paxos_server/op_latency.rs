@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ddbb_libs::clock::SharedClock;
+use omnipaxos_core::util::NodeId;
+
+use super::OmniMessage;
+use crate::tasks::spawn_named;
+
+/// Latency injected on the link to a peer, to rehearse failovers under WAN-like
+/// latency on a test cluster.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkLatency {
+    pub delay_ms: u64,
+    /// each msg is delayed by `delay_ms` give or take up to this
+    pub jitter_ms: u64,
+}
+
+impl LinkLatency {
+    pub fn is_zero(&self) -> bool {
+        self.delay_ms == 0 && self.jitter_ms == 0
+    }
+
+    /// #Descriptions: the delay of one msg, jittered by `random`.
+    fn sample(&self, random: u64) -> Duration {
+        let jitter = random % (2 * self.jitter_ms + 1);
+        Duration::from_millis((self.delay_ms + jitter).saturating_sub(self.jitter_ms))
+    }
+}
+
+/// The msgs to a peer with a latency, held until they are due.
+struct DelayLine {
+    latency: LinkLatency,
+    sender: mpsc::UnboundedSender<(Instant, OmniMessage)>,
+    /// a msg is never due before the one held before it, the link stays in order
+    last_due: Instant,
+    /// xorshift state of the jitter
+    random: u64,
+}
+
+impl DelayLine {
+    fn next_random(&mut self) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        self.random
+    }
+}
+
+/// The latencies injected on the links of OmniSIMO, see `OmniSIMO::set_link_latency`.
+/// Once a link had a latency its msgs always go through its delay line, so that
+/// removing the latency does not send the new msgs before the held ones.
+#[derive(Clone, Default)]
+pub struct NetworkPolicy {
+    lines: Arc<Mutex<HashMap<NodeId, DelayLine>>>,
+}
+
+impl NetworkPolicy {
+    /// #Descriptions: delay the msgs to `peer` by `latency` from now on, passing each to
+    /// `forward` once due on `clock`.
+    pub fn set_latency(
+        &self,
+        peer: NodeId,
+        latency: LinkLatency,
+        clock: SharedClock,
+        forward: impl Fn(OmniMessage) + Send + 'static,
+    ) {
+        let mut lines = self.lines.lock().unwrap();
+        if let Some(line) = lines.get_mut(&peer) {
+            line.latency = latency;
+            return;
+        }
+        if latency.is_zero() {
+            return;
+        }
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(Instant, OmniMessage)>();
+        let line_clock = clock.clone();
+        spawn_named(&format!("omni_simo latency to {}", peer), async move {
+            while let Some((due, msg)) = receiver.recv().await {
+                let now = line_clock.now();
+                if due > now {
+                    line_clock.sleep(due - now).await;
+                }
+                forward(msg);
+            }
+        });
+        lines.insert(
+            peer,
+            DelayLine {
+                latency,
+                sender,
+                last_due: clock.now(),
+                random: (clock.unix_millis() ^ (peer << 32)) | 1,
+            },
+        );
+    }
+
+    /// #Descriptions: hold `msg` in the delay line of its receiver, or hand it back if
+    /// its link has none.
+    pub fn hold(&self, msg: OmniMessage, now: Instant) -> Option<OmniMessage> {
+        let mut lines = self.lines.lock().unwrap();
+        let line = match lines.get_mut(&msg.get_receiver()) {
+            Some(line) => line,
+            None => return Some(msg),
+        };
+        let random = line.next_random();
+        let due = (now + line.latency.sample(random)).max(line.last_due);
+        line.last_due = due;
+        match line.sender.send((due, msg)) {
+            Ok(()) => None,
+            // the runtime is shutting down
+            Err(mpsc::error::SendError((_, msg))) => Some(msg),
+        }
+    }
+
+    pub fn latencies(&self) -> BTreeMap<NodeId, LinkLatency> {
+        self.lines
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, line)| !line.latency.is_zero())
+            .map(|(peer, line)| (*peer, line.latency))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddbb_libs::clock::MockClock;
+    use omnipaxos_core::ballot_leader_election::Ballot;
+    use omnipaxos_core::messages::sequence_paxos::{PaxosMessage, PaxosMsg, Prepare};
+    use omnipaxos_core::messages::Message;
+    use tokio::time::timeout;
+
+    fn msg(to: NodeId, n: u32) -> OmniMessage {
+        Message::SequencePaxos(PaxosMessage {
+            from: 1,
+            to,
+            msg: PaxosMsg::Prepare(Prepare {
+                n: Ballot::with(n, 0, 1),
+                decided_idx: 0,
+                n_accepted: Ballot::default(),
+                accepted_idx: 0,
+            }),
+        })
+    }
+
+    #[test]
+    fn test_latency_sample() {
+        let latency = LinkLatency {
+            delay_ms: 100,
+            jitter_ms: 20,
+        };
+        for random in 0..100 {
+            let delay = latency.sample(random).as_millis();
+            assert!((80..=120).contains(&delay));
+        }
+        let fixed = LinkLatency {
+            delay_ms: 50,
+            jitter_ms: 0,
+        };
+        assert_eq!(fixed.sample(7), Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_network_policy() {
+        let clock = MockClock::new(0);
+        let policy = NetworkPolicy::default();
+        let (sent, mut received) = mpsc::unbounded_channel();
+        let latency = LinkLatency {
+            delay_ms: 100,
+            jitter_ms: 10,
+        };
+        policy.set_latency(2, latency, clock.shared(), move |msg| {
+            let _ = sent.send(msg);
+        });
+        assert_eq!(policy.latencies()[&2], latency);
+
+        // other links are not delayed
+        assert!(policy.hold(msg(3, 1), clock.shared().now()).is_some());
+        for n in 1..=3 {
+            assert!(policy.hold(msg(2, n), clock.shared().now()).is_none());
+        }
+        clock.advance(Duration::from_millis(80));
+        tokio::task::yield_now().await;
+        assert!(received.try_recv().is_err());
+        clock.advance(Duration::from_millis(40));
+        for n in 1..=3 {
+            let msg = timeout(Duration::from_secs(1), received.recv()).await.unwrap();
+            assert_eq!(format!("{:?}", msg), format!("{:?}", Some(self::msg(2, n))));
+        }
+
+        // without the latency the link keeps its delay line
+        policy.set_latency(2, LinkLatency::default(), clock.shared(), |_| {});
+        assert!(policy.latencies().is_empty());
+        assert!(policy.hold(msg(2, 4), clock.shared().now()).is_none());
+        let msg = timeout(Duration::from_secs(1), received.recv()).await.unwrap();
+        assert_eq!(format!("{:?}", msg), format!("{:?}", Some(self::msg(2, 4))));
+    }
+}
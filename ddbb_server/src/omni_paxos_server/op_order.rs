@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use omnipaxos_core::util::NodeId;
+
+use crate::bootstrap::NodeIdentity;
+
+/// Where an `OmniMessageSequenced` frame stands in the order of its channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// the next one, or the first one of a process
+    InOrder,
+    /// after that many frames that never arrived
+    Gap(u64),
+    /// a frame numbered before the latest one read, e.g. from a superseded connection
+    Stale,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    /// the instance and the latest seq read, by the node and channel sending
+    latest: HashMap<(NodeId, String), (String, u64)>,
+    /// the peers with frames lost since the last `take_resets`
+    resets: Vec<NodeId>,
+}
+
+/// The FIFO order of the msgs of each peer, per channel: OmniSIMO reads the frames of
+/// a connection in order, and the sequence numbers tell the frames lost or read twice
+/// once a peer reconnected. A gap resets the session with the peer, omnipaxos then
+/// resyncs with it instead of taking the msgs after the gap as the next ones.
+#[derive(Clone, Debug, Default)]
+pub struct FrameOrder(Arc<Mutex<Sessions>>);
+
+impl FrameOrder {
+    /// #Descriptions: the delivery of frame `seq` from `peer` on `channel`, registered
+    /// as the latest one unless stale.
+    pub fn accept(&self, peer: &NodeIdentity, channel: &str, seq: u64) -> Delivery {
+        let mut sessions = self.0.lock().unwrap();
+        let key = (peer.node_id, channel.to_string());
+        let delivery = match sessions.latest.get(&key) {
+            // a restarted peer numbers its frames from 1 again
+            Some((instance_id, latest)) if *instance_id == peer.instance_id => {
+                if seq <= *latest {
+                    Delivery::Stale
+                } else if seq == latest + 1 {
+                    Delivery::InOrder
+                } else {
+                    Delivery::Gap(seq - latest - 1)
+                }
+            }
+            _ => Delivery::InOrder,
+        };
+        if delivery != Delivery::Stale {
+            sessions.latest.insert(key, (peer.instance_id.clone(), seq));
+        }
+        if let Delivery::Gap(_) = delivery {
+            if !sessions.resets.contains(&peer.node_id) {
+                sessions.resets.push(peer.node_id);
+            }
+        }
+        delivery
+    }
+
+    /// #Descriptions: the peers to resync with, once each, in the order of their gaps.
+    pub fn take_resets(&self) -> Vec<NodeId> {
+        std::mem::take(&mut self.0.lock().unwrap().resets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_order() {
+        let order = FrameOrder::default();
+        let peer = NodeIdentity::new(2);
+        assert_eq!(order.accept(&peer, "live", 5), Delivery::InOrder);
+        assert_eq!(order.accept(&peer, "live", 6), Delivery::InOrder);
+        // the channels are numbered apart
+        assert_eq!(order.accept(&peer, "catch_up", 1), Delivery::InOrder);
+        assert!(order.take_resets().is_empty());
+
+        // reconnected, two frames lost with the old connection
+        assert_eq!(order.accept(&peer, "live", 9), Delivery::Gap(2));
+        assert_eq!(order.accept(&peer, "live", 7), Delivery::Stale);
+        assert_eq!(order.accept(&peer, "live", 9), Delivery::Stale);
+        assert_eq!(order.accept(&peer, "catch_up", 3), Delivery::Gap(1));
+        assert_eq!(order.take_resets(), vec![2]);
+        assert!(order.take_resets().is_empty());
+
+        // restarted
+        let restarted = NodeIdentity::new(2);
+        assert_eq!(order.accept(&restarted, "live", 1), Delivery::InOrder);
+        assert_eq!(order.accept(&restarted, "live", 2), Delivery::InOrder);
+        assert!(order.take_resets().is_empty());
+    }
+}
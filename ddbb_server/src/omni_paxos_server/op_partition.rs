@@ -0,0 +1,70 @@
+use omnipaxos_core::util::NodeId;
+
+/// The peers the outgoing connections of this node are up to, out of all its peers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Connectivity {
+    pub connected: Vec<NodeId>,
+    pub peers: usize,
+}
+
+impl Connectivity {
+    /// #Descriptions: whether this node reaches a majority of the cluster, itself included.
+    pub fn has_quorum(&self) -> bool {
+        self.connected.len() + 1 > (self.peers + 1) / 2
+    }
+}
+
+/// Tells when this node rejoins the cluster after a partition: omnipaxos does not see
+/// the connections, and a follower cut off from the leader would wait for the next
+/// msg of the leader to find out that it missed some, or a new leader was elected.
+#[derive(Debug, Default)]
+pub struct PartitionTracker {
+    /// a majority was out of reach at the last `observe`
+    partitioned: bool,
+}
+
+impl PartitionTracker {
+    /// #Descriptions: observe the `connectivity` of now; the peers to resync with, with a
+    /// `PrepareReq`, once a majority is in reach again after it was not.
+    pub fn observe(&mut self, connectivity: &Connectivity) -> Option<Vec<NodeId>> {
+        let partitioned = !connectivity.has_quorum();
+        let rejoined = self.partitioned && !partitioned;
+        self.partitioned = partitioned;
+        if rejoined {
+            Some(connectivity.connected.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn is_partitioned(&self) -> bool {
+        self.partitioned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_tracker() {
+        let connectivity = |connected: &[NodeId]| Connectivity {
+            connected: connected.to_vec(),
+            peers: 4,
+        };
+        assert!(connectivity(&[2, 3]).has_quorum());
+        assert!(!connectivity(&[2]).has_quorum());
+        assert!(Connectivity::default().has_quorum());
+
+        let mut tracker = PartitionTracker::default();
+        assert_eq!(tracker.observe(&connectivity(&[2, 3, 4])), None);
+        // one peer lost, still in the majority
+        assert_eq!(tracker.observe(&connectivity(&[2, 3])), None);
+        assert_eq!(tracker.observe(&connectivity(&[2])), None);
+        assert!(tracker.is_partitioned());
+        assert_eq!(tracker.observe(&connectivity(&[])), None);
+        assert_eq!(tracker.observe(&connectivity(&[4, 5])), Some(vec![4, 5]));
+        // once
+        assert_eq!(tracker.observe(&connectivity(&[4, 5, 2])), None);
+    }
+}
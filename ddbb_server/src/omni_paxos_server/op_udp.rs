@@ -0,0 +1,209 @@
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use omnipaxos_core::messages::Message;
+use omnipaxos_core::util::NodeId;
+
+use super::OmniMessage;
+use crate::config::MAX_HEARTBEAT_DATAGRAM;
+use crate::metrics::PeerStats;
+use ddbb_libs::Result;
+
+/// A heartbeat sent in a UDP datagram of its own. Datagrams are lost, duplicated and
+/// reordered: the leader election tolerates losing some, and `seq` drops the ones
+/// arriving after a later one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeartbeatDatagram {
+    /// the datagrams of another cluster are dropped
+    pub cluster_uuid: String,
+    /// unix ms at which the sender started, its `seq` starts again on a restart
+    pub epoch: u64,
+    pub seq: u64,
+    pub msg: OmniMessage,
+}
+
+/// The latest datagram received from each sender, to drop the stale ones and count
+/// the lost ones.
+#[derive(Debug, Default)]
+pub struct SeqTracker {
+    latest: HashMap<NodeId, (u64, u64)>,
+}
+
+impl SeqTracker {
+    /// #Descriptions: whether the datagram `seq` of `sender` started at `epoch` is
+    /// newer than the ones received before, with the datagrams lost in between.
+    pub fn accept(&mut self, sender: NodeId, epoch: u64, seq: u64) -> Option<u64> {
+        match self.latest.get(&sender) {
+            // a restarted sender
+            Some((latest_epoch, _)) if epoch > *latest_epoch => {}
+            Some((latest_epoch, latest_seq)) if epoch < *latest_epoch || seq <= *latest_seq => {
+                return None;
+            }
+            _ => {}
+        }
+        let lost = match self.latest.get(&sender) {
+            Some((latest_epoch, latest_seq)) if *latest_epoch == epoch => seq - latest_seq - 1,
+            _ => 0,
+        };
+        self.latest.insert(sender, (epoch, seq));
+        Some(lost)
+    }
+}
+
+/// Sends the heartbeats to the peers, numbered per peer.
+#[derive(Debug)]
+pub struct HeartbeatSender {
+    socket: UdpSocket,
+    cluster_uuid: String,
+    epoch: u64,
+    seqs: Mutex<HashMap<NodeId, u64>>,
+}
+
+impl HeartbeatSender {
+    pub async fn bind(cluster_uuid: String) -> Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind("0.0.0.0:0").await?,
+            cluster_uuid,
+            epoch: unix_millis(),
+            seqs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// #Descriptions: send `msg` to `addr`, once; returns the bytes sent.
+    pub async fn send(&self, addr: &str, msg: OmniMessage) -> Result<u64> {
+        let seq = {
+            let mut seqs = self.seqs.lock().unwrap();
+            let seq = seqs.entry(msg.get_receiver()).or_insert(0);
+            *seq += 1;
+            *seq
+        };
+        let datagram = HeartbeatDatagram {
+            cluster_uuid: self.cluster_uuid.clone(),
+            epoch: self.epoch,
+            seq,
+            msg,
+        };
+        let bytes = serde_json::to_vec(&datagram)?;
+        self.socket.send_to(&bytes, addr).await?;
+        Ok(bytes.len() as u64)
+    }
+}
+
+/// #Descriptions: receive the heartbeats sent to `addr` in UDP datagrams into
+/// `incoming_buffer`, only those of the cluster of `cluster_uuid`, if any.
+pub async fn receive_heartbeats(
+    addr: String,
+    cluster_uuid: Option<String>,
+    incoming_buffer: Arc<Mutex<VecDeque<OmniMessage>>>,
+    received: Arc<Notify>,
+    peer_stats: Arc<Mutex<HashMap<NodeId, PeerStats>>>,
+) -> Result<()> {
+    let socket = UdpSocket::bind(&addr).await?;
+    let mut tracker = SeqTracker::default();
+    let mut buf = vec![0; MAX_HEARTBEAT_DATAGRAM];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                error!("Receive heartbeat failed: {:?}", e);
+                continue;
+            }
+        };
+        let datagram: HeartbeatDatagram = match serde_json::from_slice(&buf[..len]) {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                debug!("Dropped datagram from {}: {}", from, e);
+                continue;
+            }
+        };
+        let same_cluster = cluster_uuid
+            .as_ref()
+            .map_or(true, |uuid| *uuid == datagram.cluster_uuid);
+        // only heartbeats come this way
+        if !same_cluster || !matches!(datagram.msg, Message::BLE(_)) {
+            debug!("Dropped datagram from {}", from);
+            continue;
+        }
+        let sender = datagram.msg.get_sender();
+        let lost = match tracker.accept(sender, datagram.epoch, datagram.seq) {
+            Some(lost) => lost,
+            None => continue,
+        };
+        {
+            let mut peer_stats = peer_stats.lock().unwrap();
+            let stats = peer_stats.entry(sender).or_default();
+            stats.msgs_received += 1;
+            stats.bytes_received += len as u64;
+            stats.heartbeats_lost += lost;
+            stats.last_seen_ms = unix_millis();
+        }
+        incoming_buffer.lock().unwrap().push_back(datagram.msg);
+        received.notify_one();
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use omnipaxos_core::messages::ballot_leader_election::{
+        BLEMessage, HeartbeatMsg, HeartbeatRequest,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_seq_tracker() {
+        let mut tracker = SeqTracker::default();
+        assert_eq!(tracker.accept(2, 100, 1), Some(0));
+        assert_eq!(tracker.accept(2, 100, 4), Some(2));
+        // late or duplicated
+        assert_eq!(tracker.accept(2, 100, 3), None);
+        assert_eq!(tracker.accept(2, 100, 4), None);
+        // restarted, and a datagram of before the restart
+        assert_eq!(tracker.accept(2, 200, 1), Some(0));
+        assert_eq!(tracker.accept(2, 100, 5), None);
+        assert_eq!(tracker.accept(3, 100, 7), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_udp_heartbeats() {
+        let incoming_buffer = Arc::new(Mutex::new(VecDeque::new()));
+        let received = Arc::new(Notify::new());
+        let peer_stats = Arc::new(Mutex::new(HashMap::new()));
+        let receiver = receive_heartbeats(
+            "127.0.0.1:5690".to_string(),
+            Some("uuid".to_string()),
+            incoming_buffer.clone(),
+            received.clone(),
+            peer_stats.clone(),
+        );
+        tokio::spawn(async move { receiver.await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let heartbeat = OmniMessage::BLE(BLEMessage {
+            from: 1,
+            to: 2,
+            msg: HeartbeatMsg::Request(HeartbeatRequest { round: 1 }),
+        });
+        let other = HeartbeatSender::bind("other".to_string()).await.unwrap();
+        other.send("127.0.0.1:5690", heartbeat.clone()).await.unwrap();
+        let sender = HeartbeatSender::bind("uuid".to_string()).await.unwrap();
+        sender.send("127.0.0.1:5690", heartbeat).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), received.notified()).await.unwrap();
+        // the datagram of the other cluster was dropped
+        assert_eq!(incoming_buffer.lock().unwrap().len(), 1);
+        assert_eq!(peer_stats.lock().unwrap()[&1].msgs_received, 1);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use ddbb_libs::{Error, Result};
+
+use crate::op_data_structure::LogEntry;
+
+struct QueuedProposal {
+    log: LogEntry,
+    /// the proposer gives up waiting then
+    deadline: Instant,
+}
+
+/// Proposals made while this node has no leader to replicate them, in its prepare phase
+/// or during a leader change. They are proposed in order once a leader is established,
+/// instead of being dropped with a leader that is gone.
+pub struct ProposalQueue {
+    queued: VecDeque<QueuedProposal>,
+    capacity: usize,
+}
+
+impl ProposalQueue {
+    pub fn new(capacity: usize) -> Self {
+        ProposalQueue {
+            queued: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Queue `log` until `deadline`, fails with a retryable error once full.
+    pub fn push(&mut self, log: LogEntry, deadline: Instant) -> Result<()> {
+        if self.queued.len() >= self.capacity {
+            return Err(Error::Overloaded(
+                "no leader and too many queued proposals, retry later".to_string(),
+            ));
+        }
+        self.queued.push_back(QueuedProposal { log, deadline });
+        Ok(())
+    }
+
+    /// Drop the proposal of `opid`, nobody waits for it anymore.
+    pub fn remove(&mut self, opid: &(String, u64)) {
+        self.queued.retain(|queued| queued.log.opid() != Some(opid));
+    }
+
+    /// Drop the proposals whose deadline passed at `now`, returning how many.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let len = self.queued.len();
+        self.queued.retain(|queued| queued.deadline > now);
+        len - self.queued.len()
+    }
+
+    /// Take the proposals still live at `now`, in the order they were queued.
+    pub fn take(&mut self, now: Instant) -> Vec<LogEntry> {
+        self.queued
+            .drain(..)
+            .filter(|queued| queued.deadline > now)
+            .map(|queued| queued.log)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn write(ts: u64) -> LogEntry {
+        LogEntry::LINWrite {
+            opid: ("127.0.0.1:6550".to_string(), ts),
+            key: "k".to_string(),
+            value: Vec::from("v"),
+        }
+    }
+
+    #[test]
+    fn test_proposal_queue() {
+        let now = Instant::now();
+        let at = |ms| now + Duration::from_millis(ms);
+        let mut queue = ProposalQueue::new(3);
+        queue.push(write(1), at(10)).unwrap();
+        queue.push(write(2), at(100)).unwrap();
+        queue.push(write(3), at(100)).unwrap();
+        assert!(queue.push(write(4), now).unwrap_err().is_retryable());
+
+        queue.remove(&("127.0.0.1:6550".to_string(), 2));
+        assert_eq!(queue.expire(at(20)), 1);
+        queue.push(write(5), at(100)).unwrap();
+
+        let taken: Vec<u64> = queue
+            .take(at(50))
+            .iter()
+            .map(|log| log.opid().unwrap().1)
+            .collect();
+        assert_eq!(taken, vec![3, 5]);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+/// A change of whether the node reaches a majority of the cluster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuorumChange {
+    Lost,
+    Regained,
+}
+
+/// Whether this node still reaches a majority of the cluster, through its OmniSIMO
+/// connections and the heartbeats of the leader election. The quorum is only taken as
+/// lost once out of reach for `timeout`, so a heartbeat round missed or a reconnect
+/// does not fail the proposals in flight.
+#[derive(Debug)]
+pub struct QuorumWatch {
+    timeout: Duration,
+    /// out of reach since, `None` while a majority is in reach
+    unreachable_since: Option<Instant>,
+    lost: bool,
+}
+
+impl QuorumWatch {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            unreachable_since: None,
+            lost: false,
+        }
+    }
+
+    /// #Descriptions: observe whether a majority is in reach at `now`; the change, if
+    /// the quorum is lost or regained with it.
+    pub fn observe(&mut self, in_reach: bool, now: Instant) -> Option<QuorumChange> {
+        if in_reach {
+            self.unreachable_since = None;
+            if self.lost {
+                self.lost = false;
+                return Some(QuorumChange::Regained);
+            }
+            return None;
+        }
+        let since = *self.unreachable_since.get_or_insert(now);
+        if !self.lost && now.saturating_duration_since(since) >= self.timeout {
+            self.lost = true;
+            return Some(QuorumChange::Lost);
+        }
+        None
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quorum_watch() {
+        let now = Instant::now();
+        let at = |ms| now + Duration::from_millis(ms);
+        let mut watch = QuorumWatch::new(Duration::from_millis(300));
+        assert_eq!(watch.observe(true, at(0)), None);
+        // a short loss is tolerated
+        assert_eq!(watch.observe(false, at(100)), None);
+        assert_eq!(watch.observe(true, at(200)), None);
+        assert_eq!(watch.observe(false, at(300)), None);
+        assert_eq!(watch.observe(false, at(500)), None);
+        assert!(!watch.is_lost());
+        assert_eq!(watch.observe(false, at(600)), Some(QuorumChange::Lost));
+        assert_eq!(watch.observe(false, at(700)), None);
+        assert!(watch.is_lost());
+        assert_eq!(watch.observe(true, at(800)), Some(QuorumChange::Regained));
+        assert!(!watch.is_lost());
+        assert_eq!(watch.observe(false, at(900)), None);
+    }
+}
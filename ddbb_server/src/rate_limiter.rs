@@ -0,0 +1,169 @@
+use std::time::Instant;
+
+/// Allows `rate` operations per second on average, and bursts of up to `burst`.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Change the limits, e.g. to a replicated setting, keeping the tokens within `burst`.
+    pub fn set_limits(&mut self, rate: f64, burst: f64) {
+        self.rate = rate;
+        self.burst = burst;
+        self.tokens = self.tokens.min(burst);
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    pub fn try_take_at(&mut self, now: Instant) -> bool {
+        self.try_take_n_at(1.0, now)
+    }
+
+    pub fn try_take_n(&mut self, n: f64) -> bool {
+        self.try_take_n_at(n, Instant::now())
+    }
+
+    /// Take `n` tokens while at least one is left, going below zero for more than
+    /// left, e.g. a batch larger than `burst`: the next ones wait until it is paid back.
+    pub fn try_take_n_at(&mut self, n: f64, now: Instant) -> bool {
+        if !self.has_token_at(now) {
+            return false;
+        }
+        self.tokens -= n;
+        true
+    }
+
+    /// Refill up to `now`, returns whether a token is left.
+    fn has_token_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.last_refill = self.last_refill.max(now);
+        self.tokens >= 1.0
+    }
+}
+
+/// Limits on writes to key prefixes, shared by all client connections.
+/// A key is only limited by its longest matching prefix.
+#[derive(Debug)]
+pub struct PrefixLimiter {
+    buckets: Vec<(String, TokenBucket)>,
+}
+
+impl PrefixLimiter {
+    /// `limits` are `(prefix, rate, burst)`.
+    pub fn new(limits: &[(&str, f64, f64)]) -> Self {
+        Self {
+            buckets: limits
+                .iter()
+                .map(|(prefix, rate, burst)| (prefix.to_string(), TokenBucket::new(*rate, *burst)))
+                .collect(),
+        }
+    }
+
+    pub fn try_take(&mut self, key: &str) -> bool {
+        self.try_take_at(key, Instant::now())
+    }
+
+    pub fn try_take_at(&mut self, key: &str, now: Instant) -> bool {
+        self.try_take_keys_at(&[key], now)
+    }
+
+    pub fn try_take_keys(&mut self, keys: &[&str]) -> bool {
+        self.try_take_keys_at(keys, Instant::now())
+    }
+
+    /// Take a token per key of a batch from the bucket limiting it, all of them only
+    /// if every bucket reached has a token left, see `TokenBucket::try_take_n_at`.
+    pub fn try_take_keys_at(&mut self, keys: &[&str], now: Instant) -> bool {
+        let mut taken = vec![0usize; self.buckets.len()];
+        for key in keys {
+            if let Some(idx) = self.bucket_of(key) {
+                taken[idx] += 1;
+            }
+        }
+        let reached: Vec<(usize, usize)> = taken
+            .into_iter()
+            .enumerate()
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        if !reached.iter().all(|(idx, _)| self.buckets[*idx].1.has_token_at(now)) {
+            return false;
+        }
+        for (idx, n) in reached {
+            self.buckets[idx].1.try_take_n_at(n as f64, now);
+        }
+        true
+    }
+
+    /// The bucket of the longest prefix of `key`.
+    fn bucket_of(&self, key: &str) -> Option<usize> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, (prefix, _))| key.starts_with(prefix.as_str()))
+            .max_by_key(|(_, (prefix, _))| prefix.len())
+            .map(|(idx, _)| idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 2.0);
+        assert!(bucket.try_take_at(start));
+        assert!(bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start));
+        // one token every 100ms
+        assert!(bucket.try_take_at(start + Duration::from_millis(100)));
+        assert!(!bucket.try_take_at(start + Duration::from_millis(150)));
+    }
+
+    #[test]
+    fn test_prefix_limiter() {
+        let start = Instant::now();
+        let mut limiter = PrefixLimiter::new(&[("/hot", 1.0, 1.0), ("/hot/cold", 100.0, 100.0)]);
+        assert!(limiter.try_take_at("/hot/a", start));
+        assert!(!limiter.try_take_at("/hot/b", start));
+        // longest prefix wins
+        assert!(limiter.try_take_at("/hot/cold/a", start));
+        // unlimited
+        assert!(limiter.try_take_at("/other", start));
+    }
+
+    #[test]
+    fn test_batch_charged() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 2.0);
+        // a batch over the burst goes through, the next write waits until it is paid back
+        assert!(bucket.try_take_n_at(5.0, start));
+        assert!(!bucket.try_take_at(start + Duration::from_millis(300)));
+        assert!(bucket.try_take_at(start + Duration::from_millis(500)));
+
+        let mut limiter = PrefixLimiter::new(&[("/hot", 1.0, 1.0), ("/cold", 1.0, 1.0)]);
+        assert!(limiter.try_take_at("/cold/a", start));
+        // none taken while one of the buckets is empty
+        assert!(!limiter.try_take_keys_at(&["/hot/a", "/cold/b"], start));
+        assert!(limiter.try_take_keys_at(&["/hot/a", "/hot/b", "/other"], start));
+        assert!(!limiter.try_take_at("/hot/c", start + Duration::from_millis(1500)));
+    }
+}
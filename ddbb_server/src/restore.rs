@@ -0,0 +1,178 @@
+use omnipaxos_core::util::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::bootstrap::{persist_cluster_uuid, ClusterManifest};
+use crate::config::STAGED_RESTORE_FILE;
+use crate::export::SnapshotExport;
+use ddbb_libs::Result;
+
+/// Written to the data directory of each member seeded by `restore_cluster`.
+pub const RESTORE_MANIFEST_FILE: &str = "restore_manifest";
+
+/// What a data directory was seeded with by `restore_cluster`, checked at the first
+/// start of the node against its command line, see `check_restore_manifest`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RestoreManifest {
+    /// the new cluster, with the uuid every member persisted
+    pub manifest: ClusterManifest,
+    /// the node of the old cluster the backup was taken on, and its id in the new one
+    pub source_node: NodeId,
+    pub remapped_node: Option<NodeId>,
+    /// the logs of the old cluster applied to the backup
+    pub source_applied_idx: u64,
+}
+
+/// #Descriptions: the data directory of member `id` under `data_root`, as the server
+/// defaults it, `<data_root>/<id>`.
+pub fn member_data_dir(data_root: &str, id: NodeId) -> String {
+    format!("{}/{}", data_root, id)
+}
+
+/// #Descriptions: parse `old=new` node id pairs, the ids of the old cluster and the
+/// members of the new one they became.
+pub fn parse_remap(pairs: &[String]) -> Result<BTreeMap<NodeId, NodeId>> {
+    let mut remap = BTreeMap::new();
+    for pair in pairs {
+        let (old, new) = pair
+            .split_once('=')
+            .ok_or(format!("{} is not old=new", pair))?;
+        let old: NodeId = old.trim().parse().map_err(|_| format!("{} is not old=new", pair))?;
+        let new: NodeId = new.trim().parse().map_err(|_| format!("{} is not old=new", pair))?;
+        if remap.insert(old, new).is_some() {
+            return Err(format!("node {} remapped twice", old).into());
+        }
+    }
+    Ok(remap)
+}
+
+/// #Descriptions: seed the data directories of a fresh cluster described by `manifest`
+/// from the backup at `backup_path`, for when every node of the old cluster is lost.
+/// Each member under `data_root` gets the backup staged, see
+/// `DDBB::apply_staged_restore`, a new cluster uuid shared by all of them, so that no
+/// node of the old cluster can join, and the `RestoreManifest`. `remap` gives the new
+/// id of the node the backup was taken on. Refuses to touch a data directory that is
+/// not empty, before writing any. Returns the seeded directories.
+pub fn restore_cluster(
+    backup_path: &str,
+    manifest: &ClusterManifest,
+    remap: &BTreeMap<NodeId, NodeId>,
+    data_root: &str,
+) -> Result<Vec<String>> {
+    if manifest.members.is_empty() {
+        return Err("the new cluster has no members".into());
+    }
+    if let Some(new) = remap.values().find(|id| !manifest.members.contains_key(id)) {
+        return Err(format!("node {} is remapped to but not a member", new).into());
+    }
+    let export = SnapshotExport::read(backup_path)?;
+    let dirs: Vec<String> = manifest
+        .members
+        .keys()
+        .map(|id| member_data_dir(data_root, *id))
+        .collect();
+    for dir in dirs.iter() {
+        let used = match fs::read_dir(dir) {
+            Ok(mut entries) => entries.next().is_some(),
+            Err(_) => false,
+        };
+        if used {
+            return Err(format!("{} is not empty, not seeding it", dir).into());
+        }
+    }
+
+    let mut manifest = manifest.clone();
+    let cluster_uuid = Uuid::new_v4().to_string();
+    manifest.cluster_uuid = Some(cluster_uuid.clone());
+    let record = RestoreManifest {
+        manifest,
+        source_node: export.node_id,
+        remapped_node: remap.get(&export.node_id).copied(),
+        source_applied_idx: export.applied_idx,
+    };
+    for dir in dirs.iter() {
+        let mut staged = export.clone();
+        if let Some(new) = record.remapped_node {
+            staged.node_id = new;
+        }
+        staged.write(&format!("{}/{}", dir, STAGED_RESTORE_FILE))?;
+        persist_cluster_uuid(dir, &cluster_uuid)?;
+        fs::write(
+            Path::new(dir).join(RESTORE_MANIFEST_FILE),
+            serde_json::to_vec_pretty(&record)?,
+        )?;
+    }
+    Ok(dirs)
+}
+
+/// #Descriptions: check that a node started on `data_dir` runs as the cluster it was
+/// seeded for by `restore_cluster`, if it was. Returns the seeded manifest.
+pub fn check_restore_manifest(
+    data_dir: &str,
+    manifest: &ClusterManifest,
+) -> Result<Option<RestoreManifest>> {
+    let path = Path::new(data_dir).join(RESTORE_MANIFEST_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let record: RestoreManifest = serde_json::from_slice(&fs::read(path)?)?;
+    if !record.manifest.agrees_with(manifest) {
+        return Err(format!(
+            "{} was seeded for cluster {} epoch {} with members {:?}, not {} epoch {} with {:?}",
+            data_dir,
+            record.manifest.cluster_id,
+            record.manifest.epoch,
+            record.manifest.members,
+            manifest.cluster_id,
+            manifest.epoch,
+            manifest.members
+        )
+        .into());
+    }
+    Ok(Some(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstrap::load_cluster_uuid;
+    use crate::dynamic_config::DynamicConfig;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_restore_cluster() {
+        let root = std::env::temp_dir().join(format!("ddbb_test_restore_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        let backup = format!("{}/backup.json", root);
+        SnapshotExport::new(5, 9, 8, DynamicConfig::default(), Vec::from("state"))
+            .write(&backup)
+            .unwrap();
+        let mut peers = HashMap::new();
+        peers.insert(2, "127.0.0.1:7551".to_string());
+        let addr = "127.0.0.1:7550".to_string();
+        let manifest = ClusterManifest::new("restored".to_string(), 1, 1, addr, &peers, &[]);
+        let remap = parse_remap(&["5=1".to_string()]).unwrap();
+        assert!(parse_remap(&["5".to_string()]).is_err());
+
+        let dirs = restore_cluster(&backup, &manifest, &remap, root).unwrap();
+        assert_eq!(dirs, vec![member_data_dir(root, 1), member_data_dir(root, 2)]);
+        let staged = SnapshotExport::read(&format!("{}/{}", dirs[1], STAGED_RESTORE_FILE)).unwrap();
+        assert_eq!((staged.node_id, staged.applied_idx), (1, 8));
+        // one new uuid for the whole cluster
+        let uuid = load_cluster_uuid(&dirs[0]).unwrap();
+        assert!(uuid.is_some());
+        assert_eq!(load_cluster_uuid(&dirs[1]).unwrap(), uuid);
+
+        let record = check_restore_manifest(&dirs[0], &manifest).unwrap().unwrap();
+        assert_eq!((record.source_node, record.remapped_node), (5, Some(1)));
+        let mut other = manifest.clone();
+        other.cluster_id = "ddbb".to_string();
+        assert!(check_restore_manifest(&dirs[0], &other).is_err());
+        // seeded once only
+        assert!(restore_cluster(&backup, &manifest, &remap, root).is_err());
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,569 @@
+use fs2::FileExt;
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// bytes before the payload of a record: its length, its position in the log, then
+/// the crc32 of the length, the position and the payload
+const RECORD_HEADER: usize = 16;
+const SEGMENT_EXTENSION: &str = "log";
+const SPARE_EXTENSION: &str = "spare";
+
+/// Sizes of the segment files of a `SegmentLog`.
+#[derive(Clone, Copy, Debug)]
+pub struct SegmentOptions {
+    /// a segment takes records until the next one would go over this, a larger record
+    /// gets a segment of its own
+    pub segment_size: u64,
+    /// preallocated segment files kept ready, recycled from the trimmed ones first
+    pub spares: usize,
+}
+
+/// A log cut short by `SegmentLog::open` at a record failing its checksum, e.g. torn by
+/// a crash or damaged on disk, rather than reading it or the records after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    /// the segment of the record
+    pub segment: PathBuf,
+    /// position of the record, the log now ends there
+    pub position: u64,
+    /// the later segments removed with it
+    pub segments_dropped: usize,
+}
+
+/// A segment file, named after the position of its first record.
+struct Segment {
+    first: u64,
+    path: PathBuf,
+    file: File,
+    /// the offset of each record
+    offsets: Vec<u64>,
+    /// bytes of the records, the rest of the file is zeros or stale records
+    len: u64,
+}
+
+impl Segment {
+    /// #Descriptions: read the records of the segment at `path`, up to the first one
+    /// missing, cut short, longer than the rest of the file or not numbered next, e.g.
+    /// the stale ones of a recycled file, and whether the scan stopped at a record
+    /// failing its checksum.
+    fn scan(path: PathBuf, first: u64) -> io::Result<(Self, bool)> {
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let file_len = file.metadata()?.len();
+        let mut offsets = Vec::new();
+        let mut len = 0;
+        let mut corrupted = false;
+        let mut reader = BufReader::new(&file);
+        loop {
+            let mut header = [0; RECORD_HEADER];
+            if !read_or_eof(&mut reader, &mut header)? {
+                break;
+            }
+            let (size, position, crc) = parse_header(&header);
+            // the length is not checked yet, a damaged one must not size the buffer
+            let rest = file_len.saturating_sub(len + RECORD_HEADER as u64);
+            if size == 0 || size as u64 > rest || position != first + offsets.len() as u64 {
+                break;
+            }
+            let mut payload = vec![0; size];
+            if !read_or_eof(&mut reader, &mut payload)? {
+                break;
+            }
+            if checksum(&header, &payload) != crc {
+                corrupted = true;
+                break;
+            }
+            offsets.push(len);
+            len += (RECORD_HEADER + size) as u64;
+        }
+        drop(reader);
+        let segment = Segment {
+            first,
+            path,
+            file,
+            offsets,
+            len,
+        };
+        Ok((segment, corrupted))
+    }
+
+    /// position after its last record
+    fn end(&self) -> u64 {
+        self.first + self.offsets.len() as u64
+    }
+
+    fn offset(&self, position: u64) -> u64 {
+        match self.offsets.get((position - self.first) as usize) {
+            Some(offset) => *offset,
+            None => self.len,
+        }
+    }
+}
+
+/// A log of records appended to fixed-size segment files, each record numbered by
+/// its position. Trimming the start of the log drops whole segments, which are
+/// kept as spares, up to `spares`, and taken again as the next segments instead of
+/// creating and growing new files. A record is never empty: zeros end a segment.
+pub struct SegmentLog {
+    dir: PathBuf,
+    options: SegmentOptions,
+    segments: Vec<Segment>,
+    spares: Vec<PathBuf>,
+    next_spare: u64,
+    /// position of the first record kept, the ones before it are trimmed
+    start: u64,
+    /// segments written since the last `sync`, by their first position
+    unsynced: BTreeSet<u64>,
+    /// files created, renamed or removed since the last `sync`
+    dir_changed: bool,
+    /// where `open` cut the log short, if it did
+    corruption: Option<Corruption>,
+}
+
+impl SegmentLog {
+    /// #Descriptions: open the segments in `dir`, the log kept from position `start`.
+    /// The log is truncated at the first record failing its checksum, see `corruption`.
+    pub fn open(dir: &Path, options: SegmentOptions, start: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut firsts = Vec::new();
+        let mut spares = Vec::new();
+        let mut next_spare = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let number = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok());
+            match (path.extension().and_then(|ext| ext.to_str()), number) {
+                (Some(SEGMENT_EXTENSION), Some(first)) => firsts.push(first),
+                (Some(SPARE_EXTENSION), Some(number)) => {
+                    next_spare = next_spare.max(number + 1);
+                    spares.push(path);
+                }
+                _ => {}
+            }
+        }
+        firsts.sort_unstable();
+        let mut log = SegmentLog {
+            dir: dir.to_path_buf(),
+            options,
+            segments: Vec::new(),
+            spares,
+            next_spare,
+            start,
+            unsynced: BTreeSet::new(),
+            dir_changed: false,
+            corruption: None,
+        };
+        for first in firsts {
+            let path = log.segment_path(first);
+            // past a gap or a corrupted record, nothing follows the records before it
+            if log.corruption.is_some()
+                || log.segments.last().map_or(false, |last| last.end() != first)
+            {
+                if let Some(corruption) = &mut log.corruption {
+                    corruption.segments_dropped += 1;
+                }
+                fs::remove_file(&path)?;
+                log.dir_changed = true;
+                continue;
+            }
+            let (segment, corrupted) = Segment::scan(path, first)?;
+            if corrupted {
+                // the records after it would be read again past the ones appended
+                zero(&segment.file, segment.len, segment.file.metadata()?.len())?;
+                segment.file.sync_data()?;
+                log.corruption = Some(Corruption {
+                    segment: segment.path.clone(),
+                    position: segment.end(),
+                    segments_dropped: 0,
+                });
+            }
+            log.segments.push(segment);
+        }
+        if let Some(first) = log.segments.first() {
+            log.start = log.start.clamp(first.first, log.end());
+        }
+        Ok(log)
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// position after the last record
+    pub fn end(&self) -> u64 {
+        self.segments.last().map_or(self.start, |last| last.end())
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn spare_count(&self) -> usize {
+        self.spares.len()
+    }
+
+    pub fn corruption(&self) -> Option<&Corruption> {
+        self.corruption.as_ref()
+    }
+
+    /// #Descriptions: append `records` at `end`, rolling over to the next segment once
+    /// the current one is full.
+    pub fn append(&mut self, records: &[Vec<u8>]) -> io::Result<()> {
+        let mut position = self.end();
+        let mut buffer = Vec::new();
+        for record in records {
+            let size = (RECORD_HEADER + record.len()) as u64;
+            let full = match self.segments.last() {
+                Some(last) => {
+                    !last.offsets.is_empty()
+                        && last.len + (buffer.len() as u64) + size > self.options.segment_size
+                }
+                None => true,
+            };
+            if full {
+                self.write_buffer(&mut buffer)?;
+                self.roll(position)?;
+            }
+            let last = self.segments.last_mut().unwrap();
+            last.offsets.push(last.len + buffer.len() as u64);
+            let mut header = [0; RECORD_HEADER];
+            header[..4].copy_from_slice(&(record.len() as u32).to_le_bytes());
+            header[4..12].copy_from_slice(&position.to_le_bytes());
+            let crc = checksum(&header, record);
+            header[12..].copy_from_slice(&crc.to_le_bytes());
+            buffer.extend_from_slice(&header);
+            buffer.extend_from_slice(record);
+            position += 1;
+        }
+        self.write_buffer(&mut buffer)
+    }
+
+    fn write_buffer(&mut self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let last = self.segments.last_mut().unwrap();
+        let mut file = &last.file;
+        file.seek(SeekFrom::Start(last.len))?;
+        file.write_all(buffer)?;
+        last.len += buffer.len() as u64;
+        self.unsynced.insert(last.first);
+        buffer.clear();
+        Ok(())
+    }
+
+    /// #Descriptions: start a segment at `first`, from a spare if one is ready.
+    fn roll(&mut self, first: u64) -> io::Result<()> {
+        let path = self.segment_path(first);
+        let file = match self.spares.pop() {
+            // its stale records are numbered before `start`, never read as the new ones
+            Some(spare) => {
+                fs::rename(&spare, &path)?;
+                OpenOptions::new().read(true).write(true).open(&path)?
+            }
+            None => self.preallocate(&path)?,
+        };
+        self.dir_changed = true;
+        self.segments.push(Segment {
+            first,
+            path,
+            file,
+            offsets: Vec::new(),
+            len: 0,
+        });
+        Ok(())
+    }
+
+    fn preallocate(&self, path: &Path) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.allocate(self.options.segment_size)?;
+        Ok(file)
+    }
+
+    /// #Descriptions: the records from position `from` up to `to`, as far as kept.
+    pub fn read(&self, from: u64, to: u64) -> io::Result<Vec<Vec<u8>>> {
+        let from = from.max(self.start);
+        let to = to.min(self.end());
+        let mut records = Vec::new();
+        let mut position = from;
+        let mut i = self.segments.partition_point(|segment| segment.end() <= from);
+        while position < to {
+            let segment = &self.segments[i];
+            let last = to.min(segment.end());
+            let begin = segment.offset(position);
+            let mut bytes = vec![0; (segment.offset(last) - begin) as usize];
+            let mut file = &segment.file;
+            file.seek(SeekFrom::Start(begin))?;
+            file.read_exact(&mut bytes)?;
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                let (size, _, _) = parse_header(rest);
+                records.push(rest[RECORD_HEADER..RECORD_HEADER + size].to_vec());
+                rest = &rest[RECORD_HEADER + size..];
+            }
+            position = last;
+            i += 1;
+        }
+        Ok(records)
+    }
+
+    /// #Descriptions: drop the records from `position` on, to append others instead.
+    pub fn truncate(&mut self, position: u64) -> io::Result<()> {
+        let position = position.max(self.start);
+        if position >= self.end() {
+            return Ok(());
+        }
+        // the later segments first, a crash leaves no gap
+        while self.segments.len() > 1 && self.segments.last().unwrap().first >= position {
+            let segment = self.segments.pop().unwrap();
+            self.unsynced.remove(&segment.first);
+            drop(segment.file);
+            fs::remove_file(&segment.path)?;
+            self.dir_changed = true;
+        }
+        let last = self.segments.last_mut().unwrap();
+        let kept = position.saturating_sub(last.first) as usize;
+        if kept < last.offsets.len() {
+            let len = last.offsets[kept];
+            // the records dropped would be read again past the ones appended
+            zero(&last.file, len, last.len)?;
+            last.offsets.truncate(kept);
+            last.len = len;
+            self.unsynced.insert(last.first);
+        }
+        Ok(())
+    }
+
+    /// #Descriptions: drop the records before `position`, their segments are recycled
+    /// by the next `recycle`.
+    pub fn trim(&mut self, position: u64) {
+        self.start = position.clamp(self.start, self.end());
+    }
+
+    /// #Descriptions: keep the segments holding only trimmed records as spares, or
+    /// remove them once `spares` are kept, then preallocate the spares missing.
+    pub fn recycle(&mut self) -> io::Result<()> {
+        while self.segments.len() > 1 && self.segments[1].first <= self.start {
+            let segment = self.segments.remove(0);
+            self.unsynced.remove(&segment.first);
+            drop(segment.file);
+            if self.spares.len() < self.options.spares {
+                let spare = self.spare_path();
+                fs::rename(&segment.path, &spare)?;
+                self.spares.push(spare);
+            } else {
+                fs::remove_file(&segment.path)?;
+            }
+            self.dir_changed = true;
+        }
+        while self.spares.len() < self.options.spares {
+            let spare = self.spare_path();
+            self.preallocate(&spare)?;
+            self.spares.push(spare);
+            self.dir_changed = true;
+        }
+        Ok(())
+    }
+
+    /// #Descriptions: make the records written, and the files created or removed,
+    /// durable.
+    pub fn sync(&mut self) -> io::Result<()> {
+        for first in mem::take(&mut self.unsynced) {
+            if let Some(segment) = self.segments.iter().find(|segment| segment.first == first) {
+                segment.file.sync_data()?;
+            }
+        }
+        if mem::take(&mut self.dir_changed) {
+            sync_dir(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    fn segment_path(&self, first: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", first, SEGMENT_EXTENSION))
+    }
+
+    fn spare_path(&mut self) -> PathBuf {
+        self.next_spare += 1;
+        self.dir
+            .join(format!("{:020}.{}", self.next_spare - 1, SPARE_EXTENSION))
+    }
+}
+
+/// #Descriptions: make the files created, renamed or removed in `dir` durable.
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+fn parse_header(bytes: &[u8]) -> (usize, u64, u32) {
+    let mut size = [0; 4];
+    size.copy_from_slice(&bytes[..4]);
+    let mut position = [0; 8];
+    position.copy_from_slice(&bytes[4..12]);
+    let mut crc = [0; 4];
+    crc.copy_from_slice(&bytes[12..RECORD_HEADER]);
+    (
+        u32::from_le_bytes(size) as usize,
+        u64::from_le_bytes(position),
+        u32::from_le_bytes(crc),
+    )
+}
+
+/// #Descriptions: the crc32 of a record, over its length, position and payload.
+fn checksum(header: &[u8], payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[..12]);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+/// #Descriptions: fill `buf`, false if the reader ends before.
+fn read_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn zero(file: &File, from: u64, to: u64) -> io::Result<()> {
+    const ZEROS: [u8; 4096] = [0; 4096];
+    let mut file = file;
+    file.seek(SeekFrom::Start(from))?;
+    let mut left = to.saturating_sub(from);
+    while left > 0 {
+        let chunk = left.min(ZEROS.len() as u64) as usize;
+        file.write_all(&ZEROS[..chunk])?;
+        left -= chunk as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn record(i: u64) -> Vec<u8> {
+        format!("record {}", i).into_bytes()
+    }
+
+    fn records(range: std::ops::Range<u64>) -> Vec<Vec<u8>> {
+        range.map(record).collect()
+    }
+
+    #[test]
+    fn test_segment_log() {
+        let dir = std::env::temp_dir().join(format!("ddbb_segments_{}", Uuid::new_v4()));
+        // two records of 24 bytes per segment
+        let options = SegmentOptions {
+            segment_size: 48,
+            spares: 1,
+        };
+        let mut log = SegmentLog::open(&dir, options, 0).unwrap();
+        log.append(&records(0..5)).unwrap();
+        assert_eq!((log.end(), log.segment_count()), (5, 3));
+        assert_eq!(log.read(1, 4).unwrap(), records(1..4));
+        assert_eq!(log.read(3, 9).unwrap(), records(3..5));
+
+        // the tail across segments replaced
+        log.truncate(1).unwrap();
+        assert_eq!(log.segment_count(), 1);
+        log.append(&records(11..14)).unwrap();
+        let mut expected = records(0..1);
+        expected.extend(records(11..14));
+        assert_eq!(log.read(0, 4).unwrap(), expected);
+
+        // whole segments only
+        log.trim(3);
+        log.recycle().unwrap();
+        assert_eq!((log.segment_count(), log.spare_count()), (1, 1));
+        assert_eq!(log.read(0, 4).unwrap(), records(13..14));
+        log.sync().unwrap();
+
+        // the spare is taken next, its stale records are not read
+        log.append(&records(14..17)).unwrap();
+        assert_eq!(log.spare_count(), 0);
+        log.truncate(6).unwrap();
+        drop(log);
+        let mut log = SegmentLog::open(&dir, options, 3).unwrap();
+        assert_eq!((log.start(), log.end()), (3, 6));
+        assert_eq!(log.read(0, 9).unwrap(), records(13..16));
+        log.append(&records(16..17)).unwrap();
+        assert_eq!(log.read(5, 7).unwrap(), records(15..17));
+
+        // a record over the segment size gets a segment of its own
+        log.append(&[vec![7; 100]]).unwrap();
+        assert_eq!(log.read(7, 8).unwrap(), vec![vec![7; 100]]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segment_corruption() {
+        let dir = std::env::temp_dir().join(format!("ddbb_segments_{}", Uuid::new_v4()));
+        let options = SegmentOptions {
+            segment_size: 48,
+            spares: 0,
+        };
+        let mut log = SegmentLog::open(&dir, options, 0).unwrap();
+        log.append(&records(0..5)).unwrap();
+        assert!(log.corruption().is_none());
+        drop(log);
+
+        // a bit flipped in the payload of record 2, the first of the second segment
+        let path = dir.join(format!("{:020}.{}", 2, SEGMENT_EXTENSION));
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[RECORD_HEADER] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        let mut log = SegmentLog::open(&dir, options, 0).unwrap();
+        let corruption = Corruption {
+            segment: path,
+            position: 2,
+            segments_dropped: 1,
+        };
+        assert_eq!(log.corruption(), Some(&corruption));
+        assert_eq!(log.end(), 2);
+        assert_eq!(log.read(0, 9).unwrap(), records(0..2));
+
+        // written again, the records cut off are not read past them
+        log.append(&records(12..13)).unwrap();
+        drop(log);
+        let log = SegmentLog::open(&dir, options, 0).unwrap();
+        assert!(log.corruption().is_none());
+        let mut expected = records(0..2);
+        expected.extend(records(12..13));
+        assert_eq!(log.read(0, 9).unwrap(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segment_oversized_record() {
+        let dir = std::env::temp_dir().join(format!("ddbb_segments_{}", Uuid::new_v4()));
+        let options = SegmentOptions {
+            segment_size: 48,
+            spares: 0,
+        };
+        let mut log = SegmentLog::open(&dir, options, 0).unwrap();
+        log.append(&records(0..2)).unwrap();
+        drop(log);
+
+        // the length of record 1 damaged to far past the end of the file
+        let path = dir.join(format!("{:020}.{}", 0, SEGMENT_EXTENSION));
+        let mut bytes = fs::read(&path).unwrap();
+        let second = RECORD_HEADER + record(0).len();
+        bytes[second..second + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        let (segment, corrupted) = Segment::scan(path, 0).unwrap();
+        assert_eq!((segment.end(), corrupted), (1, false));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// A counting semaphore replicated through the log, its permits held by client
+/// sessions: a permit lives as long as the session holding it, refreshed along with
+/// it by the session keepalive, and is released once the session closes or expires.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Semaphore {
+    /// number of permits, fixed by the first acquire
+    permits: u64,
+    /// the sessions holding a permit
+    #[serde(default)]
+    sessions: BTreeSet<u64>,
+}
+
+impl Semaphore {
+    pub fn new(permits: u64) -> Self {
+        Self {
+            permits,
+            sessions: BTreeSet::new(),
+        }
+    }
+
+    /// Take a permit for `session`, after releasing the ones of the sessions no longer
+    /// `alive`. A session takes at most one permit, acquiring again keeps it.
+    pub fn acquire(&mut self, session: u64, alive: impl Fn(u64) -> bool) -> bool {
+        self.sessions.retain(|holder| alive(*holder));
+        if !alive(session) {
+            return false;
+        }
+        if self.sessions.contains(&session) {
+            return true;
+        }
+        if (self.sessions.len() as u64) < self.permits {
+            self.sessions.insert(session);
+            return true;
+        }
+        false
+    }
+
+    pub fn release(&mut self, session: u64) {
+        self.sessions.remove(&session);
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_semaphore() {
+        let alive = |session| session != 9;
+        let mut semaphore = Semaphore::new(2);
+        assert!(semaphore.acquire(1, alive));
+        assert!(semaphore.acquire(2, alive));
+        // held once per session
+        assert!(semaphore.acquire(1, alive));
+        assert!(!semaphore.acquire(3, alive));
+        assert!(!semaphore.acquire(9, alive));
+
+        semaphore.release(1);
+        assert!(semaphore.acquire(3, alive));
+
+        // session 2 expired
+        assert!(semaphore.acquire(4, |session| session != 2));
+        semaphore.release(3);
+        semaphore.release(4);
+        assert!(semaphore.is_free());
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// A client session replicated through the log, so that a client reconnecting to
+/// another node after a failover resumes it with its ephemeral keys. It lives while it
+/// is kept alive within its ttl; as for elections, time only advances with the `now`
+/// carried by applied logs.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Session {
+    pub id: u64,
+    pub ttl: u64,
+    /// unix ms, as of the latest keepalive applied
+    pub expires_at: u64,
+    /// the ephemeral keys written in the session, deleted once it closes or expires
+    pub keys: BTreeSet<String>,
+}
+
+/// The sessions of a `KVStore`, with the session each ephemeral key is attached to.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Sessions {
+    sessions: HashMap<u64, Session>,
+    /// id of the next session opened, ids are never reused
+    next_id: u64,
+    /// the session each ephemeral key is attached to
+    leases: HashMap<String, u64>,
+}
+
+impl Sessions {
+    /// Open a session until `now + ttl`, returns its id, never 0.
+    pub fn open(&mut self, ttl: u64, now: u64) -> u64 {
+        self.next_id = self.next_id.max(1);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sessions.insert(
+            id,
+            Session {
+                id,
+                ttl,
+                expires_at: now + ttl,
+                keys: BTreeSet::new(),
+            },
+        );
+        id
+    }
+
+    /// Whether session `id` is open and not expired at `now`.
+    pub fn is_alive(&self, id: u64, now: u64) -> bool {
+        self.sessions
+            .get(&id)
+            .map_or(false, |session| session.expires_at > now)
+    }
+
+    /// Keep session `id` alive for another ttl from `now`, false if it expired.
+    pub fn keep_alive(&mut self, id: u64, now: u64) -> bool {
+        if !self.is_alive(id, now) {
+            return false;
+        }
+        let session = self.sessions.get_mut(&id).unwrap();
+        session.expires_at = now + session.ttl;
+        true
+    }
+
+    /// Attach `key` to session `id`, from the session it was attached to before if any.
+    pub fn attach(&mut self, id: u64, key: &str) {
+        self.detach(key);
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.keys.insert(key.to_string());
+            self.leases.insert(key.to_string(), id);
+        }
+    }
+
+    /// Detach `key` from its session, e.g. once it is deleted.
+    pub fn detach(&mut self, key: &str) {
+        if let Some(id) = self.leases.remove(key) {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.keys.remove(key);
+            }
+        }
+    }
+
+    /// The session `key` is attached to.
+    pub fn lease_of(&self, key: &str) -> Option<u64> {
+        self.leases.get(key).copied()
+    }
+
+    /// Close session `id`, returns the keys attached to it, in order, to delete.
+    pub fn close(&mut self, id: u64) -> Option<Vec<String>> {
+        let session = self.sessions.remove(&id)?;
+        for key in session.keys.iter() {
+            self.leases.remove(key);
+        }
+        Some(session.keys.into_iter().collect())
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Session> {
+        self.sessions.get(&id)
+    }
+
+    /// The sessions, by id.
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self.sessions.values().cloned().collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions() {
+        let mut sessions = Sessions::default();
+        let s1 = sessions.open(100, 0);
+        let s2 = sessions.open(100, 10);
+        assert_eq!((s1, s2), (1, 2));
+        sessions.attach(s1, "/lock");
+        sessions.attach(s1, "/a");
+        // a key written again in another session moves to it
+        sessions.attach(s2, "/a");
+        assert_eq!(sessions.lease_of("/a"), Some(s2));
+        assert_eq!(sessions.get(s1).unwrap().keys.len(), 1);
+
+        // s1 kept alive until 150, s2 expires at 110
+        assert!(sessions.keep_alive(s1, 50));
+        assert!(!sessions.keep_alive(s2, 120));
+        assert!(sessions.is_alive(s1, 120));
+        assert_eq!(sessions.close(s2), Some(vec!["/a".to_string()]));
+        assert_eq!(sessions.close(s2), None);
+        assert_eq!(sessions.lease_of("/a"), None);
+
+        sessions.detach("/lock");
+        assert_eq!(sessions.close(s1), Some(Vec::new()));
+        // ids are not reused
+        assert_eq!(sessions.open(100, 200), 3);
+    }
+}
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::op_data_structure::LogEntry;
+
+/// A proposal that took longer than the slow-log threshold, times in microseconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct SlowLogEntry {
+    pub opid: (String, u64),
+    pub kind: String,
+    /// index of the log in the decided sequence
+    pub idx: u64,
+    /// waiting to be handed to omnipaxos
+    pub queue_us: u64,
+    /// from omnipaxos to decided, including OmniSIMO transport
+    pub replication_us: u64,
+    /// from decided to applied by the state machine
+    pub apply_us: u64,
+    pub total_us: u64,
+}
+
+/// The latest slow proposals, oldest dropped first.
+#[derive(Debug)]
+pub struct SlowLog {
+    threshold: Duration,
+    capacity: usize,
+    entries: VecDeque<SlowLogEntry>,
+}
+
+impl SlowLog {
+    pub fn new(threshold: Duration, capacity: usize) -> Self {
+        Self {
+            threshold,
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: Duration) {
+        self.threshold = threshold;
+    }
+
+    pub fn is_slow(&self, total: Duration) -> bool {
+        total >= self.threshold
+    }
+
+    pub fn record(&mut self, entry: SlowLogEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> Vec<SlowLogEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+/// Name of the log variant, for the slow-log.
+pub fn log_kind(log: &LogEntry) -> &'static str {
+    match log {
+        LogEntry::SetValue { .. } => "SetValue",
+        LogEntry::LINRead { .. } => "LINRead",
+        LogEntry::LINWrite { .. } => "LINWrite",
+        LogEntry::LINStat { .. } => "LINStat",
+        LogEntry::Compact => "Compact",
+        LogEntry::SemAcquire { .. } => "SemAcquire",
+        LogEntry::SemRelease { .. } => "SemRelease",
+        LogEntry::PutIfRevision { .. } => "PutIfRevision",
+        LogEntry::CreateNamespace { .. } => "CreateNamespace",
+        LogEntry::DeleteNamespace { .. } => "DeleteNamespace",
+        LogEntry::BulkSet { .. } => "BulkSet",
+        LogEntry::DeleteTree { .. } => "DeleteTree",
+        LogEntry::DeletePrefix { .. } => "DeletePrefix",
+        LogEntry::Campaign { .. } => "Campaign",
+        LogEntry::Resign { .. } => "Resign",
+        LogEntry::FencedWrite { .. } => "FencedWrite",
+        LogEntry::ValueChunk { .. } => "ValueChunk",
+        LogEntry::ChunkedSet { .. } => "ChunkedSet",
+        LogEntry::OpenSession { .. } => "OpenSession",
+        LogEntry::KeepAlive { .. } => "KeepAlive",
+        LogEntry::EphemeralSet { .. } => "EphemeralSet",
+        LogEntry::CloseSession { .. } => "CloseSession",
+        LogEntry::CompactRevision { .. } => "CompactRevision",
+        LogEntry::Backup { .. } => "Backup",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow_entry(ts: u64) -> SlowLogEntry {
+        SlowLogEntry {
+            opid: ("127.0.0.1:6550".to_string(), ts),
+            kind: "LINWrite".to_string(),
+            idx: ts,
+            queue_us: 0,
+            replication_us: 0,
+            apply_us: 0,
+            total_us: 0,
+        }
+    }
+
+    #[test]
+    fn test_slow_log_capacity() {
+        let mut slow_log = SlowLog::new(Duration::from_millis(10), 2);
+        assert!(!slow_log.is_slow(Duration::from_millis(5)));
+        assert!(slow_log.is_slow(Duration::from_millis(10)));
+
+        slow_log.record(slow_entry(1));
+        slow_log.record(slow_entry(2));
+        slow_log.record(slow_entry(3));
+        let idxs: Vec<u64> = slow_log.entries().iter().map(|e| e.idx).collect();
+        assert_eq!(idxs, vec![2, 3]);
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use ddbb_libs::Result;
+
+/// Writes a snapshot as a sequence of chunks, one json line each after its crc32, so
+/// that the state is never serialized in memory as a whole.
+pub struct SnapshotWriter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl SnapshotWriter {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Box::new(BufWriter::new(writer)),
+        }
+    }
+
+    pub fn write_chunk<T: Serialize>(&mut self, chunk: &T) -> Result<()> {
+        let json = serde_json::to_vec(chunk)?;
+        write!(self.writer, "{:08x} ", crc32fast::hash(&json))?;
+        self.writer.write_all(&json)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the chunks written by a `SnapshotWriter` one at a time, so that a
+/// snapshot is installed while it is read.
+pub struct SnapshotReader {
+    reader: Box<dyn BufRead + Send>,
+    line: String,
+    /// chunks read so far
+    chunks: u64,
+}
+
+impl SnapshotReader {
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            reader: Box::new(BufReader::new(reader)),
+            line: String::new(),
+            chunks: 0,
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+
+    /// #Descriptions: the next chunk, `None` at the end of the snapshot, an error rather
+    /// than a chunk failing its checksum.
+    pub fn next_chunk<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line)? == 0 {
+            return Ok(None);
+        }
+        self.chunks += 1;
+        let (checksum, json) = self.line.split_once(' ').unwrap_or(("", &self.line));
+        let json = json.trim_end_matches('\n');
+        let expected = u32::from_str_radix(checksum, 16).ok();
+        let checksum = crc32fast::hash(json.as_bytes());
+        if expected != Some(checksum) {
+            return Err(format!(
+                "corrupted snapshot chunk {}: checksum {:08x}, expected {:08x}",
+                self.chunks,
+                checksum,
+                expected.unwrap_or(0)
+            )
+            .into());
+        }
+        Ok(Some(serde_json::from_str(json)?))
+    }
+}
+
+/// A snapshot file streamed to a temporary file, replacing `path` only once
+/// it is complete.
+pub struct SnapshotFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: SnapshotWriter,
+}
+
+impl SnapshotFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        let tmp_path = path.with_extension("tmp");
+        let writer = SnapshotWriter::new(File::create(&tmp_path)?);
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp_path,
+            writer,
+        })
+    }
+
+    pub fn writer(&mut self) -> &mut SnapshotWriter {
+        &mut self.writer
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.writer.finish()?;
+        fs::rename(self.tmp_path, self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_stream() {
+        let dir = std::env::temp_dir().join(format!("ddbb_test_stream_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot");
+        let mut file = SnapshotFile::create(&path).unwrap();
+        for chunk in 0..3u64 {
+            file.writer().write_chunk(&vec![("k", chunk)]).unwrap();
+        }
+        // not replaced before it is complete
+        assert!(!path.exists());
+        file.finish().unwrap();
+
+        let mut reader = SnapshotReader::open(&path).unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = reader.next_chunk::<Vec<(String, u64)>>().unwrap() {
+            chunks.push(chunk[0].1);
+        }
+        assert_eq!(chunks, vec![0, 1, 2]);
+
+        // a chunk damaged on disk is not read
+        let mut bytes = fs::read(&path).unwrap();
+        let second = bytes.iter().position(|b| *b == b'\n').unwrap() + 1;
+        bytes[second + 12] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        let mut reader = SnapshotReader::open(&path).unwrap();
+        assert!(reader.next_chunk::<Vec<(String, u64)>>().unwrap().is_some());
+        assert!(reader.next_chunk::<Vec<(String, u64)>>().is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1503 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+
+use crate::config::{DEDUP_WINDOW, SNAPSHOT_CHUNK_KEYS, UPLOAD_TTL};
+use crate::election::Election;
+use crate::keyspace::KeyspaceStats;
+use crate::namespace::{namespace_of, scoped_key, Namespace};
+use crate::op_data_structure::LogEntry;
+use crate::semaphore::Semaphore;
+use crate::session::{Session, Sessions};
+use crate::snapshot_stream::{SnapshotReader, SnapshotWriter};
+use ddbb_libs::data_structure::KeyMeta;
+use ddbb_libs::{Error, Result};
+
+/// A deterministic state machine replicated by DDBB. Every node applies the
+/// decided logs in the same order, so all replicas end up in the same state.
+pub trait StateMachine: Debug + Send {
+    /// Apply a decided log and return it as applied, e.g. a `LINRead` filled
+    /// with the value that was read.
+    fn apply(&mut self, log: LogEntry) -> LogEntry;
+
+    /// Serialize the whole state.
+    fn snapshot(&self) -> Result<Vec<u8>>;
+
+    /// Replace the whole state with a snapshot taken by `snapshot`.
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()>;
+
+    /// Write the whole state to `writer` in chunks, by default a single chunk
+    /// holding `snapshot`.
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) -> Result<()> {
+        writer.write_chunk(&self.snapshot()?)
+    }
+
+    /// Replace the whole state with the chunks written by `write_snapshot`.
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<()> {
+        let snapshot: Vec<u8> = reader.next_chunk()?.ok_or("empty snapshot")?;
+        self.restore(&snapshot)
+    }
+
+    /// Local, possibly stale read of a key.
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Local, possibly stale read of the metadata of a key.
+    fn stat(&self, key: &str) -> Option<KeyMeta> {
+        None
+    }
+
+    /// Local, possibly stale read of the keys under `prefix`, in order.
+    fn scan(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        Vec::new()
+    }
+
+    /// Local, possibly stale listing of the names right under `path`, in order, see
+    /// `child_names`.
+    fn list_children(&self, path: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// The namespace `name`, if it exists.
+    fn namespace(&self, name: &str) -> Option<Namespace> {
+        None
+    }
+
+    /// Revision of the latest write, bumped once per write, 0 for a state machine
+    /// that does not count them and so can not be watched.
+    fn revision(&self) -> u64 {
+        0
+    }
+
+    /// The fencing token of `candidate` in `election`, while it campaigns in it.
+    fn fencing_token(&self, election: &str, candidate: &str) -> Option<u64> {
+        None
+    }
+
+    /// The open sessions, by id, expired ones included until they are closed.
+    fn sessions(&self) -> Vec<Session> {
+        Vec::new()
+    }
+
+    /// Session `id`, while open.
+    fn session(&self, id: u64) -> Option<Session> {
+        None
+    }
+
+    /// Local statistics of the keys held, none for a state machine not counting them.
+    fn keyspace(&self) -> KeyspaceStats {
+        KeyspaceStats::default()
+    }
+
+    /// Why a write to `key` applied now would be dropped, `None` if it would be written.
+    fn refusal(&self, key: &str) -> Option<Error> {
+        None
+    }
+}
+
+/// The default state machine: a key-value map, plus the semaphores and elections.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KVStore {
+    store: HashMap<String, Vec<u8>>,
+    /// revision at which each key was last modified
+    mod_revs: HashMap<String, u64>,
+    /// revision at which each key was created
+    #[serde(default)]
+    create_revs: HashMap<String, u64>,
+    /// writes to each key since it was created
+    #[serde(default)]
+    versions: HashMap<String, u64>,
+    /// bumped by every write
+    revision: u64,
+    semaphores: HashMap<String, Semaphore>,
+    /// latest `now` seen in the applied logs, in unix ms
+    clock: u64,
+    #[serde(default)]
+    namespaces: HashMap<String, Namespace>,
+    #[serde(default)]
+    elections: HashMap<String, Election>,
+    /// sequence number the next election created starts at, the fencing tokens stay
+    /// increasing once an election was dropped
+    #[serde(default)]
+    election_seq: u64,
+    /// the token each key was last written with by a `FencedWrite`, kept once deleted
+    #[serde(default)]
+    fences: HashMap<String, u64>,
+    /// the latest opids applied of each proposer, a log proposed again after a leader
+    /// change is only applied once
+    #[serde(default)]
+    applied_opids: HashMap<String, BTreeSet<u64>>,
+    /// the `ValueChunk`s of the values being written, by proposer, upload and seq
+    #[serde(default)]
+    uploads: HashMap<String, BTreeMap<u64, BTreeMap<u32, Vec<u8>>>>,
+    /// when each upload got its first piece, in unix ms, by proposer and upload; one
+    /// whose `ChunkedSet` did not come within `UPLOAD_TTL` is dropped
+    #[serde(default)]
+    upload_started: HashMap<String, BTreeMap<u64, u64>>,
+    /// the client sessions, with their ephemeral keys
+    #[serde(default)]
+    sessions: Sessions,
+    /// the keys in order, for scans and listings, rebuilt when restored
+    #[serde(skip)]
+    index: BTreeSet<String>,
+    /// counted as the keys are written, rebuilt when restored
+    #[serde(skip)]
+    stats: KeyspaceStats,
+}
+
+/// Everything of a `KVStore` but its keys, the first chunk of its streamed snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+struct KVStoreHeader {
+    revision: u64,
+    semaphores: HashMap<String, Semaphore>,
+    clock: u64,
+    namespaces: HashMap<String, Namespace>,
+    #[serde(default)]
+    elections: HashMap<String, Election>,
+    #[serde(default)]
+    election_seq: u64,
+    #[serde(default)]
+    fences: HashMap<String, u64>,
+    #[serde(default)]
+    applied_opids: HashMap<String, BTreeSet<u64>>,
+    #[serde(default)]
+    uploads: HashMap<String, BTreeMap<u64, BTreeMap<u32, Vec<u8>>>>,
+    #[serde(default)]
+    upload_started: HashMap<String, BTreeMap<u64, u64>>,
+    #[serde(default)]
+    sessions: Sessions,
+}
+
+impl KVStore {
+    pub fn new() -> Self {
+        Self {
+            store: HashMap::new(),
+            mod_revs: HashMap::new(),
+            create_revs: HashMap::new(),
+            versions: HashMap::new(),
+            revision: 0,
+            semaphores: HashMap::new(),
+            clock: 0,
+            namespaces: HashMap::new(),
+            elections: HashMap::new(),
+            election_seq: 0,
+            fences: HashMap::new(),
+            applied_opids: HashMap::new(),
+            uploads: HashMap::new(),
+            upload_started: HashMap::new(),
+            sessions: Sessions::default(),
+            index: BTreeSet::new(),
+            stats: KeyspaceStats::default(),
+        }
+    }
+
+    /// Returns whether the log of `opid` was applied already, remembering it if not.
+    /// Only the latest `DEDUP_WINDOW` opids of each proposer are remembered.
+    fn seen(&mut self, opid: &(String, u64)) -> bool {
+        let (proposer, ts) = opid;
+        let applied = self.applied_opids.entry(proposer.clone()).or_default();
+        if !applied.insert(*ts) {
+            return true;
+        }
+        if applied.len() > DEDUP_WINDOW {
+            let oldest = *applied.iter().next().unwrap();
+            applied.remove(&oldest);
+        }
+        false
+    }
+
+    /// Drop the pieces of `upload` of `proposer`, once written or expired.
+    fn drop_upload(&mut self, proposer: &str, upload: u64) -> BTreeMap<u32, Vec<u8>> {
+        let pieces = self
+            .uploads
+            .get_mut(proposer)
+            .and_then(|uploads| uploads.remove(&upload))
+            .unwrap_or_default();
+        if self.uploads.get(proposer).map_or(false, |uploads| uploads.is_empty()) {
+            self.uploads.remove(proposer);
+        }
+        if let Some(started) = self.upload_started.get_mut(proposer) {
+            started.remove(&upload);
+            if started.is_empty() {
+                self.upload_started.remove(proposer);
+            }
+        }
+        pieces
+    }
+
+    /// Drop the uploads whose first piece came over `UPLOAD_TTL` before the clock, e.g.
+    /// of a proposer that failed before its `ChunkedSet`. Uploads restored without a
+    /// start, from older snapshots, start now.
+    fn expire_uploads(&mut self) {
+        let ttl = UPLOAD_TTL.as_millis() as u64;
+        let mut expired = Vec::new();
+        for (proposer, uploads) in self.uploads.iter() {
+            let started = self.upload_started.entry(proposer.clone()).or_default();
+            for upload in uploads.keys() {
+                let at = *started.entry(*upload).or_insert(self.clock);
+                if at.saturating_add(ttl) < self.clock {
+                    expired.push((proposer.clone(), *upload));
+                }
+            }
+        }
+        for (proposer, upload) in expired {
+            self.drop_upload(&proposer, upload);
+        }
+    }
+
+    /// Returns the new revision of the key. An ephemeral key written over is detached
+    /// from its session, it outlives the session like any other key.
+    pub fn put(&mut self, key: String, value: Vec<u8>) -> u64 {
+        self.revision += 1;
+        self.sessions.detach(&key);
+        if !self.store.contains_key(&key) {
+            let namespace = namespace_of(&key).and_then(|ns| self.namespaces.get_mut(ns));
+            if let Some(namespace) = namespace {
+                namespace.keys += 1;
+            }
+            self.create_revs.insert(key.clone(), self.revision);
+            self.index.insert(key.clone());
+        }
+        self.mod_revs.insert(key.clone(), self.revision);
+        *self.versions.entry(key.clone()).or_insert(0) += 1;
+        self.stats.add(&key, value.len());
+        if let Some(replaced) = self.store.insert(key.clone(), value) {
+            self.stats.remove(&key, replaced.len());
+        }
+        self.revision
+    }
+
+    /// Returns whether `key` existed, a deletion bumps the revision too.
+    pub fn delete(&mut self, key: &str) -> bool {
+        let value = match self.store.remove(key) {
+            Some(value) => value,
+            None => return false,
+        };
+        self.revision += 1;
+        self.stats.remove(key, value.len());
+        let namespace = namespace_of(key).and_then(|ns| self.namespaces.get_mut(ns));
+        if let Some(namespace) = namespace {
+            namespace.keys = namespace.keys.saturating_sub(1);
+        }
+        self.mod_revs.remove(key);
+        self.create_revs.remove(key);
+        self.versions.remove(key);
+        self.index.remove(key);
+        self.sessions.detach(key);
+        true
+    }
+
+    /// The keys under `prefix`, in order.
+    fn keys_under<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.index
+            .range(prefix.to_string()..)
+            .take_while(move |key| key.starts_with(prefix))
+    }
+
+    /// `path` and the keys under it, in order.
+    fn tree(&self, path: &str) -> Vec<String> {
+        let prefix = tree_prefix(path);
+        let mut keys: Vec<String> = self.keys_under(&prefix).cloned().collect();
+        if path != prefix && self.store.contains_key(path) {
+            keys.insert(0, path.to_string());
+        }
+        keys
+    }
+
+    /// Revision at which `key` was last modified, 0 if it does not exist.
+    pub fn mod_rev(&self, key: &str) -> u64 {
+        self.mod_revs.get(key).copied().unwrap_or(0)
+    }
+
+    /// Whether `key` may be written, see `refusal`.
+    fn admits(&self, key: &str) -> bool {
+        self.refusal(key).is_none()
+    }
+}
+
+impl StateMachine for KVStore {
+    fn apply(&mut self, log: LogEntry) -> LogEntry {
+        if log.opid().map_or(false, |opid| self.seen(opid)) {
+            return log;
+        }
+        match log {
+            LogEntry::SetValue { ref key, ref value } => {
+                if self.admits(key) {
+                    self.put(key.clone(), value.clone());
+                }
+                log
+            }
+            LogEntry::LINRead { opid, key, .. } => {
+                let value = self.get(&key);
+                LogEntry::LINRead { opid, key, value }
+            }
+            LogEntry::LINStat { opid, key, .. } => {
+                let value = self.get(&key);
+                let meta = self.stat(&key);
+                LogEntry::LINStat {
+                    opid,
+                    key,
+                    value,
+                    meta,
+                }
+            }
+            LogEntry::LINWrite {
+                ref key, ref value, ..
+            } => {
+                if self.admits(key) {
+                    self.put(key.clone(), value.clone());
+                }
+                log
+            }
+            LogEntry::Compact => log,
+            LogEntry::SemAcquire {
+                opid,
+                name,
+                permits,
+                session,
+                now,
+                ..
+            } => {
+                self.clock = self.clock.max(now);
+                let (sessions, clock) = (&self.sessions, self.clock);
+                let semaphore = self
+                    .semaphores
+                    .entry(name.clone())
+                    .or_insert_with(|| Semaphore::new(permits));
+                let acquired = semaphore.acquire(session, |id| sessions.is_alive(id, clock));
+                if semaphore.is_free() {
+                    self.semaphores.remove(&name);
+                }
+                LogEntry::SemAcquire {
+                    opid,
+                    name,
+                    permits,
+                    session,
+                    now,
+                    acquired,
+                }
+            }
+            LogEntry::PutIfRevision {
+                opid,
+                key,
+                value,
+                expected_mod_rev,
+                ..
+            } => {
+                let current = self.mod_rev(&key);
+                let (succeeded, mod_rev) = if current == expected_mod_rev && self.admits(&key) {
+                    (true, self.put(key.clone(), value.clone()))
+                } else {
+                    (false, current)
+                };
+                LogEntry::PutIfRevision {
+                    opid,
+                    key,
+                    value,
+                    expected_mod_rev,
+                    succeeded,
+                    mod_rev,
+                }
+            }
+            LogEntry::SemRelease {
+                ref name, session, ..
+            } => {
+                if let Some(semaphore) = self.semaphores.get_mut(name) {
+                    semaphore.release(session);
+                    if semaphore.is_free() {
+                        self.semaphores.remove(name);
+                    }
+                }
+                log
+            }
+            LogEntry::CreateNamespace {
+                opid,
+                name,
+                max_keys,
+                token,
+                ..
+            } => {
+                let created = !self.namespaces.contains_key(&name);
+                if created {
+                    let namespace = Namespace::new(max_keys, token.clone());
+                    self.namespaces.insert(name.clone(), namespace);
+                }
+                LogEntry::CreateNamespace {
+                    opid,
+                    name,
+                    max_keys,
+                    token,
+                    created,
+                }
+            }
+            LogEntry::DeleteNamespace { opid, name, .. } => {
+                let deleted = self.namespaces.remove(&name).is_some();
+                let prefix = scoped_key(&name, "");
+                self.stats.remove_namespace(&name);
+                self.store.retain(|key, _| !key.starts_with(&prefix));
+                self.mod_revs.retain(|key, _| !key.starts_with(&prefix));
+                self.create_revs.retain(|key, _| !key.starts_with(&prefix));
+                self.versions.retain(|key, _| !key.starts_with(&prefix));
+                self.index.retain(|key| !key.starts_with(&prefix));
+                self.fences.retain(|key, _| !key.starts_with(&prefix));
+                let leased: Vec<String> = self
+                    .sessions
+                    .list()
+                    .into_iter()
+                    .flat_map(|session| session.keys)
+                    .filter(|key| key.starts_with(&prefix))
+                    .collect();
+                for key in leased {
+                    self.sessions.detach(&key);
+                }
+                LogEntry::DeleteNamespace {
+                    opid,
+                    name,
+                    deleted,
+                }
+            }
+            LogEntry::BulkSet { opid, pairs } => {
+                let mut written = Vec::with_capacity(pairs.len());
+                for (key, value) in pairs {
+                    if self.admits(&key) {
+                        self.put(key.clone(), value.clone());
+                        written.push((key, value));
+                    }
+                }
+                LogEntry::BulkSet {
+                    opid,
+                    pairs: written,
+                }
+            }
+            LogEntry::DeleteTree { opid, path, .. } => {
+                let deleted = self.tree(&path);
+                for key in deleted.iter() {
+                    self.delete(key);
+                }
+                LogEntry::DeleteTree {
+                    opid,
+                    path,
+                    deleted,
+                }
+            }
+            LogEntry::DeletePrefix {
+                opid,
+                prefix,
+                limit,
+                ..
+            } => {
+                let deleted: Vec<String> = self
+                    .keys_under(&prefix)
+                    .filter(|key| in_bulk_delete(&prefix, key))
+                    .take(limit as usize)
+                    .cloned()
+                    .collect();
+                for key in deleted.iter() {
+                    self.delete(key);
+                }
+                LogEntry::DeletePrefix {
+                    opid,
+                    prefix,
+                    limit,
+                    deleted,
+                }
+            }
+            LogEntry::Campaign {
+                opid,
+                election,
+                candidate,
+                ttl,
+                now,
+                ..
+            } => {
+                self.clock = self.clock.max(now);
+                let seq = self.election_seq;
+                let state = self
+                    .elections
+                    .entry(election.clone())
+                    .or_insert_with(|| Election::starting_at(seq));
+                state.campaign(candidate.clone(), ttl, self.clock);
+                self.election_seq = seq.max(state.next_seq());
+                let leader = state.leader().map(|leader| leader.to_string());
+                LogEntry::Campaign {
+                    opid,
+                    election,
+                    candidate,
+                    ttl,
+                    now,
+                    leader,
+                }
+            }
+            LogEntry::Resign {
+                opid,
+                election,
+                candidate,
+                ..
+            } => {
+                let mut leader = None;
+                if let Some(state) = self.elections.get_mut(&election) {
+                    state.resign(&candidate);
+                    state.expire(self.clock);
+                    leader = state.leader().map(|leader| leader.to_string());
+                    if state.is_empty() {
+                        self.elections.remove(&election);
+                    }
+                }
+                LogEntry::Resign {
+                    opid,
+                    election,
+                    candidate,
+                    leader,
+                }
+            }
+            LogEntry::FencedWrite {
+                opid,
+                key,
+                value,
+                election,
+                token,
+                ..
+            } => {
+                let fence = self.fences.get(&key).copied().unwrap_or(0);
+                let leader_token = self
+                    .elections
+                    .get(&election)
+                    .and_then(|state| state.fencing_token());
+                let stale = token < fence || leader_token.map_or(false, |leader| token < leader);
+                let (succeeded, fence) = if !stale && self.admits(&key) {
+                    self.put(key.clone(), value.clone());
+                    self.fences.insert(key.clone(), token);
+                    (true, token)
+                } else {
+                    (false, fence)
+                };
+                LogEntry::FencedWrite {
+                    opid,
+                    key,
+                    value,
+                    election,
+                    token,
+                    succeeded,
+                    fence,
+                }
+            }
+            LogEntry::ValueChunk {
+                ref opid,
+                upload,
+                seq,
+                ref data,
+                now,
+            } => {
+                self.clock = self.clock.max(now);
+                self.upload_started
+                    .entry(opid.0.clone())
+                    .or_default()
+                    .entry(upload)
+                    .or_insert(self.clock);
+                self.uploads
+                    .entry(opid.0.clone())
+                    .or_default()
+                    .entry(upload)
+                    .or_default()
+                    .insert(seq, data.clone());
+                self.expire_uploads();
+                log
+            }
+            LogEntry::ChunkedSet {
+                opid, key, chunks, ..
+            } => {
+                let pieces = self.drop_upload(&opid.0, opid.1);
+                let complete = pieces.keys().copied().eq(0..chunks);
+                let written = complete && self.admits(&key);
+                if written {
+                    let value = pieces.into_values().flatten().collect();
+                    self.put(key.clone(), value);
+                }
+                LogEntry::ChunkedSet {
+                    opid,
+                    key,
+                    chunks,
+                    written,
+                }
+            }
+            LogEntry::OpenSession { opid, ttl, now, .. } => {
+                self.clock = self.clock.max(now);
+                let session = self.sessions.open(ttl, self.clock);
+                LogEntry::OpenSession {
+                    opid,
+                    ttl,
+                    now,
+                    session,
+                }
+            }
+            LogEntry::KeepAlive {
+                opid, session, now, ..
+            } => {
+                self.clock = self.clock.max(now);
+                let alive = self.sessions.keep_alive(session, self.clock);
+                LogEntry::KeepAlive {
+                    opid,
+                    session,
+                    now,
+                    alive,
+                }
+            }
+            LogEntry::EphemeralSet {
+                opid,
+                session,
+                key,
+                value,
+                ..
+            } => {
+                let written = self.sessions.is_alive(session, self.clock) && self.admits(&key);
+                if written {
+                    self.put(key.clone(), value.clone());
+                    self.sessions.attach(session, &key);
+                }
+                LogEntry::EphemeralSet {
+                    opid,
+                    session,
+                    key,
+                    value,
+                    written,
+                }
+            }
+            LogEntry::CloseSession {
+                opid,
+                session,
+                now,
+                only_expired,
+                ..
+            } => {
+                self.clock = self.clock.max(now);
+                // kept alive since the sweep that proposed it
+                let keys = if only_expired && self.sessions.is_alive(session, self.clock) {
+                    None
+                } else {
+                    self.sessions.close(session)
+                };
+                let closed = keys.is_some();
+                if closed {
+                    // its permits go with it
+                    for semaphore in self.semaphores.values_mut() {
+                        semaphore.release(session);
+                    }
+                    self.semaphores.retain(|_, semaphore| !semaphore.is_free());
+                }
+                let deleted: Vec<String> = keys
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|key| self.delete(key))
+                    .collect();
+                LogEntry::CloseSession {
+                    opid,
+                    session,
+                    now,
+                    only_expired,
+                    closed,
+                    deleted,
+                }
+            }
+            // the history is kept by the node, see `WatchHub::compact`
+            LogEntry::CompactRevision { opid, revision, .. } => LogEntry::CompactRevision {
+                opid,
+                revision,
+                compacted: revision.min(self.revision),
+            },
+            // taken by the node, see `DDBB::consistent_backup`
+            LogEntry::Backup { opid, node, .. } => LogEntry::Backup {
+                opid,
+                node,
+                revision: Some(self.revision),
+            },
+        }
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&self)?)
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        *self = serde_json::from_slice(snapshot)?;
+        self.index = self.store.keys().cloned().collect();
+        for (key, value) in self.store.iter() {
+            self.stats.add(key, value.len());
+        }
+        Ok(())
+    }
+
+    /// The keys are written in chunks of `SNAPSHOT_CHUNK_KEYS` after the header,
+    /// without cloning the map.
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) -> Result<()> {
+        writer.write_chunk(&KVStoreHeader {
+            revision: self.revision,
+            semaphores: self.semaphores.clone(),
+            clock: self.clock,
+            namespaces: self.namespaces.clone(),
+            elections: self.elections.clone(),
+            election_seq: self.election_seq,
+            fences: self.fences.clone(),
+            applied_opids: self.applied_opids.clone(),
+            uploads: self.uploads.clone(),
+            upload_started: self.upload_started.clone(),
+            sessions: self.sessions.clone(),
+        })?;
+        let mut chunk: Vec<(&str, &[u8], u64, u64, u64)> = Vec::with_capacity(SNAPSHOT_CHUNK_KEYS);
+        for (key, value) in self.store.iter() {
+            let meta = self.stat(key).unwrap_or_default();
+            chunk.push((
+                key.as_str(),
+                value.as_slice(),
+                meta.mod_rev,
+                meta.create_rev,
+                meta.version,
+            ));
+            if chunk.len() == SNAPSHOT_CHUNK_KEYS {
+                writer.write_chunk(&chunk)?;
+                chunk.clear();
+            }
+        }
+        if !chunk.is_empty() {
+            writer.write_chunk(&chunk)?;
+        }
+        Ok(())
+    }
+
+    fn read_snapshot(&mut self, reader: &mut SnapshotReader) -> Result<()> {
+        let header: KVStoreHeader = reader.next_chunk()?.ok_or("empty snapshot")?;
+        let mut restored = KVStore {
+            revision: header.revision,
+            semaphores: header.semaphores,
+            clock: header.clock,
+            namespaces: header.namespaces,
+            elections: header.elections,
+            election_seq: header.election_seq,
+            fences: header.fences,
+            applied_opids: header.applied_opids,
+            uploads: header.uploads,
+            upload_started: header.upload_started,
+            sessions: header.sessions,
+            ..KVStore::new()
+        };
+        while let Some(chunk) = reader.next_chunk::<Vec<(String, Vec<u8>, u64, u64, u64)>>()? {
+            for (key, value, mod_rev, create_rev, version) in chunk {
+                restored.mod_revs.insert(key.clone(), mod_rev);
+                restored.create_revs.insert(key.clone(), create_rev);
+                restored.versions.insert(key.clone(), version);
+                restored.index.insert(key.clone());
+                restored.stats.add(&key, value.len());
+                restored.store.insert(key, value);
+            }
+        }
+        *self = restored;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.store.get(key).cloned()
+    }
+
+    /// Keys restored from snapshots taken before creation revisions and versions
+    /// were counted report their last modification as creation, with version 1.
+    fn stat(&self, key: &str) -> Option<KeyMeta> {
+        let value = self.store.get(key)?;
+        let mod_rev = self.mod_rev(key);
+        Some(KeyMeta {
+            create_rev: self.create_revs.get(key).copied().unwrap_or(mod_rev),
+            mod_rev,
+            version: self.versions.get(key).copied().unwrap_or(1),
+            value_len: value.len() as u64,
+            lease: self.sessions.lease_of(key),
+        })
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<(String, Vec<u8>)> {
+        self.keys_under(prefix)
+            .filter_map(|key| Some((key.clone(), self.store.get(key)?.clone())))
+            .collect()
+    }
+
+    fn list_children(&self, path: &str) -> Vec<String> {
+        let prefix = tree_prefix(path);
+        child_names(&prefix, self.keys_under(&prefix).map(|key| key.as_str()))
+    }
+
+    fn namespace(&self, name: &str) -> Option<Namespace> {
+        self.namespaces.get(name).cloned()
+    }
+
+    fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    fn fencing_token(&self, election: &str, candidate: &str) -> Option<u64> {
+        self.elections.get(election)?.token_of(candidate)
+    }
+
+    fn sessions(&self) -> Vec<Session> {
+        self.sessions.list()
+    }
+
+    fn session(&self, id: u64) -> Option<Session> {
+        self.sessions.get(id).cloned()
+    }
+
+    fn keyspace(&self) -> KeyspaceStats {
+        self.stats.clone()
+    }
+
+    /// A key of a namespace is written only while the namespace exists, and a new one
+    /// only within its quota.
+    fn refusal(&self, key: &str) -> Option<Error> {
+        let name = namespace_of(key)?;
+        match self.namespaces.get(name) {
+            Some(namespace) if self.store.contains_key(key) || namespace.has_room() => None,
+            Some(namespace) => Some(Error::QuotaExceeded(format!(
+                "namespace {} holds {} keys",
+                name, namespace.max_keys
+            ))),
+            None => Some(Error::Other(format!("namespace not found: {}", name))),
+        }
+    }
+}
+
+/// Whether a bulk delete of `prefix` reaches `key`: the reserved keys, of the
+/// namespaces and settings, only from a reserved prefix.
+pub fn in_bulk_delete(prefix: &str, key: &str) -> bool {
+    key.starts_with(prefix) && (!key.starts_with('\u{0}') || prefix.starts_with('\u{0}'))
+}
+
+/// #Descriptions: the prefix of the keys under `path` of the `/` separated keys,
+/// `path/`, or `/` for the root.
+pub fn tree_prefix(path: &str) -> String {
+    if path.ends_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/", path)
+    }
+}
+
+/// #Descriptions: the names right under `prefix`, the first segment of each of the
+/// `keys` after it, in order and without duplicates.
+pub fn child_names<'a>(prefix: &str, keys: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let children: BTreeSet<&str> = keys
+        .filter_map(|key| key.strip_prefix(prefix))
+        .filter_map(|rest| rest.split('/').next())
+        .filter(|child| !child.is_empty())
+        .collect();
+    children
+        .into_iter()
+        .map(|child| child.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_if_revision(kv_store: &mut KVStore, ts: u64, expected_mod_rev: u64) -> (bool, u64) {
+        let applied = kv_store.apply(LogEntry::PutIfRevision {
+            opid: ("127.0.0.1:6550".to_string(), ts),
+            key: "k1".to_string(),
+            value: Vec::from(format!("v{}", ts)),
+            expected_mod_rev,
+            succeeded: false,
+            mod_rev: 0,
+        });
+        match applied {
+            LogEntry::PutIfRevision {
+                succeeded, mod_rev, ..
+            } => (succeeded, mod_rev),
+            other => panic!("unexpected log: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_kv_store_put_if_revision() {
+        let mut kv_store = KVStore::new();
+        assert_eq!(put_if_revision(&mut kv_store, 1, 0), (true, 1));
+        // stale revision fails with the current one
+        assert_eq!(put_if_revision(&mut kv_store, 2, 0), (false, 1));
+        assert_eq!(kv_store.get("k1"), Some(Vec::from("v1")));
+        assert_eq!(put_if_revision(&mut kv_store, 3, 1), (true, 2));
+        assert_eq!(kv_store.get("k1"), Some(Vec::from("v3")));
+    }
+
+    #[test]
+    fn test_kv_store_fenced_write() {
+        let mut kv_store = KVStore::new();
+        let campaign = |ts, candidate: &str| LogEntry::Campaign {
+            opid: ("127.0.0.1:6550".to_string(), ts),
+            election: "e".to_string(),
+            candidate: candidate.to_string(),
+            ttl: 100,
+            now: 0,
+            leader: None,
+        };
+        let fenced = |ts, token| LogEntry::FencedWrite {
+            opid: ("127.0.0.1:6550".to_string(), ts),
+            key: "k1".to_string(),
+            value: Vec::from(format!("v{}", ts)),
+            election: "e".to_string(),
+            token,
+            succeeded: false,
+            fence: 0,
+        };
+        let write = |kv_store: &mut KVStore, ts, token| match kv_store.apply(fenced(ts, token)) {
+            LogEntry::FencedWrite {
+                succeeded, fence, ..
+            } => (succeeded, fence),
+            other => panic!("unexpected log: {:?}", other),
+        };
+        kv_store.apply(campaign(1, "c1"));
+        kv_store.apply(campaign(2, "c2"));
+        let c1 = kv_store.fencing_token("e", "c1").unwrap();
+        let c2 = kv_store.fencing_token("e", "c2").unwrap();
+        assert!(c2 > c1);
+        assert_eq!(write(&mut kv_store, 3, c1), (true, c1));
+
+        // c1 lost its lease, its delayed write is rejected once c2 leads
+        kv_store.apply(LogEntry::Resign {
+            opid: ("127.0.0.1:6550".to_string(), 4),
+            election: "e".to_string(),
+            candidate: "c1".to_string(),
+            leader: None,
+        });
+        assert_eq!(write(&mut kv_store, 5, c1), (false, c1));
+        assert_eq!(write(&mut kv_store, 6, c2), (true, c2));
+        assert_eq!(write(&mut kv_store, 7, c1), (false, c2));
+        assert_eq!(kv_store.get("k1"), Some(Vec::from("v6")));
+
+        // the tokens keep increasing once the election was emptied and dropped
+        kv_store.apply(LogEntry::Resign {
+            opid: ("127.0.0.1:6550".to_string(), 8),
+            election: "e".to_string(),
+            candidate: "c2".to_string(),
+            leader: None,
+        });
+        kv_store.apply(campaign(9, "c3"));
+        assert!(kv_store.fencing_token("e", "c3").unwrap() > c2);
+    }
+
+    #[test]
+    fn test_kv_store_dedup() {
+        let mut kv_store = KVStore::new();
+        let write = |ts, value: &str| LogEntry::LINWrite {
+            opid: ("127.0.0.1:6550".to_string(), ts),
+            key: "k1".to_string(),
+            value: Vec::from(value),
+        };
+        kv_store.apply(write(2, "v2"));
+        kv_store.apply(write(1, "v1"));
+        // proposed again after a leader change
+        kv_store.apply(write(2, "v2"));
+        assert_eq!(kv_store.get("k1"), Some(Vec::from("v1")));
+        assert_eq!(kv_store.revision(), 2);
+
+        let mut restored = KVStore::new();
+        restored.restore(&kv_store.snapshot().unwrap()).unwrap();
+        restored.apply(write(1, "v1"));
+        assert_eq!(restored.revision(), 2);
+    }
+
+    #[test]
+    fn test_kv_store_snapshot_restore() {
+        let mut kv_store = KVStore::new();
+        kv_store.apply(LogEntry::SetValue {
+            key: "k1".to_string(),
+            value: Vec::from("v1"),
+        });
+        kv_store.apply(LogEntry::LINWrite {
+            opid: ("127.0.0.1:6550".to_string(), 1),
+            key: "k2".to_string(),
+            value: Vec::from("v2"),
+        });
+        let snapshot = kv_store.snapshot().unwrap();
+
+        let mut restored = KVStore::new();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.get("k1"), Some(Vec::from("v1")));
+        assert_eq!(restored.get("k2"), Some(Vec::from("v2")));
+
+        let read = restored.apply(LogEntry::LINRead {
+            opid: ("127.0.0.1:6550".to_string(), 2),
+            key: "k1".to_string(),
+            value: None,
+        });
+        assert_eq!(
+            read,
+            LogEntry::LINRead {
+                opid: ("127.0.0.1:6550".to_string(), 2),
+                key: "k1".to_string(),
+                value: Some(Vec::from("v1")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_kv_store_streamed_snapshot() {
+        let mut kv_store = KVStore::new();
+        for i in 0..(SNAPSHOT_CHUNK_KEYS + 1) {
+            kv_store.apply(LogEntry::SetValue {
+                key: format!("k{}", i),
+                value: Vec::from(format!("v{}", i)),
+            });
+        }
+        let path = std::env::temp_dir().join(format!("ddbb_test_kv_stream_{}", std::process::id()));
+        let mut writer = SnapshotWriter::new(std::fs::File::create(&path).unwrap());
+        kv_store.write_snapshot(&mut writer).unwrap();
+        writer.finish().unwrap();
+
+        let mut restored = KVStore::new();
+        let mut reader = SnapshotReader::open(&path).unwrap();
+        restored.read_snapshot(&mut reader).unwrap();
+        assert_eq!(restored.get("k1024"), Some(Vec::from("v1024")));
+        assert_eq!(restored.mod_rev("k0"), kv_store.mod_rev("k0"));
+        assert_eq!(restored.revision, kv_store.revision);
+        assert_eq!(restored.keyspace(), kv_store.keyspace());
+        assert_eq!(restored.keyspace().keys, SNAPSHOT_CHUNK_KEYS as u64 + 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_kv_store_namespace_quota() {
+        let mut kv_store = KVStore::new();
+        let set = |key: &str| LogEntry::SetValue {
+            key: scoped_key("app1", key),
+            value: Vec::from("v"),
+        };
+        // no keys before the namespace is created
+        kv_store.apply(set("k0"));
+        assert_eq!(kv_store.get(&scoped_key("app1", "k0")), None);
+
+        kv_store.apply(LogEntry::CreateNamespace {
+            opid: ("127.0.0.1:6550".to_string(), 1),
+            name: "app1".to_string(),
+            max_keys: 2,
+            token: None,
+            created: false,
+        });
+        for key in &["k1", "k2", "k3"] {
+            kv_store.apply(set(key));
+        }
+        assert_eq!(kv_store.namespace("app1").unwrap().keys, 2);
+        assert_eq!(kv_store.get(&scoped_key("app1", "k3")), None);
+        assert!(matches!(
+            kv_store.refusal(&scoped_key("app1", "k3")),
+            Some(Error::QuotaExceeded(_))
+        ));
+        assert!(kv_store.refusal(&scoped_key("app1", "k2")).is_none());
+        // overwriting a key does not take quota
+        kv_store.apply(set("k2"));
+        assert_eq!(kv_store.namespace("app1").unwrap().keys, 2);
+        assert_eq!(kv_store.keyspace().namespaces["app1"].keys, 2);
+
+        kv_store.apply(LogEntry::DeleteNamespace {
+            opid: ("127.0.0.1:6550".to_string(), 2),
+            name: "app1".to_string(),
+            deleted: false,
+        });
+        assert_eq!(kv_store.namespace("app1"), None);
+        assert_eq!(kv_store.get(&scoped_key("app1", "k1")), None);
+        assert_eq!(kv_store.keyspace(), KeyspaceStats::default());
+    }
+
+    #[test]
+    fn test_kv_store_bulk_set() {
+        let mut kv_store = KVStore::new();
+        let applied = kv_store.apply(LogEntry::BulkSet {
+            opid: ("127.0.0.1:6550".to_string(), 1),
+            pairs: vec![
+                ("k1".to_string(), Vec::from("v1")),
+                // no namespace app1, dropped
+                (scoped_key("app1", "k2"), Vec::from("v2")),
+                ("k1".to_string(), Vec::from("v3")),
+            ],
+        });
+        match applied {
+            LogEntry::BulkSet { pairs, .. } => assert_eq!(pairs.len(), 2),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        assert_eq!(kv_store.get("k1"), Some(Vec::from("v3")));
+        assert_eq!(kv_store.revision(), 2);
+        assert_eq!(kv_store.mod_rev("k1"), 2);
+    }
+
+    #[test]
+    fn test_kv_store_chunked_set() {
+        let mut kv_store = KVStore::new();
+        let opid = |ts| ("127.0.0.1:6550".to_string(), ts);
+        let chunk = |ts, seq, data: &str| LogEntry::ValueChunk {
+            opid: opid(ts),
+            upload: 1,
+            seq,
+            data: Vec::from(data),
+            now: 0,
+        };
+        let chunked_set = |ts, chunks| LogEntry::ChunkedSet {
+            opid: opid(ts),
+            key: "k1".to_string(),
+            chunks,
+            written: false,
+        };
+        kv_store.apply(chunk(3, 1, "lo"));
+        kv_store.apply(chunk(2, 0, "hel"));
+        // the uploads survive a snapshot
+        let mut restored = KVStore::new();
+        restored.restore(&kv_store.snapshot().unwrap()).unwrap();
+        match restored.apply(chunked_set(1, 2)) {
+            LogEntry::ChunkedSet { written, .. } => assert!(written),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        assert_eq!(restored.get("k1"), Some(Vec::from("hello")));
+        assert_eq!(restored.revision(), 1);
+        assert!(restored.uploads.is_empty());
+
+        // a piece missing
+        kv_store.apply(chunked_set(1, 3));
+        assert_eq!(kv_store.get("k1"), None);
+    }
+
+    #[test]
+    fn test_kv_store_uploads_expire() {
+        let mut kv_store = KVStore::new();
+        let opid = |ts| ("127.0.0.1:6550".to_string(), ts);
+        let chunk = |ts, upload, now| LogEntry::ValueChunk {
+            opid: opid(ts),
+            upload,
+            seq: 0,
+            data: Vec::from("v"),
+            now,
+        };
+        kv_store.apply(chunk(2, 1, 1000));
+        // the dedup window moving on does not drop the upload
+        for ts in 3..DEDUP_WINDOW as u64 + 10 {
+            kv_store.apply(LogEntry::LINRead {
+                opid: opid(ts),
+                key: "k1".to_string(),
+                value: None,
+            });
+        }
+        assert_eq!(kv_store.uploads["127.0.0.1:6550"].len(), 1);
+
+        // another upload long after the first one drops it
+        let ttl = UPLOAD_TTL.as_millis() as u64;
+        kv_store.apply(chunk(DEDUP_WINDOW as u64 + 20, 2, 1000 + ttl + 1));
+        let uploads: Vec<u64> = kv_store.uploads["127.0.0.1:6550"].keys().copied().collect();
+        assert_eq!(uploads, vec![2]);
+        match kv_store.apply(LogEntry::ChunkedSet {
+            opid: opid(1),
+            key: "k1".to_string(),
+            chunks: 1,
+            written: false,
+        }) {
+            LogEntry::ChunkedSet { written, .. } => assert!(!written),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        assert_eq!(kv_store.upload_started["127.0.0.1:6550"].len(), 1);
+    }
+
+    #[test]
+    fn test_kv_store_stat() {
+        let mut kv_store = KVStore::new();
+        kv_store.put("k0".to_string(), Vec::from("v0"));
+        kv_store.put("k1".to_string(), Vec::from("v1"));
+        kv_store.put("k1".to_string(), Vec::from("value"));
+        let applied = kv_store.apply(LogEntry::LINStat {
+            opid: ("127.0.0.1:6550".to_string(), 1),
+            key: "k1".to_string(),
+            value: None,
+            meta: None,
+        });
+        let expected = KeyMeta {
+            create_rev: 2,
+            mod_rev: 3,
+            version: 2,
+            value_len: 5,
+            lease: None,
+        };
+        match applied {
+            LogEntry::LINStat { value, meta, .. } => {
+                assert_eq!(value, Some(Vec::from("value")));
+                assert_eq!(meta, Some(expected));
+            }
+            other => panic!("unexpected log: {:?}", other),
+        }
+        assert_eq!(kv_store.stat("k2"), None);
+    }
+
+    #[test]
+    fn test_kv_store_tree() {
+        let mut kv_store = KVStore::new();
+        for key in &[
+            "/app",
+            "/app/db/host",
+            "/app/db/port",
+            "/app/http",
+            "/apps/x",
+        ] {
+            kv_store.put(key.to_string(), Vec::from("v"));
+        }
+        assert_eq!(kv_store.list_children("/app"), vec!["db", "http"]);
+        assert_eq!(kv_store.list_children("/"), vec!["app", "apps"]);
+        assert!(kv_store.list_children("/app/http").is_empty());
+
+        let applied = kv_store.apply(LogEntry::DeleteTree {
+            opid: ("127.0.0.1:6550".to_string(), 1),
+            path: "/app/db".to_string(),
+            deleted: Vec::new(),
+        });
+        match applied {
+            LogEntry::DeleteTree { deleted, .. } => {
+                assert_eq!(deleted, vec!["/app/db/host", "/app/db/port"])
+            }
+            other => panic!("unexpected log: {:?}", other),
+        }
+        // one revision per key deleted
+        assert_eq!(kv_store.revision(), 7);
+        kv_store.apply(LogEntry::DeleteTree {
+            opid: ("127.0.0.1:6550".to_string(), 2),
+            path: "/app".to_string(),
+            deleted: Vec::new(),
+        });
+        assert_eq!(kv_store.get("/app"), None);
+        assert_eq!(kv_store.stat("/app/http"), None);
+        assert_eq!(kv_store.list_children("/"), vec!["apps"]);
+
+        let mut restored = KVStore::new();
+        restored.restore(&kv_store.snapshot().unwrap()).unwrap();
+        assert_eq!(
+            restored.scan("/"),
+            vec![("/apps/x".to_string(), Vec::from("v"))]
+        );
+    }
+
+    #[test]
+    fn test_kv_store_delete_prefix() {
+        let mut kv_store = KVStore::new();
+        for key in &["\u{0}config/compact_every", "a/1", "a/2", "a/3", "b/1"] {
+            kv_store.put(key.to_string(), Vec::from("v"));
+        }
+        let delete_prefix = |ts, prefix: &str| LogEntry::DeletePrefix {
+            opid: ("127.0.0.1:6550".to_string(), ts),
+            prefix: prefix.to_string(),
+            limit: 2,
+            deleted: Vec::new(),
+        };
+        match kv_store.apply(delete_prefix(1, "a/")) {
+            LogEntry::DeletePrefix { deleted, .. } => assert_eq!(deleted, vec!["a/1", "a/2"]),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        // the settings are never reached from an unreserved prefix
+        match kv_store.apply(delete_prefix(2, "")) {
+            LogEntry::DeletePrefix { deleted, .. } => assert_eq!(deleted, vec!["a/3", "b/1"]),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        assert_eq!(kv_store.revision(), 9);
+        assert_eq!(kv_store.scan("").len(), 1);
+    }
+
+    #[test]
+    fn test_kv_store_sessions() {
+        let mut kv_store = KVStore::new();
+        let opid = |ts| ("127.0.0.1:6550".to_string(), ts);
+        let session = match kv_store.apply(LogEntry::OpenSession {
+            opid: opid(1),
+            ttl: 100,
+            now: 0,
+            session: 0,
+        }) {
+            LogEntry::OpenSession { session, .. } => session,
+            other => panic!("unexpected log: {:?}", other),
+        };
+        let ephemeral = |ts, session, key: &str| LogEntry::EphemeralSet {
+            opid: opid(ts),
+            session,
+            key: key.to_string(),
+            value: Vec::from("v"),
+            written: false,
+        };
+        kv_store.apply(ephemeral(2, session, "/lock"));
+        kv_store.apply(ephemeral(3, session, "/a"));
+        assert_eq!(kv_store.stat("/lock").unwrap().lease, Some(session));
+        // a plain delete detaches the key
+        kv_store.delete("/a");
+        assert_eq!(kv_store.session(session).unwrap().keys.len(), 1);
+
+        // kept alive until 150, so a sweep at 120 does not close it
+        kv_store.apply(LogEntry::KeepAlive {
+            opid: opid(4),
+            session,
+            now: 50,
+            alive: false,
+        });
+        let close = |ts, now, only_expired| LogEntry::CloseSession {
+            opid: opid(ts),
+            session,
+            now,
+            only_expired,
+            closed: false,
+            deleted: Vec::new(),
+        };
+        match kv_store.apply(close(5, 120, true)) {
+            LogEntry::CloseSession { closed, .. } => assert!(!closed),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        match kv_store.apply(close(6, 160, true)) {
+            LogEntry::CloseSession {
+                closed, deleted, ..
+            } => {
+                assert!(closed);
+                assert_eq!(deleted, vec!["/lock"]);
+            }
+            other => panic!("unexpected log: {:?}", other),
+        }
+        assert_eq!(kv_store.get("/lock"), None);
+        assert!(kv_store.sessions().is_empty());
+
+        // an expired session neither writes nor is kept alive
+        match kv_store.apply(ephemeral(7, session, "/b")) {
+            LogEntry::EphemeralSet { written, .. } => assert!(!written),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        match kv_store.apply(LogEntry::KeepAlive {
+            opid: opid(8),
+            session,
+            now: 170,
+            alive: false,
+        }) {
+            LogEntry::KeepAlive { alive, .. } => assert!(!alive),
+            other => panic!("unexpected log: {:?}", other),
+        }
+
+        // sessions and leases survive a snapshot
+        kv_store.apply(LogEntry::OpenSession {
+            opid: opid(9),
+            ttl: 100,
+            now: 170,
+            session: 0,
+        });
+        kv_store.apply(ephemeral(10, session + 1, "/c"));
+        let mut restored = KVStore::new();
+        restored.restore(&kv_store.snapshot().unwrap()).unwrap();
+        assert_eq!(restored.stat("/c").unwrap().lease, Some(session + 1));
+    }
+
+    #[test]
+    fn test_kv_store_put_detaches_ephemeral() {
+        let mut kv_store = KVStore::new();
+        let opid = |ts| ("127.0.0.1:6550".to_string(), ts);
+        kv_store.apply(LogEntry::OpenSession {
+            opid: opid(1),
+            ttl: 100,
+            now: 0,
+            session: 0,
+        });
+        let session = kv_store.sessions()[0].id;
+        kv_store.apply(LogEntry::EphemeralSet {
+            opid: opid(2),
+            session,
+            key: "/lock".to_string(),
+            value: Vec::from("v1"),
+            written: false,
+        });
+        kv_store.apply(LogEntry::LINWrite {
+            opid: opid(3),
+            key: "/lock".to_string(),
+            value: Vec::from("v2"),
+        });
+        assert_eq!(kv_store.stat("/lock").unwrap().lease, None);
+        assert!(kv_store.session(session).unwrap().keys.is_empty());
+
+        // closing the session keeps the key written over
+        match kv_store.apply(LogEntry::CloseSession {
+            opid: opid(4),
+            session,
+            now: 10,
+            only_expired: false,
+            closed: false,
+            deleted: Vec::new(),
+        }) {
+            LogEntry::CloseSession { deleted, .. } => assert!(deleted.is_empty()),
+            other => panic!("unexpected log: {:?}", other),
+        }
+        assert_eq!(kv_store.get("/lock"), Some(Vec::from("v2")));
+    }
+
+    #[test]
+    fn test_kv_store_semaphore_sessions() {
+        let mut kv_store = KVStore::new();
+        let opid = |ts| ("127.0.0.1:6550".to_string(), ts);
+        let open = |kv_store: &mut KVStore, ts, now| {
+            let log = LogEntry::OpenSession {
+                opid: opid(ts),
+                ttl: 100,
+                now,
+                session: 0,
+            };
+            match kv_store.apply(log) {
+                LogEntry::OpenSession { session, .. } => session,
+                other => panic!("unexpected log: {:?}", other),
+            }
+        };
+        let acquire = |kv_store: &mut KVStore, ts, session, now| {
+            let log = LogEntry::SemAcquire {
+                opid: opid(ts),
+                name: "s".to_string(),
+                permits: 1,
+                session,
+                now,
+                acquired: false,
+            };
+            match kv_store.apply(log) {
+                LogEntry::SemAcquire { acquired, .. } => acquired,
+                other => panic!("unexpected log: {:?}", other),
+            }
+        };
+        let keep_alive = |ts, session, now| LogEntry::KeepAlive {
+            opid: opid(ts),
+            session,
+            now,
+            alive: false,
+        };
+        let s1 = open(&mut kv_store, 1, 0);
+        assert!(acquire(&mut kv_store, 2, s1, 10));
+        // the permit is held by the session, acquiring again keeps it
+        assert!(acquire(&mut kv_store, 3, s1, 20));
+        let s2 = open(&mut kv_store, 4, 50);
+        assert!(!acquire(&mut kv_store, 5, s2, 60));
+        // no session, no permit
+        assert!(!acquire(&mut kv_store, 6, 0, 60));
+
+        // the keepalive of the session keeps its permit until 180
+        kv_store.apply(keep_alive(7, s1, 80));
+        kv_store.apply(keep_alive(8, s2, 140));
+        assert!(!acquire(&mut kv_store, 9, s2, 140));
+        // released once the session expired and was closed
+        kv_store.apply(LogEntry::CloseSession {
+            opid: opid(10),
+            session: s1,
+            now: 190,
+            only_expired: true,
+            closed: false,
+            deleted: Vec::new(),
+        });
+        assert!(kv_store.semaphores.is_empty());
+        assert!(acquire(&mut kv_store, 11, s2, 200));
+
+        // or as soon as it expired, before it is closed: s2 was kept alive until 240
+        let s3 = open(&mut kv_store, 12, 250);
+        assert!(kv_store.session(s2).is_some());
+        assert!(acquire(&mut kv_store, 13, s3, 250));
+        let s4 = open(&mut kv_store, 14, 260);
+        assert!(!acquire(&mut kv_store, 15, s4, 260));
+        // s3 was the only holder left
+        kv_store.apply(LogEntry::SemRelease {
+            opid: opid(16),
+            name: "s".to_string(),
+            session: s3,
+        });
+        assert!(kv_store.semaphores.is_empty());
+    }
+}
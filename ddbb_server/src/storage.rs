@@ -0,0 +1,1521 @@
+use omnipaxos_core::{
+    ballot_leader_election::Ballot,
+    storage::{StopSign, StopSignEntry, Storage},
+};
+use log::error;
+use omnipaxos_storage::memory_storage::MemoryStorage;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write as _},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "rocksdb")]
+use crate::config::{ROCKSDB_BACKGROUND_JOBS, ROCKSDB_LOG_WRITE_BUFFER_SIZE};
+use crate::config::{
+    ROCKSDB_STORAGE_DIR, SEGMENT_SIZE, SEGMENT_SPARES, SEGMENT_STORAGE_DIR, SLED_STORAGE_DIR,
+    STORAGE_MAX_BATCH_LATENCY,
+};
+use crate::omni_paxos_server::op_data_structure::{LogEntry, Snapshot};
+use crate::segments::{self, Corruption, SegmentLog, SegmentOptions};
+use ddbb_libs::clock::{SharedClock, Ticker};
+use ddbb_libs::{Error, Result};
+use tokio::task;
+
+/// Where omnipaxos keeps its log and replica state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// lost on restart, the node recovers from its state snapshot and its peers
+    Memory,
+    /// a sled database in the data directory
+    Sled,
+    /// a rocksdb database in the data directory, for large logs, only with the
+    /// `rocksdb` feature
+    RocksDB,
+    /// fixed-size segment files in the data directory, appended to in order and
+    /// trimmed a segment at a time
+    Segments,
+}
+
+/// When a persistent backend makes its writes durable, see `--fsync`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncMode {
+    /// flush every write before returning, so a promise or accept answered survives a crash
+    EveryWrite,
+    /// sync every backend this often, with or without writes since, see
+    /// `StorageFlusher::sync_periodically`: a crash loses up to this much
+    Periodic(Duration),
+    /// never sync, the OS writes the pages back when it sees fit; a crash of the machine
+    /// may lose any write, and sled holds the latest ones in its own buffers until they
+    /// fill. For benchmarks and test clusters only
+    Never,
+}
+
+impl std::str::FromStr for SyncMode {
+    type Err = String;
+
+    /// #Descriptions: `always`, `never`, or `interval:<ms>`.
+    fn from_str(mode: &str) -> std::result::Result<Self, Self::Err> {
+        match mode {
+            "always" => Ok(SyncMode::EveryWrite),
+            "never" => Ok(SyncMode::Never),
+            other => other
+                .strip_prefix("interval:")
+                .and_then(|ms| ms.parse().ok())
+                .map(|ms| SyncMode::Periodic(Duration::from_millis(ms)))
+                .ok_or_else(|| {
+                    format!("the fsync policy is always, interval:<ms> or never, not {}", other)
+                }),
+        }
+    }
+}
+
+/// A storage whose writes may be held back, to be written together.
+trait NodeStorage: Storage<LogEntry, Snapshot> + Send {
+    /// Write everything held back.
+    fn flush(&mut self) {}
+
+    /// Write everything held back and make all the writes so far durable.
+    fn sync(&mut self) {}
+
+    /// Whether some writes were not made durable yet, which a crash of the machine
+    /// may lose.
+    fn unsynced(&self) -> bool {
+        false
+    }
+}
+
+impl NodeStorage for MemoryStorage<LogEntry, Snapshot> {}
+
+type SharedStorage = Arc<Mutex<Box<dyn NodeStorage>>>;
+
+/// The omnipaxos storage of a node, one of the backends picked at start.
+pub struct DDBBStorage {
+    inner: SharedStorage,
+    /// where the log was cut short when opened, if it was
+    corruption: Option<Corruption>,
+    sync: SyncMode,
+}
+
+/// Flushes the writes of a `DDBBStorage` once omnipaxos owns it, so they are durable
+/// before anything depending on them is sent or answered.
+#[derive(Clone)]
+pub struct StorageFlusher {
+    storage: SharedStorage,
+    sync: SyncMode,
+}
+
+impl StorageFlusher {
+    pub fn flush(&self) {
+        self.storage.lock().unwrap().flush()
+    }
+
+    /// #Descriptions: whether some writes were not made durable yet.
+    pub fn unsynced(&self) -> bool {
+        self.storage.lock().unwrap().unsynced()
+    }
+
+    /// #Descriptions: under `SyncMode::Periodic`, sync the storage every period of
+    /// `clock`, so the last writes of a node gone idle are made durable too. Returns
+    /// at once under the other modes.
+    pub async fn sync_periodically(self, clock: SharedClock) {
+        let period = match self.sync {
+            SyncMode::Periodic(period) => period,
+            SyncMode::EveryWrite | SyncMode::Never => return,
+        };
+        let mut syncs = Ticker::new(clock, period);
+        syncs.reset();
+        loop {
+            syncs.tick().await;
+            // off the runtime threads, an fsync may take a while
+            let storage = self.storage.clone();
+            if let Err(e) = task::spawn_blocking(move || storage.lock().unwrap().sync()).await {
+                error!("Storage sync failed: {:?}", e);
+            }
+        }
+    }
+}
+
+impl DDBBStorage {
+    fn with(storage: impl NodeStorage + 'static, sync: SyncMode) -> Self {
+        DDBBStorage {
+            inner: Arc::new(Mutex::new(Box::new(storage))),
+            corruption: None,
+            sync,
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self::with(MemoryStorage::default(), SyncMode::Never)
+    }
+
+    pub fn sled(path: impl AsRef<Path>, sync: SyncMode) -> Result<Self> {
+        let backend = SledBackend::open(path, sync)?;
+        Ok(Self::with(
+            KVStorage::open(backend, STORAGE_MAX_BATCH_LATENCY),
+            sync,
+        ))
+    }
+
+    #[cfg(feature = "rocksdb")]
+    pub fn rocksdb(path: impl AsRef<Path>, sync: SyncMode) -> Result<Self> {
+        let backend = RocksDBBackend::open(path, sync)?;
+        Ok(Self::with(
+            KVStorage::open(backend, STORAGE_MAX_BATCH_LATENCY),
+            sync,
+        ))
+    }
+
+    pub fn segments(path: impl AsRef<Path>, sync: SyncMode) -> Result<Self> {
+        let options = SegmentOptions {
+            segment_size: SEGMENT_SIZE,
+            spares: SEGMENT_SPARES,
+        };
+        let storage = SegmentStorage::open(path, options, sync, STORAGE_MAX_BATCH_LATENCY)?;
+        let corruption = storage.log.corruption().cloned();
+        Ok(DDBBStorage {
+            corruption,
+            ..Self::with(storage, sync)
+        })
+    }
+
+    /// #Descriptions: the record the log was truncated at when opened, as it failed its
+    /// checksum, to report to the operator.
+    pub fn corruption(&self) -> Option<&Corruption> {
+        self.corruption.as_ref()
+    }
+
+    pub fn flusher(&self) -> StorageFlusher {
+        StorageFlusher {
+            storage: self.inner.clone(),
+            sync: self.sync,
+        }
+    }
+
+    /// #Descriptions: open `backend`, keeping its files in `data_dir`, synced as of `sync`.
+    pub fn open(
+        backend: StorageBackend,
+        data_dir: impl AsRef<Path>,
+        sync: SyncMode,
+    ) -> Result<Self> {
+        match backend {
+            StorageBackend::Memory => Ok(Self::memory()),
+            StorageBackend::Sled => Self::sled(data_dir.as_ref().join(SLED_STORAGE_DIR), sync),
+            #[cfg(feature = "rocksdb")]
+            StorageBackend::RocksDB => {
+                Self::rocksdb(data_dir.as_ref().join(ROCKSDB_STORAGE_DIR), sync)
+            }
+            #[cfg(not(feature = "rocksdb"))]
+            StorageBackend::RocksDB => Err(Error::Other(
+                "ddbb_server built without the rocksdb feature".to_string(),
+            )),
+            StorageBackend::Segments => {
+                Self::segments(data_dir.as_ref().join(SEGMENT_STORAGE_DIR), sync)
+            }
+        }
+    }
+}
+
+impl Default for DDBBStorage {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+impl Storage<LogEntry, Snapshot> for DDBBStorage {
+    fn append_entry(&mut self, entry: LogEntry) -> u64 {
+        self.inner.lock().unwrap().append_entry(entry)
+    }
+
+    fn append_entries(&mut self, entries: Vec<LogEntry>) -> u64 {
+        self.inner.lock().unwrap().append_entries(entries)
+    }
+
+    fn append_on_prefix(&mut self, from_idx: u64, entries: Vec<LogEntry>) -> u64 {
+        self.inner
+            .lock()
+            .unwrap()
+            .append_on_prefix(from_idx, entries)
+    }
+
+    fn set_promise(&mut self, n_prom: Ballot) {
+        self.inner.lock().unwrap().set_promise(n_prom)
+    }
+
+    fn set_decided_idx(&mut self, ld: u64) {
+        self.inner.lock().unwrap().set_decided_idx(ld)
+    }
+
+    fn get_decided_idx(&self) -> u64 {
+        self.inner.lock().unwrap().get_decided_idx()
+    }
+
+    fn set_accepted_round(&mut self, na: Ballot) {
+        self.inner.lock().unwrap().set_accepted_round(na)
+    }
+
+    fn get_accepted_round(&self) -> Ballot {
+        self.inner.lock().unwrap().get_accepted_round()
+    }
+
+    fn get_entries(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        self.inner.lock().unwrap().get_entries(from, to)
+    }
+
+    fn get_log_len(&self) -> u64 {
+        self.inner.lock().unwrap().get_log_len()
+    }
+
+    fn get_suffix(&self, from: u64) -> Vec<LogEntry> {
+        self.inner.lock().unwrap().get_suffix(from)
+    }
+
+    fn get_promise(&self) -> Ballot {
+        self.inner.lock().unwrap().get_promise()
+    }
+
+    fn set_stopsign(&mut self, s: StopSignEntry) {
+        self.inner.lock().unwrap().set_stopsign(s)
+    }
+
+    fn get_stopsign(&self) -> Option<StopSignEntry> {
+        self.inner.lock().unwrap().get_stopsign()
+    }
+
+    fn trim(&mut self, idx: u64) {
+        self.inner.lock().unwrap().trim(idx)
+    }
+
+    fn set_compacted_idx(&mut self, idx: u64) {
+        self.inner.lock().unwrap().set_compacted_idx(idx)
+    }
+
+    fn get_compacted_idx(&self) -> u64 {
+        self.inner.lock().unwrap().get_compacted_idx()
+    }
+
+    fn set_snapshot(&mut self, snapshot: Snapshot) {
+        self.inner.lock().unwrap().set_snapshot(snapshot)
+    }
+
+    fn get_snapshot(&self) -> Option<Snapshot> {
+        self.inner.lock().unwrap().get_snapshot()
+    }
+}
+
+const PROMISE: &[u8] = b"PROMISE";
+const ACCEPTED_ROUND: &[u8] = b"ACCEPTED_ROUND";
+const DECIDED_IDX: &[u8] = b"DECIDED_IDX";
+const COMPACTED_IDX: &[u8] = b"COMPACTED_IDX";
+const STOPSIGN: &[u8] = b"STOPSIGN";
+const SNAPSHOT: &[u8] = b"SNAPSHOT";
+/// position of the first entry kept, the entries before it were trimmed
+const LOG_START: &[u8] = b"LOG_START";
+
+/// What a key of a key-value backend holds, a column family of rocksdb.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Column {
+    /// the entries, keyed by their position
+    Log,
+    /// the rounds, indices and stopsign of the replica
+    Meta,
+    /// the snapshot of the state machine
+    State,
+}
+
+impl Column {
+    const ALL: [Column; 3] = [Column::Log, Column::Meta, Column::State];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Column::Log => "log",
+            Column::Meta => "meta",
+            Column::State => "state",
+        }
+    }
+}
+
+enum Write {
+    Put(Column, Vec<u8>, Vec<u8>),
+    Delete(Column, Vec<u8>),
+}
+
+/// A persistent key-value store the log and replica state are kept in. The
+/// storage api can not fail, so a failing disk panics.
+trait KVBackend: Send {
+    fn get(&self, column: Column, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Apply every write of `batch`, or none of them.
+    fn write(&mut self, batch: Vec<Write>);
+
+    /// The keys from `from` up to `to` with their values, in order.
+    fn range(&self, column: Column, from: &[u8], to: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)>;
+
+    fn last_key(&self, column: Column) -> Option<Vec<u8>>;
+
+    /// Make the writes so far durable, for `SyncMode::Periodic`.
+    fn sync(&self);
+}
+
+/// `StopSignEntry` is not serializable itself.
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredStopSign {
+    stopsign: StopSign,
+    decided: bool,
+}
+
+fn log_key(position: u64) -> Vec<u8> {
+    position.to_be_bytes().to_vec()
+}
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).expect("Failed to serialize for storage")
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> T {
+    serde_json::from_slice(bytes).expect("Failed to deserialize from storage")
+}
+
+/// The omnipaxos storage over a key-value backend. The writes are held back and
+/// written as a single batch by `flush`, e.g. once per tick of the server, or once
+/// the oldest one waited `max_batch_latency`. Reads see the writes held back.
+struct KVStorage<B: KVBackend> {
+    backend: B,
+    /// cached from `LOG_START`
+    log_start: u64,
+    log_len: u64,
+    /// the latest write of every key held back, `None` deleting it
+    pending: BTreeMap<(Column, Vec<u8>), Option<Vec<u8>>>,
+    pending_since: Option<Instant>,
+    max_batch_latency: Duration,
+    /// written to the backend since its last `sync`
+    unsynced: bool,
+}
+
+impl<B: KVBackend> KVStorage<B> {
+    fn open(backend: B, max_batch_latency: Duration) -> Self {
+        let mut storage = KVStorage {
+            backend,
+            log_start: 0,
+            log_len: 0,
+            pending: BTreeMap::new(),
+            pending_since: None,
+            max_batch_latency,
+            unsynced: false,
+        };
+        storage.log_start = storage.get(Column::Meta, LOG_START).unwrap_or(0);
+        if let Some(last) = storage.backend.last_key(Column::Log) {
+            let mut position = [0; 8];
+            position.copy_from_slice(&last);
+            storage.log_len = u64::from_be_bytes(position) + 1 - storage.log_start;
+        }
+        storage
+    }
+
+    fn get<T: DeserializeOwned>(&self, column: Column, key: &[u8]) -> Option<T> {
+        let bytes = match self.pending.get(&(column, key.to_vec())) {
+            Some(pending) => pending.clone(),
+            None => self.backend.get(column, key),
+        };
+        bytes.map(|bytes| decode(&bytes))
+    }
+
+    fn set<T: Serialize>(&mut self, column: Column, key: &[u8], value: &T) {
+        let write = Write::Put(column, key.to_vec(), encode(value));
+        self.write(vec![write]);
+    }
+
+    fn write(&mut self, batch: Vec<Write>) {
+        for write in batch {
+            match write {
+                Write::Put(column, key, value) => self.pending.insert((column, key), Some(value)),
+                Write::Delete(column, key) => self.pending.insert((column, key), None),
+            };
+        }
+        let since = *self.pending_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= self.max_batch_latency {
+            self.flush_pending();
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        self.pending_since = None;
+        if self.pending.is_empty() {
+            return;
+        }
+        let batch = mem::take(&mut self.pending)
+            .into_iter()
+            .map(|((column, key), value)| match value {
+                Some(value) => Write::Put(column, key, value),
+                None => Write::Delete(column, key),
+            })
+            .collect();
+        self.backend.write(batch);
+        self.unsynced = true;
+    }
+
+    /// #Descriptions: replace the entries from `from_idx` on with `entries`.
+    fn write_from(&mut self, from_idx: u64, entries: Vec<LogEntry>) -> u64 {
+        let from = self.log_start + from_idx.min(self.log_len);
+        let end = self.log_start + self.log_len;
+        let mut batch: Vec<Write> = (from + entries.len() as u64..end)
+            .map(|position| Write::Delete(Column::Log, log_key(position)))
+            .collect();
+        let len = from - self.log_start + entries.len() as u64;
+        for (i, entry) in entries.iter().enumerate() {
+            let key = log_key(from + i as u64);
+            batch.push(Write::Put(Column::Log, key, encode(entry)));
+        }
+        self.write(batch);
+        self.log_len = len;
+        len
+    }
+
+    fn read(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        // as `MemoryStorage`, a range past the end is empty
+        if from >= to || to > self.log_len {
+            return vec![];
+        }
+        let from = log_key(self.log_start + from);
+        let to = log_key(self.log_start + to);
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = self
+            .backend
+            .range(Column::Log, &from, &to)
+            .into_iter()
+            .collect();
+        let pending = self.pending.range((Column::Log, from)..(Column::Log, to));
+        for ((_, key), value) in pending {
+            match value {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+        entries.values().map(|entry| decode(entry)).collect()
+    }
+}
+
+impl<B: KVBackend> NodeStorage for KVStorage<B> {
+    fn flush(&mut self) {
+        self.flush_pending()
+    }
+
+    fn sync(&mut self) {
+        self.flush_pending();
+        if mem::take(&mut self.unsynced) {
+            self.backend.sync();
+        }
+    }
+
+    fn unsynced(&self) -> bool {
+        self.unsynced || !self.pending.is_empty()
+    }
+}
+
+impl<B: KVBackend> Drop for KVStorage<B> {
+    fn drop(&mut self) {
+        self.flush_pending()
+    }
+}
+
+impl<B: KVBackend> Storage<LogEntry, Snapshot> for KVStorage<B> {
+    fn append_entry(&mut self, entry: LogEntry) -> u64 {
+        self.write_from(self.log_len, vec![entry])
+    }
+
+    fn append_entries(&mut self, entries: Vec<LogEntry>) -> u64 {
+        self.write_from(self.log_len, entries)
+    }
+
+    fn append_on_prefix(&mut self, from_idx: u64, entries: Vec<LogEntry>) -> u64 {
+        self.write_from(from_idx, entries)
+    }
+
+    fn set_promise(&mut self, n_prom: Ballot) {
+        self.set(Column::Meta, PROMISE, &n_prom)
+    }
+
+    fn set_decided_idx(&mut self, ld: u64) {
+        self.set(Column::Meta, DECIDED_IDX, &ld)
+    }
+
+    fn get_decided_idx(&self) -> u64 {
+        self.get(Column::Meta, DECIDED_IDX).unwrap_or(0)
+    }
+
+    fn set_accepted_round(&mut self, na: Ballot) {
+        self.set(Column::Meta, ACCEPTED_ROUND, &na)
+    }
+
+    fn get_accepted_round(&self) -> Ballot {
+        self.get(Column::Meta, ACCEPTED_ROUND).unwrap_or_default()
+    }
+
+    fn get_entries(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        self.read(from, to)
+    }
+
+    fn get_log_len(&self) -> u64 {
+        self.log_len
+    }
+
+    fn get_suffix(&self, from: u64) -> Vec<LogEntry> {
+        self.read(from, self.log_len)
+    }
+
+    fn get_promise(&self) -> Ballot {
+        self.get(Column::Meta, PROMISE).unwrap_or_default()
+    }
+
+    fn set_stopsign(&mut self, s: StopSignEntry) {
+        let stored = StoredStopSign {
+            stopsign: s.stopsign,
+            decided: s.decided,
+        };
+        self.set(Column::Meta, STOPSIGN, &stored)
+    }
+
+    fn get_stopsign(&self) -> Option<StopSignEntry> {
+        self.get(Column::Meta, STOPSIGN)
+            .map(|s: StoredStopSign| StopSignEntry::with(s.stopsign, s.decided))
+    }
+
+    fn trim(&mut self, idx: u64) {
+        let idx = idx.min(self.log_len);
+        let mut batch: Vec<Write> = (self.log_start..self.log_start + idx)
+            .map(|position| Write::Delete(Column::Log, log_key(position)))
+            .collect();
+        let log_start = encode(&(self.log_start + idx));
+        batch.push(Write::Put(Column::Meta, LOG_START.to_vec(), log_start));
+        self.write(batch);
+        self.log_start += idx;
+        self.log_len -= idx;
+    }
+
+    fn set_compacted_idx(&mut self, idx: u64) {
+        self.set(Column::Meta, COMPACTED_IDX, &idx)
+    }
+
+    fn get_compacted_idx(&self) -> u64 {
+        self.get(Column::Meta, COMPACTED_IDX).unwrap_or(0)
+    }
+
+    fn set_snapshot(&mut self, snapshot: Snapshot) {
+        self.set(Column::State, SNAPSHOT, &snapshot)
+    }
+
+    fn get_snapshot(&self) -> Option<Snapshot> {
+        self.get(Column::State, SNAPSHOT)
+    }
+}
+
+/// the replica state and the snapshot of a `SegmentStorage`, next to its segments,
+/// each a line with the crc32 of the json after it
+const SEGMENT_META_FILE: &str = "meta.json";
+const SEGMENT_SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// The replica state of a `SegmentStorage`, rewritten as a whole.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct SegmentMeta {
+    promise: Ballot,
+    accepted_round: Ballot,
+    decided_idx: u64,
+    compacted_idx: u64,
+    stopsign: Option<StoredStopSign>,
+    /// position of the first entry kept, the entries before it were trimmed
+    log_start: u64,
+}
+
+/// The omnipaxos storage over a `SegmentLog`. As with `KVStorage`, the writes are held
+/// back until `flush`, or until the oldest one waited `max_batch_latency`: the entries
+/// are then appended to the segments, after the tail replaced was cut off, and the
+/// replica state and the snapshot changed are written to a temporary file renamed over
+/// the previous one. The segments trimmed are recycled once the new start is written.
+/// A log cut short at a corrupted record on open is synced from the peers again, the
+/// decided index lowered to its end.
+struct SegmentStorage {
+    dir: PathBuf,
+    log: SegmentLog,
+    meta: SegmentMeta,
+    snapshot: Option<Snapshot>,
+    log_len: u64,
+    /// the entries held back, from position `pending_from` on, replacing the ones there
+    pending_from: Option<u64>,
+    pending: Vec<LogEntry>,
+    meta_changed: bool,
+    snapshot_changed: bool,
+    pending_since: Option<Instant>,
+    max_batch_latency: Duration,
+    sync: SyncMode,
+    last_sync: Instant,
+    /// written since the last sync
+    unsynced: bool,
+}
+
+impl SegmentStorage {
+    fn open(
+        dir: impl AsRef<Path>,
+        options: SegmentOptions,
+        sync: SyncMode,
+        max_batch_latency: Duration,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut meta: SegmentMeta = read_state(&dir.join(SEGMENT_META_FILE))?.unwrap_or_default();
+        let snapshot = read_state(&dir.join(SEGMENT_SNAPSHOT_FILE))?;
+        let log = SegmentLog::open(&dir, options, meta.log_start)?;
+        meta.log_start = log.start();
+        let log_len = log.end() - log.start();
+        let mut meta_changed = false;
+        if let Some(corruption) = log.corruption() {
+            error!(
+                "Segment storage truncated at position {}, a corrupted record in {:?}, {} later segments dropped",
+                corruption.position, corruption.segment, corruption.segments_dropped
+            );
+            let decided_idx = meta.decided_idx.min(meta.compacted_idx + log_len);
+            meta_changed = decided_idx != meta.decided_idx;
+            meta.decided_idx = decided_idx;
+        }
+        Ok(SegmentStorage {
+            log_len,
+            dir,
+            log,
+            meta,
+            snapshot,
+            pending_from: None,
+            pending: Vec::new(),
+            meta_changed,
+            snapshot_changed: false,
+            pending_since: None,
+            max_batch_latency,
+            sync,
+            last_sync: Instant::now(),
+            unsynced: false,
+        })
+    }
+
+    fn changed(&mut self) {
+        let since = *self.pending_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= self.max_batch_latency {
+            self.flush_pending();
+        }
+    }
+
+    fn set_meta(&mut self, set: impl FnOnce(&mut SegmentMeta)) {
+        set(&mut self.meta);
+        self.meta_changed = true;
+        self.changed();
+    }
+
+    fn flush_pending(&mut self) {
+        let sync = match self.sync {
+            SyncMode::EveryWrite => true,
+            SyncMode::Periodic(period) => self.last_sync.elapsed() >= period,
+            SyncMode::Never => false,
+        };
+        self.write_pending(sync);
+    }
+
+    /// #Descriptions: write everything held back, then make it and the writes before
+    /// durable if `sync`.
+    fn write_pending(&mut self, sync: bool) {
+        self.pending_since = None;
+        self.unsynced |= self.pending_from.is_some() || self.meta_changed || self.snapshot_changed;
+        if let Some(from) = self.pending_from.take() {
+            let records: Vec<Vec<u8>> = mem::take(&mut self.pending)
+                .iter()
+                .map(|entry| encode(entry))
+                .collect();
+            self.log.truncate(from).expect("Failed to write segment storage");
+            self.log.append(&records).expect("Failed to write segment storage");
+        }
+        if sync {
+            self.log.sync().expect("Failed to sync segment storage");
+        }
+        // the entries before the replica state naming them
+        if mem::take(&mut self.meta_changed) {
+            self.write_state(SEGMENT_META_FILE, &self.meta, sync);
+        }
+        if mem::take(&mut self.snapshot_changed) {
+            self.write_state(SEGMENT_SNAPSHOT_FILE, &self.snapshot, sync);
+        }
+        if sync {
+            segments::sync_dir(&self.dir).expect("Failed to sync segment storage");
+            self.last_sync = Instant::now();
+            self.unsynced = false;
+        }
+        // once the new start is written
+        self.log.trim(self.meta.log_start);
+        self.log.recycle().expect("Failed to recycle segment storage");
+    }
+
+    fn write_state<T: Serialize>(&self, name: &str, state: &T, sync: bool) {
+        let path = self.dir.join(name);
+        let tmp_path = path.with_extension("tmp");
+        let json = encode(state);
+        let mut file = File::create(&tmp_path).expect("Failed to write segment storage");
+        file.write_all(format!("{:08x}\n", crc32fast::hash(&json)).as_bytes())
+            .and_then(|_| file.write_all(&json))
+            .expect("Failed to write segment storage");
+        if sync {
+            file.sync_data().expect("Failed to sync segment storage");
+        }
+        fs::rename(tmp_path, path).expect("Failed to write segment storage");
+    }
+
+    /// #Descriptions: replace the entries from `from_idx` on with `entries`.
+    fn write_from(&mut self, from_idx: u64, entries: Vec<LogEntry>) -> u64 {
+        let from = self.meta.log_start + from_idx.min(self.log_len);
+        match self.pending_from {
+            Some(pending_from) if from >= pending_from => {
+                self.pending.truncate((from - pending_from) as usize)
+            }
+            _ => {
+                self.pending_from = Some(from);
+                self.pending.clear();
+            }
+        }
+        self.log_len = from - self.meta.log_start + entries.len() as u64;
+        self.pending.extend(entries);
+        self.changed();
+        self.log_len
+    }
+
+    fn read(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        // as `MemoryStorage`, a range past the end is empty
+        if from >= to || to > self.log_len {
+            return vec![];
+        }
+        let from = self.meta.log_start + from;
+        let to = self.meta.log_start + to;
+        let pending_from = self.pending_from.unwrap_or(u64::MAX);
+        let mut entries: Vec<LogEntry> = self
+            .log
+            .read(from, to.min(pending_from))
+            .expect("Failed to read segment storage")
+            .iter()
+            .map(|entry| decode(entry))
+            .collect();
+        if to > pending_from {
+            let skip = from.saturating_sub(pending_from) as usize;
+            entries.extend_from_slice(&self.pending[skip..(to - pending_from) as usize]);
+        }
+        entries
+    }
+}
+
+/// #Descriptions: the state written to `path` by `write_state`, `None` if never written,
+/// an error rather than a state failing its checksum.
+fn read_state<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let (checksum, json) = match bytes.iter().position(|b| *b == b'\n') {
+        Some(newline) => (&bytes[..newline], &bytes[newline + 1..]),
+        None => (&bytes[..0], &bytes[..]),
+    };
+    let expected = std::str::from_utf8(checksum)
+        .ok()
+        .and_then(|checksum| u32::from_str_radix(checksum, 16).ok());
+    let checksum = crc32fast::hash(json);
+    if expected != Some(checksum) {
+        return Err(format!(
+            "corrupted {:?}: checksum {:08x}, expected {:08x}",
+            path,
+            checksum,
+            expected.unwrap_or(0)
+        )
+        .into());
+    }
+    Ok(Some(serde_json::from_slice(json)?))
+}
+
+impl NodeStorage for SegmentStorage {
+    fn flush(&mut self) {
+        self.flush_pending()
+    }
+
+    fn sync(&mut self) {
+        if self.unsynced || self.pending_since.is_some() {
+            self.write_pending(true)
+        }
+    }
+
+    fn unsynced(&self) -> bool {
+        self.unsynced || self.pending_since.is_some()
+    }
+}
+
+impl Drop for SegmentStorage {
+    fn drop(&mut self) {
+        self.flush_pending()
+    }
+}
+
+impl Storage<LogEntry, Snapshot> for SegmentStorage {
+    fn append_entry(&mut self, entry: LogEntry) -> u64 {
+        self.write_from(self.log_len, vec![entry])
+    }
+
+    fn append_entries(&mut self, entries: Vec<LogEntry>) -> u64 {
+        self.write_from(self.log_len, entries)
+    }
+
+    fn append_on_prefix(&mut self, from_idx: u64, entries: Vec<LogEntry>) -> u64 {
+        self.write_from(from_idx, entries)
+    }
+
+    fn set_promise(&mut self, n_prom: Ballot) {
+        self.set_meta(|meta| meta.promise = n_prom)
+    }
+
+    fn set_decided_idx(&mut self, ld: u64) {
+        self.set_meta(|meta| meta.decided_idx = ld)
+    }
+
+    fn get_decided_idx(&self) -> u64 {
+        self.meta.decided_idx
+    }
+
+    fn set_accepted_round(&mut self, na: Ballot) {
+        self.set_meta(|meta| meta.accepted_round = na)
+    }
+
+    fn get_accepted_round(&self) -> Ballot {
+        self.meta.accepted_round
+    }
+
+    fn get_entries(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        self.read(from, to)
+    }
+
+    fn get_log_len(&self) -> u64 {
+        self.log_len
+    }
+
+    fn get_suffix(&self, from: u64) -> Vec<LogEntry> {
+        self.read(from, self.log_len)
+    }
+
+    fn get_promise(&self) -> Ballot {
+        self.meta.promise
+    }
+
+    fn set_stopsign(&mut self, s: StopSignEntry) {
+        let stored = StoredStopSign {
+            stopsign: s.stopsign,
+            decided: s.decided,
+        };
+        self.set_meta(|meta| meta.stopsign = Some(stored))
+    }
+
+    fn get_stopsign(&self) -> Option<StopSignEntry> {
+        self.meta
+            .stopsign
+            .as_ref()
+            .map(|s| StopSignEntry::with(s.stopsign.clone(), s.decided))
+    }
+
+    fn trim(&mut self, idx: u64) {
+        let idx = idx.min(self.log_len);
+        let log_start = self.meta.log_start + idx;
+        // the entries held back before the new start are still written, then trimmed
+        self.log_len -= idx;
+        self.set_meta(|meta| meta.log_start = log_start)
+    }
+
+    fn set_compacted_idx(&mut self, idx: u64) {
+        self.set_meta(|meta| meta.compacted_idx = idx)
+    }
+
+    fn get_compacted_idx(&self) -> u64 {
+        self.meta.compacted_idx
+    }
+
+    fn set_snapshot(&mut self, snapshot: Snapshot) {
+        self.snapshot = Some(snapshot);
+        self.snapshot_changed = true;
+        self.changed();
+    }
+
+    fn get_snapshot(&self) -> Option<Snapshot> {
+        self.snapshot.clone()
+    }
+}
+
+/// A single sled tree, its keys prefixed by their column, as batches only span
+/// one tree.
+struct SledBackend {
+    db: sled::Db,
+    sync: SyncMode,
+}
+
+impl SledBackend {
+    fn open(path: impl AsRef<Path>, sync: SyncMode) -> Result<Self> {
+        // `SyncMode::Periodic` is synced from outside, as the other backends
+        let db = sled::Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .open()
+            .map_err(io::Error::from)?;
+        Ok(SledBackend { db, sync })
+    }
+
+    fn key(column: Column, key: &[u8]) -> Vec<u8> {
+        let mut prefixed = vec![column as u8];
+        prefixed.extend_from_slice(key);
+        prefixed
+    }
+}
+
+impl KVBackend for SledBackend {
+    fn get(&self, column: Column, key: &[u8]) -> Option<Vec<u8>> {
+        self.db
+            .get(Self::key(column, key))
+            .expect("Failed to read sled storage")
+            .map(|value| value.to_vec())
+    }
+
+    fn write(&mut self, batch: Vec<Write>) {
+        let mut sled_batch = sled::Batch::default();
+        for write in batch {
+            match write {
+                Write::Put(column, key, value) => sled_batch.insert(Self::key(column, &key), value),
+                Write::Delete(column, key) => sled_batch.remove(Self::key(column, &key)),
+            }
+        }
+        self.db
+            .apply_batch(sled_batch)
+            .expect("Failed to write sled storage");
+        if self.sync == SyncMode::EveryWrite {
+            self.db.flush().expect("Failed to flush sled storage");
+        }
+    }
+
+    fn range(&self, column: Column, from: &[u8], to: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.db
+            .range(Self::key(column, from)..Self::key(column, to))
+            .map(|pair| {
+                let (key, value) = pair.expect("Failed to read sled storage");
+                (key[1..].to_vec(), value.to_vec())
+            })
+            .collect()
+    }
+
+    fn last_key(&self, column: Column) -> Option<Vec<u8>> {
+        self.db
+            .scan_prefix([column as u8])
+            .keys()
+            .next_back()
+            .map(|key| key.expect("Failed to read sled storage")[1..].to_vec())
+    }
+
+    fn sync(&self) {
+        self.db.flush().expect("Failed to flush sled storage");
+    }
+}
+
+/// A rocksdb database with a column family per `Column`, so that the many log
+/// entries written once and trimmed in order are compacted apart from the few
+/// metadata keys overwritten at every round.
+#[cfg(feature = "rocksdb")]
+struct RocksDBBackend {
+    db: rocksdb::DB,
+    write_options: rocksdb::WriteOptions,
+}
+
+#[cfg(feature = "rocksdb")]
+impl RocksDBBackend {
+    fn open(path: impl AsRef<Path>, sync: SyncMode) -> Result<Self> {
+        use rocksdb::{ColumnFamilyDescriptor, DBCompressionType, Options, WriteOptions, DB};
+
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        options.set_max_background_jobs(ROCKSDB_BACKGROUND_JOBS);
+        let columns = Column::ALL.iter().map(|column| {
+            let mut column_options = Options::default();
+            if *column == Column::Log {
+                column_options.set_write_buffer_size(ROCKSDB_LOG_WRITE_BUFFER_SIZE);
+                column_options.set_compression_type(DBCompressionType::Lz4);
+            }
+            ColumnFamilyDescriptor::new(column.name(), column_options)
+        });
+        let db = DB::open_cf_descriptors(&options, path, columns)
+            .map_err(|e| Error::Other(format!("rocksdb: {}", e)))?;
+        let mut write_options = WriteOptions::default();
+        // otherwise a write reaches the OS at once, but is only synced with later ones
+        write_options.set_sync(sync == SyncMode::EveryWrite);
+        Ok(RocksDBBackend { db, write_options })
+    }
+
+    fn column(&self, column: Column) -> &rocksdb::ColumnFamily {
+        self.db
+            .cf_handle(column.name())
+            .expect("Column family missing from rocksdb storage")
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl KVBackend for RocksDBBackend {
+    fn get(&self, column: Column, key: &[u8]) -> Option<Vec<u8>> {
+        self.db
+            .get_cf(self.column(column), key)
+            .expect("Failed to read rocksdb storage")
+    }
+
+    fn write(&mut self, batch: Vec<Write>) {
+        let mut rocksdb_batch = rocksdb::WriteBatch::default();
+        for write in batch {
+            match write {
+                Write::Put(column, key, value) => {
+                    rocksdb_batch.put_cf(self.column(column), key, value)
+                }
+                Write::Delete(column, key) => rocksdb_batch.delete_cf(self.column(column), key),
+            }
+        }
+        self.db
+            .write_opt(rocksdb_batch, &self.write_options)
+            .expect("Failed to write rocksdb storage");
+    }
+
+    fn range(&self, column: Column, from: &[u8], to: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mode = rocksdb::IteratorMode::From(from, rocksdb::Direction::Forward);
+        self.db
+            .iterator_cf(self.column(column), mode)
+            .take_while(|(key, _)| &key[..] < to)
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect()
+    }
+
+    fn last_key(&self, column: Column) -> Option<Vec<u8>> {
+        self.db
+            .iterator_cf(self.column(column), rocksdb::IteratorMode::End)
+            .next()
+            .map(|(key, _)| key.to_vec())
+    }
+
+    fn sync(&self) {
+        self.db.flush_wal(true).expect("Failed to sync rocksdb storage");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddbb_libs::clock::MockClock;
+    use std::env;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn entry(i: u64) -> LogEntry {
+        LogEntry::SetValue {
+            key: format!("k{}", i),
+            value: i.to_be_bytes().to_vec(),
+        }
+    }
+
+    fn entries(range: std::ops::Range<u64>) -> Vec<LogEntry> {
+        range.map(entry).collect()
+    }
+
+    /// Removed with its files once dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            TempDir(env::temp_dir().join(format!("ddbb_storage_{}", Uuid::new_v4())))
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Drops every write after the first `writes_left`, as if the node crashed then.
+    struct Failpoint<B> {
+        inner: B,
+        writes_left: usize,
+    }
+
+    impl<B: KVBackend> KVBackend for Failpoint<B> {
+        fn get(&self, column: Column, key: &[u8]) -> Option<Vec<u8>> {
+            self.inner.get(column, key)
+        }
+
+        fn write(&mut self, batch: Vec<Write>) {
+            if self.writes_left > 0 {
+                self.writes_left -= 1;
+                self.inner.write(batch);
+            }
+        }
+
+        fn range(&self, column: Column, from: &[u8], to: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+            self.inner.range(column, from, to)
+        }
+
+        fn last_key(&self, column: Column) -> Option<Vec<u8>> {
+            self.inner.last_key(column)
+        }
+    }
+
+    /// A call of the storage api, each one a single write of `KVStorage`.
+    enum Op {
+        Promise(Ballot),
+        AcceptRound(Ballot),
+        Append(Vec<LogEntry>),
+        AppendOnPrefix(u64, Vec<LogEntry>),
+        Decide(u64),
+        Trim(u64),
+        Snapshot(Snapshot),
+        Compact(u64),
+        StopSign(StopSign),
+    }
+
+    impl Op {
+        fn apply(self, storage: &mut dyn Storage<LogEntry, Snapshot>) {
+            match self {
+                Op::Promise(ballot) => storage.set_promise(ballot),
+                Op::AcceptRound(ballot) => storage.set_accepted_round(ballot),
+                Op::Append(entries) => {
+                    storage.append_entries(entries);
+                }
+                Op::AppendOnPrefix(from_idx, entries) => {
+                    storage.append_on_prefix(from_idx, entries);
+                }
+                Op::Decide(idx) => storage.set_decided_idx(idx),
+                Op::Trim(idx) => storage.trim(idx),
+                Op::Snapshot(snapshot) => storage.set_snapshot(snapshot),
+                Op::Compact(idx) => storage.set_compacted_idx(idx),
+                Op::StopSign(stopsign) => storage.set_stopsign(StopSignEntry::with(stopsign, true)),
+            }
+        }
+    }
+
+    /// A replica promising, accepting and deciding in two rounds, the second leader
+    /// overwriting the undecided tail, then compacting as `InternalStorage` does.
+    fn workload() -> Vec<Op> {
+        let (first, second) = (Ballot::with(1, 0, 1), Ballot::with(2, 0, 2));
+        vec![
+            Op::Promise(first),
+            Op::AcceptRound(first),
+            Op::Append(entries(0..3)),
+            Op::Decide(2),
+            Op::Append(entries(3..5)),
+            Op::Promise(second),
+            Op::AcceptRound(second),
+            Op::AppendOnPrefix(3, entries(10..12)),
+            Op::Decide(5),
+            Op::Trim(2),
+            Op::Snapshot(Snapshot {
+                logs: entries(0..2),
+            }),
+            Op::Compact(2),
+            Op::StopSign(StopSign::with(2, vec![1, 2], None)),
+        ]
+    }
+
+    /// Everything a storage returns, to compare storages by.
+    #[derive(Debug, PartialEq)]
+    struct Observed {
+        log: Vec<LogEntry>,
+        promise: Ballot,
+        accepted_round: Ballot,
+        decided_idx: u64,
+        compacted_idx: u64,
+        snapshot: Option<Snapshot>,
+        stopsign: Option<(StopSign, bool)>,
+    }
+
+    fn observe(storage: &dyn Storage<LogEntry, Snapshot>) -> Observed {
+        Observed {
+            log: storage.get_suffix(0),
+            promise: storage.get_promise(),
+            accepted_round: storage.get_accepted_round(),
+            decided_idx: storage.get_decided_idx(),
+            compacted_idx: storage.get_compacted_idx(),
+            snapshot: storage.get_snapshot(),
+            stopsign: storage.get_stopsign().map(|s| (s.stopsign, s.decided)),
+        }
+    }
+
+    /// The behavior every backend has to share with `MemoryStorage`.
+    fn check_storage(storage: &mut dyn Storage<LogEntry, Snapshot>) {
+        assert_eq!(storage.get_log_len(), 0);
+        assert_eq!(storage.append_entry(entry(0)), 1);
+        assert_eq!(storage.append_entries(entries(1..5)), 5);
+        assert_eq!(storage.get_entries(1, 3), entries(1..3));
+        assert_eq!(storage.get_suffix(3), entries(3..5));
+        // out of bounds reads are empty
+        assert!(storage.get_entries(4, 9).is_empty());
+        assert!(storage.get_suffix(9).is_empty());
+
+        // a new leader overwrites the tail
+        assert_eq!(storage.append_on_prefix(2, entries(10..11)), 3);
+        assert_eq!(storage.get_suffix(0), vec![entry(0), entry(1), entry(10)]);
+
+        // indices are relative to the entries kept
+        storage.trim(2);
+        assert_eq!(storage.get_log_len(), 1);
+        assert_eq!(storage.get_suffix(0), vec![entry(10)]);
+        assert_eq!(storage.append_entries(entries(11..13)), 3);
+        assert_eq!(storage.get_entries(1, 3), entries(11..13));
+
+        let mut expected = Observed {
+            log: entries(10..13),
+            promise: Ballot::with(2, 0, 1),
+            accepted_round: Ballot::with(2, 0, 1),
+            decided_idx: 3,
+            compacted_idx: 2,
+            snapshot: None,
+            stopsign: None,
+        };
+        storage.set_promise(expected.promise);
+        storage.set_accepted_round(expected.accepted_round);
+        storage.set_decided_idx(expected.decided_idx);
+        storage.set_compacted_idx(expected.compacted_idx);
+        assert_eq!(observe(storage), expected);
+
+        let stopsign = StopSign::with(2, vec![1, 2], None);
+        storage.set_stopsign(StopSignEntry::with(stopsign.clone(), true));
+        expected.stopsign = Some((stopsign, true));
+        let snapshot = Snapshot {
+            logs: entries(0..2),
+        };
+        storage.set_snapshot(snapshot.clone());
+        expected.snapshot = Some(snapshot);
+        assert_eq!(observe(storage), expected);
+    }
+
+    /// The workload crashing after every number of writes recovers exactly the writes
+    /// done until then, as `MemoryStorage` applying only those.
+    fn check_crash_recovery<B: KVBackend>(open: impl Fn(&Path) -> B) {
+        let ops = workload().len();
+        for crash_at in 0..=ops {
+            let mut reference = DDBBStorage::memory();
+            for op in workload().into_iter().take(crash_at) {
+                op.apply(&mut reference);
+            }
+
+            let dir = TempDir::new();
+            let failpoint = Failpoint {
+                inner: open(dir.path()),
+                writes_left: crash_at,
+            };
+            let mut storage = KVStorage::open(failpoint, Duration::ZERO);
+            for op in workload() {
+                op.apply(&mut storage);
+            }
+            drop(storage);
+
+            let recovered = KVStorage::open(open(dir.path()), Duration::ZERO);
+            let observed = observe(&recovered);
+            assert_eq!(
+                observed,
+                observe(&reference),
+                "crash after {} writes",
+                crash_at
+            );
+            // a replica never accepted a round it did not promise
+            assert!(observed.accepted_round <= observed.promise);
+        }
+    }
+
+    /// Writes held back until a flush are read back, then written as one batch.
+    fn check_batching<B: KVBackend>(open: impl Fn(&Path) -> B) {
+        let mut reference = DDBBStorage::memory();
+        for op in workload() {
+            op.apply(&mut reference);
+        }
+
+        let dir = TempDir::new();
+        // any write after the first one is lost
+        let failpoint = Failpoint {
+            inner: open(dir.path()),
+            writes_left: 1,
+        };
+        let mut storage = KVStorage::open(failpoint, Duration::from_secs(3600));
+        for op in workload() {
+            op.apply(&mut storage);
+        }
+        assert_eq!(observe(&storage), observe(&reference));
+        storage.flush();
+        drop(storage);
+
+        let recovered = KVStorage::open(open(dir.path()), Duration::ZERO);
+        assert_eq!(observe(&recovered), observe(&reference));
+    }
+
+    /// Conformance tests of a storage, `$open` returning an empty one.
+    macro_rules! storage_conformance {
+        ($name:ident, $open:expr) => {
+            mod $name {
+                use super::*;
+
+                #[test]
+                fn test_storage_round_trip() {
+                    check_storage(&mut $open);
+                }
+            }
+        };
+    }
+
+    /// Conformance tests of a persistent backend, `$open` opening it at a path, also
+    /// to recover it after a crash.
+    macro_rules! backend_conformance {
+        ($name:ident, $open:expr) => {
+            mod $name {
+                use super::*;
+
+                #[test]
+                fn test_storage_round_trip() {
+                    let dir = TempDir::new();
+                    check_storage(&mut KVStorage::open($open(dir.path()), Duration::ZERO));
+                    let mut reference = DDBBStorage::memory();
+                    check_storage(&mut reference);
+                    let reopened = KVStorage::open($open(dir.path()), Duration::ZERO);
+                    assert_eq!(observe(&reopened), observe(&reference));
+                }
+
+                #[test]
+                fn test_storage_crash_recovery() {
+                    check_crash_recovery($open);
+                }
+
+                #[test]
+                fn test_storage_batching() {
+                    check_batching($open);
+                }
+            }
+        };
+    }
+
+    #[test]
+    fn test_sync_mode() {
+        assert_eq!("always".parse(), Ok(SyncMode::EveryWrite));
+        assert_eq!(
+            "interval:100".parse(),
+            Ok(SyncMode::Periodic(Duration::from_millis(100)))
+        );
+        assert_eq!("never".parse(), Ok(SyncMode::Never));
+        assert!("interval".parse::<SyncMode>().is_err());
+        assert!("sometimes".parse::<SyncMode>().is_err());
+    }
+
+    mod segment_storage {
+        use super::*;
+
+        // two entries a segment, so that they roll over and are recycled
+        const OPTIONS: SegmentOptions = SegmentOptions {
+            segment_size: 128,
+            spares: 1,
+        };
+
+        fn open(dir: &Path, max_batch_latency: Duration) -> SegmentStorage {
+            SegmentStorage::open(dir, OPTIONS, SyncMode::EveryWrite, max_batch_latency).unwrap()
+        }
+
+        #[test]
+        fn test_storage_round_trip() {
+            let dir = TempDir::new();
+            check_storage(&mut open(dir.path(), Duration::ZERO));
+            let mut reference = DDBBStorage::memory();
+            check_storage(&mut reference);
+            let reopened = open(dir.path(), Duration::ZERO);
+            assert_eq!(observe(&reopened), observe(&reference));
+        }
+
+        #[test]
+        fn test_storage_batching() {
+            let mut reference = DDBBStorage::memory();
+            for op in workload() {
+                op.apply(&mut reference);
+            }
+
+            let dir = TempDir::new();
+            let mut storage = open(dir.path(), Duration::from_secs(3600));
+            for op in workload() {
+                op.apply(&mut storage);
+            }
+            assert_eq!(observe(&storage), observe(&reference));
+            // nothing written before the flush
+            assert!(!dir.path().join(SEGMENT_META_FILE).exists());
+            storage.flush();
+            drop(storage);
+
+            let recovered = open(dir.path(), Duration::ZERO);
+            assert_eq!(observe(&recovered), observe(&reference));
+        }
+
+        #[test]
+        fn test_storage_corruption() {
+            let dir = TempDir::new();
+            let mut storage = open(dir.path(), Duration::ZERO);
+            storage.append_entries(entries(0..6));
+            storage.set_decided_idx(6);
+            drop(storage);
+
+            // the first record damaged, the log is cut short before it
+            let first = dir.path().join(format!("{:020}.log", 0));
+            let mut bytes = fs::read(&first).unwrap();
+            bytes[20] ^= 1;
+            fs::write(&first, bytes).unwrap();
+            let storage = open(dir.path(), Duration::ZERO);
+            assert_eq!(storage.log.corruption().map(|c| c.position), Some(0));
+            assert_eq!((storage.get_log_len(), storage.get_decided_idx()), (0, 0));
+            drop(storage);
+            assert!(open(dir.path(), Duration::ZERO).log.corruption().is_none());
+
+            // the replica state is not loaded damaged
+            let meta = dir.path().join(SEGMENT_META_FILE);
+            let mut bytes = fs::read(&meta).unwrap();
+            *bytes.last_mut().unwrap() ^= 1;
+            fs::write(&meta, bytes).unwrap();
+            let opened =
+                SegmentStorage::open(dir.path(), OPTIONS, SyncMode::EveryWrite, Duration::ZERO);
+            assert!(opened.is_err());
+        }
+
+        #[tokio::test]
+        async fn test_storage_periodic_sync() {
+            let dir = TempDir::new();
+            let sync = SyncMode::Periodic(Duration::from_secs(1));
+            let mut storage = SegmentStorage::open(dir.path(), OPTIONS, sync, Duration::ZERO).unwrap();
+            storage.append_entries(entries(0..2));
+            // written, the period not over yet
+            assert!(storage.unsynced());
+            let flusher = DDBBStorage::with(storage, sync).flusher();
+            let clock = MockClock::new(0);
+            let syncs = tokio::spawn(flusher.clone().sync_periodically(clock.shared()));
+
+            // no further write, synced once the period passed
+            while flusher.unsynced() {
+                clock.advance(Duration::from_millis(100));
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            assert!(clock.elapsed() >= Duration::from_secs(1));
+            syncs.abort();
+        }
+    }
+
+    storage_conformance!(memory, DDBBStorage::memory());
+    backend_conformance!(sled_backend, |path: &Path| {
+        SledBackend::open(path, SyncMode::EveryWrite).unwrap()
+    });
+    #[cfg(feature = "rocksdb")]
+    backend_conformance!(rocksdb_backend, |path: &Path| {
+        RocksDBBackend::open(path, SyncMode::EveryWrite).unwrap()
+    });
+}
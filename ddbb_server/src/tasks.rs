@@ -0,0 +1,42 @@
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+
+/// #Descriptions: spawn `future` as a task named `name`, e.g. `omni_simo sender to 2`,
+/// which tokio-console shows next to its poll times. Tasks are only named in builds with
+/// the `console` feature and `--cfg tokio_unstable`, both of which tokio-console needs.
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "console"))]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("spawn task");
+    #[cfg(not(all(tokio_unstable, feature = "console")))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// #Descriptions: serve the tasks of the runtime to tokio-console, on the address in
+/// `TOKIO_CONSOLE_BIND` (default `127.0.0.1:6669`). Does nothing without the `console`
+/// feature; call it before the runtime spawns the tasks to inspect.
+pub fn init_console() {
+    #[cfg(feature = "console")]
+    console_subscriber::init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spawn_named() {
+        let task = spawn_named("test task", async { 1 + 1 });
+        assert_eq!(task.await.unwrap(), 2);
+    }
+}
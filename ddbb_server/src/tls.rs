@@ -0,0 +1,158 @@
+use tokio::net::TcpStream;
+
+use ddbb_libs::connection::Connection;
+use ddbb_libs::Result;
+
+#[cfg(feature = "tls")]
+use ddbb_libs::connection::Stream;
+#[cfg(feature = "tls")]
+use std::{fs::File, io::BufReader, sync::Arc};
+#[cfg(feature = "tls")]
+use tokio_rustls::{
+    rustls::{
+        server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, PrivateKey,
+        RootCertStore, ServerConfig, ServerName,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+/// Where the certificates the peers authenticate each other with are, see `--tls-cert`.
+#[derive(Clone, Debug)]
+pub struct TlsPaths {
+    /// the certificate chain of this node, in PEM
+    pub cert: String,
+    /// the private key of the certificate, PKCS#8 in PEM
+    pub key: String,
+    /// the certificate authority that signed the certificates of every node, in PEM
+    pub ca: String,
+    /// the name the certificates of the nodes are issued for, checked on connect
+    pub server_name: String,
+}
+
+/// The TLS sessions between the peers: each side presents its certificate, and only
+/// the nodes with one signed by the CA of the cluster are accepted. Needs ddbb_server
+/// built with `--features tls`.
+#[derive(Clone)]
+pub struct PeerTls {
+    #[cfg(feature = "tls")]
+    acceptor: TlsAcceptor,
+    #[cfg(feature = "tls")]
+    connector: TlsConnector,
+    #[cfg(feature = "tls")]
+    server_name: ServerName,
+}
+
+impl std::fmt::Debug for PeerTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PeerTls").finish()
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Stream for tokio_rustls::server::TlsStream<TcpStream> {
+    fn tcp_stream(&self) -> &TcpStream {
+        self.get_ref().0
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Stream for tokio_rustls::client::TlsStream<TcpStream> {
+    fn tcp_stream(&self) -> &TcpStream {
+        self.get_ref().0
+    }
+}
+
+#[cfg(feature = "tls")]
+fn read_certs(path: &str) -> Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", path).into());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+#[cfg(feature = "tls")]
+fn read_key(path: &str) -> Result<PrivateKey> {
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))?;
+    match keys.pop() {
+        Some(key) => Ok(PrivateKey(key)),
+        None => Err(format!("no PKCS#8 private key in {}", path).into()),
+    }
+}
+
+impl PeerTls {
+    /// #Descriptions: read the certificates at `paths`.
+    #[cfg(feature = "tls")]
+    pub fn load(paths: &TlsPaths) -> Result<Self> {
+        let certs = read_certs(&paths.cert)?;
+        let key = read_key(&paths.key)?;
+        let mut roots = RootCertStore::empty();
+        for ca in read_certs(&paths.ca)? {
+            roots.add(&ca).map_err(|e| format!("{}: {}", paths.ca, e))?;
+        }
+        let server = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots.clone()))
+            .with_single_cert(certs.clone(), key.clone())
+            .map_err(|e| e.to_string())?;
+        let client = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_single_cert(certs, key)
+            .map_err(|e| e.to_string())?;
+        let server_name = ServerName::try_from(paths.server_name.as_str())
+            .map_err(|_| format!("{} is not a server name", paths.server_name))?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            connector: TlsConnector::from(Arc::new(client)),
+            server_name,
+        })
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn load(_paths: &TlsPaths) -> Result<Self> {
+        Err("ddbb_server is built without tls, build it with --features tls".into())
+    }
+
+    /// #Descriptions: the server side of the session on an accepted `tcp_stream`.
+    pub async fn accept(&self, tcp_stream: TcpStream) -> Result<Connection> {
+        #[cfg(feature = "tls")]
+        {
+            let stream = self.acceptor.accept(tcp_stream).await?;
+            Ok(Connection::with_stream(Box::new(stream)))
+        }
+        #[cfg(not(feature = "tls"))]
+        Ok(Connection::new(tcp_stream))
+    }
+
+    /// #Descriptions: the client side of the session on a `tcp_stream` connected to a peer.
+    pub async fn connect(&self, tcp_stream: TcpStream) -> Result<Connection> {
+        #[cfg(feature = "tls")]
+        {
+            let stream = self
+                .connector
+                .connect(self.server_name.clone(), tcp_stream)
+                .await?;
+            Ok(Connection::with_stream(Box::new(stream)))
+        }
+        #[cfg(not(feature = "tls"))]
+        Ok(Connection::new(tcp_stream))
+    }
+}
+
+/// #Descriptions: a connection over `tcp_stream`, in a TLS session if `tls` is set.
+pub async fn accept_with(tls: &Option<PeerTls>, tcp_stream: TcpStream) -> Result<Connection> {
+    match tls {
+        Some(tls) => tls.accept(tcp_stream).await,
+        None => Ok(Connection::new(tcp_stream)),
+    }
+}
+
+/// #Descriptions: a connection over `tcp_stream` connected to a peer, in a TLS session
+/// if `tls` is set.
+pub async fn connect_with(tls: &Option<PeerTls>, tcp_stream: TcpStream) -> Result<Connection> {
+    match tls {
+        Some(tls) => tls.connect(tcp_stream).await,
+        None => Ok(Connection::new(tcp_stream)),
+    }
+}
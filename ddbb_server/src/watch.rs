@@ -0,0 +1,607 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+use crate::config::{SLOW_WATCHERS, WATCH_QUEUE_CAPACITY};
+use crate::memory::MemoryGauge;
+use ddbb_libs::data_structure::{WatchDelivery, WatchEventEntry};
+use ddbb_libs::{Error, Result};
+
+/// What the hub does once the queue of a watch is full, its client reading the
+/// writes slower than they are applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlowWatcherPolicy {
+    /// end the watch with an `Overloaded` error telling the revision to resume after
+    Disconnect,
+    /// keep only the latest queued write of each key, in a batch marked as coalesced;
+    /// a watch of each write is still ended, its client could not tell
+    DropIntermediate,
+}
+
+/// The watches that fell behind, served with the metrics.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchStats {
+    /// watches ended because their queue was full
+    pub slow_disconnected: u64,
+    /// writes never streamed to a slow watch, for a later one to the same key
+    pub dropped_events: u64,
+}
+
+/// Writes streamed to a watch together.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WatchBatch {
+    pub events: Vec<WatchEventEntry>,
+    /// some writes were dropped for a later one to the same key
+    pub coalesced: bool,
+}
+
+#[derive(Debug, Default)]
+struct WatchQueue {
+    batches: VecDeque<WatchBatch>,
+    /// writes in `batches`
+    events: usize,
+    /// revision of the latest write read from the queue
+    read_rev: u64,
+    /// the queue was full, the watch ends once it reads this
+    overflowed: bool,
+}
+
+/// The queue of a watch, filled by the hub.
+#[derive(Debug, Default)]
+struct SharedQueue {
+    queue: Mutex<WatchQueue>,
+    notify: Notify,
+}
+
+/// A watch just established: the retained events it missed, then the live ones,
+/// a batch per batch of decided logs applied.
+#[derive(Debug)]
+pub struct Watch {
+    /// revision of the latest write when the watch was established
+    pub revision: u64,
+    pub replay: Vec<WatchEventEntry>,
+    shared: Arc<SharedQueue>,
+    /// bytes of the events queued, shared by all the watches of the hub
+    queued: MemoryGauge,
+}
+
+impl Watch {
+    /// #Descriptions: the next batch of live events, once there is one. Fails with
+    /// `Overloaded` once the watch fell too far behind.
+    pub async fn next_batch(&mut self) -> Result<WatchBatch> {
+        loop {
+            if let Some(batch) = self.try_next_batch()? {
+                return Ok(batch);
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// #Descriptions: the next batch of live events, if one is queued.
+    pub fn try_next_batch(&mut self) -> Result<Option<WatchBatch>> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.overflowed {
+            return Err(Error::Overloaded(format!(
+                "watch too slow, resume after revision {}",
+                queue.read_rev
+            )));
+        }
+        let batch = match queue.batches.pop_front() {
+            Some(batch) => batch,
+            None => return Ok(None),
+        };
+        queue.events -= batch.events.len();
+        if let Some(last) = batch.events.last() {
+            queue.read_rev = last.revision;
+        }
+        self.queued.sub(batch_size(&batch.events));
+        Ok(Some(batch))
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        // the events the client never read are freed with the queue
+        let queue = self.shared.queue.lock().unwrap();
+        for batch in queue.batches.iter() {
+            self.queued.sub(batch_size(&batch.events));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Watcher {
+    prefix: String,
+    shared: Arc<SharedQueue>,
+    /// the client asked for each write, none may be dropped
+    each: bool,
+    /// the events published since the last flush
+    pending: Vec<WatchEventEntry>,
+}
+
+impl Watcher {
+    /// #Descriptions: queue the `pending` events as a batch, holding the queue to
+    /// `capacity` writes as `policy` tells. False if the watch ends.
+    fn enqueue(
+        &mut self,
+        capacity: usize,
+        policy: SlowWatcherPolicy,
+        queued: &MemoryGauge,
+        stats: &mut WatchStats,
+    ) -> bool {
+        let events = std::mem::take(&mut self.pending);
+        let mut queue = self.shared.queue.lock().unwrap();
+        queued.add(batch_size(&events));
+        queue.events += events.len();
+        queue.batches.push_back(WatchBatch {
+            events,
+            coalesced: false,
+        });
+        let full = |queue: &WatchQueue| capacity != 0 && queue.events > capacity;
+        if full(&queue) && policy == SlowWatcherPolicy::DropIntermediate && !self.each {
+            // all the queued writes as one batch of the latest write of each key
+            let mut coalesced = false;
+            let mut events = Vec::new();
+            for batch in queue.batches.drain(..) {
+                coalesced |= batch.coalesced;
+                events.extend(batch.events);
+            }
+            queued.sub(batch_size(&events));
+            let total = events.len();
+            let (latest, dropped) = coalesce(events);
+            stats.dropped_events += (total - latest.len()) as u64;
+            queued.add(batch_size(&latest));
+            queue.events = latest.len();
+            queue.batches.push_back(WatchBatch {
+                events: latest,
+                coalesced: coalesced || dropped,
+            });
+        }
+        let overflowed = full(&queue);
+        if overflowed {
+            for batch in queue.batches.drain(..) {
+                queued.sub(batch_size(&batch.events));
+            }
+            queue.events = 0;
+            queue.overflowed = true;
+            stats.slow_disconnected += 1;
+        }
+        drop(queue);
+        self.shared.notify.notify_one();
+        !overflowed
+    }
+}
+
+/// A retained write, with the value it replaced.
+#[derive(Debug)]
+struct Write {
+    event: WatchEventEntry,
+    /// `None` if the key did not exist
+    prev: Option<Vec<u8>>,
+}
+
+/// The latest writes applied, oldest dropped first, so that a client resuming a
+/// watch after a disconnect gets the events it missed, and reads can be served
+/// as of a revision since.
+#[derive(Debug)]
+pub struct WatchHub {
+    capacity: usize,
+    history: VecDeque<Write>,
+    /// the events up to this revision are no longer retained
+    compacted_rev: u64,
+    /// revision of the latest write
+    revision: u64,
+    watchers: Vec<Watcher>,
+    /// bytes of the events sent to the watches and not read yet
+    queued: MemoryGauge,
+    /// writes queued at most for a watch, 0 for no limit, and what happens beyond
+    queue_capacity: usize,
+    slow_watchers: SlowWatcherPolicy,
+    stats: WatchStats,
+}
+
+impl WatchHub {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            history: VecDeque::new(),
+            compacted_rev: 0,
+            revision: 0,
+            watchers: Vec::new(),
+            queued: MemoryGauge::default(),
+            queue_capacity: WATCH_QUEUE_CAPACITY,
+            slow_watchers: SLOW_WATCHERS,
+            stats: WatchStats::default(),
+        }
+    }
+
+    /// #Descriptions: queue at most `capacity` writes for a watch, 0 for no limit,
+    /// treating the watches beyond as `policy` tells.
+    pub fn set_slow_watchers(&mut self, capacity: usize, policy: SlowWatcherPolicy) {
+        self.queue_capacity = capacity;
+        self.slow_watchers = policy;
+    }
+
+    pub fn stats(&self) -> WatchStats {
+        self.stats.clone()
+    }
+
+    /// Bytes of the events streamed to the watches that their clients have not read yet.
+    pub fn queued_bytes(&self) -> u64 {
+        self.queued.get()
+    }
+
+    /// #Descriptions: the state machine was replaced at `revision`, e.g. restored from
+    /// a snapshot, so none of the events before are retained.
+    pub fn reset(&mut self, revision: u64) {
+        self.history.clear();
+        self.compacted_rev = revision;
+        self.revision = revision;
+    }
+
+    /// #Descriptions: drop the events up to `revision`, so that reads at an older
+    /// revision and watches resuming before it fail as compacted.
+    pub fn compact(&mut self, revision: u64) {
+        if revision <= self.compacted_rev {
+            return;
+        }
+        while let Some(write) = self.history.front() {
+            if write.event.revision > revision {
+                break;
+            }
+            self.history.pop_front();
+        }
+        self.compacted_rev = revision;
+    }
+
+    /// #Descriptions: retain `event` and queue it for the watchers until the next
+    /// `flush`, `prev` is the value of the key before it.
+    pub fn publish(&mut self, event: WatchEventEntry, prev: Option<Vec<u8>>) {
+        self.revision = event.revision;
+        for watcher in self.watchers.iter_mut() {
+            if matches(&watcher.prefix, &event.key) {
+                watcher.pending.push(event.clone());
+            }
+        }
+        if self.capacity == 0 {
+            self.compacted_rev = event.revision;
+            return;
+        }
+        if self.history.len() >= self.capacity {
+            if let Some(dropped) = self.history.pop_front() {
+                self.compacted_rev = dropped.event.revision;
+            }
+        }
+        self.history.push_back(Write { event, prev });
+    }
+
+    /// #Descriptions: stream the events published since the last flush to the watchers,
+    /// as one batch each, once a batch of decided logs was applied.
+    pub fn flush(&mut self) {
+        for mut watcher in std::mem::take(&mut self.watchers) {
+            // the watch is gone with its connection
+            if Arc::strong_count(&watcher.shared) == 1 {
+                continue;
+            }
+            if !watcher.pending.is_empty() {
+                let (capacity, policy) = (self.queue_capacity, self.slow_watchers);
+                if !watcher.enqueue(capacity, policy, &self.queued, &mut self.stats) {
+                    continue;
+                }
+            }
+            self.watchers.push(watcher);
+        }
+    }
+
+    /// The writes after `revision` are all retained, and it was reached.
+    fn check_revision(&self, revision: u64) -> Result<()> {
+        if revision < self.compacted_rev {
+            return Err(Error::Compacted(format!(
+                "revision {} compacted, oldest readable {}",
+                revision, self.compacted_rev
+            )));
+        }
+        if revision > self.revision {
+            return Err(Error::Unavailable(format!(
+                "revision {} not applied yet, at {}",
+                revision, self.revision
+            )));
+        }
+        Ok(())
+    }
+
+    /// #Descriptions: the value of `key` at `revision`, given its `current` one: the
+    /// value replaced by the first write to it after `revision`, if any.
+    pub fn value_at(
+        &self,
+        key: &str,
+        revision: u64,
+        current: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.check_revision(revision)?;
+        let first_after = self
+            .history
+            .iter()
+            .find(|write| write.event.revision > revision && write.event.key == key);
+        Ok(match first_after {
+            Some(write) => write.prev.clone(),
+            None => current,
+        })
+    }
+
+    /// #Descriptions: the keys under `prefix` at `revision`, in order, given the
+    /// `current` ones.
+    pub fn scan_at(
+        &self,
+        prefix: &str,
+        revision: u64,
+        current: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        self.check_revision(revision)?;
+        let mut keys: BTreeMap<String, Option<Vec<u8>>> = current
+            .into_iter()
+            .map(|(key, value)| (key, Some(value)))
+            .collect();
+        // the newest first, so that the first write after `revision` is taken last
+        for write in self.history.iter().rev() {
+            if write.event.revision <= revision {
+                break;
+            }
+            if matches(prefix, &write.event.key) {
+                keys.insert(write.event.key.clone(), write.prev.clone());
+            }
+        }
+        Ok(keys
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect())
+    }
+
+    /// #Descriptions: watch the keys under `prefix`, replaying the retained events after
+    /// `after_revision`, to stream as `delivery` tells. Fails if some of them are no
+    /// longer retained.
+    pub fn watch(
+        &mut self,
+        prefix: &str,
+        after_revision: u64,
+        delivery: WatchDelivery,
+    ) -> Result<Watch> {
+        if after_revision != 0 && after_revision < self.compacted_rev {
+            return Err(Error::Compacted(format!(
+                "revision {} compacted, oldest retained {}",
+                after_revision,
+                self.compacted_rev + 1
+            )));
+        }
+        let replay = match after_revision {
+            0 => Vec::new(),
+            _ => self
+                .history
+                .iter()
+                .map(|write| &write.event)
+                .filter(|event| event.revision > after_revision && matches(prefix, &event.key))
+                .cloned()
+                .collect(),
+        };
+        let shared = Arc::new(SharedQueue::default());
+        // the replay ends at the current revision
+        shared.queue.lock().unwrap().read_rev = self.revision;
+        self.watchers.push(Watcher {
+            prefix: prefix.to_string(),
+            shared: shared.clone(),
+            each: delivery == WatchDelivery::Each,
+            pending: Vec::new(),
+        });
+        Ok(Watch {
+            revision: self.revision,
+            replay,
+            shared,
+            queued: self.queued.clone(),
+        })
+    }
+}
+
+fn batch_size(batch: &[WatchEventEntry]) -> u64 {
+    batch
+        .iter()
+        .map(|event| (event.key.len() + event.value.len()) as u64)
+        .sum()
+}
+
+/// #Descriptions: the latest of the `events` to each key, in revision order, and
+/// whether any was left out.
+pub fn coalesce(events: Vec<WatchEventEntry>) -> (Vec<WatchEventEntry>, bool) {
+    let total = events.len();
+    let mut latest: HashMap<String, WatchEventEntry> = HashMap::new();
+    for event in events {
+        latest.insert(event.key.clone(), event);
+    }
+    let mut events: Vec<WatchEventEntry> = latest.into_values().collect();
+    events.sort_by_key(|event| event.revision);
+    let coalesced = events.len() < total;
+    (events, coalesced)
+}
+
+/// The reserved keys of namespaces and settings are never watched.
+fn matches(prefix: &str, key: &str) -> bool {
+    key.starts_with(prefix) && !key.starts_with('\u{0}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn event(revision: u64, key: &str) -> WatchEventEntry {
+        WatchEventEntry {
+            revision,
+            key: key.to_string(),
+            value: Bytes::from("v"),
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_watch_resumes_from_revision() {
+        let mut hub = WatchHub::new(3);
+        hub.publish(event(1, "a/1"), None);
+        hub.publish(event(2, "b/1"), None);
+        hub.publish(event(3, "a/2"), None);
+
+        let mut watch = hub.watch("a/", 1, WatchDelivery::Each).unwrap();
+        assert_eq!(watch.revision, 3);
+        assert_eq!(watch.replay, vec![event(3, "a/2")]);
+        hub.publish(event(4, "a/3"), None);
+        hub.publish(event(5, "\u{0}config/read_mode"), None);
+        // nothing is streamed before the batch is flushed
+        assert_eq!(watch.try_next_batch().unwrap(), None);
+        hub.flush();
+        let batch = watch.try_next_batch().unwrap().unwrap();
+        assert_eq!(batch.events, vec![event(4, "a/3")]);
+        assert_eq!(watch.try_next_batch().unwrap(), None);
+
+        // revision 2 was dropped, a client that only saw revision 1 missed it
+        assert!(matches!(hub.watch("a/", 1, WatchDelivery::Each), Err(Error::Compacted(_))));
+        assert!(hub.watch("a/", 2, WatchDelivery::Each).is_ok());
+
+        hub.reset(10);
+        assert!(matches!(hub.watch("a/", 5, WatchDelivery::Each), Err(Error::Compacted(_))));
+        // the events a watch never read are freed with it
+        let watch = hub.watch("c/", 0, WatchDelivery::Each).unwrap();
+        hub.publish(event(11, "c/1"), None);
+        hub.flush();
+        assert_eq!(hub.queued_bytes(), 4);
+        drop(watch);
+        assert_eq!(hub.queued_bytes(), 0);
+        assert!(hub.watch("a/", 0, WatchDelivery::Each).unwrap().replay.is_empty());
+    }
+
+    #[test]
+    fn test_batches_coalesced() {
+        let mut hub = WatchHub::new(10);
+        let mut watch = hub.watch("a/", 0, WatchDelivery::Each).unwrap();
+        hub.publish(event(1, "a/1"), None);
+        hub.publish(event(2, "a/2"), None);
+        hub.publish(event(3, "a/1"), None);
+        hub.flush();
+        let batch = watch.try_next_batch().unwrap().unwrap();
+        assert_eq!(batch.events.len(), 3);
+
+        let (latest, coalesced) = coalesce(batch.events);
+        assert_eq!(latest, vec![event(2, "a/2"), event(3, "a/1")]);
+        assert!(coalesced);
+        assert!(!coalesce(vec![event(4, "a/3")]).1);
+    }
+
+    #[test]
+    fn test_slow_watchers() {
+        let mut hub = WatchHub::new(10);
+        hub.set_slow_watchers(2, SlowWatcherPolicy::DropIntermediate);
+        let mut latest = hub.watch("a/", 0, WatchDelivery::Latest).unwrap();
+        let mut each = hub.watch("a/", 0, WatchDelivery::Each).unwrap();
+        hub.publish(event(1, "a/1"), None);
+        hub.flush();
+        let batch = each.try_next_batch().unwrap().unwrap();
+        assert_eq!(batch.events, vec![event(1, "a/1")]);
+        for (revision, key) in [(2, "a/1"), (3, "a/2"), (4, "a/1")] {
+            hub.publish(event(revision, key), None);
+            hub.flush();
+        }
+
+        // the intermediate write to a/1 is dropped, the watch keeps up
+        let batch = latest.try_next_batch().unwrap().unwrap();
+        assert_eq!(batch.events, vec![event(3, "a/2"), event(4, "a/1")]);
+        assert!(batch.coalesced);
+        assert_eq!(latest.try_next_batch().unwrap(), None);
+        // a watch of each write resumes after the last one it read
+        assert!(matches!(
+            each.try_next_batch(),
+            Err(Error::Overloaded(msg)) if msg.ends_with("revision 1")
+        ));
+        assert_eq!(
+            hub.stats(),
+            WatchStats {
+                slow_disconnected: 1,
+                dropped_events: 2,
+            }
+        );
+
+        hub.set_slow_watchers(1, SlowWatcherPolicy::Disconnect);
+        hub.publish(event(5, "a/3"), None);
+        hub.publish(event(6, "a/4"), None);
+        hub.flush();
+        assert!(matches!(
+            latest.try_next_batch(),
+            Err(Error::Overloaded(msg)) if msg.ends_with("revision 4")
+        ));
+        assert_eq!(hub.queued_bytes(), 0);
+        assert!(hub.watchers.is_empty());
+    }
+
+    #[test]
+    fn test_read_at_revision() {
+        let mut hub = WatchHub::new(10);
+        let write = |revision, key: &str, value: &str| WatchEventEntry {
+            revision,
+            key: key.to_string(),
+            value: Bytes::from(value.to_string()),
+            deleted: false,
+        };
+        hub.publish(write(1, "a/1", "v1"), None);
+        hub.publish(write(2, "a/2", "v2"), None);
+        hub.publish(write(3, "a/1", "v3"), Some(Vec::from("v1")));
+        let current = || {
+            vec![
+                ("a/1".to_string(), Vec::from("v3")),
+                ("a/2".to_string(), Vec::from("v2")),
+            ]
+        };
+
+        assert_eq!(
+            hub.value_at("a/1", 2, Some(Vec::from("v3"))).unwrap(),
+            Some(Vec::from("v1"))
+        );
+        assert_eq!(hub.value_at("a/2", 1, Some(Vec::from("v2"))).unwrap(), None);
+        assert_eq!(
+            hub.scan_at("a/", 1, current()).unwrap(),
+            vec![("a/1".to_string(), Vec::from("v1"))]
+        );
+        assert_eq!(hub.scan_at("a/", 3, current()).unwrap(), current());
+        assert!(matches!(
+            hub.value_at("a/1", 4, None),
+            Err(Error::Unavailable(_))
+        ));
+        // a deleted key is still read before its deletion
+        hub.publish(
+            WatchEventEntry {
+                deleted: true,
+                ..write(4, "a/2", "")
+            },
+            Some(Vec::from("v2")),
+        );
+        let after_delete = vec![("a/1".to_string(), Vec::from("v3"))];
+        assert_eq!(hub.scan_at("a/", 3, after_delete).unwrap(), current());
+        hub.reset(3);
+        assert!(matches!(
+            hub.scan_at("a/", 2, current()),
+            Err(Error::Compacted(_))
+        ));
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut hub = WatchHub::new(10);
+        for revision in 1..=4 {
+            hub.publish(event(revision, "a/1"), Some(Vec::from("v")));
+        }
+        hub.compact(2);
+        assert!(matches!(hub.value_at("a/1", 1, None), Err(Error::Compacted(_))));
+        assert_eq!(hub.value_at("a/1", 2, None).unwrap(), Some(Vec::from("v")));
+        let watch = hub.watch("a/", 2, WatchDelivery::Each).unwrap();
+        assert_eq!(watch.replay, vec![event(3, "a/1"), event(4, "a/1")]);
+        // an older revision compacts nothing
+        hub.compact(1);
+        assert!(hub.watch("a/", 2, WatchDelivery::Each).is_ok());
+    }
+}
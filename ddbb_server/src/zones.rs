@@ -0,0 +1,148 @@
+use omnipaxos_core::util::{GroupedQuorum, NodeId};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::PRIMARY_ZONE_PRIORITY;
+use ddbb_libs::Result;
+
+/// #Descriptions: the zones, e.g. regions or data centers, the nodes of a
+/// geo-distributed cluster run in. The nodes of the primary zone are preferred as
+/// leader, clients in a zone read from the nodes of their own, and the quorums may be
+/// taken over the zones to survive losing a whole one.
+#[derive(Clone, Debug, Default)]
+pub struct Zones {
+    zone: Option<String>,
+    peers: HashMap<NodeId, String>,
+    primary: Option<String>,
+}
+
+impl Zones {
+    /// #Descriptions: the zone of this node and those of `peer_ids`, in their order; no
+    /// zones leaves every node alike.
+    pub fn new(
+        zone: Option<String>,
+        peer_ids: &[NodeId],
+        peer_zones: &[String],
+        primary: Option<String>,
+    ) -> Result<Self> {
+        if !peer_zones.is_empty() && peer_zones.len() != peer_ids.len() {
+            return Err(format!(
+                "{} peer zones given for {} peers",
+                peer_zones.len(),
+                peer_ids.len()
+            )
+            .into());
+        }
+        if primary.is_some() && zone.is_none() {
+            return Err("a primary zone needs the zone of this node".into());
+        }
+        Ok(Zones {
+            zone,
+            peers: peer_ids
+                .iter()
+                .copied()
+                .zip(peer_zones.iter().cloned())
+                .collect(),
+            primary,
+        })
+    }
+
+    pub fn zone(&self) -> Option<&str> {
+        self.zone.as_deref()
+    }
+
+    pub fn zone_of(&self, peer: NodeId) -> Option<&str> {
+        self.peers.get(&peer).map(|zone| zone.as_str())
+    }
+
+    /// #Descriptions: the voters among `node_id` and `peer_ids` grouped by zone, every
+    /// one of them needs a zone. A quorum is then a majority within a majority of the
+    /// zones.
+    pub fn quorum(
+        &self,
+        node_id: NodeId,
+        peer_ids: &[NodeId],
+        learners: &[NodeId],
+    ) -> Result<GroupedQuorum> {
+        let mut groups: BTreeMap<&str, Vec<NodeId>> = BTreeMap::new();
+        let voters = std::iter::once(node_id).chain(peer_ids.iter().copied());
+        for id in voters.filter(|id| !learners.contains(id)) {
+            let zone = if id == node_id {
+                self.zone()
+            } else {
+                self.zone_of(id)
+            };
+            let zone = zone.ok_or(format!("a zone quorum needs the zone of node {}", id))?;
+            groups.entry(zone).or_default().push(id);
+        }
+        let groups = groups
+            .into_values()
+            .map(|mut group| {
+                group.sort_unstable();
+                group
+            })
+            .collect();
+        Ok(GroupedQuorum { groups })
+    }
+
+    /// #Descriptions: the ballot priority of this node, `PRIMARY_ZONE_PRIORITY` in the
+    /// primary zone. It breaks the ties between candidates of the same round, so a
+    /// node of the primary zone wins the elections it runs in with the others.
+    pub fn leader_priority(&self) -> u64 {
+        match (&self.zone, &self.primary) {
+            (Some(zone), Some(primary)) if zone == primary => PRIMARY_ZONE_PRIORITY,
+            _ => 0,
+        }
+    }
+
+    /// #Descriptions: the ballot priority `peer` takes from its zone, as
+    /// `leader_priority` on that node.
+    pub fn peer_priority(&self, peer: NodeId) -> u64 {
+        match (self.zone_of(peer), &self.primary) {
+            (Some(zone), Some(primary)) if zone == primary => PRIMARY_ZONE_PRIORITY,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zones() {
+        let zones = Zones::new(
+            Some("eu".to_string()),
+            &[2, 3],
+            &["us".to_string(), "eu".to_string()],
+            Some("eu".to_string()),
+        )
+        .unwrap();
+        assert_eq!(zones.leader_priority(), PRIMARY_ZONE_PRIORITY);
+        assert_eq!(zones.zone_of(2), Some("us"));
+        assert_eq!(zones.zone_of(3), Some("eu"));
+        assert_eq!((zones.peer_priority(2), zones.peer_priority(3)), (0, PRIMARY_ZONE_PRIORITY));
+
+        let secondary = Zones::new(Some("us".to_string()), &[], &[], Some("eu".to_string()));
+        assert_eq!(secondary.unwrap().leader_priority(), 0);
+        assert_eq!(Zones::default().leader_priority(), 0);
+        assert!(Zones::new(None, &[2, 3], &["us".to_string()], None).is_err());
+        assert!(Zones::new(None, &[], &[], Some("eu".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_zone_quorum() {
+        let peer_zones: Vec<String> = ["us", "eu", "ap", "us", "ap"]
+            .iter()
+            .map(|zone| zone.to_string())
+            .collect();
+        let zones = Zones::new(Some("eu".to_string()), &[2, 3, 4, 5, 6], &peer_zones, None);
+        let quorum = zones.unwrap().quorum(1, &[2, 3, 4, 5, 6], &[6]).unwrap();
+        // in the order of the zone names, the learner left out
+        assert_eq!(quorum.groups, vec![vec![4], vec![1, 3], vec![2, 5]]);
+
+        // every voter needs a zone
+        let zones = Zones::new(Some("eu".to_string()), &[2, 3], &[], None).unwrap();
+        assert!(zones.quorum(1, &[2, 3], &[]).is_err());
+        assert!(Zones::default().quorum(1, &[], &[]).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use ddbb_server::bootstrap::{check_node_ids, ClusterManifest};
+use ddbb_server::config::DATA_DIR;
+use ddbb_server::restore::{parse_remap, restore_cluster};
+use omnipaxos_core::util::NodeId;
+//StructOpt - used for getting input from the command line
+use structopt::StructOpt;
+
+/// Offline administration of the data directories of a cluster.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "ddbb-admin")]
+enum Command {
+    /// seed the data directories of a fresh cluster from a backup, after losing every
+    /// node of the old one
+    Restore {
+        /// a file written by `backup`, `export` or `--backup-dir`
+        #[structopt(long)]
+        backup: String,
+        /// the new cluster, every node must then be started with the same ones
+        #[structopt(long)]
+        cluster_id: String,
+        #[structopt(long, default_value = "1")]
+        epoch: u32,
+        /// the members of the new cluster and their addresses, in the same order
+        #[structopt(long, required = true)]
+        member_ids: Vec<u64>,
+        #[structopt(long, required = true)]
+        member_addrs: Vec<String>,
+        #[structopt(long)]
+        learner_ids: Vec<u64>,
+        /// `old=new`, the id in the new cluster of a node of the old one
+        #[structopt(long)]
+        remap: Vec<String>,
+        /// the data directories are seeded in `<data_root>/<id>`, `ddbb_data` by default
+        #[structopt(long)]
+        data_root: Option<String>,
+    },
+}
+
+fn main() {
+    match Command::from_args() {
+        Command::Restore {
+            backup,
+            cluster_id,
+            epoch,
+            member_ids,
+            member_addrs,
+            learner_ids,
+            remap,
+            data_root,
+        } => {
+            if let Err(e) = restore(
+                &backup,
+                cluster_id,
+                epoch,
+                &member_ids,
+                &member_addrs,
+                &learner_ids,
+                &remap,
+                &data_root.unwrap_or(DATA_DIR.to_string()),
+            ) {
+                eprintln!("restore failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore(
+    backup: &str,
+    cluster_id: String,
+    epoch: u32,
+    member_ids: &[u64],
+    member_addrs: &[String],
+    learner_ids: &[u64],
+    remap: &[String],
+    data_root: &str,
+) -> ddbb_libs::Result<()> {
+    let (first_id, first_addr) = match (member_ids.first(), member_addrs.first()) {
+        (Some(id), Some(addr)) => (*id, addr.clone()),
+        _ => return Err("the new cluster has no members".into()),
+    };
+    check_node_ids(first_id, &first_addr, &member_ids[1..], &member_addrs[1..])?;
+    let peers: HashMap<NodeId, String> = member_ids[1..]
+        .iter()
+        .copied()
+        .zip(member_addrs[1..].iter().cloned())
+        .collect();
+    let manifest =
+        ClusterManifest::new(cluster_id, epoch, first_id, first_addr, &peers, learner_ids);
+    let remap = parse_remap(remap)?;
+    let dirs = restore_cluster(backup, &manifest, &remap, data_root)?;
+    println!("Seeded {} from {}, start each node with:", data_root, backup);
+    for ((id, addr), dir) in manifest.members.iter().zip(dirs.iter()) {
+        let mut args = format!(
+            "--pid {} --ip-addr {} --cluster-id {} --epoch {} --data-dir {}",
+            id, addr, manifest.cluster_id, manifest.epoch, dir
+        );
+        for (peer, peer_addr) in manifest.members.iter().filter(|(peer, _)| *peer != id) {
+            args.push_str(&format!(" --peer-ids {} --peers-addrs {}", peer, peer_addr));
+        }
+        for learner in manifest.learners.iter() {
+            args.push_str(&format!(" --learner-ids {}", learner));
+        }
+        println!("  node {}: {}", id, args);
+    }
+    Ok(())
+}
@@ -1,54 +1,215 @@
 #![allow(unused)]
 use log::{debug, error, info, log_enabled, Level};
 use omnipaxos_core::{
-    messages::Message, omni_paxos::OmniPaxosConfig, omni_paxos::*, util::LogEntry as OmniLogEntry,
-    util::NodeId,
+    failure_detector::FailureDetectorConfig, messages::Message, omni_paxos::OmniPaxosConfig, omni_paxos::*, util::LogEntry as OmniLogEntry,
+    util::{FlexibleQuorum, NodeId},
 };
 use tokio::time::{sleep, Duration};
 use tokio::{runtime::Builder, sync::mpsc, time};
 use std::error::Error;
 use std::collections::HashMap;
 use std::env::set_var;
+use std::str::FromStr;
 use std::string;
 use std::sync::{Arc, Mutex};
 
-use ddbb_server::config::{ELECTION_TIMEOUT, OUTGOING_MESSAGE_PERIOD, WAIT_DECIDED_TIMEOUT};
+use ddbb_server::bootstrap::{
+    agree_manifest, check_node_ids, load_cluster_uuid, persist_cluster_uuid, Bootstrap,
+    ClusterManifest, NodeIdentity,
+};
+use ddbb_server::config::{
+    BACKUP_RETENTION, DATA_DIR, ELECTION_TIMEOUT, OUTGOING_MESSAGE_PERIOD, PHI_MIN_STD_DEV_MS,
+    PHI_THRESHOLD, PHI_WINDOW, STORAGE_BACKEND, STORAGE_SYNC_MODE, WAIT_DECIDED_TIMEOUT,
+    WATCH_QUEUE_CAPACITY,
+};
+use ddbb_server::client_limits::ClientLimits;
+use ddbb_server::client_listener::start_client_listener;
 use ddbb_server::ddbb_server::DDBB;
+use ddbb_server::net::ListenerOptions;
+use ddbb_server::restore::check_restore_manifest;
+use ddbb_server::storage::{DDBBStorage, SyncMode};
+use ddbb_server::tasks::{init_console, spawn_named};
+use ddbb_server::tls::{PeerTls, TlsPaths};
+use ddbb_server::watch::SlowWatcherPolicy;
+use ddbb_server::zones::Zones;
 use ddbb_server::omni_paxos_server::{
-    op_connection::OmniSIMO, op_data_structure::LogEntry, op_data_structure::Snapshot,
+    op_connection::{HeartbeatTransport, OmniSIMO}, op_data_structure::LogEntry, op_data_structure::Snapshot,
     OmniPaxosInstance, OmniPaxosServer,
 };
 //StructOpt - used for getting input from the command line
 use structopt::StructOpt;
 //Serde - used for serializing (turning into bytes) and deserializing messages
 use serde::{Serialize, Deserialize};
-use omnipaxos_storage::memory_storage::MemoryStorage;
 #[derive(Debug, Serialize, Deserialize, StructOpt)]
 struct Node {
+    #[structopt(long, required_unless = "statefulset")]
+    pid: Option<u64>,
+    #[structopt(long, required_unless = "statefulset")]
+    ip_addr: Option<String>,
     #[structopt(long)]
-    pid: u64,
+    peer_ids: Vec<u64>,
     #[structopt(long)]
-    ip_addr: String,
+    peers_addrs: Vec<String>,
+    /// nodes (possibly including this one) that replicate the log without voting
     #[structopt(long)]
-    peer_ids: Vec<u64>,
+    learner_ids: Vec<u64>,
+    /// serve only stale reads and watches, sending the other commands to the leader;
+    /// this node must be in `learner_ids`
+    #[structopt(long)]
+    observer: bool,
+    /// prepare quorum size, must be given together with `write_quorum_size`
+    #[structopt(long)]
+    read_quorum_size: Option<usize>,
+    /// accept quorum size, must be given together with `read_quorum_size`
+    #[structopt(long)]
+    write_quorum_size: Option<usize>,
+    /// address to serve ddbb_client connections on
+    #[structopt(long)]
+    client_addr: Option<String>,
+    /// client addresses of the peers, in the order of `peer_ids`, to send clients to the leader
+    #[structopt(long)]
+    peer_client_addrs: Vec<String>,
+    /// answer the commands served by the leader with where it is, instead of forwarding them
+    #[structopt(long)]
+    no_forward: bool,
+    /// every node of a cluster must be started with the same cluster id and epoch
+    #[structopt(long, default_value = "ddbb")]
+    cluster_id: String,
+    #[structopt(long, default_value = "1")]
+    epoch: u32,
+    /// where the cluster uuid is persisted, `ddbb_data/<pid>` by default
+    #[structopt(long)]
+    data_dir: Option<String>,
+    /// take a backup to this directory every `BACKUP_INTERVAL`
+    #[structopt(long)]
+    backup_dir: Option<String>,
+    /// start a new cluster from a file written by the `export` admin command
+    #[structopt(long)]
+    import_snapshot: Option<String>,
+    /// clients must send this token before any command, unset leaves the client port open
+    #[structopt(long, env = "DDBB_AUTH_TOKEN", hide_env_values = true)]
+    auth_token: Option<String>,
+    /// client connections open at once, in total and from one address, 0 for no limit
+    #[structopt(long)]
+    max_client_connections: Option<usize>,
+    #[structopt(long)]
+    max_client_connections_per_ip: Option<usize>,
+    /// close a client connection sending no command for this many seconds, 0 for never
+    #[structopt(long)]
+    client_idle_timeout_secs: Option<u64>,
+    /// also write membership, leadership and snapshot events as json log lines
+    #[structopt(long)]
+    log_events: bool,
+    /// bytes the peer buffers, pending proposals and watch queues may hold before
+    /// writes are shed, `MEMORY_BUDGET` by default
+    #[structopt(long)]
+    memory_budget: Option<u64>,
+    /// reject writes while the data directory has less than this many bytes free,
+    /// e.g. 536870912 for 512MiB; off by default (`DISK_LOW_WATERMARK`) and with 0
+    #[structopt(long)]
+    disk_low_watermark: Option<u64>,
+    /// when the storage syncs its writes to disk: `always` before they are answered,
+    /// `interval:<ms>` at most this often, losing the latest ones on a crash, or `never`,
+    /// left to the OS, for benchmarks and test clusters; `STORAGE_SYNC_MODE` by default
+    #[structopt(long)]
+    fsync: Option<SyncMode>,
+    /// writes queued at most for a watch whose client reads slower than they are applied,
+    /// `WATCH_QUEUE_CAPACITY` by default, 0 for no limit
+    #[structopt(long)]
+    watch_queue_capacity: Option<usize>,
+    /// `disconnect` a watch beyond, the client resumes from the revision told, or `drop`
+    /// its intermediate writes to a key, keeping only the latest queued
+    #[structopt(long, default_value = "disconnect")]
+    slow_watchers: String,
+    /// accept faults injected through the admin API, e.g. latency on the peer links;
+    /// for test clusters only
+    #[structopt(long)]
+    fault_injection: bool,
+    /// zone, e.g. region or data center, this node runs in
+    #[structopt(long, env = "DDBB_ZONE")]
+    zone: Option<String>,
+    /// zones of the peers, in the order of `peer_ids`
+    #[structopt(long)]
+    peer_zones: Vec<String>,
+    /// the nodes of this zone are preferred as leader
+    #[structopt(long)]
+    primary_zone: Option<String>,
+    /// quorums are a majority of the voters of a majority of the zones, instead of a
+    /// majority of the voters, so a cluster survives losing a whole zone
+    #[structopt(long)]
+    zone_quorum: bool,
+    /// the leader hands the leadership over to a connected node preferred by the
+    /// `leader_priorities` and `drained_nodes` settings or the primary zone
+    #[structopt(long)]
+    leader_balance: bool,
+    /// `shared` sends the heartbeats of the leader election on the connection of the paxos
+    /// msgs, `connection` on one of their own, `udp` in datagrams
+    #[structopt(long, default_value = "shared")]
+    heartbeat_transport: String,
+    /// `rounds` takes a peer missing a heartbeat round as disconnected, `phi` only once the
+    /// phi accrual of its heartbeats reaches `phi_threshold`, for links with jitter
+    #[structopt(long, default_value = "rounds")]
+    failure_detector: FailureDetectorKind,
+    /// `PHI_THRESHOLD` by default, higher suspects the peers later
+    #[structopt(long)]
+    phi_threshold: Option<f64>,
+    /// the connections between the nodes are TLS sessions with this certificate chain and
+    /// PKCS#8 key, in PEM, signed by `tls_ca`; every node of the cluster needs them, and
+    /// ddbb_server built with `--features tls`
     #[structopt(long)]
-    peers_addrs: Vec<String>
+    tls_cert: Option<String>,
+    #[structopt(long)]
+    tls_key: Option<String>,
+    #[structopt(long)]
+    tls_ca: Option<String>,
+    /// the name the certificates of the nodes are issued for
+    #[structopt(long, default_value = "ddbb")]
+    tls_server_name: String,
+    /// derive pid and peers from POD_NAME, DDBB_SERVICE_DOMAIN, DDBB_REPLICAS and DDBB_PORT
+    #[structopt(long)]
+    statefulset: bool,
 }
+
+/// The failure detectors of `--failure-detector`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum FailureDetectorKind {
+    Rounds,
+    Phi,
+}
+
+impl FromStr for FailureDetectorKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> std::result::Result<Self, Self::Err> {
+        match kind {
+            "rounds" => Ok(FailureDetectorKind::Rounds),
+            "phi" => Ok(FailureDetectorKind::Phi),
+            other => Err(format!("the failure detector is rounds or phi, not {}", other)),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // setup the logger
     set_var("RUST_LOG", "debug");
     env_logger::init();
+    init_console();
     // error!("this is printed by default");
     // info!("info temp");
 
     // initialize
     let node = Node::from_args();
     // let mut node_ids: Vec<u64> = vec![1, 2, 3];
-    let node_id:u64 = node.pid;
-    let node_addr:String = node.ip_addr;
-    let peer_ids = node.peer_ids;
-    let peers_addrs = node.peers_addrs;
+    let (node_id, node_addr, peer_ids, peers_addrs) = if node.statefulset {
+        let bootstrap = Bootstrap::from_env().unwrap();
+        let (peer_ids, peers_addrs): (Vec<u64>, Vec<String>) = bootstrap.peers.into_iter().unzip();
+        (bootstrap.node_id, bootstrap.node_addr, peer_ids, peers_addrs)
+    } else {
+        (node.pid.unwrap(), node.ip_addr.clone().unwrap(), node.peer_ids.clone(), node.peers_addrs.clone())
+    };
+    check_node_ids(node_id, &node_addr, &peer_ids, &peers_addrs).unwrap();
+    let zones = Zones::new(node.zone.clone(), &peer_ids, &node.peer_zones, node.primary_zone.clone()).unwrap();
     let peer_num = peer_ids.len();
     // let mut servers: HashMap<NodeId, String> = HashMap::new();
     // servers.insert(node_id, node_addr);
@@ -68,23 +229,141 @@ async fn main() {
             peers.insert(peer_ids[i], addr);
         }
 
+        // joining: wait until a majority agrees on the cluster before creating omnipaxos
+        let data_dir = node
+            .data_dir
+            .clone()
+            .unwrap_or(format!("{}/{}", DATA_DIR, node_id));
+        let mut manifest = ClusterManifest::new(
+            node.cluster_id.clone(),
+            node.epoch,
+            node_id,
+            node_addr.clone(),
+            &peers,
+            &node.learner_ids,
+        );
+        manifest.cluster_uuid = load_cluster_uuid(&data_dir).unwrap();
+        check_restore_manifest(&data_dir, &manifest).unwrap();
+        let manifest = agree_manifest(node_id, &manifest, &ListenerOptions::default())
+            .await
+            .unwrap();
+        persist_cluster_uuid(&data_dir, manifest.cluster_uuid.as_ref().unwrap()).unwrap();
+
         let op_config = OmniPaxosConfig {
             pid: node_id,
-            configuration_id: 1,
+            configuration_id: manifest.epoch,
             peers: peer_ids.clone(),
+            learners: node.learner_ids.clone(),
+            flexible_quorum: match (node.read_quorum_size, node.write_quorum_size) {
+                (Some(read_quorum_size), Some(write_quorum_size)) => Some(FlexibleQuorum {
+                    read_quorum_size,
+                    write_quorum_size,
+                }),
+                (None, None) => None,
+                _ => panic!("--read-quorum-size and --write-quorum-size go together"),
+            },
+            grouped_quorum: if node.zone_quorum {
+                Some(zones.quorum(node_id, &peer_ids, &node.learner_ids).unwrap())
+            } else {
+                None
+            },
+            leader_priority: zones.leader_priority(),
+            failure_detector: match node.failure_detector {
+                FailureDetectorKind::Rounds => FailureDetectorConfig::Rounds,
+                FailureDetectorKind::Phi => FailureDetectorConfig::PhiAccrual {
+                    threshold: node.phi_threshold.unwrap_or(PHI_THRESHOLD),
+                    window: PHI_WINDOW,
+                    min_std_dev_ms: PHI_MIN_STD_DEV_MS,
+                },
+            },
             ..Default::default()
         };
-        let omni: OmniPaxosInstance = op_config.build(MemoryStorage::default());
+        let sync = node.fsync.unwrap_or(STORAGE_SYNC_MODE);
+        let storage = DDBBStorage::open(STORAGE_BACKEND, &data_dir, sync).unwrap();
+        let storage_flusher = storage.flusher();
+        let storage_corruption = storage.corruption().cloned();
+        let omni: OmniPaxosInstance = op_config.build(storage);
         // !! peer.clone
-        let simo = OmniSIMO::new(node_addr.to_string(), peers.clone());
+        let mut simo = OmniSIMO::new(node_addr.to_string(), peers.clone());
+        let heartbeat_transport: HeartbeatTransport = node.heartbeat_transport.parse().unwrap();
+        simo.set_heartbeat_transport(heartbeat_transport);
+        match (&node.tls_cert, &node.tls_key, &node.tls_ca) {
+            (Some(cert), Some(key), Some(ca)) => {
+                let paths = TlsPaths {
+                    cert: cert.clone(),
+                    key: key.clone(),
+                    ca: ca.clone(),
+                    server_name: node.tls_server_name.clone(),
+                };
+                simo.set_tls(PeerTls::load(&paths).unwrap());
+            }
+            (None, None, None) => {}
+            _ => panic!("--tls-cert, --tls-key and --tls-ca go together"),
+        }
+        simo.set_manifest(manifest);
+        simo.set_identity(NodeIdentity::new(node_id));
         let mut ddbb = DDBB::new(node_id, node_addr.clone(), peers, simo, omni);
+        ddbb.set_data_dir(data_dir.clone());
+        ddbb.set_storage_flusher(storage_flusher);
+        if let Some(corruption) = &storage_corruption {
+            ddbb.report_storage_corruption(corruption);
+        }
+        ddbb.set_log_events(node.log_events);
+        ddbb.set_forward_to_leader(!node.no_forward);
+        ddbb.set_peer_client_addrs(peer_ids.iter().copied().zip(node.peer_client_addrs.clone()).collect());
+        ddbb.set_zones(zones);
+        ddbb.set_leader_balance(node.leader_balance);
+        ddbb.set_observer(node.observer).unwrap();
+        if let Some(budget) = node.memory_budget {
+            ddbb.set_memory_budget(budget);
+        }
+        if let Some(low_watermark) = node.disk_low_watermark {
+            ddbb.set_disk_low_watermark(low_watermark);
+        }
+        let slow_watchers = match node.slow_watchers.as_str() {
+            "disconnect" => SlowWatcherPolicy::Disconnect,
+            "drop" => SlowWatcherPolicy::DropIntermediate,
+            other => panic!("--slow-watchers is disconnect or drop, not {}", other),
+        };
+        ddbb.set_slow_watchers(node.watch_queue_capacity.unwrap_or(WATCH_QUEUE_CAPACITY), slow_watchers);
+        ddbb.set_fault_injection(node.fault_injection);
+        if let Some(backup_dir) = &node.backup_dir {
+            ddbb.set_backups(backup_dir.clone(), BACKUP_RETENTION);
+        }
+        ddbb.apply_staged_restore().unwrap();
+        ddbb.restore_snapshot().unwrap();
+        if let Some(path) = &node.import_snapshot {
+            ddbb.import_snapshot(path).unwrap();
+        }
         let ddbb = Arc::new(Mutex::new(ddbb));
 
         let ddbb_copy = ddbb.clone();
-        let omni_server_handler = tokio::spawn(async move {
+        let omni_server_handler = spawn_named("omnipaxos server", async move {
             DDBB::start(ddbb_copy).await.unwrap();
         });
 
+        if let Some(client_addr) = node.client_addr.clone() {
+            let mut limits = ClientLimits::default();
+            if let Some(max) = node.max_client_connections {
+                limits.max_connections = max;
+            }
+            if let Some(max) = node.max_client_connections_per_ip {
+                limits.max_connections_per_ip = max;
+            }
+            if let Some(secs) = node.client_idle_timeout_secs {
+                limits.idle_timeout = Some(Duration::from_secs(secs)).filter(|_| secs != 0);
+            }
+            start_client_listener(
+                ddbb.clone(),
+                client_addr,
+                ListenerOptions::default(),
+                node.auth_token.clone(),
+                limits,
+            )
+            .await
+            .unwrap();
+        }
+
         ddbbs.insert(ddbbs.len(), ddbb);
     // }
     
@@ -129,7 +408,7 @@ async fn main() {
                 let res = DDBB::lin_write(ddbb1.clone(), input_vector[1].to_string(), input_vector[2].as_bytes().to_vec()).await;
                 match res {
                     Ok(value)=>{
-                        println!("Succesfully wrote.")
+                        println!("Succesfully wrote at index {}.", value)
                     },
                     Err(e) =>{
                         println!("Error occurred!")
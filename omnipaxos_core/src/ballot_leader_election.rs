@@ -4,15 +4,17 @@ use crate::util::defaults::*;
 #[allow(unused_imports)]
 use crate::utils::hocon_kv::LOG_FILE_PATH;
 use serde::{Serialize, Deserialize};
+use std::{collections::HashMap, time::Instant};
 
 #[cfg(feature = "logging")]
 use crate::utils::logger::create_logger;
 use crate::{
+    failure_detector::{FailureDetector, FailureDetectorConfig},
     messages::ballot_leader_election::{
         BLEMessage, HeartbeatMsg, HeartbeatReply, HeartbeatRequest,
     },
     omni_paxos::OmniPaxosConfig,
-    util::NodeId,
+    util::{FlexibleQuorum, GroupedQuorum, NodeId, Quorum},
 };
 #[cfg(feature = "logging")]
 use slog::{debug, info, trace, warn, Logger};
@@ -47,18 +49,27 @@ pub(crate) struct BallotLeaderElection {
     pid: NodeId,
     /// Vector that holds all the other replicas.
     peers: Vec<u64>,
+    /// Replicas that follow the elected leader but never vote or become candidates.
+    learners: Vec<NodeId>,
     /// The current round of the heartbeat cycle.
     hb_round: u32,
     /// Vector which holds all the received ballots.
     ballots: Vec<(Ballot, bool)>,
+    /// The ballot and candidacy of the latest reply of each voter, in any round.
+    latest_replies: HashMap<NodeId, (Ballot, bool)>,
+    /// Tells which voters missing a round are still connected.
+    failure_detector: Box<dyn FailureDetector>,
     /// Holds the current ballot of this instance.
     current_ballot: Ballot, // (round, pid)
     /// States if the instance is a candidate to become a leader.
     quorum_connected: bool,
+    /// States if the instance hands its leadership over, it is no candidate until
+    /// another leader is elected.
+    stepping_down: bool,
     /// Current elected leader.
     leader: Option<Ballot>,
-    /// The majority of replicas inside a cluster. It is measured in ticks.
-    majority: usize,
+    /// The number of voters that must be connected for a leader to be elected.
+    quorum: Quorum,
     /// Vector which holds all the outgoing messages of the BLE instance.
     outgoing: Vec<BLEMessage>,
     /// Logger used to output the status of the component.
@@ -71,19 +82,27 @@ impl BallotLeaderElection {
     pub(crate) fn with(config: BLEConfig) -> Self {
         let pid = config.pid;
         let peers = config.peers;
-        let n = &peers.len() + 1;
+        let learners = config.learners;
+        let n = std::iter::once(&pid)
+            .chain(peers.iter())
+            .filter(|p| !learners.contains(p))
+            .count();
         let initial_ballot = match &config.initial_leader {
             Some(leader_ballot) if leader_ballot.pid == pid => *leader_ballot,
             _ => Ballot::with(0, config.priority, pid),
         };
         let mut ble = BallotLeaderElection {
             pid,
-            majority: n / 2 + 1, // +1 because peers is exclusive ourselves
+            quorum: Quorum::with(config.flexible_quorum, config.grouped_quorum, n),
             peers,
+            learners,
             hb_round: 0,
             ballots: Vec::with_capacity(n),
+            latest_replies: HashMap::with_capacity(n),
+            failure_detector: config.failure_detector.build(),
             current_ballot: initial_ballot,
             quorum_connected: true,
+            stepping_down: false,
             leader: config.initial_leader,
             outgoing: Vec::with_capacity(config.buffer_size),
             #[cfg(feature = "logging")]
@@ -111,6 +130,29 @@ impl BallotLeaderElection {
         self.current_ballot.priority = p;
     }
 
+    /// Stop being a candidate until another leader is elected, without raising the
+    /// own ballot, so that the leadership goes to another server.
+    pub(crate) fn step_down(&mut self) {
+        self.stepping_down = true;
+    }
+
+    /// Be a candidate again, e.g. when no other leader could be elected.
+    pub(crate) fn cancel_step_down(&mut self) {
+        self.stepping_down = false;
+    }
+
+    pub(crate) fn is_stepping_down(&self) -> bool {
+        self.stepping_down
+    }
+
+    pub(crate) fn is_quorum_connected(&self) -> bool {
+        self.quorum_connected
+    }
+
+    fn is_candidate(&self) -> bool {
+        self.quorum_connected && !self.is_learner() && !self.stepping_down
+    }
+
     /// Returns outgoing messages
     pub(crate) fn get_outgoing_msgs(&mut self) -> Vec<BLEMessage> {
         std::mem::take(&mut self.outgoing)
@@ -119,10 +161,11 @@ impl BallotLeaderElection {
     /// Handle an incoming message.
     /// # Arguments
     /// * `m` - the message to be handled.
-    pub(crate) fn handle(&mut self, m: BLEMessage) {
+    /// * `now` - when it arrived, on the clock of the caller.
+    pub(crate) fn handle(&mut self, m: BLEMessage, now: Instant) {
         match m.msg {
             HeartbeatMsg::Request(req) => self.handle_request(m.from, req),
-            HeartbeatMsg::Reply(rep) => self.handle_reply(rep),
+            HeartbeatMsg::Reply(rep) => self.handle_reply(m.from, rep, now),
         }
     }
 
@@ -157,12 +200,18 @@ impl BallotLeaderElection {
 
         if top_ballot < self.leader.unwrap_or_default() {
             // did not get HB from leader
-            self.current_ballot.n = self.leader.unwrap_or_default().n + 1;
+            if !self.stepping_down {
+                self.current_ballot.n = self.leader.unwrap_or_default().n + 1;
+            }
             self.leader = None;
             None
         } else if self.leader != Some(top_ballot) {
             // got a new leader with greater ballot
             self.leader = Some(top_ballot);
+            if top_ballot.pid != self.pid {
+                // handed over, the own ballot stays below the new leader
+                self.stepping_down = false;
+            }
             #[cfg(feature = "logging")]
             debug!(
                 self.logger,
@@ -184,7 +233,9 @@ impl BallotLeaderElection {
             self.hb_round
         );
 
-        for peer in &self.peers {
+        // only voters take part in the election, learners just need to hear from them
+        let learners = &self.learners;
+        for peer in self.peers.iter().filter(|p| !learners.contains(p)) {
             let hb_request = HeartbeatRequest {
                 round: self.hb_round,
             };
@@ -197,15 +248,42 @@ impl BallotLeaderElection {
         }
     }
 
-    pub(crate) fn hb_timeout(&mut self) -> Option<Ballot> {
-        let result: Option<Ballot> = if self.ballots.len() + 1 >= self.majority {
+    fn is_learner(&self) -> bool {
+        self.learners.contains(&self.pid)
+    }
+
+    /// Adds the voters that missed the current round but that the failure detector still
+    /// takes as connected at `now`, with the ballot of their latest reply.
+    fn add_available_voters(&mut self, now: Instant) {
+        for (pid, reply) in self.latest_replies.iter() {
+            let replied = self.ballots.iter().any(|(b, _)| b.pid == *pid);
+            if !replied && self.failure_detector.is_available(*pid, now) {
+                #[cfg(feature = "logging")]
+                trace!(
+                    self.logger,
+                    "No heartbeat of {} in round {}, still available",
+                    pid,
+                    self.hb_round
+                );
+                self.ballots.push(*reply);
+            }
+        }
+    }
+
+    pub(crate) fn hb_timeout(&mut self, now: Instant) -> Option<Ballot> {
+        self.add_available_voters(now);
+        let mut connected: Vec<NodeId> = self.ballots.iter().map(|(b, _)| b.pid).collect();
+        if !self.is_learner() {
+            connected.push(self.pid);
+        }
+        let result: Option<Ballot> = if self.quorum.is_prepare_quorum(&connected) {
             #[cfg(feature = "logging")]
             debug!(
                 self.logger,
                 "Received a majority of heartbeats, round: {}, {:?}", self.hb_round, self.ballots
             );
-            self.ballots
-                .push((self.current_ballot, self.quorum_connected));
+            let candidate = self.is_candidate();
+            self.ballots.push((self.current_ballot, candidate));
             self.check_leader()
         } else {
             #[cfg(feature = "logging")]
@@ -227,7 +305,7 @@ impl BallotLeaderElection {
         let hb_reply = HeartbeatReply {
             round: req.round,
             ballot: self.current_ballot,
-            quorum_connected: self.quorum_connected,
+            quorum_connected: self.is_candidate(),
         };
 
         self.outgoing.push(BLEMessage {
@@ -237,7 +315,13 @@ impl BallotLeaderElection {
         });
     }
 
-    fn handle_reply(&mut self, rep: HeartbeatReply) {
+    fn handle_reply(&mut self, from: NodeId, rep: HeartbeatReply, now: Instant) {
+        if self.learners.contains(&from) {
+            return;
+        }
+        self.failure_detector.heartbeat(from, now);
+        self.latest_replies
+            .insert(from, (rep.ballot, rep.quorum_connected));
         if rep.round == self.hb_round {
             self.ballots.push((rep.ballot, rep.quorum_connected));
         } else {
@@ -254,6 +338,9 @@ impl BallotLeaderElection {
 /// # Fields
 /// * `pid`: The unique identifier of this node. Must not be 0.
 /// * `peers`: The peers of this node i.e. the `pid`s of the other replicas in the configuration.
+/// * `learners`: The `pid`s of the replicas that do not take part in the election.
+/// * `flexible_quorum`: Optional quorum sizes, a leader needs to be connected to a prepare quorum.
+/// * `grouped_quorum`: Optional groups of the voters, a leader needs to be connected to a quorum of them instead.
 /// * `priority`: Set custom priority for this node to be elected as the leader.
 /// * `hb_delay`: Timeout for waiting on heartbeat messages. It is measured in number of ticks.
 /// * `initial_leader`: The initial leader of the cluster.
@@ -261,13 +348,18 @@ impl BallotLeaderElection {
 /// * `logger`: Custom logger for logging events of Ballot Leader Election.
 /// * `logger_file_path`: The path where the default logger logs events.
 /// * `buffer_size`: The buffer size for outgoing messages.
+/// * `failure_detector`: Decides which voters missing a heartbeat round are still connected.
 #[derive(Clone, Debug)]
 pub(crate) struct BLEConfig {
     pid: NodeId,
     peers: Vec<u64>,
+    learners: Vec<NodeId>,
+    flexible_quorum: Option<FlexibleQuorum>,
+    grouped_quorum: Option<GroupedQuorum>,
     priority: u64,
     initial_leader: Option<Ballot>,
     buffer_size: usize,
+    failure_detector: FailureDetectorConfig,
     #[cfg(feature = "logging")]
     logger: Option<Logger>,
     #[cfg(feature = "logging")]
@@ -279,9 +371,13 @@ impl From<OmniPaxosConfig> for BLEConfig {
         Self {
             pid: config.pid,
             peers: config.peers,
+            learners: config.learners,
+            flexible_quorum: config.flexible_quorum,
+            grouped_quorum: config.grouped_quorum,
             priority: config.leader_priority,
             initial_leader: config.initial_leader,
             buffer_size: BLE_BUFFER_SIZE,
+            failure_detector: config.failure_detector,
             #[cfg(feature = "logging")]
             logger: None,
             #[cfg(feature = "logging")]
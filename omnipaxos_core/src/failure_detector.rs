@@ -0,0 +1,218 @@
+use crate::util::NodeId;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    time::Instant,
+};
+
+/// Decides whether a peer that did not reply in the current heartbeat round is still taken as connected by the
+/// Ballot Leader Election, with the ballot of its latest reply. A peer that replied in the round always is.
+pub trait FailureDetector: Debug + Send {
+    /// A heartbeat reply of `peer` arrived at `now`, in the current round or a late one.
+    fn heartbeat(&mut self, peer: NodeId, now: Instant);
+
+    /// Whether `peer` is still taken as connected at `now` although it missed the current round.
+    fn is_available(&self, peer: NodeId, now: Instant) -> bool;
+}
+
+/// The failure detectors `OmniPaxosConfig` can be built with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailureDetectorConfig {
+    /// A peer is connected only if it replied in the current heartbeat round.
+    Rounds,
+    /// A peer is connected until the phi of its heartbeats reaches `threshold`, see `PhiAccrualDetector`.
+    PhiAccrual {
+        /// The phi at which a peer is suspected, e.g. 8 for a chance of about 10^-8 to suspect a peer that is up.
+        threshold: f64,
+        /// The number of latest intervals between heartbeats the distribution is estimated from.
+        window: usize,
+        /// The lower bound of the standard deviation in ms, so that steady heartbeats do not make it too sensitive.
+        min_std_dev_ms: f64,
+    },
+}
+
+impl Default for FailureDetectorConfig {
+    fn default() -> Self {
+        FailureDetectorConfig::Rounds
+    }
+}
+
+impl FailureDetectorConfig {
+    /// Creates the failure detector.
+    pub fn build(self) -> Box<dyn FailureDetector> {
+        match self {
+            FailureDetectorConfig::Rounds => Box::new(RoundsDetector),
+            FailureDetectorConfig::PhiAccrual {
+                threshold,
+                window,
+                min_std_dev_ms,
+            } => Box::new(PhiAccrualDetector::new(threshold, window, min_std_dev_ms)),
+        }
+    }
+}
+
+/// Takes a peer as connected only in the rounds it replied in, a peer missing a single round can cause an election.
+#[derive(Debug, Default)]
+pub struct RoundsDetector;
+
+impl FailureDetector for RoundsDetector {
+    fn heartbeat(&mut self, _peer: NodeId, _now: Instant) {}
+
+    fn is_available(&self, _peer: NodeId, _now: Instant) -> bool {
+        false
+    }
+}
+
+/// The phi accrual failure detector (Hayashibara et al.): the suspicion of a peer grows with the time since its latest
+/// heartbeat, compared to the intervals observed between its heartbeats, which are taken as normally distributed.
+/// Peers on a link with jitter get the time they usually need, instead of being suspected after one late reply.
+#[derive(Debug)]
+pub struct PhiAccrualDetector {
+    threshold: f64,
+    window: usize,
+    min_std_dev_ms: f64,
+    peers: HashMap<NodeId, ArrivalWindow>,
+}
+
+#[derive(Debug, Default)]
+struct ArrivalWindow {
+    last: Option<Instant>,
+    intervals_ms: VecDeque<f64>,
+}
+
+impl PhiAccrualDetector {
+    /// Creates a detector, see `FailureDetectorConfig::PhiAccrual` for the parameters.
+    pub fn new(threshold: f64, window: usize, min_std_dev_ms: f64) -> Self {
+        assert!(threshold > 0.0, "Phi threshold must be greater than 0");
+        assert!(window > 0, "Window must be greater than 0");
+        Self {
+            threshold,
+            window,
+            min_std_dev_ms,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// The suspicion of `peer` at `now`, `None` until two heartbeats of it arrived.
+    pub fn phi(&self, peer: NodeId, now: Instant) -> Option<f64> {
+        let arrivals = self.peers.get(&peer)?;
+        let last = arrivals.last?;
+        if arrivals.intervals_ms.is_empty() {
+            return None;
+        }
+        let n = arrivals.intervals_ms.len() as f64;
+        let mean = arrivals.intervals_ms.iter().sum::<f64>() / n;
+        let variance = arrivals
+            .intervals_ms
+            .iter()
+            .map(|i| (i - mean) * (i - mean))
+            .sum::<f64>()
+            / n;
+        let std_dev = variance.sqrt().max(self.min_std_dev_ms);
+        let elapsed = now.saturating_duration_since(last).as_secs_f64() * 1000.0;
+        // logistic approximation of the cumulative distribution function of the normal distribution
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        let phi = if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        };
+        Some(phi)
+    }
+}
+
+impl FailureDetector for PhiAccrualDetector {
+    fn heartbeat(&mut self, peer: NodeId, now: Instant) {
+        let window = self.window;
+        let arrivals = self.peers.entry(peer).or_default();
+        if let Some(last) = arrivals.last {
+            let interval = now.saturating_duration_since(last).as_secs_f64() * 1000.0;
+            if arrivals.intervals_ms.len() == window {
+                arrivals.intervals_ms.pop_front();
+            }
+            arrivals.intervals_ms.push_back(interval);
+        }
+        arrivals.last = Some(now);
+    }
+
+    fn is_available(&self, peer: NodeId, now: Instant) -> bool {
+        // not enough heartbeats yet to tell, the rounds decide
+        self.phi(peer, now).map_or(false, |phi| phi < self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// `peer` heartbeats at each of the `ms` after `start`.
+    fn heartbeats(detector: &mut PhiAccrualDetector, peer: NodeId, start: Instant, ms: &[u64]) {
+        for ms in ms {
+            detector.heartbeat(peer, start + Duration::from_millis(*ms));
+        }
+    }
+
+    #[test]
+    fn test_phi_grows_with_silence() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut detector = PhiAccrualDetector::new(8.0, 10, 50.0);
+        assert_eq!(detector.phi(1, at(0)), None);
+        heartbeats(&mut detector, 1, start, &[0]);
+        // a single heartbeat tells no interval
+        assert_eq!(detector.phi(1, at(100)), None);
+        heartbeats(&mut detector, 1, start, &[100, 200, 300, 400]);
+        let phis: Vec<f64> = [450, 500, 600, 800]
+            .iter()
+            .map(|ms| detector.phi(1, at(*ms)).unwrap())
+            .collect();
+        assert!(phis.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", phis);
+    }
+
+    #[test]
+    fn test_phi_window_eviction() {
+        let start = Instant::now();
+        let mut detector = PhiAccrualDetector::new(8.0, 3, 50.0);
+        heartbeats(&mut detector, 1, start, &[0, 1000, 2000, 3000]);
+        let slow = detector.phi(1, start + Duration::from_millis(3500)).unwrap();
+        assert!(slow < 1.0, "{}", slow);
+        // the intervals of 1s are evicted by the later ones of 100ms
+        heartbeats(&mut detector, 1, start, &[3100, 3200, 3300]);
+        let fast = detector.phi(1, start + Duration::from_millis(3800)).unwrap();
+        assert!(fast > 8.0, "{}", fast);
+    }
+
+    #[test]
+    fn test_phi_min_std_dev() {
+        let start = Instant::now();
+        let steady = [0, 100, 200, 300, 400];
+        let late = start + Duration::from_millis(550);
+        // the steady intervals have no deviation, the floor decides how much a late heartbeat counts
+        let mut tolerant = PhiAccrualDetector::new(8.0, 10, 100.0);
+        heartbeats(&mut tolerant, 1, start, &steady);
+        assert!(tolerant.phi(1, late).unwrap() < 1.0);
+        let mut strict = PhiAccrualDetector::new(8.0, 10, 1.0);
+        heartbeats(&mut strict, 1, start, &steady);
+        assert!(strict.phi(1, late).unwrap() > 8.0);
+    }
+
+    #[test]
+    fn test_phi_availability() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut detector = PhiAccrualDetector::new(8.0, 10, 50.0);
+        // not known yet, the rounds decide
+        assert!(!detector.is_available(1, at(0)));
+        heartbeats(&mut detector, 1, start, &[0, 100, 200, 300, 400]);
+        assert!(detector.is_available(1, at(500)));
+        assert!(detector.is_available(1, at(550)));
+        // past the threshold
+        assert!(!detector.is_available(1, at(1400)));
+        // a heartbeat again
+        heartbeats(&mut detector, 1, start, &[1450]);
+        assert!(detector.is_available(1, at(1500)));
+        assert!(!detector.is_available(2, at(1500)));
+    }
+}
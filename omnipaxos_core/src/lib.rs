@@ -13,6 +13,8 @@
 #![deny(missing_docs)]
 /// Trait and struct related to the leader election in Omni-Paxos.
 pub mod ballot_leader_election;
+/// Failure detectors deciding which peers the leader election takes as connected.
+pub mod failure_detector;
 /// The different messages Omni-Paxos replicas can communicate to each other with.
 pub mod messages;
 /// The user-facing Omni-Paxos struct.
@@ -2,21 +2,27 @@
 use crate::utils::hocon_kv::*;
 use crate::{
     ballot_leader_election::{Ballot, BallotLeaderElection},
+    failure_detector::FailureDetectorConfig,
     messages::Message,
     sequence_paxos::SequencePaxos,
     storage::{Entry, Snapshot, StopSign, Storage},
-    util::{defaults::BUFFER_SIZE, LogEntry, NodeId},
+    util::{defaults::BUFFER_SIZE, FlexibleQuorum, GroupedQuorum, LogEntry, NodeId},
 };
 #[cfg(feature = "hocon_config")]
 use hocon::Hocon;
 use std::ops::RangeBounds;
+use std::time::Instant;
 
 /// Configuration for `OmniPaxos`.
 /// # Fields
 /// * `configuration_id`: The identifier for the configuration that this Sequence Paxos replica is part of.
 /// * `pid`: The unique identifier of this node. Must not be 0.
 /// * `peers`: The peers of this node i.e. the `pid`s of the other replicas in the configuration.
+/// * `learners`: The `pid`s of the learner replicas in the configuration (may include this node). Learners replicate the log but never vote in leader election or count toward any quorum.
+/// * `flexible_quorum`: Optional sizes of the prepare and accept quorums (Flexible Paxos). If `None`, a majority of the voters is used for both.
+/// * `grouped_quorum`: Optional groups of the voters, e.g. zones, whose quorums are a majority of the voters of a majority of the groups. Cannot be combined with `flexible_quorum`.
 /// * `buffer_size`: The buffer size for outgoing messages.
+/// * `failure_detector`: Decides which peers missing a heartbeat round the leader election still takes as connected. Only the peers that replied in the round by default.
 /// * `skip_prepare_use_leader`: The initial leader of the cluster. Could be used in combination with reconfiguration to skip the prepare phase in the new configuration.
 /// * `logger`: Custom logger for logging events of Sequence Paxos.
 /// * `logger_file_path`: The path where the default logger logs events.
@@ -26,12 +32,16 @@ pub struct OmniPaxosConfig {
     pub configuration_id: u32,
     pub pid: NodeId,
     pub peers: Vec<u64>,
+    pub learners: Vec<NodeId>,
+    pub flexible_quorum: Option<FlexibleQuorum>,
+    pub grouped_quorum: Option<GroupedQuorum>,
     pub buffer_size: usize,
     pub skip_prepare_use_leader: Option<Ballot>,
     pub logger_file_path: Option<String>,
     /*** BLE config fields ***/
     pub leader_priority: u64,
     pub initial_leader: Option<Ballot>,
+    pub failure_detector: FailureDetectorConfig,
     #[cfg(feature = "logging")]
     pub logger_path: Option<String>,
 }
@@ -55,6 +65,21 @@ impl OmniPaxosConfig {
                 unimplemented!("Peers in Hocon should be parsed as array!")
             }
         }
+        if let Hocon::Array(v) = &h[LEARNERS] {
+            config.learners = v
+                .iter()
+                .map(|x| x.as_i64().expect("Failed to load learner pid in Hocon array") as u64)
+                .collect();
+        }
+        if let (Some(r), Some(w)) = (
+            h[READ_QUORUM_SIZE].as_i64(),
+            h[WRITE_QUORUM_SIZE].as_i64(),
+        ) {
+            config.flexible_quorum = Some(FlexibleQuorum {
+                read_quorum_size: r as usize,
+                write_quorum_size: w as usize,
+            });
+        }
         if let Some(b) = h[BUFFER_SIZE].as_i64() {
             config.buffer_size = b as usize;
         }
@@ -81,8 +106,64 @@ impl OmniPaxosConfig {
             "Peers should not include self pid"
         );
         assert!(self.buffer_size > 0, "Buffer size must be greater than 0");
+        assert!(
+            self.learners
+                .iter()
+                .all(|l| *l == self.pid || self.peers.contains(l)),
+            "Learners must be part of the configuration"
+        );
+        assert!(
+            std::iter::once(&self.pid)
+                .chain(self.peers.iter())
+                .any(|p| !self.learners.contains(p)),
+            "Configuration must contain at least one voter"
+        );
+        if let Some(f) = self.flexible_quorum {
+            let num_voters = std::iter::once(&self.pid)
+                .chain(self.peers.iter())
+                .filter(|p| !self.learners.contains(p))
+                .count();
+            assert!(
+                f.read_quorum_size > 0 && f.write_quorum_size > 0,
+                "Quorum sizes must be greater than 0"
+            );
+            assert!(
+                f.read_quorum_size <= num_voters && f.write_quorum_size <= num_voters,
+                "Quorum sizes cannot exceed the number of voters"
+            );
+            assert!(
+                f.read_quorum_size + f.write_quorum_size > num_voters,
+                "Prepare and accept quorums must intersect"
+            );
+        }
+        if let Some(g) = &self.grouped_quorum {
+            assert!(
+                self.flexible_quorum.is_none(),
+                "Grouped and flexible quorums cannot be combined"
+            );
+            assert!(
+                !g.groups.is_empty() && g.groups.iter().all(|group| !group.is_empty()),
+                "Quorum groups cannot be empty"
+            );
+            let mut grouped: Vec<NodeId> = g.groups.iter().flatten().copied().collect();
+            grouped.sort_unstable();
+            let mut voters: Vec<NodeId> = std::iter::once(&self.pid)
+                .chain(self.peers.iter())
+                .filter(|p| !self.learners.contains(p))
+                .copied()
+                .collect();
+            voters.sort_unstable();
+            assert_eq!(
+                grouped, voters,
+                "Every voter must be in exactly one quorum group"
+            );
+        }
         if let Some(x) = self.skip_prepare_use_leader {
-            assert_ne!(x.pid, 0, "Initial leader cannot be 0")
+            assert_ne!(x.pid, 0, "Initial leader cannot be 0");
+            assert!(
+                !self.learners.contains(&x.pid),
+                "Initial leader cannot be a learner"
+            );
         };
         OmniPaxos {
             seq_paxos: SequencePaxos::with(self.clone().into(), storage),
@@ -97,11 +178,15 @@ impl Default for OmniPaxosConfig {
             configuration_id: 0,
             pid: 0,
             peers: Vec::new(),
+            learners: Vec::new(),
+            flexible_quorum: None,
+            grouped_quorum: None,
             buffer_size: BUFFER_SIZE,
             skip_prepare_use_leader: None,
             logger_file_path: None,
             leader_priority: 0,
             initial_leader: None,
+            failure_detector: FailureDetectorConfig::default(),
             #[cfg(feature = "logging")]
             logger_path: None,
         }
@@ -150,6 +235,11 @@ where
         self.seq_paxos.get_decided_idx()
     }
 
+    /// Return the length of the local log, accepted or decided, as if it was never compacted.
+    pub fn get_log_len(&self) -> u64 {
+        self.seq_paxos.get_log_len()
+    }
+
     /// Return trim index from storage.
     pub fn get_compacted_idx(&self) -> u64 {
         self.seq_paxos.get_compacted_idx()
@@ -160,11 +250,26 @@ where
         self.seq_paxos.fail_recovery()
     }
 
+    /// Returns the log length each follower promised to this leader has accepted, as far as this leader knows, to tell how far behind a follower is. `None` if this server is not the leader.
+    pub fn get_follower_accepted_idx(&self) -> Option<Vec<(NodeId, u64)>> {
+        self.seq_paxos.get_follower_accepted_idx()
+    }
+
     /// Returns the id of the current leader.
     pub fn get_current_leader(&self) -> Option<NodeId> {
         self.get_current_leader_ballot().map(|ballot| ballot.pid)
     }
 
+    /// Returns whether this replica is a learner, i.e. it replicates the log but never votes.
+    pub fn is_learner(&self) -> bool {
+        self.seq_paxos.is_learner()
+    }
+
+    /// Returns whether this replica is in the accept phase of an established leader, i.e. an entry appended now is replicated at once instead of held in the prepare phase or until a leader is elected.
+    pub fn is_accepting(&self) -> bool {
+        self.seq_paxos.is_accepting()
+    }
+
     /// Returns the ballot of the current leader.
     pub fn get_current_leader_ballot(&self) -> Option<Ballot> {
         let ballot = self.seq_paxos.get_current_leader();
@@ -215,9 +320,15 @@ where
 
     /// Handle an incoming message.
     pub fn handle_incoming(&mut self, m: Message<T, S>) {
+        self.handle_incoming_at(m, Instant::now())
+    }
+
+    /// Handle an incoming message that arrived at `now`, on the clock of the caller, which the failure detector
+    /// times the heartbeats with.
+    pub fn handle_incoming_at(&mut self, m: Message<T, S>, now: Instant) {
         match m {
             Message::SequencePaxos(p) => self.seq_paxos.handle(p),
-            Message::BLE(b) => self.ble.handle(b),
+            Message::BLE(b) => self.ble.handle(b, now),
         }
     }
 
@@ -248,12 +359,38 @@ where
         self.ble.set_priority(p)
     }
 
+    /// Hand the leadership over to another server: this server is no candidate in the
+    /// following elections, until another leader is elected. It stays leader until then.
+    pub fn step_down(&mut self) {
+        self.ble.step_down()
+    }
+
+    /// Be a candidate again after `step_down`, e.g. if no other leader was elected in time.
+    pub fn cancel_step_down(&mut self) {
+        self.ble.cancel_step_down()
+    }
+
+    /// Returns whether this server is stepping down, see `step_down`.
+    pub fn is_stepping_down(&self) -> bool {
+        self.ble.is_stepping_down()
+    }
+
+    /// Returns whether this server received the heartbeats of a majority in the latest heartbeat round.
+    pub fn is_quorum_connected(&self) -> bool {
+        self.ble.is_quorum_connected()
+    }
+
     /// If the heartbeat of a leader is not received when election_timeout() is called, the server might attempt to become the leader.
     /// It is also used for the election process, where the server checks if it can become the leader.
     /// This function should be called periodically to detect leader failure and drive the election process.
     /// For instance if `election_timeout()` is called every 100ms, then if the leader fails, the servers will detect it after 100ms and elect a new server after another 100ms if possible.
     pub fn election_timeout(&mut self) {
-        if let Some(b) = self.ble.hb_timeout() {
+        self.election_timeout_at(Instant::now())
+    }
+
+    /// `election_timeout()` at `now`, on the clock of the caller, which the failure detector times the heartbeats with.
+    pub fn election_timeout_at(&mut self, now: Instant) {
+        if let Some(b) = self.ble.hb_timeout(now) {
             self.seq_paxos.handle_leader(b);
         }
     }
@@ -28,7 +28,8 @@ where
                 n,
                 None,
                 self.leader_state.max_pid,
-                self.leader_state.majority,
+                self.leader_state.quorum.clone(),
+                self.learners.clone(),
             );
             self.leader = n;
             self.internal_storage.set_promise(n);
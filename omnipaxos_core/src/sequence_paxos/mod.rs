@@ -2,7 +2,7 @@ use super::{
     ballot_leader_election::Ballot,
     messages::sequence_paxos::*,
     storage::{Entry, Snapshot, StopSign, StopSignEntry, Storage},
-    util::{defaults::BUFFER_SIZE, LeaderState},
+    util::{defaults::BUFFER_SIZE, FlexibleQuorum, GroupedQuorum, LeaderState, Quorum},
 };
 #[cfg(feature = "logging")]
 use crate::utils::logger::create_logger;
@@ -31,6 +31,7 @@ where
     config_id: ConfigurationId,
     pid: NodeId,
     peers: Vec<u64>, // excluding self pid
+    learners: Vec<NodeId>,
     state: (Role, Phase),
     leader: Ballot,
     pending_proposals: Vec<T>,
@@ -55,9 +56,13 @@ where
     pub(crate) fn with(config: SequencePaxosConfig, storage: B) -> Self {
         let pid = config.pid;
         let peers = config.peers;
+        let learners = config.learners;
         let config_id = config.configuration_id;
-        let num_nodes = &peers.len() + 1;
-        let majority = num_nodes / 2 + 1;
+        let num_voters = std::iter::once(&pid)
+            .chain(peers.iter())
+            .filter(|p| !learners.contains(p))
+            .count();
+        let quorum = Quorum::with(config.flexible_quorum, config.grouped_quorum, num_voters);
         let max_peer_pid = peers.iter().max().unwrap();
         let max_pid = *std::cmp::max(max_peer_pid, &pid) as usize;
         let (state, leader, lds) = match &config.skip_prepare_use_leader {
@@ -88,12 +93,19 @@ where
             config_id,
             pid,
             peers,
+            learners: learners.clone(),
             state,
             pending_proposals: vec![],
             pending_stopsign: None,
             leader,
             outgoing: Vec::with_capacity(BUFFER_SIZE),
-            leader_state: LeaderState::<T, S>::with(leader, lds, max_pid, majority),
+            leader_state: LeaderState::<T, S>::with(
+                leader,
+                lds,
+                max_pid,
+                quorum,
+                learners,
+            ),
             latest_accepted_meta: None,
             buffer_size: config.buffer_size,
             s: PhantomData,
@@ -181,6 +193,11 @@ where
         self.internal_storage.get_decided_idx()
     }
 
+    /// Return the length of the local log, as if it was never compacted.
+    pub(crate) fn get_log_len(&self) -> u64 {
+        self.internal_storage.get_log_len()
+    }
+
     /// Return trim index from storage.
     pub(crate) fn get_compacted_idx(&self) -> u64 {
         self.internal_storage.get_compacted_idx()
@@ -210,6 +227,37 @@ where
         }
     }
 
+    /// Returns whether this replica is a learner.
+    pub(crate) fn is_learner(&self) -> bool {
+        self.learners.contains(&self.pid)
+    }
+
+    /// Returns whether an entry appended now is replicated at once, instead of held until a leader is established.
+    pub(crate) fn is_accepting(&self) -> bool {
+        matches!(
+            self.state,
+            (_, Phase::Accept) | (Role::Leader, Phase::FirstAccept)
+        )
+    }
+
+    /// Returns the log length each follower promised to this leader has accepted, as far as this leader knows. `None` if this replica is not the leader.
+    pub(crate) fn get_follower_accepted_idx(&self) -> Option<Vec<(NodeId, u64)>> {
+        if self.state.0 != Role::Leader {
+            return None;
+        }
+        let accepted = self
+            .peers
+            .iter()
+            .filter_map(|pid| {
+                // the decided index promised stands until the follower accepts anything
+                let decided_idx = (*self.leader_state.get_decided_idx(*pid))?;
+                let accepted_idx = self.leader_state.get_accepted_idx(*pid);
+                Some((*pid, accepted_idx.max(decided_idx)))
+            })
+            .collect();
+        Some(accepted)
+    }
+
     /// Returns the id of the current leader.
     pub(crate) fn get_current_leader(&self) -> Ballot {
         self.leader
@@ -403,6 +451,8 @@ enum Role {
 /// * `configuration_id`: The identifier for the configuration that this Sequence Paxos replica is part of.
 /// * `pid`: The unique identifier of this node. Must not be 0.
 /// * `peers`: The peers of this node i.e. the `pid`s of the other replicas in the configuration.
+/// * `learners`: The `pid`s of the replicas that replicate the log without voting.
+/// * `flexible_quorum`: Optional prepare and accept quorum sizes, a majority is used for both if `None`.
 /// * `buffer_size`: The buffer size for outgoing messages.
 /// * `skip_prepare_use_leader`: The initial leader of the cluster. Could be used in combination with reconfiguration to skip the prepare phase in the new configuration.
 /// * `logger`: Custom logger for logging events of Sequence Paxos.
@@ -412,6 +462,9 @@ pub struct SequencePaxosConfig {
     configuration_id: u32,
     pid: NodeId,
     peers: Vec<u64>,
+    learners: Vec<NodeId>,
+    flexible_quorum: Option<FlexibleQuorum>,
+    grouped_quorum: Option<GroupedQuorum>,
     buffer_size: usize,
     skip_prepare_use_leader: Option<Ballot>,
     #[cfg(feature = "logging")]
@@ -424,6 +477,9 @@ impl From<OmniPaxosConfig> for SequencePaxosConfig {
             configuration_id: config.configuration_id,
             pid: config.pid,
             peers: config.peers,
+            learners: config.learners,
+            flexible_quorum: config.flexible_quorum,
+            grouped_quorum: config.grouped_quorum,
             buffer_size: config.buffer_size,
             skip_prepare_use_leader: config.skip_prepare_use_leader,
             #[cfg(feature = "logging")]
@@ -37,7 +37,7 @@ impl PartialEq for PromiseMetaData {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub(crate) struct LeaderState<T, S>
 where
     T: Entry,
@@ -54,7 +54,8 @@ where
     pub batch_accept_meta: Vec<Option<(Ballot, usize)>>, //  index in outgoing
     pub accepted_stopsign: Vec<bool>,
     pub max_pid: usize,
-    pub majority: usize,
+    pub quorum: Quorum,
+    pub learners: Vec<NodeId>,
 }
 
 impl<T, S> LeaderState<T, S>
@@ -66,7 +67,8 @@ where
         n_leader: Ballot,
         decided_indexes: Option<Vec<Option<u64>>>,
         max_pid: usize,
-        majority: usize,
+        quorum: Quorum,
+        learners: Vec<NodeId>,
     ) -> Self {
         Self {
             n_leader,
@@ -80,7 +82,8 @@ where
             batch_accept_meta: vec![None; max_pid],
             accepted_stopsign: vec![false; max_pid],
             max_pid,
-            majority,
+            quorum,
+            learners,
         }
     }
 
@@ -88,6 +91,11 @@ where
         (pid - 1) as usize
     }
 
+    /// Learners are replicated to but never count toward a quorum.
+    fn is_voter_idx(&self, idx: usize) -> bool {
+        !self.learners.contains(&((idx + 1) as u64))
+    }
+
     pub fn set_decided_idx(&mut self, pid: NodeId, idx: Option<u64>) {
         self.decided_indexes[Self::pid_to_idx(pid)] = idx;
     }
@@ -105,8 +113,14 @@ where
         }
         self.decided_indexes[Self::pid_to_idx(from)] = Some(prom.decided_idx);
         self.promises_meta[Self::pid_to_idx(from)] = Some(promise_meta);
-        let num_promised = self.promises_meta.iter().filter(|x| x.is_some()).count();
-        num_promised >= self.majority
+        let promised: Vec<NodeId> = self
+            .promises_meta
+            .iter()
+            .enumerate()
+            .filter(|(idx, x)| x.is_some() && self.is_voter_idx(*idx))
+            .map(|(idx, _)| (idx + 1) as NodeId)
+            .collect();
+        self.quorum.is_prepare_quorum(&promised)
     }
 
     pub fn take_max_promise(&mut self) -> Option<(Option<SnapshotType<T, S>>, Vec<T>)> {
@@ -175,21 +189,34 @@ where
             .copied()
     }
 
+    pub fn get_accepted_idx(&self, pid: NodeId) -> u64 {
+        self.accepted_indexes[Self::pid_to_idx(pid)]
+    }
+
     pub fn get_decided_idx(&self, pid: NodeId) -> &Option<u64> {
         self.decided_indexes.get(Self::pid_to_idx(pid)).unwrap()
     }
 
     pub fn is_stopsign_chosen(&self) -> bool {
-        let num_accepted = self.accepted_stopsign.iter().filter(|x| **x).count();
-        num_accepted >= self.majority
+        let accepted: Vec<NodeId> = self
+            .accepted_stopsign
+            .iter()
+            .enumerate()
+            .filter(|(idx, x)| **x && self.is_voter_idx(*idx))
+            .map(|(idx, _)| (idx + 1) as NodeId)
+            .collect();
+        self.quorum.is_accept_quorum(&accepted)
     }
 
     pub fn is_chosen(&self, idx: u64) -> bool {
-        self.accepted_indexes
+        let accepted: Vec<NodeId> = self
+            .accepted_indexes
             .iter()
-            .filter(|la| **la >= idx)
-            .count()
-            >= self.majority
+            .enumerate()
+            .filter(|(i, la)| **la >= idx && self.is_voter_idx(*i))
+            .map(|(i, _)| (i + 1) as NodeId)
+            .collect();
+        self.quorum.is_accept_quorum(&accepted)
     }
 
     pub fn take_max_promise_stopsign(&mut self) -> Option<StopSign> {
@@ -197,6 +224,78 @@ where
     }
 }
 
+/// Sizes of the prepare (read) and accept (write) quorums used by Flexible Paxos.
+/// Every prepare quorum must intersect every accept quorum, i.e. `read_quorum_size + write_quorum_size` must be larger than the number of voters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FlexibleQuorum {
+    /// Number of voters that must promise before a leader may start the accept phase.
+    pub read_quorum_size: usize,
+    /// Number of voters that must accept an entry before it is decided.
+    pub write_quorum_size: usize,
+}
+
+/// The voters split in groups, e.g. the zones of a multi-datacenter deployment. A quorum is a majority of the voters of each of a majority of the groups.
+/// Any two such quorums intersect, so they are used for both phases and the leader election, and all the voters of a minority of the groups may fail at once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupedQuorum {
+    /// Every voter is in exactly one group.
+    pub groups: Vec<Vec<NodeId>>,
+}
+
+impl GroupedQuorum {
+    fn is_quorum(&self, nodes: &[NodeId]) -> bool {
+        let groups = self
+            .groups
+            .iter()
+            .filter(|group| {
+                let members = group.iter().filter(|pid| nodes.contains(pid)).count();
+                members > group.len() / 2
+            })
+            .count();
+        groups > self.groups.len() / 2
+    }
+}
+
+/// The quorums used for the prepare phase, accept phase and leader election.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Quorum {
+    Majority(usize),
+    Flexible(FlexibleQuorum),
+    Grouped(GroupedQuorum),
+}
+
+impl Quorum {
+    pub(crate) fn with(
+        flexible_quorum: Option<FlexibleQuorum>,
+        grouped_quorum: Option<GroupedQuorum>,
+        num_voters: usize,
+    ) -> Self {
+        match (grouped_quorum, flexible_quorum) {
+            (Some(g), _) => Quorum::Grouped(g),
+            (None, Some(f)) => Quorum::Flexible(f),
+            (None, None) => Quorum::Majority(num_voters / 2 + 1),
+        }
+    }
+
+    /// Whether the voters `nodes` are a prepare quorum.
+    pub(crate) fn is_prepare_quorum(&self, nodes: &[NodeId]) -> bool {
+        match self {
+            Quorum::Majority(majority) => nodes.len() >= *majority,
+            Quorum::Flexible(f) => nodes.len() >= f.read_quorum_size,
+            Quorum::Grouped(g) => g.is_quorum(nodes),
+        }
+    }
+
+    /// Whether the voters `nodes` are an accept quorum.
+    pub(crate) fn is_accept_quorum(&self, nodes: &[NodeId]) -> bool {
+        match self {
+            Quorum::Majority(majority) => nodes.len() >= *majority,
+            Quorum::Flexible(f) => nodes.len() >= f.write_quorum_size,
+            Quorum::Grouped(g) => g.is_quorum(nodes),
+        }
+    }
+}
+
 /*
 /// Item used for log synchronization in the Prepare phase.
 #[allow(missing_docs)]
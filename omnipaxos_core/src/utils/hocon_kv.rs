@@ -5,6 +5,12 @@ pub const CONFIG_ID: &str = "config_id";
 pub const PID: &str = "pid";
 /// The peers of this replica.
 pub const PEERS: &str = "peers";
+/// The learners of the configuration.
+pub const LEARNERS: &str = "learners";
+/// The size of the prepare quorum when using flexible quorums.
+pub const READ_QUORUM_SIZE: &str = "read_quorum_size";
+/// The size of the accept quorum when using flexible quorums.
+pub const WRITE_QUORUM_SIZE: &str = "write_quorum_size";
 /// The priority of this replica
 pub const PRIORITY: &str = "priority";
 /// A fixed delay that is added to the current_delay. It is measured in ticks.
@@ -13,9 +13,9 @@ use ddbb_server::ddbb_server::DDBB;
 use ddbb_server::omni_paxos_server::op_connection::OmniSIMO;
 use ddbb_server::omni_paxos_server::op_data_structure::LogEntry;
 use ddbb_server::omni_paxos_server::OmniPaxosInstance;
+use ddbb_server::storage::DDBBStorage;
 use omnipaxos_core::omni_paxos::OmniPaxosConfig;
 use omnipaxos_core::util::NodeId;
-use omnipaxos_storage::memory_storage::MemoryStorage;
 
 use crate::configs::{ELECTION_TIMEOUT, LOG_CUNCURRENT_NUM, STRAT_PORT};
 
@@ -136,7 +136,7 @@ pub async fn run_ddbb(
         peers: peer_ids,
         ..Default::default()
     };
-    let omni: OmniPaxosInstance = op_config.build(MemoryStorage::default());
+    let omni: OmniPaxosInstance = op_config.build(DDBBStorage::memory());
     // !! peer.clone
     let simo = OmniSIMO::new(node_addr.to_string(), peers.clone());
     let mut ddbb = DDBB::new(nodeid, node_addr.to_string(), peers, simo, omni);